@@ -0,0 +1,98 @@
+//! Compares how long the interpreter and the virtual machine take to run the same programs.
+//!
+//! Run with `cargo bench -p interp`. Each program is checked once and then run several times
+//! each way, and the fastest run of each is reported, with the time compiling took kept apart.
+
+use interp::{Interpreter, Vm, compile};
+use parser::node_id::NodeIndex;
+use std::time::{Duration, Instant};
+
+const RUNS: usize = 5;
+
+const PROGRAMS: &[(&str, &str)] = &[
+    (
+        "recursion",
+        "fn fib(i32 n) -> i32 { if n < 2 { n } else { fib(n - 1) + fib(n - 2) } }
+         fn main() { println(fib(24)); }",
+    ),
+    (
+        "loops",
+        "fn main() {
+             @total = 0;
+             for i in 0..300000 { if i % 3 == 0 { total += 1; } }
+             println(total);
+         }",
+    ),
+    (
+        "closures",
+        "fn main() {
+             @total = 0;
+             step = |i32 x| x % 7 + 1;
+             for i in 0..100000 { total += step(i); }
+             println(total);
+         }",
+    ),
+    (
+        "arrays and records",
+        "record point { x: i32; y: i32; }
+         fn main() {
+             @points = [point { x: 0, y: 0 }, point { x: 1, y: 1 }];
+             for i in 0..50000 { points[i % 2].x = points[i % 2].x + i % 10; }
+             println(points[0].x + points[1].x);
+         }",
+    ),
+];
+
+fn main() {
+    println!(
+        "{:<20} {:>12} {:>12} {:>12} {:>8}",
+        "program", "interpreter", "compile", "vm", "speedup"
+    );
+    for (name, source) in PROGRAMS {
+        let program = parser::parse(source).expect("benchmark programs parse");
+        let index = NodeIndex::new(&program);
+        let resolution = semantics::resolve(&program, &index);
+        let checked = semantics::check(&program, &index, &resolution);
+        assert!(
+            resolution.errors.is_empty() && checked.errors.is_empty(),
+            "benchmark programs check"
+        );
+
+        let interpreted = fastest(|| {
+            Interpreter::new(&program, &index, &checked.types)
+                .with_output(std::io::sink())
+                .run()
+                .expect("benchmark programs run");
+        });
+        let compiled = fastest(|| {
+            compile(&program, &index, &checked.types);
+        });
+        let bytecode = compile(&program, &index, &checked.types);
+        let run = fastest(|| {
+            Vm::new(&bytecode)
+                .with_output(std::io::sink())
+                .run()
+                .expect("benchmark programs run");
+        });
+        println!(
+            "{:<20} {:>12.2?} {:>12.2?} {:>12.2?} {:>7.2}x",
+            name,
+            interpreted,
+            compiled,
+            run,
+            interpreted.as_secs_f64() / run.as_secs_f64()
+        );
+    }
+}
+
+/// The shortest of [`RUNS`] runs of `f`.
+fn fastest(mut f: impl FnMut()) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            f();
+            start.elapsed()
+        })
+        .min()
+        .unwrap_or_default()
+}
@@ -0,0 +1,44 @@
+//! Where a program was when a runtime error stopped it.
+//!
+//! The [`Interpreter`](crate::Interpreter) and the [`Vm`](crate::Vm) both keep the calls that
+//! were running when an error stopped the program, innermost first. Each call is at a node of
+//! the function it runs: the node the error was raised at for the innermost call, and the call of
+//! the next one for the others. [`diagnostic`] reports the error with a note for each.
+
+use crate::interpreter::RuntimeError;
+use parser::node_id::NodeId;
+use semantics::Diagnostic;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frame<'p> {
+    /// The declared name of the function, or `<closure>` or `<top level>`.
+    pub function: &'p str,
+    /// The statement or expression the function was running, in the program the function was
+    /// declared in.
+    pub node: NodeId,
+}
+
+/// The diagnostic reporting `error` at the node it was raised at, with a note for each call of
+/// `backtrace`, or `None` if the error was not raised in one, as when a const cannot be evaluated.
+/// Calls from the same place one after another, as recursion makes, share a note.
+pub fn diagnostic(error: &RuntimeError, backtrace: &[Frame]) -> Option<Diagnostic> {
+    let mut diagnostic = Diagnostic::error(error.to_string(), backtrace.first()?.node);
+    let mut frames = backtrace.iter().peekable();
+    while let Some(frame) = frames.next() {
+        let mut repeated = 0;
+        while frames.next_if_eq(&frame).is_some() {
+            repeated += 1;
+        }
+        diagnostic = diagnostic.with_note(format!("in {}", frame.function), Some(frame.node));
+        if repeated > 0 {
+            diagnostic = diagnostic.with_note(
+                format!(
+                    "and {} more calls of {} from there",
+                    repeated, frame.function
+                ),
+                None,
+            );
+        }
+    }
+    Some(diagnostic)
+}
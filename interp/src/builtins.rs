@@ -0,0 +1,265 @@
+//! The functions the language provides, which every program can call without declaring them.
+//!
+//! Each builtin is a Rust function that gets the [`Runtime`] of the engine running the program
+//! and the values it was called with. The resolver knows their names from
+//! [`semantics::BUILTINS`], which lists the same functions as [`BUILTINS`].
+
+use crate::heap::Heap;
+use crate::host::HostFunction;
+use crate::interpreter::RuntimeError;
+use crate::operations::automatic_dereference;
+use crate::value::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::rc::Rc;
+
+/// The Rust side of a builtin.
+pub type Native = for<'p> fn(&mut Runtime<'_, 'p>, &[Value<'p>]) -> Result<Value<'p>, RuntimeError>;
+
+/// What the interpreter and the virtual machine share with the builtins they call.
+pub struct Runtime<'o, 'p> {
+    pub heap: Heap<'p>,
+    pub output: Box<dyn Write + 'o>,
+    /// The functions the application running the program gives it, by name.
+    pub hosts: HashMap<String, Rc<HostFunction>>,
+}
+
+impl<'p> Runtime<'_, 'p> {
+    /// The host function called `name`, as a value.
+    pub fn host(&self, name: &str) -> Option<Value<'p>> {
+        self.hosts.get(name).cloned().map(Value::Host)
+    }
+
+    /// Write `text` to the program's output.
+    pub fn write(&mut self, text: &str) -> Result<(), RuntimeError> {
+        self.output
+            .write_all(text.as_bytes())
+            .map_err(|error| RuntimeError::Output {
+                message: error.to_string(),
+            })
+    }
+}
+
+impl Default for Runtime<'_, '_> {
+    /// A runtime that writes to standard output.
+    fn default() -> Self {
+        Runtime {
+            heap: Heap::default(),
+            output: Box::new(std::io::stdout()),
+            hosts: HashMap::new(),
+        }
+    }
+}
+
+pub struct Builtin {
+    pub name: &'static str,
+    /// How many arguments it takes, or `None` for any number.
+    pub arity: Option<usize>,
+    pub function: Native,
+}
+
+impl Builtin {
+    /// The builtin called `name`.
+    pub fn named(name: &str) -> Option<&'static Builtin> {
+        BUILTINS.iter().find(|builtin| builtin.name == name)
+    }
+
+    /// Check the number of `arguments` and run the builtin with them.
+    pub fn call<'p>(
+        &self,
+        runtime: &mut Runtime<'_, 'p>,
+        arguments: &[Value<'p>],
+    ) -> Result<Value<'p>, RuntimeError> {
+        match self.arity {
+            Some(arity) if arity != arguments.len() => Err(RuntimeError::ArgumentCount {
+                function: self.name.to_string(),
+                expected: arity,
+                found: arguments.len(),
+            }),
+            _ => (self.function)(runtime, arguments),
+        }
+    }
+}
+
+impl fmt::Debug for Builtin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Builtin({})", self.name)
+    }
+}
+
+/// Every builtin.
+pub static BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "print",
+        arity: None,
+        function: print,
+    },
+    Builtin {
+        name: "println",
+        arity: None,
+        function: println,
+    },
+    Builtin {
+        name: "len",
+        arity: Some(1),
+        function: len,
+    },
+    Builtin {
+        name: "assert",
+        arity: Some(1),
+        function: assert,
+    },
+    Builtin {
+        name: "to_string",
+        arity: Some(1),
+        function: to_string,
+    },
+];
+
+/// Writes its arguments, separated by spaces.
+fn print<'p>(
+    runtime: &mut Runtime<'_, 'p>,
+    arguments: &[Value<'p>],
+) -> Result<Value<'p>, RuntimeError> {
+    runtime.write(&spaced(arguments))?;
+    Ok(Value::Unit)
+}
+
+/// Writes its arguments, separated by spaces, as one line.
+fn println<'p>(
+    runtime: &mut Runtime<'_, 'p>,
+    arguments: &[Value<'p>],
+) -> Result<Value<'p>, RuntimeError> {
+    runtime.write(&format!("{}\n", spaced(arguments)))?;
+    Ok(Value::Unit)
+}
+
+fn spaced(arguments: &[Value]) -> String {
+    let words: Vec<String> = arguments.iter().map(ToString::to_string).collect();
+    words.join(" ")
+}
+
+/// The number of elements of an array or range, or of chars of a string.
+fn len<'p>(_: &mut Runtime<'_, 'p>, arguments: &[Value<'p>]) -> Result<Value<'p>, RuntimeError> {
+    let length = match automatic_dereference(arguments[0].clone())? {
+        Value::Array(elements) => elements.len() as i128,
+        Value::String(string) => string.chars().count() as i128,
+        Value::Range {
+            start,
+            end,
+            inclusive,
+        } => match (*start, *end) {
+            (Value::Integer(start), Value::Integer(end)) => end
+                .saturating_sub(start)
+                .saturating_add(i128::from(inclusive))
+                .max(0),
+            (Value::Char(start), Value::Char(end)) => {
+                (i128::from(end as u32) - i128::from(start as u32) + i128::from(inclusive)).max(0)
+            }
+            (start, _) => {
+                return Err(RuntimeError::TypeMismatch {
+                    expected: "range of integers or chars",
+                    found: start.kind(),
+                });
+            }
+        },
+        value => {
+            return Err(RuntimeError::TypeMismatch {
+                expected: "array, string, or range",
+                found: value.kind(),
+            });
+        }
+    };
+    Ok(Value::Integer(length))
+}
+
+/// Stops the program if its argument is false.
+fn assert<'p>(_: &mut Runtime<'_, 'p>, arguments: &[Value<'p>]) -> Result<Value<'p>, RuntimeError> {
+    match arguments[0] {
+        Value::Boolean(true) => Ok(Value::Unit),
+        Value::Boolean(false) => Err(RuntimeError::AssertionFailed),
+        ref value => Err(RuntimeError::TypeMismatch {
+            expected: "bool",
+            found: value.kind(),
+        }),
+    }
+}
+
+/// Its argument as `print` writes it.
+fn to_string<'p>(
+    _: &mut Runtime<'_, 'p>,
+    arguments: &[Value<'p>],
+) -> Result<Value<'p>, RuntimeError> {
+    Ok(Value::String(arguments[0].to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn call(
+        name: &str,
+        arguments: &[Value<'static>],
+    ) -> Result<(Value<'static>, String), RuntimeError> {
+        let mut output = Vec::new();
+        let mut runtime = Runtime {
+            heap: Heap::default(),
+            output: Box::new(&mut output),
+            hosts: HashMap::new(),
+        };
+        let value = Builtin::named(name)
+            .unwrap()
+            .call(&mut runtime, arguments)?;
+        drop(runtime);
+        Ok((value, String::from_utf8(output).unwrap()))
+    }
+
+    #[test]
+    fn test_the_resolver_knows_every_builtin() {
+        let names: Vec<&str> = BUILTINS.iter().map(|builtin| builtin.name).collect();
+
+        assert_eq!(names, semantics::BUILTINS);
+    }
+
+    #[test_case("print", &[Value::Integer(1), Value::String("a".to_string())], "1 a" ; "print")]
+    #[test_case("println", &[Value::Char('x')], "x\n" ; "println")]
+    fn test_output(name: &str, arguments: &[Value<'static>], expected: &str) {
+        assert_eq!(
+            call(name, arguments),
+            Ok((Value::Unit, expected.to_string()))
+        );
+    }
+
+    #[test_case(Value::String("héllo".to_string()), 5 ; "chars of a string")]
+    #[test_case(Value::Array(vec![Value::Unit; 3]), 3 ; "array")]
+    #[test_case(Value::Range { start: Box::new(Value::Integer(2)), end: Box::new(Value::Integer(5)), inclusive: true }, 4 ; "inclusive range")]
+    #[test_case(Value::Range { start: Box::new(Value::Integer(5)), end: Box::new(Value::Integer(2)), inclusive: false }, 0 ; "empty range")]
+    fn test_len(value: Value<'static>, expected: i128) {
+        assert_eq!(
+            call("len", &[value]),
+            Ok((Value::Integer(expected), String::new()))
+        );
+    }
+
+    #[test]
+    fn test_assert_and_to_string() {
+        assert_eq!(
+            call("assert", &[Value::Boolean(false)]),
+            Err(RuntimeError::AssertionFailed)
+        );
+        assert_eq!(
+            call("to_string", &[Value::Array(vec![Value::Char('a')])]),
+            Ok((Value::String("['a']".to_string()), String::new()))
+        );
+        assert_eq!(
+            call("len", &[]),
+            Err(RuntimeError::ArgumentCount {
+                function: "len".to_string(),
+                expected: 1,
+                found: 0
+            })
+        );
+    }
+}
@@ -0,0 +1,189 @@
+//! The instructions the [compiler](crate::compiler) turns a program into and the
+//! [virtual machine](crate::vm) runs.
+//!
+//! Each function is compiled to a list of instructions for a stack machine: an instruction pops
+//! its operands off the value stack and pushes its result. A function's arguments and local
+//! variables live on the same stack, in slots numbered from its first argument, so reading a
+//! variable copies a value from a position the compiler worked out instead of looking its name
+//! up. Jumps name the position of an instruction in the same function.
+//!
+//! A variable that a closure captures, that is referenced, or that has a field or element
+//! assigned to is kept in a cell: its slot holds a reference to storage of its own, which the
+//! closures and references share with the function.
+
+use crate::interpreter::RuntimeError;
+pub use crate::operations::Fit;
+use crate::value::Value;
+use parser::ast::{
+    BinaryOperator, FunctionDeclaration, Parameter, Pattern, Program, RecordDeclaration, Type,
+    UnaryOperator,
+};
+use parser::node_id::NodeId;
+use std::collections::{HashMap, HashSet};
+
+/// A compiled program.
+#[derive(Debug)]
+pub struct Bytecode<'p> {
+    pub(crate) program: &'p Program,
+    /// Every compiled function. The first is the program's top-level code, which runs its
+    /// top-level statements and then calls `main`.
+    pub functions: Vec<Function<'p>>,
+    /// The name of every global: functions, variants, consts, and top-level variables.
+    pub globals: Vec<&'p str>,
+    /// The values globals start with before the top-level code runs: functions and variants.
+    /// Consts are evaluated when the program starts.
+    pub(crate) initial: Vec<(usize, Value<'p>)>,
+    /// The function each function declaration was compiled to, by the address of the
+    /// declaration, for calls to a function that was passed around as a value.
+    pub(crate) declared: HashMap<*const FunctionDeclaration, usize>,
+    /// The names of every variant, which a bare name in a pattern matches.
+    pub(crate) variants: HashSet<&'p str>,
+}
+
+#[derive(Debug)]
+pub struct Function<'p> {
+    /// The declared name, or `<closure>` or `<top level>`.
+    pub name: &'p str,
+    pub params: &'p [Parameter],
+    /// Whether the function is a patch method, whose receiver is passed before its arguments
+    /// and is the variable `self`.
+    pub receiver: bool,
+    pub code: Vec<Instruction<'p>>,
+    /// The statement or expression each instruction was compiled from, if any, which is where
+    /// the function is when a runtime error stops it there.
+    pub nodes: Vec<Option<NodeId>>,
+    /// The values [`Instruction::Constant`] pushes.
+    pub constants: Vec<Value<'p>>,
+}
+
+/// How a closure gets the cell of a variable it captures.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Capture {
+    /// From a local of the function that creates the closure.
+    Local(usize),
+    /// From a variable the function creating the closure captured itself.
+    Capture(usize),
+}
+
+/// One step of a compiled function. The stack effect of each is given as the values it pops
+/// and then pushes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction<'p> {
+    /// Push constant `n` of the function.
+    Constant(usize),
+    Unit,
+    /// Pop `n` values.
+    Pop(usize),
+    /// Pop the `n` values under the top one, which ends a scope that declared `n` variables.
+    PopUnder(usize),
+    /// Push the value of local `n`; for a local in a cell, the cell.
+    GetLocal(usize),
+    /// Pop a value into local `n`, which is not in a cell.
+    SetLocal(usize),
+    /// Replace the top value with a new cell holding it.
+    MakeCell,
+    /// Push the value in the cell local `n` holds.
+    GetCell(usize),
+    /// Push the value of captured variable `n`.
+    GetCapture(usize),
+    /// Push the cell of captured variable `n`.
+    CaptureCell(usize),
+    /// Push the value of global `n`, which fails if it has none yet.
+    GetGlobal(usize),
+    /// Pop a value into a new cell for global `n`, as a top-level variable declaration does.
+    DefineGlobal(usize),
+    /// Push the cell of global `n`.
+    GlobalCell(usize),
+    /// Push the host function with this name, which fails if the machine was not given one.
+    GetHost(&'p str),
+    /// Pop two operands and push the result.
+    Binary(BinaryOperator, Fit),
+    Unary(UnaryOperator, Fit),
+    Cast(&'p Type),
+    Dereference,
+    /// Follow references until the top value is not one, as field access, method calls,
+    /// indexing, and loops do.
+    AutoDereference,
+    Field(&'p str),
+    /// Pop an index and a collection and push the element.
+    Index,
+    /// Pop `n` values and push an array of them.
+    Array(usize),
+    /// Pop the end and start of a range and push the range.
+    Range {
+        inclusive: bool,
+    },
+    /// Pop the values of `fields`, then the record after `..` if there is one, and push the
+    /// record.
+    Record {
+        name: &'p str,
+        declaration: Option<&'p RecordDeclaration>,
+        fields: Vec<&'p str>,
+        base: bool,
+    },
+    /// Push a variant, popping its payload first if it has one.
+    Variant {
+        name: &'p str,
+        payload: bool,
+    },
+    /// Push a closure of function `function` that captures `captures`.
+    Closure {
+        function: usize,
+        captures: Vec<Capture>,
+    },
+    /// Pop `n` arguments and the function under them, call it, and push its result.
+    Call(usize),
+    /// Pop `arguments` arguments, call function `function` with them, and push its result.
+    CallFunction {
+        function: usize,
+        arguments: usize,
+    },
+    /// Pop the result and return it to the caller.
+    Return,
+    Jump(usize),
+    /// Pop a condition and jump if it is false.
+    JumpIfFalse(usize),
+    /// Pop a value and, if it matches `pattern`, push the values of `bindings` in order, each
+    /// in a cell if its flag says so. Otherwise jump to `otherwise`.
+    Match {
+        pattern: &'p Pattern,
+        bindings: Vec<(&'p str, bool)>,
+        otherwise: usize,
+    },
+    /// Pop what a `for` loop goes through and push the state of the loop, which takes two
+    /// slots.
+    Iterate,
+    /// Push the next element of the loop whose state is in local `state`, or jump to `exit`
+    /// after the last one.
+    Next {
+        state: usize,
+        exit: usize,
+    },
+    /// Pop a reference and push a reference to its field `field`, following any references
+    /// it holds first.
+    FieldPlace(&'p str),
+    /// Pop an index and a reference and push a reference to the element, following any
+    /// references it holds first.
+    IndexPlace,
+    /// Check that the top value is a reference, to assign through.
+    ReferencePlace,
+    /// Replace the top value with a reference to a copy of it that no variable holds.
+    Temporary,
+    /// Make the reference on top of the stack a value of the program.
+    Borrow {
+        is_mutable: bool,
+    },
+    /// Pop a reference and a value and store the value through the reference.
+    Assign,
+    /// Pop a reference and a value and store the result of the operator applied to what the
+    /// reference refers to and the value.
+    CompoundAssign(BinaryOperator, Fit),
+    /// Stop with an error, for code the checker lets through but that can only fail.
+    Fail(RuntimeError),
+    /// Pop `arguments` arguments and a receiver, and stop because no patch gives the receiver
+    /// the method.
+    NoMethod {
+        method: &'p str,
+        arguments: usize,
+    },
+}
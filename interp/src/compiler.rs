@@ -0,0 +1,1074 @@
+//! Compiling a checked program to [bytecode](crate::bytecode) for the [`Vm`](crate::vm::Vm).
+//!
+//! The compiler makes the decisions the interpreter makes each time it evaluates a node once,
+//! ahead of time: where each variable lives, which function a call by name or a method call
+//! runs, and what each arithmetic result must fit in.
+
+use crate::builtins::Builtin;
+use crate::bytecode::{Bytecode, Capture, Fit, Function, Instruction};
+use crate::interpreter::RuntimeError;
+use crate::operations;
+use crate::pattern::{names, type_name};
+use crate::value::Value;
+use parser::ast::{
+    BinaryOperator, Declaration, Expression, ExpressionKind, FunctionDeclaration, Program,
+    RecordDeclaration, Statement, StatementKind, UnaryOperator, WhenBranch,
+};
+use parser::node_id::{NodeId, NodeIndex};
+use parser::visit::{Visitor, walk_expression, walk_statement};
+use semantics::TypeMap;
+use std::collections::{HashMap, HashSet};
+
+/// Compile `program`, which must have been resolved and type checked without errors, whose
+/// nodes `index` numbers and whose expressions `types` gives the types of.
+pub fn compile<'p>(program: &'p Program, index: &'p NodeIndex, types: &'p TypeMap) -> Bytecode<'p> {
+    Compiler::new(program, index, types).compile()
+}
+
+struct Compiler<'p> {
+    index: &'p NodeIndex,
+    types: &'p TypeMap,
+    bytecode: Bytecode<'p>,
+    globals: HashMap<&'p str, usize>,
+    /// Top-level functions, which a call by name runs without looking the name up.
+    functions: HashMap<&'p str, usize>,
+    /// Patch methods, by the node that declares them.
+    methods: HashMap<NodeId, usize>,
+    records: HashMap<&'p str, &'p RecordDeclaration>,
+    /// The function being compiled and every function it is written in, innermost last.
+    scopes: Vec<Scope<'p>>,
+    /// The innermost statement or expression being compiled.
+    node: Option<NodeId>,
+}
+
+#[derive(PartialEq)]
+enum Kind {
+    TopLevel,
+    Function,
+    Closure,
+}
+
+/// A function being compiled.
+struct Scope<'p> {
+    kind: Kind,
+    code: Vec<Instruction<'p>>,
+    nodes: Vec<Option<NodeId>>,
+    constants: Vec<Value<'p>>,
+    locals: Vec<Local<'p>>,
+    captures: Vec<(&'p str, Capture)>,
+    /// The variables kept in cells; see [`cells`].
+    cells: HashSet<String>,
+    loops: Vec<Loop>,
+    /// How many values are on the stack above the first argument.
+    height: usize,
+}
+
+struct Local<'p> {
+    /// Empty for the slots the compiler keeps for itself, such as the state of a `for` loop.
+    name: &'p str,
+    slot: usize,
+    cell: bool,
+}
+
+struct Loop {
+    /// The height of the stack in the loop, which a `break` unwinds to.
+    height: usize,
+    /// Whether `break` gives the loop its value, as it does for `loop` but not `for`.
+    valued: bool,
+    /// The jumps of the loop's `break`s, to be pointed at its end.
+    breaks: Vec<usize>,
+}
+
+/// Where a name used in an expression is found.
+enum Variable {
+    Local { slot: usize, cell: bool },
+    Capture(usize),
+    Global(usize),
+    Builtin(&'static Builtin),
+    Unknown,
+}
+
+impl<'p> Compiler<'p> {
+    fn new(program: &'p Program, index: &'p NodeIndex, types: &'p TypeMap) -> Self {
+        let mut compiler = Compiler {
+            index,
+            types,
+            bytecode: Bytecode {
+                program,
+                functions: Vec::new(),
+                globals: Vec::new(),
+                initial: Vec::new(),
+                declared: HashMap::new(),
+                variants: HashSet::new(),
+            },
+            globals: HashMap::new(),
+            functions: HashMap::new(),
+            methods: HashMap::new(),
+            records: HashMap::new(),
+            scopes: Vec::new(),
+            node: None,
+        };
+        compiler.bytecode.functions.push(Function {
+            name: "<top level>",
+            params: &[],
+            receiver: false,
+            code: Vec::new(),
+            nodes: Vec::new(),
+            constants: Vec::new(),
+        });
+        for declaration in &program.declarations {
+            match declaration {
+                Declaration::Function(function) => {
+                    let id = compiler.declare(function, false);
+                    compiler.functions.insert(&function.name, id);
+                    let global = compiler.global(&function.name);
+                    compiler
+                        .bytecode
+                        .initial
+                        .push((global, Value::Function(function)));
+                }
+                Declaration::Patch(patch) => {
+                    for method in &patch.methods {
+                        let id = compiler.declare(method, true);
+                        compiler
+                            .methods
+                            .insert(compiler.index.expect_id(method), id);
+                    }
+                }
+                Declaration::Record(record) => {
+                    compiler.records.insert(&record.name, record);
+                }
+                Declaration::Union(union) => {
+                    for variant in &union.variants {
+                        let value = match variant.variant_type {
+                            Some(_) => Value::Constructor(&variant.name),
+                            None => Value::Variant {
+                                name: &variant.name,
+                                payload: None,
+                            },
+                        };
+                        let global = compiler.global(&variant.name);
+                        compiler.bytecode.initial.push((global, value));
+                        compiler.bytecode.variants.insert(&variant.name);
+                    }
+                }
+                Declaration::Const(constant) => {
+                    compiler.global(&constant.name);
+                }
+                Declaration::Statement(Statement {
+                    kind: StatementKind::VariableDeclaration { name, .. },
+                    ..
+                }) => {
+                    compiler.global(name);
+                }
+                Declaration::Statement(_) | Declaration::Import(_) => {}
+            }
+        }
+        compiler
+    }
+
+    fn compile(mut self) -> Bytecode<'p> {
+        let program = self.bytecode.program;
+        let mut cells = Cells::default();
+        for declaration in &program.declarations {
+            if let Declaration::Statement(statement) = declaration {
+                cells.visit_statement(statement);
+            }
+        }
+        self.begin(Kind::TopLevel, cells.names);
+        for declaration in &program.declarations {
+            match declaration {
+                Declaration::Statement(Statement {
+                    kind: StatementKind::VariableDeclaration { name, value, .. },
+                    ..
+                }) => {
+                    self.expression(value);
+                    let global = self.globals[name.as_str()];
+                    self.emit(Instruction::DefineGlobal(global));
+                }
+                Declaration::Statement(statement) => self.statement(statement),
+                _ => {}
+            }
+        }
+        match self.functions.get("main") {
+            Some(&main) => self.emit(Instruction::CallFunction {
+                function: main,
+                arguments: 0,
+            }),
+            None => self.emit(Instruction::Unit),
+        };
+        self.emit(Instruction::Return);
+        self.end(0);
+
+        for declaration in &program.declarations {
+            match declaration {
+                Declaration::Function(function) => self.function(function, false),
+                Declaration::Patch(patch) => {
+                    for method in &patch.methods {
+                        self.function(method, true);
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.bytecode
+    }
+
+    /// What the result of `node` is made to fit.
+    fn fit(&self, node: &Expression) -> Fit {
+        Fit::of(self.types.expression(self.index.expect_id(node)))
+    }
+
+    fn declare(&mut self, function: &'p FunctionDeclaration, receiver: bool) -> usize {
+        let id = self.bytecode.functions.len();
+        self.bytecode.functions.push(Function {
+            name: &function.name,
+            params: &function.params,
+            receiver,
+            code: Vec::new(),
+            nodes: Vec::new(),
+            constants: Vec::new(),
+        });
+        self.bytecode.declared.insert(function, id);
+        id
+    }
+
+    fn global(&mut self, name: &'p str) -> usize {
+        *self.globals.entry(name).or_insert_with(|| {
+            self.bytecode.globals.push(name);
+            self.bytecode.globals.len() - 1
+        })
+    }
+
+    fn function(&mut self, function: &'p FunctionDeclaration, receiver: bool) {
+        let id = self.bytecode.declared[&(function as *const _)];
+        let mut cells = Cells::default();
+        cells.visit_expression(&function.body);
+        self.begin(Kind::Function, cells.names);
+        if receiver {
+            self.parameter("self");
+        }
+        for parameter in &function.params {
+            self.parameter(&parameter.name);
+        }
+        self.expression(&function.body);
+        self.emit(Instruction::Return);
+        self.end(id);
+    }
+
+    fn begin(&mut self, kind: Kind, cells: HashSet<String>) {
+        self.scopes.push(Scope {
+            kind,
+            code: Vec::new(),
+            nodes: Vec::new(),
+            constants: Vec::new(),
+            locals: Vec::new(),
+            captures: Vec::new(),
+            cells,
+            loops: Vec::new(),
+            height: 0,
+        });
+    }
+
+    /// Finish the function being compiled as function `id`, returning what it captures.
+    fn end(&mut self, id: usize) -> Vec<Capture> {
+        let scope = self.scopes.pop().expect("a function is being compiled");
+        let function = &mut self.bytecode.functions[id];
+        function.code = scope.code;
+        function.nodes = scope.nodes;
+        function.constants = scope.constants;
+        scope
+            .captures
+            .into_iter()
+            .map(|(_, capture)| capture)
+            .collect()
+    }
+
+    fn scope(&mut self) -> &mut Scope<'p> {
+        self.scopes
+            .last_mut()
+            .expect("a function is being compiled")
+    }
+
+    fn parameter(&mut self, name: &'p str) {
+        let slot = self.scope().height;
+        self.scope().height += 1;
+        let cell = self.declare_local(name, slot);
+        if cell {
+            self.emit(Instruction::GetLocal(slot));
+            self.emit(Instruction::MakeCell);
+            self.emit(Instruction::SetLocal(slot));
+        }
+    }
+
+    /// Make the value in `slot` the variable `name`, returning whether it is kept in a cell.
+    fn declare_local(&mut self, name: &'p str, slot: usize) -> bool {
+        let scope = self.scope();
+        let cell = scope.cells.contains(name);
+        scope.locals.push(Local { name, slot, cell });
+        cell
+    }
+
+    /// Declare the value on top of the stack as the variable `name`.
+    fn local(&mut self, name: &'p str) {
+        if self.scope().cells.contains(name) {
+            self.emit(Instruction::MakeCell);
+        }
+        let slot = self.scope().height - 1;
+        self.declare_local(name, slot);
+    }
+
+    /// Drop the variables declared since there were `count`, keeping the value on top.
+    fn end_locals(&mut self, count: usize) {
+        let declared = self.scope().locals.len() - count;
+        if declared > 0 {
+            self.emit(Instruction::PopUnder(declared));
+            self.scope().locals.truncate(count);
+        }
+    }
+
+    fn emit(&mut self, instruction: Instruction<'p>) -> usize {
+        let (pops, pushes) = effect(&instruction);
+        let node = self.node;
+        let scope = self.scope();
+        scope.height = scope.height - pops + pushes;
+        scope.code.push(instruction);
+        scope.nodes.push(node);
+        scope.code.len() - 1
+    }
+
+    fn constant(&mut self, value: Value<'p>) {
+        let scope = self.scope();
+        scope.constants.push(value);
+        let constant = scope.constants.len() - 1;
+        self.emit(Instruction::Constant(constant));
+    }
+
+    /// Point the jump at `jump` to the next instruction.
+    fn patch(&mut self, jump: usize) {
+        let scope = self.scope();
+        let next = scope.code.len();
+        match &mut scope.code[jump] {
+            Instruction::Jump(target)
+            | Instruction::JumpIfFalse(target)
+            | Instruction::Match {
+                otherwise: target, ..
+            }
+            | Instruction::Next { exit: target, .. } => *target = next,
+            instruction => unreachable!("{:?} is not a jump", instruction),
+        }
+    }
+
+    fn variable(&mut self, name: &'p str) -> Variable {
+        let depth = self.scopes.len() - 1;
+        if let Some(local) = self.scopes[depth].find(name) {
+            return Variable::Local {
+                slot: local.slot,
+                cell: local.cell,
+            };
+        }
+        if let Some(capture) = self.capture(depth, name) {
+            return Variable::Capture(capture);
+        }
+        if let Some(&global) = self.globals.get(name) {
+            return Variable::Global(global);
+        }
+        match Builtin::named(name) {
+            Some(builtin) => Variable::Builtin(builtin),
+            None => Variable::Unknown,
+        }
+    }
+
+    /// The capture through which the closure at `depth` reaches the variable `name` of a
+    /// function it is written in, adding one if it has none yet.
+    fn capture(&mut self, depth: usize, name: &'p str) -> Option<usize> {
+        let scope = &self.scopes[depth];
+        if scope.kind != Kind::Closure {
+            return None;
+        }
+        if let Some(position) = scope
+            .captures
+            .iter()
+            .position(|(captured, _)| *captured == name)
+        {
+            return Some(position);
+        }
+        let capture = match self.scopes[depth - 1].find(name) {
+            Some(local) => Capture::Local(local.slot),
+            None => Capture::Capture(self.capture(depth - 1, name)?),
+        };
+        let captures = &mut self.scopes[depth].captures;
+        captures.push((name, capture));
+        Some(captures.len() - 1)
+    }
+
+    fn statement(&mut self, statement: &'p Statement) {
+        let outer = self.node.replace(self.index.expect_id(statement));
+        match &statement.kind {
+            StatementKind::VariableDeclaration { name, value, .. } => {
+                self.expression(value);
+                self.local(name);
+            }
+            StatementKind::Assignment {
+                target,
+                operator,
+                value,
+            } => self.assignment(target, *operator, value),
+            StatementKind::Expression(expression) => {
+                self.expression(expression);
+                self.emit(Instruction::Pop(1));
+            }
+            StatementKind::Return(value) => {
+                self.optional(value.as_deref());
+                if self.scope().kind == Kind::TopLevel {
+                    self.emit(Instruction::Pop(1));
+                    self.emit(Instruction::Fail(RuntimeError::ReturnOutsideFunction));
+                } else {
+                    self.emit(Instruction::Return);
+                }
+            }
+            StatementKind::Break(value) => self.break_loop(value.as_deref()),
+        }
+        self.node = outer;
+    }
+
+    fn assignment(
+        &mut self,
+        target: &'p Expression,
+        operator: BinaryOperator,
+        value: &'p Expression,
+    ) {
+        let compound = operator.compound_operator();
+        if let ExpressionKind::Identifier(name) = &target.ungrouped().kind
+            && let Variable::Local { slot, cell: false } = self.variable(name)
+        {
+            // Nothing can change a variable outside a cell while the value is evaluated, so
+            // it can be read first.
+            if let Some(operator) = compound {
+                self.emit(Instruction::GetLocal(slot));
+                self.expression(value);
+                self.emit(Instruction::Binary(operator, self.fit(target)));
+            } else {
+                self.expression(value);
+            }
+            self.emit(Instruction::SetLocal(slot));
+            return;
+        }
+        self.expression(value);
+        self.place(target);
+        match compound {
+            Some(operator) => self.emit(Instruction::CompoundAssign(operator, self.fit(target))),
+            None => self.emit(Instruction::Assign),
+        };
+    }
+
+    fn break_loop(&mut self, value: Option<&'p Expression>) {
+        let height = self.scope().height;
+        self.optional(value);
+        let Some(valued) = self.scope().loops.last().map(|inner| inner.valued) else {
+            self.emit(Instruction::Pop(1));
+            self.emit(Instruction::Fail(RuntimeError::BreakOutsideLoop));
+            return;
+        };
+        if !valued {
+            self.emit(Instruction::Pop(1));
+            self.emit(Instruction::Unit);
+        }
+        let scope = self.scope();
+        let unwound = scope.height - 1 - scope.loops.last().map_or(0, |inner| inner.height);
+        if unwound > 0 {
+            self.emit(Instruction::PopUnder(unwound));
+        }
+        let jump = self.emit(Instruction::Jump(0));
+        let scope = self.scope();
+        if let Some(inner) = scope.loops.last_mut() {
+            inner.breaks.push(jump);
+        }
+        // The code after a `break` never runs, but is compiled as if the `break` had not
+        // changed the stack.
+        scope.height = height;
+    }
+
+    fn optional(&mut self, expression: Option<&'p Expression>) {
+        match expression {
+            Some(expression) => self.expression(expression),
+            None => {
+                self.emit(Instruction::Unit);
+            }
+        }
+    }
+
+    fn expression(&mut self, expression: &'p Expression) {
+        let outer = self.node.replace(self.index.expect_id(expression));
+        match &expression.kind {
+            ExpressionKind::Literal(literal) => match Value::from(literal) {
+                Value::Float(value) => {
+                    self.constant(operations::float(value, self.fit(expression)));
+                }
+                value => self.constant(value),
+            },
+            ExpressionKind::Identifier(name) => match self.variable(name) {
+                Variable::Local { slot, cell: false } => {
+                    self.emit(Instruction::GetLocal(slot));
+                }
+                Variable::Local { slot, cell: true } => {
+                    self.emit(Instruction::GetCell(slot));
+                }
+                Variable::Capture(capture) => {
+                    self.emit(Instruction::GetCapture(capture));
+                }
+                Variable::Global(global) => {
+                    self.emit(Instruction::GetGlobal(global));
+                }
+                Variable::Builtin(builtin) => self.constant(Value::Builtin(builtin)),
+                // Host functions are only known once the program runs.
+                Variable::Unknown => {
+                    self.emit(Instruction::GetHost(name));
+                }
+            },
+            ExpressionKind::BinaryOperation {
+                left,
+                operator: BinaryOperator::And,
+                right,
+            } => {
+                self.expression(left);
+                let short = self.emit(Instruction::JumpIfFalse(0));
+                self.expression(right);
+                let end = self.emit(Instruction::Jump(0));
+                self.patch(short);
+                self.scope().height -= 1;
+                self.constant(Value::Boolean(false));
+                self.patch(end);
+            }
+            ExpressionKind::BinaryOperation {
+                left,
+                operator: BinaryOperator::Or,
+                right,
+            } => {
+                self.expression(left);
+                let long = self.emit(Instruction::JumpIfFalse(0));
+                self.constant(Value::Boolean(true));
+                let end = self.emit(Instruction::Jump(0));
+                self.patch(long);
+                self.scope().height -= 1;
+                self.expression(right);
+                self.patch(end);
+            }
+            ExpressionKind::BinaryOperation {
+                left,
+                operator,
+                right,
+            } => {
+                self.expression(left);
+                self.expression(right);
+                self.emit(Instruction::Binary(*operator, self.fit(expression)));
+            }
+            ExpressionKind::UnaryOperation { operator, operand } => match operator {
+                UnaryOperator::Reference | UnaryOperator::MutableReference => {
+                    self.reference(operand, *operator == UnaryOperator::MutableReference);
+                }
+                UnaryOperator::Dereference => {
+                    self.expression(operand);
+                    self.emit(Instruction::Dereference);
+                }
+                UnaryOperator::Not | UnaryOperator::Negate => {
+                    self.expression(operand);
+                    self.emit(Instruction::Unary(*operator, self.fit(expression)));
+                }
+            },
+            ExpressionKind::FunctionCall {
+                function,
+                arguments,
+            } => {
+                // A call of a top-level function by name runs it directly, unless a variable
+                // hides the function.
+                let direct = match &function.ungrouped().kind {
+                    ExpressionKind::Identifier(name) => match self.variable(name) {
+                        Variable::Global(_) => self.functions.get(name.as_str()).copied(),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                match direct {
+                    Some(function) => {
+                        self.arguments(arguments);
+                        self.emit(Instruction::CallFunction {
+                            function,
+                            arguments: arguments.len(),
+                        });
+                    }
+                    None => {
+                        self.expression(function);
+                        self.arguments(arguments);
+                        self.emit(Instruction::Call(arguments.len()));
+                    }
+                }
+            }
+            ExpressionKind::RecordAccess { record, field } => {
+                self.expression(record);
+                self.emit(Instruction::Field(field));
+            }
+            ExpressionKind::MethodCall {
+                receiver,
+                method,
+                arguments,
+            } => {
+                self.expression(receiver);
+                self.emit(Instruction::AutoDereference);
+                self.arguments(arguments);
+                let declaration = self
+                    .types
+                    .method(self.index.expect_id(expression))
+                    .and_then(|node| self.methods.get(&node).copied());
+                match declaration {
+                    Some(function) => {
+                        self.emit(Instruction::CallFunction {
+                            function,
+                            arguments: arguments.len() + 1,
+                        });
+                    }
+                    None => {
+                        self.emit(Instruction::NoMethod {
+                            method,
+                            arguments: arguments.len(),
+                        });
+                        self.emit(Instruction::Unit);
+                    }
+                }
+            }
+            ExpressionKind::IndexAccess { collection, index } => {
+                self.expression(collection);
+                self.emit(Instruction::AutoDereference);
+                self.expression(index);
+                self.emit(Instruction::Index);
+            }
+            ExpressionKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(condition);
+                let otherwise = self.emit(Instruction::JumpIfFalse(0));
+                self.expression(then_branch);
+                let end = self.emit(Instruction::Jump(0));
+                self.patch(otherwise);
+                self.scope().height -= 1;
+                self.optional(else_branch.as_deref());
+                self.patch(end);
+            }
+            ExpressionKind::When {
+                expression: value,
+                branches,
+            } => self.when(expression, value, branches),
+            ExpressionKind::Block {
+                statements,
+                final_expression,
+            } => {
+                let count = self.scope().locals.len();
+                for statement in statements {
+                    self.statement(statement);
+                }
+                self.optional(final_expression.as_deref());
+                self.end_locals(count);
+            }
+            ExpressionKind::Loop { body } => {
+                let start = self.scope().code.len();
+                self.begin_loop(true);
+                self.expression(body);
+                self.emit(Instruction::Pop(1));
+                self.emit(Instruction::Jump(start));
+                self.end_loop();
+            }
+            ExpressionKind::While { condition, body } => {
+                let start = self.scope().code.len();
+                self.begin_loop(false);
+                self.expression(condition);
+                let exit = self.emit(Instruction::JumpIfFalse(0));
+                self.expression(body);
+                self.emit(Instruction::Pop(1));
+                self.emit(Instruction::Jump(start));
+                self.patch(exit);
+                self.emit(Instruction::Unit);
+                self.end_loop();
+            }
+            ExpressionKind::For {
+                variable,
+                iterable,
+                body,
+            } => {
+                let count = self.scope().locals.len();
+                self.expression(iterable);
+                self.emit(Instruction::Iterate);
+                let state = self.scope().height - 2;
+                self.declare_local("", state);
+                self.declare_local("", state + 1);
+                // A `break` leaves the loop with the element it was given popped, as the last
+                // `Next` does.
+                self.begin_loop(false);
+                let start = self.emit(Instruction::Next { state, exit: 0 });
+                self.local(variable);
+                self.expression(body);
+                self.emit(Instruction::Pop(2));
+                self.scope().locals.pop();
+                self.emit(Instruction::Jump(start));
+                self.patch(start);
+                self.emit(Instruction::Unit);
+                self.end_loop();
+                self.end_locals(count);
+            }
+            ExpressionKind::ArrayLiteral(elements) => {
+                self.arguments(elements);
+                self.emit(Instruction::Array(elements.len()));
+            }
+            ExpressionKind::RecordLiteral {
+                record_type,
+                fields,
+                base,
+            } => {
+                for (_, value) in fields {
+                    self.expression(value);
+                }
+                if let Some(base) = base {
+                    self.expression(base);
+                }
+                let name = type_name(record_type).unwrap_or_default();
+                self.emit(Instruction::Record {
+                    name,
+                    declaration: self.records.get(name).copied(),
+                    fields: fields.iter().map(|(field, _)| field.as_str()).collect(),
+                    base: base.is_some(),
+                });
+            }
+            ExpressionKind::UnionLiteral { variant, value, .. } => {
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+                self.emit(Instruction::Variant {
+                    name: variant,
+                    payload: value.is_some(),
+                });
+            }
+            ExpressionKind::Reference {
+                is_mutable,
+                expression,
+            } => self.reference(expression, *is_mutable),
+            ExpressionKind::Dereference(expression) => {
+                self.expression(expression);
+                self.emit(Instruction::Dereference);
+            }
+            ExpressionKind::Grouped(expression)
+            | ExpressionKind::TypeAnnotation { expression, .. } => {
+                self.expression(expression);
+            }
+            ExpressionKind::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                self.expression(start);
+                self.expression(end);
+                self.emit(Instruction::Range {
+                    inclusive: *inclusive,
+                });
+            }
+            ExpressionKind::Cast {
+                expression: value,
+                target,
+            } => {
+                self.expression(value);
+                self.emit(Instruction::Cast(target));
+            }
+            ExpressionKind::Closure { params, body, .. } => {
+                let id = self.bytecode.functions.len();
+                self.bytecode.functions.push(Function {
+                    name: "<closure>",
+                    params,
+                    receiver: false,
+                    code: Vec::new(),
+                    nodes: Vec::new(),
+                    constants: Vec::new(),
+                });
+                let mut cells = Cells::default();
+                cells.visit_expression(body);
+                self.begin(Kind::Closure, cells.names);
+                for parameter in params {
+                    self.parameter(&parameter.name);
+                }
+                self.expression(body);
+                self.emit(Instruction::Return);
+                let captures = self.end(id);
+                self.emit(Instruction::Closure {
+                    function: id,
+                    captures,
+                });
+            }
+        }
+        self.node = outer;
+    }
+
+    fn arguments(&mut self, arguments: &'p [Expression]) {
+        for argument in arguments {
+            self.expression(argument);
+        }
+    }
+
+    /// Stop with `error` where an expression's value was expected.
+    fn fail(&mut self, error: RuntimeError) {
+        self.emit(Instruction::Fail(error));
+        // Never runs, but keeps the stack the shape the expression promises.
+        self.emit(Instruction::Unit);
+    }
+
+    fn begin_loop(&mut self, valued: bool) {
+        let height = self.scope().height;
+        self.scope().loops.push(Loop {
+            height,
+            valued,
+            breaks: Vec::new(),
+        });
+    }
+
+    /// Point the loop's `break`s at the next instruction, where its value is on the stack.
+    fn end_loop(&mut self) {
+        let inner = self.scope().loops.pop().expect("a loop is being compiled");
+        for jump in inner.breaks {
+            self.patch(jump);
+        }
+        self.scope().height = inner.height + 1;
+    }
+
+    fn when(&mut self, when: &Expression, value: &'p Expression, branches: &'p [WhenBranch]) {
+        let count = self.scope().locals.len();
+        self.expression(value);
+        let scrutinee = self.scope().height - 1;
+        self.declare_local("", scrutinee);
+        let mut ends = Vec::new();
+        for branch in branches {
+            let bindings: Vec<(&str, bool)> = names(&branch.pattern, &self.bytecode.variants)
+                .into_iter()
+                .map(|name| (name, self.scope().cells.contains(name)))
+                .collect();
+            let bound = bindings.len();
+            let height = self.scope().height;
+            self.emit(Instruction::GetLocal(scrutinee));
+            let next = self.emit(Instruction::Match {
+                pattern: &branch.pattern,
+                bindings: bindings.clone(),
+                otherwise: 0,
+            });
+            for (offset, (name, _)) in bindings.into_iter().enumerate() {
+                let scope = self.scope();
+                scope.locals.push(Local {
+                    name,
+                    slot: height + offset,
+                    cell: scope.cells.contains(name),
+                });
+            }
+            let guard = branch.guard.as_deref().map(|guard| {
+                self.expression(guard);
+                self.emit(Instruction::JumpIfFalse(0))
+            });
+            self.expression(&branch.body);
+            self.emit(Instruction::PopUnder(bound));
+            ends.push(self.emit(Instruction::Jump(0)));
+            self.scope().height = height + bound;
+            if let Some(guard) = guard {
+                self.patch(guard);
+                self.emit(Instruction::Pop(bound));
+            }
+            self.scope().locals.truncate(count + 1);
+            self.scope().height = height;
+            self.patch(next);
+        }
+        // A `when` whose value is unused falls through.
+        if self.types.is_discarded(self.index.expect_id(when)) {
+            self.emit(Instruction::Unit);
+        } else {
+            self.fail(RuntimeError::NoMatchingBranch);
+        }
+        for end in ends {
+            self.patch(end);
+        }
+        self.end_locals(count);
+    }
+
+    /// Push the reference `&expression` makes.
+    fn reference(&mut self, expression: &'p Expression, is_mutable: bool) {
+        if expression.is_lvalue() {
+            self.place(expression);
+        } else {
+            self.expression(expression);
+            self.emit(Instruction::Temporary);
+        }
+        self.emit(Instruction::Borrow { is_mutable });
+    }
+
+    /// Push a reference to the storage an assignment to `target` writes to.
+    fn place(&mut self, target: &'p Expression) {
+        match &target.kind {
+            ExpressionKind::Grouped(target) => self.place(target),
+            ExpressionKind::Identifier(name) => match self.variable(name) {
+                Variable::Local { slot, cell: true } => {
+                    self.emit(Instruction::GetLocal(slot));
+                }
+                Variable::Local { slot, cell: false } => {
+                    // Not reached: `cells` keeps every variable that is referenced or has a
+                    // part assigned to in a cell.
+                    self.emit(Instruction::GetLocal(slot));
+                    self.emit(Instruction::Temporary);
+                }
+                Variable::Capture(capture) => {
+                    self.emit(Instruction::CaptureCell(capture));
+                }
+                Variable::Global(global) => {
+                    self.emit(Instruction::GlobalCell(global));
+                }
+                Variable::Builtin(_) | Variable::Unknown => {
+                    self.fail(RuntimeError::UnknownName { name: name.clone() });
+                }
+            },
+            ExpressionKind::RecordAccess { record, field } => {
+                self.place(record);
+                self.emit(Instruction::FieldPlace(field));
+            }
+            ExpressionKind::IndexAccess { collection, index } => {
+                self.place(collection);
+                self.expression(index);
+                self.emit(Instruction::IndexPlace);
+            }
+            ExpressionKind::Dereference(operand)
+            | ExpressionKind::UnaryOperation {
+                operator: UnaryOperator::Dereference,
+                operand,
+            } => {
+                self.expression(operand);
+                self.emit(Instruction::ReferencePlace);
+            }
+            _ => {
+                self.expression(target);
+                self.emit(Instruction::Temporary);
+            }
+        }
+    }
+}
+
+impl<'p> Scope<'p> {
+    /// The innermost variable named `name`.
+    fn find(&self, name: &str) -> Option<&Local<'p>> {
+        self.locals.iter().rev().find(|local| local.name == name)
+    }
+}
+
+/// The values `instruction` pops and then pushes, on the path that continues to the next
+/// instruction.
+fn effect(instruction: &Instruction) -> (usize, usize) {
+    match instruction {
+        Instruction::Constant(_)
+        | Instruction::Unit
+        | Instruction::GetLocal(_)
+        | Instruction::GetCell(_)
+        | Instruction::GetCapture(_)
+        | Instruction::CaptureCell(_)
+        | Instruction::GetGlobal(_)
+        | Instruction::GlobalCell(_)
+        | Instruction::GetHost(_)
+        | Instruction::Closure { .. } => (0, 1),
+        Instruction::Pop(count) => (*count, 0),
+        Instruction::PopUnder(count) => (count + 1, 1),
+        Instruction::SetLocal(_)
+        | Instruction::DefineGlobal(_)
+        | Instruction::JumpIfFalse(_)
+        | Instruction::Return => (1, 0),
+        Instruction::MakeCell
+        | Instruction::Unary(..)
+        | Instruction::Cast(_)
+        | Instruction::Dereference
+        | Instruction::AutoDereference
+        | Instruction::Field(_)
+        | Instruction::FieldPlace(_)
+        | Instruction::ReferencePlace
+        | Instruction::Temporary
+        | Instruction::Borrow { .. } => (1, 1),
+        Instruction::Binary(..)
+        | Instruction::Index
+        | Instruction::Range { .. }
+        | Instruction::IndexPlace => (2, 1),
+        Instruction::Array(count) => (*count, 1),
+        Instruction::Record { fields, base, .. } => (fields.len() + usize::from(*base), 1),
+        Instruction::Variant { payload, .. } => (usize::from(*payload), 1),
+        Instruction::Call(arguments) => (arguments + 1, 1),
+        Instruction::CallFunction { arguments, .. } => (*arguments, 1),
+        Instruction::Jump(_) | Instruction::Fail(_) => (0, 0),
+        Instruction::Match { bindings, .. } => (1, bindings.len()),
+        Instruction::Iterate => (1, 2),
+        Instruction::Next { .. } => (0, 1),
+        Instruction::Assign | Instruction::CompoundAssign(..) => (2, 0),
+        Instruction::NoMethod { arguments, .. } => (arguments + 1, 0),
+    }
+}
+
+/// Collects the names of the variables of a function that are kept in cells: those a closure
+/// written in it uses, those that are referenced, and those with a field or element assigned
+/// to. Names are collected without regard to which of several variables of the same name is
+/// meant, which at worst keeps a variable in a cell that did not need one.
+#[derive(Default)]
+struct Cells {
+    names: HashSet<String>,
+    /// How many closures the node being visited is in.
+    closures: usize,
+}
+
+impl Visitor for Cells {
+    fn visit_statement(&mut self, statement: &Statement) {
+        if let StatementKind::Assignment { target, .. } = &statement.kind
+            && !matches!(&target.ungrouped().kind, ExpressionKind::Identifier(_))
+            && let Some(root) = root(target)
+        {
+            self.names.insert(root.to_string());
+        }
+        walk_statement(self, statement);
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        match &expression.kind {
+            ExpressionKind::Identifier(name) if self.closures > 0 => {
+                self.names.insert(name.clone());
+            }
+            ExpressionKind::Reference {
+                expression: operand,
+                ..
+            }
+            | ExpressionKind::UnaryOperation {
+                operator: UnaryOperator::Reference | UnaryOperator::MutableReference,
+                operand,
+            } => {
+                if operand.is_lvalue()
+                    && let Some(root) = root(operand)
+                {
+                    self.names.insert(root.to_string());
+                }
+            }
+            ExpressionKind::Closure { .. } => {
+                self.closures += 1;
+                walk_expression(self, expression);
+                self.closures -= 1;
+                return;
+            }
+            _ => {}
+        }
+        walk_expression(self, expression);
+    }
+}
+
+/// The variable whose storage the place `expression` is part of, if it is one.
+fn root(expression: &Expression) -> Option<&str> {
+    match &expression.kind {
+        ExpressionKind::Identifier(name) => Some(name),
+        ExpressionKind::Grouped(inner)
+        | ExpressionKind::RecordAccess { record: inner, .. }
+        | ExpressionKind::IndexAccess {
+            collection: inner, ..
+        } => root(inner),
+        _ => None,
+    }
+}
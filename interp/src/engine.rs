@@ -0,0 +1,239 @@
+//! Checking and running CV programs from a Rust application, with host functions it provides.
+//!
+//! An [`Engine`] does what `cv run --vm` does, and a program it runs can also call the
+//! [host functions](crate::host) registered with it, which the type checker knows the types of.
+
+use crate::builtins::Builtin;
+use crate::compiler::compile;
+use crate::host::{HostFunction, HostValue, IntoHostFunction};
+use crate::interpreter::RuntimeError;
+use crate::vm::Vm;
+use parser::ParseError;
+use parser::node_id::NodeIndex;
+use semantics::{Diagnostic, Host, Severity, ShadowingLint};
+use std::rc::Rc;
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Clone)]
+pub enum EngineError {
+    #[error("{0}")]
+    Parse(#[from] ParseError),
+    /// The errors checking the program found.
+    #[error("{}", messages(.0))]
+    Check(Vec<Diagnostic>),
+    #[error("Runtime error: {0}")]
+    Runtime(#[from] RuntimeError),
+}
+
+/// The messages of `diagnostics`, one per line.
+fn messages(diagnostics: &[Diagnostic]) -> String {
+    let messages: Vec<&str> = diagnostics
+        .iter()
+        .map(|diagnostic| diagnostic.message.as_str())
+        .collect();
+    messages.join("\n")
+}
+
+/// Runs programs, with the [prelude](semantics::prelude) unless told otherwise, on the
+/// [virtual machine](Vm), giving them every function registered with the engine.
+pub struct Engine {
+    hosts: Vec<Rc<HostFunction>>,
+    prelude: bool,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Engine::new()
+    }
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Engine {
+            hosts: Vec::new(),
+            prelude: true,
+        }
+    }
+
+    /// Run programs without the prelude.
+    pub fn without_prelude(mut self) -> Self {
+        self.prelude = false;
+        self
+    }
+
+    /// Let programs call `function` as `name`, replacing any function registered with that name
+    /// before. Its parameters and result can be of any type that implements [`HostValue`], and
+    /// the result can also be a `Result`, whose error stops the program. The function takes the
+    /// place of a prelude declaration of the same name, and a program that declares the name
+    /// itself is rejected.
+    ///
+    /// # Panics
+    ///
+    /// If `name` is the name of a builtin.
+    pub fn register_fn<Args>(
+        &mut self,
+        name: &str,
+        function: impl IntoHostFunction<Args>,
+    ) -> &mut Self {
+        assert!(
+            Builtin::named(name).is_none(),
+            "'{}' is the name of a builtin",
+            name
+        );
+        self.hosts.retain(|host| host.name != name);
+        self.hosts.push(Rc::new(HostFunction::new(name, function)));
+        self
+    }
+
+    /// Check `source` and, if it has no errors, run it, returning what `main` returns, or unit
+    /// without it.
+    pub fn run<T: HostValue>(&self, source: &str) -> Result<T, EngineError> {
+        let mut program = parser::parse(source)?;
+        if self.prelude {
+            let names: Vec<&str> = self.hosts.iter().map(|host| host.name.as_str()).collect();
+            program = semantics::prelude::with_prelude_except(program, &names);
+        }
+        let index = NodeIndex::new(&program);
+        let hosts: Vec<Host> = self.hosts.iter().map(|host| host.signature()).collect();
+        let resolution = semantics::resolve_with_hosts(&program, &index, &hosts);
+        let checked = semantics::check_with_hosts(&program, &index, &resolution, &hosts);
+
+        let errors: Vec<Diagnostic> = semantics::check_program(
+            &program,
+            &index,
+            &resolution,
+            &checked,
+            ShadowingLint::SameScope,
+        )
+        .into_iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .collect();
+        if !errors.is_empty() {
+            return Err(EngineError::Check(errors));
+        }
+
+        let bytecode = compile(&program, &index, &checked.types);
+        let mut vm = Vm::new(&bytecode);
+        for host in &self.hosts {
+            vm = vm.with_host(host.clone());
+        }
+        let value = vm.run()?;
+        let found = value.quoted().to_string();
+        T::from_value(value).map_err(|_| {
+            EngineError::Runtime(RuntimeError::ReturnConversion {
+                expected: T::ty(),
+                found,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::ast::Type;
+    use std::cell::RefCell;
+
+    /// The messages of the errors checking `source` finds.
+    fn check_errors(engine: &Engine, source: &str) -> Vec<String> {
+        match engine.run::<()>(source) {
+            Err(EngineError::Check(errors)) => {
+                errors.into_iter().map(|error| error.message).collect()
+            }
+            other => panic!("expected check errors, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_host_functions() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut engine = Engine::new();
+        let written = log.clone();
+        engine
+            .register_fn("log", move |line: String| written.borrow_mut().push(line))
+            .register_fn("scale", |values: Vec<f64>, by: f64| -> Vec<f64> {
+                values.iter().map(|value| value * by).collect()
+            })
+            .register_fn("lookup", |key: char| (key == 'a').then_some(1_u8));
+
+        let total: f64 = engine
+            .run(
+                "fn main() -> f64 {
+                    log(\"start\");
+                    scaled = scale([1.0, 2.5], 2.0);
+                    when lookup('a') { some(n): log(to_string(n)); none: log(\"missing\"); };
+                    log(to_string(lookup('b')));
+                    scaled[0] + scaled[1]
+                }",
+            )
+            .unwrap();
+        assert_eq!(total, 7.0);
+        assert_eq!(*log.borrow(), ["start", "1", "none"]);
+    }
+
+    #[test]
+    fn test_calls_are_checked() {
+        let mut engine = Engine::new();
+        engine.register_fn("double", |n: i32| n * 2);
+
+        assert_eq!(
+            check_errors(&engine, "fn main() { double(\"two\"); }"),
+            ["Expected 'i32', found 'string'"]
+        );
+        assert_eq!(
+            check_errors(&engine, "fn main() { triple(2); }"),
+            ["Cannot find 'triple' in this scope"]
+        );
+    }
+
+    #[test]
+    fn test_host_functions_replace_the_prelude() {
+        let mut engine = Engine::new();
+        engine.register_fn("max", |a: i32, b: i32| a.max(b) * 10);
+
+        assert_eq!(engine.run::<i32>("fn main() -> i32 { max(2, 7) }"), Ok(70));
+    }
+
+    #[test]
+    fn test_declarations_may_not_take_host_names() {
+        let mut engine = Engine::new();
+        engine.register_fn("double", |n: i32| n * 2);
+
+        assert_eq!(
+            check_errors(
+                &engine,
+                "fn double(i32 n) -> i32 { n + n } fn main() { double(2); }"
+            ),
+            ["'double' is already defined as a host function"]
+        );
+        assert_eq!(
+            check_errors(&engine, "double = 2; fn main() {}"),
+            ["'double' is already defined as a host function"]
+        );
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        let mut engine = Engine::new();
+        engine.register_fn("parse", |text: String| text.parse::<i32>());
+
+        assert_eq!(
+            engine.run::<i32>("fn main() -> i32 { parse(\"42\") }"),
+            Ok(42)
+        );
+        assert_eq!(
+            engine.run::<i32>("fn main() -> i32 { parse(\"x\") }"),
+            Err(EngineError::Runtime(RuntimeError::Host {
+                function: "parse".to_string(),
+                message: "invalid digit found in string".to_string()
+            }))
+        );
+        assert_eq!(
+            engine.run::<u8>("fn main() -> i32 { -1 }"),
+            Err(EngineError::Runtime(RuntimeError::ReturnConversion {
+                expected: Type::U8,
+                found: "-1".to_string()
+            }))
+        );
+    }
+}
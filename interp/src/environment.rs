@@ -0,0 +1,87 @@
+//! The variables visible at one point of a running program.
+
+use crate::heap::{Gc, Heap, Trace, Tracer};
+use crate::value::{Slot, Value};
+use std::cell::RefCell;
+
+/// The local variables in scope, innermost declaration first.
+///
+/// Declaring a variable makes a new environment that points at the old one and leaves the old
+/// one unchanged, so a closure keeps seeing exactly the variables that were visible where it was
+/// written, whatever its scope declares after it. Each variable has a [`Slot`] of its own that
+/// assignments write to, which every environment and reference that can see the variable shares.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Environment<'p>(Option<Gc<Binding<'p>>>);
+
+#[derive(Debug)]
+struct Binding<'p> {
+    name: &'p str,
+    slot: Slot<'p>,
+    parent: Environment<'p>,
+}
+
+impl<'p> Environment<'p> {
+    /// This environment with `name` declared as a new variable holding `value`, hiding any
+    /// variable of the same name.
+    pub fn define(&self, heap: &mut Heap<'p>, name: &'p str, value: Value<'p>) -> Self {
+        let slot = heap.allocate(RefCell::new(value));
+        Environment(Some(heap.allocate(Binding {
+            name,
+            slot,
+            parent: self.clone(),
+        })))
+    }
+
+    /// The storage of the innermost variable named `name`.
+    pub fn lookup(&self, name: &str) -> Option<&Slot<'p>> {
+        let mut environment = self;
+        while let Some(binding) = &environment.0 {
+            if binding.name == name {
+                return Some(&binding.slot);
+            }
+            environment = &binding.parent;
+        }
+        None
+    }
+
+    /// Show `tracer` the innermost binding, through which a closure keeps every variable it
+    /// can see.
+    pub fn trace(&self, tracer: &mut Tracer) {
+        if let Some(binding) = &self.0 {
+            tracer.visit(binding);
+        }
+    }
+}
+
+impl Trace for Binding<'_> {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.visit(&self.slot);
+        self.parent.trace(tracer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inner_declaration_hides_outer() {
+        let heap = &mut Heap::default();
+        let outer = Environment::default().define(heap, "x", Value::Integer(1));
+        let inner = outer.define(heap, "x", Value::Integer(2));
+
+        assert_eq!(*inner.lookup("x").unwrap().borrow(), Value::Integer(2));
+        assert_eq!(*outer.lookup("x").unwrap().borrow(), Value::Integer(1));
+        assert!(inner.lookup("y").is_none());
+    }
+
+    #[test]
+    fn test_assignment_is_seen_through_every_environment() {
+        let heap = &mut Heap::default();
+        let outer = Environment::default().define(heap, "x", Value::Integer(1));
+        let inner = outer.define(heap, "y", Value::Integer(2));
+        *inner.lookup("x").unwrap().borrow_mut() = Value::Integer(3);
+
+        assert_eq!(*outer.lookup("x").unwrap().borrow(), Value::Integer(3));
+    }
+}
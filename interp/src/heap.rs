@@ -0,0 +1,239 @@
+//! The storage that running programs share: variables, the environments closures keep, and
+//! closures themselves.
+//!
+//! Values that live on the heap are reference counted, so most are freed as soon as the last
+//! variable, reference, or closure that holds them goes away. Counting alone cannot free a
+//! cycle, such as a closure stored in a variable it captured, so the [`Heap`] keeps track of
+//! everything it allocated and now and then looks for groups of objects that only hold each
+//! other, the way CPython's cycle collector does: it subtracts the references heap objects hold
+//! to each other from their counts, keeps every object something outside the heap still holds
+//! and everything that object reaches, and clears the rest.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Deref;
+use std::rc::{Rc, Weak};
+
+/// How many objects the heap tracks before it first looks for cycles.
+const FIRST_COLLECTION: usize = 1024;
+
+/// A handle to an object on the [`Heap`], which keeps the object alive while it exists.
+pub struct Gc<T: ?Sized>(Rc<T>);
+
+impl<T: ?Sized> Gc<T> {
+    /// Whether two handles are to the same object.
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        Rc::ptr_eq(&this.0, &other.0)
+    }
+
+    /// What identifies the object while it is alive.
+    fn address(&self) -> *const () {
+        Rc::as_ptr(&self.0) as *const ()
+    }
+}
+
+impl<T: ?Sized> Clone for Gc<T> {
+    fn clone(&self) -> Self {
+        Gc(self.0.clone())
+    }
+}
+
+impl<T: ?Sized> Deref for Gc<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Gc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// An object that can live on the [`Heap`].
+pub trait Trace {
+    /// Show `tracer` every handle this object holds itself, each one once, without following
+    /// them into the objects they point to.
+    fn trace(&self, tracer: &mut Tracer);
+
+    /// Drop the handles this object holds, which breaks a cycle it is part of. Only objects
+    /// that can change after they are made can close a cycle, so only they need to do anything.
+    fn clear(&self) {}
+}
+
+/// What [`Trace::trace`] reports handles to.
+pub struct Tracer<'t> {
+    visit: &'t mut dyn FnMut(*const ()),
+}
+
+impl Tracer<'_> {
+    pub fn visit<T: ?Sized>(&mut self, gc: &Gc<T>) {
+        (self.visit)(gc.address());
+    }
+}
+
+/// Every object a running program allocated, for finding the cycles reference counting does
+/// not free.
+pub struct Heap<'p> {
+    objects: Vec<Weak<dyn Trace + 'p>>,
+    /// How many objects the heap may track before it next looks for cycles.
+    threshold: usize,
+}
+
+impl Default for Heap<'_> {
+    fn default() -> Self {
+        Heap {
+            objects: Vec::new(),
+            threshold: FIRST_COLLECTION,
+        }
+    }
+}
+
+impl<'p> Heap<'p> {
+    /// Put `object` on the heap, first looking for cycles if the heap has grown enough since it
+    /// last did.
+    pub fn allocate<T: Trace + 'p>(&mut self, object: T) -> Gc<T> {
+        if self.objects.len() >= self.threshold {
+            self.collect();
+            self.threshold = FIRST_COLLECTION.max(2 * self.objects.len());
+        }
+        let object = Rc::new(object);
+        let weak: Weak<dyn Trace + 'p> = Rc::downgrade(&object) as Weak<dyn Trace + 'p>;
+        self.objects.push(weak);
+        Gc(object)
+    }
+
+    /// How many objects are alive, including unreachable cycles not collected yet.
+    pub fn len(&self) -> usize {
+        self.objects
+            .iter()
+            .filter(|object| object.strong_count() > 0)
+            .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Free every object that only other unreachable objects hold, returning how many there
+    /// were.
+    pub fn collect(&mut self) -> usize {
+        self.objects.retain(|object| object.strong_count() > 0);
+        let objects: Vec<Rc<dyn Trace + 'p>> =
+            self.objects.iter().filter_map(Weak::upgrade).collect();
+        let positions: HashMap<*const (), usize> = objects
+            .iter()
+            .enumerate()
+            .map(|(position, object)| (Rc::as_ptr(object) as *const (), position))
+            .collect();
+        let children = |object: &Rc<dyn Trace + 'p>| {
+            let mut children = Vec::new();
+            object.trace(&mut Tracer {
+                visit: &mut |address| children.extend(positions.get(&address).copied()),
+            });
+            children
+        };
+
+        // The handles to each object that other objects on the heap hold. Whatever else holds
+        // it, apart from `objects` itself, is outside the heap.
+        let mut internal = vec![0; objects.len()];
+        for object in &objects {
+            for child in children(object) {
+                internal[child] += 1;
+            }
+        }
+        let mut reachable = vec![false; objects.len()];
+        let mut pending: Vec<usize> = (0..objects.len())
+            .filter(|&position| Rc::strong_count(&objects[position]) - 1 > internal[position])
+            .collect();
+        while let Some(position) = pending.pop() {
+            if !std::mem::replace(&mut reachable[position], true) {
+                pending.extend(children(&objects[position]));
+            }
+        }
+
+        let mut collected = 0;
+        for (object, reachable) in objects.iter().zip(&reachable) {
+            if !reachable {
+                object.clear();
+                collected += 1;
+            }
+        }
+        // Dropping the last handles frees the cycles the clearing broke.
+        drop(objects);
+        self.objects.retain(|object| object.strong_count() > 0);
+        collected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    /// A node that can point at other nodes, which can be changed to close a cycle.
+    struct Node {
+        children: RefCell<Vec<Gc<Node>>>,
+    }
+
+    impl Trace for Node {
+        fn trace(&self, tracer: &mut Tracer) {
+            for child in self.children.borrow().iter() {
+                tracer.visit(child);
+            }
+        }
+
+        fn clear(&self) {
+            self.children.borrow_mut().clear();
+        }
+    }
+
+    fn node(heap: &mut Heap) -> Gc<Node> {
+        heap.allocate(Node {
+            children: RefCell::new(Vec::new()),
+        })
+    }
+
+    #[test]
+    fn test_unreachable_cycle_is_freed() {
+        let mut heap = Heap::default();
+        let first = node(&mut heap);
+        let second = node(&mut heap);
+        first.children.borrow_mut().push(second.clone());
+        second.children.borrow_mut().push(first.clone());
+        let watch = Rc::downgrade(&first.0);
+        drop((first, second));
+
+        assert_eq!(heap.len(), 2);
+        assert_eq!(heap.collect(), 2);
+        assert!(heap.is_empty());
+        assert_eq!(watch.strong_count(), 0);
+    }
+
+    #[test]
+    fn test_cycle_held_from_outside_is_kept() {
+        let mut heap = Heap::default();
+        let outside = node(&mut heap);
+        let inside = node(&mut heap);
+        outside.children.borrow_mut().push(inside.clone());
+        inside.children.borrow_mut().push(inside.clone());
+        drop(inside);
+
+        assert_eq!(heap.collect(), 0);
+        assert_eq!(heap.len(), 2);
+        assert_eq!(outside.children.borrow()[0].children.borrow().len(), 1);
+    }
+
+    #[test]
+    fn test_allocating_collects_once_the_heap_grows() {
+        let mut heap = Heap::default();
+        for _ in 0..10 * FIRST_COLLECTION {
+            let looped = node(&mut heap);
+            looped.children.borrow_mut().push(looped.clone());
+        }
+
+        assert!(heap.len() <= 2 * FIRST_COLLECTION);
+    }
+}
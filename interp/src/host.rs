@@ -0,0 +1,420 @@
+//! Functions written in Rust that an application embedding CV gives the programs it runs.
+//!
+//! A host function is an ordinary Rust function or closure, such as
+//! `|url: String| -> String { .. }`. Its parameter and result types implement [`HostValue`],
+//! which says what CV type each stands for and converts between Rust values and [`Value`]s, so
+//! calls to it are type checked like calls to a function the program declares. A host function
+//! that can fail returns a `Result`, and its error stops the program.
+
+use crate::interpreter::RuntimeError;
+use crate::operations::automatic_dereference;
+use crate::value::Value;
+use parser::ast::Type;
+use parser::operators::ArithmeticError;
+use semantics::Host;
+use std::fmt;
+
+/// The Rust side of a host function: the result of converting the arguments, calling the
+/// function, and converting what it returned, which may be the message of an error it reported.
+type Function =
+    Box<dyn for<'p> Fn(&[Value<'p>]) -> Result<Result<Value<'p>, String>, RuntimeError>>;
+
+pub struct HostFunction {
+    pub name: String,
+    pub parameters: Vec<Type>,
+    pub result: Type,
+    function: Function,
+}
+
+impl HostFunction {
+    /// `function`, called `name` in programs.
+    pub fn new<Args>(name: impl Into<String>, function: impl IntoHostFunction<Args>) -> Self {
+        let (parameters, result, function) = function.into_host();
+        HostFunction {
+            name: name.into(),
+            parameters,
+            result,
+            function,
+        }
+    }
+
+    /// The name and type the resolver and type checker know the function by.
+    pub fn signature(&self) -> Host {
+        Host {
+            name: self.name.clone(),
+            ty: Type::Function {
+                param_types: self.parameters.clone(),
+                return_type: (self.result != Type::Unit).then(|| Box::new(self.result.clone())),
+            },
+        }
+    }
+
+    /// Check the number of `arguments` and call the function with them.
+    pub fn call<'p>(&self, arguments: &[Value<'p>]) -> Result<Value<'p>, RuntimeError> {
+        if arguments.len() != self.parameters.len() {
+            return Err(RuntimeError::ArgumentCount {
+                function: self.name.clone(),
+                expected: self.parameters.len(),
+                found: arguments.len(),
+            });
+        }
+        (self.function)(arguments)?.map_err(|message| RuntimeError::Host {
+            function: self.name.clone(),
+            message,
+        })
+    }
+}
+
+impl fmt::Debug for HostFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HostFunction({})", self.name)
+    }
+}
+
+/// A Rust type that stands for a CV type, whose values convert to and from values of it.
+pub trait HostValue: Sized {
+    /// The CV type.
+    fn ty() -> Type;
+
+    fn from_value(value: Value) -> Result<Self, RuntimeError>;
+
+    fn into_value<'p>(self) -> Result<Value<'p>, RuntimeError>;
+}
+
+fn mismatch(expected: &'static str, found: &Value) -> RuntimeError {
+    RuntimeError::TypeMismatch {
+        expected,
+        found: found.kind(),
+    }
+}
+
+macro_rules! integer {
+    ($($rust:ty => $cv:ident),*) => {$(
+        impl HostValue for $rust {
+            fn ty() -> Type {
+                Type::$cv
+            }
+
+            fn from_value(value: Value) -> Result<Self, RuntimeError> {
+                match value {
+                    Value::Integer(value) => {
+                        <$rust>::try_from(value).map_err(|_| ArithmeticError::Overflow.into())
+                    }
+                    value => Err(mismatch("integer", &value)),
+                }
+            }
+
+            fn into_value<'p>(self) -> Result<Value<'p>, RuntimeError> {
+                i128::try_from(self)
+                    .map(Value::Integer)
+                    .map_err(|_| ArithmeticError::Overflow.into())
+            }
+        }
+    )*};
+}
+
+integer!(
+    i8 => I8, i16 => I16, i32 => I32, i64 => I64, isize => ISize,
+    u8 => U8, u16 => U16, u32 => U32, u64 => U64, usize => USize
+);
+
+impl HostValue for f64 {
+    fn ty() -> Type {
+        Type::F64
+    }
+
+    fn from_value(value: Value) -> Result<Self, RuntimeError> {
+        value.float().ok_or_else(|| mismatch("float", &value))
+    }
+
+    fn into_value<'p>(self) -> Result<Value<'p>, RuntimeError> {
+        Ok(Value::Float(self))
+    }
+}
+
+impl HostValue for f32 {
+    fn ty() -> Type {
+        Type::F32
+    }
+
+    fn from_value(value: Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::Single(value) => Ok(value),
+            value => f64::from_value(value).map(|value| value as f32),
+        }
+    }
+
+    fn into_value<'p>(self) -> Result<Value<'p>, RuntimeError> {
+        Ok(Value::Single(self))
+    }
+}
+
+impl HostValue for bool {
+    fn ty() -> Type {
+        Type::Bool
+    }
+
+    fn from_value(value: Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::Boolean(value) => Ok(value),
+            value => Err(mismatch("bool", &value)),
+        }
+    }
+
+    fn into_value<'p>(self) -> Result<Value<'p>, RuntimeError> {
+        Ok(Value::Boolean(self))
+    }
+}
+
+impl HostValue for char {
+    fn ty() -> Type {
+        Type::Char
+    }
+
+    fn from_value(value: Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::Char(value) => Ok(value),
+            value => Err(mismatch("char", &value)),
+        }
+    }
+
+    fn into_value<'p>(self) -> Result<Value<'p>, RuntimeError> {
+        Ok(Value::Char(self))
+    }
+}
+
+impl HostValue for String {
+    fn ty() -> Type {
+        Type::String
+    }
+
+    fn from_value(value: Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::String(value) => Ok(value),
+            value => Err(mismatch("string", &value)),
+        }
+    }
+
+    fn into_value<'p>(self) -> Result<Value<'p>, RuntimeError> {
+        Ok(Value::String(self))
+    }
+}
+
+impl HostValue for () {
+    fn ty() -> Type {
+        Type::Unit
+    }
+
+    fn from_value(value: Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::Unit => Ok(()),
+            value => Err(mismatch("unit", &value)),
+        }
+    }
+
+    fn into_value<'p>(self) -> Result<Value<'p>, RuntimeError> {
+        Ok(Value::Unit)
+    }
+}
+
+/// An `arrayList`.
+impl<T: HostValue> HostValue for Vec<T> {
+    fn ty() -> Type {
+        Type::ArrayList(Box::new(T::ty()))
+    }
+
+    fn from_value(value: Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::Array(elements) => elements
+                .into_iter()
+                .map(|element| T::from_value(automatic_dereference(element)?))
+                .collect(),
+            value => Err(mismatch("array", &value)),
+        }
+    }
+
+    fn into_value<'p>(self) -> Result<Value<'p>, RuntimeError> {
+        self.into_iter()
+            .map(T::into_value)
+            .collect::<Result<_, _>>()
+            .map(Value::Array)
+    }
+}
+
+/// The prelude's `option`, which a program run without the prelude has to declare itself.
+impl<T: HostValue> HostValue for Option<T> {
+    fn ty() -> Type {
+        Type::Generic {
+            name: "option".to_string(),
+            parameters: vec![T::ty()],
+        }
+    }
+
+    fn from_value(value: Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::Variant {
+                name: "some",
+                payload: Some(payload),
+            } => T::from_value(automatic_dereference(*payload)?).map(Some),
+            Value::Variant {
+                name: "none",
+                payload: None,
+            } => Ok(None),
+            value => Err(mismatch("option", &value)),
+        }
+    }
+
+    fn into_value<'p>(self) -> Result<Value<'p>, RuntimeError> {
+        Ok(match self {
+            Some(value) => Value::Variant {
+                name: "some",
+                payload: Some(Box::new(value.into_value()?)),
+            },
+            None => Value::Variant {
+                name: "none",
+                payload: None,
+            },
+        })
+    }
+}
+
+/// What a host function returns: a value, or a `Result` whose error stops the program.
+pub trait HostResult {
+    type Value: HostValue;
+
+    fn into_result(self) -> Result<Self::Value, String>;
+}
+
+impl<T: HostValue> HostResult for T {
+    type Value = T;
+
+    fn into_result(self) -> Result<T, String> {
+        Ok(self)
+    }
+}
+
+impl<T: HostValue, E: fmt::Display> HostResult for Result<T, E> {
+    type Value = T;
+
+    fn into_result(self) -> Result<T, String> {
+        self.map_err(|error| error.to_string())
+    }
+}
+
+/// A Rust function that can be a host function, taking `Args` as a tuple of its parameter
+/// types.
+pub trait IntoHostFunction<Args> {
+    /// The function's parameter types, result type, and Rust side.
+    fn into_host(self) -> (Vec<Type>, Type, Function);
+}
+
+/// Box `function`, which makes the compiler infer the lifetimes of a closure's signature from
+/// [`Function`]'s.
+fn boxed<F>(function: F) -> Function
+where
+    F: for<'p> Fn(&[Value<'p>]) -> Result<Result<Value<'p>, String>, RuntimeError> + 'static,
+{
+    Box::new(function)
+}
+
+macro_rules! into_host_function {
+    ($($argument:ident),*) => {
+        impl<F, R, $($argument),*> IntoHostFunction<($($argument,)*)> for F
+        where
+            F: Fn($($argument),*) -> R + 'static,
+            R: HostResult,
+            $($argument: HostValue,)*
+        {
+            #[allow(non_snake_case, unused_mut, unused_variables)]
+            fn into_host(self) -> (Vec<Type>, Type, Function) {
+                let function = boxed(move |arguments| {
+                    let mut arguments = arguments.iter().cloned();
+                    $(
+                        let $argument = $argument::from_value(automatic_dereference(
+                            arguments.next().expect("the number of arguments was checked"),
+                        )?)?;
+                    )*
+                    match self($($argument),*).into_result() {
+                        Ok(value) => value.into_value().map(Ok),
+                        Err(message) => Ok(Err(message)),
+                    }
+                });
+                (vec![$($argument::ty()),*], R::Value::ty(), function)
+            }
+        }
+    };
+}
+
+into_host_function!();
+into_host_function!(A);
+into_host_function!(A, B);
+into_host_function!(A, B, C);
+into_host_function!(A, B, C, D);
+into_host_function!(A, B, C, D, E);
+into_host_function!(A, B, C, D, E, G);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test]
+    fn test_signature() {
+        let host = HostFunction::new("repeat", |text: String, times: u8| {
+            text.repeat(times.into())
+        });
+
+        assert_eq!(
+            host.signature().ty,
+            Type::Function {
+                param_types: vec![Type::String, Type::U8],
+                return_type: Some(Box::new(Type::String)),
+            }
+        );
+        assert_eq!(
+            host.call(&[Value::String("ab".to_string()), Value::Integer(2)]),
+            Ok(Value::String("abab".to_string()))
+        );
+    }
+
+    #[test_case(Value::Integer(300), Err(RuntimeError::Arithmetic(ArithmeticError::Overflow)) ; "out of range")]
+    #[test_case(Value::Float(1.0), Err(RuntimeError::TypeMismatch { expected: "integer", found: "float" }) ; "wrong kind")]
+    #[test_case(Value::Integer(7), Ok(7) ; "fits")]
+    fn test_integer_arguments(value: Value<'static>, expected: Result<u8, RuntimeError>) {
+        assert_eq!(u8::from_value(value), expected);
+    }
+
+    #[test]
+    fn test_collections() {
+        let values = vec![Some(1.5), None];
+        let value = values.clone().into_value().unwrap();
+
+        assert_eq!(value.to_string(), "[some(1.5), none]");
+        assert_eq!(Vec::<Option<f64>>::from_value(value), Ok(values));
+    }
+
+    #[test]
+    fn test_errors_stop_the_program() {
+        let host = HostFunction::new("parse", |text: String| text.parse::<i32>());
+
+        assert_eq!(
+            host.call(&[Value::String("12".to_string())]),
+            Ok(Value::Integer(12))
+        );
+        assert_eq!(
+            host.call(&[Value::String("twelve".to_string())]),
+            Err(RuntimeError::Host {
+                function: "parse".to_string(),
+                message: "invalid digit found in string".to_string()
+            })
+        );
+        assert_eq!(
+            host.call(&[]),
+            Err(RuntimeError::ArgumentCount {
+                function: "parse".to_string(),
+                expected: 1,
+                found: 0
+            })
+        );
+    }
+}
@@ -0,0 +1,1049 @@
+//! Evaluating a checked program by walking its syntax tree.
+
+use crate::backtrace::Frame;
+use crate::builtins::{Builtin, Runtime};
+use crate::environment::Environment;
+use crate::heap::Heap;
+use crate::host::HostFunction;
+use crate::operations::{self, Fit, automatic_dereference, check_arguments, dereference};
+use crate::pattern::{bindings, type_name};
+use crate::value::{Closure, Reference, Slot, Step, Value};
+use parser::ast::{
+    BinaryOperator, Declaration, Expression, ExpressionKind, FunctionDeclaration, Program,
+    RecordDeclaration, Statement, StatementKind, Type, UnaryOperator,
+};
+use parser::const_eval::ConstError;
+use parser::node_id::{Node, NodeId, NodeIndex};
+use parser::operators::ArithmeticError;
+use semantics::TypeMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::rc::Rc;
+use thiserror::Error;
+
+/// How deeply calls may nest before the program is stopped, so that runaway recursion is
+/// reported instead of overflowing the interpreter's own stack.
+pub const MAX_CALL_DEPTH: usize = 1000;
+
+#[derive(Debug, Error, PartialEq, Clone)]
+pub enum RuntimeError {
+    #[error("{0}")]
+    Arithmetic(#[from] ArithmeticError),
+    #[error("Index {index} is out of bounds for length {length}")]
+    IndexOutOfBounds { index: i128, length: usize },
+    /// A value of the wrong kind, which the type checker rejects before a program runs.
+    #[error("Expected {expected}, found {found}")]
+    TypeMismatch {
+        expected: &'static str,
+        found: &'static str,
+    },
+    #[error("Operator '{operator}' cannot be applied to {left} and {right}")]
+    InvalidOperands {
+        operator: BinaryOperator,
+        left: &'static str,
+        right: &'static str,
+    },
+    #[error("Operator '{operator}' cannot be applied to {operand}")]
+    InvalidOperand {
+        operator: UnaryOperator,
+        operand: &'static str,
+    },
+    #[error("Cannot cast {from} to '{to}'")]
+    InvalidCast { from: &'static str, to: Type },
+    #[error("Cannot find '{name}'")]
+    UnknownName { name: String },
+    #[error("No field '{field}'")]
+    NoField { field: String },
+    /// A method call on a value no `patch` gives the method.
+    #[error("No method '{method}' on {receiver}")]
+    UnknownMethod {
+        method: String,
+        receiver: &'static str,
+    },
+    #[error("'{function}' takes {expected} arguments but {found} were given")]
+    ArgumentCount {
+        function: String,
+        expected: usize,
+        found: usize,
+    },
+    #[error("{0}")]
+    Const(#[from] ConstError),
+    #[error("Calls are nested more than {limit} deep")]
+    CallDepth { limit: usize },
+    #[error("'break' outside a loop")]
+    BreakOutsideLoop,
+    #[error("'return' outside a function")]
+    ReturnOutsideFunction,
+    #[error("Assertion failed")]
+    AssertionFailed,
+    /// A `when` whose value is used matched none of its branches, which a guard or a value of
+    /// unknown type can let happen.
+    #[error("No branch of 'when' matches the value")]
+    NoMatchingBranch,
+    /// A host function returned an error.
+    #[error("'{function}' failed: {message}")]
+    Host { function: String, message: String },
+    #[error("Cannot write output: {message}")]
+    Output { message: String },
+    /// The value a program returned to its host does not fit the Rust type the host asked for.
+    #[error("Cannot return {found} as '{expected}'")]
+    ReturnConversion { expected: Type, found: String },
+}
+
+/// Why evaluation stopped before producing a value.
+enum Unwind<'p> {
+    Break(Value<'p>),
+    Return(Value<'p>),
+    Error(RuntimeError),
+}
+
+impl From<RuntimeError> for Unwind<'_> {
+    fn from(error: RuntimeError) -> Self {
+        Unwind::Error(error)
+    }
+}
+
+impl From<ArithmeticError> for Unwind<'_> {
+    fn from(error: ArithmeticError) -> Self {
+        Unwind::Error(error.into())
+    }
+}
+
+type Eval<'p> = Result<Value<'p>, Unwind<'p>>;
+
+/// Runs programs, which must have been resolved and type checked without errors: the types of
+/// their expressions decide which patch method a call runs and how wide integers are.
+///
+/// An interpreter starts with one program and can be [extended](Interpreter::extend) with more,
+/// each checked on its own, that see and replace what the earlier ones declared.
+pub struct Interpreter<'p> {
+    /// Every program loaded so far, in the order they were loaded.
+    units: Vec<Unit<'p>>,
+    /// The unit whose code is running.
+    unit: usize,
+    /// The unit each function was declared in, by the address of its declaration.
+    functions: HashMap<*const FunctionDeclaration, usize>,
+    /// Functions, consts, variants, and top-level variables, which every function sees.
+    globals: HashMap<&'p str, Slot<'p>>,
+    records: HashMap<&'p str, &'p RecordDeclaration>,
+    /// The names of every variant, which a bare name in a pattern matches.
+    variants: HashSet<&'p str>,
+    /// Patch methods, by the node that declares them.
+    methods: HashMap<NodeId, &'p FunctionDeclaration>,
+    /// The const declarations of every program loaded so far.
+    constants: Vec<&'p Declaration>,
+    runtime: Runtime<'p, 'p>,
+    /// The name of each function being called, innermost last.
+    calls: Vec<&'p str>,
+    /// The calls the error being unwound was raised in, innermost first, as far as it has
+    /// unwound.
+    backtrace: Vec<Frame<'p>>,
+    /// How many calls deep the call last added to the backtrace is.
+    unwound: usize,
+}
+
+/// One checked program and the side tables its node ids index.
+struct Unit<'p> {
+    program: &'p Program,
+    index: &'p NodeIndex,
+    types: &'p TypeMap,
+}
+
+impl<'p> Interpreter<'p> {
+    /// An interpreter for `program`, whose nodes `index` numbers and whose expressions `types`
+    /// gives the types of. Output goes to standard output.
+    pub fn new(program: &'p Program, index: &'p NodeIndex, types: &'p TypeMap) -> Self {
+        let mut interpreter = Interpreter {
+            units: Vec::new(),
+            unit: 0,
+            functions: HashMap::new(),
+            globals: HashMap::new(),
+            records: HashMap::new(),
+            variants: HashSet::new(),
+            methods: HashMap::new(),
+            constants: Vec::new(),
+            runtime: Runtime::default(),
+            calls: Vec::new(),
+            backtrace: Vec::new(),
+            unwound: 0,
+        };
+        interpreter.load(program, index, types);
+        interpreter
+    }
+
+    /// Send what the program prints to `output` instead of standard output.
+    pub fn with_output(mut self, output: impl Write + 'p) -> Self {
+        self.runtime.output = Box::new(output);
+        self
+    }
+
+    /// Give the program the host function `host`, which it must have been checked with.
+    pub fn with_host(mut self, host: Rc<HostFunction>) -> Self {
+        self.runtime.hosts.insert(host.name.clone(), host);
+        self
+    }
+
+    /// Run the program: evaluate its consts, run its top-level statements in order, and then
+    /// call `main` if it declares one. The result is what `main` returns, or unit without it.
+    pub fn run(&mut self) -> Result<Value<'p>, RuntimeError> {
+        self.backtrace.clear();
+        let program = self.units[self.unit].program;
+        for (name, value) in operations::constants(&[], program)? {
+            self.define_global(name, value);
+        }
+        for declaration in &program.declarations {
+            if let Declaration::Statement(statement) = declaration {
+                self.execute(statement)?;
+            }
+        }
+        match self.global("main") {
+            Some(main @ Value::Function(_)) => self.call(main, Vec::new()),
+            _ => Ok(Value::Unit),
+        }
+    }
+
+    /// Load another checked program, whose declarations replace any of the same name, and
+    /// evaluate its consts, which may use the ones of the programs loaded before it. Its
+    /// top-level statements are left for the caller to [execute](Interpreter::execute).
+    pub fn extend(
+        &mut self,
+        program: &'p Program,
+        index: &'p NodeIndex,
+        types: &'p TypeMap,
+    ) -> Result<(), RuntimeError> {
+        self.backtrace.clear();
+        let constants = operations::constants(&self.constants, program)?;
+        self.load(program, index, types);
+        for (name, value) in constants {
+            self.define_global(name, value);
+        }
+        Ok(())
+    }
+
+    /// Run one top-level statement of the program loaded last. A variable it declares becomes
+    /// a global.
+    pub fn execute(&mut self, statement: &'p Statement) -> Result<(), RuntimeError> {
+        self.backtrace.clear();
+        let result = match &statement.kind {
+            StatementKind::VariableDeclaration { name, value, .. } => self
+                .expression(value, &Environment::default())
+                .map(|value| self.define_global(name, value)),
+            _ => self.statement(statement, &mut Environment::default()),
+        };
+        result.map_err(outside_function)
+    }
+
+    /// Evaluate one top-level expression of the program loaded last.
+    pub fn evaluate(&mut self, expression: &'p Expression) -> Result<Value<'p>, RuntimeError> {
+        self.backtrace.clear();
+        self.expression(expression, &Environment::default())
+            .map_err(outside_function)
+    }
+
+    /// Call the function or closure `callee` with `arguments`.
+    pub fn call(
+        &mut self,
+        callee: Value<'p>,
+        arguments: Vec<Value<'p>>,
+    ) -> Result<Value<'p>, RuntimeError> {
+        match callee {
+            Value::Function(function) => self.call_function(function, None, arguments),
+            Value::Closure(closure) => {
+                check_arguments("closure", closure.params, arguments.len())?;
+                let mut environment = closure.environment.clone();
+                for (parameter, argument) in closure.params.iter().zip(arguments) {
+                    environment =
+                        environment.define(&mut self.runtime.heap, &parameter.name, argument);
+                }
+                self.enter("<closure>", closure.unit, |interpreter| {
+                    interpreter.expression(closure.body, &environment)
+                })
+            }
+            Value::Constructor(name) => Ok(operations::construct(name, arguments)),
+            Value::Builtin(builtin) => builtin.call(&mut self.runtime, &arguments),
+            Value::Host(host) => host.call(&arguments),
+            callee => Err(RuntimeError::TypeMismatch {
+                expected: "function",
+                found: callee.kind(),
+            }),
+        }
+    }
+
+    /// The value of the top-level name `name`: a function, const, variant, or top-level
+    /// variable.
+    pub fn global(&self, name: &str) -> Option<Value<'p>> {
+        self.globals.get(name).map(|slot| slot.borrow().clone())
+    }
+
+    /// The storage the program has allocated.
+    pub fn heap(&self) -> &Heap<'p> {
+        &self.runtime.heap
+    }
+
+    /// The calls that were running when the last [run](Interpreter::run),
+    /// [execute](Interpreter::execute), or [evaluate](Interpreter::evaluate) stopped with an
+    /// error, innermost first.
+    pub fn backtrace(&self) -> &[Frame<'p>] {
+        &self.backtrace
+    }
+
+    fn id<T: Node>(&self, node: &T) -> NodeId {
+        self.units[self.unit].index.expect_id(node)
+    }
+
+    fn types(&self) -> &'p TypeMap {
+        self.units[self.unit].types
+    }
+
+    fn define_global(&mut self, name: &'p str, value: Value<'p>) {
+        let slot = self.runtime.heap.allocate(RefCell::new(value));
+        self.globals.insert(name, slot);
+    }
+
+    fn load(&mut self, program: &'p Program, index: &'p NodeIndex, types: &'p TypeMap) {
+        self.unit = self.units.len();
+        self.units.push(Unit {
+            program,
+            index,
+            types,
+        });
+        for declaration in &program.declarations {
+            match declaration {
+                Declaration::Function(function) => {
+                    self.functions.insert(function, self.unit);
+                    self.define_global(&function.name, Value::Function(function));
+                }
+                Declaration::Record(record) => {
+                    self.records.insert(&record.name, record);
+                }
+                Declaration::Union(union) => {
+                    for variant in &union.variants {
+                        let value = match variant.variant_type {
+                            Some(_) => Value::Constructor(&variant.name),
+                            None => Value::Variant {
+                                name: &variant.name,
+                                payload: None,
+                            },
+                        };
+                        self.define_global(&variant.name, value);
+                        self.variants.insert(&variant.name);
+                    }
+                }
+                Declaration::Patch(patch) => {
+                    for method in &patch.methods {
+                        let node = self.id(method);
+                        self.functions.insert(method, self.unit);
+                        self.methods.insert(node, method);
+                    }
+                }
+                Declaration::Const(_) => self.constants.push(declaration),
+                Declaration::Import(_) | Declaration::Statement(_) => {}
+            }
+        }
+    }
+
+    fn call_function(
+        &mut self,
+        function: &'p FunctionDeclaration,
+        receiver: Option<Value<'p>>,
+        arguments: Vec<Value<'p>>,
+    ) -> Result<Value<'p>, RuntimeError> {
+        check_arguments(&function.name, &function.params, arguments.len())?;
+        let mut environment = Environment::default();
+        if let Some(receiver) = receiver {
+            environment = environment.define(&mut self.runtime.heap, "self", receiver);
+        }
+        for (parameter, argument) in function.params.iter().zip(arguments) {
+            environment = environment.define(&mut self.runtime.heap, &parameter.name, argument);
+        }
+        let unit = self.functions.get(&(function as *const _)).copied();
+        self.enter(&function.name, unit.unwrap_or(self.unit), |interpreter| {
+            interpreter.expression(&function.body, &environment)
+        })
+    }
+
+    /// Evaluate the body of the function or closure `name` declared in `unit` with `body`, one
+    /// call deeper.
+    fn enter(
+        &mut self,
+        name: &'p str,
+        unit: usize,
+        body: impl FnOnce(&mut Self) -> Eval<'p>,
+    ) -> Result<Value<'p>, RuntimeError> {
+        if self.calls.len() >= MAX_CALL_DEPTH {
+            return Err(RuntimeError::CallDepth {
+                limit: MAX_CALL_DEPTH,
+            });
+        }
+        let caller = std::mem::replace(&mut self.unit, unit);
+        self.calls.push(name);
+        let result = body(self);
+        self.calls.pop();
+        self.unit = caller;
+        match result {
+            Ok(value) | Err(Unwind::Return(value)) => Ok(value),
+            Err(Unwind::Break(_)) => Err(RuntimeError::BreakOutsideLoop),
+            Err(Unwind::Error(error)) => Err(error),
+        }
+    }
+
+    fn lookup(&self, name: &str, environment: &Environment<'p>) -> Result<Value<'p>, RuntimeError> {
+        if let Some(slot) = environment.lookup(name).or_else(|| self.globals.get(name)) {
+            return Ok(slot.borrow().clone());
+        }
+        Builtin::named(name)
+            .map(Value::Builtin)
+            .or_else(|| self.runtime.host(name))
+            .ok_or_else(|| RuntimeError::UnknownName {
+                name: name.to_string(),
+            })
+    }
+
+    fn statement(
+        &mut self,
+        statement: &'p Statement,
+        environment: &mut Environment<'p>,
+    ) -> Result<(), Unwind<'p>> {
+        let result = self.statement_effect(statement, environment);
+        if let Err(Unwind::Error(_)) = result {
+            self.unwind(self.id(statement));
+        }
+        result
+    }
+
+    fn statement_effect(
+        &mut self,
+        statement: &'p Statement,
+        environment: &mut Environment<'p>,
+    ) -> Result<(), Unwind<'p>> {
+        match &statement.kind {
+            StatementKind::VariableDeclaration { name, value, .. } => {
+                let value = self.expression(value, environment)?;
+                *environment = environment.define(&mut self.runtime.heap, name, value);
+            }
+            StatementKind::Assignment {
+                target,
+                operator,
+                value,
+            } => {
+                let value = self.expression(value, environment)?;
+                let place = self.place(target, environment)?;
+                let value = match operator.compound_operator() {
+                    Some(operator) => {
+                        let current = place.get()?;
+                        operations::binary(operator, current, value, self.fit(target))?
+                    }
+                    None => value,
+                };
+                place.set(value)?;
+            }
+            StatementKind::Expression(expression) => {
+                self.expression(expression, environment)?;
+            }
+            StatementKind::Return(value) => {
+                let value = self.optional(value.as_deref(), environment)?;
+                return Err(Unwind::Return(value));
+            }
+            StatementKind::Break(value) => {
+                let value = self.optional(value.as_deref(), environment)?;
+                return Err(Unwind::Break(value));
+            }
+        }
+        Ok(())
+    }
+
+    fn optional(
+        &mut self,
+        expression: Option<&'p Expression>,
+        environment: &Environment<'p>,
+    ) -> Eval<'p> {
+        match expression {
+            Some(expression) => self.expression(expression, environment),
+            None => Ok(Value::Unit),
+        }
+    }
+
+    fn expression(
+        &mut self,
+        expression: &'p Expression,
+        environment: &Environment<'p>,
+    ) -> Eval<'p> {
+        let result = self.expression_value(expression, environment);
+        if let Err(Unwind::Error(_)) = result {
+            self.unwind(self.id(expression));
+        }
+        result
+    }
+
+    /// Note that an error is unwinding through `node` of the running call. The first node of a
+    /// call an error unwinds through is where the call was when it happened.
+    fn unwind(&mut self, node: NodeId) {
+        let depth = self.calls.len();
+        if self.backtrace.is_empty() || depth < self.unwound {
+            self.backtrace.push(Frame {
+                function: self.calls.last().copied().unwrap_or("<top level>"),
+                node,
+            });
+            self.unwound = depth;
+        }
+    }
+
+    fn expression_value(
+        &mut self,
+        expression: &'p Expression,
+        environment: &Environment<'p>,
+    ) -> Eval<'p> {
+        let value = match &expression.kind {
+            ExpressionKind::Literal(literal) => match Value::from(literal) {
+                Value::Float(value) => operations::float(value, self.fit(expression)),
+                value => value,
+            },
+            ExpressionKind::Identifier(name) => self.lookup(name, environment)?,
+            ExpressionKind::BinaryOperation {
+                left,
+                operator: operator @ (BinaryOperator::And | BinaryOperator::Or),
+                right,
+            } => {
+                // The right side is only evaluated when it decides the result.
+                let left = self.condition(left, environment)?;
+                if left == (*operator == BinaryOperator::Or) {
+                    Value::Boolean(left)
+                } else {
+                    Value::Boolean(self.condition(right, environment)?)
+                }
+            }
+            ExpressionKind::BinaryOperation {
+                left,
+                operator,
+                right,
+            } => {
+                let left = self.expression(left, environment)?;
+                let right = self.expression(right, environment)?;
+                operations::binary(*operator, left, right, self.fit(expression))?
+            }
+            ExpressionKind::UnaryOperation { operator, operand } => match operator {
+                UnaryOperator::Reference | UnaryOperator::MutableReference => {
+                    let is_mutable = *operator == UnaryOperator::MutableReference;
+                    self.reference(operand, is_mutable, environment)?
+                }
+                UnaryOperator::Dereference => {
+                    let value = self.expression(operand, environment)?;
+                    dereference(value)?
+                }
+                UnaryOperator::Not | UnaryOperator::Negate => {
+                    let value = self.expression(operand, environment)?;
+                    operations::unary(*operator, value, self.fit(expression))?
+                }
+            },
+            ExpressionKind::FunctionCall {
+                function,
+                arguments,
+            } => {
+                let callee = self.expression(function, environment)?;
+                let arguments = self.arguments(arguments, environment)?;
+                self.call(callee, arguments)?
+            }
+            ExpressionKind::RecordAccess { record, field } => {
+                operations::field(self.expression(record, environment)?, field)?
+            }
+            ExpressionKind::MethodCall {
+                receiver,
+                method,
+                arguments,
+            } => {
+                let receiver = automatic_dereference(self.expression(receiver, environment)?)?;
+                let arguments = self.arguments(arguments, environment)?;
+                let declaration = self
+                    .types()
+                    .method(self.id(expression))
+                    .and_then(|node| self.methods.get(&node).copied());
+                match declaration {
+                    Some(declaration) => {
+                        self.call_function(declaration, Some(receiver), arguments)?
+                    }
+                    None => {
+                        return Err(RuntimeError::UnknownMethod {
+                            method: method.clone(),
+                            receiver: receiver.kind(),
+                        }
+                        .into());
+                    }
+                }
+            }
+            ExpressionKind::IndexAccess { collection, index } => {
+                let collection = automatic_dereference(self.expression(collection, environment)?)?;
+                let index = self.expression(index, environment)?;
+                operations::index(collection, index)?
+            }
+            ExpressionKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                if self.condition(condition, environment)? {
+                    self.expression(then_branch, environment)?
+                } else {
+                    self.optional(else_branch.as_deref(), environment)?
+                }
+            }
+            ExpressionKind::When {
+                expression: value,
+                branches,
+            } => {
+                let value = self.expression(value, environment)?;
+                for branch in branches {
+                    let Some(bound) = bindings(&branch.pattern, &value, &self.variants) else {
+                        continue;
+                    };
+                    let mut scope = environment.clone();
+                    for (name, value) in bound {
+                        scope = scope.define(&mut self.runtime.heap, name, value);
+                    }
+                    if let Some(guard) = &branch.guard
+                        && !self.condition(guard, &scope)?
+                    {
+                        continue;
+                    }
+                    return self.expression(&branch.body, &scope);
+                }
+                // A `when` whose value is unused falls through.
+                if !self.types().is_discarded(self.id(expression)) {
+                    return Err(RuntimeError::NoMatchingBranch.into());
+                }
+                Value::Unit
+            }
+            ExpressionKind::Block {
+                statements,
+                final_expression,
+            } => {
+                let mut scope = environment.clone();
+                for statement in statements {
+                    self.statement(statement, &mut scope)?;
+                }
+                self.optional(final_expression.as_deref(), &scope)?
+            }
+            ExpressionKind::Loop { body } => loop {
+                match self.expression(body, environment) {
+                    Ok(_) => {}
+                    Err(Unwind::Break(value)) => break value,
+                    Err(unwind) => return Err(unwind),
+                }
+            },
+            ExpressionKind::While { condition, body } => {
+                while self.condition(condition, environment)? {
+                    match self.expression(body, environment) {
+                        Ok(_) => {}
+                        Err(Unwind::Break(_)) => break,
+                        Err(unwind) => return Err(unwind),
+                    }
+                }
+                Value::Unit
+            }
+            ExpressionKind::For {
+                variable,
+                iterable,
+                body,
+            } => {
+                let iteration = operations::iteration(self.expression(iterable, environment)?)?;
+                let mut position = 0;
+                while let Some(element) = operations::element(&iteration, position) {
+                    position += 1;
+                    let scope = environment.define(&mut self.runtime.heap, variable, element);
+                    match self.expression(body, &scope) {
+                        Ok(_) => {}
+                        Err(Unwind::Break(_)) => break,
+                        Err(unwind) => return Err(unwind),
+                    }
+                }
+                Value::Unit
+            }
+            ExpressionKind::ArrayLiteral(elements) => {
+                Value::Array(self.arguments(elements, environment)?)
+            }
+            ExpressionKind::RecordLiteral {
+                record_type,
+                fields,
+                base,
+            } => self.record(record_type, fields, base.as_deref(), environment)?,
+            ExpressionKind::UnionLiteral { variant, value, .. } => Value::Variant {
+                name: variant,
+                payload: match value {
+                    Some(value) => Some(Box::new(self.expression(value, environment)?)),
+                    None => None,
+                },
+            },
+            ExpressionKind::Reference {
+                is_mutable,
+                expression,
+            } => self.reference(expression, *is_mutable, environment)?,
+            ExpressionKind::Dereference(expression) => {
+                let value = self.expression(expression, environment)?;
+                dereference(value)?
+            }
+            ExpressionKind::Grouped(expression)
+            | ExpressionKind::TypeAnnotation { expression, .. } => {
+                self.expression(expression, environment)?
+            }
+            ExpressionKind::Range {
+                start,
+                end,
+                inclusive,
+            } => Value::Range {
+                start: Box::new(self.expression(start, environment)?),
+                end: Box::new(self.expression(end, environment)?),
+                inclusive: *inclusive,
+            },
+            ExpressionKind::Cast {
+                expression: value,
+                target,
+            } => {
+                let value = self.expression(value, environment)?;
+                operations::cast(value, target)?
+            }
+            ExpressionKind::Closure { params, body, .. } => {
+                Value::Closure(self.runtime.heap.allocate(Closure {
+                    params,
+                    body,
+                    environment: environment.clone(),
+                    unit: self.unit,
+                }))
+            }
+        };
+        Ok(value)
+    }
+
+    fn arguments(
+        &mut self,
+        arguments: &'p [Expression],
+        environment: &Environment<'p>,
+    ) -> Result<Vec<Value<'p>>, Unwind<'p>> {
+        arguments
+            .iter()
+            .map(|argument| self.expression(argument, environment))
+            .collect()
+    }
+
+    fn condition(
+        &mut self,
+        condition: &'p Expression,
+        environment: &Environment<'p>,
+    ) -> Result<bool, Unwind<'p>> {
+        match self.expression(condition, environment)? {
+            Value::Boolean(value) => Ok(value),
+            value => Err(RuntimeError::TypeMismatch {
+                expected: "bool",
+                found: value.kind(),
+            }
+            .into()),
+        }
+    }
+
+    /// The storage an assignment to `target` writes to.
+    fn place(
+        &mut self,
+        target: &'p Expression,
+        environment: &Environment<'p>,
+    ) -> Result<Reference<'p>, Unwind<'p>> {
+        let reference = match &target.kind {
+            ExpressionKind::Grouped(target) => return self.place(target, environment),
+            ExpressionKind::Identifier(name) => {
+                let slot = environment
+                    .lookup(name)
+                    .or_else(|| self.globals.get(name.as_str()))
+                    .ok_or_else(|| RuntimeError::UnknownName { name: name.clone() })?;
+                Reference::to(slot.clone())
+            }
+            ExpressionKind::RecordAccess { record, field } => {
+                let mut reference = self.part_of(record, environment)?;
+                reference.path.push(Step::Field(field));
+                reference
+            }
+            ExpressionKind::IndexAccess { collection, index } => {
+                let mut reference = self.part_of(collection, environment)?;
+                let index = self.expression(index, environment)?;
+                let index = operations::position(index, &reference)?;
+                reference.path.push(Step::Index(index));
+                reference
+            }
+            ExpressionKind::Dereference(operand)
+            | ExpressionKind::UnaryOperation {
+                operator: UnaryOperator::Dereference,
+                operand,
+            } => operations::expect_reference(self.expression(operand, environment)?)?,
+            _ => {
+                let value = self.expression(target, environment)?;
+                Reference::to_temporary(&mut self.runtime.heap, value)
+            }
+        };
+        Ok(reference)
+    }
+
+    /// The storage of the record or array whose field or element `expression` accesses,
+    /// following any references to it.
+    fn part_of(
+        &mut self,
+        expression: &'p Expression,
+        environment: &Environment<'p>,
+    ) -> Result<Reference<'p>, Unwind<'p>> {
+        let reference = self.place(expression, environment)?;
+        Ok(operations::follow(reference)?)
+    }
+
+    fn reference(
+        &mut self,
+        expression: &'p Expression,
+        is_mutable: bool,
+        environment: &Environment<'p>,
+    ) -> Eval<'p> {
+        let place = if expression.is_lvalue() {
+            self.place(expression, environment)?
+        } else {
+            let value = self.expression(expression, environment)?;
+            Reference::to_temporary(&mut self.runtime.heap, value)
+        };
+        Ok(Value::Reference(Reference {
+            is_mutable,
+            ..place
+        }))
+    }
+
+    fn record(
+        &mut self,
+        record_type: &'p Type,
+        fields: &'p [(String, Expression)],
+        base: Option<&'p Expression>,
+        environment: &Environment<'p>,
+    ) -> Eval<'p> {
+        let name = type_name(record_type).unwrap_or_default();
+        let mut values = Vec::new();
+        for (field, value) in fields {
+            values.push((field.as_str(), self.expression(value, environment)?));
+        }
+        let base = match base {
+            Some(base) => Some(self.expression(base, environment)?),
+            None => None,
+        };
+        let declaration = self.records.get(name).copied();
+        Ok(operations::record(name, declaration, values, base)?)
+    }
+
+    /// What the result of `node` is made to fit.
+    fn fit(&self, node: &Expression) -> Fit {
+        Fit::of(self.types().expression(self.id(node)))
+    }
+}
+
+/// The error for a `break` or `return` that unwound out of top-level code.
+fn outside_function(unwind: Unwind) -> RuntimeError {
+    match unwind {
+        Unwind::Error(error) => error,
+        Unwind::Break(_) => RuntimeError::BreakOutsideLoop,
+        Unwind::Return(_) => RuntimeError::ReturnOutsideFunction,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use semantics::{Severity, check, resolve};
+    use test_case::test_case;
+
+    /// Check and run `source`, returning what it prints and the value `main` returns.
+    fn run(source: &str) -> Result<(String, String), RuntimeError> {
+        let program = parser::parse(source).expect("Parse error");
+        let index = NodeIndex::new(&program);
+        let resolution = resolve(&program, &index);
+        assert_eq!(resolution.errors, []);
+        let checked = check(&program, &index, &resolution);
+        let errors: Vec<_> = checked
+            .errors
+            .iter()
+            .filter(|error| error.severity() == Severity::Error)
+            .collect();
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        let mut output = Vec::new();
+        let value = Interpreter::new(&program, &index, &checked.types)
+            .with_output(&mut output)
+            .run()?
+            .to_string();
+        Ok((String::from_utf8(output).unwrap(), value))
+    }
+
+    fn output(source: &str) -> String {
+        run(source).expect("Runtime error").0
+    }
+
+    #[test_case("fn main() { println(1 + 2 * 3, -7 / 2, -7 % 2, 1.5 * 2.0); }", "7 -3 -1 3.0\n" ; "arithmetic")]
+    #[test_case("fn main() { println(\"a\" + \"b\", 1 < 2, 2 == 3, not true); }", "ab true false false\n" ; "strings and comparisons")]
+    #[test_case("fn main() { println(false and 1 / 0 == 0, true or 1 / 0 == 0); }", "false true\n" ; "short circuit")]
+    #[test_case("fn fib(i32 n) -> i32 { if n < 2 { n } else { fib(n - 1) + fib(n - 2) } } fn main() { println(fib(15)); }", "610\n" ; "recursion")]
+    #[test_case("fn main() { x = 1; { x = 2; println(x); }; println(x); }", "2\n1\n" ; "block scope")]
+    #[test_case("fn main() { x = 1; get = || x; x = 2; println(get(), x); }", "1 2\n" ; "closure sees the variable it was written next to")]
+    #[test_case("fn adder(i32 n) -> fn(i32) -> i32 { |i32 x| x + n } fn main() { add2 = adder(2); println(add2(40)); }", "42\n" ; "closure outlives its function")]
+    #[test_case("fn main() { @total = 0; for i in 1..=4 { (total) = total + i; }; total += 10; println(total); }", "20\n" ; "assignment")]
+    #[test_case("fn main() { @i = 0; n = loop { i += 1; if i == 5 { break i * 10; }; }; println(n); }", "50\n" ; "loop with break value")]
+    #[test_case("fn main() { for c in \"hey\" { println(c); }; for x in [1, 2] { println(x); }; }", "h\ne\ny\n1\n2\n" ; "for over strings and arrays")]
+    #[test_case("fn main() { for x in 0..10 { if x == 2 { break; }; println(x); }; }", "0\n1\n" ; "break out of for")]
+    #[test_case("fn first() -> i32 { for x in [7, 8] { return x; }; 0 } fn main() { println(first()); }", "7\n" ; "return from a loop")]
+    #[test_case("fn main() { @xs = [1, 2, 3]; xs[1] = 20; xs[2] *= 2; println(xs, xs[1]); }", "[1, 20, 6] 20\n" ; "element assignment")]
+    #[test_case("fn main() { println(\"abc\"[1], [[1, 2], [3]][0][1]); }", "b 2\n" ; "indexing")]
+    #[test_case("fn main() { println(300 as u8, -2.7 as i32, 3 as f64 / 2.0, 1.1 as f32); }", "44 -2 1.5 1.1\n" ; "casts")]
+    #[test_case("fn main() { u64 a = 9223372036854775807; u64 b = 18446744073709551615; println(-1 as u64, a + 1, b, b as i64); }", "18446744073709551615 9223372036854775808 18446744073709551615 -1\n" ; "full u64 range")]
+    #[test_case("fn main() { @n = 1; while n < 100 { n *= 3; } println(n); }", "243\n" ; "while loop")]
+    #[test_case("fn main() { f32 y = 0.1; println(y, y * 3.0, when y { 0.1: 1; _: 0; }); }", "0.1 0.3 1\n" ; "single precision floats")]
+    #[test_case("const u32 size = base * 2; const u32 base = 4; fn main() { println(size); }", "8\n" ; "consts")]
+    #[test_case("greeting = \"hi\"; fn main() { println(greeting); }", "hi\n" ; "top-level variables run first")]
+    fn test_output(source: &str, expected: &str) {
+        assert_eq!(output(source), expected);
+    }
+
+    #[test_case("
+        record point { x: i32; y: i32; }
+        fn main() {
+            @p = point { y: 2, x: 1 };
+            p.x = 10;
+            q = point { y: 5, ..p };
+            println(p, q, q.x);
+        }",
+        "point { x: 10, y: 2 } point { x: 10, y: 5 } 10\n" ;
+        "records"
+    )]
+    #[test_case("
+        union shape = circle(f64) | square(f64) | dot;
+        fn area(shape s) -> f64 {
+            when s {
+                circle(r): 3.0 * r * r;
+                square(side): side * side;
+                dot: 0.0;
+            }
+        }
+        fn main() { println(area(circle(1.0)), area(square(2.0)), area(dot)); }",
+        "3.0 4.0 0.0\n" ;
+        "union values"
+    )]
+    #[test_case("
+        fn describe(i32 n) -> string {
+            when n {
+                0: \"zero\";
+                1 | 2 | 3: \"few\";
+                m if m < 0: \"negative\";
+                4..=9: \"several\";
+                _: \"many\";
+            }
+        }
+        fn main() { println(describe(0), describe(2), describe(-5), describe(9), describe(10)); }",
+        "zero few negative several many\n" ;
+        "patterns"
+    )]
+    #[test_case("
+        record point { x: i32; y: i32; }
+        fn main() {
+            p = point { x: 3, y: 0 };
+            when p {
+                point { x, y: 0 }: println(\"on the axis at\", x);
+                _: println(\"elsewhere\");
+            };
+            when 7 { 1: println(\"one\"); };
+        }",
+        "on the axis at 3\n" ;
+        "record pattern and unmatched statement"
+    )]
+    #[test_case("
+        fn increment(i32& @count) { *count += 1; }
+        record counter { hits: i32; }
+        fn main() {
+            @n = 1;
+            increment(&@n);
+            @c = counter { hits: 0 };
+            hits = &@c;
+            hits.hits = 5;
+            r = &n;
+            println(n, *r, c.hits);
+        }",
+        "2 2 5\n" ;
+        "references"
+    )]
+    #[test_case("
+        record point { x: i32; y: i32; }
+        patch point {
+            fn sum() -> i32 { self.x + self.y }
+            fn scaled(i32 factor) -> point { point { x: self.x * factor, y: self.y * factor } }
+        }
+        fn main() { p = point { x: 1, y: 2 }; println(p.scaled(3).sum()); }",
+        "9\n" ;
+        "patch methods"
+    )]
+    #[test_case("fn main() { i64 min = -9223372036854775808; println(min, min + 1); }", "-9223372036854775808 -9223372036854775807\n" ; "i64 minimum")]
+    #[test_case("
+        patch i32 { fn double() -> i32 { self * 2 } }
+        fn main() { println(5.double()); }",
+        "10\n" ;
+        "patch method on an integer literal"
+    )]
+    fn test_program(source: &str, expected: &str) {
+        assert_eq!(output(source), expected);
+    }
+
+    #[test]
+    fn test_main_returns_its_value() {
+        assert_eq!(
+            run("fn main() -> i32 { 6 * 7 }"),
+            Ok((String::new(), "42".to_string()))
+        );
+        assert_eq!(run("x = 1;"), Ok((String::new(), "()".to_string())));
+    }
+
+    /// A closure stored in the variable it captures makes a cycle.
+    const CYCLES: &str = "
+        fn main() {
+            for i in 0..5000 {
+                @f = || 0;
+                (f) = || f() + 1;
+            };
+        }";
+
+    #[test]
+    fn test_cycles_are_collected() {
+        let program = parser::parse(CYCLES).expect("Parse error");
+        let index = NodeIndex::new(&program);
+        let resolution = resolve(&program, &index);
+        let checked = check(&program, &index, &resolution);
+        let mut interpreter = Interpreter::new(&program, &index, &checked.types);
+        interpreter.run().unwrap();
+
+        // Each iteration leaves several objects in a cycle.
+        assert!(interpreter.heap().len() < 5000);
+    }
+
+    #[test_case("fn main() { x = 0; println(1 / x); }", RuntimeError::Arithmetic(ArithmeticError::DivisionByZero) ; "division by zero")]
+    #[test_case("fn main() { u8 x = 255; println(x + 1); }", RuntimeError::Arithmetic(ArithmeticError::Overflow) ; "narrow overflow")]
+    #[test_case("fn main() { u64 x = 18446744073709551615; println(x + 1); }", RuntimeError::Arithmetic(ArithmeticError::Overflow) ; "u64 overflow")]
+    #[test_case("fn main() { i64 x = 9223372036854775807; println(x + 1); }", RuntimeError::Arithmetic(ArithmeticError::Overflow) ; "overflow")]
+    #[test_case("fn main() { xs = [1, 2]; println(xs[2]); }", RuntimeError::IndexOutOfBounds { index: 2, length: 2 } ; "index out of bounds")]
+    #[test_case("fn main() { @xs = [1]; i = -1; xs[i] = 0; }", RuntimeError::IndexOutOfBounds { index: -1, length: 1 } ; "negative index")]
+    #[test_case("fn shout<T>(T value) { value.shout(); } fn main() { shout(\"text\"); }", RuntimeError::UnknownMethod { method: "shout".to_string(), receiver: "string" } ; "unknown method")]
+    #[test_case("fn main() { x = 5; y = when x { n if n > 9: n; }; println(y); }", RuntimeError::NoMatchingBranch ; "when matching no branch")]
+    fn test_runtime_error(source: &str, expected: RuntimeError) {
+        assert_eq!(run(source), Err(expected));
+    }
+
+    #[test]
+    fn test_runaway_recursion_is_stopped() {
+        // Each call takes several frames of the interpreter's own stack.
+        let result = std::thread::Builder::new()
+            .stack_size(128 * 1024 * 1024)
+            .spawn(|| run("fn down(i32 n) -> i32 { down(n + 1) } fn main() { down(0); }"))
+            .unwrap()
+            .join()
+            .unwrap();
+
+        assert_eq!(
+            result,
+            Err(RuntimeError::CallDepth {
+                limit: MAX_CALL_DEPTH
+            })
+        );
+    }
+}
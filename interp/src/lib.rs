@@ -0,0 +1,31 @@
+//! Two ways of running checked CV programs.
+//!
+//! The [`Interpreter`] runs the syntax tree directly, using the side tables of the semantic
+//! passes for what the tree does not say itself: which patch method a method call runs, and
+//! which integer type an arithmetic result must fit in. The [`compiler`] makes those decisions
+//! once, turning a program into [`bytecode`] that the [`Vm`] runs faster. Both share the
+//! operations on values, so they print the same output and stop with the same errors.
+//!
+//! An application that embeds CV runs programs through an [`Engine`], which checks and runs them
+//! in one step and lets the application give them [host functions](host) written in Rust.
+
+pub mod backtrace;
+pub mod builtins;
+pub mod bytecode;
+pub mod compiler;
+pub mod engine;
+mod environment;
+pub mod heap;
+pub mod host;
+pub mod interpreter;
+mod operations;
+mod pattern;
+pub mod value;
+pub mod vm;
+
+pub use compiler::compile;
+pub use engine::{Engine, EngineError};
+pub use host::{HostFunction, HostValue};
+pub use interpreter::{Interpreter, MAX_CALL_DEPTH, RuntimeError};
+pub use value::Value;
+pub use vm::Vm;
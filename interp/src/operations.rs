@@ -0,0 +1,429 @@
+//! The operations every way of running a program shares, so that the interpreter and the
+//! virtual machine compute the same values and fail with the same errors.
+
+use crate::interpreter::RuntimeError;
+use crate::value::{Reference, Value};
+use parser::ast::{
+    BinaryOperator, Declaration, Parameter, Program, RecordDeclaration, Type, UnaryOperator,
+};
+use parser::const_eval::ConstEvaluator;
+use parser::operators::{ArithmeticError, integer_arithmetic, integer_bounds, integer_cast};
+
+/// What the result of arithmetic is made to fit, decided by the type of the expression that
+/// computes it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fit {
+    /// An integer, which overflows outside `min..=max`.
+    Integer { min: i128, max: i128 },
+    /// A float, rounded to single precision.
+    Single,
+    /// Anything else, kept as computed.
+    Exact,
+}
+
+impl Fit {
+    /// What a result of type `ty` is made to fit.
+    pub fn of(ty: Option<&Type>) -> Self {
+        match ty {
+            Some(Type::F32) => Fit::Single,
+            Some(ty) => {
+                integer_bounds(ty).map_or(Fit::Exact, |(min, max)| Fit::Integer { min, max })
+            }
+            None => Fit::Exact,
+        }
+    }
+}
+
+pub(crate) fn binary<'p>(
+    operator: BinaryOperator,
+    left: Value<'p>,
+    right: Value<'p>,
+    fit: Fit,
+) -> Result<Value<'p>, RuntimeError> {
+    let invalid = |left: &Value, right: &Value| RuntimeError::InvalidOperands {
+        operator,
+        left: left.kind(),
+        right: right.kind(),
+    };
+    let value = match operator {
+        BinaryOperator::Equal => Value::Boolean(left == right),
+        BinaryOperator::NotEqual => Value::Boolean(left != right),
+        BinaryOperator::LessThan
+        | BinaryOperator::LessThanOrEqual
+        | BinaryOperator::GreaterThan
+        | BinaryOperator::GreaterThanOrEqual => {
+            let Some(ordering) = left.compare(&right) else {
+                // NaN compares false with everything, including itself.
+                return match (&left, &right) {
+                    _ if left.float().is_some() && right.float().is_some() => {
+                        Ok(Value::Boolean(false))
+                    }
+                    _ => Err(invalid(&left, &right)),
+                };
+            };
+            Value::Boolean(match operator {
+                BinaryOperator::LessThan => ordering.is_lt(),
+                BinaryOperator::LessThanOrEqual => ordering.is_le(),
+                BinaryOperator::GreaterThan => ordering.is_gt(),
+                _ => ordering.is_ge(),
+            })
+        }
+        _ => match (&left, &right) {
+            (Value::Integer(left_value), Value::Integer(right_value)) => {
+                match integer_arithmetic(operator, *left_value, *right_value) {
+                    Some(result) => integer(result?, fit)?,
+                    None => return Err(invalid(&left, &right)),
+                }
+            }
+            _ if let (Some(left_value), Some(right_value)) = (left.float(), right.float()) => {
+                let result = match operator {
+                    BinaryOperator::Add => left_value + right_value,
+                    BinaryOperator::Subtract => left_value - right_value,
+                    BinaryOperator::Multiply => left_value * right_value,
+                    BinaryOperator::Divide => left_value / right_value,
+                    BinaryOperator::Modulus => left_value % right_value,
+                    _ => return Err(invalid(&left, &right)),
+                };
+                float(result, fit)
+            }
+            (Value::String(left_value), Value::String(right_value))
+                if operator == BinaryOperator::Add =>
+            {
+                Value::String(format!("{}{}", left_value, right_value))
+            }
+            _ => return Err(invalid(&left, &right)),
+        },
+    };
+    Ok(value)
+}
+
+/// `not` and `-`. References are taken and followed by whoever evaluates the operand, which
+/// this does not see.
+pub(crate) fn unary(
+    operator: UnaryOperator,
+    operand: Value,
+    fit: Fit,
+) -> Result<Value, RuntimeError> {
+    match (operator, operand) {
+        (UnaryOperator::Negate, Value::Integer(value)) => {
+            let negated = value.checked_neg().ok_or(ArithmeticError::Overflow)?;
+            integer(negated, fit)
+        }
+        (UnaryOperator::Negate, Value::Float(value)) => Ok(float(-value, fit)),
+        (UnaryOperator::Negate, Value::Single(value)) => Ok(Value::Single(-value)),
+        (UnaryOperator::Not, Value::Boolean(value)) => Ok(Value::Boolean(!value)),
+        (operator, operand) => Err(RuntimeError::InvalidOperand {
+            operator,
+            operand: operand.kind(),
+        }),
+    }
+}
+
+/// An integer result, which must fit in the integer type it was computed as.
+pub(crate) fn integer<'p>(value: i128, fit: Fit) -> Result<Value<'p>, RuntimeError> {
+    match fit {
+        Fit::Integer { min, max } if !(min..=max).contains(&value) => {
+            Err(ArithmeticError::Overflow.into())
+        }
+        _ => Ok(Value::Integer(value)),
+    }
+}
+
+/// A float result, rounded to single precision if it was computed as an `f32`.
+pub(crate) fn float<'p>(value: f64, fit: Fit) -> Value<'p> {
+    match fit {
+        Fit::Single => Value::Single(value as f32),
+        _ => Value::Float(value),
+    }
+}
+
+/// Convert `value` the way `value as target` does: an integer cast to a narrower integer type
+/// keeps its low bits, a float cast to an integer type is truncated toward zero, and a
+/// reference stays the same reference.
+pub(crate) fn cast<'p>(value: Value<'p>, target: &Type) -> Result<Value<'p>, RuntimeError> {
+    let invalid = |value: &Value| RuntimeError::InvalidCast {
+        from: value.kind(),
+        to: target.clone(),
+    };
+    let value = match (&value, target) {
+        (Value::Integer(integer), Type::F32) => Value::Single(*integer as f32),
+        (Value::Integer(integer), Type::F64) => Value::Float(*integer as f64),
+        (Value::Integer(integer), _) => {
+            Value::Integer(integer_cast(*integer, target).ok_or_else(|| invalid(&value))?)
+        }
+        _ if let Some(float) = value.float() => match target {
+            Type::F32 => Value::Single(float as f32),
+            Type::F64 => Value::Float(float),
+            _ => {
+                Value::Integer(integer_cast(float as i128, target).ok_or_else(|| invalid(&value))?)
+            }
+        },
+        (Value::Reference(_), Type::Reference { .. }) => value,
+        _ => return Err(invalid(&value)),
+    };
+    Ok(value)
+}
+
+/// The value of every const `program` declares, whose values may use the consts among
+/// `earlier`, declared by programs loaded before it.
+pub(crate) fn constants<'p>(
+    earlier: &[&'p Declaration],
+    program: &'p Program,
+) -> Result<Vec<(&'p str, Value<'p>)>, RuntimeError> {
+    let mut evaluator = ConstEvaluator::of(earlier.iter().copied().chain(&program.declarations));
+    let mut constants = Vec::new();
+    for declaration in &program.declarations {
+        if let Declaration::Const(constant) = declaration {
+            let value = evaluator.constant(&constant.name)?;
+            constants.push((constant.name.as_str(), value.into()));
+        }
+    }
+    Ok(constants)
+}
+
+pub(crate) fn check_arguments(
+    function: &str,
+    params: &[Parameter],
+    arguments: usize,
+) -> Result<(), RuntimeError> {
+    if params.len() == arguments {
+        return Ok(());
+    }
+    Err(RuntimeError::ArgumentCount {
+        function: function.to_string(),
+        expected: params.len(),
+        found: arguments,
+    })
+}
+
+/// The variant the constructor of `name` builds from `arguments`.
+pub(crate) fn construct<'p>(name: &'p str, arguments: Vec<Value<'p>>) -> Value<'p> {
+    let payload = match <[_; 1]>::try_from(arguments) {
+        Ok([value]) => value,
+        Err(values) => Value::Tuple(values),
+    };
+    Value::Variant {
+        name,
+        payload: Some(Box::new(payload)),
+    }
+}
+
+/// The record `name` built from the fields a literal lists and the record after its `..`,
+/// with its fields in the order `declaration` declares them.
+pub(crate) fn record<'p>(
+    name: &'p str,
+    declaration: Option<&'p RecordDeclaration>,
+    values: Vec<(&'p str, Value<'p>)>,
+    base: Option<Value<'p>>,
+) -> Result<Value<'p>, RuntimeError> {
+    let base = match base.map(automatic_dereference).transpose()? {
+        Some(Value::Record { fields, .. }) => fields,
+        Some(base) => {
+            return Err(RuntimeError::TypeMismatch {
+                expected: "record",
+                found: base.kind(),
+            });
+        }
+        None => Vec::new(),
+    };
+    let Some(declaration) = declaration else {
+        return Ok(Value::Record {
+            name,
+            fields: values,
+        });
+    };
+    // Fields are kept in declaration order, whatever order the literal lists them in.
+    let mut fields = Vec::new();
+    for declared in &declaration.fields {
+        let field = declared.name.as_str();
+        let value = values
+            .iter()
+            .chain(&base)
+            .find(|(name, _)| *name == field)
+            .map(|(_, value)| value.clone())
+            .ok_or_else(|| RuntimeError::NoField {
+                field: field.to_string(),
+            })?;
+        fields.push((field, value));
+    }
+    Ok(Value::Record { name, fields })
+}
+
+/// The field `field` of `record`.
+pub(crate) fn field<'p>(record: Value<'p>, field: &str) -> Result<Value<'p>, RuntimeError> {
+    match automatic_dereference(record)? {
+        Value::Record { fields, .. } => fields
+            .into_iter()
+            .find(|(name, _)| *name == field)
+            .map(|(_, value)| value)
+            .ok_or_else(|| RuntimeError::NoField {
+                field: field.to_string(),
+            }),
+        record => Err(RuntimeError::TypeMismatch {
+            expected: "record",
+            found: record.kind(),
+        }),
+    }
+}
+
+/// The value `value` refers to.
+pub(crate) fn dereference(value: Value) -> Result<Value, RuntimeError> {
+    match value {
+        Value::Reference(reference) => reference.get(),
+        value => Err(RuntimeError::TypeMismatch {
+            expected: "reference",
+            found: value.kind(),
+        }),
+    }
+}
+
+/// The reference `value` must be to be assigned through.
+pub(crate) fn expect_reference(value: Value) -> Result<Reference, RuntimeError> {
+    match value {
+        Value::Reference(reference) => Ok(reference),
+        value => Err(RuntimeError::TypeMismatch {
+            expected: "reference",
+            found: value.kind(),
+        }),
+    }
+}
+
+/// `value`, or what it refers to if it is a reference, as field access, method calls, indexing,
+/// and loops see it.
+pub(crate) fn automatic_dereference(mut value: Value) -> Result<Value, RuntimeError> {
+    while let Value::Reference(reference) = value {
+        value = reference.get()?;
+    }
+    Ok(value)
+}
+
+/// `reference`, or what it refers to if it holds a reference, as the record or array whose
+/// field or element is assigned to.
+pub(crate) fn follow(mut reference: Reference) -> Result<Reference, RuntimeError> {
+    while let Some(inner) = reference.with_value(|value| match value {
+        Value::Reference(inner) => Some(inner.clone()),
+        _ => None,
+    })? {
+        reference = inner;
+    }
+    Ok(reference)
+}
+
+/// The position `index` gives in the array `reference` refers to, for assigning to an element.
+pub(crate) fn position(index: Value, reference: &Reference) -> Result<usize, RuntimeError> {
+    match index {
+        Value::Integer(index) => {
+            usize::try_from(index).map_err(|_| RuntimeError::IndexOutOfBounds {
+                index,
+                length: reference.with_value(length).unwrap_or(0),
+            })
+        }
+        index => Err(RuntimeError::TypeMismatch {
+            expected: "integer",
+            found: index.kind(),
+        }),
+    }
+}
+
+fn length(value: &Value) -> usize {
+    match value {
+        Value::Array(elements) => elements.len(),
+        Value::String(string) => string.chars().count(),
+        _ => 0,
+    }
+}
+
+/// The element of `collection` at `index`: an element of an array or a char of a string.
+pub(crate) fn index<'p>(
+    collection: Value<'p>,
+    index: Value<'p>,
+) -> Result<Value<'p>, RuntimeError> {
+    let Value::Integer(index) = index else {
+        return Err(RuntimeError::TypeMismatch {
+            expected: "integer",
+            found: index.kind(),
+        });
+    };
+    let out_of_bounds = RuntimeError::IndexOutOfBounds {
+        index,
+        length: length(&collection),
+    };
+    let Ok(position) = usize::try_from(index) else {
+        return Err(out_of_bounds);
+    };
+    match collection {
+        Value::Array(elements) => elements.into_iter().nth(position).ok_or(out_of_bounds),
+        Value::String(string) => string
+            .chars()
+            .nth(position)
+            .map(Value::Char)
+            .ok_or(out_of_bounds),
+        collection => Err(RuntimeError::TypeMismatch {
+            expected: "array or string",
+            found: collection.kind(),
+        }),
+    }
+}
+
+/// What a `for` loop over `iterable` goes through, with a string split into its chars, so that
+/// [`element`] can find each element by its position.
+pub(crate) fn iteration(iterable: Value) -> Result<Value, RuntimeError> {
+    match automatic_dereference(iterable)? {
+        Value::String(string) => Ok(Value::Array(string.chars().map(Value::Char).collect())),
+        array @ Value::Array(_) => Ok(array),
+        Value::Range {
+            start,
+            end,
+            inclusive,
+        } => match (*start, *end) {
+            (start @ Value::Integer(_), end @ Value::Integer(_))
+            | (start @ Value::Char(_), end @ Value::Char(_)) => Ok(Value::Range {
+                start: Box::new(start),
+                end: Box::new(end),
+                inclusive,
+            }),
+            (start, _) => Err(RuntimeError::TypeMismatch {
+                expected: "range of integers or chars",
+                found: start.kind(),
+            }),
+        },
+        iterable => Err(RuntimeError::TypeMismatch {
+            expected: "array, string, or range",
+            found: iterable.kind(),
+        }),
+    }
+}
+
+/// The element at `position` of what [`iteration`] made, or `None` past the last one.
+pub(crate) fn element<'p>(iteration: &Value<'p>, position: usize) -> Option<Value<'p>> {
+    match iteration {
+        Value::Array(elements) => elements.get(position).cloned(),
+        Value::Range {
+            start,
+            end,
+            inclusive,
+        } => {
+            let element = match **start {
+                Value::Integer(start) => {
+                    Value::Integer(start.checked_add(i128::try_from(position).ok()?)?)
+                }
+                Value::Char(start) => Value::Char(nth_char(start, position)?),
+                _ => return None,
+            };
+            let ordering = element.compare(end)?;
+            (ordering.is_lt() || (*inclusive && ordering.is_eq())).then_some(element)
+        }
+        _ => None,
+    }
+}
+
+/// The char `n` places after `start`, skipping the code points of UTF-16 surrogates, which are
+/// not chars.
+fn nth_char(start: char, n: usize) -> Option<char> {
+    const SURROGATES: std::ops::Range<u32> = 0xD800..0xE000;
+    let mut code = u32::try_from(n).ok()?.checked_add(start as u32)?;
+    if (start as u32) < SURROGATES.start && code >= SURROGATES.start {
+        code = code.checked_add(SURROGATES.len() as u32)?;
+    }
+    char::from_u32(code)
+}
@@ -0,0 +1,235 @@
+//! Matching values against the patterns of `when` branches.
+
+use crate::value::Value;
+use parser::ast::{Pattern, PatternKind, Type};
+use std::collections::HashSet;
+
+/// The variables `pattern` binds when `value` matches it, or `None` if it does not match.
+/// `variants` holds the names of every union variant, which a bare name matches instead of
+/// binding it.
+pub(crate) fn bindings<'p>(
+    pattern: &'p Pattern,
+    value: &Value<'p>,
+    variants: &HashSet<&str>,
+) -> Option<Vec<(&'p str, Value<'p>)>> {
+    let mut bindings = Vec::new();
+    matches(pattern, value, variants, &mut bindings).then_some(bindings)
+}
+
+fn matches<'p>(
+    pattern: &'p Pattern,
+    value: &Value<'p>,
+    variants: &HashSet<&str>,
+    bindings: &mut Vec<(&'p str, Value<'p>)>,
+) -> bool {
+    // A reference matches what the value it refers to does.
+    if let Value::Reference(reference) = value {
+        return reference
+            .get()
+            .is_ok_and(|value| matches(pattern, &value, variants, bindings));
+    }
+    match &pattern.kind {
+        PatternKind::Identifier(name) if variants.contains(name.as_str()) => {
+            matches!(value, Value::Variant { name: variant, payload: None } if variant == name)
+        }
+        PatternKind::Identifier(name) => {
+            bindings.push((name, value.clone()));
+            true
+        }
+        PatternKind::Literal(literal) => *value == Value::from(literal),
+        PatternKind::Union { variant, payload } => match value {
+            Value::Variant {
+                name,
+                payload: value,
+            } if name == variant => match (payload, value) {
+                (Some(pattern), Some(value)) => matches(pattern, value, variants, bindings),
+                (Some(_), None) => false,
+                (None, _) => true,
+            },
+            _ => false,
+        },
+        PatternKind::Record {
+            record_type,
+            fields,
+        } => match value {
+            Value::Record {
+                name,
+                fields: values,
+            } if Some(*name) == type_name(record_type) => fields.iter().all(|(field, pattern)| {
+                values
+                    .iter()
+                    .find(|(name, _)| name == field)
+                    .is_some_and(|(_, value)| matches(pattern, value, variants, bindings))
+            }),
+            _ => false,
+        },
+        PatternKind::Tuple(elements) => match value {
+            Value::Tuple(values) if values.len() == elements.len() => elements
+                .iter()
+                .zip(values)
+                .all(|(pattern, value)| matches(pattern, value, variants, bindings)),
+            _ => false,
+        },
+        PatternKind::Range {
+            start,
+            end,
+            inclusive,
+        } => {
+            let above_start = Value::from(start)
+                .compare(value)
+                .is_some_and(|ordering| ordering.is_le());
+            let below_end = value.compare(&Value::from(end)).is_some_and(|ordering| {
+                if *inclusive {
+                    ordering.is_le()
+                } else {
+                    ordering.is_lt()
+                }
+            });
+            above_start && below_end
+        }
+        PatternKind::Or(alternatives) => alternatives.iter().any(|alternative| {
+            let bound = bindings.len();
+            let matched = matches(alternative, value, variants, bindings);
+            if !matched {
+                bindings.truncate(bound);
+            }
+            matched
+        }),
+        PatternKind::Wildcard | PatternKind::Else => true,
+    }
+}
+
+/// The variables `pattern` binds, in the order [`bindings`] binds them when it matches the
+/// first alternative of each `|`.
+pub(crate) fn names<'p>(pattern: &'p Pattern, variants: &HashSet<&str>) -> Vec<&'p str> {
+    let mut names = Vec::new();
+    collect_names(pattern, variants, &mut names);
+    names
+}
+
+fn collect_names<'p>(pattern: &'p Pattern, variants: &HashSet<&str>, names: &mut Vec<&'p str>) {
+    match &pattern.kind {
+        PatternKind::Identifier(name) if !variants.contains(name.as_str()) => names.push(name),
+        PatternKind::Union {
+            payload: Some(payload),
+            ..
+        } => collect_names(payload, variants, names),
+        PatternKind::Record { fields, .. } => {
+            for (_, pattern) in fields {
+                collect_names(pattern, variants, names);
+            }
+        }
+        PatternKind::Tuple(elements) => {
+            for pattern in elements {
+                collect_names(pattern, variants, names);
+            }
+        }
+        PatternKind::Or(alternatives) => {
+            if let Some(first) = alternatives.first() {
+                collect_names(first, variants, names);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The name of the record or union a type names, without its type arguments.
+pub(crate) fn type_name(ty: &Type) -> Option<&str> {
+    match ty {
+        Type::Named(name) | Type::Generic { name, .. } => Some(name),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::ast::Literal;
+    use test_case::test_case;
+
+    fn some(value: Value<'static>) -> Value<'static> {
+        Value::Variant {
+            name: "some",
+            payload: Some(Box::new(value)),
+        }
+    }
+
+    fn bound(pattern: &Pattern, value: &Value<'static>) -> Option<Vec<String>> {
+        let pattern: &'static Pattern = Box::leak(Box::new(pattern.clone()));
+        let variants = HashSet::from(["some", "none"]);
+        bindings(pattern, value, &variants).map(|bindings| {
+            bindings
+                .iter()
+                .map(|(name, value)| format!("{} = {}", name, value))
+                .collect()
+        })
+    }
+
+    fn binding(name: &str) -> Pattern {
+        PatternKind::Identifier(name.to_string()).into()
+    }
+
+    fn some_of(payload: Pattern) -> Pattern {
+        PatternKind::Union {
+            variant: "some".to_string(),
+            payload: Some(Box::new(payload)),
+        }
+        .into()
+    }
+
+    #[test_case(some_of(binding("x")), some(Value::Integer(1)), Some(&["x = 1"]) ; "variant payload")]
+    #[test_case(binding("none"), some(Value::Integer(1)), None ; "bare variant name")]
+    #[test_case(binding("none"), Value::Variant { name: "none", payload: None }, Some(&[]) ; "bare variant name matches")]
+    #[test_case(
+        some_of(PatternKind::Tuple(vec![binding("a"), PatternKind::Wildcard.into()]).into()),
+        some(Value::Tuple(vec![Value::Integer(1), Value::Integer(2)])),
+        Some(&["a = 1"]) ;
+        "tuple payload"
+    )]
+    #[test_case(
+        PatternKind::Range { start: Literal::Char('a'), end: Literal::Char('z'), inclusive: true }.into(),
+        Value::Char('z'),
+        Some(&[]) ;
+        "inclusive range"
+    )]
+    #[test_case(
+        PatternKind::Range { start: Literal::Integer(0), end: Literal::Integer(10), inclusive: false }.into(),
+        Value::Integer(10),
+        None ;
+        "exclusive range"
+    )]
+    #[test_case(
+        PatternKind::Or(vec![some_of(PatternKind::Literal(Literal::Integer(0)).into()), some_of(binding("n"))]).into(),
+        some(Value::Integer(4)),
+        Some(&["n = 4"]) ;
+        "or pattern"
+    )]
+    fn test_bindings(pattern: Pattern, value: Value<'static>, expected: Option<&[&str]>) {
+        assert_eq!(
+            bound(&pattern, &value),
+            expected.map(|expected| expected.iter().map(ToString::to_string).collect())
+        );
+    }
+
+    #[test]
+    fn test_record_pattern() {
+        let pattern = PatternKind::Record {
+            record_type: Type::Named("point".to_string()),
+            fields: vec![
+                ("x".to_string(), binding("x")),
+                (
+                    "y".to_string(),
+                    PatternKind::Literal(Literal::Integer(0)).into(),
+                ),
+            ],
+        }
+        .into();
+        let point = |y| Value::Record {
+            name: "point",
+            fields: vec![("x", Value::Integer(3)), ("y", Value::Integer(y))],
+        };
+
+        assert_eq!(bound(&pattern, &point(0)), Some(vec!["x = 3".to_string()]));
+        assert_eq!(bound(&pattern, &point(1)), None);
+    }
+}
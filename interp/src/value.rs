@@ -0,0 +1,559 @@
+//! The values a running CV program computes with.
+
+use crate::builtins::Builtin;
+use crate::environment::Environment;
+use crate::heap::{Gc, Heap, Trace, Tracer};
+use crate::host::HostFunction;
+use crate::interpreter::RuntimeError;
+use parser::ast::{Expression, FunctionDeclaration, Literal, Parameter};
+use parser::const_eval::ConstValue;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::fmt;
+use std::rc::Rc;
+
+/// The storage of a variable, shared by the variable and every reference to it.
+pub type Slot<'p> = Gc<RefCell<Value<'p>>>;
+
+/// A value of a program whose syntax tree lives for `'p`, which functions and closures point
+/// into.
+#[derive(Debug, Clone)]
+pub enum Value<'p> {
+    Integer(i128),
+    Float(f64),
+    /// An `f32`, kept at its own width so that it prints the way an `f32` does.
+    Single(f32),
+    Boolean(bool),
+    Char(char),
+    String(String),
+    Unit,
+    /// An `arrayList` or a `fixedArray`.
+    Array(Vec<Value<'p>>),
+    /// The payload of a variant that carries several values, as in `rectangle(2.0, 3.0)`.
+    Tuple(Vec<Value<'p>>),
+    Range {
+        start: Box<Value<'p>>,
+        end: Box<Value<'p>>,
+        inclusive: bool,
+    },
+    /// A record, with its fields in the order the record declares them.
+    Record {
+        name: &'p str,
+        fields: Vec<(&'p str, Value<'p>)>,
+    },
+    Variant {
+        name: &'p str,
+        payload: Option<Box<Value<'p>>>,
+    },
+    Function(&'p FunctionDeclaration),
+    Closure(Gc<Closure<'p>>),
+    /// A closure the [`Vm`](crate::vm::Vm) runs.
+    Compiled(Gc<CompiledClosure<'p>>),
+    /// A variant that carries data, used as the function that builds it.
+    Constructor(&'p str),
+    Builtin(&'static Builtin),
+    Host(Rc<HostFunction>),
+    Reference(Reference<'p>),
+}
+
+/// A closure together with the variables that were visible where it was written.
+pub struct Closure<'p> {
+    pub(crate) params: &'p [Parameter],
+    pub(crate) body: &'p Expression,
+    pub(crate) environment: Environment<'p>,
+    /// The program the closure was written in, which numbers the nodes of its body.
+    pub(crate) unit: usize,
+}
+
+impl fmt::Debug for Closure<'_> {
+    // The environment can hold the closure itself, through a variable it was assigned to.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Closure")
+            .field("params", &self.params)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A closure compiled to bytecode: the function its body was compiled to, and the cells of the
+/// variables it captured, which it shares with the function that declared them.
+pub struct CompiledClosure<'p> {
+    pub(crate) function: usize,
+    pub(crate) captures: Vec<Reference<'p>>,
+}
+
+impl fmt::Debug for CompiledClosure<'_> {
+    // A captured variable can hold the closure itself.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompiledClosure")
+            .field("function", &self.function)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A step from a value to a part of it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step<'p> {
+    Field(&'p str),
+    Index(usize),
+}
+
+/// A reference to a variable, or to a field or element somewhere inside one.
+#[derive(Debug, Clone)]
+pub struct Reference<'p> {
+    pub slot: Slot<'p>,
+    pub path: Vec<Step<'p>>,
+    pub is_mutable: bool,
+}
+
+impl<'p> Reference<'p> {
+    /// A reference to the whole of `slot`.
+    pub fn to(slot: Slot<'p>) -> Self {
+        Reference {
+            slot,
+            path: Vec::new(),
+            is_mutable: false,
+        }
+    }
+
+    /// A reference to a value that no variable holds, such as the result of a call.
+    pub fn to_temporary(heap: &mut Heap<'p>, value: Value<'p>) -> Self {
+        Reference::to(heap.allocate(RefCell::new(value)))
+    }
+
+    /// The value referred to.
+    pub fn get(&self) -> Result<Value<'p>, RuntimeError> {
+        self.with_value(Value::clone)
+    }
+
+    /// Replace the value referred to.
+    pub fn set(&self, value: Value<'p>) -> Result<(), RuntimeError> {
+        self.with_value_mut(|target| *target = value)
+    }
+
+    /// Apply `f` to the value referred to without copying it.
+    pub fn with_value<T>(&self, f: impl FnOnce(&Value<'p>) -> T) -> Result<T, RuntimeError> {
+        let mut value = &*self.slot.borrow();
+        for step in &self.path {
+            value = match (value, step) {
+                (Value::Record { fields, .. }, Step::Field(field)) => field_of(fields, field)?,
+                (Value::Array(elements), Step::Index(index)) => {
+                    elements.get(*index).ok_or(RuntimeError::IndexOutOfBounds {
+                        index: *index as i128,
+                        length: elements.len(),
+                    })?
+                }
+                (value, step) => return Err(mismatched_step(value, step)),
+            };
+        }
+        Ok(f(value))
+    }
+
+    fn with_value_mut<T>(&self, f: impl FnOnce(&mut Value<'p>) -> T) -> Result<T, RuntimeError> {
+        let mut value = &mut *self.slot.borrow_mut();
+        for step in &self.path {
+            value = match (value, step) {
+                (Value::Record { fields, .. }, Step::Field(field)) => {
+                    match fields.iter_mut().find(|(name, _)| name == field) {
+                        Some((_, value)) => value,
+                        None => return Err(missing_field(field)),
+                    }
+                }
+                (Value::Array(elements), Step::Index(index)) => {
+                    let length = elements.len();
+                    elements
+                        .get_mut(*index)
+                        .ok_or(RuntimeError::IndexOutOfBounds {
+                            index: *index as i128,
+                            length,
+                        })?
+                }
+                (value, step) => return Err(mismatched_step(value, step)),
+            };
+        }
+        Ok(f(value))
+    }
+}
+
+fn field_of<'v, 'p>(
+    fields: &'v [(&'p str, Value<'p>)],
+    field: &str,
+) -> Result<&'v Value<'p>, RuntimeError> {
+    fields
+        .iter()
+        .find(|(name, _)| *name == field)
+        .map(|(_, value)| value)
+        .ok_or_else(|| missing_field(field))
+}
+
+fn missing_field(field: &str) -> RuntimeError {
+    RuntimeError::NoField {
+        field: field.to_string(),
+    }
+}
+
+fn mismatched_step(value: &Value, step: &Step) -> RuntimeError {
+    RuntimeError::TypeMismatch {
+        expected: match step {
+            Step::Field(_) => "record",
+            Step::Index(_) => "array",
+        },
+        found: value.kind(),
+    }
+}
+
+impl Value<'_> {
+    /// Show `tracer` the handles this value holds, looking inside arrays, records, and the
+    /// other values it holds in place.
+    fn trace(&self, tracer: &mut Tracer) {
+        match self {
+            Value::Array(elements) | Value::Tuple(elements) => {
+                for element in elements {
+                    element.trace(tracer);
+                }
+            }
+            Value::Range { start, end, .. } => {
+                start.trace(tracer);
+                end.trace(tracer);
+            }
+            Value::Record { fields, .. } => {
+                for (_, value) in fields {
+                    value.trace(tracer);
+                }
+            }
+            Value::Variant {
+                payload: Some(payload),
+                ..
+            } => payload.trace(tracer),
+            Value::Closure(closure) => tracer.visit(closure),
+            Value::Compiled(closure) => tracer.visit(closure),
+            Value::Reference(reference) => tracer.visit(&reference.slot),
+            _ => {}
+        }
+    }
+}
+
+/// A variable, which assignments can make part of a cycle.
+impl Trace for RefCell<Value<'_>> {
+    // A slot that is being read or written while the heap collects counts as reachable,
+    // since what it holds cannot be seen.
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Ok(value) = self.try_borrow() {
+            value.trace(tracer);
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut value) = self.try_borrow_mut() {
+            *value = Value::Unit;
+        }
+    }
+}
+
+impl Trace for Closure<'_> {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.environment.trace(tracer);
+    }
+}
+
+impl Trace for CompiledClosure<'_> {
+    fn trace(&self, tracer: &mut Tracer) {
+        for capture in &self.captures {
+            tracer.visit(&capture.slot);
+        }
+    }
+}
+
+impl Value<'_> {
+    /// The kind of value, for error messages.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Value::Integer(_) => "integer",
+            Value::Float(_) | Value::Single(_) => "float",
+            Value::Boolean(_) => "bool",
+            Value::Char(_) => "char",
+            Value::String(_) => "string",
+            Value::Unit => "unit",
+            Value::Array(_) => "array",
+            Value::Tuple(_) => "tuple",
+            Value::Range { .. } => "range",
+            Value::Record { .. } => "record",
+            Value::Variant { .. } => "variant",
+            Value::Function(_)
+            | Value::Closure(_)
+            | Value::Compiled(_)
+            | Value::Constructor(_)
+            | Value::Builtin(_)
+            | Value::Host(_) => "function",
+            Value::Reference(_) => "reference",
+        }
+    }
+
+    /// How two values of the same kind are ordered, if they can be compared with `<`.
+    pub fn compare(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Integer(left), Value::Integer(right)) => left.partial_cmp(right),
+            (Value::Char(left), Value::Char(right)) => left.partial_cmp(right),
+            (Value::String(left), Value::String(right)) => left.partial_cmp(right),
+            (left, right) => left.float()?.partial_cmp(&right.float()?),
+        }
+    }
+
+    /// The number an `f32` or `f64` holds, widened to an `f64`.
+    pub fn float(&self) -> Option<f64> {
+        match self {
+            Value::Float(value) => Some(*value),
+            Value::Single(value) => Some(f64::from(*value)),
+            _ => None,
+        }
+    }
+
+    /// The value as it is shown inside other values, with strings and chars quoted, which is
+    /// how a REPL echoes it.
+    pub fn quoted(&self) -> impl fmt::Display + '_ {
+        Nested(self)
+    }
+}
+
+impl PartialEq for Value<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Integer(left), Value::Integer(right)) => left == right,
+            (Value::Float(left), Value::Float(right)) => left == right,
+            (Value::Single(left), Value::Single(right)) => left == right,
+            // A float literal matched against an `f32` is rounded the way it is when it is
+            // written where an `f32` is expected.
+            (Value::Single(single), Value::Float(float))
+            | (Value::Float(float), Value::Single(single)) => *single == *float as f32,
+            (Value::Boolean(left), Value::Boolean(right)) => left == right,
+            (Value::Char(left), Value::Char(right)) => left == right,
+            (Value::String(left), Value::String(right)) => left == right,
+            (Value::Unit, Value::Unit) => true,
+            (Value::Array(left), Value::Array(right))
+            | (Value::Tuple(left), Value::Tuple(right)) => left == right,
+            (
+                Value::Range {
+                    start,
+                    end,
+                    inclusive,
+                },
+                Value::Range {
+                    start: other_start,
+                    end: other_end,
+                    inclusive: other_inclusive,
+                },
+            ) => start == other_start && end == other_end && inclusive == other_inclusive,
+            (
+                Value::Record { name, fields },
+                Value::Record {
+                    name: other_name,
+                    fields: other_fields,
+                },
+            ) => name == other_name && fields == other_fields,
+            (
+                Value::Variant { name, payload },
+                Value::Variant {
+                    name: other_name,
+                    payload: other_payload,
+                },
+            ) => name == other_name && payload == other_payload,
+            (Value::Function(left), Value::Function(right)) => std::ptr::eq(*left, *right),
+            (Value::Closure(left), Value::Closure(right)) => Gc::ptr_eq(left, right),
+            (Value::Compiled(left), Value::Compiled(right)) => Gc::ptr_eq(left, right),
+            (Value::Constructor(left), Value::Constructor(right)) => left == right,
+            (Value::Builtin(left), Value::Builtin(right)) => std::ptr::eq(*left, *right),
+            (Value::Host(left), Value::Host(right)) => Rc::ptr_eq(left, right),
+            // References are equal when the values they refer to are.
+            (Value::Reference(left), Value::Reference(right)) => {
+                matches!((left.get(), right.get()), (Ok(left), Ok(right)) if left == right)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl From<&Literal> for Value<'_> {
+    fn from(literal: &Literal) -> Self {
+        match literal {
+            Literal::Integer(value) => Value::Integer(*value),
+            Literal::Float(value) => Value::Float(*value),
+            Literal::Boolean(value) => Value::Boolean(*value),
+            Literal::String(value) => Value::String(value.clone()),
+            Literal::Char(value) => Value::Char(*value),
+            Literal::Bytes(bytes) => Value::Array(
+                bytes
+                    .iter()
+                    .map(|byte| Value::Integer(i128::from(*byte)))
+                    .collect(),
+            ),
+            Literal::Unit => Value::Unit,
+        }
+    }
+}
+
+impl From<ConstValue> for Value<'_> {
+    fn from(value: ConstValue) -> Self {
+        match value {
+            ConstValue::Integer(value) => Value::Integer(value),
+            ConstValue::Float(value) => Value::Float(value),
+            ConstValue::Boolean(value) => Value::Boolean(value),
+            ConstValue::Char(value) => Value::Char(value),
+            ConstValue::String(value) => Value::String(value),
+            ConstValue::Unit => Value::Unit,
+            ConstValue::Array(elements) => {
+                Value::Array(elements.into_iter().map(Value::from).collect())
+            }
+            ConstValue::Range {
+                start,
+                end,
+                inclusive,
+            } => Value::Range {
+                start: Box::new(Value::from(*start)),
+                end: Box::new(Value::from(*end)),
+                inclusive,
+            },
+        }
+    }
+}
+
+/// Strings and chars print as they are, and quoted inside other values: `print("a")` prints
+/// `a`, and `print(["a"])` prints `["a"]`.
+impl fmt::Display for Value<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::String(value) => write!(f, "{}", value),
+            Value::Char(value) => write!(f, "{}", value),
+            value => write!(f, "{}", Nested(value)),
+        }
+    }
+}
+
+/// A value shown inside another one.
+struct Nested<'v, 'p>(&'v Value<'p>);
+
+impl fmt::Display for Nested<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let list = |f: &mut fmt::Formatter<'_>, values: &[Value]| {
+            let values: Vec<String> = values
+                .iter()
+                .map(|value| Nested(value).to_string())
+                .collect();
+            write!(f, "{}", values.join(", "))
+        };
+        match self.0 {
+            Value::Integer(value) => write!(f, "{}", value),
+            Value::Float(value) => write!(f, "{:?}", value),
+            Value::Single(value) => write!(f, "{:?}", value),
+            Value::Boolean(value) => write!(f, "{}", value),
+            Value::Char(value) => write!(f, "{:?}", value),
+            Value::String(value) => write!(f, "{:?}", value),
+            Value::Unit => write!(f, "()"),
+            Value::Array(elements) => {
+                write!(f, "[")?;
+                list(f, elements)?;
+                write!(f, "]")
+            }
+            Value::Tuple(elements) => {
+                write!(f, "(")?;
+                list(f, elements)?;
+                write!(f, ")")
+            }
+            Value::Range {
+                start,
+                end,
+                inclusive,
+            } => write!(
+                f,
+                "{}{}{}",
+                Nested(start),
+                if *inclusive { "..=" } else { ".." },
+                Nested(end)
+            ),
+            Value::Record { name, fields } => {
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|(field, value)| format!("{}: {}", field, Nested(value)))
+                    .collect();
+                write!(f, "{} {{ {} }}", name, fields.join(", "))
+            }
+            Value::Variant {
+                name,
+                payload: Some(payload),
+            } => match payload.as_ref() {
+                Value::Tuple(elements) => {
+                    write!(f, "{}(", name)?;
+                    list(f, elements)?;
+                    write!(f, ")")
+                }
+                payload => write!(f, "{}({})", name, Nested(payload)),
+            },
+            Value::Variant {
+                name,
+                payload: None,
+            } => write!(f, "{}", name),
+            Value::Function(function) => write!(f, "<fn {}>", function.name),
+            Value::Closure(_) | Value::Compiled(_) => write!(f, "<closure>"),
+            Value::Constructor(name) => write!(f, "<fn {}>", name),
+            Value::Builtin(builtin) => write!(f, "<fn {}>", builtin.name),
+            Value::Host(host) => write!(f, "<fn {}>", host.name),
+            Value::Reference(reference) => match reference.get() {
+                Ok(value) => write!(f, "{}", Nested(&value)),
+                Err(_) => write!(f, "<invalid reference>"),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn point<'p>(x: i128, y: i128) -> Value<'p> {
+        Value::Record {
+            name: "point",
+            fields: vec![("x", Value::Integer(x)), ("y", Value::Integer(y))],
+        }
+    }
+
+    #[test_case(Value::String("a b".to_string()), "a b" ; "string")]
+    #[test_case(Value::Float(3.0), "3.0" ; "float")]
+    #[test_case(Value::Single(0.1), "0.1" ; "single precision float")]
+    #[test_case(Value::Array(vec![Value::String("a".to_string()), Value::Char('b')]), "[\"a\", 'b']" ; "array")]
+    #[test_case(point(1, 2), "point { x: 1, y: 2 }" ; "record")]
+    #[test_case(Value::Variant { name: "some", payload: Some(Box::new(Value::Integer(1))) }, "some(1)" ; "variant")]
+    #[test_case(Value::Variant { name: "rectangle", payload: Some(Box::new(Value::Tuple(vec![Value::Float(2.0), Value::Float(3.0)]))) }, "rectangle(2.0, 3.0)" ; "variant with several values")]
+    #[test_case(Value::Variant { name: "none", payload: None }, "none" ; "unit variant")]
+    fn test_display(value: Value, expected: &str) {
+        assert_eq!(value.to_string(), expected);
+    }
+
+    #[test]
+    fn test_reference_reads_and_writes_a_field() {
+        let slot = Heap::default().allocate(RefCell::new(Value::Array(vec![point(1, 2)])));
+        let reference = Reference {
+            slot: slot.clone(),
+            path: vec![Step::Index(0), Step::Field("y")],
+            is_mutable: true,
+        };
+        reference.set(Value::Integer(5)).unwrap();
+
+        assert_eq!(reference.get(), Ok(Value::Integer(5)));
+        assert_eq!(*slot.borrow(), Value::Array(vec![point(1, 5)]));
+    }
+
+    #[test]
+    fn test_reference_past_the_end() {
+        let reference = Reference {
+            path: vec![Step::Index(3)],
+            ..Reference::to_temporary(&mut Heap::default(), Value::Array(vec![Value::Unit]))
+        };
+
+        assert_eq!(
+            reference.get(),
+            Err(RuntimeError::IndexOutOfBounds {
+                index: 3,
+                length: 1
+            })
+        );
+    }
+}
@@ -0,0 +1,744 @@
+//! Running [bytecode](crate::bytecode) on a stack machine.
+
+use crate::backtrace;
+use crate::builtins::Runtime;
+use crate::bytecode::{Bytecode, Capture, Instruction};
+use crate::heap::{Gc, Heap};
+use crate::host::HostFunction;
+use crate::interpreter::{MAX_CALL_DEPTH, RuntimeError};
+use crate::operations::{self, check_arguments, expect_reference};
+use crate::pattern::bindings;
+use crate::value::{CompiledClosure, Reference, Slot, Step, Value};
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+/// Runs a compiled program. A call pushes a frame instead of recursing, so a program can nest
+/// calls up to [`MAX_CALL_DEPTH`] deep whatever the size of the thread's stack.
+pub struct Vm<'b, 'p> {
+    bytecode: &'b Bytecode<'p>,
+    stack: Vec<Value<'p>>,
+    frames: Vec<Frame<'p>>,
+    /// The storage of each global, by the number the compiler gave it; `None` until it has a
+    /// value.
+    globals: Vec<Option<Slot<'p>>>,
+    runtime: Runtime<'b, 'p>,
+}
+
+/// A call that has not returned yet.
+struct Frame<'p> {
+    function: usize,
+    /// The next instruction to run.
+    ip: usize,
+    /// Where the first argument is on the stack, which local slots count from.
+    base: usize,
+    /// How high the stack was before the callee and its arguments were pushed, which returning
+    /// leaves it at.
+    bottom: usize,
+    /// The closure being run, whose captures the function reads.
+    closure: Option<Gc<CompiledClosure<'p>>>,
+}
+
+impl<'b, 'p> Vm<'b, 'p> {
+    /// A machine for `bytecode`. Output goes to standard output.
+    pub fn new(bytecode: &'b Bytecode<'p>) -> Self {
+        Vm {
+            bytecode,
+            stack: Vec::new(),
+            frames: Vec::new(),
+            globals: Vec::new(),
+            runtime: Runtime::default(),
+        }
+    }
+
+    /// Send what the program prints to `output` instead of standard output.
+    pub fn with_output(mut self, output: impl Write + 'b) -> Self {
+        self.runtime.output = Box::new(output);
+        self
+    }
+
+    /// Give the program the host function `host`, which it must have been checked with.
+    pub fn with_host(mut self, host: Rc<HostFunction>) -> Self {
+        self.runtime.hosts.insert(host.name.clone(), host);
+        self
+    }
+
+    /// Run the program the way [`Interpreter::run`](crate::Interpreter::run) does: evaluate
+    /// its consts, run its top-level statements in order, and then call `main` if it declares
+    /// one. The result is what `main` returns, or unit without it.
+    pub fn run(&mut self) -> Result<Value<'p>, RuntimeError> {
+        let bytecode = self.bytecode;
+        self.stack.clear();
+        self.frames.clear();
+        self.globals = vec![None; bytecode.globals.len()];
+        for (global, value) in &bytecode.initial {
+            self.globals[*global] = Some(self.runtime.heap.allocate(RefCell::new(value.clone())));
+        }
+        for (name, value) in operations::constants(&[], bytecode.program)? {
+            if let Some(global) = bytecode.globals.iter().position(|global| *global == name) {
+                self.globals[global] = Some(self.runtime.heap.allocate(RefCell::new(value)));
+            }
+        }
+        self.frames.push(Frame {
+            function: 0,
+            ip: 0,
+            base: 0,
+            bottom: 0,
+            closure: None,
+        });
+        self.execute()
+    }
+
+    /// The storage the program has allocated.
+    pub fn heap(&self) -> &Heap<'p> {
+        &self.runtime.heap
+    }
+
+    /// The calls that were running when the last [run](Vm::run) stopped with an error,
+    /// innermost first. The top-level code is left out when all it was doing is calling `main`.
+    pub fn backtrace(&self) -> Vec<backtrace::Frame<'p>> {
+        self.frames
+            .iter()
+            .rev()
+            .filter_map(|frame| {
+                let function = &self.bytecode.functions[frame.function];
+                // The instruction that was running, which has already moved the frame past it.
+                let node = function.nodes[frame.ip.checked_sub(1)?]?;
+                Some(backtrace::Frame {
+                    function: function.name,
+                    node,
+                })
+            })
+            .collect()
+    }
+
+    /// Run instructions until the top-level code returns.
+    fn execute(&mut self) -> Result<Value<'p>, RuntimeError> {
+        let bytecode = self.bytecode;
+        loop {
+            let frame = self.frames.last_mut().expect("a function is running");
+            let function = &bytecode.functions[frame.function];
+            let instruction = &function.code[frame.ip];
+            let base = frame.base;
+            frame.ip += 1;
+            match instruction {
+                Instruction::Constant(constant) => {
+                    self.stack.push(function.constants[*constant].clone());
+                }
+                Instruction::Unit => self.stack.push(Value::Unit),
+                Instruction::Pop(count) => {
+                    let height = self.stack.len() - count;
+                    self.stack.truncate(height);
+                }
+                Instruction::PopUnder(count) => {
+                    let top = self.pop();
+                    let height = self.stack.len() - count;
+                    self.stack.truncate(height);
+                    self.stack.push(top);
+                }
+                Instruction::GetLocal(slot) => {
+                    let value = self.stack[base + slot].clone();
+                    self.stack.push(value);
+                }
+                Instruction::SetLocal(slot) => {
+                    let value = self.pop();
+                    self.stack[base + slot] = value;
+                }
+                Instruction::MakeCell => {
+                    let value = self.pop();
+                    let cell = self.cell(value);
+                    self.stack.push(cell);
+                }
+                Instruction::GetCell(slot) => {
+                    let value = expect_reference(self.stack[base + slot].clone())?.get()?;
+                    self.stack.push(value);
+                }
+                Instruction::GetCapture(capture) => {
+                    let value = self.captures()[*capture].get()?;
+                    self.stack.push(value);
+                }
+                Instruction::CaptureCell(capture) => {
+                    let reference = self.captures()[*capture].clone();
+                    self.stack.push(Value::Reference(reference));
+                }
+                Instruction::GetGlobal(global) => {
+                    let value = self.global(*global)?.borrow().clone();
+                    self.stack.push(value);
+                }
+                Instruction::DefineGlobal(global) => {
+                    let value = self.pop();
+                    self.globals[*global] = Some(self.runtime.heap.allocate(RefCell::new(value)));
+                }
+                Instruction::GlobalCell(global) => {
+                    let slot = self.global(*global)?.clone();
+                    self.stack.push(Value::Reference(Reference::to(slot)));
+                }
+                Instruction::GetHost(name) => {
+                    let host =
+                        self.runtime
+                            .host(name)
+                            .ok_or_else(|| RuntimeError::UnknownName {
+                                name: name.to_string(),
+                            })?;
+                    self.stack.push(host);
+                }
+                Instruction::Binary(operator, fit) => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    let value = operations::binary(*operator, left, right, *fit)?;
+                    self.stack.push(value);
+                }
+                Instruction::Unary(operator, fit) => {
+                    let operand = self.pop();
+                    self.stack
+                        .push(operations::unary(*operator, operand, *fit)?);
+                }
+                Instruction::Cast(target) => {
+                    let value = self.pop();
+                    self.stack.push(operations::cast(value, target)?);
+                }
+                Instruction::Dereference => {
+                    let value = self.pop();
+                    self.stack.push(operations::dereference(value)?);
+                }
+                Instruction::AutoDereference => {
+                    let value = self.pop();
+                    self.stack.push(operations::automatic_dereference(value)?);
+                }
+                Instruction::Field(field) => {
+                    let record = self.pop();
+                    self.stack.push(operations::field(record, field)?);
+                }
+                Instruction::Index => {
+                    let index = self.pop();
+                    let collection = self.pop();
+                    self.stack.push(operations::index(collection, index)?);
+                }
+                Instruction::Array(count) => {
+                    let elements = self.pop_many(*count);
+                    self.stack.push(Value::Array(elements));
+                }
+                Instruction::Range { inclusive } => {
+                    let end = self.pop();
+                    let start = self.pop();
+                    self.stack.push(Value::Range {
+                        start: Box::new(start),
+                        end: Box::new(end),
+                        inclusive: *inclusive,
+                    });
+                }
+                Instruction::Record {
+                    name,
+                    declaration,
+                    fields,
+                    base: has_base,
+                } => {
+                    let record_base = has_base.then(|| self.pop());
+                    let values = fields
+                        .iter()
+                        .copied()
+                        .zip(self.pop_many(fields.len()))
+                        .collect();
+                    let record = operations::record(name, *declaration, values, record_base)?;
+                    self.stack.push(record);
+                }
+                Instruction::Variant { name, payload } => {
+                    let payload = payload.then(|| Box::new(self.pop()));
+                    self.stack.push(Value::Variant { name, payload });
+                }
+                Instruction::Closure { function, captures } => {
+                    let captures = captures
+                        .iter()
+                        .map(|capture| match capture {
+                            Capture::Local(slot) => {
+                                expect_reference(self.stack[base + slot].clone())
+                            }
+                            Capture::Capture(capture) => Ok(self.captures()[*capture].clone()),
+                        })
+                        .collect::<Result<_, _>>()?;
+                    self.stack.push(Value::Compiled(self.runtime.heap.allocate(
+                        CompiledClosure {
+                            function: *function,
+                            captures,
+                        },
+                    )));
+                }
+                Instruction::Call(arguments) => self.call(*arguments)?,
+                Instruction::CallFunction {
+                    function,
+                    arguments,
+                } => {
+                    let bottom = self.stack.len() - arguments;
+                    self.enter(*function, None, *arguments, bottom)?;
+                }
+                Instruction::Return => {
+                    let value = self.pop();
+                    let frame = self.frames.pop().expect("a function is running");
+                    self.stack.truncate(frame.bottom);
+                    if self.frames.is_empty() {
+                        return Ok(value);
+                    }
+                    self.stack.push(value);
+                }
+                Instruction::Jump(target) => self.jump(*target),
+                Instruction::JumpIfFalse(target) => match self.pop() {
+                    Value::Boolean(true) => {}
+                    Value::Boolean(false) => self.jump(*target),
+                    value => {
+                        return Err(RuntimeError::TypeMismatch {
+                            expected: "bool",
+                            found: value.kind(),
+                        });
+                    }
+                },
+                Instruction::Match {
+                    pattern,
+                    bindings: names,
+                    otherwise,
+                } => {
+                    let value = self.pop();
+                    match bindings(pattern, &value, &bytecode.variants) {
+                        Some(bound) => {
+                            for (name, in_cell) in names {
+                                let value = bound
+                                    .iter()
+                                    .find(|(bound, _)| bound == name)
+                                    .map_or(Value::Unit, |(_, value)| value.clone());
+                                let value = if *in_cell { self.cell(value) } else { value };
+                                self.stack.push(value);
+                            }
+                        }
+                        None => self.jump(*otherwise),
+                    }
+                }
+                Instruction::Iterate => {
+                    let iterable = self.pop();
+                    self.stack.push(operations::iteration(iterable)?);
+                    self.stack.push(Value::Integer(0));
+                }
+                Instruction::Next { state, exit } => {
+                    let Value::Integer(position) = self.stack[base + state + 1] else {
+                        unreachable!("the position of a loop is an integer");
+                    };
+                    match operations::element(&self.stack[base + state], position as usize) {
+                        Some(element) => {
+                            self.stack[base + state + 1] = Value::Integer(position + 1);
+                            self.stack.push(element);
+                        }
+                        None => self.jump(*exit),
+                    }
+                }
+                Instruction::FieldPlace(field) => {
+                    let mut reference = operations::follow(self.pop_reference()?)?;
+                    reference.path.push(Step::Field(field));
+                    self.stack.push(Value::Reference(reference));
+                }
+                Instruction::IndexPlace => {
+                    let index = self.pop();
+                    let mut reference = operations::follow(self.pop_reference()?)?;
+                    let index = operations::position(index, &reference)?;
+                    reference.path.push(Step::Index(index));
+                    self.stack.push(Value::Reference(reference));
+                }
+                Instruction::ReferencePlace => {
+                    let reference = self.pop_reference()?;
+                    self.stack.push(Value::Reference(reference));
+                }
+                Instruction::Temporary => {
+                    let value = self.pop();
+                    let cell = self.cell(value);
+                    self.stack.push(cell);
+                }
+                Instruction::Borrow { is_mutable } => {
+                    let reference = self.pop_reference()?;
+                    self.stack.push(Value::Reference(Reference {
+                        is_mutable: *is_mutable,
+                        ..reference
+                    }));
+                }
+                Instruction::Assign => {
+                    let reference = self.pop_reference()?;
+                    let value = self.pop();
+                    reference.set(value)?;
+                }
+                Instruction::CompoundAssign(operator, fit) => {
+                    let reference = self.pop_reference()?;
+                    let value = self.pop();
+                    let current = reference.get()?;
+                    reference.set(operations::binary(*operator, current, value, *fit)?)?;
+                }
+                Instruction::Fail(error) => return Err(error.clone()),
+                Instruction::NoMethod { method, arguments } => {
+                    self.pop_many(*arguments);
+                    let receiver = self.pop();
+                    return Err(RuntimeError::UnknownMethod {
+                        method: method.to_string(),
+                        receiver: receiver.kind(),
+                    });
+                }
+            }
+        }
+    }
+
+    /// Call the value under the top `arguments` values with them.
+    fn call(&mut self, arguments: usize) -> Result<(), RuntimeError> {
+        let bottom = self.stack.len() - arguments - 1;
+        match std::mem::replace(&mut self.stack[bottom], Value::Unit) {
+            Value::Function(function) => {
+                let function = self.bytecode.declared[&(function as *const _)];
+                self.enter(function, None, arguments, bottom)
+            }
+            Value::Compiled(closure) => {
+                self.enter(closure.function, Some(closure), arguments, bottom)
+            }
+            Value::Constructor(name) => {
+                let arguments = self.pop_many(arguments);
+                self.stack.pop();
+                self.stack.push(operations::construct(name, arguments));
+                Ok(())
+            }
+            Value::Builtin(builtin) => {
+                let arguments = self.pop_many(arguments);
+                self.stack.pop();
+                let value = builtin.call(&mut self.runtime, &arguments)?;
+                self.stack.push(value);
+                Ok(())
+            }
+            Value::Host(host) => {
+                let arguments = self.pop_many(arguments);
+                self.stack.pop();
+                self.stack.push(host.call(&arguments)?);
+                Ok(())
+            }
+            callee => Err(RuntimeError::TypeMismatch {
+                expected: "function",
+                found: callee.kind(),
+            }),
+        }
+    }
+
+    /// Start running `function` with the `arguments` values on top of the stack, leaving the
+    /// stack at `bottom` when it returns.
+    fn enter(
+        &mut self,
+        function: usize,
+        closure: Option<Gc<CompiledClosure<'p>>>,
+        arguments: usize,
+        bottom: usize,
+    ) -> Result<(), RuntimeError> {
+        let declaration = &self.bytecode.functions[function];
+        let receiver = usize::from(declaration.receiver);
+        check_arguments(declaration.name, declaration.params, arguments - receiver)?;
+        // The top-level code has a frame but is not a call.
+        if self.frames.len() > MAX_CALL_DEPTH {
+            return Err(RuntimeError::CallDepth {
+                limit: MAX_CALL_DEPTH,
+            });
+        }
+        self.frames.push(Frame {
+            function,
+            ip: 0,
+            base: self.stack.len() - arguments,
+            bottom,
+            closure,
+        });
+        Ok(())
+    }
+
+    fn jump(&mut self, target: usize) {
+        self.frames.last_mut().expect("a function is running").ip = target;
+    }
+
+    /// The cells of the variables the running closure captured.
+    fn captures(&self) -> &[Reference<'p>] {
+        let frame = self.frames.last().expect("a function is running");
+        frame
+            .closure
+            .as_ref()
+            .map_or(&[], |closure| &closure.captures)
+    }
+
+    fn global(&self, global: usize) -> Result<&Slot<'p>, RuntimeError> {
+        self.globals[global]
+            .as_ref()
+            .ok_or_else(|| RuntimeError::UnknownName {
+                name: self.bytecode.globals[global].to_string(),
+            })
+    }
+
+    /// A new cell holding `value`.
+    fn cell(&mut self, value: Value<'p>) -> Value<'p> {
+        Value::Reference(Reference::to_temporary(&mut self.runtime.heap, value))
+    }
+
+    fn pop(&mut self) -> Value<'p> {
+        self.stack
+            .pop()
+            .expect("the compiler keeps the stack balanced")
+    }
+
+    fn pop_many(&mut self, count: usize) -> Vec<Value<'p>> {
+        let height = self.stack.len() - count;
+        self.stack.split_off(height)
+    }
+
+    fn pop_reference(&mut self) -> Result<Reference<'p>, RuntimeError> {
+        let value = self.pop();
+        expect_reference(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile;
+    use crate::interpreter::Interpreter;
+    use lexer::source::SourceMap;
+    use parser::ast::Program;
+    use parser::node_id::NodeIndex;
+    use parser::operators::ArithmeticError;
+    use semantics::{Severity, check, resolve, with_prelude};
+    use test_case::test_case;
+
+    /// Check `source`, then run it on the interpreter and compiled on the machine, returning
+    /// what each printed and returned.
+    fn run_both(source: &str) -> [Result<(String, String), RuntimeError>; 2] {
+        run_program(parser::parse(source).expect("Parse error"))
+    }
+
+    fn run_program(program: Program) -> [Result<(String, String), RuntimeError>; 2] {
+        let index = NodeIndex::new(&program);
+        let resolution = resolve(&program, &index);
+        assert_eq!(resolution.errors, []);
+        let checked = check(&program, &index, &resolution);
+        let errors: Vec<_> = checked
+            .errors
+            .iter()
+            .filter(|error| error.severity() == Severity::Error)
+            .collect();
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        let mut interpreted = Vec::new();
+        let interpreter = Interpreter::new(&program, &index, &checked.types)
+            .with_output(&mut interpreted)
+            .run()
+            .map(|value| value.to_string());
+        let bytecode = compile(&program, &index, &checked.types);
+        let mut compiled = Vec::new();
+        let vm = Vm::new(&bytecode)
+            .with_output(&mut compiled)
+            .run()
+            .map(|value| value.to_string());
+        [
+            interpreter.map(|value| (String::from_utf8(interpreted).unwrap(), value)),
+            vm.map(|value| (String::from_utf8(compiled).unwrap(), value)),
+        ]
+    }
+
+    /// Run `source` both ways, check that they agree, and return what it printed.
+    fn output(source: &str) -> String {
+        let [interpreted, compiled] = run_both(source);
+        assert_eq!(compiled, interpreted);
+        compiled.expect("Runtime error").0
+    }
+
+    #[test_case("fn main() { println(1 + 2 * 3, -7 / 2, -7 % 2, 1.5 * 2.0); }", "7 -3 -1 3.0\n" ; "arithmetic")]
+    #[test_case("fn main() { println(false and 1 / 0 == 0, true or 1 / 0 == 0, true and 1 < 2); }", "false true true\n" ; "short circuit")]
+    #[test_case("fn fib(i32 n) -> i32 { if n < 2 { n } else { fib(n - 1) + fib(n - 2) } } fn main() { println(fib(15)); }", "610\n" ; "recursion")]
+    #[test_case("fn main() { x = 1; { x = 2; println(x); }; println(x); }", "2\n1\n" ; "block scope")]
+    #[test_case("fn main() { x = 1; get = || x; x = 2; println(get(), x); }", "1 2\n" ; "closure sees the variable it was written next to")]
+    #[test_case("fn adder(i32 n) -> fn(i32) -> i32 { |i32 x| x + n } fn main() { add2 = adder(2); println(add2(40)); }", "42\n" ; "closure outlives its function")]
+    #[test_case("fn main() { a = 1; outer = |i32 b| { inner = |i32 c| a + b + c; inner(3) }; println(outer(2)); }", "6\n" ; "closure captures through a closure")]
+    #[test_case("fn apply(fn(i32) -> i32 f, i32 x) -> i32 { f(x) } fn double(i32 x) -> i32 { x * 2 } fn main() { println(apply(double, 4)); }", "8\n" ; "function as a value")]
+    #[test_case("fn main() { @total = 0; for i in 1..=4 { (total) = total + i; }; total += 10; println(total); }", "20\n" ; "assignment")]
+    #[test_case("fn main() { @i = 0; n = loop { i += 1; if i == 5 { break i * 10; }; }; println(n); }", "50\n" ; "loop with break value")]
+    #[test_case("fn main() { for c in \"hey\" { println(c); }; for x in [1, 2] { println(x); }; }", "h\ne\ny\n1\n2\n" ; "for over strings and arrays")]
+    #[test_case("fn main() { for x in 0..10 { y = x * 2; if x == 2 { break; }; println(y); }; println(\"done\"); }", "0\n2\ndone\n" ; "break out of for")]
+    #[test_case("fn first() -> i32 { for x in [7, 8] { return x; }; 0 } fn main() { println(first()); }", "7\n" ; "return from a loop")]
+    #[test_case("fn main() { @xs = [1, 2, 3]; xs[1] = 20; xs[2] *= 2; println(xs, xs[1]); }", "[1, 20, 6] 20\n" ; "element assignment")]
+    #[test_case("fn main() { println(300 as u8, -2.7 as i32, 3 as f64 / 2.0, 1.1 as f32); }", "44 -2 1.5 1.1\n" ; "casts")]
+    #[test_case("fn main() { u64 a = 9223372036854775807; u64 b = 18446744073709551615; println(-1 as u64, a + 1, b, b as i64); }", "18446744073709551615 9223372036854775808 18446744073709551615 -1\n" ; "full u64 range")]
+    #[test_case("fn main() { @n = 1; while n < 100 { n *= 3; } println(n); }", "243\n" ; "while loop")]
+    #[test_case("fn main() { f32 y = 0.1; println(y, y * 3.0, when y { 0.1: 1; _: 0; }); }", "0.1 0.3 1\n" ; "single precision floats")]
+    #[test_case("const u32 size = base * 2; const u32 base = 4; fn main() { println(size); }", "8\n" ; "consts")]
+    #[test_case("fn main() { x = 5; when x { n if n > 9: println(n); }; println(x); }", "5\n" ; "when falling through")]
+    #[test_case("greeting = \"hi\"; fn main() { println(greeting); }", "hi\n" ; "top-level variables run first")]
+    #[test_case("fn main() { print(\"a\", 1); assert(len(\"héllo\") == 5); println(len([1, 2]), to_string(0..3)); }", "a 12 0..3\n" ; "builtins")]
+    fn test_output(source: &str, expected: &str) {
+        assert_eq!(output(source), expected);
+    }
+
+    #[test_case("
+        union shape = circle(f64) | square(f64) | dot;
+        fn area(shape s) -> f64 {
+            when s {
+                circle(r): 3.0 * r * r;
+                square(side): side * side;
+                dot: 0.0;
+            }
+        }
+        fn main() { println(area(circle(1.0)), area(square(2.0)), area(dot)); }",
+        "3.0 4.0 0.0\n" ;
+        "union values"
+    )]
+    #[test_case("
+        fn describe(i32 n) -> string {
+            when n {
+                0: \"zero\";
+                1 | 2 | 3: \"few\";
+                m if m < 0: \"negative\";
+                4..=9: \"several\";
+                _: \"many\";
+            }
+        }
+        fn main() { println(describe(0), describe(2), describe(-5), describe(9), describe(10)); }",
+        "zero few negative several many\n" ;
+        "patterns"
+    )]
+    #[test_case("
+        fn increment(i32& @count) { *count += 1; }
+        record counter { hits: i32; }
+        fn main() {
+            @n = 1;
+            increment(&@n);
+            @c = counter { hits: 0 };
+            hits = &@c;
+            hits.hits = 5;
+            r = &n;
+            println(n, *r, c.hits);
+        }",
+        "2 2 5\n" ;
+        "references"
+    )]
+    #[test_case("
+        record point { x: i32; y: i32; }
+        patch point {
+            fn sum() -> i32 { self.x + self.y }
+            fn scaled(i32 factor) -> point { point { x: self.x * factor, y: self.y * factor } }
+        }
+        fn main() {
+            @p = point { y: 2, x: 1 };
+            p.x = 10;
+            q = point { y: 5, ..p };
+            println(p, q, q.scaled(3).sum());
+        }",
+        "point { x: 10, y: 2 } point { x: 10, y: 5 } 45\n" ;
+        "records and patch methods"
+    )]
+    fn test_program(source: &str, expected: &str) {
+        assert_eq!(output(source), expected);
+    }
+
+    #[test_case("fn main() { x = 0; println(1 / x); }", RuntimeError::Arithmetic(ArithmeticError::DivisionByZero) ; "division by zero")]
+    #[test_case("fn main() { u8 x = 255; println(x + 1); }", RuntimeError::Arithmetic(ArithmeticError::Overflow) ; "narrow overflow")]
+    #[test_case("fn main() { u64 x = 18446744073709551615; println(x + 1); }", RuntimeError::Arithmetic(ArithmeticError::Overflow) ; "u64 overflow")]
+    #[test_case("fn main() { xs = [1, 2]; println(xs[2]); }", RuntimeError::IndexOutOfBounds { index: 2, length: 2 } ; "index out of bounds")]
+    #[test_case("fn main() { assert(1 > 2); }", RuntimeError::AssertionFailed ; "failed assertion")]
+    #[test_case("fn shout<T>(T value) { value.shout(); } fn main() { shout(\"text\"); }", RuntimeError::UnknownMethod { method: "shout".to_string(), receiver: "string" } ; "unknown method")]
+    #[test_case("fn main() { x = 5; y = when x { n if n > 9: n; }; println(y); }", RuntimeError::NoMatchingBranch ; "when matching no branch")]
+    fn test_runtime_error(source: &str, expected: RuntimeError) {
+        let [interpreted, compiled] = run_both(source);
+        assert_eq!(interpreted, Err(expected.clone()));
+        assert_eq!(compiled, Err(expected));
+    }
+
+    #[test_case(
+        "fn get(arrayList<i32> xs, i32 i) -> i32 {\n    xs[i]\n}\nfn main() {\n    xs = [1];\n    println(get(xs, 1));\n}",
+        "error at 2:5: Index 1 is out of bounds for length 1\n  note: in get at 2:5\n  note: in main at 6:13" ;
+        "index out of bounds"
+    )]
+    #[test_case(
+        "fn down(i32 n) -> i32 {\n    if n == 0 { 1 / n } else { down(n - 1) }\n}\nfn main() { down(3); }",
+        "error at 2:17: Division by zero\n  note: in down at 2:17\n  note: in down at 2:32\n  note: and 2 more calls of down from there\n  note: in main at 4:13" ;
+        "recursion"
+    )]
+    #[test_case(
+        "fn main() {\n    f = |i32 x| 10 % x;\n    f(0);\n}",
+        "error at 2:17: Division by zero\n  note: in <closure> at 2:17\n  note: in main at 3:5" ;
+        "closure"
+    )]
+    #[test_case(
+        "total = [1][2];\nfn main() {}",
+        "error at 1:9: Index 2 is out of bounds for length 1\n  note: in <top level> at 1:9" ;
+        "top level"
+    )]
+    fn test_backtrace(source: &str, expected: &str) {
+        let mut sources = SourceMap::new();
+        let file = sources.add_file("main.cv", source);
+        let (program, spans, errors) = parser::parse_file(sources.get(file).unwrap());
+        assert_eq!(errors, []);
+        let index = NodeIndex::new(&program);
+        let resolution = resolve(&program, &index);
+        let checked = check(&program, &index, &resolution);
+
+        let mut interpreter = Interpreter::new(&program, &index, &checked.types);
+        let interpreted = interpreter.run().unwrap_err();
+        let bytecode = compile(&program, &index, &checked.types);
+        let mut vm = Vm::new(&bytecode);
+        let compiled = vm.run().unwrap_err();
+
+        assert_eq!(vm.backtrace(), interpreter.backtrace());
+        for (error, backtrace) in [
+            (interpreted, interpreter.backtrace()),
+            (compiled, &vm.backtrace()[..]),
+        ] {
+            let diagnostic = backtrace::diagnostic(&error, backtrace).unwrap();
+            assert_eq!(diagnostic.render(&spans, &sources), expected);
+        }
+    }
+
+    #[test]
+    fn test_prelude() {
+        let program = parser::parse(
+            "fn main() {
+                println(min(3, 4), max(2.5, 1.0), abs(-3), abs(-2.5), clamp(15, 0, 10));
+                xs = [1, 2, 3];
+                println(fold(xs, 0, |i32 total, i32 x| total + x), any(xs, |i32 x| x > 2), all(xs, |i32 x| x > 2));
+                println(find(xs, |i32 x| x > 1), find(xs, |i32 x| x > 5).unwrapOr(0));
+                result<i32, string> failed = err(\"bad\");
+                println(failed.isOk(), failed.unwrapOr(7), some(1).isSome());
+            }",
+        )
+        .expect("Parse error");
+        let [interpreted, compiled] = run_program(with_prelude(program));
+
+        assert_eq!(interpreted, compiled);
+        assert_eq!(
+            compiled.expect("Runtime error").0,
+            "3 2.5 3 2.5 10\n6 true false\nsome(2) 0\nfalse 7 true\n"
+        );
+    }
+
+    #[test]
+    fn test_cycles_are_collected() {
+        let program =
+            parser::parse("fn main() { for i in 0..5000 { @f = || 0; (f) = || f() + 1; }; }")
+                .expect("Parse error");
+        let index = NodeIndex::new(&program);
+        let resolution = resolve(&program, &index);
+        let checked = check(&program, &index, &resolution);
+        let bytecode = compile(&program, &index, &checked.types);
+        let mut vm = Vm::new(&bytecode);
+        vm.run().unwrap();
+
+        // Each iteration leaves a closure in a cycle with the cell it captured.
+        assert!(vm.heap().len() < 5000);
+    }
+
+    #[test]
+    fn test_runaway_recursion_is_stopped_without_a_large_stack() {
+        let program = parser::parse("fn down(i32 n) -> i32 { down(n + 1) } fn main() { down(0); }")
+            .expect("Parse error");
+        let index = NodeIndex::new(&program);
+        let resolution = resolve(&program, &index);
+        let checked = check(&program, &index, &resolution);
+        let bytecode = compile(&program, &index, &checked.types);
+
+        assert_eq!(
+            Vm::new(&bytecode).run(),
+            Err(RuntimeError::CallDepth {
+                limit: MAX_CALL_DEPTH
+            })
+        );
+    }
+}
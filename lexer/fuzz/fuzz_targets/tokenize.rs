@@ -0,0 +1,13 @@
+#![no_main]
+
+use lexer::Lexer;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(code) = std::str::from_utf8(data) {
+        let (tokens, _) = Lexer::new(code).tokenize_with_errors();
+        for token in &tokens {
+            let _ = token.text(code);
+        }
+    }
+});
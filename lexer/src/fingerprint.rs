@@ -0,0 +1,177 @@
+//! Content fingerprints of CV source, used as cache keys.
+//!
+//! A fingerprint hashes the token stream rather than the raw text, so edits to whitespace,
+//! newlines, or comments do not change it. The hash is computed by hand (64-bit FNV-1a) instead
+//! of with `std::hash`, whose output may change between Rust releases, so fingerprints stay
+//! valid across toolchain upgrades.
+
+use crate::tokens::{Token, TokenKind};
+use crate::version::LanguageVersion;
+use std::fmt;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Separates hashed items, so that e.g. the tokens `ab` and `a b` hash differently. It cannot
+/// occur in UTF-8 text.
+const SEPARATOR: u8 = 0xff;
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct Fingerprint(u64);
+
+impl Fingerprint {
+    /// Fingerprint a module's tokens. Only the token kinds count; spans and newlines are ignored.
+    pub fn of_tokens(tokens: &[Token]) -> Self {
+        let mut hasher = Fnv1a::new();
+        for token in tokens {
+            if token.kind != TokenKind::Newline {
+                hasher.write(token.kind.to_string().as_bytes());
+                hasher.write(&[SEPARATOR]);
+            }
+        }
+        Fingerprint(hasher.finish())
+    }
+
+    /// Fold the fingerprints of a module's dependencies into its own, so the result changes
+    /// whenever any of them does. The order of `dependencies` is significant.
+    pub fn with_dependencies(self, dependencies: &[Fingerprint]) -> Self {
+        let mut hasher = Fnv1a::new();
+        hasher.write(&self.0.to_le_bytes());
+        for dependency in dependencies {
+            hasher.write(&[SEPARATOR]);
+            hasher.write(&dependency.0.to_le_bytes());
+        }
+        Fingerprint(hasher.finish())
+    }
+
+    /// Fold the language version a module declares with its `#:version` pragma into its
+    /// fingerprint, so the same tokens read under another version get another key. A module
+    /// that declares none keeps its fingerprint.
+    pub fn with_version(self, version: Option<LanguageVersion>) -> Self {
+        let Some(version) = version else {
+            return self;
+        };
+        let mut hasher = Fnv1a::new();
+        hasher.write(&self.0.to_le_bytes());
+        hasher.write(&[SEPARATOR]);
+        hasher.write(version.to_string().as_bytes());
+        Fingerprint(hasher.finish())
+    }
+
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    /// Prints the fingerprint as 16 lowercase hex digits.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Self {
+        Fnv1a(FNV_OFFSET_BASIS)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Lexer;
+    use test_case::test_case;
+
+    fn fingerprint(source: &str) -> Fingerprint {
+        Fingerprint::of_tokens(&Lexer::new(source).tokenize().expect("Lexer error"))
+    }
+
+    #[test]
+    fn test_fnv1a_reference_values() {
+        let hash = |text: &str| {
+            let mut hasher = Fnv1a::new();
+            hasher.write(text.as_bytes());
+            hasher.finish()
+        };
+
+        assert_eq!(hash(""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(hash("a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(hash("foobar"), 0x8594_4171_f739_67e8);
+    }
+
+    #[test_case("x = 1 + 2;", "x=1+2;" ; "whitespace")]
+    #[test_case("x = 1;\ny = 2;", "x = 1; y = 2;" ; "newlines")]
+    #[test_case("x = 1; // one", "/* set x */ x = 1;" ; "comments")]
+    #[test_case("x = 1.50;", "x = 1.5;" ; "number spelling")]
+    fn test_insignificant_changes(a: &str, b: &str) {
+        assert_eq!(fingerprint(a), fingerprint(b));
+    }
+
+    #[test_case("x = 1;", "x = 2;" ; "literal")]
+    #[test_case("x = 1;", "y = 1;" ; "identifier")]
+    #[test_case("ab = 1;", "a b = 1;" ; "token boundary")]
+    #[test_case("x = 1;", "x = 1.0;" ; "number kind")]
+    #[test_case("x = a;", "x = \"a\";" ; "identifier and string")]
+    fn test_significant_changes(a: &str, b: &str) {
+        assert_ne!(fingerprint(a), fingerprint(b));
+    }
+
+    #[test]
+    fn test_dependencies() {
+        let module = fingerprint("fn main() {}");
+        let first = fingerprint("x = 1;");
+        let second = fingerprint("y = 2;");
+
+        assert_ne!(
+            module.with_dependencies(&[]),
+            module.with_dependencies(&[first])
+        );
+        assert_ne!(
+            module.with_dependencies(&[first]),
+            module.with_dependencies(&[second])
+        );
+        assert_ne!(
+            module.with_dependencies(&[first, second]),
+            module.with_dependencies(&[second, first])
+        );
+    }
+
+    #[test]
+    fn test_version() {
+        let module = fingerprint("x = 1;");
+
+        assert_eq!(module.with_version(None), module);
+        assert_ne!(
+            module.with_version(Some(LanguageVersion::new(0, 1))),
+            module
+        );
+        assert_ne!(
+            module.with_version(Some(LanguageVersion::new(0, 1))),
+            module.with_version(Some(LanguageVersion::new(1, 0)))
+        );
+    }
+
+    /// Fingerprints are cache keys, so any change to how they are computed must be deliberate.
+    #[test]
+    fn test_fingerprint_is_stable() {
+        assert_eq!(fingerprint("x = 1;").value(), 0x43e0_3c91_2de3_2fd6);
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Fingerprint(0xab).to_string(), "00000000000000ab");
+    }
+}
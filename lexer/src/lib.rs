@@ -4,41 +4,174 @@
 
 #![allow(dead_code)]
 
-use crate::tokens::{NumberLiteral, Token, TokenKind};
+use crate::source::{FileId, SourceFile};
+use crate::tokens::{NumberLiteral, Span, Token, TokenKind};
+use crate::version::LanguageVersion;
 use thiserror::Error;
 
+pub mod fingerprint;
+pub mod source;
 pub mod tokens;
+pub mod version;
 
 #[derive(Debug)]
-struct Lexer<'a> {
+pub struct Lexer<'a> {
     content: &'a str,
+    /// The content split into characters; positions and spans index into this, not into bytes.
+    chars: Vec<char>,
+    file: FileId,
     position: usize,
     error_state: Option<LexerError>,
+    version: Option<LanguageVersion>,
+    /// Whether a `-` directly followed by a digit is lexed as part of a negative number.
+    fold_negative_literals: bool,
+    /// Whether the last token produced could end an operand, making a following `-` binary.
+    after_operand: bool,
 }
 
-type Result<T> = std::result::Result<T, LexerError>;
+/// A snapshot of the lexer's progress, produced by [`Lexer::checkpoint`] and consumed by
+/// [`Lexer::restore`] to rewind the lexer without re-lexing from the start.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct LexerState {
+    position: usize,
+    error_state: Option<LexerError>,
+    after_operand: bool,
+}
+
+/// A single text edit, expressed in character offsets into the source the old tokens were
+/// produced from: `deleted` characters starting at `start` were replaced by `inserted` new ones.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct TextEdit {
+    pub start: usize,
+    pub deleted: usize,
+    pub inserted: usize,
+}
+
+pub type Result<T> = std::result::Result<T, LexerError>;
 
 #[derive(Debug, Error, PartialEq, Clone, Copy)]
-enum LexerError {
-    #[error("Unexpected character '{0}' at position {1}")]
-    UnexpectedCharacter(char, usize),
-    #[error("Invalid number format starting at position {0}")]
-    InvalidNumberFormat(usize),
+pub enum LexerError {
+    #[error("Unexpected character '{}' at position {}", .0, .1.start)]
+    UnexpectedCharacter(char, Span),
+    #[error("Invalid number format starting at position {}", .0.start)]
+    InvalidNumberFormat(Span),
+    #[error("Invalid byte literal starting at position {}", .0.start)]
+    InvalidByteLiteral(Span),
+    #[error("Invalid character literal starting at position {}", .0.start)]
+    InvalidCharLiteral(Span),
+    #[error("Invalid escape sequence at position {}", .0.start)]
+    InvalidEscape(Span),
+    #[error("Unterminated block comment starting at position {}", .0.start)]
+    UnterminatedComment(Span),
+    #[error("Unterminated string literal starting at position {}", .0.start)]
+    UnterminatedString(Span),
+    #[error("Invalid pragma at position {}", .0.start)]
+    InvalidPragma(Span),
+    #[error(
+        "CV version {0} is not supported by this toolchain (supported: {min} to {current})",
+        min = LanguageVersion::MIN_SUPPORTED,
+        current = LanguageVersion::CURRENT
+    )]
+    UnsupportedVersion(LanguageVersion),
+}
+
+impl LexerError {
+    /// Where the error is. Only an unsupported version, which is about the whole file, has none.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            LexerError::UnexpectedCharacter(_, span)
+            | LexerError::InvalidNumberFormat(span)
+            | LexerError::InvalidByteLiteral(span)
+            | LexerError::InvalidCharLiteral(span)
+            | LexerError::InvalidEscape(span)
+            | LexerError::UnterminatedComment(span)
+            | LexerError::UnterminatedString(span)
+            | LexerError::InvalidPragma(span) => Some(*span),
+            LexerError::UnsupportedVersion(_) => None,
+        }
+    }
 }
 
 impl<'a> Lexer<'a> {
     /// Create a new lexer instance with the given input content.
-    fn new(content: &'a str) -> Self {
+    pub fn new(content: &'a str) -> Self {
+        Self::with_file_id(content, FileId::default())
+    }
+
+    /// Create a lexer for a file registered with a [`source::SourceMap`], so that every token's
+    /// span refers back to that file.
+    pub fn for_file(file: &'a SourceFile) -> Self {
+        Self::with_file_id(&file.content, file.id)
+    }
+
+    fn with_file_id(content: &'a str, file: FileId) -> Self {
         Self {
             content,
+            chars: content.chars().collect(),
+            file,
             position: 0,
             error_state: None,
+            version: None,
+            fold_negative_literals: false,
+            after_operand: false,
+        }
+    }
+
+    /// Lex a `-` that directly precedes a number as part of that number, unless it follows an
+    /// operand (as in `a -1`), so that a negative literal such as `-9223372036854775808` is a
+    /// single token.
+    pub fn fold_negative_literals(mut self) -> Self {
+        self.fold_negative_literals = true;
+        self
+    }
+
+    /// Capture the current lexer state so it can later be restored with [`Lexer::restore`].
+    pub fn checkpoint(&self) -> LexerState {
+        LexerState {
+            position: self.position,
+            error_state: self.error_state,
+            after_operand: self.after_operand,
         }
     }
 
+    /// Rewind (or fast-forward) the lexer to a previously captured state.
+    pub fn restore(&mut self, state: LexerState) {
+        self.position = state.position;
+        self.error_state = state.error_state;
+        self.after_operand = state.after_operand;
+    }
+
+    /// Read the `#:version` pragma if the content starts with one, leaving the lexer positioned
+    /// at the end of the pragma line. Returns `None` if the file declares no version.
+    pub fn version_pragma(&mut self) -> Result<Option<LanguageVersion>> {
+        if self.position != 0 || !self.content.starts_with("#:") {
+            return Ok(self.version);
+        }
+
+        let line = self.content.lines().next().unwrap_or_default();
+        let version = line
+            .strip_prefix("#:version")
+            .filter(|rest| rest.starts_with(char::is_whitespace))
+            .and_then(|rest| LanguageVersion::parse(rest.trim()))
+            .ok_or_else(|| LexerError::InvalidPragma(self.span(0, line.chars().count())))?;
+
+        if !version.is_supported() {
+            return Err(LexerError::UnsupportedVersion(version));
+        }
+
+        self.advance(line.trim_end_matches('\r').chars().count());
+        self.version = Some(version);
+        Ok(self.version)
+    }
+
+    /// The language version declared by the source, once the pragma has been read.
+    pub fn version(&self) -> Option<LanguageVersion> {
+        self.version
+    }
+
     /// Peek at the current character without advancing the position.
     fn peek_char(&self, num_ahead: usize) -> Option<char> {
-        self.content.chars().nth(self.position + num_ahead)
+        self.chars.get(self.position + num_ahead).copied()
     }
 
     /// Advance the current position by the specified number of steps.
@@ -56,10 +189,19 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    /// A span of `length` characters starting at `start` in the current file.
+    fn span(&self, start: usize, length: usize) -> Span {
+        Span {
+            file: self.file,
+            start,
+            end: start + length - 1,
+        }
+    }
+
     fn create_simple_token(&mut self, kind: TokenKind, length: usize) -> Token {
         let start = self.position;
         self.advance(length);
-        Token::new(kind, start, length)
+        Token::new(kind, self.file, start, length)
     }
 
     fn create_optional_eq_token(
@@ -70,10 +212,10 @@ impl<'a> Lexer<'a> {
         let start = self.position;
         if self.peek_char(1) == Some('=') {
             self.advance(2);
-            Token::new(with_eq_kind, start, 2)
+            Token::new(with_eq_kind, self.file, start, 2)
         } else {
             self.advance(1);
-            Token::new(without_eq_kid, start, 1)
+            Token::new(without_eq_kid, self.file, start, 1)
         }
     }
 
@@ -87,17 +229,24 @@ impl<'a> Lexer<'a> {
             }
         }
 
-        String::from(&self.content[start..self.position])
+        self.chars[start..self.position].iter().collect()
     }
 
-    fn create_number_token(&mut self) -> Result<Token> {
+    /// Lex a number starting at the current position. With `negative`, the current character is
+    /// a `-` that belongs to the literal.
+    fn create_number_token(&mut self, negative: bool) -> Result<Token> {
         let start = self.position;
         let mut has_decimal_point = false;
+        if negative {
+            self.advance(1);
+        }
 
         while let Some(c) = self.peek_char(0) {
-            if c.is_digit(10) {
+            if c.is_ascii_digit() {
                 self.advance(1);
-            } else if c == '.' {
+            } else if c == '.' && self.peek_char(1).is_some_and(|c| c.is_ascii_digit()) {
+                // Only a digit makes the `.` a decimal point: `1..5` is a range and `5.double()`
+                // a method call.
                 has_decimal_point = true;
                 self.advance(1);
             } else {
@@ -106,40 +255,208 @@ impl<'a> Lexer<'a> {
         }
 
         let length = self.position - start;
-        let value = &self.content[start..self.position];
+        let value: String = self.chars[start..self.position].iter().collect();
 
         if has_decimal_point {
             match value.parse::<f64>() {
                 Ok(num) => Ok(Token::new(
                     TokenKind::Number(NumberLiteral::Float(num)),
+                    self.file,
                     start,
                     length,
                 )),
-                Err(_) => Err(LexerError::InvalidNumberFormat(start)),
+                Err(_) => Err(LexerError::InvalidNumberFormat(self.span(start, length))),
             }
         } else {
-            match value.parse::<i64>() {
-                Ok(num) => Ok(Token::new(
+            // No integer type holds a value below `i64::MIN` or above `u64::MAX`.
+            let representable = i128::from(i64::MIN)..=i128::from(u64::MAX);
+            match value.parse::<i128>() {
+                Ok(num) if representable.contains(&num) => Ok(Token::new(
                     TokenKind::Number(NumberLiteral::Integer(num)),
+                    self.file,
                     start,
                     length,
                 )),
-                Err(_) => Err(LexerError::InvalidNumberFormat(start)),
+                _ => Err(LexerError::InvalidNumberFormat(self.span(start, length))),
+            }
+        }
+    }
+
+    /// Lex a `b"..."` byte string or `b'x'` byte character. Only ASCII characters and the escapes
+    /// `\n`, `\r`, `\t`, `\\`, `\0`, `\'`, `\"` and `\xHH` are allowed. On error the whole
+    /// literal is still consumed.
+    fn create_byte_literal_token(&mut self) -> Result<Token> {
+        let start = self.position;
+        let quote = self.peek_char(1).unwrap_or('"');
+        self.advance(2);
+
+        let mut bytes = Vec::new();
+        let mut is_valid = true;
+        loop {
+            match self.peek_char(0) {
+                None => {
+                    return Err(LexerError::UnterminatedString(self.span(start, 2)));
+                }
+                Some(c) if c == quote => {
+                    self.advance(1);
+                    break;
+                }
+                Some('\\') => match self.read_byte_escape() {
+                    Some(byte) => bytes.push(byte),
+                    None => is_valid = false,
+                },
+                Some(c) => {
+                    if c.is_ascii() {
+                        bytes.push(c as u8);
+                    } else {
+                        is_valid = false;
+                    }
+                    self.advance(1);
+                }
+            }
+        }
+
+        let length = self.position - start;
+        let kind = match (quote, bytes.as_slice()) {
+            ('"', _) if is_valid => TokenKind::ByteString(bytes),
+            ('\'', [byte]) if is_valid => TokenKind::ByteChar(*byte),
+            _ => return Err(LexerError::InvalidByteLiteral(self.span(start, length))),
+        };
+        Ok(Token::new(kind, self.file, start, length))
+    }
+
+    /// Lex a string (`"..."`) or character (`'c'`) literal. Like byte literals, the whole literal
+    /// is consumed even when it is invalid so that lexing can resume after it.
+    fn create_text_literal_token(&mut self) -> Result<Token> {
+        let start = self.position;
+        let quote = self.peek_char(0).unwrap_or('"');
+        self.advance(1);
+
+        let mut text = String::new();
+        let mut invalid_escape = None;
+        loop {
+            match self.peek_char(0) {
+                None => {
+                    return Err(LexerError::UnterminatedString(self.span(start, 1)));
+                }
+                Some(c) if c == quote => {
+                    self.advance(1);
+                    break;
+                }
+                Some('\\') => {
+                    let escape_start = self.position;
+                    match self.read_char_escape() {
+                        Some(c) => text.push(c),
+                        None => {
+                            invalid_escape.get_or_insert(
+                                self.span(escape_start, self.position - escape_start),
+                            );
+                        }
+                    }
+                }
+                Some(c) => {
+                    text.push(c);
+                    self.advance(1);
+                }
+            }
+        }
+
+        if let Some(span) = invalid_escape {
+            return Err(LexerError::InvalidEscape(span));
+        }
+
+        let length = self.position - start;
+        let mut chars = text.chars();
+        let kind = match (quote, chars.next(), chars.next()) {
+            ('"', ..) => TokenKind::String(text),
+            (_, Some(c), None) => TokenKind::Char(c),
+            _ => return Err(LexerError::InvalidCharLiteral(self.span(start, length))),
+        };
+        Ok(Token::new(kind, self.file, start, length))
+    }
+
+    /// Read the escape sequence at the current `\`. Accepts the ASCII byte escapes plus
+    /// `\u{...}` for any Unicode scalar value.
+    fn read_char_escape(&mut self) -> Option<char> {
+        if self.peek_char(1) != Some('u') {
+            return self.read_byte_escape().filter(u8::is_ascii).map(char::from);
+        }
+
+        self.advance(2);
+        if self.peek_char(0) != Some('{') {
+            return None;
+        }
+        self.advance(1);
+        let mut value: u32 = 0;
+        let mut digits = 0;
+        while let Some(c) = self.peek_char(0) {
+            self.advance(1);
+            match c {
+                '}' if digits > 0 => return char::from_u32(value),
+                c if digits < 6 => {
+                    value = value * 16 + c.to_digit(16)?;
+                    digits += 1;
+                }
+                _ => return None,
+            }
+        }
+        None
+    }
+
+    /// Read the escape sequence starting at the current backslash, returning `None` if it is not
+    /// a valid byte escape.
+    fn read_byte_escape(&mut self) -> Option<u8> {
+        self.advance(1);
+        let escaped = self.peek_char(0)?;
+        self.advance(1);
+        match escaped {
+            'n' => Some(b'\n'),
+            'r' => Some(b'\r'),
+            't' => Some(b'\t'),
+            '\\' => Some(b'\\'),
+            '0' => Some(b'\0'),
+            '\'' => Some(b'\''),
+            '"' => Some(b'"'),
+            'x' => {
+                let high = self.peek_char(0)?.to_digit(16)?;
+                self.advance(1);
+                let low = self.peek_char(0)?.to_digit(16)?;
+                self.advance(1);
+                Some((high * 16 + low) as u8)
             }
+            _ => None,
         }
     }
 
     /// Get the next token from the input content. Returns `None` if the end of input is reached.
-    fn next_token(&mut self) -> Result<Option<Token>> {
+    pub fn next_token(&mut self) -> Result<Option<Token>> {
+        let token = self.scan_token();
+        self.after_operand = matches!(&token, Ok(Some(token)) if token.kind.ends_operand());
+        token
+    }
+
+    fn scan_token(&mut self) -> Result<Option<Token>> {
         self.skip_whitespace();
 
         if let Some(char) = self.peek_char(0) {
             match char {
+                '#' if self.position == 0 && self.content.starts_with("#:") => {
+                    self.version_pragma()?;
+                    self.scan_token()
+                }
                 '@' => Ok(Some(self.create_simple_token(TokenKind::Mut, 1))),
                 ';' => Ok(Some(self.create_simple_token(TokenKind::Semicolon, 1))),
                 ',' => Ok(Some(self.create_simple_token(TokenKind::Comma, 1))),
-                '&' => Ok(Some(self.create_simple_token(TokenKind::Ampersand, 1))),
-                '|' => Ok(Some(self.create_simple_token(TokenKind::Pipe, 1))),
+                '&' => match self.peek_char(1) {
+                    Some('&') => Ok(Some(
+                        self.create_simple_token(TokenKind::DoubleAmpersand, 2),
+                    )),
+                    _ => Ok(Some(self.create_simple_token(TokenKind::Ampersand, 1))),
+                },
+                '|' => match self.peek_char(1) {
+                    Some('|') => Ok(Some(self.create_simple_token(TokenKind::DoublePipe, 2))),
+                    _ => Ok(Some(self.create_simple_token(TokenKind::Pipe, 1))),
+                },
                 '{' => Ok(Some(self.create_simple_token(TokenKind::LeftBrace, 1))),
                 '}' => Ok(Some(self.create_simple_token(TokenKind::RightBrace, 1))),
                 '[' => Ok(Some(self.create_simple_token(TokenKind::LeftBracket, 1))),
@@ -147,8 +464,7 @@ impl<'a> Lexer<'a> {
                 '(' => Ok(Some(self.create_simple_token(TokenKind::LeftParen, 1))),
                 ')' => Ok(Some(self.create_simple_token(TokenKind::RightParen, 1))),
                 '\n' => Ok(Some(self.create_simple_token(TokenKind::Newline, 1))),
-                '\'' => Ok(Some(self.create_simple_token(TokenKind::SingleQuote, 1))),
-                '"' => Ok(Some(self.create_simple_token(TokenKind::DoubleQuote, 1))),
+                '\'' | '"' => Ok(Some(self.create_text_literal_token()?)),
                 '=' => Ok(Some(self.create_optional_eq_token(
                     TokenKind::Equal,
                     TokenKind::DoubleEqual,
@@ -177,7 +493,10 @@ impl<'a> Lexer<'a> {
                 },
                 '!' => match self.peek_char(1) {
                     Some('=') => Ok(Some(self.create_simple_token(TokenKind::NotEqual, 2))),
-                    _ => Err(LexerError::UnexpectedCharacter('!', self.position)),
+                    _ => Err(LexerError::UnexpectedCharacter(
+                        '!',
+                        self.span(self.position, 1),
+                    )),
                 },
                 '.' => match self.peek_char(1) {
                     Some('.') => match self.peek_char(2) {
@@ -189,6 +508,9 @@ impl<'a> Lexer<'a> {
                     _ => Ok(Some(self.create_simple_token(TokenKind::Dot, 1))),
                 },
                 '-' => match self.peek_char(1) {
+                    Some('0'..='9') if self.fold_negative_literals && !self.after_operand => {
+                        Ok(Some(self.create_number_token(true)?))
+                    }
                     Some('>') => Ok(Some(self.create_simple_token(TokenKind::RightArrow, 2))),
                     Some('=') => Ok(Some(self.create_simple_token(TokenKind::MinusEqual, 2))),
                     _ => Ok(Some(self.create_simple_token(TokenKind::Minus, 1))),
@@ -203,60 +525,54 @@ impl<'a> Lexer<'a> {
                             }
                             self.advance(1);
                         }
-                        self.next_token()
+                        self.scan_token()
                     }
                     Some('*') => {
                         // Skip multi-line comment
+                        let start = self.position;
                         self.advance(2);
-                        while let Some(c) = self.peek_char(0) {
-                            if c == '*' && self.peek_char(1) == Some('/') {
-                                self.advance(2);
-                                break;
+                        loop {
+                            match self.peek_char(0) {
+                                Some('*') if self.peek_char(1) == Some('/') => {
+                                    self.advance(2);
+                                    break;
+                                }
+                                Some(_) => self.advance(1),
+                                None => {
+                                    return Err(LexerError::UnterminatedComment(
+                                        self.span(start, 2),
+                                    ));
+                                }
                             }
-                            self.advance(1);
                         }
-                        self.next_token()
+                        self.scan_token()
                     }
                     Some('=') => Ok(Some(self.create_simple_token(TokenKind::DivideEqual, 2))),
                     _ => Ok(Some(self.create_simple_token(TokenKind::Divide, 1))),
                 },
-                'a'..='z' | 'A'..='Z' | '_' => {
+                'b' if matches!(self.peek_char(1), Some('"' | '\'')) => {
+                    Ok(Some(self.create_byte_literal_token()?))
+                }
+                c if c.is_alphabetic() || c == '_' => {
+                    let start = self.position;
                     let identifier = self.get_identifier_string();
-                    let length = identifier.len();
-                    let kind = match identifier.as_str() {
-                        // Keywords
-                        "break" => TokenKind::Break,
-                        "else" => TokenKind::Else,
-                        "end" => TokenKind::End,
-                        "false" => TokenKind::False,
-                        "fn" => TokenKind::Fun,
-                        "for" => TokenKind::For,
-                        "if" => TokenKind::If,
-                        "in" => TokenKind::In,
-                        "loop" => TokenKind::Loop,
-                        "patch" => TokenKind::Patch,
-                        "record" => TokenKind::Record,
-                        "return" => TokenKind::Return,
-                        "true" => TokenKind::True,
-                        "union" => TokenKind::Union,
-                        "when" => TokenKind::When,
-
-                        // Operators
-                        "and" => TokenKind::And,
-                        "or" => TokenKind::Or,
-                        "not" => TokenKind::Not,
-
-                        // Default to identifier
-                        _ => TokenKind::Identifier(identifier),
-                    };
-                    let start = self.position - length;
-                    Ok(Some(Token::new(kind, start, length)))
+                    let kind = tokens::reserved_keyword(&identifier)
+                        .unwrap_or(TokenKind::Identifier(identifier));
+                    Ok(Some(Token::new(
+                        kind,
+                        self.file,
+                        start,
+                        self.position - start,
+                    )))
                 }
-                '0'..='9' => Ok(Some(self.create_number_token()?)),
-                char => Err(LexerError::UnexpectedCharacter(char, self.position)),
+                '0'..='9' => Ok(Some(self.create_number_token(false)?)),
+                char => Err(LexerError::UnexpectedCharacter(
+                    char,
+                    self.span(self.position, 1),
+                )),
             }
         } else {
-            return Ok(None);
+            Ok(None)
         }
     }
 
@@ -272,6 +588,93 @@ impl<'a> Lexer<'a> {
 
         Ok(tokens)
     }
+
+    /// Tokenize the whole content, recovering from errors instead of stopping at the first one,
+    /// so that every lexical problem can be reported at once.
+    pub fn tokenize_with_errors(mut self) -> (Vec<Token>, Vec<LexerError>) {
+        let mut tokens = Vec::new();
+        let mut errors = Vec::new();
+        loop {
+            match self.next_token() {
+                Ok(Some(token)) => tokens.push(token),
+                Ok(None) => break,
+                Err(e) => {
+                    self.error_state = Some(e);
+                    self.recover(e);
+                    errors.push(e);
+                }
+            }
+        }
+
+        (tokens, errors)
+    }
+
+    /// Move past the input that caused `error` so lexing can resume.
+    fn recover(&mut self, error: LexerError) {
+        match error {
+            LexerError::UnexpectedCharacter(..) => self.advance(1),
+            // The malformed literal has already been consumed.
+            LexerError::InvalidNumberFormat(_)
+            | LexerError::InvalidByteLiteral(_)
+            | LexerError::InvalidCharLiteral(_)
+            | LexerError::InvalidEscape(_) => {}
+            // Unterminated constructs run to the end of the input.
+            LexerError::UnterminatedComment(_) | LexerError::UnterminatedString(_) => {}
+            LexerError::InvalidPragma(_) | LexerError::UnsupportedVersion(_) => {
+                while let Some(c) = self.peek_char(0) {
+                    if c == '\n' {
+                        break;
+                    }
+                    self.advance(1);
+                }
+            }
+        }
+    }
+
+    /// Re-tokenize the lexer's content after `edit` was applied to the source that produced
+    /// `old_tokens`. Tokens before the damaged region are reused as-is, and once the new token
+    /// stream lines up with the old one again the remaining old tokens are spliced in with their
+    /// spans shifted, so only the region around the edit is actually lexed.
+    pub fn relex(mut self, old_tokens: &[Token], edit: TextEdit) -> Result<Vec<Token>> {
+        // A token may depend on the character right after it (e.g. `.` vs `..`), so tokens that
+        // end directly before the edit are relexed as well.
+        let reused = old_tokens
+            .iter()
+            .take_while(|token| token.position.end + 1 < edit.start)
+            .count();
+        let mut tokens = old_tokens[..reused].to_vec();
+
+        self.position = tokens.last().map_or(0, |token| token.position.end + 1);
+        self.error_state = None;
+        self.after_operand = tokens.last().is_some_and(|token| token.kind.ends_operand());
+
+        let edit_end = edit.start + edit.inserted;
+        let mut remaining = old_tokens[reused..].iter().peekable();
+
+        while let Some(token) = self.next_token()? {
+            if token.position.start >= edit_end {
+                let old_start = token.position.start - edit.inserted + edit.deleted;
+                while remaining
+                    .next_if(|old| old.position.start < old_start)
+                    .is_some()
+                {}
+
+                // The kinds must agree too: whether `-1` folds depends on the token before it.
+                if remaining
+                    .peek()
+                    .is_some_and(|old| old.position.start == old_start && old.kind == token.kind)
+                {
+                    // From here on the text is identical to the old source, so the old tokens
+                    // only need their spans moved.
+                    tokens.extend(remaining.map(|old| old.shifted(edit)));
+                    return Ok(tokens);
+                }
+            }
+            tokens.push(token);
+        }
+
+        Ok(tokens)
+    }
 }
 
 impl<'a> Iterator for Lexer<'a> {
@@ -282,7 +685,7 @@ impl<'a> Iterator for Lexer<'a> {
             Ok(Some(token)) => Some(Ok(token)),
             Ok(None) => None,
             Err(e) => {
-                self.error_state = Some(e.clone());
+                self.error_state = Some(e);
                 Some(Err(e))
             }
         }
@@ -292,8 +695,17 @@ impl<'a> Iterator for Lexer<'a> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
     use test_case::test_case;
 
+    fn span(start: usize, end: usize) -> Span {
+        Span {
+            file: FileId::default(),
+            start,
+            end,
+        }
+    }
+
     fn expect_token(lexer: &mut Lexer, expected_kind: TokenKind) {
         match lexer.next_token() {
             Ok(token) => {
@@ -315,10 +727,11 @@ mod tests {
 
     #[test]
     fn test_keywords() {
-        let input = "break else end false fn for if in loop patch record return true union when";
+        let input = "break const else end false fn for if in loop patch record return true union when while";
         let mut lexer = Lexer::new(input);
 
         expect_token(&mut lexer, TokenKind::Break);
+        expect_token(&mut lexer, TokenKind::Const);
         expect_token(&mut lexer, TokenKind::Else);
         expect_token(&mut lexer, TokenKind::End);
         expect_token(&mut lexer, TokenKind::False);
@@ -333,13 +746,14 @@ mod tests {
         expect_token(&mut lexer, TokenKind::True);
         expect_token(&mut lexer, TokenKind::Union);
         expect_token(&mut lexer, TokenKind::When);
+        expect_token(&mut lexer, TokenKind::While);
 
         expect_eof(&mut lexer);
     }
 
     #[test]
     fn test_syntax_tokens() {
-        let input = ": :: ; -> . .. ..= , & * | { } [ ] ( ) < > ' \" \n";
+        let input = ": :: ; -> . .. ..= , & * | { } [ ] ( ) < > \n";
         let mut lexer = Lexer::new(input);
 
         expect_token(&mut lexer, TokenKind::Colon);
@@ -361,8 +775,6 @@ mod tests {
         expect_token(&mut lexer, TokenKind::RightParen);
         expect_token(&mut lexer, TokenKind::LessThan);
         expect_token(&mut lexer, TokenKind::GreaterThan);
-        expect_token(&mut lexer, TokenKind::SingleQuote);
-        expect_token(&mut lexer, TokenKind::DoubleQuote);
         expect_token(&mut lexer, TokenKind::Newline);
 
         expect_eof(&mut lexer);
@@ -394,6 +806,104 @@ mod tests {
         assert!(lexer.next_token().unwrap().is_none());
     }
 
+    fn integer(value: i128) -> TokenKind {
+        TokenKind::Number(NumberLiteral::Integer(value))
+    }
+
+    #[test_case("-9223372036854775808", &[integer(i64::MIN.into())] ; "i64 min")]
+    #[test_case("18446744073709551615", &[integer(u64::MAX.into())] ; "u64 max")]
+    #[test_case("x = -1", &[TokenKind::Identifier("x".to_string()), TokenKind::Equal, integer(-1)] ; "after operator")]
+    #[test_case("f(-2.5)", &[TokenKind::Identifier("f".to_string()), TokenKind::LeftParen, TokenKind::Number(NumberLiteral::Float(-2.5)), TokenKind::RightParen] ; "float")]
+    #[test_case("a -1", &[TokenKind::Identifier("a".to_string()), TokenKind::Minus, integer(1)] ; "after identifier")]
+    #[test_case("(a) -1", &[TokenKind::LeftParen, TokenKind::Identifier("a".to_string()), TokenKind::RightParen, TokenKind::Minus, integer(1)] ; "after paren")]
+    #[test_case("1 - -1", &[integer(1), TokenKind::Minus, integer(-1)] ; "negated operand")]
+    #[test_case("- 1", &[TokenKind::Minus, integer(1)] ; "not adjacent")]
+    fn test_fold_negative_literals(input: &str, expected: &[TokenKind]) {
+        let tokens = Lexer::new(input)
+            .fold_negative_literals()
+            .tokenize()
+            .expect("Lexer error");
+        let kinds: Vec<TokenKind> = tokens.into_iter().map(|token| token.kind).collect();
+
+        assert_eq!(kinds, expected);
+    }
+
+    #[test]
+    fn test_negative_literals_not_folded_by_default() {
+        let mut lexer = Lexer::new("-1");
+
+        expect_token(&mut lexer, TokenKind::Minus);
+        expect_token(&mut lexer, integer(1));
+        expect_eof(&mut lexer);
+    }
+
+    #[test_case("18446744073709551616" ; "above u64 max")]
+    #[test_case("-9223372036854775809" ; "below i64 min")]
+    fn test_integer_out_of_range(input: &str) {
+        assert_eq!(
+            Lexer::new(input).fold_negative_literals().tokenize(),
+            Err(LexerError::InvalidNumberFormat(span(0, 19)))
+        );
+    }
+
+    #[test_case("x = -1;", "x = a -1;", 4, 0, 2 ; "operand inserted before")]
+    #[test_case("x = a -1;", "x = -1;", 4, 2, 0 ; "operand deleted before")]
+    #[test_case("x = a -1;", "x = a + -1;", 6, 0, 2 ; "operator inserted between")]
+    #[test_case("x = a + -1;", "x = a -1;", 6, 2, 0 ; "operator deleted between")]
+    #[test_case("x = a -1;", "y = a -1;", 0, 1, 1 ; "edit before the operand")]
+    #[test_case("a -1; y = 2;", "a -1; y = 3;", 10, 1, 1 ; "edit after the literal")]
+    #[test_case("a -2;", "a -1;", 3, 1, 1 ; "edit inside the literal after an operand")]
+    fn test_relex_refolds_negative_literal(
+        old: &str,
+        new: &str,
+        start: usize,
+        deleted: usize,
+        inserted: usize,
+    ) {
+        let old_tokens = Lexer::new(old)
+            .fold_negative_literals()
+            .tokenize()
+            .expect("Lexer error");
+        let edit = TextEdit {
+            start,
+            deleted,
+            inserted,
+        };
+
+        assert_eq!(
+            Lexer::new(new)
+                .fold_negative_literals()
+                .relex(&old_tokens, edit),
+            Lexer::new(new).fold_negative_literals().tokenize()
+        );
+    }
+
+    #[test]
+    fn test_symbolic_logical_operators() {
+        let input = "a && b || c & d | e";
+        let mut lexer = Lexer::new(input);
+
+        expect_token(&mut lexer, TokenKind::Identifier("a".to_string()));
+        expect_token(&mut lexer, TokenKind::DoubleAmpersand);
+        expect_token(&mut lexer, TokenKind::Identifier("b".to_string()));
+        expect_token(&mut lexer, TokenKind::DoublePipe);
+        expect_token(&mut lexer, TokenKind::Identifier("c".to_string()));
+        expect_token(&mut lexer, TokenKind::Ampersand);
+        expect_token(&mut lexer, TokenKind::Identifier("d".to_string()));
+        expect_token(&mut lexer, TokenKind::Pipe);
+        expect_token(&mut lexer, TokenKind::Identifier("e".to_string()));
+        expect_eof(&mut lexer);
+
+        assert_eq!(
+            TokenKind::DoubleAmpersand.binary_precedence(),
+            TokenKind::And.binary_precedence()
+        );
+        assert_eq!(
+            TokenKind::DoublePipe.binary_precedence(),
+            TokenKind::Or.binary_precedence()
+        );
+    }
+
     #[test]
     fn test_identifiers_and_numbers() {
         let input = "var1 _var2 123 45.67 0.589";
@@ -433,7 +943,10 @@ mod tests {
                 Ok(Some(_)) => continue,
                 Ok(None) => panic!("Expected an error but got EOF"),
                 Err(e) => {
-                    assert_eq!(e, LexerError::UnexpectedCharacter(char, position));
+                    assert_eq!(
+                        e,
+                        LexerError::UnexpectedCharacter(char, span(position, position))
+                    );
                     return;
                 }
             }
@@ -449,7 +962,7 @@ mod tests {
             Ok(Some(_)) => panic!("Expected an error but got a token"),
             Ok(None) => panic!("Expected an error but got EOF"),
             Err(e) => {
-                assert_eq!(e, LexerError::InvalidNumberFormat(0));
+                assert_eq!(e, LexerError::InvalidNumberFormat(span(0, 8)));
             }
         }
     }
@@ -504,7 +1017,7 @@ mod tests {
         let lexer = Lexer::new(code);
         let tokens: Vec<Token> = lexer.map(|res| res.expect("Lexer error")).collect();
 
-        let expected_kinds = vec![
+        let expected_kinds = [
             TokenKind::Identifier("x".to_string()),
             TokenKind::Equal,
             TokenKind::Number(NumberLiteral::Integer(10)),
@@ -517,4 +1030,484 @@ mod tests {
             assert_eq!(&token.kind, expected_kind);
         }
     }
+
+    #[test]
+    fn test_checkpoint_and_restore() {
+        let code = "x = 10 + 20;";
+        let mut lexer = Lexer::new(code);
+
+        expect_token(&mut lexer, TokenKind::Identifier("x".to_string()));
+        let checkpoint = lexer.checkpoint();
+
+        expect_token(&mut lexer, TokenKind::Equal);
+        expect_token(&mut lexer, TokenKind::Number(NumberLiteral::Integer(10)));
+
+        lexer.restore(checkpoint);
+        expect_token(&mut lexer, TokenKind::Equal);
+        expect_token(&mut lexer, TokenKind::Number(NumberLiteral::Integer(10)));
+        expect_token(&mut lexer, TokenKind::Plus);
+    }
+
+    #[test]
+    fn test_restore_clears_error_state() {
+        let code = "x $";
+        let mut lexer = Lexer::new(code);
+
+        let checkpoint = lexer.checkpoint();
+        let _ = lexer.by_ref().find(|res| res.is_err());
+        assert_eq!(
+            lexer.error(),
+            Some(&LexerError::UnexpectedCharacter('$', span(2, 2)))
+        );
+
+        lexer.restore(checkpoint);
+        assert_eq!(lexer.error(), None);
+        expect_token(&mut lexer, TokenKind::Identifier("x".to_string()));
+    }
+
+    #[test_case("x = 10 + 20;", 4, 2, "300" ; "replace number")]
+    #[test_case("x = 10 + 20;", 1, 0, "yz" ; "extend identifier")]
+    #[test_case("x = a.b;", 5, 0, "." ; "dot becomes range")]
+    #[test_case("x = 1;\ny = 2;", 6, 0, " /* " ; "open comment")]
+    #[test_case("a /* b */ c d", 7, 2, "" ; "close comment removed")]
+    #[test_case("fn add(i32 a) {\n    a + 1\n}", 0, 0, "record " ; "insert at start")]
+    #[test_case("x = 10 + 20;", 11, 1, "" ; "delete at end")]
+    fn test_relex_matches_full_tokenize(old: &str, start: usize, deleted: usize, inserted: &str) {
+        let old_tokens = Lexer::new(old).tokenize().expect("Lexer error");
+
+        let mut new: String = old.chars().take(start).collect();
+        new.push_str(inserted);
+        new.extend(old.chars().skip(start + deleted));
+
+        let edit = TextEdit {
+            start,
+            deleted,
+            inserted: inserted.chars().count(),
+        };
+        let relexed = Lexer::new(&new).relex(&old_tokens, edit);
+
+        assert_eq!(relexed, Lexer::new(&new).tokenize());
+    }
+
+    #[test]
+    fn test_version_pragma() {
+        let code = "#:version 0.1\nx = 1;";
+        let mut lexer = Lexer::new(code);
+
+        assert_eq!(lexer.version_pragma(), Ok(Some(LanguageVersion::new(0, 1))));
+        expect_token(&mut lexer, TokenKind::Newline);
+        expect_token(&mut lexer, TokenKind::Identifier("x".to_string()));
+    }
+
+    #[test]
+    fn test_version_pragma_read_while_tokenizing() {
+        let mut lexer = Lexer::new("#:version 0.1\n");
+
+        expect_token(&mut lexer, TokenKind::Newline);
+        expect_eof(&mut lexer);
+        assert_eq!(lexer.version(), Some(LanguageVersion::new(0, 1)));
+    }
+
+    #[test_case("#:version 9.0", LexerError::UnsupportedVersion(LanguageVersion::new(9, 0)) ; "too new")]
+    #[test_case("#:version 0.0", LexerError::UnsupportedVersion(LanguageVersion::new(0, 0)) ; "too old")]
+    #[test_case("#:version 1", LexerError::InvalidPragma(span(0, 10)) ; "missing minor")]
+    #[test_case("#:versions 0.1", LexerError::InvalidPragma(span(0, 13)) ; "misspelled")]
+    #[test_case("#:edition 2024", LexerError::InvalidPragma(span(0, 13)) ; "unknown pragma")]
+    fn test_invalid_version_pragma(code: &str, expected: LexerError) {
+        assert_eq!(Lexer::new(code).tokenize(), Err(expected));
+    }
+
+    #[test]
+    fn test_no_version_pragma() {
+        let mut lexer = Lexer::new("x");
+
+        assert_eq!(lexer.version_pragma(), Ok(None));
+        expect_token(&mut lexer, TokenKind::Identifier("x".to_string()));
+    }
+
+    #[test]
+    fn test_spans_carry_file_id() {
+        let mut source_map = source::SourceMap::new();
+        source_map.add_file("first.cv", "x");
+        let id = source_map.add_file("second.cv", "y = 1;");
+
+        let tokens = Lexer::for_file(source_map.get(id).unwrap())
+            .tokenize()
+            .expect("Lexer error");
+
+        assert!(tokens.iter().all(|token| token.position.file == id));
+    }
+
+    #[test]
+    fn test_tokenize_with_errors() {
+        let code = "x = $1 + 2.3.4;\ny ! z";
+        let (tokens, errors) = Lexer::new(code).tokenize_with_errors();
+
+        let kinds: Vec<TokenKind> = tokens.into_iter().map(|token| token.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                TokenKind::Identifier("x".to_string()),
+                TokenKind::Equal,
+                TokenKind::Number(NumberLiteral::Integer(1)),
+                TokenKind::Plus,
+                TokenKind::Semicolon,
+                TokenKind::Newline,
+                TokenKind::Identifier("y".to_string()),
+                TokenKind::Identifier("z".to_string()),
+            ]
+        );
+        assert_eq!(
+            errors,
+            [
+                LexerError::UnexpectedCharacter('$', span(4, 4)),
+                LexerError::InvalidNumberFormat(span(9, 13)),
+                LexerError::UnexpectedCharacter('!', span(18, 18)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_with_errors_skips_bad_pragma() {
+        let (tokens, errors) = Lexer::new("#:version 9.9\nx").tokenize_with_errors();
+
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(
+            errors,
+            [LexerError::UnsupportedVersion(LanguageVersion::new(9, 9))]
+        );
+    }
+
+    #[test]
+    fn test_contextual_keywords_lex_as_identifiers() {
+        let mut lexer = Lexer::new("pub as");
+
+        let token = lexer.next_token().unwrap().unwrap();
+        assert_eq!(token.kind, TokenKind::Identifier("pub".to_string()));
+        assert_eq!(
+            token.as_contextual_keyword(&TokenKind::Pub),
+            Some(TokenKind::Pub)
+        );
+        assert_eq!(token.as_contextual_keyword(&TokenKind::As), None);
+
+        let token = lexer.next_token().unwrap().unwrap();
+        assert_eq!(token.kind, TokenKind::Identifier("as".to_string()));
+        assert_eq!(
+            token.as_contextual_keyword(&TokenKind::As),
+            Some(TokenKind::As)
+        );
+    }
+
+    #[test]
+    fn test_reserved_keywords_are_not_promoted() {
+        let token = Lexer::new("when").next_token().unwrap().unwrap();
+
+        assert_eq!(token.kind, TokenKind::When);
+        assert_eq!(token.as_contextual_keyword(&TokenKind::When), None);
+    }
+
+    #[test]
+    fn test_token_kind_classification() {
+        assert!(TokenKind::Record.is_keyword());
+        assert!(TokenKind::And.is_keyword());
+        assert!(TokenKind::Pub.is_keyword());
+        assert!(!TokenKind::Plus.is_keyword());
+
+        assert!(TokenKind::And.is_operator());
+        assert!(TokenKind::ModuloEqual.is_operator());
+        assert!(!TokenKind::LeftParen.is_operator());
+
+        assert!(TokenKind::Number(NumberLiteral::Float(1.5)).is_literal());
+        assert!(TokenKind::False.is_literal());
+        assert!(!TokenKind::Identifier("x".to_string()).is_literal());
+
+        assert!(TokenKind::Identifier("x".to_string()).can_start_expression());
+        assert!(TokenKind::When.can_start_expression());
+        assert!(TokenKind::Not.can_start_expression());
+        assert!(TokenKind::Pipe.can_start_expression());
+        assert!(TokenKind::DoublePipe.can_start_expression());
+        assert!(TokenKind::DoubleAmpersand.can_start_expression());
+        assert!(!TokenKind::RightParen.can_start_expression());
+        assert!(!TokenKind::Semicolon.can_start_expression());
+    }
+
+    #[test]
+    fn test_operator_table() {
+        for operator in tokens::OPERATORS {
+            assert!(operator.is_operator(), "{:?}", operator);
+
+            let text = operator.to_string();
+            let tokens = Lexer::new(&text).tokenize().expect("Lexer error");
+            let kinds: Vec<&TokenKind> = tokens.iter().map(|token| &token.kind).collect();
+            assert_eq!(
+                kinds,
+                [operator],
+                "{:?} does not lex back from {:?}",
+                operator,
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn test_binary_precedence_order() {
+        let precedence = |kind: TokenKind| kind.binary_precedence().unwrap();
+
+        assert!(precedence(TokenKind::Star) > precedence(TokenKind::Plus));
+        assert!(precedence(TokenKind::Plus) > precedence(TokenKind::LessThan));
+        assert!(precedence(TokenKind::LessThan) > precedence(TokenKind::DoubleEqual));
+        assert!(precedence(TokenKind::DoubleEqual) > precedence(TokenKind::And));
+        assert!(precedence(TokenKind::And) > precedence(TokenKind::Or));
+        assert!(precedence(TokenKind::Or) > precedence(TokenKind::Equal));
+        assert_eq!(TokenKind::Not.binary_precedence(), None);
+        assert_eq!(TokenKind::Dot.binary_precedence(), None);
+    }
+
+    #[test]
+    fn test_token_text() {
+        let code = "total += 1.5 // comment";
+        let tokens = Lexer::new(code).tokenize().expect("Lexer error");
+
+        let texts: Vec<&str> = tokens.iter().map(|token| token.text(code)).collect();
+        assert_eq!(texts, ["total", "+=", "1.5"]);
+    }
+
+    #[test]
+    fn test_token_kind_display_roundtrips() {
+        let code = r#"record when pub += != ..= :: -> @ foo 42 b"a\n" b'z' { } ( ) [ ]"#;
+        let tokens = Lexer::new(code).tokenize().expect("Lexer error");
+
+        let rendered: Vec<String> = tokens.iter().map(|token| token.kind.to_string()).collect();
+        assert_eq!(rendered.join(" "), code);
+    }
+
+    #[test]
+    fn test_keyword_display() {
+        for keyword in tokens::KEYWORDS {
+            assert_eq!(keyword.kind.to_string(), keyword.text);
+        }
+        assert_eq!(
+            TokenKind::Number(NumberLiteral::Float(2.0)).to_string(),
+            "2.0"
+        );
+    }
+
+    #[test]
+    fn test_byte_literals() {
+        let code = r#"b"hi\n\x41\"" b'z' b'\'' b"" bar b"#;
+        let mut lexer = Lexer::new(code);
+
+        expect_token(&mut lexer, TokenKind::ByteString(b"hi\nA\"".to_vec()));
+        expect_token(&mut lexer, TokenKind::ByteChar(b'z'));
+        expect_token(&mut lexer, TokenKind::ByteChar(b'\''));
+        expect_token(&mut lexer, TokenKind::ByteString(vec![]));
+        expect_token(&mut lexer, TokenKind::Identifier("bar".to_string()));
+        expect_token(&mut lexer, TokenKind::Identifier("b".to_string()));
+        expect_eof(&mut lexer);
+    }
+
+    #[test_case(r#"b'ab'"# ; "byte char with two bytes")]
+    #[test_case(r#"b''"# ; "empty byte char")]
+    #[test_case("b\"\u{e9}\"" ; "non ascii byte string")]
+    #[test_case(r#"b"\q""# ; "unknown escape")]
+    #[test_case(r#"b"\x4""# ; "short hex escape")]
+    fn test_invalid_byte_literal(code: &str) {
+        assert_eq!(
+            Lexer::new(code).tokenize(),
+            Err(LexerError::InvalidByteLiteral(span(
+                0,
+                code.chars().count() - 1
+            )))
+        );
+    }
+
+    #[test]
+    fn test_invalid_byte_literal_is_consumed() {
+        let (tokens, errors) = Lexer::new(r#"b"\q" x"#).tokenize_with_errors();
+
+        assert_eq!(errors, [LexerError::InvalidByteLiteral(span(0, 4))]);
+        assert_eq!(tokens.len(), 1);
+    }
+
+    #[test_case("x /* never closed", 2 ; "block comment")]
+    #[test_case("/* never closed */ /*", 19 ; "second block comment")]
+    fn test_unterminated_comment(code: &str, start: usize) {
+        let file = FileId::default();
+
+        assert_eq!(
+            Lexer::new(code).tokenize(),
+            Err(LexerError::UnterminatedComment(Span {
+                file,
+                start,
+                end: start + 1,
+            }))
+        );
+    }
+
+    #[test]
+    fn test_string_and_char_literals() {
+        let code = r#""hello, \"world\"\n" 'a' '\'' '\u{e9}' "" "ü""#;
+        let mut lexer = Lexer::new(code);
+
+        expect_token(
+            &mut lexer,
+            TokenKind::String("hello, \"world\"\n".to_string()),
+        );
+        expect_token(&mut lexer, TokenKind::Char('a'));
+        expect_token(&mut lexer, TokenKind::Char('\''));
+        expect_token(&mut lexer, TokenKind::Char('é'));
+        expect_token(&mut lexer, TokenKind::String(String::new()));
+        expect_token(&mut lexer, TokenKind::String("ü".to_string()));
+        expect_eof(&mut lexer);
+    }
+
+    #[test_case("''", LexerError::InvalidCharLiteral(span(0, 1)) ; "empty char")]
+    #[test_case("x = 'ab'", LexerError::InvalidCharLiteral(span(4, 7)) ; "two chars")]
+    #[test_case(r#""a\qb""#, LexerError::InvalidEscape(span(2, 3)) ; "unknown escape")]
+    #[test_case(r#""\x80""#, LexerError::InvalidEscape(span(1, 4)) ; "non-ascii hex escape")]
+    #[test_case(r#"'\u{110000}'"#, LexerError::InvalidEscape(span(1, 10)) ; "not a scalar value")]
+    #[test_case(r#""\u{}""#, LexerError::InvalidEscape(span(1, 4)) ; "empty unicode escape")]
+    fn test_invalid_text_literals(code: &str, expected: LexerError) {
+        let (tokens, errors) = Lexer::new(&format!("{code} end")).tokenize_with_errors();
+
+        assert_eq!(errors, [expected]);
+        assert_eq!(
+            tokens.last().map(|token| &token.kind),
+            Some(&TokenKind::End)
+        );
+    }
+
+    #[test]
+    fn test_integer_range() {
+        let mut lexer = Lexer::new("0..10 1.5..=2");
+
+        expect_token(&mut lexer, TokenKind::Number(NumberLiteral::Integer(0)));
+        expect_token(&mut lexer, TokenKind::Range);
+        expect_token(&mut lexer, TokenKind::Number(NumberLiteral::Integer(10)));
+        expect_token(&mut lexer, TokenKind::Number(NumberLiteral::Float(1.5)));
+        expect_token(&mut lexer, TokenKind::RangeInclusive);
+        expect_token(&mut lexer, TokenKind::Number(NumberLiteral::Integer(2)));
+        expect_eof(&mut lexer);
+    }
+
+    #[test]
+    fn test_method_call_on_integer() {
+        let mut lexer = Lexer::new("5.double()");
+
+        expect_token(&mut lexer, TokenKind::Number(NumberLiteral::Integer(5)));
+        expect_token(&mut lexer, TokenKind::Dot);
+        expect_token(&mut lexer, TokenKind::Identifier("double".to_string()));
+        expect_token(&mut lexer, TokenKind::LeftParen);
+        expect_token(&mut lexer, TokenKind::RightParen);
+        expect_eof(&mut lexer);
+    }
+
+    #[test]
+    fn test_unterminated_text_string() {
+        assert_eq!(
+            Lexer::new(r#"x = "abc"#).tokenize(),
+            Err(LexerError::UnterminatedString(Span {
+                file: FileId::default(),
+                start: 4,
+                end: 4,
+            }))
+        );
+    }
+
+    #[test_case(r#"x = b"abc"#, 4 ; "byte string")]
+    #[test_case(r#"b'a"#, 0 ; "byte char")]
+    #[test_case(r#"b"abc\"#, 0 ; "escaped closing quote")]
+    fn test_unterminated_string(code: &str, start: usize) {
+        let (_, errors) = Lexer::new(code).tokenize_with_errors();
+
+        assert_eq!(
+            errors,
+            [LexerError::UnterminatedString(Span {
+                file: FileId::default(),
+                start,
+                end: start + 1,
+            })]
+        );
+    }
+
+    #[test]
+    fn test_multibyte_characters() {
+        let code = "größe = 1; // ü\nπ";
+        let tokens = Lexer::new(code).tokenize().expect("Lexer error");
+
+        let texts: Vec<&str> = tokens.iter().map(|token| token.text(code)).collect();
+        assert_eq!(texts, ["größe", "=", "1", ";", "\n", "π"]);
+    }
+
+    /// Whether `gap` is text the lexer skips between tokens: whitespace and comments.
+    fn is_trivia(mut gap: &str) -> bool {
+        loop {
+            gap = gap.trim_start_matches(|c: char| c.is_whitespace() && c != '\n');
+            if gap.is_empty() {
+                return true;
+            } else if let Some(rest) = gap.strip_prefix("//") {
+                gap = rest.find('\n').map_or("", |end| &rest[end..]);
+            } else if let Some(rest) = gap.strip_prefix("/*") {
+                match rest.find("*/") {
+                    Some(end) => gap = &rest[end + 2..],
+                    None => return false,
+                }
+            } else {
+                return false;
+            }
+        }
+    }
+
+    fn lexeme() -> impl Strategy<Value = String> {
+        prop_oneof![
+            "[a-zA-Z_][a-zA-Z0-9_]{0,8}",
+            "[0-9]{1,6}(\\.[0-9]{1,4})?",
+            "b\"[a-z ]{0,5}\"",
+            "\"[a-zé ]{0,5}\"",
+            "'[a-z]'",
+            prop::sample::select(vec![
+                "@", ";", ",", "&", "|", "{", "}", "[", "]", "(", ")", "=", "==", "<", "<=", ">",
+                ">=", "+", "+=", "-", "-=", "->", "*", "*=", "/", "/=", "%", "%=", ":", "::", "!=",
+                ".", "..", "..=", "\n", "é", "日本",
+            ])
+            .prop_map(String::from),
+        ]
+    }
+
+    fn separator() -> impl Strategy<Value = String> {
+        prop::sample::select(vec!["", " ", "\t", "\n", " /* c\n ü */ ", " // c\n"])
+            .prop_map(String::from)
+    }
+
+    proptest! {
+        #[test]
+        fn prop_lexer_never_panics(code in any::<String>()) {
+            let _ = Lexer::new(&code).tokenize_with_errors();
+        }
+
+        #[test]
+        fn prop_token_texts_and_trivia_reproduce_input(
+            parts in prop::collection::vec((lexeme(), separator()), 0..40)
+        ) {
+            let code: String = parts.iter().flat_map(|(lexeme, sep)| [lexeme.as_str(), sep]).collect();
+            let Ok(tokens) = Lexer::new(&code).tokenize() else {
+                // Adjacent lexemes can combine into invalid input, such as `1.2` followed by `.3`.
+                return Ok(());
+            };
+
+            let mut rebuilt = String::new();
+            for token in &tokens {
+                let consumed = rebuilt.chars().count();
+                let gap: String = code.chars().skip(consumed).take(token.position.start - consumed).collect();
+                prop_assert!(is_trivia(&gap), "non-trivia gap {:?} in {:?}", gap, code);
+                rebuilt.push_str(&gap);
+                rebuilt.push_str(token.text(&code));
+            }
+            let tail: String = code.chars().skip(rebuilt.chars().count()).collect();
+            prop_assert!(is_trivia(&tail), "non-trivia tail {:?} in {:?}", tail, code);
+            rebuilt.push_str(&tail);
+
+            prop_assert_eq!(rebuilt, code);
+        }
+    }
 }
@@ -0,0 +1,108 @@
+//! Registry of the source files taking part in a compilation.
+
+/// Identifies a file registered with a [`SourceMap`].
+#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct FileId(u32);
+
+impl FileId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SourceFile {
+    pub id: FileId,
+    pub name: String,
+    pub content: String,
+    /// Character offsets at which each line starts.
+    line_starts: Vec<usize>,
+}
+
+impl SourceFile {
+    fn new(id: FileId, name: String, content: String) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(
+                content
+                    .chars()
+                    .enumerate()
+                    .filter(|(_, c)| *c == '\n')
+                    .map(|(offset, _)| offset + 1),
+            )
+            .collect();
+
+        Self {
+            id,
+            name,
+            content,
+            line_starts,
+        }
+    }
+
+    /// Convert a character offset into a 1-based `(line, column)` pair.
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let line = self.line_starts.partition_point(|&start| start <= offset) - 1;
+        (line + 1, offset - self.line_starts[line] + 1)
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+}
+
+/// Owns every source file of a compilation and hands out the [`FileId`]s carried by spans.
+#[derive(Debug, Default, Clone)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a file and return the id its tokens' spans will refer to.
+    pub fn add_file(&mut self, name: impl Into<String>, content: impl Into<String>) -> FileId {
+        let id = FileId(self.files.len() as u32);
+        self.files
+            .push(SourceFile::new(id, name.into(), content.into()));
+        id
+    }
+
+    pub fn get(&self, id: FileId) -> Option<&SourceFile> {
+        self.files.get(id.index())
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = &SourceFile> {
+        self.files.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_file_assigns_sequential_ids() {
+        let mut source_map = SourceMap::new();
+        let first = source_map.add_file("main.cv", "x = 1;");
+        let second = source_map.add_file("util.cv", "y = 2;");
+
+        assert_ne!(first, second);
+        assert_eq!(source_map.get(first).unwrap().name, "main.cv");
+        assert_eq!(source_map.get(second).unwrap().content, "y = 2;");
+    }
+
+    #[test]
+    fn test_line_col() {
+        let mut source_map = SourceMap::new();
+        let id = source_map.add_file("main.cv", "x = 1;\nüy = 2;\n");
+        let file = source_map.get(id).unwrap();
+
+        assert_eq!(file.line_col(0), (1, 1));
+        assert_eq!(file.line_col(5), (1, 6));
+        assert_eq!(file.line_col(7), (2, 1));
+        assert_eq!(file.line_col(8), (2, 2));
+        assert_eq!(file.line_count(), 3);
+    }
+}
@@ -1,6 +1,10 @@
+use crate::TextEdit;
+use crate::source::FileId;
+use std::fmt;
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum NumberLiteral {
-    Integer(i64),
+    Integer(i128),
     Float(f64),
 }
 
@@ -8,6 +12,7 @@ pub enum NumberLiteral {
 pub enum TokenKind {
     // Keywords:
     Break,  // break
+    Const,  // const
     Else,   // else
     End,    // end
     False,  // false
@@ -22,6 +27,12 @@ pub enum TokenKind {
     True,   // true
     Union,  // union
     When,   // when
+    While,  // while
+
+    // Contextual keywords (lexed as identifiers, promoted by the parser):
+    As,     // as
+    Import, // import
+    Pub,    // pub
 
     // Syntax:
     Mut,            // @
@@ -44,38 +55,315 @@ pub enum TokenKind {
     RightParen,     // )
     LessThan,       // <
     GreaterThan,    // >
-    SingleQuote,    // '
-    DoubleQuote,    // "
     Newline,        // Newline character
 
     // Operators:
-    And,          // and
-    Or,           // or
-    Not,          // not
-    Plus,         // +
-    Minus,        // -
-    Divide,       // /
-    Modulo,       // %
-    Equal,        // =
-    DoubleEqual,  // ==
-    NotEqual,     // !=
-    LessEqual,    // <=
-    GreaterEqual, // >=
-    PlusEqual,    // +=
-    MinusEqual,   // -=
-    TimesEqual,   // *=
-    DivideEqual,  // /=
-    ModuloEqual,  // %=
+    And,             // and
+    Or,              // or
+    DoubleAmpersand, // && (alias for and)
+    DoublePipe,      // || (alias for or)
+    Not,             // not
+    Plus,            // +
+    Minus,           // -
+    Divide,          // /
+    Modulo,          // %
+    Equal,           // =
+    DoubleEqual,     // ==
+    NotEqual,        // !=
+    LessEqual,       // <=
+    GreaterEqual,    // >=
+    PlusEqual,       // +=
+    MinusEqual,      // -=
+    TimesEqual,      // *=
+    DivideEqual,     // /=
+    ModuloEqual,     // %=
 
     // Identifiers and literals:
-    Identifier(String), // e.g., variable, function, and type names
+    Identifier(String),    // e.g., variable, function, and type names
     Number(NumberLiteral), // e.g., 123, 45.67
+    String(String),        // e.g., "text\n"
+    Char(char),            // e.g., 'c'
+    ByteString(Vec<u8>),   // e.g., b"bytes\n"
+    ByteChar(u8),          // e.g., b'x'
+}
+
+impl TokenKind {
+    /// Whether this is a reserved or contextual keyword, including the word operators.
+    pub fn is_keyword(&self) -> bool {
+        KEYWORDS.iter().any(|keyword| keyword.kind == *self)
+    }
+
+    pub fn is_operator(&self) -> bool {
+        matches!(
+            self,
+            TokenKind::And
+                | TokenKind::Or
+                | TokenKind::DoubleAmpersand
+                | TokenKind::DoublePipe
+                | TokenKind::Not
+                | TokenKind::Plus
+                | TokenKind::Minus
+                | TokenKind::Star
+                | TokenKind::Divide
+                | TokenKind::Modulo
+                | TokenKind::Ampersand
+                | TokenKind::Range
+                | TokenKind::RangeInclusive
+                | TokenKind::Equal
+                | TokenKind::DoubleEqual
+                | TokenKind::NotEqual
+                | TokenKind::LessThan
+                | TokenKind::GreaterThan
+                | TokenKind::LessEqual
+                | TokenKind::GreaterEqual
+                | TokenKind::PlusEqual
+                | TokenKind::MinusEqual
+                | TokenKind::TimesEqual
+                | TokenKind::DivideEqual
+                | TokenKind::ModuloEqual
+        )
+    }
 
-                        // Other:
+    pub fn is_literal(&self) -> bool {
+        matches!(
+            self,
+            TokenKind::Number(_)
+                | TokenKind::String(_)
+                | TokenKind::Char(_)
+                | TokenKind::ByteString(_)
+                | TokenKind::ByteChar(_)
+                | TokenKind::True
+                | TokenKind::False
+        )
+    }
+
+    /// Binding strength of this token when used as an infix operator; higher binds tighter.
+    /// Returns `None` for tokens that cannot be binary operators.
+    pub fn binary_precedence(&self) -> Option<u8> {
+        match self {
+            TokenKind::Equal
+            | TokenKind::PlusEqual
+            | TokenKind::MinusEqual
+            | TokenKind::TimesEqual
+            | TokenKind::DivideEqual
+            | TokenKind::ModuloEqual => Some(1),
+            TokenKind::Range | TokenKind::RangeInclusive => Some(2),
+            TokenKind::Or | TokenKind::DoublePipe => Some(3),
+            TokenKind::And | TokenKind::DoubleAmpersand => Some(4),
+            TokenKind::DoubleEqual | TokenKind::NotEqual => Some(5),
+            TokenKind::LessThan
+            | TokenKind::GreaterThan
+            | TokenKind::LessEqual
+            | TokenKind::GreaterEqual => Some(6),
+            TokenKind::Plus | TokenKind::Minus => Some(7),
+            TokenKind::Star | TokenKind::Divide | TokenKind::Modulo => Some(8),
+            _ => None,
+        }
+    }
+
+    /// Whether an expression may end with this token, so that a `-` after it is a binary minus.
+    pub fn ends_operand(&self) -> bool {
+        self.is_literal()
+            || matches!(
+                self,
+                TokenKind::Identifier(_)
+                    | TokenKind::RightParen
+                    | TokenKind::RightBracket
+                    | TokenKind::RightBrace
+            )
+    }
+
+    /// Whether an expression may begin with this token.
+    pub fn can_start_expression(&self) -> bool {
+        self.is_literal()
+            || matches!(
+                self,
+                TokenKind::Identifier(_)
+                    | TokenKind::LeftParen
+                    | TokenKind::LeftBracket
+                    | TokenKind::LeftBrace
+                    | TokenKind::If
+                    | TokenKind::When
+                    | TokenKind::Loop
+                    | TokenKind::For
+                    | TokenKind::While
+                    | TokenKind::Minus
+                    | TokenKind::Not
+                    | TokenKind::Ampersand
+                    | TokenKind::DoubleAmpersand
+                    | TokenKind::Star
+                    | TokenKind::Pipe
+                    | TokenKind::DoublePipe
+            )
+    }
+}
+
+impl fmt::Display for NumberLiteral {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NumberLiteral::Integer(value) => write!(f, "{}", value),
+            NumberLiteral::Float(value) => write!(f, "{:?}", value),
+        }
+    }
+}
+
+impl fmt::Display for TokenKind {
+    /// Prints the canonical lexeme of the token, e.g. `+=` or `record`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lexeme = match self {
+            TokenKind::Identifier(name) => return write!(f, "{}", name),
+            TokenKind::Number(number) => return write!(f, "{}", number),
+            TokenKind::String(text) => return write!(f, "{:?}", text),
+            TokenKind::Char(c) => return write!(f, "{:?}", c),
+            TokenKind::ByteString(bytes) => {
+                return write!(f, "b\"{}\"", bytes.escape_ascii());
+            }
+            TokenKind::ByteChar(byte) => return write!(f, "b'{}'", byte.escape_ascii()),
+            TokenKind::Mut => "@",
+            TokenKind::Colon => ":",
+            TokenKind::Scope => "::",
+            TokenKind::Semicolon => ";",
+            TokenKind::RightArrow => "->",
+            TokenKind::Dot => ".",
+            TokenKind::Range => "..",
+            TokenKind::RangeInclusive => "..=",
+            TokenKind::Comma => ",",
+            TokenKind::Ampersand => "&",
+            TokenKind::Star => "*",
+            TokenKind::Pipe => "|",
+            TokenKind::LeftBrace => "{",
+            TokenKind::RightBrace => "}",
+            TokenKind::LeftBracket => "[",
+            TokenKind::RightBracket => "]",
+            TokenKind::LeftParen => "(",
+            TokenKind::RightParen => ")",
+            TokenKind::LessThan => "<",
+            TokenKind::GreaterThan => ">",
+            TokenKind::Newline => "\\n",
+            TokenKind::DoubleAmpersand => "&&",
+            TokenKind::DoublePipe => "||",
+            TokenKind::Plus => "+",
+            TokenKind::Minus => "-",
+            TokenKind::Divide => "/",
+            TokenKind::Modulo => "%",
+            TokenKind::Equal => "=",
+            TokenKind::DoubleEqual => "==",
+            TokenKind::NotEqual => "!=",
+            TokenKind::LessEqual => "<=",
+            TokenKind::GreaterEqual => ">=",
+            TokenKind::PlusEqual => "+=",
+            TokenKind::MinusEqual => "-=",
+            TokenKind::TimesEqual => "*=",
+            TokenKind::DivideEqual => "/=",
+            TokenKind::ModuloEqual => "%=",
+            // Every remaining kind is a keyword, spelled as in the keyword table.
+            _ => KEYWORDS
+                .iter()
+                .find(|keyword| keyword.kind == *self)
+                .map_or("", |keyword| keyword.text),
+        };
+        write!(f, "{}", lexeme)
+    }
+}
+
+/// An entry in the keyword table.
+///
+/// Reserved keywords are always lexed as their own token kind. Contextual keywords are lexed as
+/// plain identifiers so they stay usable as names, and the parser promotes them to their keyword
+/// kind where the grammar expects one.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Keyword {
+    pub text: &'static str,
+    pub kind: TokenKind,
+    pub contextual: bool,
+}
+
+const fn reserved(text: &'static str, kind: TokenKind) -> Keyword {
+    Keyword {
+        text,
+        kind,
+        contextual: false,
+    }
+}
+
+const fn contextual(text: &'static str, kind: TokenKind) -> Keyword {
+    Keyword {
+        text,
+        kind,
+        contextual: true,
+    }
+}
+
+pub const KEYWORDS: &[Keyword] = &[
+    reserved("break", TokenKind::Break),
+    reserved("const", TokenKind::Const),
+    reserved("else", TokenKind::Else),
+    reserved("end", TokenKind::End),
+    reserved("false", TokenKind::False),
+    reserved("fn", TokenKind::Fun),
+    reserved("for", TokenKind::For),
+    reserved("if", TokenKind::If),
+    reserved("in", TokenKind::In),
+    reserved("loop", TokenKind::Loop),
+    reserved("patch", TokenKind::Patch),
+    reserved("record", TokenKind::Record),
+    reserved("return", TokenKind::Return),
+    reserved("true", TokenKind::True),
+    reserved("union", TokenKind::Union),
+    reserved("when", TokenKind::When),
+    reserved("while", TokenKind::While),
+    reserved("and", TokenKind::And),
+    reserved("or", TokenKind::Or),
+    reserved("not", TokenKind::Not),
+    contextual("as", TokenKind::As),
+    contextual("import", TokenKind::Import),
+    contextual("pub", TokenKind::Pub),
+];
+
+/// Every operator token, including the word operators, in no particular order.
+pub const OPERATORS: &[TokenKind] = &[
+    TokenKind::And,
+    TokenKind::Or,
+    TokenKind::Not,
+    TokenKind::DoubleAmpersand,
+    TokenKind::DoublePipe,
+    TokenKind::Plus,
+    TokenKind::Minus,
+    TokenKind::Star,
+    TokenKind::Divide,
+    TokenKind::Modulo,
+    TokenKind::Ampersand,
+    TokenKind::Range,
+    TokenKind::RangeInclusive,
+    TokenKind::Equal,
+    TokenKind::DoubleEqual,
+    TokenKind::NotEqual,
+    TokenKind::LessThan,
+    TokenKind::GreaterThan,
+    TokenKind::LessEqual,
+    TokenKind::GreaterEqual,
+    TokenKind::PlusEqual,
+    TokenKind::MinusEqual,
+    TokenKind::TimesEqual,
+    TokenKind::DivideEqual,
+    TokenKind::ModuloEqual,
+];
+
+/// Look up the keyword spelled `text`, whether reserved or contextual.
+pub fn keyword(text: &str) -> Option<&'static Keyword> {
+    KEYWORDS.iter().find(|keyword| keyword.text == text)
+}
+
+/// The token kind the lexer should emit for `text`, if it is a reserved keyword.
+pub fn reserved_keyword(text: &str) -> Option<TokenKind> {
+    keyword(text)
+        .filter(|keyword| !keyword.contextual)
+        .map(|keyword| keyword.kind.clone())
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub struct Span {
+    pub file: FileId,
     pub start: usize,
     pub end: usize,
 }
@@ -87,17 +375,54 @@ pub struct Token {
 }
 
 impl Token {
-    /// Create a new token with the given kind, start position, and length.
-    pub fn new(kind: TokenKind, start: usize, len: usize) -> Self {
+    /// Create a new token with the given kind, file, start position, and length.
+    pub fn new(kind: TokenKind, file: FileId, start: usize, len: usize) -> Self {
         Self {
             kind,
             position: Span {
+                file,
                 start,
                 end: start + len - 1,
             },
         }
     }
 
+    /// Move this token to where it ends up after `edit`, which must lie entirely before it.
+    pub(crate) fn shifted(&self, edit: TextEdit) -> Self {
+        let shift = |offset: usize| offset + edit.inserted - edit.deleted;
+        Self {
+            kind: self.kind.clone(),
+            position: Span {
+                file: self.position.file,
+                start: shift(self.position.start),
+                end: shift(self.position.end),
+            },
+        }
+    }
+
+    /// The slice of `source` this token was lexed from.
+    pub fn text<'a>(&self, source: &'a str) -> &'a str {
+        // Spans count characters, so translate them to byte offsets before slicing.
+        let byte_offset = |offset: usize| {
+            source
+                .char_indices()
+                .nth(offset)
+                .map_or(source.len(), |(index, _)| index)
+        };
+        &source[byte_offset(self.position.start)..byte_offset(self.position.end + 1)]
+    }
+
+    /// Promote an identifier token to the contextual keyword `expected` if it is spelled as one.
+    /// Used by the parser in positions where the grammar allows that keyword.
+    pub fn as_contextual_keyword(&self, expected: &TokenKind) -> Option<TokenKind> {
+        match &self.kind {
+            TokenKind::Identifier(name) => keyword(name)
+                .filter(|keyword| keyword.contextual && &keyword.kind == expected)
+                .map(|keyword| keyword.kind.clone()),
+            _ => None,
+        }
+    }
+
     pub fn is_single_char_token(&self) -> bool {
         self.position.start == self.position.end
     }
@@ -0,0 +1,41 @@
+use std::fmt;
+
+/// A CV language version, as declared by a `#:version <major>.<minor>` pragma on the first line
+/// of a source file.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct LanguageVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl LanguageVersion {
+    /// The language version implemented by this toolchain.
+    pub const CURRENT: LanguageVersion = LanguageVersion::new(0, 1);
+    /// The oldest language version this toolchain still accepts.
+    pub const MIN_SUPPORTED: LanguageVersion = LanguageVersion::new(0, 1);
+
+    pub const fn new(major: u32, minor: u32) -> Self {
+        Self { major, minor }
+    }
+
+    /// Parse a `<major>.<minor>` version string.
+    pub fn parse(text: &str) -> Option<Self> {
+        let (major, minor) = text.split_once('.')?;
+        let is_number = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit());
+        if !is_number(major) || !is_number(minor) {
+            return None;
+        }
+
+        Some(Self::new(major.parse().ok()?, minor.parse().ok()?))
+    }
+
+    pub fn is_supported(&self) -> bool {
+        (Self::MIN_SUPPORTED..=Self::CURRENT).contains(self)
+    }
+}
+
+impl fmt::Display for LanguageVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(code) = std::str::from_utf8(data) {
+        if let Ok(program) = parser::parse(code) {
+            let _ = parser::validate::validate(&program);
+        }
+    }
+});
@@ -1,81 +1,166 @@
+use crate::node_id::NodeId;
 use std::fmt;
 
+pub use crate::diff::{AstChange, diff};
+
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Program {
     pub declarations: Vec<Declaration>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Declaration {
     Function(FunctionDeclaration),
     Record(RecordDeclaration),
     Union(UnionDeclaration),
     Patch(PatchDeclaration),
+    Const(ConstDeclaration),
+    Import(ImportDeclaration),
     Statement(Statement),
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FunctionDeclaration {
+    pub id: NodeId,
     pub name: String,
+    /// Whether the function is declared `pub`, so other modules can import it.
+    pub is_public: bool,
+    pub type_parameters: Vec<TypeParameter>,
     pub params: Vec<Parameter>,
     pub return_type: Option<Type>,
     pub body: Box<Expression>,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Parameter {
+    pub id: NodeId,
     pub name: String,
     pub param_type: Type,
     pub is_mutable: bool,
     pub is_ref: bool,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RecordDeclaration {
+    pub id: NodeId,
     pub name: String,
+    pub is_public: bool,
+    pub type_parameters: Vec<TypeParameter>,
     pub fields: Vec<RecordField>,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RecordField {
+    pub id: NodeId,
     pub name: String,
+    /// Whether the field is declared `pub`, so other modules can read it and build records
+    /// with it.
+    pub is_public: bool,
     pub field_type: Type,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct UnionDeclaration {
+    pub id: NodeId,
     pub name: String,
-    pub tyne_parameters: Vec<String>, // for generics
+    /// Whether the union is declared `pub`, which makes its variants public too.
+    pub is_public: bool,
+    pub type_parameters: Vec<TypeParameter>, // for generics
     pub variants: Vec<UnionVariant>,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// A parameter of a generic declaration: a type parameter `T`, or a const parameter `N: usize`
+/// that stands for a compile-time integer such as the length of a `fixedArray`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TypeParameter {
+    pub id: NodeId,
+    pub name: String,
+    pub const_type: Option<Type>,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct UnionVariant {
+    pub id: NodeId,
     pub name: String,
     pub variant_type: Option<Type>, // some variants may carry data
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PatchDeclaration {
+    pub id: NodeId,
     pub target_type: Type,
     pub methods: Vec<FunctionDeclaration>,
 }
 
+/// `import module::name;` or `import module::{name, ...};`, which makes public declarations of
+/// another module visible in this one.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ImportDeclaration {
+    pub id: NodeId,
+    pub module: String,
+    pub items: Vec<String>,
+}
+
+/// `const type name = value;`, a named value fixed at compile time.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConstDeclaration {
+    pub id: NodeId,
+    pub name: String,
+    pub is_public: bool,
+    pub const_type: Type,
+    pub value: Box<Expression>,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Statement {
+    pub id: NodeId,
+    pub kind: StatementKind,
+}
+
 #[derive(Debug, Clone, PartialEq)]
-pub enum Statement {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum StatementKind {
     VariableDeclaration {
         name: String,
         var_type: Option<Type>,
         is_mutable: bool,
         value: Box<Expression>,
     },
+    /// `target = value;` or a compound assignment such as `target += value;`. The target is
+    /// always an lvalue.
+    Assignment {
+        target: Box<Expression>,
+        operator: BinaryOperator,
+        value: Box<Expression>,
+    },
     Expression(Box<Expression>),
     Return(Option<Box<Expression>>),
     Break(Option<Box<Expression>>), // Break with optional value
 }
 
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Expression {
+    pub id: NodeId,
+    pub kind: ExpressionKind,
+}
+
 #[derive(Debug, Clone, PartialEq)]
-pub enum Expression {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ExpressionKind {
     Literal(Literal),
     Identifier(String),
     BinaryOperation {
@@ -95,6 +180,13 @@ pub enum Expression {
         record: Box<Expression>,
         field: String,
     },
+    /// `receiver.method(arguments)`. Calling a function stored in a field is written
+    /// `(record.field)(arguments)` and parses as a `FunctionCall` instead.
+    MethodCall {
+        receiver: Box<Expression>,
+        method: String,
+        arguments: Vec<Expression>,
+    },
     IndexAccess {
         collection: Box<Expression>,
         index: Box<Expression>,
@@ -128,6 +220,9 @@ pub enum Expression {
     RecordLiteral {
         record_type: Type,
         fields: Vec<(String, Expression)>,
+        /// The record after `..` in `point { x: 1, ..base }`, which supplies every field not
+        /// listed.
+        base: Option<Box<Expression>>,
     },
     UnionLiteral {
         union_type: Type,
@@ -139,6 +234,10 @@ pub enum Expression {
         expression: Box<Expression>,
     },
     Dereference(Box<Expression>),
+    /// `(expression)`, kept so that tools reprinting the source can keep the user's parentheses.
+    /// Only a parser made with [`Parser::keep_parentheses`](crate::Parser::keep_parentheses)
+    /// produces it; it means the same as the expression inside.
+    Grouped(Box<Expression>),
     Range {
         start: Box<Expression>,
         end: Box<Expression>,
@@ -148,36 +247,82 @@ pub enum Expression {
         expression: Box<Expression>,
         annotated_type: Type,
     },
+    /// `expression as target`, converting a number to another numeric type or a reference to a
+    /// less mutable one.
+    Cast {
+        expression: Box<Expression>,
+        target: Type,
+    },
+    Closure {
+        params: Vec<Parameter>, // untyped parameters have type `Type::Inferred`
+        return_type: Option<Type>,
+        body: Box<Expression>,
+        captures: Vec<String>, // filled in by name resolution, empty after parsing
+    },
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct WhenBranch {
+    pub id: NodeId,
     pub pattern: Pattern,
+    /// The condition after `if` in `n if n > 0: ...`. The branch is taken only when the pattern
+    /// matches and the guard is true.
+    pub guard: Option<Box<Expression>>,
     pub body: Box<Expression>,
 }
 
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Pattern {
+    pub id: NodeId,
+    pub kind: PatternKind,
+}
+
 #[derive(Debug, Clone, PartialEq)]
-pub enum Pattern {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PatternKind {
     Identifier(String),
     Literal(Literal),
+    /// A union variant, with a pattern for its payload as in `ok(value)` or `some((x, _))`.
     Union {
         variant: String,
-        binding: Option<String>,
+        payload: Option<Box<Pattern>>,
+    },
+    /// `point { x, y: 0 }`. A field written without a pattern binds a variable of its own name.
+    Record {
+        record_type: Type,
+        fields: Vec<(String, Pattern)>,
+    },
+    /// `(a, b)`, matching the elements of tuple-like data in order.
+    Tuple(Vec<Pattern>),
+    /// `0..10` or `'a'..='z'`, matching values between two literals.
+    Range {
+        start: Literal,
+        end: Literal,
+        inclusive: bool,
     },
+    /// `1 | 2 | 3`, matching when any alternative does. Every alternative binds the same names.
+    Or(Vec<Pattern>),
     Wildcard,
     Else,
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Literal {
-    Integer(i64),
+    Integer(i128),
     Float(f64),
     Boolean(bool),
     String(String),
     Char(char),
+    Bytes(Vec<u8>),
+    /// `()`, the only value of type `unit`.
+    Unit,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BinaryOperator {
     // Arithmetic
     Add,
@@ -208,6 +353,7 @@ pub enum BinaryOperator {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum UnaryOperator {
     Not,
     Negate,
@@ -217,6 +363,7 @@ pub enum UnaryOperator {
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Type {
     I8,
     I16,
@@ -233,6 +380,9 @@ pub enum Type {
     Bool,
     Char,
     String,
+    /// The type of expressions evaluated only for their effect, such as a function without a
+    /// return type or a `break` without a value.
+    Unit,
     Named(String),
     Generic {
         name: String,
@@ -245,15 +395,48 @@ pub enum Type {
     ArrayList(Box<Type>),
     FixedArray {
         element_type: Box<Type>,
-        size: usize,
+        size: ArraySize,
     },
     Function {
         param_types: Vec<Type>,
         return_type: Option<Box<Type>>,
     },
+    /// A constant argument for a const parameter, as the `16` in `buffer<16>`. Only appears among
+    /// the parameters of `Type::Generic`.
+    ConstValue(usize),
     Inferred, // For type inference (e.g., let x = 5;
 }
 
+/// The length of a `fixedArray`: a literal, the name of a const parameter of the enclosing
+/// declaration or of a `const`, or an arithmetic expression over those such as `2 * N`. Names and
+/// expressions are evaluated by validation, not by the parser.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ArraySize {
+    Literal(usize),
+    Parameter(String),
+    Expression(Box<Expression>),
+}
+
+impl fmt::Display for ArraySize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArraySize::Literal(size) => write!(f, "{}", size),
+            ArraySize::Parameter(name) => write!(f, "{}", name),
+            ArraySize::Expression(expression) => write!(f, "{}", expression.to_source()),
+        }
+    }
+}
+
+impl fmt::Display for TypeParameter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.const_type {
+            Some(const_type) => write!(f, "{}: {}", self.name, const_type),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
 impl fmt::Display for Type {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -272,6 +455,7 @@ impl fmt::Display for Type {
             Type::Bool => write!(f, "bool"),
             Type::Char => write!(f, "char"),
             Type::String => write!(f, "string"),
+            Type::Unit => write!(f, "unit"),
             Type::Named(name) => write!(f, "{}", name),
             Type::Generic { name, parameters } => {
                 let params: Vec<String> = parameters.iter().map(|p| p.to_string()).collect();
@@ -284,7 +468,7 @@ impl fmt::Display for Type {
                 if *is_mutable {
                     write!(f, "{}&@", ref_type)
                 } else {
-                    write!(f, "{}&@", ref_type)
+                    write!(f, "{}&", ref_type)
                 }
             }
             Type::ArrayList(element_type) => write!(f, "arrayList<{}>", element_type),
@@ -304,6 +488,7 @@ impl fmt::Display for Type {
                     write!(f, ")")
                 }
             }
+            Type::ConstValue(value) => write!(f, "{}", value),
             Type::Inferred => write!(f, "_"),
         }
     }
@@ -349,26 +534,93 @@ impl fmt::Display for UnaryOperator {
     }
 }
 
+impl FunctionDeclaration {
+    /// The type the function returns, which is `unit` when no return type is written.
+    pub fn result_type(&self) -> Type {
+        self.return_type.clone().unwrap_or(Type::Unit)
+    }
+}
+
 impl Expression {
     pub fn is_lvalue(&self) -> bool {
-        matches!(
-            self,
-            Expression::Identifier(_)
-                | Expression::RecordAccess { .. }
-                | Expression::IndexAccess { .. }
-                | Expression::Dereference(_)
-        )
+        match &self.kind {
+            ExpressionKind::Identifier(_)
+            | ExpressionKind::RecordAccess { .. }
+            | ExpressionKind::IndexAccess { .. }
+            | ExpressionKind::Dereference(_) => true,
+            ExpressionKind::Grouped(expression) => expression.is_lvalue(),
+            _ => false,
+        }
+    }
+
+    /// The expression inside any number of parentheses kept by the parser.
+    pub fn ungrouped(&self) -> &Expression {
+        match &self.kind {
+            ExpressionKind::Grouped(expression) => expression.ungrouped(),
+            _ => self,
+        }
     }
 
     pub fn requires_semicolon(&self) -> bool {
         !matches!(
-            self,
-            Expression::If { .. }
-                | Expression::When { .. }
-                | Expression::Block { .. }
-                | Expression::Loop { .. }
-                | Expression::While { .. }
-                | Expression::For { .. }
+            self.kind,
+            ExpressionKind::If { .. }
+                | ExpressionKind::When { .. }
+                | ExpressionKind::Block { .. }
+                | ExpressionKind::Loop { .. }
+                | ExpressionKind::While { .. }
+                | ExpressionKind::For { .. }
         )
     }
 }
+
+/// A node without an id yet, as built by hand. It gets one when it is
+/// [numbered](crate::node_id::NodeIds::number_program).
+macro_rules! from_kind {
+    ($($node:ident => $kind:ident,)*) => {$(
+        impl From<$kind> for $node {
+            fn from(kind: $kind) -> Self {
+                $node {
+                    id: NodeId::UNASSIGNED,
+                    kind,
+                }
+            }
+        }
+    )*};
+}
+
+from_kind! {
+    Statement => StatementKind,
+    Expression => ExpressionKind,
+    Pattern => PatternKind,
+}
+
+/// Nodes are equal when their syntax is, whatever ids they were given, so a parsed tree equals
+/// the same tree built by hand or parsed again.
+macro_rules! eq_ignoring_id {
+    ($($node:ident { $($field:ident),* },)*) => {$(
+        impl PartialEq for $node {
+            fn eq(&self, other: &Self) -> bool {
+                let $node { id: _, $($field),* } = self;
+                $(*$field == other.$field)&&*
+            }
+        }
+    )*};
+}
+
+eq_ignoring_id! {
+    FunctionDeclaration { name, is_public, type_parameters, params, return_type, body },
+    Parameter { name, param_type, is_mutable, is_ref },
+    RecordDeclaration { name, is_public, type_parameters, fields },
+    RecordField { name, is_public, field_type },
+    UnionDeclaration { name, is_public, type_parameters, variants },
+    TypeParameter { name, const_type },
+    UnionVariant { name, variant_type },
+    PatchDeclaration { target_type, methods },
+    ImportDeclaration { module, items },
+    ConstDeclaration { name, is_public, const_type, value },
+    WhenBranch { pattern, guard, body },
+    Statement { kind },
+    Expression { kind },
+    Pattern { kind },
+}
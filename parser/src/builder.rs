@@ -0,0 +1,369 @@
+//! A fluent API for building syntax trees in code.
+//!
+//! Desugaring passes, code generators, and tests build trees with [`AstBuilder`] instead of
+//! nesting struct literals and boxes by hand. Built trees are the same as parsed ones except that
+//! their nodes have no ids: [`AstBuilder::program`] numbers the nodes of the program it builds,
+//! and a pass that adds built nodes to a program numbers them with
+//! [`NodeIds::after`](crate::node_id::NodeIds::after) it.
+
+use crate::ast::{
+    BinaryOperator, Declaration, Expression, ExpressionKind, FunctionDeclaration, Literal,
+    Parameter, Program, Statement, StatementKind, Type, UnaryOperator,
+};
+use crate::node_id::{NodeId, NodeIds};
+
+/// Entry points for building expressions, statements, and declarations.
+pub struct AstBuilder;
+
+impl AstBuilder {
+    pub fn ident(name: impl Into<String>) -> ExpressionBuilder {
+        ExpressionBuilder::of(ExpressionKind::Identifier(name.into()))
+    }
+
+    pub fn literal(literal: Literal) -> ExpressionBuilder {
+        ExpressionBuilder::of(ExpressionKind::Literal(literal))
+    }
+
+    pub fn int(value: i128) -> ExpressionBuilder {
+        Self::literal(Literal::Integer(value))
+    }
+
+    pub fn float(value: f64) -> ExpressionBuilder {
+        Self::literal(Literal::Float(value))
+    }
+
+    pub fn bool(value: bool) -> ExpressionBuilder {
+        Self::literal(Literal::Boolean(value))
+    }
+
+    pub fn string(value: impl Into<String>) -> ExpressionBuilder {
+        Self::literal(Literal::String(value.into()))
+    }
+
+    pub fn unit() -> ExpressionBuilder {
+        Self::literal(Literal::Unit)
+    }
+
+    /// A call of the function named `function`.
+    pub fn call<A: Into<Expression>>(
+        function: impl Into<String>,
+        arguments: impl IntoIterator<Item = A>,
+    ) -> ExpressionBuilder {
+        Self::ident(function).call(arguments)
+    }
+
+    pub fn not(operand: impl Into<Expression>) -> ExpressionBuilder {
+        ExpressionBuilder::of(ExpressionKind::UnaryOperation {
+            operator: UnaryOperator::Not,
+            operand: Box::new(operand.into()),
+        })
+    }
+
+    pub fn negate(operand: impl Into<Expression>) -> ExpressionBuilder {
+        ExpressionBuilder::of(ExpressionKind::UnaryOperation {
+            operator: UnaryOperator::Negate,
+            operand: Box::new(operand.into()),
+        })
+    }
+
+    pub fn array<E: Into<Expression>>(elements: impl IntoIterator<Item = E>) -> ExpressionBuilder {
+        ExpressionBuilder::of(ExpressionKind::ArrayLiteral(
+            elements.into_iter().map(Into::into).collect(),
+        ))
+    }
+
+    pub fn block() -> BlockBuilder {
+        BlockBuilder::default()
+    }
+
+    pub fn if_then(
+        condition: impl Into<Expression>,
+        then_branch: impl Into<Expression>,
+    ) -> ExpressionBuilder {
+        ExpressionBuilder::of(ExpressionKind::If {
+            condition: Box::new(condition.into()),
+            then_branch: Box::new(then_branch.into()),
+            else_branch: None,
+        })
+    }
+
+    /// `name = value;`
+    pub fn declare(name: impl Into<String>, value: impl Into<Expression>) -> Statement {
+        StatementKind::VariableDeclaration {
+            name: name.into(),
+            var_type: None,
+            is_mutable: false,
+            value: Box::new(value.into()),
+        }
+        .into()
+    }
+
+    /// `@name = value;`
+    pub fn declare_mut(name: impl Into<String>, value: impl Into<Expression>) -> Statement {
+        StatementKind::VariableDeclaration {
+            name: name.into(),
+            var_type: None,
+            is_mutable: true,
+            value: Box::new(value.into()),
+        }
+        .into()
+    }
+
+    /// `target = value;`, or a compound assignment when `operator` is one.
+    pub fn assign(
+        target: impl Into<Expression>,
+        operator: BinaryOperator,
+        value: impl Into<Expression>,
+    ) -> Statement {
+        StatementKind::Assignment {
+            target: Box::new(target.into()),
+            operator,
+            value: Box::new(value.into()),
+        }
+        .into()
+    }
+
+    pub fn expression(expression: impl Into<Expression>) -> Statement {
+        StatementKind::Expression(Box::new(expression.into())).into()
+    }
+
+    pub fn ret(value: impl Into<Expression>) -> Statement {
+        StatementKind::Return(Some(Box::new(value.into()))).into()
+    }
+
+    pub fn function(name: impl Into<String>) -> FunctionBuilder {
+        FunctionBuilder(FunctionDeclaration {
+            id: NodeId::UNASSIGNED,
+            name: name.into(),
+            is_public: false,
+            type_parameters: Vec::new(),
+            params: Vec::new(),
+            return_type: None,
+            body: Box::new(
+                ExpressionKind::Block {
+                    statements: Vec::new(),
+                    final_expression: None,
+                }
+                .into(),
+            ),
+        })
+    }
+
+    /// A program of `declarations`, with their nodes numbered.
+    pub fn program<D: Into<Declaration>>(declarations: impl IntoIterator<Item = D>) -> Program {
+        let mut program = Program {
+            declarations: declarations.into_iter().map(Into::into).collect(),
+        };
+        NodeIds::new().number_program(&mut program);
+        program
+    }
+}
+
+/// An expression under construction. Methods wrap it in a larger expression, so
+/// `AstBuilder::ident("p").field("x").binary(BinaryOperator::Add, AstBuilder::int(1))` builds
+/// `p.x + 1`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpressionBuilder(Expression);
+
+impl ExpressionBuilder {
+    fn of(kind: ExpressionKind) -> Self {
+        ExpressionBuilder(kind.into())
+    }
+
+    pub fn binary(self, operator: BinaryOperator, right: impl Into<Expression>) -> Self {
+        ExpressionBuilder::of(ExpressionKind::BinaryOperation {
+            left: Box::new(self.0),
+            operator,
+            right: Box::new(right.into()),
+        })
+    }
+
+    pub fn call<A: Into<Expression>>(self, arguments: impl IntoIterator<Item = A>) -> Self {
+        ExpressionBuilder::of(ExpressionKind::FunctionCall {
+            function: Box::new(self.0),
+            arguments: arguments.into_iter().map(Into::into).collect(),
+        })
+    }
+
+    pub fn method<A: Into<Expression>>(
+        self,
+        method: impl Into<String>,
+        arguments: impl IntoIterator<Item = A>,
+    ) -> Self {
+        ExpressionBuilder::of(ExpressionKind::MethodCall {
+            receiver: Box::new(self.0),
+            method: method.into(),
+            arguments: arguments.into_iter().map(Into::into).collect(),
+        })
+    }
+
+    pub fn field(self, field: impl Into<String>) -> Self {
+        ExpressionBuilder::of(ExpressionKind::RecordAccess {
+            record: Box::new(self.0),
+            field: field.into(),
+        })
+    }
+
+    pub fn index(self, index: impl Into<Expression>) -> Self {
+        ExpressionBuilder::of(ExpressionKind::IndexAccess {
+            collection: Box::new(self.0),
+            index: Box::new(index.into()),
+        })
+    }
+
+    pub fn build(self) -> Expression {
+        self.0
+    }
+}
+
+impl From<ExpressionBuilder> for Expression {
+    fn from(builder: ExpressionBuilder) -> Self {
+        builder.0
+    }
+}
+
+/// A `{ ... }` block under construction.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BlockBuilder {
+    statements: Vec<Statement>,
+    final_expression: Option<Box<Expression>>,
+}
+
+impl BlockBuilder {
+    pub fn statement(mut self, statement: Statement) -> Self {
+        self.statements.push(statement);
+        self
+    }
+
+    /// The expression the block ends with and evaluates to.
+    pub fn value(mut self, value: impl Into<Expression>) -> Self {
+        self.final_expression = Some(Box::new(value.into()));
+        self
+    }
+
+    pub fn build(self) -> Expression {
+        ExpressionKind::Block {
+            statements: self.statements,
+            final_expression: self.final_expression,
+        }
+        .into()
+    }
+}
+
+impl From<BlockBuilder> for Expression {
+    fn from(builder: BlockBuilder) -> Self {
+        builder.build()
+    }
+}
+
+/// A function declaration under construction, with an empty body until one is given.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionBuilder(FunctionDeclaration);
+
+impl FunctionBuilder {
+    pub fn param(mut self, param_type: Type, name: impl Into<String>) -> Self {
+        self.0.params.push(Parameter {
+            id: NodeId::UNASSIGNED,
+            name: name.into(),
+            param_type,
+            is_mutable: false,
+            is_ref: false,
+        });
+        self
+    }
+
+    pub fn returns(mut self, return_type: Type) -> Self {
+        self.0.return_type = Some(return_type);
+        self
+    }
+
+    /// Declare the function `pub`.
+    pub fn public(mut self) -> Self {
+        self.0.is_public = true;
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Expression>) -> Self {
+        self.0.body = Box::new(body.into());
+        self
+    }
+
+    pub fn build(self) -> FunctionDeclaration {
+        self.0
+    }
+}
+
+impl From<FunctionBuilder> for Declaration {
+    fn from(builder: FunctionBuilder) -> Self {
+        Declaration::Function(builder.0)
+    }
+}
+
+impl From<Statement> for Declaration {
+    fn from(statement: Statement) -> Self {
+        Declaration::Statement(statement)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AstBuilder as b;
+    use crate::ast::{BinaryOperator, Declaration, Type};
+    use crate::node_id::NodeIndex;
+    use crate::parse;
+
+    #[test]
+    fn test_built_program_matches_parsed() {
+        let built = b::program::<Declaration>([
+            b::function("sum")
+                .param(Type::I32, "a")
+                .param(Type::I32, "b")
+                .returns(Type::I32)
+                .body(
+                    b::block()
+                        .statement(b::declare_mut("total", b::ident("a")))
+                        .statement(b::assign(
+                            b::ident("total"),
+                            BinaryOperator::AddAssign,
+                            b::ident("b"),
+                        ))
+                        .value(b::ident("total")),
+                )
+                .into(),
+            b::declare(
+                "x",
+                b::call(
+                    "sum",
+                    [
+                        b::int(1),
+                        b::int(2).binary(BinaryOperator::Multiply, b::int(3)),
+                    ],
+                ),
+            )
+            .into(),
+            b::expression(
+                b::ident("xs")
+                    .index(b::int(0))
+                    .method("show", [b::ident("x")]),
+            )
+            .into(),
+        ]);
+
+        let parsed = parse(
+            "fn sum(i32 a, i32 b) -> i32 { @total = a; total += b; total } \
+             x = sum(1, 2 * 3); \
+             xs[0].show(x);",
+        )
+        .expect("Parse error");
+        assert_eq!(built, parsed);
+    }
+
+    #[test]
+    fn test_built_program_gets_node_ids() {
+        let program = b::program([b::declare("y", b::not(b::bool(true)))]);
+        let index = NodeIndex::new(&program);
+
+        assert_eq!(index.len(), 3);
+        assert!(index.id(&program.declarations[0]).is_some());
+    }
+}
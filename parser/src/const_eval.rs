@@ -0,0 +1,500 @@
+//! Compile-time evaluation of constant expressions: `const` initializers, `fixedArray` sizes,
+//! and anything else the compiler needs the value of before the program runs.
+//!
+//! A constant expression is built from literals, operators, casts, arrays, ranges, and the
+//! names of `const`s, which may be declared in any order. Each `const` is evaluated once, when
+//! it is first needed, and a `const` that needs its own value, directly or through others, is
+//! reported as a cycle instead of being evaluated forever.
+//!
+//! As at run time, integer arithmetic overflows when its result does not fit in the type of the
+//! operation, which for a `const` is its declared type: `const u8 x = 200 + 100;` overflows.
+
+use crate::ast::{
+    BinaryOperator, Declaration, Expression, ExpressionKind, Literal, Program, Type, UnaryOperator,
+};
+use crate::operators::{ArithmeticError, integer_arithmetic, integer_bounds, integer_cast};
+use std::collections::HashMap;
+use std::fmt;
+use thiserror::Error;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConstValue {
+    Integer(i128),
+    Float(f64),
+    Boolean(bool),
+    Char(char),
+    String(String),
+    Unit,
+    Array(Vec<ConstValue>),
+    Range {
+        start: Box<ConstValue>,
+        end: Box<ConstValue>,
+        inclusive: bool,
+    },
+}
+
+impl ConstValue {
+    /// The kind of value, for error messages.
+    fn kind(&self) -> &'static str {
+        match self {
+            ConstValue::Integer(_) => "integer",
+            ConstValue::Float(_) => "float",
+            ConstValue::Boolean(_) => "bool",
+            ConstValue::Char(_) => "char",
+            ConstValue::String(_) => "string",
+            ConstValue::Unit => "unit",
+            ConstValue::Array(_) => "array",
+            ConstValue::Range { .. } => "range",
+        }
+    }
+}
+
+impl fmt::Display for ConstValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConstValue::Integer(value) => write!(f, "{}", value),
+            ConstValue::Float(value) => write!(f, "{:?}", value),
+            ConstValue::Boolean(value) => write!(f, "{}", value),
+            ConstValue::Char(value) => write!(f, "{:?}", value),
+            ConstValue::String(value) => write!(f, "{:?}", value),
+            ConstValue::Unit => write!(f, "()"),
+            ConstValue::Array(elements) => {
+                let elements: Vec<String> = elements.iter().map(ToString::to_string).collect();
+                write!(f, "[{}]", elements.join(", "))
+            }
+            ConstValue::Range {
+                start,
+                end,
+                inclusive,
+            } => write!(
+                f,
+                "{}{}{}",
+                start,
+                if *inclusive { "..=" } else { ".." },
+                end
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Clone)]
+pub enum ConstError {
+    /// `expression` is the part of the expression that cannot be evaluated, as source.
+    #[error("'{expression}' cannot be evaluated at compile time")]
+    NotConstant { expression: String },
+    #[error("'{name}' is not a const")]
+    UnknownName { name: String },
+    /// The cycle starts and ends with the same `const`.
+    #[error("Consts depend on each other in a cycle: {}", cycle.join(" -> "))]
+    Cycle { cycle: Vec<String> },
+    /// A `const` the expression uses has no value because of an error of its own.
+    #[error("'{name}' has no value")]
+    InvalidDependency { name: String },
+    #[error("Operator '{operator}' cannot be applied to {left} and {right}")]
+    InvalidOperands {
+        operator: BinaryOperator,
+        left: &'static str,
+        right: &'static str,
+    },
+    #[error("Operator '{operator}' cannot be applied to {operand}")]
+    InvalidOperand {
+        operator: UnaryOperator,
+        operand: &'static str,
+    },
+    #[error("Cannot cast {from} to '{to}'")]
+    InvalidCast { from: &'static str, to: Type },
+    #[error("{0}")]
+    Arithmetic(#[from] ArithmeticError),
+}
+
+/// Evaluates constant expressions over the `const`s of a program, remembering the value of each
+/// `const` once it has been needed.
+pub struct ConstEvaluator<'p> {
+    /// The declared type and initializer of each `const`.
+    initializers: HashMap<&'p str, (&'p Type, &'p Expression)>,
+    values: HashMap<&'p str, Result<ConstValue, ConstError>>,
+    /// The consts being evaluated, each needed by the one before it.
+    evaluating: Vec<&'p str>,
+}
+
+impl<'p> ConstEvaluator<'p> {
+    pub fn new(program: &'p Program) -> Self {
+        ConstEvaluator::of(&program.declarations)
+    }
+
+    /// An evaluator over the `const`s among `declarations`, which may come from several
+    /// programs. A later `const` replaces an earlier one of the same name.
+    pub fn of(declarations: impl IntoIterator<Item = &'p Declaration>) -> Self {
+        let initializers = declarations
+            .into_iter()
+            .filter_map(|declaration| match declaration {
+                Declaration::Const(constant) => Some((
+                    constant.name.as_str(),
+                    (&constant.const_type, constant.value.as_ref()),
+                )),
+                _ => None,
+            })
+            .collect();
+        ConstEvaluator {
+            initializers,
+            values: HashMap::new(),
+            evaluating: Vec::new(),
+        }
+    }
+
+    /// Whether the program declares a `const` named `name`.
+    pub fn is_const(&self, name: &str) -> bool {
+        self.initializers.contains_key(name)
+    }
+
+    /// The value of the `const` named `name`.
+    ///
+    /// Every `const` on a cycle, and every `const` that uses one, gets the same
+    /// [`ConstError::Cycle`], which starts at whichever `const` on it was evaluated first. A
+    /// `const` that uses another with an error of any other kind gets a
+    /// [`ConstError::InvalidDependency`] instead of a copy of that error.
+    pub fn constant(&mut self, name: &str) -> Result<ConstValue, ConstError> {
+        if let Some(value) = self.values.get(name) {
+            return value.clone();
+        }
+        let Some((&name, &(const_type, initializer))) = self.initializers.get_key_value(name)
+        else {
+            return Err(ConstError::UnknownName {
+                name: name.to_string(),
+            });
+        };
+        if let Some(start) = self.evaluating.iter().position(|open| *open == name) {
+            let cycle = self.evaluating[start..]
+                .iter()
+                .chain([&name])
+                .map(|open| open.to_string())
+                .collect();
+            return Err(ConstError::Cycle { cycle });
+        }
+
+        self.evaluating.push(name);
+        let value = self.evaluate_as(initializer, Some(const_type));
+        self.evaluating.pop();
+        self.values.insert(name, value.clone());
+        value
+    }
+
+    /// The value of `expression`, which may use any `const` of the program.
+    pub fn evaluate(&mut self, expression: &Expression) -> Result<ConstValue, ConstError> {
+        self.evaluate_as(expression, None)
+    }
+
+    /// The value of `expression` as a value of type `ty`, if that is known, which integer
+    /// arithmetic must fit in. The operands of arithmetic, the elements of an array, and the
+    /// bounds of a range take their type from `ty`; those of a comparison or cast have their own.
+    fn evaluate_as(
+        &mut self,
+        expression: &Expression,
+        ty: Option<&Type>,
+    ) -> Result<ConstValue, ConstError> {
+        match &expression.kind {
+            ExpressionKind::Literal(literal) => Ok(literal_value(literal)),
+            ExpressionKind::Identifier(name) => match self.constant(name) {
+                Err(ConstError::UnknownName { .. }) => Err(ConstError::NotConstant {
+                    expression: name.clone(),
+                }),
+                Err(error @ ConstError::Cycle { .. }) => Err(error),
+                Err(_) => Err(ConstError::InvalidDependency { name: name.clone() }),
+                value => value,
+            },
+            ExpressionKind::UnaryOperation { operator, operand } => {
+                let value = unary(*operator, self.evaluate_as(operand, ty)?)?;
+                // A negated literal out of range is reported by the type checker instead.
+                match &operand.ungrouped().kind {
+                    ExpressionKind::Literal(_) => Ok(value),
+                    _ => fit(value, ty),
+                }
+            }
+            ExpressionKind::BinaryOperation {
+                left,
+                operator,
+                right,
+            } => {
+                let operand_type = ty.filter(|_| {
+                    matches!(
+                        operator,
+                        BinaryOperator::Add
+                            | BinaryOperator::Subtract
+                            | BinaryOperator::Multiply
+                            | BinaryOperator::Divide
+                            | BinaryOperator::Modulus
+                    )
+                });
+                let left = self.evaluate_as(left, operand_type)?;
+                // `and` and `or` only evaluate their right side when it decides the result.
+                match (operator, &left) {
+                    (BinaryOperator::And, ConstValue::Boolean(false)) => return Ok(left),
+                    (BinaryOperator::Or, ConstValue::Boolean(true)) => return Ok(left),
+                    _ => {}
+                }
+                let right = self.evaluate_as(right, operand_type)?;
+                fit(binary(*operator, left, right)?, operand_type)
+            }
+            ExpressionKind::ArrayLiteral(elements) => {
+                let element_type = match ty {
+                    Some(
+                        Type::ArrayList(element)
+                        | Type::FixedArray {
+                            element_type: element,
+                            ..
+                        },
+                    ) => Some(element.as_ref()),
+                    _ => None,
+                };
+                elements
+                    .iter()
+                    .map(|element| self.evaluate_as(element, element_type))
+                    .collect::<Result<_, _>>()
+                    .map(ConstValue::Array)
+            }
+            ExpressionKind::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                let bound_type = match ty {
+                    Some(Type::Generic { name, parameters }) if name == "range" => {
+                        parameters.first()
+                    }
+                    _ => None,
+                };
+                Ok(ConstValue::Range {
+                    start: Box::new(self.evaluate_as(start, bound_type)?),
+                    end: Box::new(self.evaluate_as(end, bound_type)?),
+                    inclusive: *inclusive,
+                })
+            }
+            ExpressionKind::Cast { expression, target } => cast(self.evaluate(expression)?, target),
+            ExpressionKind::TypeAnnotation {
+                expression,
+                annotated_type,
+            } => self.evaluate_as(expression, Some(annotated_type)),
+            ExpressionKind::Grouped(expression) => self.evaluate_as(expression, ty),
+            _ => Err(ConstError::NotConstant {
+                expression: expression.to_source(),
+            }),
+        }
+    }
+}
+
+/// `value`, or an overflow if it is an integer that does not fit in the integer type `ty`.
+fn fit(value: ConstValue, ty: Option<&Type>) -> Result<ConstValue, ConstError> {
+    match (&value, ty.and_then(integer_bounds)) {
+        (ConstValue::Integer(integer), Some((min, max))) if !(min..=max).contains(integer) => {
+            Err(ConstError::Arithmetic(ArithmeticError::Overflow))
+        }
+        _ => Ok(value),
+    }
+}
+
+fn literal_value(literal: &Literal) -> ConstValue {
+    match literal {
+        Literal::Integer(value) => ConstValue::Integer(*value),
+        Literal::Float(value) => ConstValue::Float(*value),
+        Literal::Boolean(value) => ConstValue::Boolean(*value),
+        Literal::String(value) => ConstValue::String(value.clone()),
+        Literal::Char(value) => ConstValue::Char(*value),
+        Literal::Bytes(bytes) => ConstValue::Array(
+            bytes
+                .iter()
+                .map(|byte| ConstValue::Integer(i128::from(*byte)))
+                .collect(),
+        ),
+        Literal::Unit => ConstValue::Unit,
+    }
+}
+
+fn unary(operator: UnaryOperator, operand: ConstValue) -> Result<ConstValue, ConstError> {
+    match (operator, operand) {
+        (UnaryOperator::Negate, ConstValue::Integer(value)) => value
+            .checked_neg()
+            .map(ConstValue::Integer)
+            .ok_or(ConstError::Arithmetic(ArithmeticError::Overflow)),
+        (UnaryOperator::Negate, ConstValue::Float(value)) => Ok(ConstValue::Float(-value)),
+        (UnaryOperator::Not, ConstValue::Boolean(value)) => Ok(ConstValue::Boolean(!value)),
+        (operator, operand) => Err(ConstError::InvalidOperand {
+            operator,
+            operand: operand.kind(),
+        }),
+    }
+}
+
+fn binary(
+    operator: BinaryOperator,
+    left: ConstValue,
+    right: ConstValue,
+) -> Result<ConstValue, ConstError> {
+    use ConstValue::{Boolean, Char, Float, Integer, String};
+    let invalid = |left: &ConstValue, right: &ConstValue| ConstError::InvalidOperands {
+        operator,
+        left: left.kind(),
+        right: right.kind(),
+    };
+    if left.kind() != right.kind() {
+        return Err(invalid(&left, &right));
+    }
+    let value = match operator {
+        BinaryOperator::Equal => Boolean(left == right),
+        BinaryOperator::NotEqual => Boolean(left != right),
+        BinaryOperator::LessThan
+        | BinaryOperator::LessThanOrEqual
+        | BinaryOperator::GreaterThan
+        | BinaryOperator::GreaterThanOrEqual => {
+            let ordering = match (&left, &right) {
+                (Integer(left), Integer(right)) => left.partial_cmp(right),
+                (Float(left), Float(right)) => left.partial_cmp(right),
+                (Char(left), Char(right)) => left.partial_cmp(right),
+                (String(left), String(right)) => left.partial_cmp(right),
+                _ => return Err(invalid(&left, &right)),
+            };
+            Boolean(ordering.is_some_and(|ordering| match operator {
+                BinaryOperator::LessThan => ordering.is_lt(),
+                BinaryOperator::LessThanOrEqual => ordering.is_le(),
+                BinaryOperator::GreaterThan => ordering.is_gt(),
+                _ => ordering.is_ge(),
+            }))
+        }
+        BinaryOperator::And | BinaryOperator::Or => match right {
+            Boolean(_) => right,
+            _ => return Err(invalid(&left, &right)),
+        },
+        _ => match (&left, &right) {
+            (Integer(left_value), Integer(right_value)) => {
+                match integer_arithmetic(operator, *left_value, *right_value) {
+                    Some(result) => Integer(result?),
+                    None => return Err(invalid(&left, &right)),
+                }
+            }
+            (Float(left), Float(right)) => Float(match operator {
+                BinaryOperator::Add => left + right,
+                BinaryOperator::Subtract => left - right,
+                BinaryOperator::Multiply => left * right,
+                BinaryOperator::Divide => left / right,
+                BinaryOperator::Modulus => left % right,
+                _ => return Err(invalid(&Float(*left), &Float(*right))),
+            }),
+            (String(left), String(right)) if operator == BinaryOperator::Add => {
+                String(format!("{}{}", left, right))
+            }
+            _ => return Err(invalid(&left, &right)),
+        },
+    };
+    Ok(value)
+}
+
+/// Convert a number the way a cast does at run time: an integer cast to a narrower integer type
+/// keeps its low bits, and a float cast to an integer type is truncated toward zero.
+fn cast(value: ConstValue, target: &Type) -> Result<ConstValue, ConstError> {
+    let integer = match (&value, target) {
+        (ConstValue::Integer(value), Type::F32) => {
+            return Ok(ConstValue::Float(*value as f32 as f64));
+        }
+        (ConstValue::Integer(value), Type::F64) => return Ok(ConstValue::Float(*value as f64)),
+        (ConstValue::Float(value), Type::F32) => {
+            return Ok(ConstValue::Float(*value as f32 as f64));
+        }
+        (ConstValue::Float(value), Type::F64) => return Ok(ConstValue::Float(*value)),
+        (ConstValue::Integer(value), _) => *value,
+        (ConstValue::Float(value), _) => *value as i128,
+        _ => {
+            return Err(ConstError::InvalidCast {
+                from: value.kind(),
+                to: target.clone(),
+            });
+        }
+    };
+    let converted = integer_cast(integer, target).ok_or_else(|| ConstError::InvalidCast {
+        from: value.kind(),
+        to: target.clone(),
+    })?;
+    Ok(ConstValue::Integer(converted))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+    use test_case::test_case;
+
+    /// The value of the last `const` in `source`.
+    fn last_value(source: &str) -> Result<ConstValue, ConstError> {
+        let program = parse(source).expect("Parse error");
+        let mut evaluator = ConstEvaluator::new(&program);
+        let Some(Declaration::Const(last)) = program.declarations.last() else {
+            panic!("expected a const");
+        };
+        evaluator.constant(&last.name)
+    }
+
+    #[test_case("const i32 x = 2 + 3 * 4;", "14" ; "arithmetic")]
+    #[test_case("const i32 x = -7 / 2 + -7 % 2;", "-4" ; "integer division")]
+    #[test_case("const f64 x = 1.5 * 2.0;", "3.0" ; "float arithmetic")]
+    #[test_case("const bool x = 1 < 2 and not (3 == 4);", "true" ; "comparisons")]
+    #[test_case("const bool x = false and 1 / 0 == 1;", "false" ; "short circuit")]
+    #[test_case("const string x = \"a\" + \"b\";", "\"ab\"" ; "concatenation")]
+    #[test_case("const u32 a = 3; const u32 x = a * a;", "9" ; "other const")]
+    #[test_case("const u32 a = b + 1; const u32 b = 1; const u32 x = a;", "2" ; "const declared later")]
+    #[test_case("const arrayList<i32> x = [1, -2, 3];", "[1, -2, 3]" ; "array")]
+    #[test_case("const range<i32> x = 0..=9;", "0..=9" ; "range")]
+    #[test_case("const u8 x = 300 as u8;", "44" ; "narrowing cast")]
+    #[test_case("const i32 x = -2.7 as i32;", "-2" ; "float to integer")]
+    #[test_case("const f64 x = 3 as f64 / 2.0;", "1.5" ; "integer to float")]
+    #[test_case("const u8 x = (200 + 100) as u8;", "44" ; "cast operand has its own type")]
+    #[test_case("const bool x = 200 + 100 > 255;", "true" ; "comparison operands have their own type")]
+    #[test_case("const u64 x = 9223372036854775807 + 1;", "9223372036854775808" ; "beyond i64")]
+    fn test_value(source: &str, expected: &str) {
+        assert_eq!(last_value(source).unwrap().to_string(), expected);
+    }
+
+    #[test_case("const i32 x = 1 / 0;", "Division by zero" ; "division by zero")]
+    #[test_case("const u64 x = 18446744073709551615 + 1;", "Integer overflow" ; "overflow")]
+    #[test_case("const u8 x = 200 + 100;", "Integer overflow" ; "overflow of the declared type")]
+    #[test_case("const i8 a = 100; const i8 x = a + a;", "Integer overflow" ; "overflow of consts")]
+    #[test_case("const i8 a = -128; const i8 x = -a;", "Integer overflow" ; "negation overflow")]
+    #[test_case("const u32 a = 1; const u32 x = a - 2;", "Integer overflow" ; "unsigned below zero")]
+    #[test_case("const arrayList<u8> x = [1, 255 + 1];", "Integer overflow" ; "array element")]
+    #[test_case("const i32 x = 1 + 1.0;", "Operator '+' cannot be applied to integer and float" ; "mixed operands")]
+    #[test_case("const bool x = not 1;", "Operator '!' cannot be applied to integer" ; "invalid operand")]
+    #[test_case("const i32 x = true as i32;", "Cannot cast bool to 'i32'" ; "invalid cast")]
+    #[test_case("const i32 x = f(1);", "'f(1)' cannot be evaluated at compile time" ; "call")]
+    #[test_case("const i32 x = y;", "'y' cannot be evaluated at compile time" ; "variable")]
+    #[test_case("const i32 a = 1 / 0; const i32 x = a + 1;", "'a' has no value" ; "invalid dependency")]
+    #[test_case("const i32 x = x + 1;", "Consts depend on each other in a cycle: x -> x" ; "own value")]
+    #[test_case(
+        "const i32 a = b; const i32 b = c * 2; const i32 c = a + 1; const i32 x = a;",
+        "Consts depend on each other in a cycle: a -> b -> c -> a" ;
+        "longer cycle"
+    )]
+    fn test_error(source: &str, expected: &str) {
+        assert_eq!(last_value(source).unwrap_err().to_string(), expected);
+    }
+
+    #[test]
+    fn test_every_const_on_a_cycle_gets_the_same_error() {
+        let program = parse("const i32 a = b; const i32 b = a;").unwrap();
+        let mut evaluator = ConstEvaluator::new(&program);
+        let cycle = ConstError::Cycle {
+            cycle: vec!["a".to_string(), "b".to_string(), "a".to_string()],
+        };
+
+        assert_eq!(evaluator.constant("a"), Err(cycle.clone()));
+        assert_eq!(evaluator.constant("b"), Err(cycle));
+    }
+
+    #[test]
+    fn test_expression_over_consts() {
+        let program = parse("const usize header = 8;").unwrap();
+        let mut evaluator = ConstEvaluator::new(&program);
+        let tokens = lexer::Lexer::new("header * 2 + 1").tokenize().unwrap();
+        let expression = crate::Parser::new(tokens).parse_expression().unwrap();
+
+        assert_eq!(evaluator.evaluate(&expression), Ok(ConstValue::Integer(17)));
+        assert!(evaluator.is_const("header"));
+        assert!(!evaluator.is_const("footer"));
+    }
+}
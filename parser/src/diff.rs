@@ -0,0 +1,251 @@
+//! Structural comparison of two versions of a program.
+//!
+//! Declarations are matched by what they declare (`fn main`, `record point`, `patch point`)
+//! rather than by position, so reordering them is not a change. Within a declaration whose
+//! signature is unchanged, the comparison descends into its expressions and reports the smallest
+//! ones that differ, which is what incremental checking needs to re-check and what a failing
+//! test should point at. The tree has no spans, so moving code without changing it is never
+//! reported.
+
+use crate::ast::{
+    Declaration, Expression, ExpressionKind, Literal, Program, Statement, StatementKind,
+};
+use crate::visit_mut::{MutVisitor, walk_declaration, walk_expression};
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AstChange {
+    /// A declaration only the new program has.
+    Added(Declaration),
+    /// A declaration only the old program has.
+    Removed(Declaration),
+    /// A declaration whose signature changed: its parameters, fields, variants, or types.
+    Modified { old: Declaration, new: Declaration },
+    /// An expression that changed inside a declaration whose signature did not.
+    ExpressionChanged {
+        declaration: String,
+        old: Expression,
+        new: Expression,
+    },
+}
+
+impl fmt::Display for AstChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AstChange::Added(declaration) => write!(f, "added {}", key(declaration)),
+            AstChange::Removed(declaration) => write!(f, "removed {}", key(declaration)),
+            AstChange::Modified { new, .. } => write!(f, "changed the signature of {}", key(new)),
+            AstChange::ExpressionChanged {
+                declaration,
+                old,
+                new,
+            } => write!(
+                f,
+                "changed `{}` to `{}` in {}",
+                old.to_source(),
+                new.to_source(),
+                declaration
+            ),
+        }
+    }
+}
+
+/// The changes that turn `old` into `new`: removed declarations in their old order, then added
+/// and changed ones in their new order.
+pub fn diff(old: &Program, new: &Program) -> Vec<AstChange> {
+    let old_declarations = keyed(old);
+    let new_declarations = keyed(new);
+    let mut changes = Vec::new();
+
+    for (key, declaration) in &old_declarations {
+        if !new_declarations.iter().any(|(new_key, _)| new_key == key) {
+            changes.push(AstChange::Removed((*declaration).clone()));
+        }
+    }
+
+    let old_by_key: HashMap<_, _> = old_declarations.into_iter().collect();
+    for (key, new_declaration) in new_declarations {
+        match old_by_key.get(&key) {
+            None => changes.push(AstChange::Added(new_declaration.clone())),
+            Some(old_declaration) => {
+                diff_declaration(old_declaration, new_declaration, &key.0, &mut changes)
+            }
+        }
+    }
+    changes
+}
+
+fn diff_declaration(
+    old: &Declaration,
+    new: &Declaration,
+    name: &str,
+    changes: &mut Vec<AstChange>,
+) {
+    if old == new {
+        return;
+    }
+    let (old_shell, old_children) = split(old.clone(), walk_declaration);
+    let (new_shell, new_children) = split(new.clone(), walk_declaration);
+    if old_shell != new_shell || old_children.len() != new_children.len() {
+        changes.push(AstChange::Modified {
+            old: old.clone(),
+            new: new.clone(),
+        });
+        return;
+    }
+    for (old, new) in old_children.iter().zip(&new_children) {
+        diff_expression(old, new, name, changes);
+    }
+}
+
+/// Report the smallest subexpressions of `old` and `new` that differ. Two expressions with the
+/// same shape, such as two calls of the same function with the same number of arguments, are
+/// compared child by child; any other difference is reported for the whole expression.
+fn diff_expression(old: &Expression, new: &Expression, name: &str, changes: &mut Vec<AstChange>) {
+    if old == new {
+        return;
+    }
+    let (old_shell, old_children) = split(old.clone(), walk_expression);
+    let (new_shell, new_children) = split(new.clone(), walk_expression);
+    if old_shell != new_shell || old_children.len() != new_children.len() {
+        changes.push(AstChange::ExpressionChanged {
+            declaration: name.to_string(),
+            old: old.clone(),
+            new: new.clone(),
+        });
+        return;
+    }
+    for (old, new) in old_children.iter().zip(&new_children) {
+        diff_expression(old, new, name, changes);
+    }
+}
+
+/// Detach the outermost expressions below `node`, leaving `()` in their place.
+fn split<T>(mut node: T, walk: fn(&mut TakeChildren, &mut T)) -> (T, Vec<Expression>) {
+    let mut children = TakeChildren(Vec::new());
+    walk(&mut children, &mut node);
+    (node, children.0)
+}
+
+struct TakeChildren(Vec<Expression>);
+
+impl MutVisitor for TakeChildren {
+    fn visit_expression(&mut self, expression: &mut Expression) {
+        let child = std::mem::replace(expression, ExpressionKind::Literal(Literal::Unit).into());
+        self.0.push(child);
+    }
+}
+
+/// Each declaration with the name it is matched by, and how many declarations before it share
+/// that name.
+fn keyed(program: &Program) -> Vec<((String, usize), &Declaration)> {
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    program
+        .declarations
+        .iter()
+        .map(|declaration| {
+            let name = key(declaration);
+            let occurrence = seen.entry(name.clone()).or_default();
+            let key = (name, *occurrence);
+            *occurrence += 1;
+            (key, declaration)
+        })
+        .collect()
+}
+
+fn key(declaration: &Declaration) -> String {
+    match declaration {
+        Declaration::Function(function) => format!("fn {}", function.name),
+        Declaration::Record(record) => format!("record {}", record.name),
+        Declaration::Union(union) => format!("union {}", union.name),
+        Declaration::Patch(patch) => format!("patch {}", patch.target_type),
+        Declaration::Const(constant) => format!("const {}", constant.name),
+        Declaration::Import(import) => format!("import {}", import.module),
+        Declaration::Statement(Statement {
+            kind: StatementKind::VariableDeclaration { name, .. },
+            ..
+        }) => name.clone(),
+        Declaration::Statement(_) => "top-level statement".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+    use test_case::test_case;
+
+    fn changes(old: &str, new: &str) -> Vec<String> {
+        let old = parse(old).expect("Parse error");
+        let new = parse(new).expect("Parse error");
+        diff(&old, &new).iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_unchanged_program() {
+        let source = "record point { x: i32; } fn main() { print(1); }";
+        assert!(changes(source, source).is_empty());
+    }
+
+    #[test]
+    fn test_reordering_is_not_a_change() {
+        assert!(changes("fn a() {} fn b() {}", "fn b() {} fn a() {}").is_empty());
+    }
+
+    #[test_case("fn a() {}", "fn a() {} fn b() {}", &["added fn b"] ; "added")]
+    #[test_case("fn a() {} x = 1;", "fn a() {}", &["removed x"] ; "removed")]
+    #[test_case(
+        "fn a(i32 x) {}",
+        "fn a(i64 x) {}",
+        &["changed the signature of fn a"] ;
+        "parameter type"
+    )]
+    #[test_case(
+        "record point { x: i32; }",
+        "record point { x: i32; y: i32; }",
+        &["changed the signature of record point"] ;
+        "record field"
+    )]
+    #[test_case(
+        "fn f() -> i32 { g(1, 2) + 3 }",
+        "fn f() -> i32 { g(1, x) + 3 }",
+        &["changed `2` to `x` in fn f"] ;
+        "argument"
+    )]
+    #[test_case(
+        "fn f() { a(); b(); }",
+        "fn f() { a(); c(1); }",
+        &["changed `b()` to `c(1)` in fn f"] ;
+        "different call"
+    )]
+    #[test_case(
+        "fn f() { a(); }",
+        "fn f() { a(); b(); }",
+        &["changed `{\n    a();\n}` to `{\n    a();\n    b();\n}` in fn f"] ;
+        "added statement"
+    )]
+    #[test_case(
+        "const i32 n = 1; x = 2;",
+        "const i32 n = 2; x = 3;",
+        &["changed `1` to `2` in const n", "changed `2` to `3` in x"] ;
+        "several declarations"
+    )]
+    #[test_case(
+        "patch point { fn f() -> i32 { 1 } }",
+        "patch point { fn f() -> i32 { 2 } }",
+        &["changed `1` to `2` in patch point"] ;
+        "patch method body"
+    )]
+    fn test_changes(old: &str, new: &str, expected: &[&str]) {
+        assert_eq!(changes(old, new), expected);
+    }
+
+    #[test]
+    fn test_duplicate_names_are_matched_in_order() {
+        assert_eq!(
+            changes("x = 1; x = 2;", "x = 1; x = 5;"),
+            ["changed `2` to `5` in x"]
+        );
+    }
+}
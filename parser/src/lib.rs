@@ -1,3 +1,3656 @@
+//! The parser for the CV programming language.
+//!
+//! This module turns the token stream produced by the lexer into the syntax tree defined in
+//! [`ast`].
+
 #![allow(dead_code)]
 
-mod ast;
+use crate::ast::{
+    ArraySize, BinaryOperator, ConstDeclaration, Declaration, Expression, ExpressionKind,
+    FunctionDeclaration, ImportDeclaration, Literal, Parameter, PatchDeclaration, Pattern,
+    PatternKind, Program, RecordDeclaration, RecordField, Statement, StatementKind, Type,
+    TypeParameter, UnaryOperator, UnionDeclaration, UnionVariant, WhenBranch,
+};
+use crate::node_id::{NodeId, NodeIds};
+use crate::precedence::{Associativity, Precedence};
+use crate::spans::Spans;
+use lexer::source::{FileId, SourceFile};
+use lexer::tokens::{NumberLiteral, Span, Token, TokenKind};
+use lexer::{Lexer, LexerError};
+use thiserror::Error;
+
+pub mod ast;
+pub mod builder;
+pub mod const_eval;
+pub mod diff;
+pub mod node_id;
+pub mod operators;
+pub mod precedence;
+pub mod printer;
+pub mod spans;
+pub mod validate;
+pub mod visit;
+pub mod visit_mut;
+
+pub type Result<T> = std::result::Result<T, ParseError>;
+
+/// How deeply expressions and types may nest. The parser is recursive, so without a limit
+/// adversarial input such as ten thousand `(` would overflow the stack instead of failing with a
+/// diagnostic. Each link of a chain such as `a + b + c` or `a.f().g()` counts as a level too,
+/// since it nests what comes before it one level deeper, and every later pass over the tree is
+/// recursive. The limit is low enough for a debug build on a 2 MiB thread stack, the default for
+/// spawned threads.
+pub const MAX_NESTING_DEPTH: usize = 64;
+
+#[derive(Debug, Error, PartialEq, Clone)]
+pub enum ParseError {
+    #[error(transparent)]
+    Lexer(#[from] LexerError),
+    #[error("Expected {expected}, found '{found}' at position {}", span.start)]
+    UnexpectedToken {
+        expected: String,
+        found: TokenKind,
+        span: Span,
+    },
+    /// `span` is where the input ends.
+    #[error("Expected {expected}, found end of input")]
+    UnexpectedEof { expected: String, span: Span },
+    #[error(
+        "Expected ';' after position {}, found {}",
+        after.end,
+        describe_found(found.as_ref())
+    )]
+    MissingSemicolon {
+        /// The token the `;` should follow.
+        after: Span,
+        found: Option<TokenKind>,
+    },
+    #[error(
+        "Expected '{delimiter}' to close the one opened at position {}, found {}",
+        open.start,
+        describe_found(found.as_ref())
+    )]
+    UnclosedDelimiter {
+        delimiter: TokenKind,
+        open: Span,
+        /// The token the closing delimiter should follow.
+        after: Span,
+        found: Option<TokenKind>,
+    },
+    #[error(
+        "Type '{name}' takes {expected} type argument(s) but {found} were given at position {}",
+        span.start
+    )]
+    WrongTypeArgumentCount {
+        name: String,
+        expected: usize,
+        found: usize,
+        span: Span,
+    },
+    #[error("Only functions can be declared in a patch, found '{found}' at position {}", span.start)]
+    NonFunctionInPatch { found: TokenKind, span: Span },
+    #[error("Only a variable, field, element, or dereference can be assigned to, at position {}", span.start)]
+    InvalidAssignmentTarget { span: Span },
+    #[error("Nesting exceeds the limit of {MAX_NESTING_DEPTH} levels at position {}", span.start)]
+    NestingTooDeep { span: Span },
+}
+
+impl ParseError {
+    /// A stable identifier for the kind of error, for documentation and for tools to filter
+    /// diagnostics by:
+    ///
+    /// - `E0001`: the source could not be split into tokens
+    /// - `E0002`: a token that does not fit the grammar
+    /// - `E0003`: the input ended in the middle of a construct
+    /// - `E0004`: a missing `;`
+    /// - `E0005`: a `(`, `[`, or `{` that is never closed
+    /// - `E0006`: a built-in generic type with the wrong number of type arguments
+    /// - `E0007`: something other than a function in a `patch`
+    /// - `E0008`: an assignment to something that is not a variable, field, element, or
+    ///   dereference
+    /// - `E0009`: nesting beyond [`MAX_NESTING_DEPTH`]
+    pub fn code(&self) -> &'static str {
+        match self {
+            ParseError::Lexer(_) => "E0001",
+            ParseError::UnexpectedToken { .. } => "E0002",
+            ParseError::UnexpectedEof { .. } => "E0003",
+            ParseError::MissingSemicolon { .. } => "E0004",
+            ParseError::UnclosedDelimiter { .. } => "E0005",
+            ParseError::WrongTypeArgumentCount { .. } => "E0006",
+            ParseError::NonFunctionInPatch { .. } => "E0007",
+            ParseError::InvalidAssignmentTarget { .. } => "E0008",
+            ParseError::NestingTooDeep { .. } => "E0009",
+        }
+    }
+
+    /// The message without the position it mentions, for showing after the location of the
+    /// error's [`span`](Self::span). Errors without a span keep their whole message.
+    pub fn message(&self) -> String {
+        match self {
+            ParseError::Lexer(LexerError::UnexpectedCharacter(c, _)) => {
+                format!("Unexpected character '{}'", c)
+            }
+            ParseError::Lexer(LexerError::InvalidNumberFormat(_)) => {
+                "Invalid number format".to_string()
+            }
+            ParseError::Lexer(LexerError::InvalidByteLiteral(_)) => {
+                "Invalid byte literal".to_string()
+            }
+            ParseError::Lexer(LexerError::InvalidCharLiteral(_)) => {
+                "Invalid character literal".to_string()
+            }
+            ParseError::Lexer(LexerError::InvalidEscape(_)) => {
+                "Invalid escape sequence".to_string()
+            }
+            ParseError::Lexer(LexerError::UnterminatedComment(_)) => {
+                "Unterminated block comment".to_string()
+            }
+            ParseError::Lexer(LexerError::UnterminatedString(_)) => {
+                "Unterminated string literal".to_string()
+            }
+            ParseError::Lexer(LexerError::InvalidPragma(_)) => "Invalid pragma".to_string(),
+            ParseError::Lexer(LexerError::UnsupportedVersion(_))
+            | ParseError::UnexpectedEof { .. } => self.to_string(),
+            ParseError::UnexpectedToken {
+                expected, found, ..
+            } => format!("Expected {}, found '{}'", expected, found),
+            ParseError::MissingSemicolon { found, .. } => {
+                format!("Expected ';', found {}", describe_found(found.as_ref()))
+            }
+            ParseError::UnclosedDelimiter {
+                delimiter, found, ..
+            } => format!(
+                "Expected '{}', found {}",
+                delimiter,
+                describe_found(found.as_ref())
+            ),
+            ParseError::WrongTypeArgumentCount {
+                name,
+                expected,
+                found,
+                ..
+            } => format!(
+                "Type '{}' takes {} type argument(s) but {} were given",
+                name, expected, found
+            ),
+            ParseError::NonFunctionInPatch { found, .. } => {
+                format!(
+                    "Only functions can be declared in a patch, found '{}'",
+                    found
+                )
+            }
+            ParseError::InvalidAssignmentTarget { .. } => {
+                "Only a variable, field, element, or dereference can be assigned to".to_string()
+            }
+            ParseError::NestingTooDeep { .. } => {
+                format!("Nesting exceeds the limit of {} levels", MAX_NESTING_DEPTH)
+            }
+        }
+    }
+
+    /// Where the error is. An error at the end of input is at the end of the file, and only an
+    /// unsupported language version has no location.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            ParseError::Lexer(error) => error.span(),
+            ParseError::UnexpectedEof { span, .. } => Some(*span),
+            ParseError::MissingSemicolon { after, .. }
+            | ParseError::UnclosedDelimiter { after, .. } => Some(*after),
+            ParseError::UnexpectedToken { span, .. }
+            | ParseError::WrongTypeArgumentCount { span, .. }
+            | ParseError::NonFunctionInPatch { span, .. }
+            | ParseError::InvalidAssignmentTarget { span }
+            | ParseError::NestingTooDeep { span } => Some(*span),
+        }
+    }
+
+    /// A second location that explains the error, with a label saying what it is.
+    pub fn secondary_span(&self) -> Option<(Span, &'static str)> {
+        match self {
+            ParseError::UnclosedDelimiter { open, .. } => Some((*open, "opened here")),
+            _ => None,
+        }
+    }
+
+    /// A fix that is certain enough to be applied automatically.
+    pub fn suggestion(&self) -> Option<Suggestion> {
+        match self {
+            ParseError::MissingSemicolon { after, .. } => Some(Suggestion {
+                message: "insert the missing ';'".to_string(),
+                file: after.file,
+                offset: after.end + 1,
+                text: ";".to_string(),
+            }),
+            ParseError::UnclosedDelimiter {
+                delimiter, after, ..
+            } => Some(Suggestion {
+                message: format!("insert the missing '{}'", delimiter),
+                file: after.file,
+                offset: after.end + 1,
+                text: delimiter.to_string(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// An edit that fixes a [`ParseError`]: inserting `text` at a character offset of a file.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Suggestion {
+    pub message: String,
+    pub file: FileId,
+    pub offset: usize,
+    pub text: String,
+}
+
+impl Suggestion {
+    /// The contents of `source` with the edit made.
+    pub fn apply(&self, source: &str) -> String {
+        let byte_offset = source
+            .char_indices()
+            .nth(self.offset)
+            .map_or(source.len(), |(offset, _)| offset);
+        let mut fixed = source.to_string();
+        fixed.insert_str(byte_offset, &self.text);
+        fixed
+    }
+}
+
+fn describe_found(found: Option<&TokenKind>) -> String {
+    match found {
+        Some(token) => format!("'{}'", token),
+        None => "end of input".to_string(),
+    }
+}
+
+/// Lex and parse `source` as a whole CV program. A `-` directly before a number is part of it,
+/// as [`Lexer::fold_negative_literals`] lexes it, so that `i64::MIN` can be written.
+///
+/// Neither this nor any other entry point of the lexer or parser panics: every input, however
+/// malformed, produces either a syntax tree or an error.
+pub fn parse(source: &str) -> Result<Program> {
+    let tokens = Lexer::new(source).fold_negative_literals().tokenize()?;
+    Parser::new(tokens).parse_program()
+}
+
+/// Lex and parse `source`, reporting every syntax error instead of only the first. The program
+/// holds the declarations that parsed; see [`Parser::parse_program_recovering`].
+pub fn parse_all(source: &str) -> (Program, Vec<ParseError>) {
+    match Lexer::new(source).fold_negative_literals().tokenize() {
+        Ok(tokens) => Parser::new(tokens).parse_program_recovering(),
+        Err(error) => (
+            Program {
+                declarations: Vec::new(),
+            },
+            vec![error.into()],
+        ),
+    }
+}
+
+/// Lex and parse a file registered with a [`SourceMap`](lexer::source::SourceMap) the way
+/// [`parse_all`] does, also returning where each of its statements and expressions was written.
+pub fn parse_file(file: &SourceFile) -> (Program, Spans, Vec<ParseError>) {
+    parse_file_numbered(file, NodeIds::new())
+}
+
+/// Parse a file as [`parse_file`] does, giving its nodes the ids `ids` hands out, as
+/// [`Parser::numbered_from`] does.
+pub fn parse_file_numbered(file: &SourceFile, ids: NodeIds) -> (Program, Spans, Vec<ParseError>) {
+    match Lexer::for_file(file).fold_negative_literals().tokenize() {
+        Ok(tokens) => {
+            let mut parser = Parser::new(tokens).numbered_from(ids).ending_at(file);
+            let (program, errors) = parser.parse_program_recovering();
+            (program, parser.spans().clone(), errors)
+        }
+        Err(error) => (
+            Program {
+                declarations: Vec::new(),
+            },
+            Spans::default(),
+            vec![error.into()],
+        ),
+    }
+}
+
+#[derive(Debug)]
+pub struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    /// Set while parsing the head of an `if` or `for`, where `name {` starts the body rather than
+    /// a record literal.
+    no_record_literals: bool,
+    /// The number of expressions and types currently being parsed inside one another.
+    depth: usize,
+    /// Whether a parenthesized expression becomes an [`ExpressionKind::Grouped`] rather than the
+    /// expression inside.
+    keep_parentheses: bool,
+    /// Where each statement and expression parsed so far was written.
+    spans: Spans,
+    /// The ids given to the nodes parsed so far.
+    ids: NodeIds,
+    /// Where the input ends, which errors at the end of input point at.
+    end: Span,
+}
+
+impl Parser {
+    /// Create a parser over a token stream. Newlines carry no meaning in CV's grammar, so they are
+    /// dropped here.
+    pub fn new(tokens: Vec<Token>) -> Self {
+        let end = tokens.last().map_or(
+            Span {
+                file: FileId::default(),
+                start: 0,
+                end: 0,
+            },
+            |token| Span {
+                file: token.position.file,
+                start: token.position.end + 1,
+                end: token.position.end + 1,
+            },
+        );
+        Self {
+            tokens: tokens
+                .into_iter()
+                .filter(|token| token.kind != TokenKind::Newline)
+                .collect(),
+            position: 0,
+            no_record_literals: false,
+            depth: 0,
+            keep_parentheses: false,
+            spans: Spans::default(),
+            ids: NodeIds::new(),
+            end,
+        }
+    }
+
+    /// Report the input as ending where `file`, the file the tokens were lexed from, does rather
+    /// than right after its last token.
+    pub fn ending_at(mut self, file: &SourceFile) -> Self {
+        let end = file.content.chars().count();
+        self.end = Span {
+            file: file.id,
+            start: end,
+            end,
+        };
+        self
+    }
+
+    /// Keep the parentheses written around expressions as [`ExpressionKind::Grouped`] nodes, so a
+    /// formatter can print them back. By default they only group and leave no trace in the tree.
+    pub fn keep_parentheses(mut self) -> Self {
+        self.keep_parentheses = true;
+        self
+    }
+
+    /// Give the parsed nodes the ids `ids` hands out, such as ids [after](NodeIds::after) those of
+    /// a program the parsed one is added to, so that its [spans](Parser::spans) stay keyed by the
+    /// ids its nodes end up with.
+    pub fn numbered_from(mut self, ids: NodeIds) -> Self {
+        self.ids = ids;
+        self
+    }
+
+    /// Where the statements and expressions parsed so far were written.
+    pub fn spans(&self) -> &Spans {
+        &self.spans
+    }
+
+    /// Parse every remaining token as a sequence of declarations.
+    pub fn parse_program(&mut self) -> Result<Program> {
+        let mut declarations = Vec::new();
+        while self.peek().is_some() {
+            declarations.push(self.parse_declaration()?);
+        }
+
+        Ok(Program { declarations })
+    }
+
+    /// Parse a whole program, continuing after a declaration that fails to parse. The parser
+    /// skips to the end of the broken declaration, the first `;` or closing `}` that brings it
+    /// back to the top level, or the next keyword that starts a declaration there, so each
+    /// mistake is reported once and the errors after it are still found.
+    pub fn parse_program_recovering(&mut self) -> (Program, Vec<ParseError>) {
+        let mut declarations = Vec::new();
+        let mut errors = Vec::new();
+        while self.peek().is_some() {
+            let start = self.position;
+            match self.parse_declaration() {
+                Ok(declaration) => declarations.push(declaration),
+                Err(error) => {
+                    errors.push(error);
+                    self.skip_rest_of_declaration(start);
+                }
+            }
+        }
+
+        (Program { declarations }, errors)
+    }
+
+    /// Move past the rest of the declaration that started at `start`, always consuming at least
+    /// one token. A closing delimiter also closes any delimiters left open inside it, so an
+    /// unclosed `(` in a function body does not swallow the declarations after the function.
+    fn skip_rest_of_declaration(&mut self, start: usize) {
+        let mut open = Vec::new();
+        let mut position = start;
+        while let Some(token) = self.tokens.get(position) {
+            let past_error = position >= self.position;
+            if past_error
+                && position > start
+                && open.is_empty()
+                && (matches!(
+                    token.kind,
+                    TokenKind::Fun
+                        | TokenKind::Record
+                        | TokenKind::Union
+                        | TokenKind::Patch
+                        | TokenKind::Const
+                ) || self.is_visibility(position)
+                    || self.is_import(position))
+            {
+                break;
+            }
+            position += 1;
+            match &token.kind {
+                TokenKind::LeftParen | TokenKind::LeftBracket | TokenKind::LeftBrace => {
+                    open.push(token.kind.clone())
+                }
+                TokenKind::RightParen | TokenKind::RightBracket | TokenKind::RightBrace => {
+                    let opening = match token.kind {
+                        TokenKind::RightParen => TokenKind::LeftParen,
+                        TokenKind::RightBracket => TokenKind::LeftBracket,
+                        _ => TokenKind::LeftBrace,
+                    };
+                    if let Some(index) = open.iter().rposition(|kind| *kind == opening) {
+                        open.truncate(index);
+                    }
+                    if past_error && open.is_empty() && token.kind == TokenKind::RightBrace {
+                        // A `;` may follow a braced declaration.
+                        if self.tokens.get(position).map(|token| &token.kind)
+                            == Some(&TokenKind::Semicolon)
+                        {
+                            position += 1;
+                        }
+                        break;
+                    }
+                }
+                TokenKind::Semicolon if past_error && open.is_empty() => break,
+                _ => {}
+            }
+        }
+        self.position = position;
+    }
+
+    fn peek(&self) -> Option<&TokenKind> {
+        self.peek_nth(0)
+    }
+
+    fn peek_nth(&self, num_ahead: usize) -> Option<&TokenKind> {
+        self.tokens
+            .get(self.position + num_ahead)
+            .map(|token| &token.kind)
+    }
+
+    fn check(&self, kind: &TokenKind) -> bool {
+        self.peek() == Some(kind)
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        if token.is_some() {
+            self.position += 1;
+        }
+        token
+    }
+
+    /// Consume the next token if it is `kind`.
+    fn eat(&mut self, kind: &TokenKind) -> bool {
+        let matches = self.check(kind);
+        if matches {
+            self.position += 1;
+        }
+        matches
+    }
+
+    fn expect(&mut self, kind: &TokenKind) -> Result<Token> {
+        match self.tokens.get(self.position) {
+            Some(token) if token.kind == *kind => {
+                let token = token.clone();
+                self.position += 1;
+                Ok(token)
+            }
+            _ => Err(self.expected_token(kind)),
+        }
+    }
+
+    /// An error describing that the token `kind` was wanted where the current token is. A
+    /// missing `;` or closing delimiter gets an error of its own that says where it belongs.
+    fn expected_token(&self, kind: &TokenKind) -> ParseError {
+        let found = self.tokens.get(self.position);
+        let previous = self
+            .position
+            .checked_sub(1)
+            .and_then(|index| self.tokens.get(index));
+        let Some(previous) = previous else {
+            return self.error(&format!("'{}'", kind));
+        };
+        let found = found.map(|token| token.kind.clone());
+        if *kind == TokenKind::Semicolon {
+            return ParseError::MissingSemicolon {
+                after: previous.position,
+                found,
+            };
+        }
+        if let Some(open) = self.unclosed_opening(kind) {
+            return ParseError::UnclosedDelimiter {
+                delimiter: kind.clone(),
+                open,
+                after: previous.position,
+                found,
+            };
+        }
+        self.error(&format!("'{}'", kind))
+    }
+
+    /// The span of the innermost opening delimiter before the current token that `closing`
+    /// would close, if it is still open.
+    fn unclosed_opening(&self, closing: &TokenKind) -> Option<Span> {
+        let opening = match closing {
+            TokenKind::RightParen => TokenKind::LeftParen,
+            TokenKind::RightBracket => TokenKind::LeftBracket,
+            TokenKind::RightBrace => TokenKind::LeftBrace,
+            _ => return None,
+        };
+        let mut depth = 0;
+        for token in self.tokens[..self.position].iter().rev() {
+            if token.kind == *closing {
+                depth += 1;
+            } else if token.kind == opening {
+                if depth == 0 {
+                    return Some(token.position);
+                }
+                depth -= 1;
+            }
+        }
+        None
+    }
+
+    fn expect_identifier(&mut self, what: &str) -> Result<String> {
+        match self.peek() {
+            Some(TokenKind::Identifier(name)) => {
+                let name = name.clone();
+                self.position += 1;
+                Ok(name)
+            }
+            _ => Err(self.error(what)),
+        }
+    }
+
+    /// An error describing that `expected` was wanted where the current token is.
+    fn error(&self, expected: &str) -> ParseError {
+        match self.tokens.get(self.position) {
+            Some(token) => ParseError::UnexpectedToken {
+                expected: expected.to_string(),
+                found: token.kind.clone(),
+                span: token.position,
+            },
+            None => ParseError::UnexpectedEof {
+                expected: expected.to_string(),
+                span: self.end,
+            },
+        }
+    }
+
+    /// Run `parse` with record literals allowed or forbidden, restoring the previous setting after.
+    fn with_record_literals<T>(
+        &mut self,
+        allowed: bool,
+        parse: impl FnOnce(&mut Self) -> Result<T>,
+    ) -> Result<T> {
+        let previous = self.no_record_literals;
+        self.no_record_literals = !allowed;
+        let result = parse(self);
+        self.no_record_literals = previous;
+        result
+    }
+
+    /// Run `parse` one nesting level deeper, failing once [`MAX_NESTING_DEPTH`] is exceeded.
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        self.link()?;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// Run `parse`, which builds a chain such as `a + b + c` in a loop, calling [`Parser::link`]
+    /// for each link, and restore the nesting depth once the chain ends.
+    fn chain<T>(&mut self, parse: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        let depth = self.depth;
+        let result = parse(self);
+        self.depth = depth;
+        result
+    }
+
+    /// Go one nesting level deeper, failing once [`MAX_NESTING_DEPTH`] is exceeded.
+    fn link(&mut self) -> Result<()> {
+        if self.depth >= MAX_NESTING_DEPTH {
+            return Err(match self.tokens.get(self.position) {
+                Some(token) => ParseError::NestingTooDeep {
+                    span: token.position,
+                },
+                None => self.error("end of nesting"),
+            });
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// The span from the token at `start` to the last token consumed.
+    fn span_from(&self, start: usize) -> Span {
+        let first = self.tokens[start].position;
+        let last = self.tokens[self.position.max(start + 1) - 1].position;
+        Span {
+            file: first.file,
+            start: first.start,
+            end: last.end,
+        }
+    }
+
+    /// A new expression of `kind`, recording that it was written from the token at `start` to
+    /// the last token consumed.
+    fn spanned(&mut self, start: usize, kind: ExpressionKind) -> Expression {
+        let expression = Expression {
+            id: self.ids.fresh(),
+            kind,
+        };
+        let span = self.span_from(start);
+        self.spans.record(expression.id, span);
+        expression
+    }
+
+    /// A new statement of `kind`, recording that it was written from the token at `start` to the
+    /// last token consumed.
+    fn spanned_statement(&mut self, start: usize, kind: StatementKind) -> Statement {
+        let statement = Statement {
+            id: self.ids.fresh(),
+            kind,
+        };
+        let span = self.span_from(start);
+        self.spans.record(statement.id, span);
+        statement
+    }
+
+    /// A new id for a declaration, parameter, field, variant, or type parameter, recording that
+    /// it was named by the token at `name`.
+    fn declared(&mut self, name: usize) -> NodeId {
+        let id = self.ids.fresh();
+        self.spans.record(id, self.tokens[name].position);
+        id
+    }
+
+    /// A new pattern of `kind`.
+    fn pattern(&mut self, kind: PatternKind) -> Pattern {
+        Pattern {
+            id: self.ids.fresh(),
+            kind,
+        }
+    }
+
+    fn parse_declaration(&mut self) -> Result<Declaration> {
+        if self.is_import(self.position) {
+            return Ok(Declaration::Import(self.parse_import()?));
+        }
+        if self.is_visibility(self.position) {
+            self.position += 1;
+            return match self.peek() {
+                Some(TokenKind::Fun) => Ok(Declaration::Function(FunctionDeclaration {
+                    is_public: true,
+                    ..self.parse_function()?
+                })),
+                Some(TokenKind::Record) => Ok(Declaration::Record(RecordDeclaration {
+                    is_public: true,
+                    ..self.parse_record()?
+                })),
+                Some(TokenKind::Union) => Ok(Declaration::Union(UnionDeclaration {
+                    is_public: true,
+                    ..self.parse_union()?
+                })),
+                Some(TokenKind::Const) => Ok(Declaration::Const(ConstDeclaration {
+                    is_public: true,
+                    ..self.parse_const()?
+                })),
+                _ => Err(self.error("'fn', 'record', 'union', or 'const' after 'pub'")),
+            };
+        }
+        match self.peek() {
+            Some(TokenKind::Fun) => Ok(Declaration::Function(self.parse_function()?)),
+            Some(TokenKind::Record) => Ok(Declaration::Record(self.parse_record()?)),
+            Some(TokenKind::Union) => Ok(Declaration::Union(self.parse_union()?)),
+            Some(TokenKind::Patch) => Ok(Declaration::Patch(self.parse_patch()?)),
+            Some(TokenKind::Const) => Ok(Declaration::Const(self.parse_const()?)),
+            _ => {
+                let start = self.position;
+                let statement = self.parse_statement()?;
+                Ok(Declaration::Statement(
+                    self.spanned_statement(start, statement),
+                ))
+            }
+        }
+    }
+
+    /// Whether the token at `position` is `pub` making a declaration public. `pub` is a
+    /// contextual keyword, so elsewhere it is an ordinary name.
+    fn is_visibility(&self, position: usize) -> bool {
+        let is_pub = self
+            .tokens
+            .get(position)
+            .is_some_and(|token| token.as_contextual_keyword(&TokenKind::Pub).is_some());
+        is_pub
+            && matches!(
+                self.tokens.get(position + 1).map(|token| &token.kind),
+                Some(
+                    TokenKind::Fun
+                        | TokenKind::Record
+                        | TokenKind::Union
+                        | TokenKind::Patch
+                        | TokenKind::Const
+                )
+            )
+    }
+
+    /// Whether the token at `position` is the contextual keyword `import` starting an import.
+    fn is_import(&self, position: usize) -> bool {
+        let is_import = self
+            .tokens
+            .get(position)
+            .is_some_and(|token| token.as_contextual_keyword(&TokenKind::Import).is_some());
+        is_import
+            && matches!(
+                self.tokens.get(position + 1).map(|token| &token.kind),
+                Some(TokenKind::Identifier(_))
+            )
+    }
+
+    /// `import module::name;` or `import module::{name, ...};`
+    fn parse_import(&mut self) -> Result<ImportDeclaration> {
+        let start = self.position;
+        self.position += 1;
+        let module = self.expect_identifier("module name")?;
+        self.expect(&TokenKind::Scope)?;
+        let mut items = Vec::new();
+        if self.eat(&TokenKind::LeftBrace) {
+            loop {
+                items.push(self.expect_identifier("imported name")?);
+                if !self.eat(&TokenKind::Comma) || self.check(&TokenKind::RightBrace) {
+                    break;
+                }
+            }
+            self.expect(&TokenKind::RightBrace)?;
+        } else {
+            items.push(self.expect_identifier("imported name")?);
+        }
+        self.expect(&TokenKind::Semicolon)?;
+
+        let id = self.ids.fresh();
+        self.spans.record(id, self.span_from(start));
+        Ok(ImportDeclaration { id, module, items })
+    }
+
+    /// `fn name<T, ...>(type param, ...) -> type { ... }`. The type parameters are optional.
+    fn parse_function(&mut self) -> Result<FunctionDeclaration> {
+        self.expect(&TokenKind::Fun)?;
+        let name_position = self.position;
+        let name = self.expect_identifier("function name")?;
+        // A `<` right after a declared name always opens type parameters, never a comparison.
+        let type_parameters = self.parse_type_parameters()?;
+
+        self.expect(&TokenKind::LeftParen)?;
+        let mut params = Vec::new();
+        while !self.check(&TokenKind::RightParen) {
+            params.push(self.parse_parameter()?);
+            if !self.eat(&TokenKind::Comma) {
+                break;
+            }
+        }
+        self.expect(&TokenKind::RightParen)?;
+
+        let return_type = if self.eat(&TokenKind::RightArrow) {
+            Some(self.parse_type()?)
+        } else {
+            None
+        };
+        let body = self.parse_block()?;
+
+        Ok(FunctionDeclaration {
+            id: self.declared(name_position),
+            name,
+            is_public: false,
+            type_parameters,
+            params,
+            return_type,
+            body: Box::new(body),
+        })
+    }
+
+    /// `type name`, `type @name`, `type& name`, or `type& @name`.
+    fn parse_parameter(&mut self) -> Result<Parameter> {
+        let param_type = self.parse_type()?;
+        let is_mutable = self.eat(&TokenKind::Mut);
+        let name_position = self.position;
+        let name = self.expect_identifier("parameter name")?;
+
+        Ok(Parameter {
+            id: self.declared(name_position),
+            name,
+            is_ref: matches!(param_type, Type::Reference { .. }),
+            param_type,
+            is_mutable,
+        })
+    }
+
+    /// `const type name = value;`
+    fn parse_const(&mut self) -> Result<ConstDeclaration> {
+        self.expect(&TokenKind::Const)?;
+        let const_type = self.parse_type()?;
+        let name_position = self.position;
+        let name = self.expect_identifier("constant name")?;
+        self.expect(&TokenKind::Equal)?;
+        let value = self.parse_expression()?;
+        self.expect(&TokenKind::Semicolon)?;
+
+        Ok(ConstDeclaration {
+            id: self.declared(name_position),
+            name,
+            is_public: false,
+            const_type,
+            value: Box::new(value),
+        })
+    }
+
+    /// `record name { field: type; ... }`, where a field may be declared `pub`.
+    fn parse_record(&mut self) -> Result<RecordDeclaration> {
+        self.expect(&TokenKind::Record)?;
+        let name_position = self.position;
+        let name = self.expect_identifier("record name")?;
+        let type_parameters = self.parse_type_parameters()?;
+
+        self.expect(&TokenKind::LeftBrace)?;
+        let mut fields = Vec::new();
+        while !self.eat(&TokenKind::RightBrace) {
+            // A field may itself be named `pub`.
+            let is_public = self
+                .tokens
+                .get(self.position)
+                .is_some_and(|token| token.as_contextual_keyword(&TokenKind::Pub).is_some())
+                && matches!(self.peek_nth(1), Some(TokenKind::Identifier(_)));
+            if is_public {
+                self.position += 1;
+            }
+            let field_position = self.position;
+            let name = self.expect_identifier("field name")?;
+            self.expect(&TokenKind::Colon)?;
+            let field_type = self.parse_type()?;
+            self.expect(&TokenKind::Semicolon)?;
+            fields.push(RecordField {
+                id: self.declared(field_position),
+                name,
+                is_public,
+                field_type,
+            });
+        }
+
+        Ok(RecordDeclaration {
+            id: self.declared(name_position),
+            name,
+            is_public: false,
+            type_parameters,
+            fields,
+        })
+    }
+
+    /// `union name<T, ...> = variant | variant(type) | ...;` or, equivalently,
+    /// `union name<T, ...> { variant, variant(type), ... }`. The type parameters are optional.
+    fn parse_union(&mut self) -> Result<UnionDeclaration> {
+        self.expect(&TokenKind::Union)?;
+        let name_position = self.position;
+        let name = self.expect_identifier("union name")?;
+        let type_parameters = self.parse_type_parameters()?;
+
+        let mut variants = Vec::new();
+        if self.eat(&TokenKind::LeftBrace) {
+            while !self.eat(&TokenKind::RightBrace) {
+                variants.push(self.parse_union_variant()?);
+                if !self.eat(&TokenKind::Comma) {
+                    self.expect(&TokenKind::RightBrace)?;
+                    break;
+                }
+            }
+        } else {
+            self.expect(&TokenKind::Equal)?;
+            loop {
+                variants.push(self.parse_union_variant()?);
+                if !self.eat(&TokenKind::Pipe) {
+                    break;
+                }
+            }
+            self.expect(&TokenKind::Semicolon)?;
+        }
+
+        Ok(UnionDeclaration {
+            id: self.declared(name_position),
+            name,
+            is_public: false,
+            type_parameters,
+            variants,
+        })
+    }
+
+    /// `name` or `name(type)`.
+    fn parse_union_variant(&mut self) -> Result<UnionVariant> {
+        let name_position = self.position;
+        let name = self.expect_identifier("variant name")?;
+        let variant_type = if self.eat(&TokenKind::LeftParen) {
+            let variant_type = self.parse_type()?;
+            self.expect(&TokenKind::RightParen)?;
+            Some(variant_type)
+        } else {
+            None
+        };
+
+        Ok(UnionVariant {
+            id: self.declared(name_position),
+            name,
+            variant_type,
+        })
+    }
+
+    /// `<T, N: usize, ...>` after a declaration's name, or nothing. A parameter with a type is a
+    /// const parameter.
+    fn parse_type_parameters(&mut self) -> Result<Vec<TypeParameter>> {
+        let mut parameters = Vec::new();
+        if self.eat(&TokenKind::LessThan) {
+            loop {
+                let name_position = self.position;
+                let name = self.expect_identifier("type parameter")?;
+                let const_type = if self.eat(&TokenKind::Colon) {
+                    Some(self.parse_type()?)
+                } else {
+                    None
+                };
+                parameters.push(TypeParameter {
+                    id: self.declared(name_position),
+                    name,
+                    const_type,
+                });
+                if !self.eat(&TokenKind::Comma) {
+                    break;
+                }
+            }
+            self.expect(&TokenKind::GreaterThan)?;
+        }
+
+        Ok(parameters)
+    }
+
+    /// `patch type { fn ... }`, where the type may be built in or generic, as in
+    /// `patch result<T, E> { ... }`.
+    fn parse_patch(&mut self) -> Result<PatchDeclaration> {
+        self.expect(&TokenKind::Patch)?;
+        let target_type = self.parse_type()?;
+
+        self.expect(&TokenKind::LeftBrace)?;
+        let mut methods = Vec::new();
+        while !self.eat(&TokenKind::RightBrace) {
+            match self.tokens.get(self.position) {
+                Some(token) if token.kind != TokenKind::Fun => {
+                    return Err(ParseError::NonFunctionInPatch {
+                        found: token.kind.clone(),
+                        span: token.position,
+                    });
+                }
+                Some(_) => methods.push(self.parse_function()?),
+                None => return Err(self.error("'fn' or '}'")),
+            }
+        }
+
+        Ok(PatchDeclaration {
+            id: self.ids.fresh(),
+            target_type,
+            methods,
+        })
+    }
+
+    /// A type: a primitive or named type, a generic instantiation such as `arrayList<T>` or
+    /// `fixedArray<T, 4>`, a function type `fn(T, U) -> V`, or `_`, followed by any number of
+    /// `&` (reference) or `&@` (mutable reference) suffixes.
+    fn parse_type(&mut self) -> Result<Type> {
+        self.nested(|parser| parser.chain(Self::parse_type_with_suffixes))
+    }
+
+    fn parse_type_with_suffixes(&mut self) -> Result<Type> {
+        let mut parsed = match self.peek() {
+            Some(TokenKind::Fun) => self.parse_function_type()?,
+            Some(TokenKind::Identifier(name)) if name == "_" => {
+                self.position += 1;
+                Type::Inferred
+            }
+            _ => self.parse_named_type()?,
+        };
+
+        loop {
+            let depth = match self.peek() {
+                Some(TokenKind::Ampersand) => 1,
+                // `T&&` is lexed as one token but is a reference to a reference.
+                Some(TokenKind::DoubleAmpersand) => 2,
+                _ => return Ok(parsed),
+            };
+            self.link()?;
+            self.position += 1;
+            for level in 1..=depth {
+                parsed = Type::Reference {
+                    is_mutable: level == depth && self.eat_reference_mutability(),
+                    ref_type: Box::new(parsed),
+                };
+            }
+        }
+    }
+
+    /// Consume the `@` of a `&@` type suffix. In `T& @name` the `@` makes the binding mutable
+    /// rather than the reference, so it only belongs to the type when no name follows it.
+    fn eat_reference_mutability(&mut self) -> bool {
+        let is_type_suffix = self.check(&TokenKind::Mut)
+            && !matches!(self.peek_nth(1), Some(TokenKind::Identifier(_)));
+        if is_type_suffix {
+            self.position += 1;
+        }
+        is_type_suffix
+    }
+
+    /// `fn(T, ...)` with an optional `-> R`.
+    fn parse_function_type(&mut self) -> Result<Type> {
+        self.expect(&TokenKind::Fun)?;
+        self.expect(&TokenKind::LeftParen)?;
+        let mut param_types = Vec::new();
+        while !self.check(&TokenKind::RightParen) {
+            param_types.push(self.parse_type()?);
+            if !self.eat(&TokenKind::Comma) {
+                break;
+            }
+        }
+        self.expect(&TokenKind::RightParen)?;
+
+        let return_type = if self.eat(&TokenKind::RightArrow) {
+            Some(Box::new(self.parse_type()?))
+        } else {
+            None
+        };
+
+        Ok(Type::Function {
+            param_types,
+            return_type,
+        })
+    }
+
+    /// A primitive, named, or generic type, checking the argument count of the built-in generics.
+    fn parse_named_type(&mut self) -> Result<Type> {
+        let Some(span) = self.tokens.get(self.position).map(|token| token.position) else {
+            return Err(self.error("type"));
+        };
+        let name = self.expect_identifier("type")?;
+        let argument_count_error =
+            |expected: usize, found: usize| ParseError::WrongTypeArgumentCount {
+                name: name.clone(),
+                expected,
+                found,
+                span,
+            };
+
+        if name == "fixedArray" {
+            if !self.eat(&TokenKind::LessThan) {
+                return Err(argument_count_error(2, 0));
+            }
+            let element_type = self.parse_type()?;
+            if !self.eat(&TokenKind::Comma) {
+                return Err(argument_count_error(2, 1));
+            }
+            // Only operators that bind tighter than the `>` closing the type arguments can
+            // appear in the size.
+            let size = self.parse_binary(BinaryOperator::GreaterThan.precedence())?;
+            let size = match size.kind {
+                ExpressionKind::Literal(Literal::Integer(size)) if size >= 0 => {
+                    ArraySize::Literal(size as usize)
+                }
+                ExpressionKind::Identifier(name) => ArraySize::Parameter(name),
+                _ => ArraySize::Expression(Box::new(size)),
+            };
+            self.expect(&TokenKind::GreaterThan)?;
+
+            return Ok(Type::FixedArray {
+                element_type: Box::new(element_type),
+                size,
+            });
+        }
+
+        // Only user-defined generics can have const parameters.
+        let allows_const = name != "arrayList" && primitive_type(&name).is_none();
+        let mut parameters = Vec::new();
+        if self.eat(&TokenKind::LessThan) {
+            loop {
+                parameters.push(match self.peek() {
+                    Some(TokenKind::Number(NumberLiteral::Integer(value))) if allows_const => {
+                        let value = usize::try_from(*value).map_err(|_| self.error("type"))?;
+                        self.position += 1;
+                        Type::ConstValue(value)
+                    }
+                    _ => self.parse_type()?,
+                });
+                if !self.eat(&TokenKind::Comma) {
+                    break;
+                }
+            }
+            self.expect(&TokenKind::GreaterThan)?;
+        }
+
+        if let Some(primitive) = primitive_type(&name) {
+            if !parameters.is_empty() {
+                return Err(argument_count_error(0, parameters.len()));
+            }
+            return Ok(primitive);
+        }
+
+        match (name.as_str(), parameters.len()) {
+            ("arrayList", 1) => Ok(Type::ArrayList(Box::new(parameters.remove(0)))),
+            ("arrayList", found) => Err(argument_count_error(1, found)),
+            (_, 0) => Ok(Type::Named(name)),
+            _ => Ok(Type::Generic { name, parameters }),
+        }
+    }
+
+    fn parse_statement(&mut self) -> Result<StatementKind> {
+        match self.peek() {
+            Some(TokenKind::Return) => {
+                self.position += 1;
+                let value = self.parse_optional_value()?;
+                self.expect(&TokenKind::Semicolon)?;
+                Ok(StatementKind::Return(value))
+            }
+            Some(TokenKind::Break) => {
+                self.position += 1;
+                let value = self.parse_optional_value()?;
+                self.expect(&TokenKind::Semicolon)?;
+                Ok(StatementKind::Break(value))
+            }
+            _ => {
+                if let Some(declaration) = self.parse_variable_declaration()? {
+                    return Ok(declaration);
+                }
+                let expression = self.parse_statement_expression()?;
+                if self.peek().is_some_and(is_assignment) {
+                    return self.parse_assignment(expression);
+                }
+                self.finish_expression_statement(expression)
+            }
+        }
+    }
+
+    /// Parse the `op= value;` rest of an assignment statement whose target has been parsed.
+    fn parse_assignment(&mut self, target: Expression) -> Result<StatementKind> {
+        let Some(token) = self.tokens.get(self.position) else {
+            return Err(self.error("assignment operator"));
+        };
+        if !target.is_lvalue() {
+            return Err(ParseError::InvalidAssignmentTarget {
+                span: token.position,
+            });
+        }
+        let operator = BinaryOperator::from_token(&token.kind)
+            .ok_or_else(|| self.error("assignment operator"))?;
+        self.position += 1;
+
+        let value = self.parse_expression()?;
+        self.expect(&TokenKind::Semicolon)?;
+
+        Ok(StatementKind::Assignment {
+            target: Box::new(target),
+            operator,
+            value: Box::new(value),
+        })
+    }
+
+    /// The expression an expression statement starts with. A block-like expression such as
+    /// `if` ends the statement, so that what follows it, such as `-1;` or `(x).f();` on the next
+    /// line, starts another statement rather than continuing it as an operand.
+    fn parse_statement_expression(&mut self) -> Result<Expression> {
+        match self.peek() {
+            Some(
+                TokenKind::If
+                | TokenKind::When
+                | TokenKind::LeftBrace
+                | TokenKind::Loop
+                | TokenKind::While
+                | TokenKind::For,
+            ) => self.nested(Self::parse_primary),
+            _ => self.parse_expression(),
+        }
+    }
+
+    /// Terminate an expression statement. Block-like expressions such as `if` need no `;`, but
+    /// one is allowed after them.
+    fn finish_expression_statement(&mut self, expression: Expression) -> Result<StatementKind> {
+        if !self.eat(&TokenKind::Semicolon) && expression.requires_semicolon() {
+            return Err(self.expected_token(&TokenKind::Semicolon));
+        }
+        Ok(StatementKind::Expression(Box::new(expression)))
+    }
+
+    /// The value of a `return` or `break`, if one is given before the `;`.
+    fn parse_optional_value(&mut self) -> Result<Option<Box<Expression>>> {
+        if self.check(&TokenKind::Semicolon) {
+            Ok(None)
+        } else {
+            Ok(Some(Box::new(self.parse_expression()?)))
+        }
+    }
+
+    /// Parse `[type] [@]name = value;` if the upcoming tokens form a variable declaration,
+    /// leaving the position untouched otherwise.
+    fn parse_variable_declaration(&mut self) -> Result<Option<StatementKind>> {
+        let start = self.position;
+        let var_type = match (self.peek(), self.peek_nth(1)) {
+            (Some(TokenKind::Mut), _)
+            | (Some(TokenKind::Identifier(_)), Some(TokenKind::Equal)) => None,
+            (Some(TokenKind::Identifier(_)), _) => match self.parse_type() {
+                Ok(var_type) => Some(var_type),
+                Err(_) => {
+                    self.position = start;
+                    return Ok(None);
+                }
+            },
+            _ => return Ok(None),
+        };
+
+        let is_mutable = self.eat(&TokenKind::Mut);
+        let name = match (self.peek(), self.peek_nth(1)) {
+            (Some(TokenKind::Identifier(name)), Some(TokenKind::Equal)) => name.clone(),
+            _ if is_mutable && var_type.is_none() => {
+                return Err(self.error("variable name followed by '='"));
+            }
+            _ => {
+                self.position = start;
+                return Ok(None);
+            }
+        };
+        self.position += 2;
+
+        let value = self.parse_expression()?;
+        self.expect(&TokenKind::Semicolon)?;
+
+        Ok(Some(StatementKind::VariableDeclaration {
+            name,
+            var_type,
+            is_mutable,
+            value: Box::new(value),
+        }))
+    }
+
+    /// `{ statement* final_expression? }`
+    fn parse_block(&mut self) -> Result<Expression> {
+        let start = self.position;
+        self.expect(&TokenKind::LeftBrace)?;
+        self.with_record_literals(true, |parser| {
+            let mut statements = Vec::new();
+            let mut final_expression = None;
+
+            while !parser.eat(&TokenKind::RightBrace) {
+                let statement_start = parser.position;
+                let statement =
+                    if matches!(parser.peek(), Some(TokenKind::Return | TokenKind::Break)) {
+                        parser.parse_statement()?
+                    } else if let Some(declaration) = parser.parse_variable_declaration()? {
+                        declaration
+                    } else {
+                        let expression = parser.parse_statement_expression()?;
+                        if parser.peek().is_some_and(is_assignment) {
+                            parser.parse_assignment(expression)?
+                        } else if parser.eat(&TokenKind::RightBrace) {
+                            // An expression directly followed by the closing brace is the block's
+                            // value.
+                            final_expression = Some(Box::new(expression));
+                            break;
+                        } else {
+                            parser.finish_expression_statement(expression)?
+                        }
+                    };
+                statements.push(parser.spanned_statement(statement_start, statement));
+            }
+
+            Ok(parser.spanned(
+                start,
+                ExpressionKind::Block {
+                    statements,
+                    final_expression,
+                },
+            ))
+        })
+    }
+
+    pub fn parse_expression(&mut self) -> Result<Expression> {
+        self.parse_binary(Precedence::LOWEST)
+    }
+
+    /// Parse an expression whose infix operators all bind tighter than `min_precedence`, using
+    /// the table in [`precedence`]. Assignment is a statement, so an assignment operator ends
+    /// the expression, and ranges, which do not associate, cannot be chained.
+    fn parse_binary(&mut self, min_precedence: Precedence) -> Result<Expression> {
+        self.chain(|parser| parser.parse_binary_chain(min_precedence))
+    }
+
+    fn parse_binary_chain(&mut self, min_precedence: Precedence) -> Result<Expression> {
+        let start = self.position;
+        let mut left = self.parse_cast()?;
+        while let Some(operator) = self.peek().cloned() {
+            let (precedence, associativity) = match Precedence::of_infix(&operator) {
+                Some((precedence, associativity))
+                    if precedence > min_precedence && !is_assignment(&operator) =>
+                {
+                    (precedence, associativity)
+                }
+                _ => break,
+            };
+            let binary_operator = BinaryOperator::from_token(&operator);
+            if binary_operator.is_none()
+                && !matches!(operator, TokenKind::Range | TokenKind::RangeInclusive)
+            {
+                return Err(self.error("binary operator"));
+            }
+            self.link()?;
+            self.position += 1;
+            let right = Box::new(self.parse_binary(precedence)?);
+
+            let expression = match binary_operator {
+                Some(operator) => ExpressionKind::BinaryOperation {
+                    left: Box::new(left),
+                    operator,
+                    right,
+                },
+                None => {
+                    if associativity == Associativity::None
+                        && self
+                            .peek()
+                            .and_then(Precedence::of_infix)
+                            .is_some_and(|(next, _)| next == precedence)
+                    {
+                        return Err(self.error("end of range"));
+                    }
+                    ExpressionKind::Range {
+                        start: Box::new(left),
+                        end: right,
+                        inclusive: operator == TokenKind::RangeInclusive,
+                    }
+                }
+            };
+            left = self.spanned(start, expression);
+        }
+
+        Ok(left)
+    }
+
+    /// A prefix expression followed by any number of `as type`, which group to the left.
+    fn parse_cast(&mut self) -> Result<Expression> {
+        self.chain(Self::parse_cast_chain)
+    }
+
+    fn parse_cast_chain(&mut self) -> Result<Expression> {
+        let start = self.position;
+        let mut expression = self.parse_unary()?;
+        while self.is_cast(self.position) {
+            self.link()?;
+            self.position += 1;
+            let cast = ExpressionKind::Cast {
+                expression: Box::new(expression),
+                target: self.parse_type()?,
+            };
+            expression = self.spanned(start, cast);
+        }
+        Ok(expression)
+    }
+
+    /// Whether the token at `position` is an `as` followed by the start of a type, rather than
+    /// a variable named `as`.
+    fn is_cast(&self, position: usize) -> bool {
+        let is_as = self
+            .tokens
+            .get(position)
+            .is_some_and(|token| token.as_contextual_keyword(&TokenKind::As).is_some());
+        is_as
+            && matches!(
+                self.tokens.get(position + 1).map(|token| &token.kind),
+                Some(TokenKind::Identifier(_) | TokenKind::Fun)
+            )
+    }
+
+    fn parse_unary(&mut self) -> Result<Expression> {
+        self.nested(Self::parse_prefix_operators)
+    }
+
+    fn parse_prefix_operators(&mut self) -> Result<Expression> {
+        let start = self.position;
+        let expression = match self.peek() {
+            Some(TokenKind::Not) => {
+                self.position += 1;
+                ExpressionKind::UnaryOperation {
+                    operator: UnaryOperator::Not,
+                    operand: Box::new(self.parse_unary()?),
+                }
+            }
+            Some(TokenKind::Minus) => {
+                self.position += 1;
+                ExpressionKind::UnaryOperation {
+                    operator: UnaryOperator::Negate,
+                    operand: Box::new(self.parse_unary()?),
+                }
+            }
+            Some(TokenKind::Star) => {
+                self.position += 1;
+                ExpressionKind::Dereference(Box::new(self.parse_unary()?))
+            }
+            Some(TokenKind::Ampersand) => {
+                self.position += 1;
+                let is_mutable = self.eat(&TokenKind::Mut);
+                ExpressionKind::Reference {
+                    is_mutable,
+                    expression: Box::new(self.parse_unary()?),
+                }
+            }
+            // `&&x` is lexed as one token but means a reference to a reference.
+            Some(TokenKind::DoubleAmpersand) => {
+                self.position += 1;
+                let is_mutable = self.eat(&TokenKind::Mut);
+                let inner = ExpressionKind::Reference {
+                    is_mutable,
+                    expression: Box::new(self.parse_unary()?),
+                };
+                ExpressionKind::Reference {
+                    is_mutable: false,
+                    expression: Box::new(self.spanned(start, inner)),
+                }
+            }
+            _ => return self.parse_postfix(),
+        };
+        Ok(self.spanned(start, expression))
+    }
+
+    /// Calls `f(x)`, field accesses `a.b`, method calls `a.b(x)`, and indexing `a[i]`, all
+    /// left-associative.
+    fn parse_postfix(&mut self) -> Result<Expression> {
+        self.chain(Self::parse_postfix_chain)
+    }
+
+    fn parse_postfix_chain(&mut self) -> Result<Expression> {
+        let start = self.position;
+        let mut expression = self.parse_primary()?;
+        loop {
+            if matches!(
+                self.peek(),
+                Some(TokenKind::LeftParen | TokenKind::Dot | TokenKind::LeftBracket)
+            ) {
+                self.link()?;
+            }
+            let postfix = match self.peek() {
+                Some(TokenKind::LeftParen) => {
+                    self.position += 1;
+                    let arguments = self.parse_comma_separated(&TokenKind::RightParen)?;
+                    ExpressionKind::FunctionCall {
+                        function: Box::new(expression),
+                        arguments,
+                    }
+                }
+                Some(TokenKind::Dot) => {
+                    self.position += 1;
+                    let name = self.expect_identifier("field or method name")?;
+                    if self.eat(&TokenKind::LeftParen) {
+                        ExpressionKind::MethodCall {
+                            receiver: Box::new(expression),
+                            method: name,
+                            arguments: self.parse_comma_separated(&TokenKind::RightParen)?,
+                        }
+                    } else {
+                        ExpressionKind::RecordAccess {
+                            record: Box::new(expression),
+                            field: name,
+                        }
+                    }
+                }
+                Some(TokenKind::LeftBracket) => {
+                    self.position += 1;
+                    let index = self.with_record_literals(true, Self::parse_expression)?;
+                    self.expect(&TokenKind::RightBracket)?;
+                    ExpressionKind::IndexAccess {
+                        collection: Box::new(expression),
+                        index: Box::new(index),
+                    }
+                }
+                _ => return Ok(expression),
+            };
+            expression = self.spanned(start, postfix);
+        }
+    }
+
+    /// Expressions separated by commas up to and including `close`, allowing a trailing comma.
+    fn parse_comma_separated(&mut self, close: &TokenKind) -> Result<Vec<Expression>> {
+        self.with_record_literals(true, |parser| {
+            let mut expressions = Vec::new();
+            while !parser.check(close) {
+                expressions.push(parser.parse_expression()?);
+                if !parser.eat(&TokenKind::Comma) {
+                    break;
+                }
+            }
+            parser.expect(close)?;
+            Ok(expressions)
+        })
+    }
+
+    /// A literal, name, parenthesized expression, or an expression that starts with a keyword
+    /// or bracket. Those parsed by a function of their own record their spans there.
+    fn parse_primary(&mut self) -> Result<Expression> {
+        let start = self.position;
+        if let Some(literal) = self.peek().and_then(token_literal) {
+            self.position += 1;
+            return Ok(self.spanned(start, ExpressionKind::Literal(literal)));
+        }
+
+        let expression = match self.peek() {
+            Some(TokenKind::Identifier(name)) => {
+                let name = name.clone();
+                self.position += 1;
+                if self.check(&TokenKind::LeftBrace) && !self.no_record_literals {
+                    return self.parse_record_literal(start, Type::Named(name));
+                }
+                ExpressionKind::Identifier(name)
+            }
+            Some(TokenKind::LeftParen) => {
+                self.position += 1;
+                if self.eat(&TokenKind::RightParen) {
+                    return Ok(self.spanned(start, ExpressionKind::Literal(Literal::Unit)));
+                }
+                let expression = self.with_record_literals(true, Self::parse_expression)?;
+                self.expect(&TokenKind::RightParen)?;
+                if !self.keep_parentheses {
+                    return Ok(expression);
+                }
+                ExpressionKind::Grouped(Box::new(expression))
+            }
+            Some(TokenKind::LeftBracket) => {
+                self.position += 1;
+                let elements = self.parse_comma_separated(&TokenKind::RightBracket)?;
+                ExpressionKind::ArrayLiteral(elements)
+            }
+            Some(TokenKind::LeftBrace) => return self.parse_block(),
+            Some(TokenKind::If) => return self.parse_if(),
+            Some(TokenKind::Loop) => {
+                self.position += 1;
+                let body = self.parse_block()?;
+                ExpressionKind::Loop {
+                    body: Box::new(body),
+                }
+            }
+            Some(TokenKind::While) => {
+                self.position += 1;
+                let condition = self.with_record_literals(false, Self::parse_expression)?;
+                let body = self.parse_block()?;
+                ExpressionKind::While {
+                    condition: Box::new(condition),
+                    body: Box::new(body),
+                }
+            }
+            Some(TokenKind::For) => return self.parse_for(),
+            Some(TokenKind::When) => return self.parse_when(),
+            Some(TokenKind::Pipe | TokenKind::DoublePipe) => return self.parse_closure(),
+            _ => return Err(self.error("expression")),
+        };
+        Ok(self.spanned(start, expression))
+    }
+
+    /// `name { field: value, ... }`, with the type name, which starts at `start`, already
+    /// consumed.
+    fn parse_record_literal(&mut self, start: usize, record_type: Type) -> Result<Expression> {
+        self.expect(&TokenKind::LeftBrace)?;
+        self.with_record_literals(true, |parser| {
+            let mut fields = Vec::new();
+            let mut base = None;
+            while !parser.check(&TokenKind::RightBrace) {
+                // `..base` must come last, so it ends the field list.
+                if parser.eat(&TokenKind::Range) {
+                    base = Some(Box::new(parser.parse_expression()?));
+                    break;
+                }
+                let name = parser.expect_identifier("field name")?;
+                parser.expect(&TokenKind::Colon)?;
+                fields.push((name, parser.parse_expression()?));
+                if !parser.eat(&TokenKind::Comma) {
+                    break;
+                }
+            }
+            parser.expect(&TokenKind::RightBrace)?;
+
+            Ok(parser.spanned(
+                start,
+                ExpressionKind::RecordLiteral {
+                    record_type,
+                    fields,
+                    base,
+                },
+            ))
+        })
+    }
+
+    /// `if condition { ... } [else if ... | else { ... }]`
+    fn parse_if(&mut self) -> Result<Expression> {
+        let start = self.position;
+        self.expect(&TokenKind::If)?;
+        let condition = self.with_record_literals(false, Self::parse_expression)?;
+        let then_branch = self.parse_block()?;
+
+        let else_branch = if self.eat(&TokenKind::Else) {
+            let branch = if self.check(&TokenKind::If) {
+                self.nested(Self::parse_if)?
+            } else {
+                self.parse_block()?
+            };
+            Some(Box::new(branch))
+        } else {
+            None
+        };
+
+        let expression = ExpressionKind::If {
+            condition: Box::new(condition),
+            then_branch: Box::new(then_branch),
+            else_branch,
+        };
+        Ok(self.spanned(start, expression))
+    }
+
+    /// `|params| body` or `|params| -> type { ... }`, where `||` starts a closure without
+    /// parameters. A parameter is a name, optionally preceded by a type and `@`.
+    fn parse_closure(&mut self) -> Result<Expression> {
+        let start = self.position;
+        let mut params = Vec::new();
+        if !self.eat(&TokenKind::DoublePipe) {
+            self.expect(&TokenKind::Pipe)?;
+            while !self.eat(&TokenKind::Pipe) {
+                params.push(self.parse_closure_parameter()?);
+                if !self.eat(&TokenKind::Comma) {
+                    self.expect(&TokenKind::Pipe)?;
+                    break;
+                }
+            }
+        }
+
+        // As with functions, an explicit return type must be followed by a block body.
+        let (return_type, body) = if self.eat(&TokenKind::RightArrow) {
+            (Some(self.parse_type()?), self.parse_block()?)
+        } else {
+            (None, self.parse_expression()?)
+        };
+
+        let expression = ExpressionKind::Closure {
+            params,
+            return_type,
+            body: Box::new(body),
+            captures: vec![],
+        };
+        Ok(self.spanned(start, expression))
+    }
+
+    fn parse_closure_parameter(&mut self) -> Result<Parameter> {
+        let is_bare_name = matches!(
+            (self.peek(), self.peek_nth(1)),
+            (
+                Some(TokenKind::Identifier(_)),
+                Some(TokenKind::Comma | TokenKind::Pipe)
+            )
+        );
+        if !is_bare_name && !self.check(&TokenKind::Mut) {
+            return self.parse_parameter();
+        }
+
+        let is_mutable = self.eat(&TokenKind::Mut);
+        let name_position = self.position;
+        let name = self.expect_identifier("parameter name")?;
+        Ok(Parameter {
+            id: self.declared(name_position),
+            name,
+            param_type: Type::Inferred,
+            is_mutable,
+            is_ref: false,
+        })
+    }
+
+    /// `when value { pattern: expression; ... }`. Each branch ends with `;`, which may be left out
+    /// after the last branch or after a block-like body.
+    fn parse_when(&mut self) -> Result<Expression> {
+        let start = self.position;
+        self.expect(&TokenKind::When)?;
+        let expression = self.with_record_literals(false, Self::parse_expression)?;
+
+        self.expect(&TokenKind::LeftBrace)?;
+        let branches = self.with_record_literals(true, |parser| {
+            let mut branches = Vec::new();
+            while !parser.eat(&TokenKind::RightBrace) {
+                let pattern = parser.parse_pattern()?;
+                let guard = if parser.eat(&TokenKind::If) {
+                    Some(Box::new(parser.parse_expression()?))
+                } else {
+                    None
+                };
+                parser.expect(&TokenKind::Colon)?;
+                let body = parser.parse_expression()?;
+
+                if !parser.eat(&TokenKind::Semicolon)
+                    && !parser.check(&TokenKind::RightBrace)
+                    && body.requires_semicolon()
+                {
+                    return Err(parser.error("';'"));
+                }
+                branches.push(WhenBranch {
+                    id: parser.ids.fresh(),
+                    pattern,
+                    guard,
+                    body: Box::new(body),
+                });
+            }
+            Ok(branches)
+        })?;
+
+        let expression = ExpressionKind::When {
+            expression: Box::new(expression),
+            branches,
+        };
+        Ok(self.spanned(start, expression))
+    }
+
+    /// A pattern, or several alternatives separated by `|`.
+    fn parse_pattern(&mut self) -> Result<Pattern> {
+        self.nested(|parser| {
+            let start = parser.position;
+            let first = parser.parse_single_pattern()?;
+            if !parser.check(&TokenKind::Pipe) {
+                return Ok(first);
+            }
+
+            let mut alternatives = vec![first];
+            while parser.eat(&TokenKind::Pipe) {
+                alternatives.push(parser.parse_single_pattern()?);
+            }
+            let pattern = parser.pattern(PatternKind::Or(alternatives));
+            parser.spans.record(pattern.id, parser.span_from(start));
+            Ok(pattern)
+        })
+    }
+
+    /// A pattern other than an or-pattern, recording where it was written.
+    fn parse_single_pattern(&mut self) -> Result<Pattern> {
+        let start = self.position;
+        let pattern = self.parse_alternative()?;
+        self.spans.record(pattern.id, self.span_from(start));
+        Ok(pattern)
+    }
+
+    /// A `when` pattern: `else`, `_`, a literal (optionally negated), a range between two
+    /// literals, a binding name, a union variant with a pattern for its payload, a record pattern,
+    /// or a parenthesized tuple pattern.
+    fn parse_alternative(&mut self) -> Result<Pattern> {
+        if let Some(start) = self.parse_pattern_literal()? {
+            let inclusive = match self.peek() {
+                Some(TokenKind::Range) => false,
+                Some(TokenKind::RangeInclusive) => true,
+                _ => return Ok(self.pattern(PatternKind::Literal(start))),
+            };
+            self.position += 1;
+            let Some(end) = self.parse_pattern_literal()? else {
+                return Err(self.error("range end"));
+            };
+            return Ok(self.pattern(PatternKind::Range {
+                start,
+                end,
+                inclusive,
+            }));
+        }
+
+        match self.peek() {
+            Some(TokenKind::Else) => {
+                self.position += 1;
+                Ok(self.pattern(PatternKind::Else))
+            }
+            Some(TokenKind::Identifier(name)) if name == "_" => {
+                self.position += 1;
+                Ok(self.pattern(PatternKind::Wildcard))
+            }
+            Some(TokenKind::Identifier(name)) => {
+                let name = name.clone();
+                self.position += 1;
+                if self.eat(&TokenKind::LeftParen) {
+                    let payload = self.parse_parenthesized_pattern()?;
+                    Ok(self.pattern(PatternKind::Union {
+                        variant: name,
+                        payload: Some(Box::new(payload)),
+                    }))
+                } else if self.eat(&TokenKind::LeftBrace) {
+                    self.parse_record_pattern(Type::Named(name))
+                } else {
+                    Ok(self.pattern(PatternKind::Identifier(name)))
+                }
+            }
+            Some(TokenKind::LeftParen) => {
+                self.position += 1;
+                self.parse_parenthesized_pattern()
+            }
+            _ => Err(self.error("pattern")),
+        }
+    }
+
+    /// A literal in a pattern, where numbers may be negated. Returns `None` without consuming
+    /// anything if the next token does not start a literal.
+    fn parse_pattern_literal(&mut self) -> Result<Option<Literal>> {
+        if let Some(literal) = self.peek().and_then(token_literal) {
+            self.position += 1;
+            return Ok(Some(literal));
+        }
+        if !self.eat(&TokenKind::Minus) {
+            return Ok(None);
+        }
+
+        let literal = match self.peek() {
+            Some(TokenKind::Number(NumberLiteral::Integer(value))) => Literal::Integer(-value),
+            Some(TokenKind::Number(NumberLiteral::Float(value))) => Literal::Float(-value),
+            _ => return Err(self.error("number")),
+        };
+        self.position += 1;
+        Ok(Some(literal))
+    }
+
+    /// The rest of a pattern after its `(`: `()` matches unit, a single pattern is only grouped,
+    /// and several comma-separated patterns form a tuple pattern.
+    fn parse_parenthesized_pattern(&mut self) -> Result<Pattern> {
+        if self.eat(&TokenKind::RightParen) {
+            return Ok(self.pattern(PatternKind::Literal(Literal::Unit)));
+        }
+
+        let mut elements = vec![self.parse_pattern()?];
+        while self.eat(&TokenKind::Comma) {
+            elements.push(self.parse_pattern()?);
+        }
+        self.expect(&TokenKind::RightParen)?;
+
+        Ok(if elements.len() == 1 {
+            elements.pop().unwrap()
+        } else {
+            self.pattern(PatternKind::Tuple(elements))
+        })
+    }
+
+    /// The fields of a record pattern after its `{`, as in `point { x, y: 0 }`.
+    fn parse_record_pattern(&mut self, record_type: Type) -> Result<Pattern> {
+        let mut fields = Vec::new();
+        while !self.eat(&TokenKind::RightBrace) {
+            let name = self.expect_identifier("field name")?;
+            let pattern = if self.eat(&TokenKind::Colon) {
+                self.parse_pattern()?
+            } else {
+                self.pattern(PatternKind::Identifier(name.clone()))
+            };
+            fields.push((name, pattern));
+
+            if !self.eat(&TokenKind::Comma) {
+                self.expect(&TokenKind::RightBrace)?;
+                break;
+            }
+        }
+
+        Ok(self.pattern(PatternKind::Record {
+            record_type,
+            fields,
+        }))
+    }
+
+    /// `for name in iterable { ... }`
+    fn parse_for(&mut self) -> Result<Expression> {
+        let start = self.position;
+        self.expect(&TokenKind::For)?;
+        let variable = self.expect_identifier("loop variable")?;
+        self.expect(&TokenKind::In)?;
+        let iterable = self.with_record_literals(false, Self::parse_expression)?;
+        let body = self.parse_block()?;
+
+        let expression = ExpressionKind::For {
+            variable,
+            iterable: Box::new(iterable),
+            body: Box::new(body),
+        };
+        Ok(self.spanned(start, expression))
+    }
+}
+
+fn is_assignment(kind: &TokenKind) -> bool {
+    matches!(
+        kind,
+        TokenKind::Equal
+            | TokenKind::PlusEqual
+            | TokenKind::MinusEqual
+            | TokenKind::TimesEqual
+            | TokenKind::DivideEqual
+            | TokenKind::ModuloEqual
+    )
+}
+
+/// The literal a token stands for, if it is a literal token.
+fn token_literal(kind: &TokenKind) -> Option<Literal> {
+    let literal = match kind {
+        TokenKind::Number(NumberLiteral::Integer(value)) => Literal::Integer(*value),
+        TokenKind::Number(NumberLiteral::Float(value)) => Literal::Float(*value),
+        TokenKind::String(text) => Literal::String(text.clone()),
+        TokenKind::Char(c) => Literal::Char(*c),
+        TokenKind::ByteString(bytes) => Literal::Bytes(bytes.clone()),
+        TokenKind::ByteChar(byte) => Literal::Integer(i128::from(*byte)),
+        TokenKind::True => Literal::Boolean(true),
+        TokenKind::False => Literal::Boolean(false),
+        _ => return None,
+    };
+    Some(literal)
+}
+
+/// The built-in type spelled `name`, if any.
+fn primitive_type(name: &str) -> Option<Type> {
+    let primitive = match name {
+        "i8" => Type::I8,
+        "i16" => Type::I16,
+        "i32" => Type::I32,
+        "i64" => Type::I64,
+        "u8" => Type::U8,
+        "u16" => Type::U16,
+        "u32" => Type::U32,
+        "u64" => Type::U64,
+        "isize" => Type::ISize,
+        "usize" => Type::USize,
+        "f32" => Type::F32,
+        "f64" => Type::F64,
+        "bool" => Type::Bool,
+        "char" => Type::Char,
+        "string" => Type::String,
+        "unit" => Type::Unit,
+        _ => return None,
+    };
+    Some(primitive)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node_id::NodeId;
+    use proptest::prelude::*;
+    use test_case::test_case;
+
+    fn parse_expr(source: &str) -> Expression {
+        let tokens = Lexer::new(source).tokenize().expect("Lexer error");
+        let mut parser = Parser::new(tokens);
+        let expression = parser.parse_expression().expect("Parse error");
+        assert_eq!(parser.peek(), None, "unparsed input after expression");
+        expression
+    }
+
+    fn parse_statement(source: &str) -> Statement {
+        match parse(source).expect("Parse error").declarations.as_slice() {
+            [Declaration::Statement(statement)] => statement.clone(),
+            declarations => panic!("Expected a single statement, got {:?}", declarations),
+        }
+    }
+
+    fn ident(name: &str) -> Box<Expression> {
+        Box::new(ExpressionKind::Identifier(name.to_string()).into())
+    }
+
+    fn int(value: i128) -> Box<Expression> {
+        Box::new(ExpressionKind::Literal(Literal::Integer(value)).into())
+    }
+
+    fn binary(
+        left: Box<Expression>,
+        operator: BinaryOperator,
+        right: Box<Expression>,
+    ) -> Box<Expression> {
+        Box::new(
+            ExpressionKind::BinaryOperation {
+                left,
+                operator,
+                right,
+            }
+            .into(),
+        )
+    }
+
+    #[test]
+    fn test_function_declaration() {
+        let program = parse("fn add(i32 a, i32& @b) -> i32 { a + *b }").expect("Parse error");
+
+        assert_eq!(
+            program.declarations,
+            vec![Declaration::Function(FunctionDeclaration {
+                id: NodeId::UNASSIGNED,
+                name: "add".to_string(),
+                is_public: false,
+                type_parameters: vec![],
+                params: vec![
+                    Parameter {
+                        id: NodeId::UNASSIGNED,
+                        name: "a".to_string(),
+                        param_type: Type::I32,
+                        is_mutable: false,
+                        is_ref: false,
+                    },
+                    Parameter {
+                        id: NodeId::UNASSIGNED,
+                        name: "b".to_string(),
+                        param_type: Type::Reference {
+                            is_mutable: false,
+                            ref_type: Box::new(Type::I32),
+                        },
+                        is_mutable: true,
+                        is_ref: true,
+                    },
+                ],
+                return_type: Some(Type::I32),
+                body: Box::new(
+                    ExpressionKind::Block {
+                        statements: vec![],
+                        final_expression: Some(binary(
+                            ident("a"),
+                            BinaryOperator::Add,
+                            Box::new(ExpressionKind::Dereference(ident("b")).into()),
+                        )),
+                    }
+                    .into()
+                ),
+            })]
+        );
+    }
+
+    #[test]
+    fn test_record_and_union_declarations() {
+        let program = parse(
+            "record point { x: i32; y: arrayList<f64>; }\n\
+             union shape = circle(f64) | square(point) | empty;",
+        )
+        .expect("Parse error");
+
+        assert_eq!(
+            program.declarations,
+            vec![
+                Declaration::Record(RecordDeclaration {
+                    id: NodeId::UNASSIGNED,
+                    name: "point".to_string(),
+                    is_public: false,
+                    type_parameters: vec![],
+                    fields: vec![
+                        RecordField {
+                            id: NodeId::UNASSIGNED,
+                            name: "x".to_string(),
+                            is_public: false,
+                            field_type: Type::I32,
+                        },
+                        RecordField {
+                            id: NodeId::UNASSIGNED,
+                            name: "y".to_string(),
+                            is_public: false,
+                            field_type: Type::ArrayList(Box::new(Type::F64)),
+                        },
+                    ],
+                }),
+                Declaration::Union(UnionDeclaration {
+                    id: NodeId::UNASSIGNED,
+                    name: "shape".to_string(),
+                    is_public: false,
+                    type_parameters: vec![],
+                    variants: vec![
+                        UnionVariant {
+                            id: NodeId::UNASSIGNED,
+                            name: "circle".to_string(),
+                            variant_type: Some(Type::F64),
+                        },
+                        UnionVariant {
+                            id: NodeId::UNASSIGNED,
+                            name: "square".to_string(),
+                            variant_type: Some(Type::Named("point".to_string())),
+                        },
+                        UnionVariant {
+                            id: NodeId::UNASSIGNED,
+                            name: "empty".to_string(),
+                            variant_type: None,
+                        },
+                    ],
+                }),
+            ]
+        );
+    }
+
+    fn type_parameter(name: &str, const_type: Option<Type>) -> TypeParameter {
+        TypeParameter {
+            id: NodeId::UNASSIGNED,
+            name: name.to_string(),
+            const_type,
+        }
+    }
+
+    fn variant(name: &str, variant_type: Option<Type>) -> UnionVariant {
+        UnionVariant {
+            id: NodeId::UNASSIGNED,
+            name: name.to_string(),
+            variant_type,
+        }
+    }
+
+    #[test]
+    fn test_generic_function_declaration() {
+        let program = parse(
+            "fn larger<T>(T a, T b) -> T { if a < b { b } else { a } }
+             patch arrayList<T> { fn map<U>(fn(T) -> U f) -> arrayList<U> { mapped(self, f) } }",
+        )
+        .expect("Parse error");
+
+        match program.declarations.as_slice() {
+            [Declaration::Function(larger), Declaration::Patch(patch)] => {
+                assert_eq!(larger.type_parameters, vec![type_parameter("T", None)]);
+                assert_eq!(larger.return_type, Some(Type::Named("T".to_string())));
+                assert_eq!(
+                    patch.methods[0].type_parameters,
+                    vec![type_parameter("U", None)]
+                );
+            }
+            declarations => panic!("Expected a function and a patch, got {:?}", declarations),
+        }
+    }
+
+    #[test_case("union result<T, E> = ok(T) | err(E);" ; "pipe form")]
+    #[test_case("union result<T, E> { ok(T), err(E) }" ; "brace form")]
+    #[test_case("union result<T, E> {\n    ok(T),\n    err(E),\n}" ; "trailing comma")]
+    fn test_generic_union_declaration(source: &str) {
+        let named = |name: &str| Some(Type::Named(name.to_string()));
+
+        assert_eq!(
+            parse(source).expect("Parse error").declarations,
+            vec![Declaration::Union(UnionDeclaration {
+                id: NodeId::UNASSIGNED,
+                name: "result".to_string(),
+                is_public: false,
+                type_parameters: vec![type_parameter("T", None), type_parameter("E", None)],
+                variants: vec![variant("ok", named("T")), variant("err", named("E"))],
+            })]
+        );
+    }
+
+    #[test]
+    fn test_const_generic_record() {
+        let source = "record buffer<T, N: usize> { data: fixedArray<T, N>; length: usize; }";
+
+        assert_eq!(
+            parse(source).expect("Parse error").declarations,
+            vec![Declaration::Record(RecordDeclaration {
+                id: NodeId::UNASSIGNED,
+                name: "buffer".to_string(),
+                is_public: false,
+                type_parameters: vec![
+                    type_parameter("T", None),
+                    type_parameter("N", Some(Type::USize)),
+                ],
+                fields: vec![
+                    RecordField {
+                        id: NodeId::UNASSIGNED,
+                        name: "data".to_string(),
+                        is_public: false,
+                        field_type: Type::FixedArray {
+                            element_type: Box::new(Type::Named("T".to_string())),
+                            size: ArraySize::Parameter("N".to_string()),
+                        },
+                    },
+                    RecordField {
+                        id: NodeId::UNASSIGNED,
+                        name: "length".to_string(),
+                        is_public: false,
+                        field_type: Type::USize,
+                    },
+                ],
+            })]
+        );
+    }
+
+    #[test]
+    fn test_generic_union_payloads() {
+        let source = "union tree<T> { leaf, node(arrayList<tree<T>>), value(T&) }";
+
+        assert_eq!(
+            parse(source).expect("Parse error").declarations,
+            vec![Declaration::Union(UnionDeclaration {
+                id: NodeId::UNASSIGNED,
+                name: "tree".to_string(),
+                is_public: false,
+                type_parameters: vec![type_parameter("T", None)],
+                variants: vec![
+                    variant("leaf", None),
+                    variant(
+                        "node",
+                        Some(Type::ArrayList(Box::new(Type::Generic {
+                            name: "tree".to_string(),
+                            parameters: vec![Type::Named("T".to_string())],
+                        }))),
+                    ),
+                    variant(
+                        "value",
+                        Some(Type::Reference {
+                            is_mutable: false,
+                            ref_type: Box::new(Type::Named("T".to_string())),
+                        }),
+                    ),
+                ],
+            })]
+        );
+    }
+
+    #[test_case("union option<> = none;" ; "empty type parameters")]
+    #[test_case("union option<T = none;" ; "unclosed type parameters")]
+    #[test_case("union option<T> { some(T), none };" ; "semicolon after braces")]
+    #[test_case("union option<i32&> = none;" ; "type parameter is not a name")]
+    fn test_invalid_union_declaration(source: &str) {
+        assert!(matches!(
+            parse(source),
+            Err(ParseError::UnexpectedToken { .. })
+        ));
+    }
+
+    #[test]
+    fn test_patch_declaration() {
+        let program = parse("patch point { fn sum() -> i32 { self.x + self.y } fn zero() {} }")
+            .expect("Parse error");
+
+        match program.declarations.as_slice() {
+            [Declaration::Patch(patch)] => {
+                assert_eq!(patch.target_type, Type::Named("point".to_string()));
+                let names: Vec<&str> = patch.methods.iter().map(|m| m.name.as_str()).collect();
+                assert_eq!(names, ["sum", "zero"]);
+            }
+            declarations => panic!("Expected a patch, got {:?}", declarations),
+        }
+    }
+
+    #[test]
+    fn test_const_declaration() {
+        let program = parse("const fixedArray<u8, 2> magic = [202, 254];").expect("Parse error");
+
+        assert_eq!(
+            program.declarations,
+            vec![Declaration::Const(ConstDeclaration {
+                id: NodeId::UNASSIGNED,
+                name: "magic".to_string(),
+                is_public: false,
+                const_type: Type::FixedArray {
+                    element_type: Box::new(Type::U8),
+                    size: ArraySize::Literal(2),
+                },
+                value: Box::new(ExpressionKind::ArrayLiteral(vec![*int(202), *int(254)]).into()),
+            })]
+        );
+    }
+
+    #[test_case("const limit = 10;" ; "missing type")]
+    #[test_case("const i32 limit;" ; "missing value")]
+    #[test_case("const i32 limit = 10" ; "missing semicolon")]
+    fn test_malformed_const(source: &str) {
+        assert!(parse(source).is_err());
+    }
+
+    #[test]
+    fn test_public_declarations() {
+        let program = parse(
+            "pub fn area() {} pub record point { pub x: i32; y: i32; } pub union shape = dot; \
+             pub const i32 limit = 1; fn helper() {}",
+        )
+        .expect("Parse error");
+
+        let [
+            Declaration::Function(area),
+            Declaration::Record(point),
+            Declaration::Union(shape),
+            Declaration::Const(limit),
+            Declaration::Function(helper),
+        ] = program.declarations.as_slice()
+        else {
+            panic!("Unexpected declarations {:?}", program.declarations);
+        };
+        assert!(area.is_public && point.is_public && shape.is_public && limit.is_public);
+        assert!(!helper.is_public);
+        let fields: Vec<bool> = point.fields.iter().map(|field| field.is_public).collect();
+        assert_eq!(fields, [true, false]);
+    }
+
+    #[test_case("pub = 1; print(pub);" ; "variable")]
+    #[test_case("record flags { pub: bool; }" ; "field")]
+    #[test_case("import = 1; import += 1;" ; "import variable")]
+    fn test_contextual_keywords_as_names(source: &str) {
+        let program = parse(source).expect("Parse error");
+        assert_eq!(parse(&program.to_source()), Ok(program));
+    }
+
+    #[test_case("pub patch i32 {}" ; "patch")]
+    #[test_case("import geometry;" ; "no item")]
+    #[test_case("import geometry::{};" ; "empty list")]
+    #[test_case("import geometry::area" ; "missing semicolon")]
+    fn test_malformed_module_syntax(source: &str) {
+        assert!(parse(source).is_err());
+    }
+
+    #[test_case("import geometry::area;", &["area"] ; "one item")]
+    #[test_case("import geometry::{area, point,};", &["area", "point"] ; "list")]
+    fn test_import(source: &str, items: &[&str]) {
+        assert_eq!(
+            parse(source).expect("Parse error").declarations,
+            [Declaration::Import(ImportDeclaration {
+                id: NodeId::UNASSIGNED,
+                module: "geometry".to_string(),
+                items: items.iter().map(ToString::to_string).collect(),
+            })]
+        );
+    }
+
+    #[test_case("patch i32 {}", Type::I32 ; "built-in type")]
+    #[test_case("patch arrayList<T> {}", Type::ArrayList(Box::new(Type::Named("T".to_string()))) ; "built-in generic type")]
+    #[test_case("patch result<T, E> {}", Type::Generic { name: "result".to_string(), parameters: vec![Type::Named("T".to_string()), Type::Named("E".to_string())] } ; "generic type")]
+    fn test_patch_target(source: &str, expected: Type) {
+        match parse(source).expect("Parse error").declarations.as_slice() {
+            [Declaration::Patch(patch)] => assert_eq!(patch.target_type, expected),
+            declarations => panic!("Expected a patch, got {:?}", declarations),
+        }
+    }
+
+    #[test_case("patch point { x = 1; }", TokenKind::Identifier("x".to_string()), 14 ; "statement")]
+    #[test_case("patch point { record inner {} }", TokenKind::Record, 14 ; "record")]
+    #[test_case("patch point { fn f() {} patch point {} }", TokenKind::Patch, 24 ; "nested patch")]
+    fn test_non_function_in_patch(source: &str, found: TokenKind, start: usize) {
+        let end = start + found.to_string().chars().count() - 1;
+
+        assert_eq!(
+            parse(source),
+            Err(ParseError::NonFunctionInPatch {
+                found,
+                span: Span {
+                    file: Default::default(),
+                    start,
+                    end,
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_unclosed_patch() {
+        assert_eq!(
+            parse("patch point { fn f() {}"),
+            Err(ParseError::UnexpectedEof {
+                expected: "'fn' or '}'".to_string(),
+                span: Span {
+                    file: FileId::default(),
+                    start: 23,
+                    end: 23,
+                },
+            })
+        );
+    }
+
+    fn parse_type_str(source: &str) -> Result<Type> {
+        let tokens = Lexer::new(source).tokenize().expect("Lexer error");
+        let mut parser = Parser::new(tokens);
+        let parsed = parser.parse_type()?;
+        assert_eq!(parser.peek(), None, "unparsed input after type");
+        Ok(parsed)
+    }
+
+    fn named(name: &str) -> Type {
+        Type::Named(name.to_string())
+    }
+
+    fn reference(is_mutable: bool, ref_type: Type) -> Type {
+        Type::Reference {
+            is_mutable,
+            ref_type: Box::new(ref_type),
+        }
+    }
+
+    #[test_case("u64", Type::U64 ; "primitive")]
+    #[test_case("userProfile", named("userProfile") ; "named type")]
+    #[test_case("_", Type::Inferred ; "inferred")]
+    #[test_case("fn(unit) -> unit", Type::Function { param_types: vec![Type::Unit], return_type: Some(Box::new(Type::Unit)) } ; "unit")]
+    #[test_case("hashMap<string, arrayList<option<T>>>", Type::Generic { name: "hashMap".to_string(), parameters: vec![Type::String, Type::ArrayList(Box::new(Type::Generic { name: "option".to_string(), parameters: vec![named("T")] }))] } ; "nested generics")]
+    #[test_case("fixedArray<u8, 16>", Type::FixedArray { element_type: Box::new(Type::U8), size: ArraySize::Literal(16) } ; "fixed array")]
+    #[test_case("fixedArray<u8, N>", Type::FixedArray { element_type: Box::new(Type::U8), size: ArraySize::Parameter("N".to_string()) } ; "fixed array of const parameter size")]
+    #[test_case("fixedArray<u8, 2 * N>", Type::FixedArray { element_type: Box::new(Type::U8), size: ArraySize::Expression(binary(int(2), BinaryOperator::Multiply, ident("N"))) } ; "fixed array of computed size")]
+    #[test_case("buffer<u8, 16>", Type::Generic { name: "buffer".to_string(), parameters: vec![Type::U8, Type::ConstValue(16)] } ; "const argument")]
+    #[test_case("string&", reference(false, Type::String) ; "immutable reference")]
+    #[test_case("string&@", reference(true, Type::String) ; "mutable reference")]
+    #[test_case("i32&&@", reference(true, reference(false, Type::I32)) ; "reference to reference")]
+    #[test_case("arrayList<point&@>", Type::ArrayList(Box::new(reference(true, named("point")))) ; "mutable reference argument")]
+    #[test_case("fn(i32, string&) -> bool", Type::Function { param_types: vec![Type::I32, reference(false, Type::String)], return_type: Some(Box::new(Type::Bool)) } ; "function")]
+    #[test_case("fn()", Type::Function { param_types: vec![], return_type: None } ; "function without return type")]
+    #[test_case("fn(fn(T) -> U) -> fn() -> U", Type::Function { param_types: vec![Type::Function { param_types: vec![named("T")], return_type: Some(Box::new(named("U"))) }], return_type: Some(Box::new(Type::Function { param_types: vec![], return_type: Some(Box::new(named("U"))) })) } ; "higher order function")]
+    fn test_type_grammar(source: &str, expected: Type) {
+        assert_eq!(parse_type_str(source), Ok(expected.clone()));
+        assert_eq!(parse_type_str(&expected.to_string()), Ok(expected));
+    }
+
+    #[test_case("arrayList", 1, 0 ; "array list without argument")]
+    #[test_case("arrayList<i32, i32>", 1, 2 ; "array list with two arguments")]
+    #[test_case("fixedArray<i32>", 2, 1 ; "fixed array without size")]
+    #[test_case("i32<T>", 0, 1 ; "primitive with argument")]
+    fn test_wrong_type_argument_count(source: &str, expected: usize, found: usize) {
+        assert!(matches!(
+            parse_type_str(source),
+            Err(ParseError::WrongTypeArgumentCount { expected: e, found: f, .. })
+                if e == expected && f == found
+        ));
+    }
+
+    #[test_case("fixedArray<i32, n == 1>", "'>'" ; "size is a comparison")]
+    #[test_case("arrayList<16>", "type" ; "const argument to built in generic")]
+    #[test_case("hashMap<K V>", "'>'" ; "missing comma")]
+    #[test_case("<i32>", "type" ; "missing name")]
+    fn test_malformed_type(source: &str, expected: &str) {
+        match parse_type_str(source) {
+            Err(ParseError::UnexpectedToken { expected: e, .. }) => assert_eq!(e, expected),
+            result => panic!("Expected an unexpected token error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_mutable_binding_of_reference_type() {
+        assert_eq!(
+            parse_statement("string& @buffer_ref = &buffer;"),
+            StatementKind::VariableDeclaration {
+                name: "buffer_ref".to_string(),
+                var_type: Some(reference(false, Type::String)),
+                is_mutable: true,
+                value: Box::new(
+                    ExpressionKind::Reference {
+                        is_mutable: false,
+                        expression: ident("buffer"),
+                    }
+                    .into()
+                ),
+            }
+            .into()
+        );
+    }
+
+    #[test_case("x = 1;", None, false ; "inferred")]
+    #[test_case("@x = 1;", None, true ; "inferred mutable")]
+    #[test_case("i32 x = 1;", Some(Type::I32), false ; "typed")]
+    #[test_case("arrayList<i32> @x = 1;", Some(Type::ArrayList(Box::new(Type::I32))), true ; "generic mutable")]
+    #[test_case("string& @x = 1;", Some(Type::Reference { is_mutable: false, ref_type: Box::new(Type::String) }), true ; "reference")]
+    fn test_variable_declaration(source: &str, var_type: Option<Type>, is_mutable: bool) {
+        assert_eq!(
+            parse_statement(source),
+            StatementKind::VariableDeclaration {
+                name: "x".to_string(),
+                var_type,
+                is_mutable,
+                value: int(1),
+            }
+            .into()
+        );
+    }
+
+    #[test]
+    fn test_assignment_is_not_a_declaration() {
+        assert_eq!(
+            parse_statement("p.x = y + 2;"),
+            StatementKind::Assignment {
+                target: Box::new(
+                    ExpressionKind::RecordAccess {
+                        record: ident("p"),
+                        field: "x".to_string(),
+                    }
+                    .into()
+                ),
+                operator: BinaryOperator::Assign,
+                value: binary(ident("y"), BinaryOperator::Add, int(2)),
+            }
+            .into()
+        );
+    }
+
+    #[test_case("(x) = 1;", BinaryOperator::Assign ; "parenthesized variable")]
+    #[test_case("xs[0] -= 1;", BinaryOperator::SubtractAssign ; "element")]
+    #[test_case("*p %= 2;", BinaryOperator::ModulusAssign ; "dereference")]
+    fn test_assignment_statement(source: &str, expected: BinaryOperator) {
+        let StatementKind::Assignment { operator, .. } = &parse_statement(source).kind else {
+            panic!("expected an assignment");
+        };
+        assert_eq!(*operator, expected);
+    }
+
+    #[test_case("a + b = 1;" ; "binary target")]
+    #[test_case("f() += 1;" ; "call target")]
+    #[test_case("1 = x;" ; "literal target")]
+    fn test_invalid_assignment_target(source: &str) {
+        assert!(matches!(
+            parse(source),
+            Err(ParseError::InvalidAssignmentTarget { .. })
+        ));
+    }
+
+    #[test]
+    fn test_kept_parentheses() {
+        let source = "(x) = (a + 1) * b;";
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let program = Parser::new(tokens)
+            .keep_parentheses()
+            .parse_program()
+            .expect("Parse error");
+
+        assert_eq!(
+            program.declarations,
+            [Declaration::Statement(
+                StatementKind::Assignment {
+                    target: Box::new(ExpressionKind::Grouped(ident("x")).into()),
+                    operator: BinaryOperator::Assign,
+                    value: binary(
+                        Box::new(
+                            ExpressionKind::Grouped(binary(
+                                ident("a"),
+                                BinaryOperator::Add,
+                                Box::new(ExpressionKind::Literal(Literal::Integer(1)).into())
+                            ))
+                            .into()
+                        ),
+                        BinaryOperator::Multiply,
+                        ident("b")
+                    ),
+                }
+                .into()
+            )]
+        );
+        assert_eq!(
+            parse_statement(source),
+            StatementKind::Assignment {
+                target: ident("x"),
+                operator: BinaryOperator::Assign,
+                value: binary(
+                    binary(
+                        ident("a"),
+                        BinaryOperator::Add,
+                        Box::new(ExpressionKind::Literal(Literal::Integer(1)).into())
+                    ),
+                    BinaryOperator::Multiply,
+                    ident("b")
+                ),
+            }
+            .into()
+        );
+    }
+
+    #[test_case("a = b = c;" ; "chained")]
+    #[test_case("x = a * (b = c);" ; "inside an expression")]
+    #[test_case("f(x += 1);" ; "as an argument")]
+    #[test_case("fn f() { (x) = 1 }" ; "missing semicolon")]
+    fn test_assignment_is_not_an_expression(source: &str) {
+        assert!(parse(source).is_err());
+    }
+
+    #[test]
+    fn test_comparison_is_not_a_typed_declaration() {
+        assert_eq!(
+            parse_statement("a < b;"),
+            StatementKind::Expression(binary(ident("a"), BinaryOperator::LessThan, ident("b")))
+                .into()
+        );
+    }
+
+    #[test]
+    fn test_binary_precedence() {
+        assert_eq!(
+            parse_expr("1 + 2 * 3 == 7 and not done || x"),
+            *binary(
+                binary(
+                    binary(
+                        binary(
+                            int(1),
+                            BinaryOperator::Add,
+                            binary(int(2), BinaryOperator::Multiply, int(3))
+                        ),
+                        BinaryOperator::Equal,
+                        int(7),
+                    ),
+                    BinaryOperator::And,
+                    Box::new(
+                        ExpressionKind::UnaryOperation {
+                            operator: UnaryOperator::Not,
+                            operand: ident("done"),
+                        }
+                        .into()
+                    ),
+                ),
+                BinaryOperator::Or,
+                ident("x"),
+            )
+        );
+    }
+
+    /// Render the shape of an expression tree with every operation fully parenthesized.
+    fn shape(expression: &Expression) -> String {
+        match &expression.kind {
+            ExpressionKind::Identifier(name) => name.clone(),
+            ExpressionKind::Literal(Literal::Integer(value)) => value.to_string(),
+            ExpressionKind::Literal(Literal::Unit) => "()".to_string(),
+            ExpressionKind::BinaryOperation {
+                left,
+                operator,
+                right,
+            } => format!("({} {} {})", shape(left), operator, shape(right)),
+            ExpressionKind::UnaryOperation { operator, operand } => {
+                format!("({} {})", operator, shape(operand))
+            }
+            ExpressionKind::Reference {
+                is_mutable,
+                expression,
+            } => format!(
+                "({} {})",
+                if *is_mutable { "&@" } else { "&" },
+                shape(expression)
+            ),
+            ExpressionKind::Dereference(expression) => format!("(* {})", shape(expression)),
+            ExpressionKind::Range {
+                start,
+                end,
+                inclusive,
+            } => format!(
+                "({} {} {})",
+                shape(start),
+                if *inclusive { "..=" } else { ".." },
+                shape(end)
+            ),
+            ExpressionKind::FunctionCall {
+                function,
+                arguments,
+            } => {
+                let arguments: Vec<String> = arguments.iter().map(shape).collect();
+                format!("(call {} [{}])", shape(function), arguments.join(", "))
+            }
+            ExpressionKind::RecordAccess { record, field } => {
+                format!("(. {} {})", shape(record), field)
+            }
+            ExpressionKind::MethodCall {
+                receiver,
+                method,
+                arguments,
+            } => {
+                let arguments: Vec<String> = arguments.iter().map(shape).collect();
+                format!(
+                    "(method {} {} [{}])",
+                    shape(receiver),
+                    method,
+                    arguments.join(", ")
+                )
+            }
+            ExpressionKind::IndexAccess { collection, index } => {
+                format!("(index {} {})", shape(collection), shape(index))
+            }
+            ExpressionKind::Cast { expression, target } => {
+                format!("(as {} {})", shape(expression), target)
+            }
+            expression => format!("{:?}", expression),
+        }
+    }
+
+    #[test_case("a + b * c == d and not e", "(((a + (b * c)) == d) && (! e))" ; "mixed")]
+    #[test_case("a - b - c", "((a - b) - c)" ; "left associative")]
+    #[test_case("a % b / c * d", "(((a % b) / c) * d)" ; "multiplicative")]
+    #[test_case("a or b and c", "(a || (b && c))" ; "and binds tighter than or")]
+    #[test_case("a && b || c && d", "((a && b) || (c && d))" ; "symbolic logical")]
+    #[test_case("a < b == c >= d", "((a < b) == (c >= d))" ; "comparison binds tighter than equality")]
+    #[test_case("a != b and c <= d", "((a != b) && (c <= d))" ; "equality binds tighter than and")]
+    #[test_case("0..n + 1", "(0 .. (n + 1))" ; "range")]
+    #[test_case("0..=n or m", "(0 ..= (n || m))" ; "inclusive range")]
+    #[test_case("-a * b", "((- a) * b)" ; "negation")]
+    #[test_case("not a == b", "((! a) == b)" ; "not")]
+    #[test_case("*p.x + 1", "((* (. p x)) + 1)" ; "dereference")]
+    #[test_case("&@v[0]", "(&@ (index v 0))" ; "mutable reference")]
+    #[test_case("f(a, b + c).g[1]", "(index (. (call f [a, (b + c)]) g) 1)" ; "postfix")]
+    #[test_case("(a + b) * c", "((a + b) * c)" ; "grouping")]
+    #[test_case("f(()) == ()", "((call f [()]) == ())" ; "unit value")]
+    #[test_case("a + b as f64 * c", "(a + ((as b f64) * c))" ; "cast binds tighter than arithmetic")]
+    #[test_case("-x as u8", "(as (- x) u8)" ; "cast of a negation")]
+    #[test_case("x.len() as i32 as f32", "(as (as (method x len []) i32) f32)" ; "chained casts")]
+    #[test_case("p as point&", "(as p point&)" ; "reference cast")]
+    #[test_case("as + as", "(as + as)" ; "variable named as")]
+    fn test_expression_shape(source: &str, expected: &str) {
+        assert_eq!(shape(&parse_expr(source)), expected);
+    }
+
+    #[test]
+    fn test_ranges_do_not_chain() {
+        let tokens = Lexer::new("a..b..c").tokenize().expect("Lexer error");
+
+        assert!(matches!(
+            Parser::new(tokens).parse_expression(),
+            Err(ParseError::UnexpectedToken {
+                found: TokenKind::Range,
+                ..
+            })
+        ));
+    }
+
+    fn untyped(name: &str) -> Parameter {
+        Parameter {
+            id: NodeId::UNASSIGNED,
+            name: name.to_string(),
+            param_type: Type::Inferred,
+            is_mutable: false,
+            is_ref: false,
+        }
+    }
+
+    #[test]
+    fn test_closure() {
+        assert_eq!(
+            parse_expr("map(items, |x| x * 2)"),
+            ExpressionKind::FunctionCall {
+                function: ident("map"),
+                arguments: vec![
+                    ExpressionKind::Identifier("items".to_string()).into(),
+                    ExpressionKind::Closure {
+                        params: vec![untyped("x")],
+                        return_type: None,
+                        body: binary(ident("x"), BinaryOperator::Multiply, int(2)),
+                        captures: vec![],
+                    }
+                    .into(),
+                ],
+            }
+            .into()
+        );
+    }
+
+    #[test]
+    fn test_closure_without_parameters() {
+        assert_eq!(
+            parse_expr("|| 42"),
+            ExpressionKind::Closure {
+                params: vec![],
+                return_type: None,
+                body: int(42),
+                captures: vec![],
+            }
+            .into()
+        );
+    }
+
+    #[test]
+    fn test_typed_closure() {
+        assert_eq!(
+            parse_expr("|i32 a, @b, string& c| -> i32 { a }"),
+            ExpressionKind::Closure {
+                params: vec![
+                    Parameter {
+                        id: NodeId::UNASSIGNED,
+                        name: "a".to_string(),
+                        param_type: Type::I32,
+                        is_mutable: false,
+                        is_ref: false,
+                    },
+                    Parameter {
+                        is_mutable: true,
+                        ..untyped("b")
+                    },
+                    Parameter {
+                        id: NodeId::UNASSIGNED,
+                        name: "c".to_string(),
+                        param_type: reference(false, Type::String),
+                        is_mutable: false,
+                        is_ref: true,
+                    },
+                ],
+                return_type: Some(Type::I32),
+                body: Box::new(
+                    ExpressionKind::Block {
+                        statements: vec![],
+                        final_expression: Some(ident("a")),
+                    }
+                    .into()
+                ),
+                captures: vec![],
+            }
+            .into()
+        );
+    }
+
+    #[test]
+    fn test_curried_closure() {
+        assert_eq!(
+            parse_expr("|x| |y| x + y"),
+            ExpressionKind::Closure {
+                params: vec![untyped("x")],
+                return_type: None,
+                body: Box::new(
+                    ExpressionKind::Closure {
+                        params: vec![untyped("y")],
+                        return_type: None,
+                        body: binary(ident("x"), BinaryOperator::Add, ident("y")),
+                        captures: vec![],
+                    }
+                    .into()
+                ),
+                captures: vec![],
+            }
+            .into()
+        );
+    }
+
+    #[test]
+    fn test_closure_bound_to_variable() {
+        assert!(matches!(
+            parse_statement("double = |x| x * 2;").kind,
+            StatementKind::VariableDeclaration { value, .. } if matches!(&value.kind, ExpressionKind::Closure { .. })
+        ));
+    }
+
+    #[test_case("|x x + 1" ; "unclosed parameters")]
+    #[test_case("|x| -> i32 x" ; "return type without block")]
+    #[test_case("|1| x" ; "literal parameter")]
+    #[test_case("|x|" ; "missing body")]
+    fn test_malformed_closure(source: &str) {
+        let tokens = Lexer::new(source).tokenize().expect("Lexer error");
+
+        assert!(matches!(
+            Parser::new(tokens).parse_expression(),
+            Err(ParseError::UnexpectedToken { .. } | ParseError::UnexpectedEof { .. })
+        ));
+    }
+
+    #[test]
+    fn test_postfix_chain() {
+        assert_eq!(
+            parse_expr("input.trim()[0]"),
+            ExpressionKind::IndexAccess {
+                collection: Box::new(
+                    ExpressionKind::MethodCall {
+                        receiver: ident("input"),
+                        method: "trim".to_string(),
+                        arguments: vec![],
+                    }
+                    .into()
+                ),
+                index: int(0),
+            }
+            .into()
+        );
+    }
+
+    #[test_case("input.trim().replace(a, b)", "(method (method input trim []) replace [a, b])" ; "method chain")]
+    #[test_case("user.name.len()", "(method (. user name) len [])" ; "method on field")]
+    #[test_case("(user.callback)(x)", "(call (. user callback) [x])" ; "call of field")]
+    #[test_case("make().value", "(. (call make []) value)" ; "field of call result")]
+    #[test_case("rows.get(i)[0]", "(index (method rows get [i]) 0)" ; "index of method result")]
+    fn test_method_calls(source: &str, expected: &str) {
+        assert_eq!(shape(&parse_expr(source)), expected);
+    }
+
+    #[test]
+    fn test_references() {
+        assert_eq!(
+            parse_expr("&@buffer"),
+            ExpressionKind::Reference {
+                is_mutable: true,
+                expression: ident("buffer"),
+            }
+            .into()
+        );
+        assert_eq!(
+            parse_expr("&&x"),
+            ExpressionKind::Reference {
+                is_mutable: false,
+                expression: Box::new(
+                    ExpressionKind::Reference {
+                        is_mutable: false,
+                        expression: ident("x"),
+                    }
+                    .into()
+                ),
+            }
+            .into()
+        );
+    }
+
+    #[test_case("if a { f(); }\n-1;" ; "if then negative literal")]
+    #[test_case("if a { f(); } else { g(); }\n(b).h();" ; "if else then parentheses")]
+    #[test_case("when a { _: f(); }\n[1, 2].len();" ; "when then array")]
+    #[test_case("{ f(); }\n*p;" ; "block then dereference")]
+    #[test_case("loop { break; }\n&x;" ; "loop then reference")]
+    #[test_case("for x in xs { f(x); }\n- y;" ; "for then negation")]
+    #[test_case("while a { f(); }\n-1;" ; "while then negative literal")]
+    #[test_case("while a { f(); }\n(b).h();" ; "while then parentheses")]
+    fn test_block_like_statement_ends_statement(source: &str) {
+        let is_block_like = |statement: &Statement| match &statement.kind {
+            StatementKind::Expression(expression) => !expression.requires_semicolon(),
+            _ => false,
+        };
+
+        match parse(source).expect("Parse error").declarations.as_slice() {
+            [Declaration::Statement(first), Declaration::Statement(_)] => {
+                assert!(is_block_like(first), "{:?}", first);
+            }
+            declarations => panic!("Expected two statements, got {:?}", declarations),
+        }
+        match &parse_expr(&format!("{{ {} }}", source)).kind {
+            ExpressionKind::Block { statements, .. } if statements.len() == 2 => {
+                assert!(is_block_like(&statements[0]), "{:?}", statements[0]);
+            }
+            block => panic!("Expected a block of two statements, got {:?}", block),
+        }
+    }
+
+    #[test]
+    fn test_block_final_expression() {
+        assert_eq!(
+            parse_expr("{ x = 1; if x { 2 } else { 3 }; x }"),
+            ExpressionKind::Block {
+                statements: vec![
+                    StatementKind::VariableDeclaration {
+                        name: "x".to_string(),
+                        var_type: None,
+                        is_mutable: false,
+                        value: int(1),
+                    }
+                    .into(),
+                    StatementKind::Expression(Box::new(
+                        ExpressionKind::If {
+                            condition: ident("x"),
+                            then_branch: Box::new(
+                                ExpressionKind::Block {
+                                    statements: vec![],
+                                    final_expression: Some(int(2)),
+                                }
+                                .into()
+                            ),
+                            else_branch: Some(Box::new(
+                                ExpressionKind::Block {
+                                    statements: vec![],
+                                    final_expression: Some(int(3)),
+                                }
+                                .into()
+                            )),
+                        }
+                        .into()
+                    ))
+                    .into(),
+                ],
+                final_expression: Some(ident("x")),
+            }
+            .into()
+        );
+    }
+
+    #[test]
+    fn test_record_literal_not_parsed_in_condition() {
+        match &(parse_expr("if p { point { x: 1, y: 2 } }")).kind {
+            ExpressionKind::If {
+                condition,
+                then_branch,
+                ..
+            } => {
+                assert_eq!(*condition, ident("p"));
+                assert!(matches!(
+                    &then_branch.kind,
+                    ExpressionKind::Block {
+                        final_expression: Some(record),
+                        ..
+                    } if matches!(&record.kind, ExpressionKind::RecordLiteral { .. })
+                ));
+            }
+            expression => panic!("Expected an if expression, got {:?}", expression),
+        }
+    }
+
+    #[test]
+    fn test_record_update() {
+        assert_eq!(
+            parse_expr("point { x: 1, ..origin() }"),
+            ExpressionKind::RecordLiteral {
+                record_type: named("point"),
+                fields: vec![("x".to_string(), *int(1))],
+                base: Some(Box::new(
+                    ExpressionKind::FunctionCall {
+                        function: ident("origin"),
+                        arguments: vec![],
+                    }
+                    .into()
+                )),
+            }
+            .into()
+        );
+        assert!(matches!(
+            parse_expr("point { ..p }").kind,
+            ExpressionKind::RecordLiteral { fields, base: Some(_), .. } if fields.is_empty()
+        ));
+    }
+
+    #[test_case("point { ..p, x: 1 }" ; "base before fields")]
+    #[test_case("point { x: 1, ..p, }" ; "comma after base")]
+    #[test_case("point { x: 1, .. }" ; "missing base")]
+    fn test_malformed_record_update(source: &str) {
+        let tokens = Lexer::new(source).tokenize().expect("Lexer error");
+
+        assert!(Parser::new(tokens).parse_expression().is_err());
+    }
+
+    #[test]
+    fn test_for_over_range() {
+        assert_eq!(
+            parse_expr("for i in 0..=10 { total += i; }"),
+            ExpressionKind::For {
+                variable: "i".to_string(),
+                iterable: Box::new(
+                    ExpressionKind::Range {
+                        start: int(0),
+                        end: int(10),
+                        inclusive: true,
+                    }
+                    .into()
+                ),
+                body: Box::new(
+                    ExpressionKind::Block {
+                        statements: vec![
+                            StatementKind::Assignment {
+                                target: ident("total"),
+                                operator: BinaryOperator::AddAssign,
+                                value: ident("i"),
+                            }
+                            .into()
+                        ],
+                        final_expression: None,
+                    }
+                    .into()
+                ),
+            }
+            .into()
+        );
+    }
+
+    #[test]
+    fn test_while() {
+        assert_eq!(
+            parse_expr("while n < limit { n += 1; }"),
+            ExpressionKind::While {
+                condition: Box::new(
+                    ExpressionKind::BinaryOperation {
+                        left: ident("n"),
+                        operator: BinaryOperator::LessThan,
+                        right: ident("limit"),
+                    }
+                    .into()
+                ),
+                body: Box::new(
+                    ExpressionKind::Block {
+                        statements: vec![
+                            StatementKind::Assignment {
+                                target: ident("n"),
+                                operator: BinaryOperator::AddAssign,
+                                value: int(1),
+                            }
+                            .into()
+                        ],
+                        final_expression: None,
+                    }
+                    .into()
+                ),
+            }
+            .into()
+        );
+    }
+
+    fn binding(name: &str) -> Pattern {
+        PatternKind::Identifier(name.to_string()).into()
+    }
+
+    fn variant_pattern(variant: &str, payload: Pattern) -> Pattern {
+        PatternKind::Union {
+            variant: variant.to_string(),
+            payload: Some(Box::new(payload)),
+        }
+        .into()
+    }
+
+    fn branch(pattern: Pattern, body: Box<Expression>) -> WhenBranch {
+        WhenBranch {
+            id: NodeId::UNASSIGNED,
+            pattern,
+            guard: None,
+            body,
+        }
+    }
+
+    #[test]
+    fn test_when_expression() {
+        assert_eq!(
+            parse_statement("result = when value { 0: zero; n: n; else: other; };"),
+            StatementKind::VariableDeclaration {
+                name: "result".to_string(),
+                var_type: None,
+                is_mutable: false,
+                value: Box::new(
+                    ExpressionKind::When {
+                        expression: ident("value"),
+                        branches: vec![
+                            branch(
+                                PatternKind::Literal(Literal::Integer(0)).into(),
+                                ident("zero")
+                            ),
+                            branch(PatternKind::Identifier("n".to_string()).into(), ident("n")),
+                            branch(PatternKind::Else.into(), ident("other")),
+                        ],
+                    }
+                    .into()
+                ),
+            }
+            .into()
+        );
+    }
+
+    #[test_case("_", PatternKind::Wildcard.into() ; "wildcard")]
+    #[test_case("else", PatternKind::Else.into() ; "else branch")]
+    #[test_case("none", PatternKind::Identifier("none".to_string()).into() ; "identifier")]
+    #[test_case("'q'", PatternKind::Literal(Literal::Char('q')).into() ; "char")]
+    #[test_case("\"yes\"", PatternKind::Literal(Literal::String("yes".to_string())).into() ; "string")]
+    #[test_case("true", PatternKind::Literal(Literal::Boolean(true)).into() ; "boolean")]
+    #[test_case("-1", PatternKind::Literal(Literal::Integer(-1)).into() ; "negative integer")]
+    #[test_case("-0.5", PatternKind::Literal(Literal::Float(-0.5)).into() ; "negative float")]
+    #[test_case("ok(data)", variant_pattern("ok", binding("data")) ; "variant with binding")]
+    #[test_case("err(_)", variant_pattern("err", PatternKind::Wildcard.into()) ; "variant ignoring payload")]
+    #[test_case("ok(1)", variant_pattern("ok", PatternKind::Literal(Literal::Integer(1)).into()) ; "variant with literal payload")]
+    #[test_case("some(ok(value))", variant_pattern("some", variant_pattern("ok", binding("value"))) ; "nested variants")]
+    #[test_case("rectangle(w, _)", variant_pattern("rectangle", PatternKind::Tuple(vec![binding("w"), PatternKind::Wildcard.into()]).into()) ; "variant with tuple payload")]
+    #[test_case("(a, (b, 0))", PatternKind::Tuple(vec![binding("a"), PatternKind::Tuple(vec![binding("b"), PatternKind::Literal(Literal::Integer(0)).into()]).into()]).into() ; "nested tuples")]
+    #[test_case("(a)", binding("a") ; "grouped pattern")]
+    #[test_case("0..10", PatternKind::Range { start: Literal::Integer(0), end: Literal::Integer(10), inclusive: false }.into() ; "range")]
+    #[test_case("-128..=-1", PatternKind::Range { start: Literal::Integer(-128), end: Literal::Integer(-1), inclusive: true }.into() ; "negative inclusive range")]
+    #[test_case("'a'..='z' | '_'", PatternKind::Or(vec![PatternKind::Range { start: Literal::Char('a'), end: Literal::Char('z'), inclusive: true }.into(), PatternKind::Literal(Literal::Char('_')).into()]).into() ; "range alternative")]
+    #[test_case("1 | 2 | 3", PatternKind::Or(vec![PatternKind::Literal(Literal::Integer(1)).into(), PatternKind::Literal(Literal::Integer(2)).into(), PatternKind::Literal(Literal::Integer(3)).into()]).into() ; "or pattern")]
+    #[test_case("some(0 | -1)", variant_pattern("some", PatternKind::Or(vec![PatternKind::Literal(Literal::Integer(0)).into(), PatternKind::Literal(Literal::Integer(-1)).into()]).into()) ; "nested or pattern")]
+    #[test_case("()", PatternKind::Literal(Literal::Unit).into() ; "unit")]
+    #[test_case("point { x, y: 0 }", PatternKind::Record { record_type: named("point"), fields: vec![("x".to_string(), binding("x")), ("y".to_string(), PatternKind::Literal(Literal::Integer(0)).into())] }.into() ; "record")]
+    #[test_case("line { start: point { x: _, y }, }", PatternKind::Record { record_type: named("line"), fields: vec![("start".to_string(), PatternKind::Record { record_type: named("point"), fields: vec![("x".to_string(), PatternKind::Wildcard.into()), ("y".to_string(), binding("y"))] }.into())] }.into() ; "nested record with trailing comma")]
+    fn test_when_pattern(pattern: &str, expected: Pattern) {
+        match &(parse_expr(&format!("when x {{ {}: 1 }}", pattern))).kind {
+            ExpressionKind::When { branches, .. } => {
+                assert_eq!(*branches, vec![branch(expected, int(1))]);
+            }
+            expression => panic!("Expected a when expression, got {:?}", expression),
+        }
+    }
+
+    #[test]
+    fn test_when_guard() {
+        match &(parse_expr("when x { n if n > 0 and ok: 1; point { x } if x == y: 2; _: 3 }")).kind
+        {
+            ExpressionKind::When { branches, .. } => {
+                let guards: Vec<String> = branches
+                    .iter()
+                    .map(|branch| branch.guard.as_deref().map(shape).unwrap_or_default())
+                    .collect();
+                assert_eq!(guards, ["((n > 0) && ok)", "(x == y)", ""]);
+                assert_eq!(branches[0].pattern, binding("n"));
+            }
+            expression => panic!("Expected a when expression, got {:?}", expression),
+        }
+    }
+
+    #[test]
+    fn test_when_statement_with_block_bodies() {
+        let source = "when response {\n    ok(data): { log(data); process(data) }\n    err(msg): fail(msg);\n}";
+
+        match &parse_statement(source).kind {
+            StatementKind::Expression(expression) => match &expression.kind {
+                ExpressionKind::When { branches, .. } => {
+                    assert_eq!(branches.len(), 2);
+                    assert!(matches!(
+                        &branches[0].body.kind,
+                        ExpressionKind::Block { .. }
+                    ));
+                }
+                expression => panic!("Expected a when expression, got {:?}", expression),
+            },
+            statement => panic!("Expected an expression statement, got {:?}", statement),
+        }
+    }
+
+    #[test]
+    fn test_record_literal_not_parsed_in_when_subject() {
+        assert!(matches!(
+            parse_expr("when p { point: 1; }").kind,
+            ExpressionKind::When { ref expression, .. } if **expression == *ident("p")
+        ));
+    }
+
+    #[test_case("when x { 1 2 }" ; "missing colon")]
+    #[test_case("when x { 1: a 2: b }" ; "missing branch separator")]
+    #[test_case("when x { a + b: c }" ; "expression as pattern")]
+    #[test_case("when x { ok(a b): c }" ; "missing tuple comma")]
+    #[test_case("when x { point { x y }: c }" ; "missing field comma")]
+    #[test_case("when x { point { 0 }: c }" ; "field without name")]
+    #[test_case("when x { -y: c }" ; "negated identifier")]
+    #[test_case("when x { n if: c }" ; "empty guard")]
+    #[test_case("when x { 1 |: c }" ; "missing alternative")]
+    #[test_case("when x { 0..n: c }" ; "range to identifier")]
+    #[test_case("when x { 0..: c }" ; "range without end")]
+    fn test_when_syntax_errors(source: &str) {
+        let tokens = Lexer::new(source).tokenize().expect("Lexer error");
+
+        assert!(matches!(
+            Parser::new(tokens).parse_expression(),
+            Err(ParseError::UnexpectedToken { .. } | ParseError::UnclosedDelimiter { .. })
+        ));
+    }
+
+    #[test_case("x = 1" ; "missing semicolon at end of input")]
+    #[test_case("fn (i32 a) {}" ; "missing function name")]
+    #[test_case("record point { x i32; }" ; "missing field colon")]
+    #[test_case("union color = ;" ; "missing variant")]
+    #[test_case("f(1 2);" ; "missing comma")]
+    #[test_case("x = );" ; "missing expression")]
+    #[test_case("@1 = 2;" ; "mutable without name")]
+    #[test_case("{ a b }" ; "two expressions")]
+    fn test_syntax_errors(source: &str) {
+        assert!(
+            matches!(
+                parse(source),
+                Err(ParseError::UnexpectedToken { .. }
+                    | ParseError::UnexpectedEof { .. }
+                    | ParseError::MissingSemicolon { .. }
+                    | ParseError::UnclosedDelimiter { .. })
+            ),
+            "{:?}",
+            parse(source)
+        );
+    }
+
+    #[test]
+    fn test_error_reports_found_token() {
+        assert_eq!(
+            parse("x = 1 2;"),
+            Err(ParseError::MissingSemicolon {
+                after: Span {
+                    file: Default::default(),
+                    start: 4,
+                    end: 4,
+                },
+                found: Some(TokenKind::Number(NumberLiteral::Integer(2))),
+            })
+        );
+    }
+
+    #[test_case("x = ; y = 1; z = );", &["y"], 2 ; "statements")]
+    #[test_case("x = 1 y = 2; z = 3;", &["z"], 1 ; "missing semicolon")]
+    #[test_case(
+        "fn f() { g(1 } fn h() { x = ; } record r { x: i32; }",
+        &["r"],
+        2 ;
+        "unclosed parenthesis in a body"
+    )]
+    #[test_case("union u { a b }; fn f() {}", &["f"], 1 ; "braced declaration")]
+    #[test_case("} x = 1;", &["x"], 1 ; "stray brace")]
+    #[test_case("fn f() { x = [1, 2; record r {}", &[], 1 ; "unclosed to the end")]
+    #[test_case("fn f() { g(1 } pub fn h() {}", &["h"], 1 ; "public declaration")]
+    fn test_recovers_after_errors(source: &str, parsed: &[&str], error_count: usize) {
+        let (program, errors) = parse_all(source);
+        let names: Vec<String> = program
+            .declarations
+            .iter()
+            .map(|declaration| match declaration {
+                Declaration::Function(function) => function.name.clone(),
+                Declaration::Record(record) => record.name.clone(),
+                Declaration::Statement(Statement {
+                    kind: StatementKind::VariableDeclaration { name, .. },
+                    ..
+                }) => name.clone(),
+                other => panic!("unexpected declaration {:?}", other),
+            })
+            .collect();
+
+        assert_eq!(names, parsed);
+        assert_eq!(errors.len(), error_count, "{:?}", errors);
+    }
+
+    #[test]
+    fn test_parse_all_matches_parse() {
+        let source = "record point { x: i32; } fn main() { p = point { x: 1 }; }";
+        assert_eq!(parse_all(source), (parse(source).unwrap(), Vec::new()));
+
+        let (program, errors) = parse_all("x = 1; y = 'ab';");
+        assert!(program.declarations.is_empty());
+        assert!(matches!(errors.as_slice(), [ParseError::Lexer(_)]));
+        assert_eq!(parse_all("x = ; y = );").1[0], parse("x = ;").unwrap_err());
+    }
+
+    #[test_case("f(1 2);", TokenKind::RightParen, 1 ; "call")]
+    #[test_case("x = [1, (2 + 3), 4;", TokenKind::RightBracket, 4 ; "array with closed group inside")]
+    #[test_case("record r { f: fn(i32 -> i32; }", TokenKind::RightParen, 16 ; "function type")]
+    #[test_case("union option<T> { some(T) none }", TokenKind::RightBrace, 16 ; "union variants")]
+    fn test_unclosed_delimiter(source: &str, expected: TokenKind, open_at: usize) {
+        match parse(source) {
+            Err(ParseError::UnclosedDelimiter {
+                delimiter, open, ..
+            }) => assert_eq!((delimiter, open.start), (expected, open_at)),
+            result => panic!("Expected an unclosed delimiter error, got {:?}", result),
+        }
+    }
+
+    #[test_case("x = 1", "x = 1;" ; "semicolon at end of input")]
+    #[test_case("x = 1 y = 2;", "x = 1; y = 2;" ; "semicolon between statements")]
+    #[test_case("fn f() { g(1 }", "fn f() { g(1) }" ; "parenthesis")]
+    #[test_case("x = [1, 2;", "x = [1, 2];" ; "bracket")]
+    fn test_suggestion_fixes_source(source: &str, fixed: &str) {
+        let error = parse(source).expect_err("Expected a parse error");
+        let suggestion = error.suggestion().expect("Expected a suggestion");
+
+        assert_eq!(suggestion.apply(source), fixed);
+        assert!(parse(fixed).is_ok());
+    }
+
+    #[test]
+    fn test_error_codes_and_spans() {
+        let error = parse("x = (1 + 2;").expect_err("Expected a parse error");
+
+        assert_eq!(error.code(), "E0005");
+        assert_eq!(error.span().map(|span| span.start), Some(9));
+        assert_eq!(
+            error
+                .secondary_span()
+                .map(|(span, label)| (span.start, label)),
+            Some((4, "opened here"))
+        );
+        assert_eq!(parse("x = ;").unwrap_err().code(), "E0002");
+        assert_eq!(parse("x = 1").unwrap_err().code(), "E0004");
+        assert_eq!(parse("x = 1").unwrap_err().secondary_span(), None);
+    }
+
+    #[test_case("x = 1.2.3;", Some(4) ; "lexer error")]
+    #[test_case("patch point {", Some(13) ; "end of input")]
+    #[test_case("#:version 9.9\n", None ; "unsupported version")]
+    fn test_error_span(source: &str, start: Option<usize>) {
+        assert_eq!(
+            parse(source).unwrap_err().span().map(|span| span.start),
+            start
+        );
+    }
+
+    #[test_case("x = ;", "Expected expression, found ';'" ; "unexpected token")]
+    #[test_case("x = 1", "Expected ';', found end of input" ; "missing semicolon")]
+    #[test_case("x = (1;", "Expected ')', found ';'" ; "unclosed delimiter")]
+    #[test_case("x = \"a", "Unterminated string literal" ; "unterminated string")]
+    #[test_case("x = 1 ! 2;", "Unexpected character '!'" ; "unexpected character")]
+    #[test_case("x = 1.2.3;", "Invalid number format" ; "invalid number")]
+    #[test_case("#:version 9.9\nx = 1;", "CV version 9.9 is not supported by this toolchain (supported: 0.1 to 0.1)" ; "unsupported version")]
+    fn test_error_message_leaves_out_the_position(source: &str, expected: &str) {
+        assert_eq!(parse(source).unwrap_err().message(), expected);
+    }
+
+    #[test]
+    fn test_lexer_errors_are_propagated() {
+        assert_eq!(
+            parse("x = 1 ! 2;"),
+            Err(ParseError::Lexer(LexerError::UnexpectedCharacter(
+                '!',
+                Span {
+                    file: FileId::default(),
+                    start: 6,
+                    end: 6,
+                }
+            )))
+        );
+    }
+
+    const INVENTORY: &str = r#"
+        import money::{currency, format};
+
+        pub record item {
+            pub name: string;
+            quantity: u32;
+            price: f64;
+        }
+
+        union stock = inStock(u32) | backordered | discontinued;
+
+        patch item {
+            fn total() -> f64 {
+                self.price * self.quantity as f64
+            }
+        }
+
+        fn restock(arrayList<item>& @items, string name, u32 amount) -> bool {
+            for entry in items {
+                if entry.name == name {
+                    entry.quantity += amount;
+                    return true;
+                }
+            }
+            false
+        }
+
+        pub fn summary(arrayList<item>& items) -> string {
+            @total = 0.0;
+            @count = 0;
+            for entry in items {
+                total += entry.total();
+                count = count + 1;
+            }
+            "items: " + count.toString() + ", value: " + total.toString()
+        }
+
+        fn availability(stock status) -> string {
+            when status {
+                inStock(count): count.toString() + " left";
+                backordered: "on backorder";
+                _: "unavailable";
+            }
+        }
+
+        inventory = [
+            item { name: "bolt", quantity: 120, price: 0.25 },
+            item { name: "nut", quantity: 80, price: 0.1 },
+        ];
+    "#;
+
+    const COUNTDOWN: &str = r#"
+        // Counts down and reports how many steps were even.
+        fn countdown(i32 start) -> i32 {
+            @n = start;
+            @evens = 0;
+            loop {
+                if n <= 0 {
+                    break evens;
+                } else if n % 2 == 0 {
+                    evens += 1;
+                }
+                n -= 1;
+            }
+        }
+
+        fn describe(i32 value) -> string {
+            if value < 0 or value > 100 {
+                "out of range"
+            } else {
+                label = if value >= 50 { "high" } else { "low" };
+                label
+            }
+        }
+
+        /* Entry point */
+        fn main() {
+            steps = countdown(10);
+            print(describe(steps));
+            bytes = b"\x00\xff";
+            initial = 'c';
+        }
+    "#;
+
+    #[test_case(INVENTORY, 8 ; "inventory")]
+    #[test_case(COUNTDOWN, 3 ; "countdown")]
+    fn test_parse_program(source: &str, declaration_count: usize) {
+        let program = parse(source).expect("Parse error");
+
+        assert_eq!(program.declarations.len(), declaration_count);
+        assert_eq!(validate::validate(&program), vec![]);
+    }
+
+    #[test_case(INVENTORY ; "inventory")]
+    #[test_case(COUNTDOWN ; "countdown")]
+    fn test_printed_program_reparses(source: &str) {
+        let program = parse(source).expect("Parse error");
+
+        assert_eq!(parse(&program.to_source()), Ok(program));
+    }
+
+    #[test]
+    fn test_nesting_within_limit() {
+        let depth = MAX_NESTING_DEPTH - 1;
+        let source = format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+
+        assert_eq!(parse_expr(&source), *int(1));
+    }
+
+    #[test]
+    fn test_chain_within_limit() {
+        let source = format!("1{}", " + 1".repeat(MAX_NESTING_DEPTH - 1));
+
+        assert!(parse(&format!("fn f() {{ {source}; {source} }}")).is_ok());
+    }
+
+    #[test_case(&format!("{}1{}", "(".repeat(10_000), ")".repeat(10_000)) ; "parentheses")]
+    #[test_case(&format!("{}x", "not ".repeat(10_000)) ; "prefix operators")]
+    #[test_case(&format!("{}x", "|x| ".repeat(10_000)) ; "curried closures")]
+    #[test_case(&format!("{}x{}", "if ".repeat(10_000), " {}".repeat(10_000)) ; "nested conditions")]
+    #[test_case(&format!("{}1{}", "[".repeat(10_000), "]".repeat(10_000)) ; "arrays")]
+    #[test_case(&format!("a{}", "[b".repeat(10_000)) ; "index expressions")]
+    #[test_case(&format!("when x {{ {}y: 1 }}", "some(".repeat(10_000)) ; "patterns")]
+    #[test_case(&format!("1{}", " + 1".repeat(10_000)) ; "binary operators")]
+    #[test_case(&format!("x{}", ".f()".repeat(10_000)) ; "method calls")]
+    #[test_case(&format!("x{}", " as i32".repeat(10_000)) ; "casts")]
+    #[test_case(&format!("if x {{}}{}", " else if x {}".repeat(10_000)) ; "else if chains")]
+    fn test_nesting_too_deep(source: &str) {
+        let tokens = Lexer::new(source).tokenize().expect("Lexer error");
+
+        assert!(matches!(
+            Parser::new(tokens).parse_expression(),
+            Err(ParseError::NestingTooDeep { .. })
+        ));
+    }
+
+    #[test_case(&format!("fn f({}i32 x) {{}}", "arrayList<".repeat(10_000)) ; "generic arguments")]
+    #[test_case(&format!("fn f({}i32 x) {{}}", "fn(".repeat(10_000)) ; "function types")]
+    #[test_case(&format!("fn f() {{ {} }}", "{".repeat(10_000)) ; "blocks")]
+    #[test_case(&format!("fn f(i32{} x) {{}}", "&".repeat(10_000)) ; "reference types")]
+    fn test_nested_declaration_too_deep(source: &str) {
+        assert!(matches!(
+            parse(source),
+            Err(ParseError::NestingTooDeep { .. })
+        ));
+    }
+
+    /// Inputs that have broken recursive-descent parsers before: truncated constructs, stray
+    /// closers, and literals at the edges of their ranges. Only the absence of panics is checked.
+    #[test_case("" ; "empty")]
+    #[test_case("fn" ; "bare fn")]
+    #[test_case("fn f(" ; "unclosed parameters")]
+    #[test_case("fn f() -> {" ; "missing return type")]
+    #[test_case("}}}" ; "stray closers")]
+    #[test_case("union u<" ; "unclosed type parameters")]
+    #[test_case("union u { a(" ; "unclosed variant payload")]
+    #[test_case("record r { a: }" ; "missing field type")]
+    #[test_case("patch" ; "bare patch")]
+    #[test_case("patch fixedArray<i32, 99999999999999999999> {}" ; "oversized array length")]
+    #[test_case("fn f(fixedArray<i32, -1> x) {}" ; "negative array length")]
+    #[test_case("fn f() { x = [1, 2,, 3]; }" ; "doubled comma")]
+    #[test_case("fn f() { when x { 'a" ; "unterminated char in when")]
+    #[test_case("fn f() { 1..2..3 }" ; "chained range")]
+    #[test_case("fn f() { & &@ && }" ; "reference operators without operands")]
+    #[test_case("fn f() { || | }" ; "closure pipes")]
+    #[test_case("fn f() { b\"\\x }" ; "unterminated byte string")]
+    #[test_case("fn f() { \u{0} }" ; "nul character")]
+    #[test_case("#:version" ; "truncated pragma")]
+    fn test_adversarial_input(source: &str) {
+        let _ = parse(source);
+    }
+
+    /// Fragments of valid CV, so that random sequences of them get deep into the grammar.
+    fn fragment() -> impl Strategy<Value = &'static str> {
+        prop::sample::select(vec![
+            "fn",
+            "record",
+            "union",
+            "patch",
+            "when",
+            "if",
+            "else",
+            "for",
+            "while",
+            "in",
+            "return",
+            "break",
+            "x",
+            "i32",
+            "arrayList",
+            "fixedArray",
+            "_",
+            "1",
+            "-2",
+            "3.5",
+            "\"s\"",
+            "'c'",
+            "true",
+            "(",
+            ")",
+            "{",
+            "}",
+            "[",
+            "]",
+            "<",
+            ">",
+            ",",
+            ";",
+            ":",
+            "->",
+            "=",
+            "@",
+            "&",
+            "&@",
+            "&&",
+            "|",
+            "||",
+            "..",
+            "..=",
+            ".",
+            "+",
+            "*",
+            "not",
+            "and",
+        ])
+    }
+
+    proptest! {
+        #[test]
+        fn prop_parser_never_panics(code in any::<String>()) {
+            let _ = parse(&code);
+        }
+
+        #[test]
+        fn prop_parser_never_panics_on_fragments(
+            fragments in prop::collection::vec(fragment(), 0..60)
+        ) {
+            let _ = parse(&fragments.join(" "));
+        }
+    }
+}
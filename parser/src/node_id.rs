@@ -0,0 +1,492 @@
+//! Identifiers for the nodes of a parsed program.
+//!
+//! Later passes record what they learn about a node (its type, the symbol it resolves to, the
+//! diagnostics attached to it) in side tables keyed by [`NodeId`] instead of storing it in the
+//! tree. The parser gives every node an id as it builds it, and the node keeps it wherever it is
+//! moved or cloned to, so parsing the same source always gives the same ids. A pass that copies
+//! nodes into a program, or puts programs together, gives the copies new ids with [`NodeIds`]
+//! so that no two nodes of a program share one. A [`NodeIndex`] checks that they do not.
+
+use crate::ast::{
+    ConstDeclaration, Declaration, Expression, FunctionDeclaration, ImportDeclaration, Parameter,
+    PatchDeclaration, Pattern, Program, RecordDeclaration, RecordField, Statement, TypeParameter,
+    UnionDeclaration, UnionVariant, WhenBranch,
+};
+use crate::visit::{
+    Visitor, walk_const, walk_declaration, walk_expression, walk_function, walk_parameter,
+    walk_patch, walk_pattern, walk_record, walk_record_field, walk_statement, walk_type_parameter,
+    walk_union, walk_union_variant, walk_when_branch,
+};
+use crate::visit_mut::{self, MutVisitor};
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NodeId(u32);
+
+impl NodeId {
+    /// The id of a node built by hand, until it is numbered.
+    pub const UNASSIGNED: NodeId = NodeId(u32::MAX);
+
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NodeKind {
+    Function,
+    Parameter,
+    Record,
+    TypeParameter,
+    RecordField,
+    Union,
+    UnionVariant,
+    Patch,
+    Const,
+    Import,
+    Statement,
+    Expression,
+    WhenBranch,
+    Pattern,
+}
+
+/// A syntax tree node that has a [`NodeId`].
+pub trait Node {
+    fn id(&self) -> NodeId;
+}
+
+macro_rules! impl_node {
+    ($($node:ty,)*) => {
+        $(impl Node for $node {
+            fn id(&self) -> NodeId {
+                self.id
+            }
+        })*
+    };
+}
+
+impl_node! {
+    FunctionDeclaration,
+    Parameter,
+    RecordDeclaration,
+    TypeParameter,
+    RecordField,
+    UnionDeclaration,
+    UnionVariant,
+    PatchDeclaration,
+    ConstDeclaration,
+    ImportDeclaration,
+    Statement,
+    Expression,
+    WhenBranch,
+    Pattern,
+}
+
+/// A declaration has the id of the function, record, statement, or other node it declares.
+impl Node for Declaration {
+    fn id(&self) -> NodeId {
+        match self {
+            Declaration::Function(function) => function.id,
+            Declaration::Record(record) => record.id,
+            Declaration::Union(union) => union.id,
+            Declaration::Patch(patch) => patch.id,
+            Declaration::Const(constant) => constant.id,
+            Declaration::Import(import) => import.id,
+            Declaration::Statement(statement) => statement.id,
+        }
+    }
+}
+
+/// Hands out node ids, each one larger than the ones before.
+#[derive(Debug, Clone, Default)]
+pub struct NodeIds {
+    next: u32,
+}
+
+impl NodeIds {
+    pub fn new() -> Self {
+        NodeIds::default()
+    }
+
+    /// Ids for nodes added to `program`, larger than any of its own.
+    pub fn after(program: &Program) -> Self {
+        let mut largest = Largest(None);
+        largest.visit_program(program);
+        NodeIds {
+            next: largest.0.map_or(0, |id| id.0 + 1),
+        }
+    }
+
+    pub fn fresh(&mut self) -> NodeId {
+        let id = NodeId(self.next);
+        self.next += 1;
+        id
+    }
+
+    /// Give every node of `program` a new id.
+    pub fn number_program(&mut self, program: &mut Program) {
+        Numbering(self).visit_program(program);
+    }
+
+    /// Give every node of `declaration` a new id.
+    pub fn number_declaration(&mut self, declaration: &mut Declaration) {
+        Numbering(self).visit_declaration(declaration);
+    }
+
+    /// Give every node of `function` a new id.
+    pub fn number_function(&mut self, function: &mut FunctionDeclaration) {
+        Numbering(self).visit_function(function);
+    }
+}
+
+/// The kind of every node in a program, by id.
+#[derive(Debug, Default)]
+pub struct NodeIndex {
+    kinds: HashMap<NodeId, NodeKind>,
+}
+
+impl NodeIndex {
+    /// # Panics
+    ///
+    /// If two nodes of `program` have the same id, or one has none, as when nodes are copied
+    /// into it or built by hand without being numbered.
+    pub fn new(program: &Program) -> Self {
+        let mut index = NodeIndex::default();
+        index.visit_program(program);
+        index
+    }
+
+    /// The id of `node`, or `None` if no node of the indexed program has it.
+    pub fn id<T: Node>(&self, node: &T) -> Option<NodeId> {
+        let id = node.id();
+        self.kinds.contains_key(&id).then_some(id)
+    }
+
+    /// The id of `node`, which belongs to the indexed program.
+    ///
+    /// # Panics
+    ///
+    /// If no node of the indexed program has the id of `node`.
+    pub fn expect_id<T: Node>(&self, node: &T) -> NodeId {
+        self.id(node)
+            .expect("the node belongs to the indexed program")
+    }
+
+    pub fn kind(&self, id: NodeId) -> Option<NodeKind> {
+        self.kinds.get(&id).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.kinds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.kinds.is_empty()
+    }
+
+    fn assign(&mut self, id: NodeId, kind: NodeKind) {
+        assert_ne!(
+            id,
+            NodeId::UNASSIGNED,
+            "a {:?} node was never numbered",
+            kind
+        );
+        if let Some(other) = self.kinds.insert(id, kind) {
+            panic!(
+                "a {:?} node and a {:?} node share the id {}",
+                other, kind, id
+            );
+        }
+    }
+}
+
+impl Visitor for NodeIndex {
+    fn visit_declaration(&mut self, declaration: &Declaration) {
+        if let Declaration::Import(import) = declaration {
+            self.assign(import.id, NodeKind::Import);
+        }
+        walk_declaration(self, declaration);
+    }
+
+    fn visit_function(&mut self, function: &FunctionDeclaration) {
+        self.assign(function.id, NodeKind::Function);
+        walk_function(self, function);
+    }
+
+    fn visit_parameter(&mut self, parameter: &Parameter) {
+        self.assign(parameter.id, NodeKind::Parameter);
+        walk_parameter(self, parameter);
+    }
+
+    fn visit_record(&mut self, record: &RecordDeclaration) {
+        self.assign(record.id, NodeKind::Record);
+        walk_record(self, record);
+    }
+
+    fn visit_type_parameter(&mut self, parameter: &TypeParameter) {
+        self.assign(parameter.id, NodeKind::TypeParameter);
+        walk_type_parameter(self, parameter);
+    }
+
+    fn visit_record_field(&mut self, field: &RecordField) {
+        self.assign(field.id, NodeKind::RecordField);
+        walk_record_field(self, field);
+    }
+
+    fn visit_union(&mut self, union: &UnionDeclaration) {
+        self.assign(union.id, NodeKind::Union);
+        walk_union(self, union);
+    }
+
+    fn visit_union_variant(&mut self, variant: &UnionVariant) {
+        self.assign(variant.id, NodeKind::UnionVariant);
+        walk_union_variant(self, variant);
+    }
+
+    fn visit_patch(&mut self, patch: &PatchDeclaration) {
+        self.assign(patch.id, NodeKind::Patch);
+        walk_patch(self, patch);
+    }
+
+    fn visit_const(&mut self, constant: &ConstDeclaration) {
+        self.assign(constant.id, NodeKind::Const);
+        walk_const(self, constant);
+    }
+
+    fn visit_statement(&mut self, statement: &Statement) {
+        self.assign(statement.id, NodeKind::Statement);
+        walk_statement(self, statement);
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        self.assign(expression.id, NodeKind::Expression);
+        walk_expression(self, expression);
+    }
+
+    fn visit_when_branch(&mut self, branch: &WhenBranch) {
+        self.assign(branch.id, NodeKind::WhenBranch);
+        walk_when_branch(self, branch);
+    }
+
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        self.assign(pattern.id, NodeKind::Pattern);
+        walk_pattern(self, pattern);
+    }
+}
+
+/// The largest id of the nodes visited, leaving out unnumbered ones.
+struct Largest(Option<NodeId>);
+
+impl Largest {
+    fn see(&mut self, node: &impl Node) {
+        let id = node.id();
+        if id != NodeId::UNASSIGNED && self.0.is_none_or(|largest| id > largest) {
+            self.0 = Some(id);
+        }
+    }
+}
+
+impl Visitor for Largest {
+    fn visit_declaration(&mut self, declaration: &Declaration) {
+        self.see(declaration);
+        walk_declaration(self, declaration);
+    }
+
+    fn visit_parameter(&mut self, parameter: &Parameter) {
+        self.see(parameter);
+        walk_parameter(self, parameter);
+    }
+
+    fn visit_function(&mut self, function: &FunctionDeclaration) {
+        self.see(function);
+        walk_function(self, function);
+    }
+
+    fn visit_type_parameter(&mut self, parameter: &TypeParameter) {
+        self.see(parameter);
+        walk_type_parameter(self, parameter);
+    }
+
+    fn visit_record_field(&mut self, field: &RecordField) {
+        self.see(field);
+        walk_record_field(self, field);
+    }
+
+    fn visit_union_variant(&mut self, variant: &UnionVariant) {
+        self.see(variant);
+        walk_union_variant(self, variant);
+    }
+
+    fn visit_statement(&mut self, statement: &Statement) {
+        self.see(statement);
+        walk_statement(self, statement);
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        self.see(expression);
+        walk_expression(self, expression);
+    }
+
+    fn visit_when_branch(&mut self, branch: &WhenBranch) {
+        self.see(branch);
+        walk_when_branch(self, branch);
+    }
+
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        self.see(pattern);
+        walk_pattern(self, pattern);
+    }
+}
+
+/// Gives every node it visits a new id.
+struct Numbering<'n>(&'n mut NodeIds);
+
+impl MutVisitor for Numbering<'_> {
+    fn visit_declaration(&mut self, declaration: &mut Declaration) {
+        if let Declaration::Import(import) = declaration {
+            import.id = self.0.fresh();
+        }
+        visit_mut::walk_declaration(self, declaration);
+    }
+
+    fn visit_function(&mut self, function: &mut FunctionDeclaration) {
+        function.id = self.0.fresh();
+        visit_mut::walk_function(self, function);
+    }
+
+    fn visit_parameter(&mut self, parameter: &mut Parameter) {
+        parameter.id = self.0.fresh();
+        visit_mut::walk_parameter(self, parameter);
+    }
+
+    fn visit_record(&mut self, record: &mut RecordDeclaration) {
+        record.id = self.0.fresh();
+        visit_mut::walk_record(self, record);
+    }
+
+    fn visit_type_parameter(&mut self, parameter: &mut TypeParameter) {
+        parameter.id = self.0.fresh();
+        visit_mut::walk_type_parameter(self, parameter);
+    }
+
+    fn visit_record_field(&mut self, field: &mut RecordField) {
+        field.id = self.0.fresh();
+        visit_mut::walk_record_field(self, field);
+    }
+
+    fn visit_union(&mut self, union: &mut UnionDeclaration) {
+        union.id = self.0.fresh();
+        visit_mut::walk_union(self, union);
+    }
+
+    fn visit_union_variant(&mut self, variant: &mut UnionVariant) {
+        variant.id = self.0.fresh();
+        visit_mut::walk_union_variant(self, variant);
+    }
+
+    fn visit_patch(&mut self, patch: &mut PatchDeclaration) {
+        patch.id = self.0.fresh();
+        visit_mut::walk_patch(self, patch);
+    }
+
+    fn visit_const(&mut self, constant: &mut ConstDeclaration) {
+        constant.id = self.0.fresh();
+        visit_mut::walk_const(self, constant);
+    }
+
+    fn visit_statement(&mut self, statement: &mut Statement) {
+        statement.id = self.0.fresh();
+        visit_mut::walk_statement(self, statement);
+    }
+
+    fn visit_expression(&mut self, expression: &mut Expression) {
+        expression.id = self.0.fresh();
+        visit_mut::walk_expression(self, expression);
+    }
+
+    fn visit_when_branch(&mut self, branch: &mut WhenBranch) {
+        branch.id = self.0.fresh();
+        visit_mut::walk_when_branch(self, branch);
+    }
+
+    fn visit_pattern(&mut self, pattern: &mut Pattern) {
+        pattern.id = self.0.fresh();
+        visit_mut::walk_pattern(self, pattern);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::ExpressionKind;
+    use crate::parse;
+
+    #[test]
+    fn test_parsed_nodes_have_ids() {
+        let program = parse("fn f(i32 x) -> i32 { x + 1 }").expect("Parse error");
+        let index = NodeIndex::new(&program);
+
+        let Declaration::Function(function) = &program.declarations[0] else {
+            panic!("expected a function");
+        };
+        let ExpressionKind::Block {
+            final_expression: Some(sum),
+            ..
+        } = &function.body.kind
+        else {
+            panic!("expected a block with a final expression");
+        };
+
+        assert_eq!(index.len(), 6);
+        assert_eq!(index.id(&program.declarations[0]), Some(function.id));
+        assert_eq!(index.kind(function.id), Some(NodeKind::Function));
+        assert_eq!(index.kind(function.params[0].id), Some(NodeKind::Parameter));
+        assert_eq!(index.kind(sum.id), Some(NodeKind::Expression));
+    }
+
+    #[test]
+    fn test_ids_are_stable_across_parses() {
+        let source = "record point { x: i32; } p = point { x: 1 };";
+        let first = parse(source).expect("Parse error");
+        let second = parse(source).expect("Parse error");
+
+        assert_eq!(first.declarations[1].id(), second.declarations[1].id());
+        assert_eq!(NodeIndex::new(&first).len(), NodeIndex::new(&second).len());
+    }
+
+    #[test]
+    fn test_nodes_built_by_hand_have_no_id() {
+        let program = parse("x = 1;").expect("Parse error");
+        let index = NodeIndex::new(&program);
+        let other = Expression::from(ExpressionKind::Identifier("x".to_string()));
+
+        assert_eq!(index.id(&other), None);
+    }
+
+    #[test]
+    fn test_numbering_after_a_program() {
+        let mut program = parse("x = 1; fn f() { x; }").expect("Parse error");
+        let mut other = parse("y = 2;").expect("Parse error");
+        NodeIds::after(&program).number_program(&mut other);
+        program.declarations.extend(other.declarations);
+
+        assert_eq!(NodeIndex::new(&program).len(), 8);
+    }
+
+    #[test]
+    #[should_panic(expected = "share the id")]
+    fn test_index_rejects_shared_ids() {
+        let mut program = parse("x = 1;").expect("Parse error");
+        program.declarations.push(program.declarations[0].clone());
+
+        NodeIndex::new(&program);
+    }
+}
@@ -0,0 +1,578 @@
+//! The operand and result types allowed for every operator, kept as data so that every consumer
+//! (type checking, constant evaluation, documentation) works from the same table.
+
+use crate::ast::{BinaryOperator, Type, UnaryOperator};
+use lexer::tokens::TokenKind;
+use std::fmt;
+use thiserror::Error;
+
+/// A family of types an operand may belong to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TypeClass {
+    /// Any signed or unsigned integer type.
+    Integer,
+    /// Integer types that can represent negative values.
+    SignedInteger,
+    Float,
+    Bool,
+    Char,
+    String,
+    /// A reference to any type.
+    Reference,
+    /// Any type at all.
+    Any,
+}
+
+impl TypeClass {
+    pub fn contains(&self, ty: &Type) -> bool {
+        match self {
+            TypeClass::Integer => {
+                TypeClass::SignedInteger.contains(ty)
+                    || matches!(
+                        ty,
+                        Type::U8 | Type::U16 | Type::U32 | Type::U64 | Type::USize
+                    )
+            }
+            TypeClass::SignedInteger => matches!(
+                ty,
+                Type::I8 | Type::I16 | Type::I32 | Type::I64 | Type::ISize
+            ),
+            TypeClass::Float => matches!(ty, Type::F32 | Type::F64),
+            TypeClass::Bool => matches!(ty, Type::Bool),
+            TypeClass::Char => matches!(ty, Type::Char),
+            TypeClass::String => matches!(ty, Type::String),
+            TypeClass::Reference => matches!(ty, Type::Reference { .. }),
+            TypeClass::Any => true,
+        }
+    }
+}
+
+impl fmt::Display for TypeClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let class_str = match self {
+            TypeClass::Integer => "integer",
+            TypeClass::SignedInteger => "signed integer",
+            TypeClass::Float => "float",
+            TypeClass::Bool => "bool",
+            TypeClass::Char => "char",
+            TypeClass::String => "string",
+            TypeClass::Reference => "reference",
+            TypeClass::Any => "any",
+        };
+        write!(f, "{}", class_str)
+    }
+}
+
+/// The type an operator application produces, relative to its operand types.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResultType {
+    /// The (left) operand's own type.
+    Operand,
+    Bool,
+    /// No value; the operator is used for its effect.
+    Unit,
+    /// A reference to the operand's type.
+    Reference {
+        is_mutable: bool,
+    },
+    /// The type the reference operand points to.
+    Referent,
+}
+
+impl ResultType {
+    /// Resolve the result type for a concrete operand type. Returns `None` for
+    /// [`ResultType::Referent`] when the operand is not a reference.
+    pub fn resolve(&self, operand: &Type) -> Option<Type> {
+        match self {
+            ResultType::Operand => Some(operand.clone()),
+            ResultType::Bool => Some(Type::Bool),
+            ResultType::Unit => Some(Type::Unit),
+            ResultType::Reference { is_mutable } => Some(Type::Reference {
+                is_mutable: *is_mutable,
+                ref_type: Box::new(operand.clone()),
+            }),
+            ResultType::Referent => match operand {
+                Type::Reference { ref_type, .. } => Some(*ref_type.clone()),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl fmt::Display for ResultType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResultType::Operand => write!(f, "operand type"),
+            ResultType::Bool => write!(f, "bool"),
+            ResultType::Unit => write!(f, "unit"),
+            ResultType::Reference { is_mutable: false } => write!(f, "reference"),
+            ResultType::Reference { is_mutable: true } => write!(f, "mutable reference"),
+            ResultType::Referent => write!(f, "referenced type"),
+        }
+    }
+}
+
+/// One allowed combination for a binary operator. Both operands must have the same type, which
+/// must belong to `operands`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BinarySignature {
+    pub operands: TypeClass,
+    pub result: ResultType,
+}
+
+/// One allowed combination for a unary operator.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UnarySignature {
+    pub operand: TypeClass,
+    pub result: ResultType,
+}
+
+const fn binary(operands: TypeClass, result: ResultType) -> BinarySignature {
+    BinarySignature { operands, result }
+}
+
+const fn unary(operand: TypeClass, result: ResultType) -> UnarySignature {
+    UnarySignature { operand, result }
+}
+
+const ARITHMETIC: &[BinarySignature] = &[
+    binary(TypeClass::Integer, ResultType::Operand),
+    binary(TypeClass::Float, ResultType::Operand),
+];
+const ADDITION: &[BinarySignature] = &[
+    binary(TypeClass::Integer, ResultType::Operand),
+    binary(TypeClass::Float, ResultType::Operand),
+    binary(TypeClass::String, ResultType::Operand),
+];
+const LOGICAL: &[BinarySignature] = &[binary(TypeClass::Bool, ResultType::Bool)];
+const EQUALITY: &[BinarySignature] = &[binary(TypeClass::Any, ResultType::Bool)];
+const ORDERING: &[BinarySignature] = &[
+    binary(TypeClass::Integer, ResultType::Bool),
+    binary(TypeClass::Float, ResultType::Bool),
+    binary(TypeClass::Char, ResultType::Bool),
+    binary(TypeClass::String, ResultType::Bool),
+];
+const ASSIGNMENT: &[BinarySignature] = &[binary(TypeClass::Any, ResultType::Unit)];
+const ARITHMETIC_ASSIGNMENT: &[BinarySignature] = &[
+    binary(TypeClass::Integer, ResultType::Unit),
+    binary(TypeClass::Float, ResultType::Unit),
+];
+const ADDITION_ASSIGNMENT: &[BinarySignature] = &[
+    binary(TypeClass::Integer, ResultType::Unit),
+    binary(TypeClass::Float, ResultType::Unit),
+    binary(TypeClass::String, ResultType::Unit),
+];
+
+const NOT: &[UnarySignature] = &[unary(TypeClass::Bool, ResultType::Bool)];
+const NEGATION: &[UnarySignature] = &[
+    unary(TypeClass::SignedInteger, ResultType::Operand),
+    unary(TypeClass::Float, ResultType::Operand),
+];
+const REFERENCE: &[UnarySignature] = &[unary(
+    TypeClass::Any,
+    ResultType::Reference { is_mutable: false },
+)];
+const MUTABLE_REFERENCE: &[UnarySignature] = &[unary(
+    TypeClass::Any,
+    ResultType::Reference { is_mutable: true },
+)];
+const DEREFERENCE: &[UnarySignature] = &[unary(TypeClass::Reference, ResultType::Referent)];
+
+impl BinaryOperator {
+    /// The binary operator an infix token stands for, if any. `&&` and `||` are aliases for
+    /// `and` and `or`.
+    pub fn from_token(kind: &TokenKind) -> Option<Self> {
+        let operator = match kind {
+            TokenKind::Plus => BinaryOperator::Add,
+            TokenKind::Minus => BinaryOperator::Subtract,
+            TokenKind::Star => BinaryOperator::Multiply,
+            TokenKind::Divide => BinaryOperator::Divide,
+            TokenKind::Modulo => BinaryOperator::Modulus,
+            TokenKind::And | TokenKind::DoubleAmpersand => BinaryOperator::And,
+            TokenKind::Or | TokenKind::DoublePipe => BinaryOperator::Or,
+            TokenKind::DoubleEqual => BinaryOperator::Equal,
+            TokenKind::NotEqual => BinaryOperator::NotEqual,
+            TokenKind::LessThan => BinaryOperator::LessThan,
+            TokenKind::LessEqual => BinaryOperator::LessThanOrEqual,
+            TokenKind::GreaterThan => BinaryOperator::GreaterThan,
+            TokenKind::GreaterEqual => BinaryOperator::GreaterThanOrEqual,
+            TokenKind::Equal => BinaryOperator::Assign,
+            TokenKind::PlusEqual => BinaryOperator::AddAssign,
+            TokenKind::MinusEqual => BinaryOperator::SubtractAssign,
+            TokenKind::TimesEqual => BinaryOperator::MultiplyAssign,
+            TokenKind::DivideEqual => BinaryOperator::DivideAssign,
+            TokenKind::ModuloEqual => BinaryOperator::ModulusAssign,
+            _ => return None,
+        };
+        Some(operator)
+    }
+
+    /// The token that spells this operator, the inverse of [`BinaryOperator::from_token`].
+    /// `and` and `or` are spelled as words rather than with their symbolic aliases.
+    pub fn to_token(&self) -> TokenKind {
+        match self {
+            BinaryOperator::Add => TokenKind::Plus,
+            BinaryOperator::Subtract => TokenKind::Minus,
+            BinaryOperator::Multiply => TokenKind::Star,
+            BinaryOperator::Divide => TokenKind::Divide,
+            BinaryOperator::Modulus => TokenKind::Modulo,
+            BinaryOperator::And => TokenKind::And,
+            BinaryOperator::Or => TokenKind::Or,
+            BinaryOperator::Equal => TokenKind::DoubleEqual,
+            BinaryOperator::NotEqual => TokenKind::NotEqual,
+            BinaryOperator::LessThan => TokenKind::LessThan,
+            BinaryOperator::LessThanOrEqual => TokenKind::LessEqual,
+            BinaryOperator::GreaterThan => TokenKind::GreaterThan,
+            BinaryOperator::GreaterThanOrEqual => TokenKind::GreaterEqual,
+            BinaryOperator::Assign => TokenKind::Equal,
+            BinaryOperator::AddAssign => TokenKind::PlusEqual,
+            BinaryOperator::SubtractAssign => TokenKind::MinusEqual,
+            BinaryOperator::MultiplyAssign => TokenKind::TimesEqual,
+            BinaryOperator::DivideAssign => TokenKind::DivideEqual,
+            BinaryOperator::ModulusAssign => TokenKind::ModuloEqual,
+        }
+    }
+
+    /// Every operand/result combination this operator accepts.
+    pub fn signatures(&self) -> &'static [BinarySignature] {
+        match self {
+            BinaryOperator::Add => ADDITION,
+            BinaryOperator::Subtract
+            | BinaryOperator::Multiply
+            | BinaryOperator::Divide
+            | BinaryOperator::Modulus => ARITHMETIC,
+            BinaryOperator::And | BinaryOperator::Or => LOGICAL,
+            BinaryOperator::Equal | BinaryOperator::NotEqual => EQUALITY,
+            BinaryOperator::LessThan
+            | BinaryOperator::LessThanOrEqual
+            | BinaryOperator::GreaterThan
+            | BinaryOperator::GreaterThanOrEqual => ORDERING,
+            BinaryOperator::Assign => ASSIGNMENT,
+            BinaryOperator::AddAssign => ADDITION_ASSIGNMENT,
+            BinaryOperator::SubtractAssign
+            | BinaryOperator::MultiplyAssign
+            | BinaryOperator::DivideAssign
+            | BinaryOperator::ModulusAssign => ARITHMETIC_ASSIGNMENT,
+        }
+    }
+
+    /// Find the signature matching the given operand types, if the combination is allowed.
+    pub fn signature_for(&self, left: &Type, right: &Type) -> Option<&'static BinarySignature> {
+        if left != right {
+            return None;
+        }
+
+        self.signatures()
+            .iter()
+            .find(|signature| signature.operands.contains(left))
+    }
+
+    /// The operator a compound assignment applies, such as `+` for `+=`, or `None` for any other
+    /// operator.
+    pub fn compound_operator(&self) -> Option<BinaryOperator> {
+        match self {
+            BinaryOperator::AddAssign => Some(BinaryOperator::Add),
+            BinaryOperator::SubtractAssign => Some(BinaryOperator::Subtract),
+            BinaryOperator::MultiplyAssign => Some(BinaryOperator::Multiply),
+            BinaryOperator::DivideAssign => Some(BinaryOperator::Divide),
+            BinaryOperator::ModulusAssign => Some(BinaryOperator::Modulus),
+            _ => None,
+        }
+    }
+}
+
+impl UnaryOperator {
+    /// Every operand/result combination this operator accepts.
+    pub fn signatures(&self) -> &'static [UnarySignature] {
+        match self {
+            UnaryOperator::Not => NOT,
+            UnaryOperator::Negate => NEGATION,
+            UnaryOperator::Reference => REFERENCE,
+            UnaryOperator::MutableReference => MUTABLE_REFERENCE,
+            UnaryOperator::Dereference => DEREFERENCE,
+        }
+    }
+
+    /// Find the signature matching the given operand type, if the operator applies to it.
+    pub fn signature_for(&self, operand: &Type) -> Option<&'static UnarySignature> {
+        self.signatures()
+            .iter()
+            .find(|signature| signature.operand.contains(operand))
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Clone, Copy)]
+pub enum ArithmeticError {
+    #[error("Division by zero")]
+    DivisionByZero,
+    #[error("Integer overflow")]
+    Overflow,
+}
+
+/// Apply an arithmetic operator to two integers with CV's integer semantics, which every
+/// evaluator must share: `/` truncates toward zero, `%` takes the sign of the dividend (so that
+/// `a == (a / b) * b + a % b`), and division by zero or overflow is an error rather than a wrap.
+/// Here a result overflows if no integer type holds it; whether it fits the type of the
+/// operation is for the evaluator to check.
+///
+/// Returns `None` if `operator` is not an arithmetic operator.
+pub fn integer_arithmetic(
+    operator: BinaryOperator,
+    left: i128,
+    right: i128,
+) -> Option<Result<i128, ArithmeticError>> {
+    let result = match operator {
+        BinaryOperator::Add => left.checked_add(right),
+        BinaryOperator::Subtract => left.checked_sub(right),
+        BinaryOperator::Multiply => left.checked_mul(right),
+        BinaryOperator::Divide | BinaryOperator::Modulus if right == 0 => {
+            return Some(Err(ArithmeticError::DivisionByZero));
+        }
+        BinaryOperator::Divide => left.checked_div(right),
+        BinaryOperator::Modulus => left.checked_rem(right),
+        _ => return None,
+    };
+
+    let representable = i128::from(i64::MIN)..=i128::from(u64::MAX);
+    Some(
+        result
+            .filter(|result| representable.contains(result))
+            .ok_or(ArithmeticError::Overflow),
+    )
+}
+
+/// The smallest and largest values of an integer type, taking `isize` and `usize` to be 64 bits.
+pub fn integer_bounds(ty: &Type) -> Option<(i128, i128)> {
+    let bounds = match ty {
+        Type::I8 => (i8::MIN.into(), i8::MAX.into()),
+        Type::I16 => (i16::MIN.into(), i16::MAX.into()),
+        Type::I32 => (i32::MIN.into(), i32::MAX.into()),
+        Type::I64 | Type::ISize => (i64::MIN.into(), i64::MAX.into()),
+        Type::U8 => (0, u8::MAX.into()),
+        Type::U16 => (0, u16::MAX.into()),
+        Type::U32 => (0, u32::MAX.into()),
+        Type::U64 | Type::USize => (0, u64::MAX.into()),
+        _ => return None,
+    };
+    Some(bounds)
+}
+
+/// Convert an integer to the integer type `target` the way a cast does, keeping its low bits
+/// when `target` is narrower.
+///
+/// Returns `None` if `target` is not an integer type.
+pub fn integer_cast(value: i128, target: &Type) -> Option<i128> {
+    let converted = match target {
+        Type::I8 => value as i8 as i128,
+        Type::I16 => value as i16 as i128,
+        Type::I32 => value as i32 as i128,
+        Type::I64 | Type::ISize => value as i64 as i128,
+        Type::U8 => value as u8 as i128,
+        Type::U16 => value as u16 as i128,
+        Type::U32 => value as u32 as i128,
+        Type::U64 | Type::USize => value as u64 as i128,
+        _ => return None,
+    };
+    Some(converted)
+}
+
+impl fmt::Display for BinarySignature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({0}, {0}) -> {1}", self.operands, self.result)
+    }
+}
+
+impl fmt::Display for UnarySignature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({}) -> {}", self.operand, self.result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test]
+    fn test_assignment_produces_unit() {
+        let signature = BinaryOperator::AddAssign.signature_for(&Type::I32, &Type::I32);
+        assert_eq!(
+            signature.and_then(|s| s.result.resolve(&Type::I32)),
+            Some(Type::Unit)
+        );
+    }
+
+    #[test]
+    fn test_arithmetic_signatures() {
+        let signature = BinaryOperator::Multiply.signature_for(&Type::I32, &Type::I32);
+        assert_eq!(
+            signature.and_then(|s| s.result.resolve(&Type::I32)),
+            Some(Type::I32)
+        );
+
+        assert_eq!(
+            BinaryOperator::Multiply.signature_for(&Type::I32, &Type::F64),
+            None
+        );
+        assert_eq!(
+            BinaryOperator::Subtract.signature_for(&Type::String, &Type::String),
+            None
+        );
+        assert!(
+            BinaryOperator::Add
+                .signature_for(&Type::String, &Type::String)
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn test_comparison_signatures() {
+        let signature = BinaryOperator::LessThan.signature_for(&Type::U8, &Type::U8);
+        assert_eq!(
+            signature.and_then(|s| s.result.resolve(&Type::U8)),
+            Some(Type::Bool)
+        );
+
+        assert_eq!(
+            BinaryOperator::LessThan.signature_for(&Type::Bool, &Type::Bool),
+            None
+        );
+        assert!(
+            BinaryOperator::Equal
+                .signature_for(&Type::Bool, &Type::Bool)
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn test_unary_signatures() {
+        assert!(UnaryOperator::Negate.signature_for(&Type::I64).is_some());
+        assert!(UnaryOperator::Negate.signature_for(&Type::U64).is_none());
+        assert!(UnaryOperator::Not.signature_for(&Type::I32).is_none());
+
+        let reference = Type::Reference {
+            is_mutable: false,
+            ref_type: Box::new(Type::Char),
+        };
+        let signature = UnaryOperator::Dereference.signature_for(&reference);
+        assert_eq!(
+            signature.and_then(|s| s.result.resolve(&reference)),
+            Some(Type::Char)
+        );
+        assert_eq!(
+            UnaryOperator::Reference
+                .signature_for(&Type::Char)
+                .and_then(|s| s.result.resolve(&Type::Char)),
+            Some(reference)
+        );
+    }
+
+    #[test]
+    fn test_integer_division_truncates_toward_zero() {
+        let divide = |a, b| integer_arithmetic(BinaryOperator::Divide, a, b);
+
+        assert_eq!(divide(7, 2), Some(Ok(3)));
+        assert_eq!(divide(-7, 2), Some(Ok(-3)));
+        assert_eq!(divide(7, -2), Some(Ok(-3)));
+        assert_eq!(divide(-7, -2), Some(Ok(3)));
+    }
+
+    #[test]
+    fn test_integer_remainder_takes_sign_of_dividend() {
+        let remainder = |a, b| integer_arithmetic(BinaryOperator::Modulus, a, b);
+
+        assert_eq!(remainder(7, 2), Some(Ok(1)));
+        assert_eq!(remainder(-7, 2), Some(Ok(-1)));
+        assert_eq!(remainder(7, -2), Some(Ok(1)));
+        assert_eq!(remainder(-7, -2), Some(Ok(-1)));
+    }
+
+    #[test]
+    fn test_integer_arithmetic_errors() {
+        assert_eq!(
+            integer_arithmetic(BinaryOperator::Divide, 1, 0),
+            Some(Err(ArithmeticError::DivisionByZero))
+        );
+        assert_eq!(
+            integer_arithmetic(BinaryOperator::Modulus, 1, 0),
+            Some(Err(ArithmeticError::DivisionByZero))
+        );
+        assert_eq!(
+            integer_arithmetic(BinaryOperator::Add, u64::MAX.into(), 1),
+            Some(Err(ArithmeticError::Overflow))
+        );
+        assert_eq!(
+            integer_arithmetic(BinaryOperator::Subtract, i64::MIN.into(), 1),
+            Some(Err(ArithmeticError::Overflow))
+        );
+        assert_eq!(integer_arithmetic(BinaryOperator::Equal, 1, 1), None);
+    }
+
+    #[test]
+    fn test_integer_arithmetic_beyond_i64() {
+        assert_eq!(
+            integer_arithmetic(BinaryOperator::Add, i64::MAX.into(), 1),
+            Some(Ok(i128::from(i64::MAX) + 1))
+        );
+        assert_eq!(
+            integer_arithmetic(BinaryOperator::Divide, i64::MIN.into(), -1),
+            Some(Ok(-i128::from(i64::MIN)))
+        );
+    }
+
+    #[test_case(-1, Type::U64, u64::MAX.into() ; "negative to u64")]
+    #[test_case(-1, Type::USize, u64::MAX.into() ; "negative to usize")]
+    #[test_case(u64::MAX.into(), Type::I64, -1 ; "u64 max to i64")]
+    #[test_case(300, Type::U8, 44 ; "wraps to u8")]
+    #[test_case(-129, Type::I8, 127 ; "wraps to i8")]
+    fn test_integer_cast(value: i128, target: Type, expected: i128) {
+        assert_eq!(integer_cast(value, &target), Some(expected));
+    }
+
+    #[test]
+    fn test_symbolic_logical_aliases() {
+        assert_eq!(
+            BinaryOperator::from_token(&TokenKind::DoubleAmpersand),
+            Some(BinaryOperator::And)
+        );
+        assert_eq!(
+            BinaryOperator::from_token(&TokenKind::And),
+            Some(BinaryOperator::And)
+        );
+        assert_eq!(
+            BinaryOperator::from_token(&TokenKind::DoublePipe),
+            Some(BinaryOperator::Or)
+        );
+        assert_eq!(BinaryOperator::from_token(&TokenKind::Pipe), None);
+    }
+
+    #[test]
+    fn test_to_token_inverts_from_token() {
+        for kind in lexer::tokens::OPERATORS {
+            if let Some(operator) = BinaryOperator::from_token(kind) {
+                assert_eq!(
+                    BinaryOperator::from_token(&operator.to_token()),
+                    Some(operator)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_signature_display() {
+        let rendered: Vec<String> = BinaryOperator::Add
+            .signatures()
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+
+        assert_eq!(
+            rendered,
+            [
+                "(integer, integer) -> operand type",
+                "(float, float) -> operand type",
+                "(string, string) -> operand type",
+            ]
+        );
+    }
+}
@@ -0,0 +1,152 @@
+//! How tightly operators bind, as used by the parser and the printer. Formatters, highlighters,
+//! and other tools that need to agree with the compiler on how `a + b * c` groups should ask
+//! this module rather than keep a table of their own.
+//!
+//! The levels of the infix operators come from
+//! [`TokenKind::binary_precedence`](lexer::tokens::TokenKind::binary_precedence); this module
+//! places the other kinds of expression on the same scale and says how each operator groups.
+
+use crate::ast::{BinaryOperator, UnaryOperator};
+use lexer::tokens::TokenKind;
+
+/// A binding strength. A higher precedence binds tighter, so in `a + b * c` the operator with
+/// the higher precedence, `*`, takes its operands first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Precedence(u8);
+
+impl Precedence {
+    /// Closures and type annotations, which extend as far to the right as they can.
+    pub const LOWEST: Precedence = Precedence(0);
+    pub const ASSIGNMENT: Precedence = Precedence(1);
+    pub const RANGE: Precedence = Precedence(2);
+    /// `as`, which binds tighter than every binary operator but not a prefix one, so `-x as u8`
+    /// casts `-x`.
+    pub const CAST: Precedence = Precedence(9);
+    /// Prefix operators: `not`, `-`, `&`, `&@`, and `*`.
+    pub const PREFIX: Precedence = Precedence(10);
+    /// Calls, method calls, field access, and indexing.
+    pub const POSTFIX: Precedence = Precedence(11);
+    /// Literals, names, and anything delimited, such as blocks and parenthesized expressions.
+    pub const PRIMARY: Precedence = Precedence(12);
+
+    /// The precedence and grouping of `token` used as an infix operator, or `None` if it is not
+    /// one.
+    pub fn of_infix(token: &TokenKind) -> Option<(Precedence, Associativity)> {
+        let precedence = Precedence(token.binary_precedence()?);
+        let associativity = match BinaryOperator::from_token(token) {
+            Some(operator) => operator.associativity(),
+            None => Associativity::None,
+        };
+        Some((precedence, associativity))
+    }
+
+    /// The next level up, the weakest precedence that still binds tighter than this one.
+    pub fn tighter(self) -> Precedence {
+        Precedence(self.0 + 1)
+    }
+
+    pub fn value(self) -> u8 {
+        self.0
+    }
+}
+
+/// How a chain of operators with the same precedence groups.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Associativity {
+    /// `a - b - c` is `(a - b) - c`.
+    Left,
+    /// `a = b = c` would be `a = (b = c)`.
+    Right,
+    /// The operator cannot be chained: `a..b..c` is an error.
+    None,
+}
+
+impl BinaryOperator {
+    pub fn precedence(&self) -> Precedence {
+        Precedence::of_infix(&self.to_token())
+            .map(|(precedence, _)| precedence)
+            .expect("every binary operator is an infix token")
+    }
+
+    pub fn associativity(&self) -> Associativity {
+        match self {
+            BinaryOperator::Assign
+            | BinaryOperator::AddAssign
+            | BinaryOperator::SubtractAssign
+            | BinaryOperator::MultiplyAssign
+            | BinaryOperator::DivideAssign
+            | BinaryOperator::ModulusAssign => Associativity::Right,
+            _ => Associativity::Left,
+        }
+    }
+}
+
+impl UnaryOperator {
+    pub fn precedence(&self) -> Precedence {
+        Precedence::PREFIX
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    const OPERATORS: [BinaryOperator; 19] = [
+        BinaryOperator::Add,
+        BinaryOperator::Subtract,
+        BinaryOperator::Multiply,
+        BinaryOperator::Divide,
+        BinaryOperator::Modulus,
+        BinaryOperator::And,
+        BinaryOperator::Or,
+        BinaryOperator::Equal,
+        BinaryOperator::NotEqual,
+        BinaryOperator::LessThan,
+        BinaryOperator::LessThanOrEqual,
+        BinaryOperator::GreaterThan,
+        BinaryOperator::GreaterThanOrEqual,
+        BinaryOperator::Assign,
+        BinaryOperator::AddAssign,
+        BinaryOperator::SubtractAssign,
+        BinaryOperator::MultiplyAssign,
+        BinaryOperator::DivideAssign,
+        BinaryOperator::ModulusAssign,
+    ];
+
+    #[test]
+    fn test_binary_operators_sit_between_ranges_and_prefix_operators() {
+        for operator in OPERATORS {
+            let precedence = operator.precedence();
+            if operator.associativity() == Associativity::Right {
+                assert_eq!(precedence, Precedence::ASSIGNMENT, "{}", operator);
+            } else {
+                assert!(precedence > Precedence::RANGE, "{}", operator);
+                assert!(precedence < Precedence::CAST, "{}", operator);
+            }
+        }
+    }
+
+    #[test_case(BinaryOperator::Multiply, BinaryOperator::Add ; "multiplication over addition")]
+    #[test_case(BinaryOperator::Add, BinaryOperator::LessThan ; "addition over comparison")]
+    #[test_case(BinaryOperator::LessThan, BinaryOperator::Equal ; "comparison over equality")]
+    #[test_case(BinaryOperator::Equal, BinaryOperator::And ; "equality over and")]
+    #[test_case(BinaryOperator::And, BinaryOperator::Or ; "and over or")]
+    fn test_binds_tighter(tighter: BinaryOperator, looser: BinaryOperator) {
+        assert!(tighter.precedence() > looser.precedence());
+    }
+
+    #[test]
+    fn test_infix_tokens() {
+        assert_eq!(
+            Precedence::of_infix(&TokenKind::Range),
+            Some((Precedence::RANGE, Associativity::None))
+        );
+        assert_eq!(
+            Precedence::of_infix(&TokenKind::DoublePipe),
+            Some((BinaryOperator::Or.precedence(), Associativity::Left))
+        );
+        assert_eq!(Precedence::of_infix(&TokenKind::Dot), None);
+        assert_eq!(UnaryOperator::Not.precedence(), Precedence::PREFIX);
+    }
+}
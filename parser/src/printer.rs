@@ -0,0 +1,963 @@
+//! Printing syntax trees back to CV source.
+//!
+//! The output is formatted the way the README writes CV: four-space indentation, one statement
+//! per line, and a blank line between declarations. Parentheses are inserted only where the
+//! precedence or associativity of the operators requires them, so parsing the printed source
+//! gives back the tree that was printed. Parentheses the parser kept as
+//! [`ExpressionKind::Grouped`] are printed as they were written.
+
+use crate::ast::{
+    BinaryOperator, Declaration, Expression, ExpressionKind, FunctionDeclaration, Literal,
+    Parameter, Pattern, PatternKind, Program, Statement, StatementKind, Type, TypeParameter,
+    UnaryOperator,
+};
+use crate::precedence::{Associativity, Precedence};
+use crate::visit::{Visitor, walk_expression};
+use std::fmt::Write;
+
+impl Program {
+    pub fn to_source(&self) -> String {
+        let mut printer = Printer::default();
+        printer.program(self);
+        printer.out
+    }
+}
+
+impl Expression {
+    pub fn to_source(&self) -> String {
+        let mut printer = Printer::default();
+        printer.expression(self, Precedence::LOWEST);
+        printer.out
+    }
+}
+
+impl Pattern {
+    pub fn to_source(&self) -> String {
+        let mut printer = Printer::default();
+        printer.pattern(self);
+        printer.out
+    }
+}
+
+fn precedence(expression: &Expression) -> Precedence {
+    match &expression.kind {
+        ExpressionKind::BinaryOperation { operator, .. } => operator.precedence(),
+        ExpressionKind::Range { .. } => Precedence::RANGE,
+        ExpressionKind::Cast { .. } => Precedence::CAST,
+        ExpressionKind::Closure { .. } | ExpressionKind::TypeAnnotation { .. } => {
+            Precedence::LOWEST
+        }
+        ExpressionKind::UnaryOperation { .. }
+        | ExpressionKind::Reference { .. }
+        | ExpressionKind::Dereference(_) => Precedence::PREFIX,
+        ExpressionKind::Literal(Literal::Integer(value)) if *value < 0 => Precedence::PREFIX,
+        ExpressionKind::Literal(Literal::Float(value)) if value.is_sign_negative() => {
+            Precedence::PREFIX
+        }
+        ExpressionKind::FunctionCall { .. }
+        | ExpressionKind::MethodCall { .. }
+        | ExpressionKind::RecordAccess { .. }
+        | ExpressionKind::IndexAccess { .. } => Precedence::POSTFIX,
+        _ => Precedence::PRIMARY,
+    }
+}
+
+/// The operand `expression` is written starting with, such as `a` in `a.b + c`.
+fn leftmost(mut expression: &Expression) -> &Expression {
+    loop {
+        expression = match &expression.kind {
+            ExpressionKind::BinaryOperation { left, .. } => left,
+            ExpressionKind::Range { start, .. } => start,
+            ExpressionKind::Cast { expression, .. }
+            | ExpressionKind::TypeAnnotation { expression, .. } => expression,
+            ExpressionKind::FunctionCall { function, .. } => function,
+            ExpressionKind::MethodCall { receiver, .. } => receiver,
+            ExpressionKind::RecordAccess { record, .. } => record,
+            ExpressionKind::IndexAccess { collection, .. } => collection,
+            _ => return expression,
+        };
+    }
+}
+
+/// Whether `expression` is written ending with a cast, such as `a + b as i32`. A `<` after it
+/// would be read as the start of the type's arguments.
+fn ends_with_cast(mut expression: &Expression) -> bool {
+    loop {
+        expression = match &expression.kind {
+            ExpressionKind::Cast { .. } => return true,
+            ExpressionKind::BinaryOperation { right, .. } => right,
+            ExpressionKind::Range { end, .. } => end,
+            ExpressionKind::UnaryOperation { operand, .. } => operand,
+            ExpressionKind::Reference { expression, .. }
+            | ExpressionKind::Dereference(expression) => expression,
+            _ => return false,
+        };
+    }
+}
+
+#[derive(Default)]
+struct Printer {
+    out: String,
+    indent: usize,
+}
+
+impl Printer {
+    fn newline(&mut self) {
+        self.out.push('\n');
+        for _ in 0..self.indent {
+            self.out.push_str("    ");
+        }
+    }
+
+    fn program(&mut self, program: &Program) {
+        for (index, declaration) in program.declarations.iter().enumerate() {
+            // Runs of top-level statements, of constants, or of imports are kept together;
+            // everything else is set apart.
+            let grouped = index > 0
+                && matches!(
+                    (&program.declarations[index - 1], declaration),
+                    (Declaration::Statement(_), Declaration::Statement(_))
+                        | (Declaration::Const(_), Declaration::Const(_))
+                        | (Declaration::Import(_), Declaration::Import(_))
+                );
+            if index > 0 && !grouped {
+                self.out.push('\n');
+            }
+            self.declaration(declaration);
+            self.out.push('\n');
+        }
+    }
+
+    fn declaration(&mut self, declaration: &Declaration) {
+        match declaration {
+            Declaration::Function(function) => self.function(function),
+            Declaration::Record(record) => {
+                self.visibility(record.is_public);
+                write!(self.out, "record {}", record.name).unwrap();
+                self.type_parameters(&record.type_parameters);
+                self.out.push_str(" {");
+                self.indent += 1;
+                for field in &record.fields {
+                    self.newline();
+                    self.visibility(field.is_public);
+                    write!(self.out, "{}: {};", field.name, field.field_type).unwrap();
+                }
+                self.indent -= 1;
+                if !record.fields.is_empty() {
+                    self.newline();
+                }
+                self.out.push('}');
+            }
+            Declaration::Union(union) => {
+                self.visibility(union.is_public);
+                write!(self.out, "union {}", union.name).unwrap();
+                self.type_parameters(&union.type_parameters);
+                self.out.push_str(" = ");
+                for (index, variant) in union.variants.iter().enumerate() {
+                    if index > 0 {
+                        self.out.push_str(" | ");
+                    }
+                    self.out.push_str(&variant.name);
+                    if let Some(variant_type) = &variant.variant_type {
+                        write!(self.out, "({})", variant_type).unwrap();
+                    }
+                }
+                self.out.push(';');
+            }
+            Declaration::Patch(patch) => {
+                write!(self.out, "patch {} {{", patch.target_type).unwrap();
+                self.indent += 1;
+                for (index, method) in patch.methods.iter().enumerate() {
+                    if index > 0 {
+                        self.out.push('\n');
+                    }
+                    self.newline();
+                    self.function(method);
+                }
+                self.indent -= 1;
+                if !patch.methods.is_empty() {
+                    self.newline();
+                }
+                self.out.push('}');
+            }
+            Declaration::Const(constant) => {
+                self.visibility(constant.is_public);
+                write!(
+                    self.out,
+                    "const {} {} = ",
+                    constant.const_type, constant.name
+                )
+                .unwrap();
+                self.expression(&constant.value, Precedence::LOWEST);
+                self.out.push(';');
+            }
+            Declaration::Import(import) => {
+                write!(self.out, "import {}::", import.module).unwrap();
+                match import.items.as_slice() {
+                    [item] => self.out.push_str(item),
+                    items => write!(self.out, "{{{}}}", items.join(", ")).unwrap(),
+                }
+                self.out.push(';');
+            }
+            Declaration::Statement(statement) => self.statement(statement),
+        }
+    }
+
+    fn visibility(&mut self, is_public: bool) {
+        if is_public {
+            self.out.push_str("pub ");
+        }
+    }
+
+    fn type_parameters(&mut self, parameters: &[TypeParameter]) {
+        if !parameters.is_empty() {
+            let parameters: Vec<String> = parameters.iter().map(ToString::to_string).collect();
+            write!(self.out, "<{}>", parameters.join(", ")).unwrap();
+        }
+    }
+
+    fn function(&mut self, function: &FunctionDeclaration) {
+        self.visibility(function.is_public);
+        write!(self.out, "fn {}", function.name).unwrap();
+        self.type_parameters(&function.type_parameters);
+        self.out.push('(');
+        for (index, parameter) in function.params.iter().enumerate() {
+            if index > 0 {
+                self.out.push_str(", ");
+            }
+            self.parameter(parameter);
+        }
+        self.out.push(')');
+        if let Some(return_type) = &function.return_type {
+            write!(self.out, " -> {}", return_type).unwrap();
+        }
+        self.out.push(' ');
+        self.body(&function.body);
+    }
+
+    fn parameter(&mut self, parameter: &Parameter) {
+        if parameter.param_type != Type::Inferred {
+            write!(self.out, "{} ", parameter.param_type).unwrap();
+        }
+        if parameter.is_mutable {
+            self.out.push('@');
+        }
+        self.out.push_str(&parameter.name);
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match &statement.kind {
+            StatementKind::VariableDeclaration {
+                name,
+                var_type,
+                is_mutable,
+                value,
+            } => {
+                if let Some(var_type) = var_type {
+                    write!(self.out, "{} ", var_type).unwrap();
+                }
+                if *is_mutable {
+                    self.out.push('@');
+                }
+                write!(self.out, "{} = ", name).unwrap();
+                self.expression(value, Precedence::LOWEST);
+            }
+            StatementKind::Assignment {
+                target,
+                operator,
+                value,
+            } => {
+                // `name = value` at the start of a statement declares `name`, so assigning to an
+                // existing variable needs parentheses to stay an assignment.
+                match &target.kind {
+                    ExpressionKind::Identifier(name) if *operator == BinaryOperator::Assign => {
+                        write!(self.out, "({})", name).unwrap();
+                    }
+                    _ => self.statement_expression(target),
+                }
+                write!(self.out, " {} ", operator.to_token()).unwrap();
+                self.expression(value, Precedence::LOWEST);
+            }
+            StatementKind::Expression(expression) => self.statement_expression(expression),
+            StatementKind::Return(value) => self.jump("return", value.as_deref()),
+            StatementKind::Break(value) => self.jump("break", value.as_deref()),
+        }
+        self.out.push(';');
+    }
+
+    /// An expression at the start of a statement. A block-like expression such as `if` ends the
+    /// statement there, so one that is only the first operand is parenthesized.
+    fn statement_expression(&mut self, expression: &Expression) {
+        if expression.requires_semicolon() && !leftmost(expression).requires_semicolon() {
+            self.out.push('(');
+            self.expression(expression, Precedence::LOWEST);
+            self.out.push(')');
+        } else {
+            self.expression(expression, Precedence::LOWEST);
+        }
+    }
+
+    fn jump(&mut self, keyword: &str, value: Option<&Expression>) {
+        self.out.push_str(keyword);
+        if let Some(value) = value {
+            self.out.push(' ');
+            self.expression(value, Precedence::LOWEST);
+        }
+    }
+
+    /// Print `expression`, parenthesized if it binds more loosely than `min_precedence`.
+    fn expression(&mut self, expression: &Expression, min_precedence: Precedence) {
+        if precedence(expression) < min_precedence {
+            self.out.push('(');
+            self.unparenthesized(expression);
+            self.out.push(')');
+        } else {
+            self.unparenthesized(expression);
+        }
+    }
+
+    fn unparenthesized(&mut self, expression: &Expression) {
+        match &expression.kind {
+            ExpressionKind::Literal(literal) => self.literal(literal),
+            ExpressionKind::Identifier(name) => self.out.push_str(name),
+            ExpressionKind::BinaryOperation {
+                left,
+                operator,
+                right,
+            } => {
+                let precedence = operator.precedence();
+                let (left_min, right_min) = match operator.associativity() {
+                    Associativity::Left => (precedence, precedence.tighter()),
+                    Associativity::Right => (precedence.tighter(), precedence),
+                    Associativity::None => (precedence.tighter(), precedence.tighter()),
+                };
+                let left_min = if matches!(
+                    operator,
+                    BinaryOperator::LessThan | BinaryOperator::LessThanOrEqual
+                ) && ends_with_cast(left)
+                {
+                    Precedence::PRIMARY
+                } else {
+                    left_min
+                };
+                self.expression(left, left_min);
+                write!(self.out, " {} ", operator.to_token()).unwrap();
+                self.expression(right, right_min);
+            }
+            ExpressionKind::UnaryOperation { operator, operand } => {
+                self.out.push_str(match operator {
+                    UnaryOperator::Not => "not ",
+                    UnaryOperator::Negate => "-",
+                    UnaryOperator::Reference => "&",
+                    UnaryOperator::MutableReference => "&@",
+                    UnaryOperator::Dereference => "*",
+                });
+                self.prefix_operand(operand, *operator == UnaryOperator::Negate);
+            }
+            ExpressionKind::Reference {
+                is_mutable,
+                expression,
+            } => {
+                self.out.push_str(if *is_mutable { "&@" } else { "&" });
+                self.prefix_operand(expression, false);
+            }
+            ExpressionKind::Dereference(expression) => {
+                self.out.push('*');
+                self.prefix_operand(expression, false);
+            }
+            ExpressionKind::Grouped(expression) => {
+                self.out.push('(');
+                self.expression(expression, Precedence::LOWEST);
+                self.out.push(')');
+            }
+            ExpressionKind::FunctionCall {
+                function,
+                arguments,
+            } => {
+                // `a.b(x)` is a method call, so calling a function stored in a field needs
+                // parentheses around the field access.
+                if matches!(&function.kind, ExpressionKind::RecordAccess { .. }) {
+                    self.expression(function, Precedence::PRIMARY);
+                } else {
+                    self.expression(function, Precedence::POSTFIX);
+                }
+                self.arguments(arguments);
+            }
+            ExpressionKind::RecordAccess { record, field } => {
+                self.expression(record, Precedence::POSTFIX);
+                write!(self.out, ".{}", field).unwrap();
+            }
+            ExpressionKind::MethodCall {
+                receiver,
+                method,
+                arguments,
+            } => {
+                self.expression(receiver, Precedence::POSTFIX);
+                write!(self.out, ".{}", method).unwrap();
+                self.arguments(arguments);
+            }
+            ExpressionKind::IndexAccess { collection, index } => {
+                self.expression(collection, Precedence::POSTFIX);
+                self.out.push('[');
+                self.expression(index, Precedence::LOWEST);
+                self.out.push(']');
+            }
+            ExpressionKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.out.push_str("if ");
+                self.head(condition);
+                self.body(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.out.push_str(" else ");
+                    if matches!(&else_branch.kind, ExpressionKind::If { .. }) {
+                        self.unparenthesized(else_branch);
+                    } else {
+                        self.body(else_branch);
+                    }
+                }
+            }
+            ExpressionKind::When {
+                expression,
+                branches,
+            } => {
+                self.out.push_str("when ");
+                self.head(expression);
+                self.out.push('{');
+                self.indent += 1;
+                for branch in branches {
+                    self.newline();
+                    self.pattern(&branch.pattern);
+                    if let Some(guard) = &branch.guard {
+                        self.out.push_str(" if ");
+                        self.expression(guard, Precedence::LOWEST);
+                    }
+                    self.out.push_str(": ");
+                    self.expression(&branch.body, Precedence::LOWEST);
+                    self.out.push(';');
+                }
+                self.indent -= 1;
+                if !branches.is_empty() {
+                    self.newline();
+                }
+                self.out.push('}');
+            }
+            ExpressionKind::Block {
+                statements,
+                final_expression,
+            } => {
+                self.out.push('{');
+                self.indent += 1;
+                for statement in statements {
+                    self.newline();
+                    self.statement(statement);
+                }
+                if let Some(final_expression) = final_expression {
+                    self.newline();
+                    self.statement_expression(final_expression);
+                }
+                self.indent -= 1;
+                if !statements.is_empty() || final_expression.is_some() {
+                    self.newline();
+                }
+                self.out.push('}');
+            }
+            ExpressionKind::Loop { body } => {
+                self.out.push_str("loop ");
+                self.body(body);
+            }
+            ExpressionKind::While { condition, body } => {
+                self.out.push_str("while ");
+                self.head(condition);
+                self.body(body);
+            }
+            ExpressionKind::For {
+                variable,
+                iterable,
+                body,
+            } => {
+                write!(self.out, "for {} in ", variable).unwrap();
+                self.head(iterable);
+                self.body(body);
+            }
+            ExpressionKind::ArrayLiteral(elements) => {
+                self.out.push('[');
+                self.comma_separated(elements);
+                self.out.push(']');
+            }
+            ExpressionKind::RecordLiteral {
+                record_type,
+                fields,
+                base,
+            } => {
+                write!(self.out, "{} {{", record_type).unwrap();
+                for (index, (name, value)) in fields.iter().enumerate() {
+                    self.out.push_str(if index > 0 { ", " } else { " " });
+                    write!(self.out, "{}: ", name).unwrap();
+                    self.expression(value, Precedence::LOWEST);
+                }
+                if let Some(base) = base {
+                    self.out
+                        .push_str(if fields.is_empty() { " .." } else { ", .." });
+                    self.expression(base, Precedence::LOWEST);
+                }
+                let empty = fields.is_empty() && base.is_none();
+                self.out.push_str(if empty { "}" } else { " }" });
+            }
+            ExpressionKind::UnionLiteral { variant, value, .. } => {
+                self.out.push_str(variant);
+                if let Some(value) = value {
+                    self.out.push('(');
+                    self.expression(value, Precedence::LOWEST);
+                    self.out.push(')');
+                }
+            }
+            ExpressionKind::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                // Ranges do not chain, so neither side may itself be an unparenthesized range.
+                self.expression(start, Precedence::RANGE.tighter());
+                self.out.push_str(if *inclusive { "..=" } else { ".." });
+                self.expression(end, Precedence::RANGE.tighter());
+            }
+            ExpressionKind::TypeAnnotation {
+                expression,
+                annotated_type,
+            } => {
+                self.expression(expression, Precedence::ASSIGNMENT);
+                write!(self.out, " :: {}", annotated_type).unwrap();
+            }
+            ExpressionKind::Cast { expression, target } => {
+                self.expression(expression, Precedence::CAST);
+                write!(self.out, " as {}", target).unwrap();
+            }
+            ExpressionKind::Closure {
+                params,
+                return_type,
+                body,
+                ..
+            } => {
+                if params.is_empty() {
+                    self.out.push_str("||");
+                } else {
+                    self.out.push('|');
+                    for (index, parameter) in params.iter().enumerate() {
+                        if index > 0 {
+                            self.out.push_str(", ");
+                        }
+                        self.parameter(parameter);
+                    }
+                    self.out.push('|');
+                }
+                self.out.push(' ');
+                match return_type {
+                    Some(return_type) => {
+                        write!(self.out, "-> {} ", return_type).unwrap();
+                        self.body(body);
+                    }
+                    None => self.expression(body, Precedence::LOWEST),
+                }
+            }
+        }
+    }
+
+    /// The operand of a prefix operator. `- -x` would lex the same as `--x`, which is not a valid
+    /// operator, so a negated operand that starts with `-` is parenthesized. One that starts with
+    /// a digit is set apart by a space, since `-5` is lexed as a negative literal.
+    fn prefix_operand(&mut self, operand: &Expression, negated: bool) {
+        let start = self.out.len();
+        self.expression(operand, Precedence::PREFIX);
+        if negated && self.out[start..].starts_with('-') {
+            self.out.insert(start, '(');
+            self.out.push(')');
+        } else if negated && self.out[start..].starts_with(|c: char| c.is_ascii_digit()) {
+            self.out.insert(start, ' ');
+        }
+    }
+
+    /// The condition of an `if` or `while`, the iterable of a `for`, or the value of a `when`,
+    /// followed by a space. A record literal there would be read as the start of the body, so an
+    /// expression containing one is parenthesized.
+    fn head(&mut self, expression: &Expression) {
+        let mut finder = RecordLiteralFinder(false);
+        finder.visit_expression(expression);
+        if finder.0 {
+            self.out.push('(');
+            self.expression(expression, Precedence::LOWEST);
+            self.out.push(')');
+        } else {
+            self.expression(expression, Precedence::LOWEST);
+        }
+        self.out.push(' ');
+    }
+
+    /// The body of a function, loop, or branch, which the grammar requires to be a block.
+    fn body(&mut self, body: &Expression) {
+        if matches!(&body.kind, ExpressionKind::Block { .. }) {
+            self.unparenthesized(body);
+        } else {
+            self.out.push_str("{ ");
+            self.expression(body, Precedence::LOWEST);
+            self.out.push_str(" }");
+        }
+    }
+
+    fn arguments(&mut self, arguments: &[Expression]) {
+        self.out.push('(');
+        self.comma_separated(arguments);
+        self.out.push(')');
+    }
+
+    fn comma_separated(&mut self, expressions: &[Expression]) {
+        for (index, expression) in expressions.iter().enumerate() {
+            if index > 0 {
+                self.out.push_str(", ");
+            }
+            self.expression(expression, Precedence::LOWEST);
+        }
+    }
+
+    fn pattern(&mut self, pattern: &Pattern) {
+        match &pattern.kind {
+            PatternKind::Identifier(name) => self.out.push_str(name),
+            PatternKind::Literal(literal) => self.literal(literal),
+            PatternKind::Union { variant, payload } => {
+                self.out.push_str(variant);
+                match payload.as_deref() {
+                    // The variant's parentheses already delimit a tuple payload.
+                    Some(payload) if matches!(payload.kind, PatternKind::Tuple(_)) => {
+                        self.pattern(payload)
+                    }
+                    Some(payload) => {
+                        self.out.push('(');
+                        self.pattern(payload);
+                        self.out.push(')');
+                    }
+                    None => {}
+                }
+            }
+            PatternKind::Record {
+                record_type,
+                fields,
+            } => {
+                write!(self.out, "{} {{ ", record_type).unwrap();
+                for (index, (name, pattern)) in fields.iter().enumerate() {
+                    if index > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.out.push_str(name);
+                    if !matches!(&pattern.kind, PatternKind::Identifier(binding) if binding == name)
+                    {
+                        self.out.push_str(": ");
+                        self.pattern(pattern);
+                    }
+                }
+                self.out.push_str(" }");
+            }
+            PatternKind::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                self.literal(start);
+                self.out.push_str(if *inclusive { "..=" } else { ".." });
+                self.literal(end);
+            }
+            PatternKind::Or(alternatives) => {
+                for (index, alternative) in alternatives.iter().enumerate() {
+                    if index > 0 {
+                        self.out.push_str(" | ");
+                    }
+                    self.pattern(alternative);
+                }
+            }
+            PatternKind::Tuple(elements) => {
+                self.out.push('(');
+                for (index, element) in elements.iter().enumerate() {
+                    if index > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.pattern(element);
+                }
+                self.out.push(')');
+            }
+            PatternKind::Wildcard => self.out.push('_'),
+            PatternKind::Else => self.out.push_str("else"),
+        }
+    }
+
+    fn literal(&mut self, literal: &Literal) {
+        match literal {
+            Literal::Integer(value) => write!(self.out, "{}", value).unwrap(),
+            Literal::Float(value) => {
+                // `Display` never uses exponents, but drops the fraction of whole numbers.
+                let text = value.to_string();
+                self.out.push_str(&text);
+                if value.is_finite() && !text.contains('.') {
+                    self.out.push_str(".0");
+                }
+            }
+            Literal::Boolean(value) => write!(self.out, "{}", value).unwrap(),
+            Literal::Unit => self.out.push_str("()"),
+            Literal::String(value) => {
+                self.out.push('"');
+                for c in value.chars() {
+                    self.escaped_char(c, '"');
+                }
+                self.out.push('"');
+            }
+            Literal::Char(value) => {
+                self.out.push('\'');
+                self.escaped_char(*value, '\'');
+                self.out.push('\'');
+            }
+            Literal::Bytes(bytes) => {
+                self.out.push_str("b\"");
+                for byte in bytes {
+                    match byte {
+                        b'"' | b'\\' | b'\n' | b'\r' | b'\t' | b'\0' => {
+                            self.escaped_char(char::from(*byte), '"')
+                        }
+                        0x20..=0x7e => self.out.push(char::from(*byte)),
+                        _ => write!(self.out, "\\x{:02x}", byte).unwrap(),
+                    }
+                }
+                self.out.push('"');
+            }
+        }
+    }
+
+    fn escaped_char(&mut self, c: char, quote: char) {
+        match c {
+            '\\' => self.out.push_str("\\\\"),
+            '\n' => self.out.push_str("\\n"),
+            '\r' => self.out.push_str("\\r"),
+            '\t' => self.out.push_str("\\t"),
+            '\0' => self.out.push_str("\\0"),
+            c if c == quote => {
+                self.out.push('\\');
+                self.out.push(c);
+            }
+            c if c.is_control() => write!(self.out, "\\u{{{:x}}}", u32::from(c)).unwrap(),
+            c => self.out.push(c),
+        }
+    }
+}
+
+struct RecordLiteralFinder(bool);
+
+impl Visitor for RecordLiteralFinder {
+    fn visit_expression(&mut self, expression: &Expression) {
+        if matches!(&expression.kind, ExpressionKind::RecordLiteral { .. }) {
+            self.0 = true;
+        } else if !self.0 && !matches!(&expression.kind, ExpressionKind::Grouped(_)) {
+            walk_expression(self, expression);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::Expression;
+    use crate::{Lexer, Parser, parse};
+    use test_case::test_case;
+
+    fn expression(source: &str) -> Expression {
+        let tokens = Lexer::new(source)
+            .fold_negative_literals()
+            .tokenize()
+            .expect("Lexer error");
+        Parser::new(tokens).parse_expression().expect("Parse error")
+    }
+
+    #[test_case("(a + b) * c", "(a + b) * c" ; "lower precedence operand")]
+    #[test_case("a + (b * c)", "a + b * c" ; "higher precedence operand")]
+    #[test_case("a - (b - c)", "a - (b - c)" ; "right operand of left associative")]
+    #[test_case("(a - b) - c", "a - b - c" ; "left operand of left associative")]
+    #[test_case("a && b || not c", "a and b or not c" ; "logical words")]
+    #[test_case("not (a and b)", "not (a and b)" ; "not of binary")]
+    #[test_case("(-a).abs()", "(-a).abs()" ; "receiver of method")]
+    #[test_case("-(a.abs())", "-a.abs()" ; "negated method call")]
+    #[test_case("- -x", "-(-x)" ; "double negation")]
+    #[test_case("(a.callback)(x)", "(a.callback)(x)" ; "call of field")]
+    #[test_case("(|x| x)(1)", "(|x| x)(1)" ; "called closure")]
+    #[test_case("f(|x| x + 1, || 2)", "f(|x| x + 1, || 2)" ; "closure arguments")]
+    #[test_case("(0..n).len()", "(0..n).len()" ; "range receiver")]
+    #[test_case("(a..b)..c", "(a..b)..c" ; "nested range")]
+    #[test_case("*(p.x) + &@v[0]", "*p.x + &@v[0]" ; "prefix operators")]
+    #[test_case("&(&x)", "&&x" ; "reference to reference")]
+    #[test_case("point { x: 1, y: -2 }", "point { x: 1, y: -2 }" ; "record literal")]
+    #[test_case("point { x: 1, ..(base) }", "point { x: 1, ..base }" ; "record update")]
+    #[test_case("(a + b) as f64", "(a + b) as f64" ; "cast of a sum")]
+    #[test_case("-(x as i8)", "-(x as i8)" ; "negated cast")]
+    #[test_case("(-x) as u8 * 2", "-x as u8 * 2" ; "cast of a negation")]
+    #[test_case("(x as i32) < y", "(x as i32) < y" ; "cast compared")]
+    #[test_case("(a + x as u64) <= 2", "(a + x as u64) <= 2" ; "sum ending with a cast compared")]
+    #[test_case("(x as i32) > y", "x as i32 > y" ; "cast before greater than")]
+    #[test_case("y < x as i32", "y < x as i32" ; "cast on the right of a comparison")]
+    #[test_case("point { ..a..b }", "point { ..a..b }" ; "record update from range")]
+    #[test_case("xs[i + 1].name", "xs[i + 1].name" ; "index")]
+    fn test_expression_to_source(source: &str, expected: &str) {
+        let printed = expression(source).to_source();
+
+        assert_eq!(printed, expected);
+        assert_eq!(expression(&printed), expression(source));
+    }
+
+    #[test_case("a + (b * c)" ; "redundant parentheses")]
+    #[test_case("((a - b)) - c" ; "doubled parentheses")]
+    #[test_case("-(a.abs())" ; "parenthesized operand")]
+    #[test_case("(a.callback)(x)" ; "call of field")]
+    fn test_kept_parentheses_to_source(source: &str) {
+        let tokens = Lexer::new(source).tokenize().expect("Lexer error");
+        let expression = Parser::new(tokens)
+            .keep_parentheses()
+            .parse_expression()
+            .expect("Parse error");
+
+        assert_eq!(expression.to_source(), source);
+    }
+
+    #[test_case(r#""say \"hi\"\n\t\\ \u{1}""# ; "string escapes")]
+    #[test_case(r"'\''" ; "quote char")]
+    #[test_case(r"'\\'" ; "backslash char")]
+    #[test_case(r#"b"\0\xffa\"""# ; "byte string")]
+    #[test_case("1.0" ; "whole float")]
+    #[test_case("0.125" ; "fractional float")]
+    #[test_case("'ü'" ; "unicode char")]
+    #[test_case("()" ; "unit")]
+    fn test_literal_to_source(source: &str) {
+        assert_eq!(expression(source).to_source(), source);
+    }
+
+    #[test_case("some(ok(v))" ; "nested variants")]
+    #[test_case("rectangle(w, _)" ; "tuple payload")]
+    #[test_case("(a, (b, ()))" ; "nested tuples")]
+    #[test_case("line { start: point { x, y: 0 }, stop }" ; "record")]
+    #[test_case("some(n) if n > limit(point { x: 0 })" ; "guard")]
+    #[test_case("1 | 2 | some(3 | 4, _)" ; "or pattern")]
+    #[test_case("-5..0 | 0.5..=1.0" ; "ranges")]
+    fn test_pattern_to_source(pattern: &str) {
+        let source = format!("when x {{ {}: 1 }}", pattern);
+        let printed = expression(&source).to_source();
+
+        assert!(printed.contains(&format!("{}: 1", pattern)), "{}", printed);
+        assert_eq!(expression(&printed), expression(&source));
+    }
+
+    #[test]
+    fn test_negated_number_to_source() {
+        let program = parse("x = -(5.abs());").unwrap();
+        let printed = program.to_source();
+
+        assert_eq!(printed, "x = - 5.abs();\n");
+        assert_eq!(parse(&printed).unwrap(), program);
+    }
+
+    #[test]
+    fn test_generic_function_to_source() {
+        let source = "fn first<T, N: usize>(fixedArray<T, N> items, fixedArray<T, 2 * (N + 1)> pairs) -> T {\n    items[0]\n}\n";
+
+        assert_eq!(crate::parse(source).unwrap().to_source(), source);
+    }
+
+    #[test]
+    fn test_record_literal_in_condition() {
+        let source = "if (p == point { x: 1 }) { a } else if b { c }";
+        let printed = expression(source).to_source();
+
+        assert_eq!(
+            printed,
+            "if (p == point { x: 1 }) {\n    a\n} else if b {\n    c\n}"
+        );
+        assert_eq!(expression(&printed), expression(source));
+    }
+
+    #[test_case("(if a { 1 } else { 2 }) + 1;", "(if" ; "binary operand")]
+    #[test_case("(when x { _: p; }).y = 1;", "(when" ; "assignment target")]
+    #[test_case("{ (loop { break 1; }).abs() };", "{\n    (loop" ; "block value")]
+    fn test_statement_starting_with_block_like(source: &str, expected: &str) {
+        let program = parse(source).expect("Parse error");
+        let printed = program.to_source();
+
+        assert!(printed.starts_with(expected), "{}", printed);
+        assert_eq!(parse(&printed).expect("Parse error"), program);
+    }
+
+    #[test]
+    fn test_program_to_source() {
+        let source = r#"
+            union  option<T> =some(T)|none;
+            record point{x:i32;y:i32;}
+            patch point { fn norm()->f64 { (x*x+y*y).sqrt() } fn zero() {} }
+            const i32 base = 1; const i32 limit = base * 10;
+            limit = 10; @count = 0;
+            fn main(string& @name, i32 n) -> i32 {
+                i32 @total = 0;
+                for i in 0..=n { total += i; };
+                (total) = total * 2;
+                result = when lookup(name) { some(v): v; none: { log("missing"); 0 }; _: -1; };
+                if total > limit { return total; };
+                loop { break; };
+                apply(|i32 a, @b| -> i32 { a }, |x| x)
+            }
+        "#;
+        let program = parse(source).expect("Parse error");
+        let printed = program.to_source();
+
+        assert_eq!(
+            printed,
+            r#"union option<T> = some(T) | none;
+
+record point {
+    x: i32;
+    y: i32;
+}
+
+patch point {
+    fn norm() -> f64 {
+        (x * x + y * y).sqrt()
+    }
+
+    fn zero() {}
+}
+
+const i32 base = 1;
+const i32 limit = base * 10;
+
+limit = 10;
+@count = 0;
+
+fn main(string& @name, i32 n) -> i32 {
+    i32 @total = 0;
+    for i in 0..=n {
+        total += i;
+    };
+    (total) = total * 2;
+    result = when lookup(name) {
+        some(v): v;
+        none: {
+            log("missing");
+            0
+        };
+        _: -1;
+    };
+    if total > limit {
+        return total;
+    };
+    loop {
+        break;
+    };
+    apply(|i32 a, @b| -> i32 {
+        a
+    }, |x| x)
+}
+"#
+        );
+        assert_eq!(parse(&printed), Ok(program));
+    }
+}
@@ -0,0 +1,187 @@
+//! Where the statements, expressions, patterns, and imports of a parsed program were written,
+//! and where its declarations, parameters, fields, and variants were named.
+//!
+//! The syntax tree has no spans, so the parser records them on the side, by the id of each node
+//! as it finishes it. They stay right for as long as the nodes keep their ids, such as after the
+//! declarations of the prelude are put in front of the program's.
+
+use crate::node_id::NodeId;
+use lexer::tokens::Span;
+use std::collections::HashMap;
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Spans(HashMap<NodeId, Span>);
+
+impl Spans {
+    pub(crate) fn record(&mut self, id: NodeId, span: Span) {
+        self.0.insert(id, span);
+    }
+
+    /// Add the spans of another program, whose nodes have ids apart from the ones already here.
+    pub fn extend(&mut self, other: Spans) {
+        self.0.extend(other.0);
+    }
+
+    /// The span of the statement, expression, pattern, or import `id`, or of the name of the
+    /// declaration, parameter, field, variant, or type parameter `id`. `None` for any other node,
+    /// or one that was not parsed with these spans.
+    pub fn get(&self, id: NodeId) -> Option<Span> {
+        self.0.get(&id).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{Declaration, Expression, ExpressionKind, Program};
+    use crate::node_id::NodeIds;
+    use crate::visit::{Visitor, walk_expression};
+    use crate::{Parser, parse_file};
+    use lexer::Lexer;
+    use lexer::source::SourceMap;
+
+    /// Checks that the span of each expression is the text of an expression equal to it.
+    struct Reparse<'s> {
+        source: &'s str,
+        spans: &'s Spans,
+    }
+
+    impl Visitor for Reparse<'_> {
+        fn visit_expression(&mut self, expression: &Expression) {
+            let span = self.spans.get(expression.id).unwrap();
+            let text: String = self
+                .source
+                .chars()
+                .skip(span.start)
+                .take(span.end + 1 - span.start)
+                .collect();
+            let tokens = Lexer::new(&text).tokenize().unwrap();
+            assert_eq!(
+                Parser::new(tokens).parse_expression().as_ref(),
+                Ok(expression),
+                "{}",
+                text
+            );
+            walk_expression(self, expression);
+        }
+    }
+
+    #[test]
+    fn test_spans_are_recorded_for_their_nodes() {
+        let source = "const i32 LIMIT = 2 * 3;
+            record point { x: i32; y: i32; }
+            fn get(arrayList<i32> xs, fixedArray<u8, 4> _bytes) -> i32 {
+                total = xs[0] + -xs[1] * (xs[2] as i32);
+                p = point { x: 1, y: total };
+                r = &p;
+                when p.x { 1 if total > 0: { total += 1; }; _: (); };
+                for x in 0..LIMIT { if x == 1 { break; } else if true { loop { break; } } }
+                f = |a| |b| a + b;
+                f(1)(2) + [1, 2][0] + r.y
+            }
+            println(\"ü\", get([1, 2, 3], [0]));";
+        let mut sources = SourceMap::new();
+        let file = sources.add_file("main.cv", source);
+        let (program, spans, errors) = parse_file(sources.get(file).unwrap());
+        assert_eq!(errors, []);
+
+        Reparse {
+            source,
+            spans: &spans,
+        }
+        .visit_program(&program);
+    }
+
+    #[test]
+    fn test_declarations_are_spanned_by_their_names() {
+        let source = "record point<T> { x: T; } union shape = dot | circle(f64);
+            fn area(shape s) { f = |@n| n; }";
+        let mut sources = SourceMap::new();
+        let file = sources.add_file("main.cv", source);
+        let (program, spans, errors) = parse_file(sources.get(file).unwrap());
+        assert_eq!(errors, []);
+
+        let name = |id| {
+            let span = spans.get(id).unwrap();
+            source[span.start..=span.end].to_string()
+        };
+        let [
+            Declaration::Record(record),
+            Declaration::Union(union),
+            Declaration::Function(area),
+        ] = program.declarations.as_slice()
+        else {
+            panic!("expected a record, a union, and a function");
+        };
+        assert_eq!(name(record.id), "point");
+        assert_eq!(name(record.type_parameters[0].id), "T");
+        assert_eq!(name(record.fields[0].id), "x");
+        assert_eq!(name(union.id), "shape");
+        assert_eq!(name(union.variants[1].id), "circle");
+        assert_eq!(name(area.id), "area");
+        assert_eq!(name(area.params[0].id), "s");
+        let ExpressionKind::Block { statements, .. } = &area.body.kind else {
+            panic!("expected a block");
+        };
+        let crate::ast::StatementKind::VariableDeclaration { value, .. } = &statements[0].kind
+        else {
+            panic!("expected a variable declaration");
+        };
+        let ExpressionKind::Closure { params, .. } = &value.kind else {
+            panic!("expected a closure");
+        };
+        assert_eq!(name(params[0].id), "n");
+    }
+
+    #[test]
+    fn test_spans_survive_declarations_put_in_front() {
+        let mut sources = SourceMap::new();
+        let file = sources.add_file("main.cv", "x = 1;\nfn main() { x + 2; }");
+        let (parsed, spans, _) = parse_file(sources.get(file).unwrap());
+        let mut other = crate::parse("y = 0;").unwrap();
+        NodeIds::after(&parsed).number_program(&mut other);
+        let mut declarations = other.declarations;
+        declarations.extend(parsed.declarations);
+        let program = Program { declarations };
+
+        let Declaration::Function(main) = &program.declarations[2] else {
+            panic!("expected a function");
+        };
+        let ExpressionKind::Block { statements, .. } = &main.body.kind else {
+            panic!("expected a block");
+        };
+        let span = spans.get(statements[0].id).unwrap();
+        assert_eq!(
+            sources.get(span.file).unwrap().line_col(span.start),
+            (2, 13)
+        );
+        assert_eq!(span.end - span.start, "x + 2;".len() - 1);
+        let Declaration::Statement(y) = &program.declarations[0] else {
+            panic!("expected a statement");
+        };
+        assert_eq!(spans.get(y.id), None);
+    }
+
+    #[test]
+    fn test_spans_of_a_program_numbered_after_another() {
+        let earlier = crate::parse("x = 1;\ny = 2;").unwrap();
+        let mut sources = SourceMap::new();
+        let file = sources.add_file("input", "x + y;");
+        let tokens = Lexer::for_file(sources.get(file).unwrap())
+            .tokenize()
+            .unwrap();
+        let mut parser = Parser::new(tokens).numbered_from(NodeIds::after(&earlier));
+        let program = parser.parse_program().unwrap();
+
+        let Declaration::Statement(statement) = &program.declarations[0] else {
+            panic!("expected a statement");
+        };
+        assert!(
+            crate::node_id::NodeIndex::new(&earlier)
+                .kind(statement.id)
+                .is_none()
+        );
+        let span = parser.spans().get(statement.id).unwrap();
+        assert_eq!((span.start, span.end), (0, 5));
+    }
+}
@@ -0,0 +1,824 @@
+//! Early validation run right after parsing, rejecting declarations that can never be valid
+//! regardless of name resolution or typing.
+
+use crate::ast::{
+    ArraySize, BinaryOperator, Declaration, Expression, ExpressionKind, FunctionDeclaration,
+    Literal, Pattern, PatternKind, Program, Type, TypeParameter, UnaryOperator,
+};
+use crate::const_eval::{ConstError, ConstEvaluator, ConstValue};
+use crate::node_id::NodeId;
+use crate::operators::TypeClass;
+use crate::visit::{Visitor, walk_expression, walk_pattern, walk_type};
+use std::fmt;
+use thiserror::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DuplicateKind {
+    Field,
+    Parameter,
+    Method,
+    /// A field given a value twice in a record literal.
+    FieldValue,
+}
+
+impl DuplicateKind {
+    /// The kind of declaration the duplicated names belong to.
+    fn owner(&self) -> &'static str {
+        match self {
+            DuplicateKind::Field => "record",
+            DuplicateKind::Parameter => "function",
+            DuplicateKind::Method => "patch",
+            DuplicateKind::FieldValue => "literal of",
+        }
+    }
+}
+
+impl fmt::Display for DuplicateKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind_str = match self {
+            DuplicateKind::Field | DuplicateKind::FieldValue => "field",
+            DuplicateKind::Parameter => "parameter",
+            DuplicateKind::Method => "method",
+        };
+        write!(f, "{}", kind_str)
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Clone)]
+pub enum ValidationError {
+    /// `first` and `node` are the two occurrences of `name`: the field, parameter, or method
+    /// declarations, or the values of the field in a record literal.
+    #[error("Duplicate {kind} '{name}' in {} '{owner}'", kind.owner())]
+    Duplicate {
+        kind: DuplicateKind,
+        owner: String,
+        name: String,
+        first: NodeId,
+        node: NodeId,
+    },
+    /// `node` is the type parameter.
+    #[error(
+        "Const parameter '{name}' of '{owner}' has type '{found}', but must have an integer type"
+    )]
+    NonIntegerConstParameter {
+        owner: String,
+        name: String,
+        found: Type,
+        node: NodeId,
+    },
+    /// `node` is the declaration the size is written in.
+    #[error(
+        "Array size '{name}' in '{owner}' is neither a const parameter of '{owner}' nor a const"
+    )]
+    UnknownArraySize {
+        owner: String,
+        name: String,
+        node: NodeId,
+    },
+    /// `node` is the declaration the size is written in.
+    #[error("Array size '{size}' in '{owner}' is not a non-negative integer constant")]
+    InvalidArraySize {
+        owner: String,
+        size: ArraySize,
+        node: NodeId,
+    },
+    #[error("Initializer of const '{name}' is not a compile-time constant")]
+    NonConstantInitializer { name: String, node: NodeId },
+    /// A cycle is reported once, for the `const` it starts at.
+    #[error("Cannot evaluate const '{name}': {error}")]
+    InvalidConstant {
+        name: String,
+        error: ConstError,
+        node: NodeId,
+    },
+    /// `alternative` is the 1-based position of the alternative that lacks the binding, and
+    /// `node` the alternative itself.
+    #[error(
+        "Alternative {alternative} of an or-pattern does not bind '{name}', but another alternative does"
+    )]
+    InconsistentOrPattern {
+        name: String,
+        alternative: usize,
+        node: NodeId,
+    },
+}
+
+impl ValidationError {
+    /// The node the error is about.
+    pub fn node(&self) -> NodeId {
+        match self {
+            ValidationError::Duplicate { node, .. }
+            | ValidationError::NonIntegerConstParameter { node, .. }
+            | ValidationError::UnknownArraySize { node, .. }
+            | ValidationError::InvalidArraySize { node, .. }
+            | ValidationError::NonConstantInitializer { node, .. }
+            | ValidationError::InvalidConstant { node, .. }
+            | ValidationError::InconsistentOrPattern { node, .. } => *node,
+        }
+    }
+
+    /// The earlier occurrence a duplicate repeats.
+    pub fn first(&self) -> Option<NodeId> {
+        match self {
+            ValidationError::Duplicate { first, .. } => Some(*first),
+            _ => None,
+        }
+    }
+}
+
+/// Check every declaration in `program`, returning all problems found.
+pub fn validate(program: &Program) -> Vec<ValidationError> {
+    validate_after(program, &[])
+}
+
+/// Check every declaration in `program` as [`validate`] does, after the programs that made
+/// `declarations`, whose `const`s it may use.
+pub fn validate_after(program: &Program, declarations: &[&Declaration]) -> Vec<ValidationError> {
+    let mut errors = Vec::new();
+    let mut constants =
+        ConstEvaluator::of(declarations.iter().copied().chain(&program.declarations));
+    for declaration in &program.declarations {
+        match declaration {
+            Declaration::Function(function) => {
+                validate_function(function, &mut constants, &mut errors);
+            }
+            Declaration::Record(record) => {
+                let fields = record.fields.iter().map(|f| (&f.name, f.id));
+                check_duplicates(DuplicateKind::Field, &record.name, fields, &mut errors);
+                let field_types = record.fields.iter().map(|f| &f.field_type);
+                check_generics(
+                    (&record.name, record.id),
+                    &record.type_parameters,
+                    field_types,
+                    &mut constants,
+                    &mut errors,
+                );
+            }
+            // Variants share the program's namespace, so name resolution reports a duplicate
+            // one along with the other names it clashes with.
+            Declaration::Union(union) => {
+                let variant_types = union
+                    .variants
+                    .iter()
+                    .filter_map(|v| v.variant_type.as_ref());
+                check_generics(
+                    (&union.name, union.id),
+                    &union.type_parameters,
+                    variant_types,
+                    &mut constants,
+                    &mut errors,
+                );
+            }
+            Declaration::Patch(patch) => {
+                let methods = patch.methods.iter().map(|m| (&m.name, m.id));
+                let target = patch.target_type.to_string();
+                check_duplicates(DuplicateKind::Method, &target, methods, &mut errors);
+                for method in &patch.methods {
+                    validate_function(method, &mut constants, &mut errors);
+                }
+            }
+            Declaration::Const(constant) => {
+                let name = constant.name.clone();
+                let node = constant.id;
+                match constants.constant(&name) {
+                    Ok(_) | Err(ConstError::InvalidDependency { .. }) => {}
+                    Err(ConstError::NotConstant { .. }) => {
+                        errors.push(ValidationError::NonConstantInitializer { name, node });
+                    }
+                    Err(ConstError::Cycle { cycle }) if cycle[0] != name => {}
+                    Err(error) => {
+                        errors.push(ValidationError::InvalidConstant { name, error, node });
+                    }
+                }
+            }
+            Declaration::Import(_) | Declaration::Statement(_) => {}
+        }
+    }
+
+    check_or_patterns(program, &mut errors);
+    RecordLiterals(&mut errors).visit_program(program);
+    errors
+}
+
+fn validate_function(
+    function: &FunctionDeclaration,
+    constants: &mut ConstEvaluator,
+    errors: &mut Vec<ValidationError>,
+) {
+    let params = function.params.iter().map(|p| (&p.name, p.id));
+    check_duplicates(DuplicateKind::Parameter, &function.name, params, errors);
+    let signature_types = function
+        .params
+        .iter()
+        .map(|p| &p.param_type)
+        .chain(&function.return_type);
+    check_generics(
+        (&function.name, function.id),
+        &function.type_parameters,
+        signature_types,
+        constants,
+        errors,
+    );
+}
+
+/// Check that const parameters are integers, and that every `fixedArray` length in `types`
+/// names one of them or a `const`, or is an integer constant expression over those that does
+/// not come out negative. `owner` is the name and id of the declaration they belong to.
+fn check_generics<'a>(
+    (owner, node): (&str, NodeId),
+    parameters: &[TypeParameter],
+    types: impl Iterator<Item = &'a Type>,
+    constants: &mut ConstEvaluator,
+    errors: &mut Vec<ValidationError>,
+) {
+    for parameter in parameters {
+        if let Some(const_type) = &parameter.const_type
+            && !TypeClass::Integer.contains(const_type)
+        {
+            errors.push(ValidationError::NonIntegerConstParameter {
+                owner: owner.to_string(),
+                name: parameter.name.clone(),
+                found: const_type.clone(),
+                node: parameter.id,
+            });
+        }
+    }
+
+    let const_parameters: Vec<&str> = parameters
+        .iter()
+        .filter(|parameter| parameter.const_type.is_some())
+        .map(|parameter| parameter.name.as_str())
+        .collect();
+    let mut sizes = ArraySizes(Vec::new());
+    for ty in types {
+        sizes.visit_type(ty);
+    }
+    for size in sizes.0 {
+        let expression = match &size {
+            ArraySize::Literal(_) => continue,
+            ArraySize::Parameter(name) => ExpressionKind::Identifier(name.clone()).into(),
+            ArraySize::Expression(expression) => (**expression).clone(),
+        };
+
+        let mut names = Names(Vec::new());
+        names.visit_expression(&expression);
+        let mut uses_parameters = false;
+        let mut all_known = true;
+        for name in names.0 {
+            if const_parameters.contains(&name.as_str()) {
+                uses_parameters = true;
+            } else if !constants.is_const(&name) {
+                all_known = false;
+                errors.push(ValidationError::UnknownArraySize {
+                    owner: owner.to_string(),
+                    name,
+                    node,
+                });
+            }
+        }
+
+        // A size that uses const parameters is only known once the declaration is
+        // instantiated, so until then it only has to be integer arithmetic.
+        let valid = if uses_parameters {
+            is_integer_arithmetic(&expression)
+        } else {
+            matches!(constants.evaluate(&expression), Ok(ConstValue::Integer(value)) if value >= 0)
+        };
+        if all_known && !valid {
+            errors.push(ValidationError::InvalidArraySize {
+                owner: owner.to_string(),
+                size,
+                node,
+            });
+        }
+    }
+}
+
+/// Whether `expression` is built from integer literals and names with negation and arithmetic
+/// alone.
+fn is_integer_arithmetic(expression: &Expression) -> bool {
+    match &expression.kind {
+        ExpressionKind::Literal(Literal::Integer(_)) | ExpressionKind::Identifier(_) => true,
+        ExpressionKind::UnaryOperation {
+            operator: UnaryOperator::Negate,
+            operand,
+        }
+        | ExpressionKind::Grouped(operand) => is_integer_arithmetic(operand),
+        ExpressionKind::BinaryOperation {
+            left,
+            operator,
+            right,
+        } => {
+            matches!(
+                operator,
+                BinaryOperator::Add
+                    | BinaryOperator::Subtract
+                    | BinaryOperator::Multiply
+                    | BinaryOperator::Divide
+                    | BinaryOperator::Modulus
+            ) && is_integer_arithmetic(left)
+                && is_integer_arithmetic(right)
+        }
+        _ => false,
+    }
+}
+
+/// Collects every `fixedArray` length.
+struct ArraySizes(Vec<ArraySize>);
+
+impl Visitor for ArraySizes {
+    fn visit_type(&mut self, ty: &Type) {
+        if let Type::FixedArray { size, .. } = ty {
+            self.0.push(size.clone());
+        }
+        walk_type(self, ty);
+    }
+}
+
+/// Collects the names an expression refers to.
+struct Names(Vec<String>);
+
+impl Visitor for Names {
+    fn visit_expression(&mut self, expression: &Expression) {
+        if let ExpressionKind::Identifier(name) = &expression.kind {
+            self.0.push(name.clone());
+        }
+        walk_expression(self, expression);
+    }
+}
+
+/// Check that every alternative of each or-pattern binds the same names. A bare identifier that
+/// names a variant of a union declared in `program` matches that variant instead of binding.
+fn check_or_patterns(program: &Program, errors: &mut Vec<ValidationError>) {
+    let variants = program
+        .declarations
+        .iter()
+        .filter_map(|declaration| match declaration {
+            Declaration::Union(union) => Some(&union.variants),
+            _ => None,
+        })
+        .flatten()
+        .map(|variant| variant.name.as_str())
+        .collect();
+    OrPatternBindings { variants, errors }.visit_program(program);
+}
+
+struct OrPatternBindings<'a> {
+    variants: Vec<&'a str>,
+    errors: &'a mut Vec<ValidationError>,
+}
+
+impl OrPatternBindings<'_> {
+    fn bound_names(&self, pattern: &Pattern, names: &mut Vec<String>) {
+        match &pattern.kind {
+            PatternKind::Identifier(name) if !self.variants.contains(&name.as_str()) => {
+                names.push(name.clone())
+            }
+            PatternKind::Union {
+                payload: Some(payload),
+                ..
+            } => self.bound_names(payload, names),
+            PatternKind::Record { fields, .. } => {
+                for (_, field) in fields {
+                    self.bound_names(field, names);
+                }
+            }
+            PatternKind::Tuple(elements) => {
+                for element in elements {
+                    self.bound_names(element, names);
+                }
+            }
+            // A nested or-pattern is checked on its own, so its first alternative stands for all.
+            PatternKind::Or(alternatives) => {
+                if let Some(first) = alternatives.first() {
+                    self.bound_names(first, names);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Visitor for OrPatternBindings<'_> {
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        if let PatternKind::Or(alternatives) = &pattern.kind {
+            let bound: Vec<Vec<String>> = alternatives
+                .iter()
+                .map(|alternative| {
+                    let mut names = Vec::new();
+                    self.bound_names(alternative, &mut names);
+                    names
+                })
+                .collect();
+            let mut all: Vec<&String> = Vec::new();
+            for name in bound.iter().flatten() {
+                if !all.contains(&name) {
+                    all.push(name);
+                }
+            }
+
+            for (index, (names, alternative)) in bound.iter().zip(alternatives).enumerate() {
+                for name in all.iter().filter(|name| !names.contains(name)) {
+                    self.errors.push(ValidationError::InconsistentOrPattern {
+                        name: name.to_string(),
+                        alternative: index + 1,
+                        node: alternative.id,
+                    });
+                }
+            }
+        }
+        walk_pattern(self, pattern);
+    }
+}
+
+/// Reports the fields each record literal gives a value more than once.
+struct RecordLiterals<'a>(&'a mut Vec<ValidationError>);
+
+impl Visitor for RecordLiterals<'_> {
+    fn visit_expression(&mut self, expression: &Expression) {
+        if let ExpressionKind::RecordLiteral {
+            record_type,
+            fields,
+            ..
+        } = &expression.kind
+        {
+            let values = fields.iter().map(|(name, value)| (name, value.id));
+            let owner = record_type.to_string();
+            check_duplicates(DuplicateKind::FieldValue, &owner, values, self.0);
+        }
+        walk_expression(self, expression);
+    }
+}
+
+/// Report every name in `names` that already appeared earlier in the same declaration, with the
+/// node each one names.
+fn check_duplicates<'a>(
+    kind: DuplicateKind,
+    owner: &str,
+    names: impl Iterator<Item = (&'a String, NodeId)>,
+    errors: &mut Vec<ValidationError>,
+) {
+    let mut seen: Vec<(&String, NodeId)> = Vec::new();
+    for (name, node) in names {
+        if let Some(&(_, first)) = seen.iter().find(|(previous, _)| *previous == name) {
+            errors.push(ValidationError::Duplicate {
+                kind,
+                owner: owner.to_string(),
+                name: name.clone(),
+                first,
+                node,
+            });
+        }
+        seen.push((name, node));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{Parameter, PatchDeclaration, Type};
+    use crate::node_id::NodeId;
+    use crate::operators::ArithmeticError;
+
+    fn function(name: &str, params: &[&str]) -> FunctionDeclaration {
+        FunctionDeclaration {
+            id: NodeId::UNASSIGNED,
+            name: name.to_string(),
+            is_public: false,
+            type_parameters: vec![],
+            params: params
+                .iter()
+                .map(|param| Parameter {
+                    id: NodeId::UNASSIGNED,
+                    name: param.to_string(),
+                    param_type: Type::I32,
+                    is_mutable: false,
+                    is_ref: false,
+                })
+                .collect(),
+            return_type: None,
+            body: Box::new(
+                ExpressionKind::Block {
+                    statements: vec![],
+                    final_expression: None,
+                }
+                .into(),
+            ),
+        }
+    }
+
+    #[test]
+    fn test_valid_program() {
+        let program = Program {
+            declarations: vec![Declaration::Function(function("add", &["a", "b"]))],
+        };
+
+        assert_eq!(validate(&program), vec![]);
+    }
+
+    #[test]
+    fn test_duplicate_field() {
+        let program =
+            crate::parse("record point { x: i32; y: i32; x: f64; }").expect("Parse error");
+        let Declaration::Record(record) = &program.declarations[0] else {
+            panic!("expected a record");
+        };
+
+        assert_eq!(
+            validate(&program),
+            vec![ValidationError::Duplicate {
+                kind: DuplicateKind::Field,
+                owner: "point".to_string(),
+                name: "x".to_string(),
+                first: record.fields[0].id,
+                node: record.fields[2].id,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_duplicate_field_value() {
+        let program = crate::parse(
+            "record point { x: i32; y: i32; } fn f() { p = point { x: 1, x: 2, y: 3 }; }",
+        )
+        .expect("Parse error");
+        let errors = validate(&program);
+
+        assert_eq!(
+            errors.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            ["Duplicate field 'x' in literal of 'point'"]
+        );
+        assert_ne!(errors[0].first(), Some(errors[0].node()));
+    }
+
+    #[test]
+    fn test_duplicate_method() {
+        let program = crate::parse(
+            "patch point { fn norm() -> f64 { 0.0 } fn scale() {} fn norm() -> f64 { 1.0 } }",
+        )
+        .expect("Parse error");
+
+        assert_eq!(
+            validate(&program)
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            ["Duplicate method 'norm' in patch 'point'"]
+        );
+    }
+
+    #[test]
+    fn test_const_generics() {
+        let program = crate::parse(
+            "record buffer<N: usize> { data: fixedArray<u8, N>; }
+             union chunk<N: u16> = full(fixedArray<u8, N>) | partial(arrayList<u8>);
+             fn fill(buffer<16> b) {}",
+        )
+        .expect("Parse error");
+
+        assert_eq!(validate(&program), vec![]);
+    }
+
+    #[test]
+    fn test_computed_array_sizes() {
+        let program = crate::parse(
+            "const usize len = 4;
+             const usize double = len * 2;
+             record packet<N: usize> {
+                 header: fixedArray<u8, len>;
+                 body: fixedArray<u8, double + 1>;
+                 trailer: fixedArray<u8, N * 2 - 1>;
+                 footer: fixedArray<u8, footer_len>;
+             }
+             const usize footer_len = (len + 1) as usize;",
+        )
+        .expect("Parse error");
+
+        assert_eq!(validate(&program), vec![]);
+    }
+
+    #[test]
+    fn test_invalid_array_sizes() {
+        let program = crate::parse(
+            "const usize n = 2;
+             const f64 ratio = 1.5;
+             record r<N: usize> {
+                 a: fixedArray<u8, n - 3>;
+                 b: fixedArray<u8, 1.5>;
+                 c: fixedArray<u8, ratio>;
+                 d: fixedArray<u8, 1 / 0>;
+                 e: fixedArray<u8, missing + 1>;
+                 f: fixedArray<u8, N / 1.5>;
+             }",
+        )
+        .expect("Parse error");
+        let Declaration::Record(record) = &program.declarations[2] else {
+            panic!("expected a record");
+        };
+        let invalid = |field: usize| {
+            let Type::FixedArray { size, .. } = &record.fields[field].field_type else {
+                panic!("expected a fixedArray");
+            };
+            ValidationError::InvalidArraySize {
+                owner: "r".to_string(),
+                size: size.clone(),
+                node: record.id,
+            }
+        };
+
+        assert_eq!(
+            validate(&program),
+            vec![
+                invalid(0),
+                invalid(1),
+                invalid(2),
+                invalid(3),
+                ValidationError::UnknownArraySize {
+                    owner: "r".to_string(),
+                    name: "missing".to_string(),
+                    node: record.id,
+                },
+                invalid(5),
+            ]
+        );
+        assert_eq!(
+            invalid(0).to_string(),
+            "Array size 'n - 3' in 'r' is not a non-negative integer constant"
+        );
+    }
+
+    #[test]
+    fn test_const_generic_functions() {
+        let program = crate::parse(
+            "fn first<T, N: usize>(fixedArray<T, N> items) -> T { items[0] }
+             fn pad(fixedArray<u8, M> bytes) -> fixedArray<u8, M> { bytes }",
+        )
+        .expect("Parse error");
+        let Declaration::Function(pad) = &program.declarations[1] else {
+            panic!("expected a function");
+        };
+        let unknown = || ValidationError::UnknownArraySize {
+            owner: "pad".to_string(),
+            name: "M".to_string(),
+            node: pad.id,
+        };
+
+        assert_eq!(validate(&program), vec![unknown(), unknown()]);
+    }
+
+    #[test]
+    fn test_invalid_const_generics() {
+        let program = crate::parse(
+            "record grid<T, W: usize, H: f64> {
+                 rows: fixedArray<fixedArray<T, W>, H>;
+                 cells: fixedArray<T, T>;
+                 spare: arrayList<fixedArray<T, N>>;
+             }",
+        )
+        .expect("Parse error");
+        let Declaration::Record(grid) = &program.declarations[0] else {
+            panic!("expected a record");
+        };
+        let owner = || "grid".to_string();
+
+        assert_eq!(
+            validate(&program),
+            vec![
+                ValidationError::NonIntegerConstParameter {
+                    owner: owner(),
+                    name: "H".to_string(),
+                    found: Type::F64,
+                    node: grid.type_parameters[2].id,
+                },
+                ValidationError::UnknownArraySize {
+                    owner: owner(),
+                    name: "T".to_string(),
+                    node: grid.id,
+                },
+                ValidationError::UnknownArraySize {
+                    owner: owner(),
+                    name: "N".to_string(),
+                    node: grid.id,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_or_patterns() {
+        let program = crate::parse(
+            "union shape = circle(f64) | square(f64) | empty;
+             fn area(shape s) -> f64 {
+                 when s {
+                     circle(size) | square(size): size * size;
+                     empty | _: 0.0;
+                 }
+             }
+             fn describe(shape s) {
+                 when s {
+                     circle(r) | square(side): print(r);
+                     (a, b) | (b, _): print(b);
+                     some(x | y): print(x);
+                 };
+             }",
+        )
+        .expect("Parse error");
+        let inconsistent = |name: &str, alternative| {
+            ValidationError::InconsistentOrPattern {
+                name: name.to_string(),
+                alternative,
+                node: NodeId::UNASSIGNED,
+            }
+            .to_string()
+        };
+        let errors = validate(&program);
+
+        assert_eq!(
+            errors.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            [
+                inconsistent("side", 1),
+                inconsistent("r", 2),
+                inconsistent("a", 2),
+                inconsistent("y", 1),
+                inconsistent("x", 2),
+            ]
+        );
+        assert_ne!(errors[0].node(), errors[1].node());
+    }
+
+    #[test]
+    fn test_constants() {
+        let program = crate::parse(
+            "const u32 max_users = 1000;
+             const u32 max_sessions = max_users * 4 + 1;
+             const fixedArray<i32, 3> offsets = [-1, 0, -max_users];
+             const bool verbose = not (max_sessions > 10 and true);
+             const u32 cyclic = cyclic + 1;
+             const u32 later = max_retries;
+             const u32 max_retries = 3;
+             const u32 ping = pong * 2;
+             const u32 pong = ping - 1;
+             const u32 uses_cycle = ping;
+             const i32 ratio = 1 / 0;
+             const i32 uses_ratio = ratio + 1;
+             const string greeting = format(\"hello\");
+             const i32 block = { 1 };",
+        )
+        .expect("Parse error");
+        let node = |name: &str| {
+            program
+                .declarations
+                .iter()
+                .find_map(|declaration| match declaration {
+                    Declaration::Const(constant) if constant.name == name => Some(constant.id),
+                    _ => None,
+                })
+                .expect("no such const")
+        };
+        let non_constant = |name: &str| ValidationError::NonConstantInitializer {
+            name: name.to_string(),
+            node: node(name),
+        };
+        let invalid = |name: &str, error: ConstError| ValidationError::InvalidConstant {
+            name: name.to_string(),
+            error,
+            node: node(name),
+        };
+        let cycle = |names: &[&str]| ConstError::Cycle {
+            cycle: names.iter().map(ToString::to_string).collect(),
+        };
+
+        assert_eq!(
+            validate(&program),
+            vec![
+                invalid("cyclic", cycle(&["cyclic", "cyclic"])),
+                invalid("ping", cycle(&["ping", "pong", "ping"])),
+                invalid(
+                    "ratio",
+                    ConstError::Arithmetic(ArithmeticError::DivisionByZero)
+                ),
+                non_constant("greeting"),
+                non_constant("block"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_duplicate_parameter_in_patch_method() {
+        let program = Program {
+            declarations: vec![Declaration::Patch(PatchDeclaration {
+                id: NodeId::UNASSIGNED,
+                target_type: Type::Named("point".to_string()),
+                methods: vec![function("scale", &["factor", "factor"])],
+            })],
+        };
+
+        assert_eq!(
+            validate(&program)
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            ["Duplicate parameter 'factor' in function 'scale'"]
+        );
+    }
+}
@@ -0,0 +1,476 @@
+//! Read-only traversal of the syntax tree.
+//!
+//! Every `visit_*` method of [`Visitor`] defaults to the matching `walk_*` function, which visits
+//! the node's children in source order. An analysis overrides only the methods for the nodes it
+//! cares about, and calls the `walk_*` function from its override to keep descending (or leaves it
+//! out to skip the subtree).
+
+use crate::ast::{
+    ArraySize, ConstDeclaration, Declaration, Expression, ExpressionKind, FunctionDeclaration,
+    Literal, Parameter, PatchDeclaration, Pattern, PatternKind, Program, RecordDeclaration,
+    RecordField, Statement, StatementKind, Type, TypeParameter, UnionDeclaration, UnionVariant,
+    WhenBranch,
+};
+
+pub trait Visitor {
+    fn visit_program(&mut self, program: &Program) {
+        walk_program(self, program);
+    }
+
+    fn visit_declaration(&mut self, declaration: &Declaration) {
+        walk_declaration(self, declaration);
+    }
+
+    fn visit_function(&mut self, function: &FunctionDeclaration) {
+        walk_function(self, function);
+    }
+
+    fn visit_parameter(&mut self, parameter: &Parameter) {
+        walk_parameter(self, parameter);
+    }
+
+    fn visit_record(&mut self, record: &RecordDeclaration) {
+        walk_record(self, record);
+    }
+
+    fn visit_type_parameter(&mut self, parameter: &TypeParameter) {
+        walk_type_parameter(self, parameter);
+    }
+
+    fn visit_record_field(&mut self, field: &RecordField) {
+        walk_record_field(self, field);
+    }
+
+    fn visit_union(&mut self, union: &UnionDeclaration) {
+        walk_union(self, union);
+    }
+
+    fn visit_union_variant(&mut self, variant: &UnionVariant) {
+        walk_union_variant(self, variant);
+    }
+
+    fn visit_patch(&mut self, patch: &PatchDeclaration) {
+        walk_patch(self, patch);
+    }
+
+    fn visit_const(&mut self, constant: &ConstDeclaration) {
+        walk_const(self, constant);
+    }
+
+    fn visit_statement(&mut self, statement: &Statement) {
+        walk_statement(self, statement);
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        walk_expression(self, expression);
+    }
+
+    fn visit_when_branch(&mut self, branch: &WhenBranch) {
+        walk_when_branch(self, branch);
+    }
+
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        walk_pattern(self, pattern);
+    }
+
+    fn visit_type(&mut self, ty: &Type) {
+        walk_type(self, ty);
+    }
+
+    fn visit_literal(&mut self, _literal: &Literal) {}
+}
+
+pub fn walk_program<V: Visitor + ?Sized>(visitor: &mut V, program: &Program) {
+    for declaration in &program.declarations {
+        visitor.visit_declaration(declaration);
+    }
+}
+
+pub fn walk_declaration<V: Visitor + ?Sized>(visitor: &mut V, declaration: &Declaration) {
+    match declaration {
+        Declaration::Function(function) => visitor.visit_function(function),
+        Declaration::Record(record) => visitor.visit_record(record),
+        Declaration::Union(union) => visitor.visit_union(union),
+        Declaration::Patch(patch) => visitor.visit_patch(patch),
+        Declaration::Const(constant) => visitor.visit_const(constant),
+        Declaration::Statement(statement) => visitor.visit_statement(statement),
+        Declaration::Import(_) => {}
+    }
+}
+
+pub fn walk_function<V: Visitor + ?Sized>(visitor: &mut V, function: &FunctionDeclaration) {
+    for parameter in &function.type_parameters {
+        visitor.visit_type_parameter(parameter);
+    }
+    for parameter in &function.params {
+        visitor.visit_parameter(parameter);
+    }
+    if let Some(return_type) = &function.return_type {
+        visitor.visit_type(return_type);
+    }
+    visitor.visit_expression(&function.body);
+}
+
+pub fn walk_parameter<V: Visitor + ?Sized>(visitor: &mut V, parameter: &Parameter) {
+    visitor.visit_type(&parameter.param_type);
+}
+
+pub fn walk_record<V: Visitor + ?Sized>(visitor: &mut V, record: &RecordDeclaration) {
+    for parameter in &record.type_parameters {
+        visitor.visit_type_parameter(parameter);
+    }
+    for field in &record.fields {
+        visitor.visit_record_field(field);
+    }
+}
+
+pub fn walk_type_parameter<V: Visitor + ?Sized>(visitor: &mut V, parameter: &TypeParameter) {
+    if let Some(const_type) = &parameter.const_type {
+        visitor.visit_type(const_type);
+    }
+}
+
+pub fn walk_record_field<V: Visitor + ?Sized>(visitor: &mut V, field: &RecordField) {
+    visitor.visit_type(&field.field_type);
+}
+
+pub fn walk_union<V: Visitor + ?Sized>(visitor: &mut V, union: &UnionDeclaration) {
+    for parameter in &union.type_parameters {
+        visitor.visit_type_parameter(parameter);
+    }
+    for variant in &union.variants {
+        visitor.visit_union_variant(variant);
+    }
+}
+
+pub fn walk_union_variant<V: Visitor + ?Sized>(visitor: &mut V, variant: &UnionVariant) {
+    if let Some(variant_type) = &variant.variant_type {
+        visitor.visit_type(variant_type);
+    }
+}
+
+pub fn walk_patch<V: Visitor + ?Sized>(visitor: &mut V, patch: &PatchDeclaration) {
+    visitor.visit_type(&patch.target_type);
+    for method in &patch.methods {
+        visitor.visit_function(method);
+    }
+}
+
+pub fn walk_const<V: Visitor + ?Sized>(visitor: &mut V, constant: &ConstDeclaration) {
+    visitor.visit_type(&constant.const_type);
+    visitor.visit_expression(&constant.value);
+}
+
+pub fn walk_statement<V: Visitor + ?Sized>(visitor: &mut V, statement: &Statement) {
+    match &statement.kind {
+        StatementKind::VariableDeclaration {
+            var_type, value, ..
+        } => {
+            if let Some(var_type) = var_type {
+                visitor.visit_type(var_type);
+            }
+            visitor.visit_expression(value);
+        }
+        StatementKind::Assignment { target, value, .. } => {
+            visitor.visit_expression(target);
+            visitor.visit_expression(value);
+        }
+        StatementKind::Expression(expression) => visitor.visit_expression(expression),
+        StatementKind::Return(value) | StatementKind::Break(value) => {
+            if let Some(value) = value {
+                visitor.visit_expression(value);
+            }
+        }
+    }
+}
+
+pub fn walk_expression<V: Visitor + ?Sized>(visitor: &mut V, expression: &Expression) {
+    match &expression.kind {
+        ExpressionKind::Literal(literal) => visitor.visit_literal(literal),
+        ExpressionKind::Identifier(_) => {}
+        ExpressionKind::BinaryOperation { left, right, .. } => {
+            visitor.visit_expression(left);
+            visitor.visit_expression(right);
+        }
+        ExpressionKind::UnaryOperation { operand, .. } => visitor.visit_expression(operand),
+        ExpressionKind::FunctionCall {
+            function,
+            arguments,
+        } => {
+            visitor.visit_expression(function);
+            for argument in arguments {
+                visitor.visit_expression(argument);
+            }
+        }
+        ExpressionKind::RecordAccess { record, .. } => visitor.visit_expression(record),
+        ExpressionKind::MethodCall {
+            receiver,
+            arguments,
+            ..
+        } => {
+            visitor.visit_expression(receiver);
+            for argument in arguments {
+                visitor.visit_expression(argument);
+            }
+        }
+        ExpressionKind::IndexAccess { collection, index } => {
+            visitor.visit_expression(collection);
+            visitor.visit_expression(index);
+        }
+        ExpressionKind::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            visitor.visit_expression(condition);
+            visitor.visit_expression(then_branch);
+            if let Some(else_branch) = else_branch {
+                visitor.visit_expression(else_branch);
+            }
+        }
+        ExpressionKind::When {
+            expression,
+            branches,
+        } => {
+            visitor.visit_expression(expression);
+            for branch in branches {
+                visitor.visit_when_branch(branch);
+            }
+        }
+        ExpressionKind::Block {
+            statements,
+            final_expression,
+        } => {
+            for statement in statements {
+                visitor.visit_statement(statement);
+            }
+            if let Some(final_expression) = final_expression {
+                visitor.visit_expression(final_expression);
+            }
+        }
+        ExpressionKind::Loop { body } => visitor.visit_expression(body),
+        ExpressionKind::While { condition, body } => {
+            visitor.visit_expression(condition);
+            visitor.visit_expression(body);
+        }
+        ExpressionKind::For { iterable, body, .. } => {
+            visitor.visit_expression(iterable);
+            visitor.visit_expression(body);
+        }
+        ExpressionKind::ArrayLiteral(elements) => {
+            for element in elements {
+                visitor.visit_expression(element);
+            }
+        }
+        ExpressionKind::RecordLiteral {
+            record_type,
+            fields,
+            base,
+        } => {
+            visitor.visit_type(record_type);
+            for (_, value) in fields {
+                visitor.visit_expression(value);
+            }
+            if let Some(base) = base {
+                visitor.visit_expression(base);
+            }
+        }
+        ExpressionKind::UnionLiteral {
+            union_type, value, ..
+        } => {
+            visitor.visit_type(union_type);
+            if let Some(value) = value {
+                visitor.visit_expression(value);
+            }
+        }
+        ExpressionKind::Reference { expression, .. }
+        | ExpressionKind::Dereference(expression)
+        | ExpressionKind::Grouped(expression) => visitor.visit_expression(expression),
+        ExpressionKind::Range { start, end, .. } => {
+            visitor.visit_expression(start);
+            visitor.visit_expression(end);
+        }
+        ExpressionKind::TypeAnnotation {
+            expression,
+            annotated_type,
+        } => {
+            visitor.visit_expression(expression);
+            visitor.visit_type(annotated_type);
+        }
+        ExpressionKind::Cast { expression, target } => {
+            visitor.visit_expression(expression);
+            visitor.visit_type(target);
+        }
+        ExpressionKind::Closure {
+            params,
+            return_type,
+            body,
+            ..
+        } => {
+            for parameter in params {
+                visitor.visit_parameter(parameter);
+            }
+            if let Some(return_type) = return_type {
+                visitor.visit_type(return_type);
+            }
+            visitor.visit_expression(body);
+        }
+    }
+}
+
+pub fn walk_when_branch<V: Visitor + ?Sized>(visitor: &mut V, branch: &WhenBranch) {
+    visitor.visit_pattern(&branch.pattern);
+    if let Some(guard) = &branch.guard {
+        visitor.visit_expression(guard);
+    }
+    visitor.visit_expression(&branch.body);
+}
+
+pub fn walk_pattern<V: Visitor + ?Sized>(visitor: &mut V, pattern: &Pattern) {
+    match &pattern.kind {
+        PatternKind::Literal(literal) => visitor.visit_literal(literal),
+        PatternKind::Range { start, end, .. } => {
+            visitor.visit_literal(start);
+            visitor.visit_literal(end);
+        }
+        PatternKind::Union { payload, .. } => {
+            if let Some(payload) = payload {
+                visitor.visit_pattern(payload);
+            }
+        }
+        PatternKind::Record {
+            record_type,
+            fields,
+        } => {
+            visitor.visit_type(record_type);
+            for (_, pattern) in fields {
+                visitor.visit_pattern(pattern);
+            }
+        }
+        PatternKind::Tuple(elements) | PatternKind::Or(elements) => {
+            for element in elements {
+                visitor.visit_pattern(element);
+            }
+        }
+        PatternKind::Identifier(_) | PatternKind::Wildcard | PatternKind::Else => {}
+    }
+}
+
+pub fn walk_type<V: Visitor + ?Sized>(visitor: &mut V, ty: &Type) {
+    match ty {
+        Type::Generic { parameters, .. } => {
+            for parameter in parameters {
+                visitor.visit_type(parameter);
+            }
+        }
+        Type::Reference { ref_type, .. } => visitor.visit_type(ref_type),
+        Type::ArrayList(element_type) => visitor.visit_type(element_type),
+        Type::FixedArray { element_type, size } => {
+            visitor.visit_type(element_type);
+            if let ArraySize::Expression(size) = size {
+                visitor.visit_expression(size);
+            }
+        }
+        Type::Function {
+            param_types,
+            return_type,
+        } => {
+            for param_type in param_types {
+                visitor.visit_type(param_type);
+            }
+            if let Some(return_type) = return_type {
+                visitor.visit_type(return_type);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    /// Collects the names of all functions called directly by name.
+    #[derive(Default)]
+    struct CalledFunctions(Vec<String>);
+
+    impl Visitor for CalledFunctions {
+        fn visit_expression(&mut self, expression: &Expression) {
+            if let ExpressionKind::FunctionCall { function, .. } = &expression.kind
+                && let ExpressionKind::Identifier(name) = &function.kind
+            {
+                self.0.push(name.clone());
+            }
+            walk_expression(self, expression);
+        }
+    }
+
+    /// Collects the names of named types, without descending into closures.
+    #[derive(Default)]
+    struct NamedTypesOutsideClosures(Vec<String>);
+
+    impl Visitor for NamedTypesOutsideClosures {
+        fn visit_expression(&mut self, expression: &Expression) {
+            if !matches!(&expression.kind, ExpressionKind::Closure { .. }) {
+                walk_expression(self, expression);
+            }
+        }
+
+        fn visit_type(&mut self, ty: &Type) {
+            if let Type::Named(name) = ty {
+                self.0.push(name.clone());
+            }
+            walk_type(self, ty);
+        }
+    }
+
+    #[test]
+    fn test_visits_nested_expressions_in_order() {
+        let program = parse(
+            r#"
+            fn main() {
+                total = sum(map(items, |x| square(x)));
+                when lookup(total) {
+                    some(value): print(value);
+                    else: { log(format("missing")); };
+                }
+            }
+            "#,
+        )
+        .expect("Parse error");
+
+        let mut visitor = CalledFunctions::default();
+        visitor.visit_program(&program);
+
+        assert_eq!(
+            visitor.0,
+            ["sum", "map", "square", "lookup", "print", "log", "format"]
+        );
+    }
+
+    #[test]
+    fn test_overrides_can_skip_subtrees() {
+        let program = parse(
+            r#"
+            record line { start: point; rest: arrayList<point&>; }
+            union shape<T> = segment(line) | path(fn(point) -> T);
+            patch shape<i32> {
+                fn first(cursor c) -> point {
+                    f = |vector v| -> vector { v };
+                    c
+                }
+            }
+            "#,
+        )
+        .expect("Parse error");
+
+        let mut visitor = NamedTypesOutsideClosures::default();
+        visitor.visit_program(&program);
+
+        assert_eq!(
+            visitor.0,
+            ["point", "point", "line", "point", "T", "cursor", "point"]
+        );
+    }
+}
@@ -0,0 +1,523 @@
+//! In-place rewriting of the syntax tree, the mutable counterpart of [`crate::visit`].
+//!
+//! The `visit_*` methods of [`MutVisitor`] receive mutable references, so a pass can edit a node's
+//! fields or replace the node outright (`*expression = ...`), which is how desugaring and
+//! simplification passes are written. As with [`crate::visit::Visitor`], each method defaults to
+//! the matching `walk_*` function; a pass that rewrites bottom-up calls it before looking at the
+//! node, one that rewrites top-down calls it after.
+
+use crate::ast::{
+    ArraySize, ConstDeclaration, Declaration, Expression, ExpressionKind, FunctionDeclaration,
+    Literal, Parameter, PatchDeclaration, Pattern, PatternKind, Program, RecordDeclaration,
+    RecordField, Statement, StatementKind, Type, TypeParameter, UnionDeclaration, UnionVariant,
+    WhenBranch,
+};
+
+pub trait MutVisitor {
+    fn visit_program(&mut self, program: &mut Program) {
+        walk_program(self, program);
+    }
+
+    fn visit_declaration(&mut self, declaration: &mut Declaration) {
+        walk_declaration(self, declaration);
+    }
+
+    fn visit_function(&mut self, function: &mut FunctionDeclaration) {
+        walk_function(self, function);
+    }
+
+    fn visit_parameter(&mut self, parameter: &mut Parameter) {
+        walk_parameter(self, parameter);
+    }
+
+    fn visit_record(&mut self, record: &mut RecordDeclaration) {
+        walk_record(self, record);
+    }
+
+    fn visit_type_parameter(&mut self, parameter: &mut TypeParameter) {
+        walk_type_parameter(self, parameter);
+    }
+
+    fn visit_record_field(&mut self, field: &mut RecordField) {
+        walk_record_field(self, field);
+    }
+
+    fn visit_union(&mut self, union: &mut UnionDeclaration) {
+        walk_union(self, union);
+    }
+
+    fn visit_union_variant(&mut self, variant: &mut UnionVariant) {
+        walk_union_variant(self, variant);
+    }
+
+    fn visit_patch(&mut self, patch: &mut PatchDeclaration) {
+        walk_patch(self, patch);
+    }
+
+    fn visit_const(&mut self, constant: &mut ConstDeclaration) {
+        walk_const(self, constant);
+    }
+
+    fn visit_statement(&mut self, statement: &mut Statement) {
+        walk_statement(self, statement);
+    }
+
+    fn visit_expression(&mut self, expression: &mut Expression) {
+        walk_expression(self, expression);
+    }
+
+    fn visit_when_branch(&mut self, branch: &mut WhenBranch) {
+        walk_when_branch(self, branch);
+    }
+
+    fn visit_pattern(&mut self, pattern: &mut Pattern) {
+        walk_pattern(self, pattern);
+    }
+
+    fn visit_type(&mut self, ty: &mut Type) {
+        walk_type(self, ty);
+    }
+
+    fn visit_literal(&mut self, _literal: &mut Literal) {}
+}
+
+pub fn walk_program<V: MutVisitor + ?Sized>(visitor: &mut V, program: &mut Program) {
+    for declaration in &mut program.declarations {
+        visitor.visit_declaration(declaration);
+    }
+}
+
+pub fn walk_declaration<V: MutVisitor + ?Sized>(visitor: &mut V, declaration: &mut Declaration) {
+    match declaration {
+        Declaration::Function(function) => visitor.visit_function(function),
+        Declaration::Record(record) => visitor.visit_record(record),
+        Declaration::Union(union) => visitor.visit_union(union),
+        Declaration::Patch(patch) => visitor.visit_patch(patch),
+        Declaration::Const(constant) => visitor.visit_const(constant),
+        Declaration::Statement(statement) => visitor.visit_statement(statement),
+        Declaration::Import(_) => {}
+    }
+}
+
+pub fn walk_function<V: MutVisitor + ?Sized>(visitor: &mut V, function: &mut FunctionDeclaration) {
+    for parameter in &mut function.type_parameters {
+        visitor.visit_type_parameter(parameter);
+    }
+    for parameter in &mut function.params {
+        visitor.visit_parameter(parameter);
+    }
+    if let Some(return_type) = &mut function.return_type {
+        visitor.visit_type(return_type);
+    }
+    visitor.visit_expression(&mut function.body);
+}
+
+pub fn walk_parameter<V: MutVisitor + ?Sized>(visitor: &mut V, parameter: &mut Parameter) {
+    visitor.visit_type(&mut parameter.param_type);
+}
+
+pub fn walk_record<V: MutVisitor + ?Sized>(visitor: &mut V, record: &mut RecordDeclaration) {
+    for parameter in &mut record.type_parameters {
+        visitor.visit_type_parameter(parameter);
+    }
+    for field in &mut record.fields {
+        visitor.visit_record_field(field);
+    }
+}
+
+pub fn walk_type_parameter<V: MutVisitor + ?Sized>(visitor: &mut V, parameter: &mut TypeParameter) {
+    if let Some(const_type) = &mut parameter.const_type {
+        visitor.visit_type(const_type);
+    }
+}
+
+pub fn walk_record_field<V: MutVisitor + ?Sized>(visitor: &mut V, field: &mut RecordField) {
+    visitor.visit_type(&mut field.field_type);
+}
+
+pub fn walk_union<V: MutVisitor + ?Sized>(visitor: &mut V, union: &mut UnionDeclaration) {
+    for parameter in &mut union.type_parameters {
+        visitor.visit_type_parameter(parameter);
+    }
+    for variant in &mut union.variants {
+        visitor.visit_union_variant(variant);
+    }
+}
+
+pub fn walk_union_variant<V: MutVisitor + ?Sized>(visitor: &mut V, variant: &mut UnionVariant) {
+    if let Some(variant_type) = &mut variant.variant_type {
+        visitor.visit_type(variant_type);
+    }
+}
+
+pub fn walk_patch<V: MutVisitor + ?Sized>(visitor: &mut V, patch: &mut PatchDeclaration) {
+    visitor.visit_type(&mut patch.target_type);
+    for method in &mut patch.methods {
+        visitor.visit_function(method);
+    }
+}
+
+pub fn walk_const<V: MutVisitor + ?Sized>(visitor: &mut V, constant: &mut ConstDeclaration) {
+    visitor.visit_type(&mut constant.const_type);
+    visitor.visit_expression(&mut constant.value);
+}
+
+pub fn walk_statement<V: MutVisitor + ?Sized>(visitor: &mut V, statement: &mut Statement) {
+    match &mut statement.kind {
+        StatementKind::VariableDeclaration {
+            var_type, value, ..
+        } => {
+            if let Some(var_type) = var_type {
+                visitor.visit_type(var_type);
+            }
+            visitor.visit_expression(value);
+        }
+        StatementKind::Assignment { target, value, .. } => {
+            visitor.visit_expression(target);
+            visitor.visit_expression(value);
+        }
+        StatementKind::Expression(expression) => visitor.visit_expression(expression),
+        StatementKind::Return(value) | StatementKind::Break(value) => {
+            if let Some(value) = value {
+                visitor.visit_expression(value);
+            }
+        }
+    }
+}
+
+pub fn walk_expression<V: MutVisitor + ?Sized>(visitor: &mut V, expression: &mut Expression) {
+    match &mut expression.kind {
+        ExpressionKind::Literal(literal) => visitor.visit_literal(literal),
+        ExpressionKind::Identifier(_) => {}
+        ExpressionKind::BinaryOperation { left, right, .. } => {
+            visitor.visit_expression(left);
+            visitor.visit_expression(right);
+        }
+        ExpressionKind::UnaryOperation { operand, .. } => visitor.visit_expression(operand),
+        ExpressionKind::FunctionCall {
+            function,
+            arguments,
+        } => {
+            visitor.visit_expression(function);
+            for argument in arguments {
+                visitor.visit_expression(argument);
+            }
+        }
+        ExpressionKind::RecordAccess { record, .. } => visitor.visit_expression(record),
+        ExpressionKind::MethodCall {
+            receiver,
+            arguments,
+            ..
+        } => {
+            visitor.visit_expression(receiver);
+            for argument in arguments {
+                visitor.visit_expression(argument);
+            }
+        }
+        ExpressionKind::IndexAccess { collection, index } => {
+            visitor.visit_expression(collection);
+            visitor.visit_expression(index);
+        }
+        ExpressionKind::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            visitor.visit_expression(condition);
+            visitor.visit_expression(then_branch);
+            if let Some(else_branch) = else_branch {
+                visitor.visit_expression(else_branch);
+            }
+        }
+        ExpressionKind::When {
+            expression,
+            branches,
+        } => {
+            visitor.visit_expression(expression);
+            for branch in branches {
+                visitor.visit_when_branch(branch);
+            }
+        }
+        ExpressionKind::Block {
+            statements,
+            final_expression,
+        } => {
+            for statement in statements {
+                visitor.visit_statement(statement);
+            }
+            if let Some(final_expression) = final_expression {
+                visitor.visit_expression(final_expression);
+            }
+        }
+        ExpressionKind::Loop { body } => visitor.visit_expression(body),
+        ExpressionKind::While { condition, body } => {
+            visitor.visit_expression(condition);
+            visitor.visit_expression(body);
+        }
+        ExpressionKind::For { iterable, body, .. } => {
+            visitor.visit_expression(iterable);
+            visitor.visit_expression(body);
+        }
+        ExpressionKind::ArrayLiteral(elements) => {
+            for element in elements {
+                visitor.visit_expression(element);
+            }
+        }
+        ExpressionKind::RecordLiteral {
+            record_type,
+            fields,
+            base,
+        } => {
+            visitor.visit_type(record_type);
+            for (_, value) in fields {
+                visitor.visit_expression(value);
+            }
+            if let Some(base) = base {
+                visitor.visit_expression(base);
+            }
+        }
+        ExpressionKind::UnionLiteral {
+            union_type, value, ..
+        } => {
+            visitor.visit_type(union_type);
+            if let Some(value) = value {
+                visitor.visit_expression(value);
+            }
+        }
+        ExpressionKind::Reference { expression, .. }
+        | ExpressionKind::Dereference(expression)
+        | ExpressionKind::Grouped(expression) => visitor.visit_expression(expression),
+        ExpressionKind::Range { start, end, .. } => {
+            visitor.visit_expression(start);
+            visitor.visit_expression(end);
+        }
+        ExpressionKind::TypeAnnotation {
+            expression,
+            annotated_type,
+        } => {
+            visitor.visit_expression(expression);
+            visitor.visit_type(annotated_type);
+        }
+        ExpressionKind::Cast { expression, target } => {
+            visitor.visit_expression(expression);
+            visitor.visit_type(target);
+        }
+        ExpressionKind::Closure {
+            params,
+            return_type,
+            body,
+            ..
+        } => {
+            for parameter in params {
+                visitor.visit_parameter(parameter);
+            }
+            if let Some(return_type) = return_type {
+                visitor.visit_type(return_type);
+            }
+            visitor.visit_expression(body);
+        }
+    }
+}
+
+pub fn walk_when_branch<V: MutVisitor + ?Sized>(visitor: &mut V, branch: &mut WhenBranch) {
+    visitor.visit_pattern(&mut branch.pattern);
+    if let Some(guard) = &mut branch.guard {
+        visitor.visit_expression(guard);
+    }
+    visitor.visit_expression(&mut branch.body);
+}
+
+pub fn walk_pattern<V: MutVisitor + ?Sized>(visitor: &mut V, pattern: &mut Pattern) {
+    match &mut pattern.kind {
+        PatternKind::Literal(literal) => visitor.visit_literal(literal),
+        PatternKind::Range { start, end, .. } => {
+            visitor.visit_literal(start);
+            visitor.visit_literal(end);
+        }
+        PatternKind::Union { payload, .. } => {
+            if let Some(payload) = payload {
+                visitor.visit_pattern(payload);
+            }
+        }
+        PatternKind::Record {
+            record_type,
+            fields,
+        } => {
+            visitor.visit_type(record_type);
+            for (_, pattern) in fields {
+                visitor.visit_pattern(pattern);
+            }
+        }
+        PatternKind::Tuple(elements) | PatternKind::Or(elements) => {
+            for element in elements {
+                visitor.visit_pattern(element);
+            }
+        }
+        PatternKind::Identifier(_) | PatternKind::Wildcard | PatternKind::Else => {}
+    }
+}
+
+pub fn walk_type<V: MutVisitor + ?Sized>(visitor: &mut V, ty: &mut Type) {
+    match ty {
+        Type::Generic { parameters, .. } => {
+            for parameter in parameters {
+                visitor.visit_type(parameter);
+            }
+        }
+        Type::Reference { ref_type, .. } => visitor.visit_type(ref_type),
+        Type::ArrayList(element_type) => visitor.visit_type(element_type),
+        Type::FixedArray { element_type, size } => {
+            visitor.visit_type(element_type);
+            if let ArraySize::Expression(size) = size {
+                visitor.visit_expression(size);
+            }
+        }
+        Type::Function {
+            param_types,
+            return_type,
+        } => {
+            for param_type in param_types {
+                visitor.visit_type(param_type);
+            }
+            if let Some(return_type) = return_type {
+                visitor.visit_type(return_type);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::BinaryOperator;
+    use crate::operators::integer_arithmetic;
+    use crate::visit::{Visitor, walk_statement as walk};
+    use crate::{Lexer, Parser, parse};
+    use test_case::test_case;
+
+    /// Folds arithmetic on integer literals, leaving operations that would fail at runtime alone.
+    struct ConstantFolder;
+
+    impl MutVisitor for ConstantFolder {
+        fn visit_expression(&mut self, expression: &mut Expression) {
+            walk_expression(self, expression);
+            if let ExpressionKind::BinaryOperation {
+                left,
+                operator,
+                right,
+            } = &expression.kind
+                && let (
+                    ExpressionKind::Literal(Literal::Integer(left)),
+                    ExpressionKind::Literal(Literal::Integer(right)),
+                ) = (&left.kind, &right.kind)
+                && let Some(Ok(value)) = integer_arithmetic(*operator, *left, *right)
+            {
+                *expression = ExpressionKind::Literal(Literal::Integer(value)).into();
+            }
+        }
+    }
+
+    /// Rewrites `target op= value;` into `target = target op value;`.
+    struct ExpandCompoundAssignment;
+
+    impl MutVisitor for ExpandCompoundAssignment {
+        fn visit_statement(&mut self, statement: &mut Statement) {
+            if let StatementKind::Assignment {
+                target,
+                operator,
+                value,
+            } = &mut statement.kind
+            {
+                let expanded = operator.compound_operator();
+                if let Some(expanded) = expanded {
+                    let right = std::mem::replace(
+                        value.as_mut(),
+                        ExpressionKind::Literal(Literal::Boolean(false)).into(),
+                    );
+                    **value = ExpressionKind::BinaryOperation {
+                        left: target.clone(),
+                        operator: expanded,
+                        right: Box::new(right),
+                    }
+                    .into();
+                    *operator = BinaryOperator::Assign;
+                }
+            }
+            walk_statement(self, statement);
+        }
+    }
+
+    fn expression(source: &str) -> Expression {
+        let tokens = Lexer::new(source).tokenize().expect("Lexer error");
+        Parser::new(tokens).parse_expression().expect("Parse error")
+    }
+
+    #[test_case("1 + 2 * 3", "7" ; "nested")]
+    #[test_case("x + 2 * 3", "x + 6" ; "partially constant")]
+    #[test_case("f(10 / 3, [4 % 3])", "f(3, [1])" ; "inside calls and arrays")]
+    #[test_case("1 / 0", "1 / 0" ; "division by zero is kept")]
+    #[test_case("18446744073709551615 + 1", "18446744073709551615 + 1" ; "overflow is kept")]
+    fn test_constant_folding(source: &str, expected: &str) {
+        let mut folded = expression(source);
+        ConstantFolder.visit_expression(&mut folded);
+
+        assert_eq!(folded, expression(expected));
+    }
+
+    fn statement(source: &str) -> Statement {
+        let tokens = Lexer::new(source).tokenize().expect("Lexer error");
+        Parser::new(tokens)
+            .parse_statement()
+            .expect("Parse error")
+            .into()
+    }
+
+    #[test_case("x += 1;", "(x) = x + 1;" ; "add")]
+    #[test_case("p.count *= a + b;", "p.count = p.count * (a + b);" ; "field target")]
+    #[test_case("xs[i] %= { n -= 1; n };", "xs[i] = xs[i] % { (n) = n - 1; n };" ; "nested")]
+    fn test_expand_compound_assignment(source: &str, expected: &str) {
+        let mut expanded = statement(source);
+        ExpandCompoundAssignment.visit_statement(&mut expanded);
+
+        assert_eq!(expanded, statement(expected));
+    }
+
+    /// Collects every assignment operator, to check which ones a rewrite left behind.
+    #[derive(Default)]
+    struct Assignments(Vec<BinaryOperator>);
+
+    impl Visitor for Assignments {
+        fn visit_statement(&mut self, statement: &Statement) {
+            if let StatementKind::Assignment { operator, .. } = &statement.kind {
+                self.0.push(*operator);
+            }
+            walk(self, statement);
+        }
+    }
+
+    #[test]
+    fn test_rewrites_reach_every_declaration() {
+        let mut program = parse(
+            r#"
+            fn main() {
+                @total = 0;
+                for i in 0..10 {
+                    total += i * 2;
+                };
+                apply(|@x| { x -= 1; x });
+            }
+            patch counter {
+                fn bump(i32 @n) { n *= 2; }
+            }
+            "#,
+        )
+        .expect("Parse error");
+
+        ExpandCompoundAssignment.visit_program(&mut program);
+        let mut assignments = Assignments::default();
+        assignments.visit_program(&program);
+
+        assert_eq!(assignments.0, [BinaryOperator::Assign; 3]);
+    }
+}
@@ -0,0 +1,348 @@
+//! The form every pass's findings take once they are reported.
+//!
+//! Each pass has its own error type with the fields a caller may want to inspect. A
+//! [`Diagnostic`] is what they have in common: how serious the finding is, its message, and the
+//! node it is about, so a driver can collect the findings of every pass, sort them, and print
+//! them the same way. Given where the program's nodes were written, a diagnostic can be
+//! [rendered](Diagnostic::render) with the line and column of each node it mentions.
+
+use crate::exhaustive::MatchError;
+use crate::modules::ModuleError;
+use crate::monomorphize::MonomorphizeError;
+use crate::mutability::MutabilityError;
+use crate::references::ReferenceError;
+use crate::resolve::ResolveError;
+use crate::returns::ReturnError;
+use crate::shadowing::ShadowWarning;
+use crate::typeck::TypeError;
+use lexer::source::SourceMap;
+use parser::node_id::NodeId;
+use parser::spans::Spans;
+use parser::validate::ValidationError;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    /// The node the diagnostic is about.
+    pub node: NodeId,
+    /// Another node that explains it, such as the declaration an error is caused by.
+    pub related: Option<NodeId>,
+    pub notes: Vec<Note>,
+}
+
+/// A remark that comes with a diagnostic, such as one of the calls a runtime error happened in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Note {
+    pub message: String,
+    pub node: Option<NodeId>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>, node: NodeId) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            message: message.into(),
+            node,
+            related: None,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn warning(message: impl Into<String>, node: NodeId) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(message, node)
+        }
+    }
+
+    pub fn with_related(mut self, related: Option<NodeId>) -> Self {
+        self.related = related;
+        self
+    }
+
+    pub fn with_note(mut self, message: impl Into<String>, node: Option<NodeId>) -> Self {
+        self.notes.push(Note {
+            message: message.into(),
+            node,
+        });
+        self
+    }
+
+    /// The diagnostic and its notes, one per line, each with the line and column its node was
+    /// written at, and a note pointing at the related node if it has a location. A location in a
+    /// file other than the one the diagnostic's own node is in also names that file. `spans`
+    /// gives where the nodes were written in the files of `sources`; a node without a span, such
+    /// as one of the prelude, gets no location.
+    pub fn render(&self, spans: &Spans, sources: &SourceMap) -> String {
+        let primary = spans
+            .get(self.node)
+            .and_then(|span| sources.get(span.file))
+            .map(|file| file.name.as_str());
+        let location = |node: Option<NodeId>| {
+            node.and_then(|node| spans.get(node))
+                .and_then(|span| {
+                    let file = sources.get(span.file)?;
+                    let (line, column) = file.line_col(span.start);
+                    Some(if primary == Some(file.name.as_str()) {
+                        format!(" at {}:{}", line, column)
+                    } else {
+                        format!(" at {}:{}:{}", file.name, line, column)
+                    })
+                })
+                .unwrap_or_default()
+        };
+        let mut rendered = format!(
+            "{}{}: {}",
+            self.severity,
+            location(Some(self.node)),
+            self.message
+        );
+        let related = location(self.related);
+        if !related.is_empty() {
+            rendered.push_str(&format!("\n  note: declared here{}", related));
+        }
+        for note in &self.notes {
+            rendered.push_str(&format!(
+                "\n  note: {}{}",
+                note.message,
+                location(note.node)
+            ));
+        }
+        rendered
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.severity, self.message)
+    }
+}
+
+impl From<ValidationError> for Diagnostic {
+    fn from(error: ValidationError) -> Self {
+        let diagnostic = Diagnostic::error(error.to_string(), error.node());
+        match error.first() {
+            Some(first) => diagnostic.with_note("first occurrence", Some(first)),
+            None => diagnostic,
+        }
+    }
+}
+
+impl From<ResolveError> for Diagnostic {
+    fn from(error: ResolveError) -> Self {
+        let first = error.declaration();
+        let diagnostic = Diagnostic::error(error.to_string(), error.node());
+        match first {
+            Some(first) => diagnostic.with_note("first defined here", Some(first)),
+            None => diagnostic,
+        }
+    }
+}
+
+impl From<ReturnError> for Diagnostic {
+    fn from(error: ReturnError) -> Self {
+        Diagnostic::error(error.to_string(), error.node())
+    }
+}
+
+impl From<TypeError> for Diagnostic {
+    fn from(error: TypeError) -> Self {
+        Diagnostic {
+            severity: error.severity(),
+            message: error.to_string(),
+            node: error.node(),
+            related: None,
+            notes: Vec::new(),
+        }
+    }
+}
+
+impl From<MatchError> for Diagnostic {
+    fn from(error: MatchError) -> Self {
+        Diagnostic {
+            severity: error.severity(),
+            message: error.to_string(),
+            node: error.node(),
+            related: None,
+            notes: Vec::new(),
+        }
+    }
+}
+
+impl From<MutabilityError> for Diagnostic {
+    fn from(error: MutabilityError) -> Self {
+        Diagnostic::error(error.to_string(), error.node()).with_related(error.declaration())
+    }
+}
+
+impl From<ReferenceError> for Diagnostic {
+    fn from(error: ReferenceError) -> Self {
+        let related = match &error {
+            ReferenceError::LocalReferenceEscapes { declaration, .. } => *declaration,
+            ReferenceError::NotAReference { .. } => None,
+        };
+        Diagnostic::error(error.to_string(), error.node()).with_related(related)
+    }
+}
+
+impl From<MonomorphizeError> for Diagnostic {
+    fn from(error: MonomorphizeError) -> Self {
+        let node = match &error {
+            MonomorphizeError::CannotInfer { node, .. }
+            | MonomorphizeError::RecursionLimit { node, .. } => *node,
+        };
+        Diagnostic::error(error.to_string(), node)
+    }
+}
+
+impl From<ModuleError> for Diagnostic {
+    fn from(error: ModuleError) -> Self {
+        let diagnostic = Diagnostic::error(error.to_string(), error.node());
+        match error {
+            ModuleError::ConflictingImport { first, .. } => {
+                diagnostic.with_note("first imported here", Some(first))
+            }
+            _ => diagnostic.with_related(error.declaration()),
+        }
+    }
+}
+
+impl From<ShadowWarning> for Diagnostic {
+    fn from(warning: ShadowWarning) -> Self {
+        let previous = warning.previous();
+        Diagnostic::warning(warning.to_string(), warning.node()).with_related(previous)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::ast::Declaration;
+    use parser::node_id::NodeIndex;
+
+    #[test]
+    fn test_render() {
+        let mut sources = SourceMap::new();
+        let file = sources.add_file("main.cv", "x = 1;\n  y = x;");
+        let (program, spans, _) = parser::parse_file(sources.get(file).unwrap());
+        let [
+            Declaration::Statement(first),
+            Declaration::Statement(second),
+        ] = program.declarations.as_slice()
+        else {
+            panic!("expected two statements");
+        };
+        let diagnostic = Diagnostic::error("Something failed", second.id)
+            .with_note("while doing this", Some(first.id))
+            .with_note("somewhere unknown", None);
+
+        assert_eq!(
+            diagnostic.render(&spans, &sources),
+            "error at 2:3: Something failed\n  note: while doing this at 1:1\n  note: somewhere unknown"
+        );
+        assert_eq!(diagnostic.to_string(), "error: Something failed");
+    }
+
+    #[test]
+    fn test_render_note_in_another_file() {
+        let mut sources = SourceMap::new();
+        let main = sources.add_file("main.cv", "x = 1;");
+        let geometry = sources.add_file("geometry.cv", "\n  y = 2;");
+        let (main, mut spans, _) = parser::parse_file(sources.get(main).unwrap());
+        let ids = parser::node_id::NodeIds::after(&main);
+        let (geometry, geometry_spans, _) =
+            parser::parse_file_numbered(sources.get(geometry).unwrap(), ids);
+        spans.extend(geometry_spans);
+        let [Declaration::Statement(first)] = main.declarations.as_slice() else {
+            panic!("expected a statement");
+        };
+        let [Declaration::Statement(second)] = geometry.declarations.as_slice() else {
+            panic!("expected a statement");
+        };
+        let diagnostic = Diagnostic::error("Something failed", first.id)
+            .with_related(Some(second.id))
+            .with_note("while doing this", Some(second.id))
+            .with_note("and this", Some(first.id));
+
+        assert_eq!(
+            diagnostic.render(&spans, &sources),
+            "error at 1:1: Something failed\n  note: declared here at geometry.cv:2:3\n  \
+             note: while doing this at geometry.cv:2:3\n  note: and this at 1:1"
+        );
+    }
+
+    #[test]
+    fn test_render_duplicate_definition() {
+        let mut sources = SourceMap::new();
+        let file = sources.add_file(
+            "main.cv",
+            "fn area() {}
+record area { x: i32; }",
+        );
+        let (program, spans, _) = parser::parse_file(sources.get(file).unwrap());
+        let index = NodeIndex::new(&program);
+        let errors = crate::resolve(&program, &index).errors;
+        let diagnostic = Diagnostic::from(errors[0].clone());
+
+        assert_eq!(
+            diagnostic.render(&spans, &sources),
+            "error at 2:8: 'area' is already defined as a function\n  note: first defined here at 1:4"
+        );
+    }
+
+    #[test]
+    fn test_render_related_declaration() {
+        let mut sources = SourceMap::new();
+        let file = sources.add_file("main.cv", "fn main() {\n    x = 1;\n    (x) = 2;\n}");
+        let (program, spans, _) = parser::parse_file(sources.get(file).unwrap());
+        let index = NodeIndex::new(&program);
+        let resolution = crate::resolve(&program, &index);
+        let types = crate::check(&program, &index, &resolution).types;
+        let errors = crate::check_mutability(&program, &index, &resolution, &types);
+        let diagnostic = Diagnostic::from(errors[0].clone());
+
+        assert_eq!(
+            diagnostic.render(&spans, &sources),
+            "error at 3:5: Cannot assign to immutable variable 'x'\n  note: declared here at 2:5"
+        );
+    }
+
+    #[test]
+    fn test_render_related_parameter() {
+        let mut sources = SourceMap::new();
+        let file = sources.add_file(
+            "main.cv",
+            "fn count(i32 n) {
+    n += 1;
+}",
+        );
+        let (program, spans, _) = parser::parse_file(sources.get(file).unwrap());
+        let index = NodeIndex::new(&program);
+        let resolution = crate::resolve(&program, &index);
+        let types = crate::check(&program, &index, &resolution).types;
+        let errors = crate::check_mutability(&program, &index, &resolution, &types);
+        let diagnostic = Diagnostic::from(errors[0].clone());
+
+        assert_eq!(
+            diagnostic.render(&spans, &sources),
+            "error at 2:5: Cannot assign to immutable parameter 'n'\n  note: declared here at 1:14"
+        );
+    }
+}
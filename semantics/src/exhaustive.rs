@@ -0,0 +1,681 @@
+//! Exhaustiveness and reachability of `when` branches.
+//!
+//! Each `when` is checked with the pattern-matrix algorithm: the patterns of its branches are
+//! rows, and a pattern is useful if some value matches it and no row above it. A branch whose
+//! pattern is not useful can never be taken. A `when` is exhaustive if a wildcard after its last
+//! branch would not be useful, and when it is not, the values that slip through are listed as
+//! patterns.
+//!
+//! Union variants, `true` and `false`, records, and tuples are constructors whose full set is
+//! known. Other literals and ranges are not: only a binding, `_`, or `else` covers the rest of
+//! their values. A guarded branch may not be taken, so it covers nothing, and neither does a
+//! pattern that cannot match the value's type.
+
+use crate::diagnostic::Severity;
+use crate::resolve::Resolution;
+use crate::symbols::SymbolKind;
+use crate::typeck::TypeMap;
+use parser::ast::{
+    Declaration, Expression, ExpressionKind, FunctionDeclaration, Literal, Pattern, PatternKind,
+    Program, RecordDeclaration, Statement, StatementKind, Type, UnionDeclaration,
+};
+use parser::node_id::{NodeId, NodeIndex};
+use parser::visit::{Visitor, walk_expression, walk_function, walk_statement};
+use std::collections::{HashMap, HashSet};
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Clone)]
+pub enum MatchError {
+    /// `node` is the `when` expression.
+    #[error("'when' does not cover {}", missing.join(", "))]
+    NonExhaustive { missing: Vec<String>, node: NodeId },
+    /// `node` is the branch.
+    #[error("Unreachable branch '{pattern}': earlier branches match every value it does")]
+    UnreachableBranch { pattern: String, node: NodeId },
+}
+
+impl MatchError {
+    /// A `when` that can miss a value is an error; a branch that is never taken is harmless.
+    pub fn severity(&self) -> Severity {
+        match self {
+            MatchError::NonExhaustive { .. } => Severity::Error,
+            MatchError::UnreachableBranch { .. } => Severity::Warning,
+        }
+    }
+
+    pub fn node(&self) -> NodeId {
+        match self {
+            MatchError::NonExhaustive { node, .. } | MatchError::UnreachableBranch { node, .. } => {
+                *node
+            }
+        }
+    }
+}
+
+/// Check every `when` in `program`. A `when` must be exhaustive only if its value is used; in
+/// statement position a value that matches no branch falls through. `types` says which
+/// patterns cannot match the values they are matched against.
+pub fn check_matches(
+    program: &Program,
+    index: &NodeIndex,
+    resolution: &Resolution,
+    types: &TypeMap,
+) -> Vec<MatchError> {
+    check_matches_after(program, index, resolution, types, &[])
+}
+
+/// Check every `when` in `program` as [`check_matches`] does, after the programs that made
+/// `declarations`, whose unions and records it may match on.
+pub(crate) fn check_matches_after<'p>(
+    program: &'p Program,
+    index: &NodeIndex,
+    resolution: &Resolution,
+    types: &TypeMap,
+    declarations: &[&'p Declaration],
+) -> Vec<MatchError> {
+    let mut variants = HashMap::new();
+    let mut records = HashMap::new();
+    for declaration in declarations.iter().copied().chain(&program.declarations) {
+        match declaration {
+            Declaration::Union(union) => {
+                for (position, variant) in union.variants.iter().enumerate() {
+                    variants.insert(variant.id, (union, position));
+                }
+            }
+            Declaration::Record(record) => {
+                records.insert(record.name.as_str(), record);
+            }
+            _ => {}
+        }
+    }
+
+    let mut checker = Checker {
+        index,
+        resolution,
+        types,
+        variants,
+        records,
+        errors: Vec::new(),
+    };
+    checker.visit_program(program);
+    checker.errors
+}
+
+/// A way of building a value that a pattern can test for.
+#[derive(Debug, Clone, PartialEq)]
+enum Constructor<'p> {
+    /// The variant at a position in its union.
+    Variant(&'p UnionDeclaration, usize),
+    Bool(bool),
+    Record(&'p RecordDeclaration),
+    Tuple(usize),
+    Literal(Literal),
+    Range {
+        start: Literal,
+        end: Literal,
+        inclusive: bool,
+    },
+    /// A pattern naming a variant or record that does not exist, which matches nothing.
+    Unknown,
+}
+
+impl<'p> Constructor<'p> {
+    /// The number of fields the constructor has.
+    fn arity(&self) -> usize {
+        match self {
+            Constructor::Variant(union, position) => {
+                usize::from(union.variants[*position].variant_type.is_some())
+            }
+            Constructor::Record(record) => record.fields.len(),
+            Constructor::Tuple(arity) => *arity,
+            _ => 0,
+        }
+    }
+
+    /// Whether this and `other` build the same values.
+    fn same(&self, other: &Constructor) -> bool {
+        match (self, other) {
+            (
+                Constructor::Variant(union, position),
+                Constructor::Variant(other, other_position),
+            ) => std::ptr::eq(*union, *other) && position == other_position,
+            (Constructor::Record(record), Constructor::Record(other)) => {
+                std::ptr::eq(*record, *other)
+            }
+            (Constructor::Unknown, _) | (_, Constructor::Unknown) => false,
+            _ => self == other,
+        }
+    }
+
+    /// Every constructor of the type this one builds, or `None` if there are too many to list.
+    fn all(&self) -> Option<Vec<Constructor<'p>>> {
+        match self {
+            Constructor::Variant(union, _) => Some(
+                (0..union.variants.len())
+                    .map(|position| Constructor::Variant(union, position))
+                    .collect(),
+            ),
+            Constructor::Bool(_) => Some(vec![Constructor::Bool(true), Constructor::Bool(false)]),
+            Constructor::Record(_) | Constructor::Tuple(_) => Some(vec![self.clone()]),
+            Constructor::Literal(_) | Constructor::Range { .. } | Constructor::Unknown => None,
+        }
+    }
+}
+
+/// A pattern reduced to what matters for matching: bindings and `else` are wildcards, and
+/// record fields are in declaration order.
+#[derive(Debug, Clone)]
+enum Row<'p> {
+    Wildcard,
+    Constructor(Constructor<'p>, Vec<Row<'p>>),
+    Or(Vec<Row<'p>>),
+}
+
+impl Row<'_> {
+    /// The pattern this row stands for, as it would be written.
+    fn to_pattern(&self) -> Pattern {
+        match self {
+            Row::Constructor(Constructor::Variant(union, position), fields) => PatternKind::Union {
+                variant: union.variants[*position].name.clone(),
+                payload: fields.first().map(|field| Box::new(field.to_pattern())),
+            }
+            .into(),
+            Row::Constructor(Constructor::Bool(value), _) => {
+                PatternKind::Literal(Literal::Boolean(*value)).into()
+            }
+            Row::Constructor(Constructor::Record(record), fields) => {
+                let fields: Vec<(String, Pattern)> = record
+                    .fields
+                    .iter()
+                    .zip(fields)
+                    .filter(|(_, field)| !matches!(field, Row::Wildcard))
+                    .map(|(declared, field)| (declared.name.clone(), field.to_pattern()))
+                    .collect();
+                if fields.is_empty() {
+                    PatternKind::Wildcard.into()
+                } else {
+                    PatternKind::Record {
+                        record_type: Type::Named(record.name.clone()),
+                        fields,
+                    }
+                    .into()
+                }
+            }
+            Row::Constructor(Constructor::Tuple(_), fields) => {
+                PatternKind::Tuple(fields.iter().map(Row::to_pattern).collect()).into()
+            }
+            Row::Constructor(Constructor::Literal(literal), _) => {
+                PatternKind::Literal(literal.clone()).into()
+            }
+            Row::Constructor(
+                Constructor::Range {
+                    start,
+                    end,
+                    inclusive,
+                },
+                _,
+            ) => PatternKind::Range {
+                start: start.clone(),
+                end: end.clone(),
+                inclusive: *inclusive,
+            }
+            .into(),
+            Row::Or(alternatives) => {
+                PatternKind::Or(alternatives.iter().map(Row::to_pattern).collect()).into()
+            }
+            Row::Wildcard | Row::Constructor(Constructor::Unknown, _) => {
+                PatternKind::Wildcard.into()
+            }
+        }
+    }
+}
+
+/// `rows` with every row whose first pattern is an or-pattern split into one row per
+/// alternative.
+fn expand<'p>(rows: &[Vec<Row<'p>>]) -> Vec<Vec<Row<'p>>> {
+    let mut expanded = Vec::new();
+    for row in rows {
+        match row.first() {
+            Some(Row::Or(alternatives)) => {
+                let alternatives: Vec<Vec<Row>> = alternatives
+                    .iter()
+                    .map(|alternative| {
+                        let mut split = vec![alternative.clone()];
+                        split.extend_from_slice(&row[1..]);
+                        split
+                    })
+                    .collect();
+                expanded.extend(expand(&alternatives));
+            }
+            _ => expanded.push(row.clone()),
+        }
+    }
+    expanded
+}
+
+/// The rows that match a value built with `constructor`, with the first column replaced by the
+/// constructor's fields.
+fn specialize<'p>(rows: &[Vec<Row<'p>>], constructor: &Constructor<'p>) -> Vec<Vec<Row<'p>>> {
+    expand(rows)
+        .into_iter()
+        .filter_map(|row| {
+            let mut specialized = match &row[0] {
+                Row::Wildcard => vec![Row::Wildcard; constructor.arity()],
+                Row::Constructor(head, fields) if head.same(constructor) => fields.clone(),
+                _ => return None,
+            };
+            specialized.extend_from_slice(&row[1..]);
+            Some(specialized)
+        })
+        .collect()
+}
+
+/// The rows whose first pattern matches anything, without it.
+fn default<'p>(rows: &[Vec<Row<'p>>]) -> Vec<Vec<Row<'p>>> {
+    expand(rows)
+        .into_iter()
+        .filter(|row| matches!(row[0], Row::Wildcard))
+        .map(|row| row[1..].to_vec())
+        .collect()
+}
+
+/// The constructors in the first column of `rows`, and the full set of constructors of their
+/// type if every one of them appears.
+fn heads<'p>(rows: &[Vec<Row<'p>>]) -> (Vec<Constructor<'p>>, Option<Vec<Constructor<'p>>>) {
+    let heads: Vec<Constructor> = rows
+        .iter()
+        .filter_map(|row| match &row[0] {
+            Row::Constructor(head, _) => Some(head.clone()),
+            _ => None,
+        })
+        .collect();
+    let complete = heads.first().and_then(Constructor::all).filter(|all| {
+        all.iter()
+            .all(|constructor| heads.iter().any(|head| head.same(constructor)))
+    });
+    (heads, complete)
+}
+
+/// Whether some value matches `row` but none of `rows`.
+fn is_useful(rows: &[Vec<Row>], row: &[Row]) -> bool {
+    let Some((first, rest)) = row.split_first() else {
+        return rows.is_empty();
+    };
+    match first {
+        Row::Or(alternatives) => alternatives.iter().any(|alternative| {
+            let mut split = vec![alternative.clone()];
+            split.extend_from_slice(rest);
+            is_useful(rows, &split)
+        }),
+        Row::Constructor(constructor, fields) => {
+            let mut specialized = fields.clone();
+            specialized.extend_from_slice(rest);
+            is_useful(&specialize(rows, constructor), &specialized)
+        }
+        Row::Wildcard => match heads(&expand(rows)).1 {
+            Some(all) => all.iter().any(|constructor| {
+                let mut specialized = vec![Row::Wildcard; constructor.arity()];
+                specialized.extend_from_slice(rest);
+                is_useful(&specialize(rows, constructor), &specialized)
+            }),
+            None => is_useful(&default(rows), rest),
+        },
+    }
+}
+
+/// Rows of `width` patterns that together cover the values none of `rows` match.
+fn missing<'p>(rows: &[Vec<Row<'p>>], width: usize) -> Vec<Vec<Row<'p>>> {
+    if width == 0 {
+        return if rows.is_empty() {
+            vec![Vec::new()]
+        } else {
+            Vec::new()
+        };
+    }
+    let rows = expand(rows);
+    let (heads, complete) = heads(&rows);
+    if let Some(all) = complete {
+        let mut witnesses = Vec::new();
+        for constructor in all {
+            let arity = constructor.arity();
+            for witness in missing(&specialize(&rows, &constructor), arity + width - 1) {
+                let (fields, rest) = witness.split_at(arity);
+                let mut row = vec![Row::Constructor(constructor.clone(), fields.to_vec())];
+                row.extend_from_slice(rest);
+                witnesses.push(row);
+            }
+        }
+        return witnesses;
+    }
+
+    let rest = missing(&default(&rows), width - 1);
+    if rest.is_empty() {
+        return rest;
+    }
+    // Name the constructors no row mentions when they can be listed, and `_` otherwise.
+    let firsts: Vec<Row> = match heads.first().and_then(Constructor::all) {
+        Some(all) => all
+            .into_iter()
+            .filter(|constructor| !heads.iter().any(|head| head.same(constructor)))
+            .map(|constructor| {
+                let fields = vec![Row::Wildcard; constructor.arity()];
+                Row::Constructor(constructor, fields)
+            })
+            .collect(),
+        None => vec![Row::Wildcard],
+    };
+    let mut witnesses = Vec::new();
+    for first in &firsts {
+        for witness in &rest {
+            let mut row = vec![first.clone()];
+            row.extend_from_slice(witness);
+            witnesses.push(row);
+        }
+    }
+    witnesses
+}
+
+struct Checker<'p, 'i> {
+    index: &'i NodeIndex,
+    resolution: &'i Resolution,
+    types: &'i TypeMap,
+    variants: HashMap<NodeId, (&'p UnionDeclaration, usize)>,
+    records: HashMap<&'p str, &'p RecordDeclaration>,
+    errors: Vec<MatchError>,
+}
+
+impl<'p> Checker<'p, '_> {
+    /// The variant `pattern` names, if it names one.
+    fn variant(&self, pattern: &Pattern) -> Option<Constructor<'p>> {
+        let symbol = self.resolution.symbol_of(self.index.expect_id(pattern))?;
+        let symbol = self.resolution.symbols.symbol(symbol);
+        if symbol.kind != SymbolKind::Variant {
+            return None;
+        }
+        let &(union, position) = self.variants.get(&symbol.node?)?;
+        Some(Constructor::Variant(union, position))
+    }
+
+    fn row(&self, pattern: &Pattern) -> Row<'p> {
+        let constructor = |constructor: Constructor<'p>| {
+            let fields = vec![Row::Wildcard; constructor.arity()];
+            Row::Constructor(constructor, fields)
+        };
+        if self.types.is_mismatched(self.index.expect_id(pattern)) {
+            return constructor(Constructor::Unknown);
+        }
+        match &pattern.kind {
+            PatternKind::Identifier(_) => self.variant(pattern).map_or(Row::Wildcard, constructor),
+            PatternKind::Union { payload, .. } => {
+                let mut row = constructor(self.variant(pattern).unwrap_or(Constructor::Unknown));
+                if let (Row::Constructor(_, fields), Some(payload)) = (&mut row, payload)
+                    && let Some(field) = fields.first_mut()
+                {
+                    *field = self.row(payload);
+                }
+                row
+            }
+            PatternKind::Record {
+                record_type,
+                fields,
+            } => {
+                let name = match record_type {
+                    Type::Named(name) | Type::Generic { name, .. } => name.as_str(),
+                    _ => "",
+                };
+                let Some(&record) = self.records.get(name) else {
+                    return constructor(Constructor::Unknown);
+                };
+                let rows = record
+                    .fields
+                    .iter()
+                    .map(|declared| {
+                        fields
+                            .iter()
+                            .find(|(field, _)| *field == declared.name)
+                            .map_or(Row::Wildcard, |(_, pattern)| self.row(pattern))
+                    })
+                    .collect();
+                Row::Constructor(Constructor::Record(record), rows)
+            }
+            PatternKind::Tuple(elements) => Row::Constructor(
+                Constructor::Tuple(elements.len()),
+                elements.iter().map(|element| self.row(element)).collect(),
+            ),
+            PatternKind::Literal(Literal::Boolean(value)) => constructor(Constructor::Bool(*value)),
+            PatternKind::Literal(literal) => constructor(Constructor::Literal(literal.clone())),
+            PatternKind::Range {
+                start,
+                end,
+                inclusive,
+            } => constructor(Constructor::Range {
+                start: start.clone(),
+                end: end.clone(),
+                inclusive: *inclusive,
+            }),
+            PatternKind::Or(alternatives) => Row::Or(
+                alternatives
+                    .iter()
+                    .map(|pattern| self.row(pattern))
+                    .collect(),
+            ),
+            PatternKind::Wildcard | PatternKind::Else => Row::Wildcard,
+        }
+    }
+
+    fn check_when(&mut self, when: &Expression) {
+        let ExpressionKind::When { branches, .. } = &when.kind else {
+            return;
+        };
+        let mut rows: Vec<Vec<Row>> = Vec::new();
+        for branch in branches {
+            let row = vec![self.row(&branch.pattern)];
+            if !is_useful(&rows, &row) {
+                self.errors.push(MatchError::UnreachableBranch {
+                    pattern: branch.pattern.to_source(),
+                    node: self.index.expect_id(&branch.pattern),
+                });
+            }
+            if branch.guard.is_none() {
+                rows.push(row);
+            }
+        }
+
+        if self.types.is_discarded(self.index.expect_id(when)) {
+            return;
+        }
+        let mut missing_patterns: Vec<String> = Vec::new();
+        for witness in missing(&rows, 1) {
+            let pattern = witness[0].to_pattern().to_source();
+            if !missing_patterns.contains(&pattern) {
+                missing_patterns.push(pattern);
+            }
+        }
+        if !missing_patterns.is_empty() {
+            self.errors.push(MatchError::NonExhaustive {
+                missing: missing_patterns,
+                node: self.index.expect_id(when),
+            });
+        }
+    }
+}
+
+impl Visitor for Checker<'_, '_> {
+    fn visit_expression(&mut self, expression: &Expression) {
+        if let ExpressionKind::When { .. } = &expression.kind {
+            self.check_when(expression);
+        }
+        walk_expression(self, expression);
+    }
+}
+
+/// The expressions in `program` whose value is never used: expression statements, the bodies of
+/// loops and of functions that return nothing, and the parts of those whose value would become
+/// theirs.
+pub(crate) fn discarded(program: &Program, index: &NodeIndex) -> HashSet<NodeId> {
+    let mut discards = Discards {
+        index,
+        discarded: HashSet::new(),
+    };
+    discards.visit_program(program);
+    discards.discarded
+}
+
+struct Discards<'i> {
+    index: &'i NodeIndex,
+    discarded: HashSet<NodeId>,
+}
+
+impl Discards<'_> {
+    /// Mark `expression` as evaluated only for its effects, along with the expressions whose
+    /// value would become its value.
+    fn discard(&mut self, expression: &Expression) {
+        self.discarded.insert(self.index.expect_id(expression));
+        match &expression.kind {
+            ExpressionKind::Block {
+                final_expression: Some(final_expression),
+                ..
+            } => self.discard(final_expression),
+            ExpressionKind::If {
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.discard(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.discard(else_branch);
+                }
+            }
+            ExpressionKind::When { branches, .. } => {
+                for branch in branches {
+                    self.discard(&branch.body);
+                }
+            }
+            ExpressionKind::Grouped(inner) => self.discard(inner),
+            _ => {}
+        }
+    }
+}
+
+impl Visitor for Discards<'_> {
+    fn visit_function(&mut self, function: &FunctionDeclaration) {
+        // A function without a return type returns nothing, so its body's value is unused.
+        if function.return_type.is_none() {
+            self.discard(&function.body);
+        }
+        walk_function(self, function);
+    }
+
+    fn visit_statement(&mut self, statement: &Statement) {
+        if let StatementKind::Expression(expression) = &statement.kind {
+            self.discard(expression);
+        }
+        walk_statement(self, statement);
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        match &expression.kind {
+            ExpressionKind::Loop { body }
+            | ExpressionKind::While { body, .. }
+            | ExpressionKind::For { body, .. } => self.discard(body),
+            _ => {}
+        }
+        walk_expression(self, expression);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolve::resolve;
+    use parser::parse;
+    use test_case::test_case;
+
+    fn match_errors(source: &str) -> Vec<(Severity, String)> {
+        let program = parse(source).expect("Parse error");
+        let index = NodeIndex::new(&program);
+        let resolution = resolve(&program, &index);
+        let types = crate::check(&program, &index, &resolution).types;
+        check_matches(&program, &index, &resolution, &types)
+            .iter()
+            .map(|error| (error.severity(), error.to_string()))
+            .collect()
+    }
+
+    const SHAPES: &str = "union shape = circle(f64) | square(f64) | dot; ";
+    const OPTIONS: &str = "union option<T> = some(T) | none; ";
+
+    #[test_case("x = when s { circle(r): r; square(w): w; dot: 0.0; };" ; "every variant")]
+    #[test_case("x = when s { circle(r): r; _: 0.0; };" ; "wildcard")]
+    #[test_case("x = when s { circle(r): r; other: 0.0; };" ; "binding")]
+    #[test_case("x = when s { circle(r) | square(r): r; dot: 0.0; };" ; "or pattern")]
+    #[test_case("when s { circle(r): print(r); };" ; "statement position")]
+    #[test_case("fn f(shape s) { when s { dot: print(0); } }" ; "tail of a unit function")]
+    #[test_case("x = when true { true: 1; false: 0; };" ; "both booleans")]
+    #[test_case("x = when 3 { 0: 'z'; else: 'n'; };" ; "else branch")]
+    #[test_case("x = when pair { (_, true): 1; (n, false): n; };" ; "tuple")]
+    fn test_exhaustive(source: &str) {
+        assert_eq!(match_errors(&format!("{}{}", SHAPES, source)), []);
+    }
+
+    #[test_case("x = when s { circle(r): r; };", "'when' does not cover square(_), dot" ; "missing variants")]
+    #[test_case("x = when s { circle(r) if r > 1.0: r; square(w): w; dot: 0.0; };", "'when' does not cover circle(_)" ; "guarded branch")]
+    #[test_case("x = when 3 { 0: 'z'; 1: 'o'; };", "'when' does not cover _" ; "integers")]
+    #[test_case("x = when true { true: 1; };", "'when' does not cover false" ; "boolean")]
+    #[test_case(
+        "x = when some(true) { some(true): 1; none: 0; };",
+        "'when' does not cover some(false)" ;
+        "nested payload"
+    )]
+    #[test_case(
+        "x = when some(s) { some(circle(r)): r; some(dot) | none: 0.0; };",
+        "'when' does not cover some(square(_))" ;
+        "nested variant"
+    )]
+    #[test_case(
+        "record flags { a: bool; b: bool; } x = when f { flags { a: true }: 1; flags { b: true }: 2; };",
+        "'when' does not cover flags { a: false, b: false }" ;
+        "record fields"
+    )]
+    #[test_case("fn f(shape s) -> i32 { when s { dot: 0; } }", "'when' does not cover circle(_), square(_)" ; "function result")]
+    fn test_non_exhaustive(source: &str, expected: &str) {
+        assert_eq!(
+            match_errors(&format!("{}{}{}", SHAPES, OPTIONS, source)),
+            [(Severity::Error, expected.to_string())]
+        );
+    }
+
+    #[test_case("when s { _: print(1); dot: print(2); };", "dot" ; "after a wildcard")]
+    #[test_case("when s { dot: print(1); dot: print(2); };", "dot" ; "repeated variant")]
+    #[test_case("when 3 { 0 | 1: print(1); 1: print(2); };", "1" ; "covered by an alternative")]
+    #[test_case("when true { true: print(1); false: print(2); b: print(3); };", "b" ; "after every boolean")]
+    #[test_case("when s { circle(_): print(1); circle(r) if r > 0.0: print(r); };", "circle(r)" ; "guarded")]
+    #[test_case("fn f(i32 x) -> i32 { when x { (a, b): a; n: n; _: 0; } }", "_" ; "after a mismatched pattern")]
+    fn test_unreachable(source: &str, pattern: &str) {
+        assert_eq!(
+            match_errors(&format!("{}{}", SHAPES, source)),
+            [(
+                Severity::Warning,
+                format!(
+                    "Unreachable branch '{}': earlier branches match every value it does",
+                    pattern
+                )
+            )]
+        );
+    }
+
+    #[test]
+    fn test_guarded_branch_can_be_followed() {
+        let source = format!(
+            "{}x = when s {{ circle(r) if r > 1.0: r; circle(r): 0.0; _: 1.0; }};",
+            SHAPES
+        );
+        assert_eq!(match_errors(&source), []);
+    }
+
+    #[test]
+    fn test_mismatched_pattern_covers_nothing() {
+        let source = "fn f(i32 x) -> i32 { when x { (a, b): a; _: 0; } }";
+        assert_eq!(match_errors(source), []);
+    }
+}
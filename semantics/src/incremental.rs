@@ -0,0 +1,183 @@
+//! Checking a program one part at a time, as the REPL does with each input.
+//!
+//! Each part is resolved and checked on its own, after the parts accepted before it: it sees
+//! what they declared through the symbols and types kept in [`Earlier`], and the passes that
+//! need the declarations themselves, such as the unions a `when` matches on, are given them by
+//! reference. No part is checked again once it has been accepted.
+//!
+//! Monomorphization is the one check left out. It needs every generic function a part calls
+//! together with the part, so it only runs on a whole program; the interpreter does not need its
+//! instances to run one.
+
+use crate::diagnostic::Diagnostic;
+use crate::pipeline::check_program_after;
+use crate::resolve::{Resolution, resolve_after};
+use crate::scope::{ScopeId, ScopeKind};
+use crate::shadowing::ShadowingLint;
+use crate::symbols::SymbolTable;
+use crate::typeck::{TypeCheck, TypeMap, check_after};
+use parser::ast::{Declaration, Program};
+use parser::node_id::NodeIndex;
+use std::mem;
+
+/// What the parts of a program accepted so far declared, for the next part to be checked
+/// against.
+///
+/// A part is [resolved](Earlier::resolve) first, and must then be either
+/// [accepted](Earlier::accept), which keeps what it declares for the parts after it, or
+/// [rejected](Earlier::reject), which forgets it.
+pub struct Earlier<'p> {
+    /// The scopes and symbols of the accepted parts, which the part being checked holds on to
+    /// between being resolved and being accepted or rejected.
+    symbols: SymbolTable,
+    /// The scope the top-level declarations of the parts after the first go in.
+    global: ScopeId,
+    /// The types of the symbols of the accepted parts.
+    types: TypeMap,
+    /// The declarations of the accepted parts, in the order they were accepted.
+    declarations: Vec<&'p Declaration>,
+}
+
+impl<'p> Earlier<'p> {
+    /// Start with `program`, which `resolution` resolved and whose symbols `types` gives the
+    /// types of. The parts after it may declare the names it declares again, hiding its own,
+    /// but not the names each other declares.
+    pub fn new(program: &'p Program, resolution: Resolution, types: &TypeMap) -> Self {
+        let mut symbols = resolution.symbols;
+        let global = symbols.add_scope(ScopeKind::Global, symbols.root(), None);
+        let mut earlier = Earlier {
+            symbols,
+            global,
+            types: TypeMap::default(),
+            declarations: Vec::new(),
+        };
+        earlier.keep(program, types);
+        earlier
+    }
+
+    /// Resolve every name in `program`, whose nodes `index` numbers, with the names the
+    /// accepted parts declared visible.
+    pub fn resolve(&mut self, program: &Program, index: &NodeIndex) -> Resolution {
+        let symbols = mem::take(&mut self.symbols);
+        resolve_after(program, index, symbols, self.global)
+    }
+
+    /// Type check `program`, which [`Earlier::resolve`] gave `resolution`.
+    pub fn check(
+        &self,
+        program: &Program,
+        index: &NodeIndex,
+        resolution: &Resolution,
+    ) -> TypeCheck {
+        check_after(program, index, resolution, &self.types, &self.declarations)
+    }
+
+    /// Every problem the checks of [`check_program`](crate::check_program) but for
+    /// monomorphization find in `program`, given its resolution and type check, with the
+    /// shadowing `shadowing` asks for.
+    pub fn check_program(
+        &self,
+        program: &Program,
+        index: &NodeIndex,
+        resolution: &Resolution,
+        checked: &TypeCheck,
+        shadowing: ShadowingLint,
+    ) -> Vec<Diagnostic> {
+        check_program_after(
+            program,
+            index,
+            resolution,
+            checked,
+            shadowing,
+            &self.declarations,
+        )
+    }
+
+    /// Keep what `program` declares for the parts after it. `resolution` and `types` are what
+    /// resolving and checking it gave.
+    pub fn accept(&mut self, program: &'p Program, resolution: Resolution, types: &TypeMap) {
+        self.symbols = resolution.symbols;
+        self.keep(program, types);
+    }
+
+    /// Forget the part that `resolution` resolved.
+    pub fn reject(&mut self, resolution: Resolution) {
+        self.symbols = resolution.symbols;
+        self.symbols.truncate(resolution.start);
+    }
+
+    fn keep(&mut self, program: &'p Program, types: &TypeMap) {
+        self.declarations.extend(&program.declarations);
+        self.types.add_symbols(types);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostic::Severity;
+    use parser::node_id::NodeIds;
+    use parser::parse;
+
+    /// `source` parsed with ids after those of `before`.
+    fn after(before: &Program, source: &str) -> Program {
+        let mut program = parse(source).expect("the source parses");
+        NodeIds::after(before).number_program(&mut program);
+        program
+    }
+
+    /// The messages of the errors `program` has after the parts `earlier` accepted, with its
+    /// resolution and the types of its symbols.
+    fn problems(earlier: &mut Earlier, program: &Program) -> (Vec<String>, Resolution, TypeMap) {
+        let index = NodeIndex::new(program);
+        let resolution = earlier.resolve(program, &index);
+        let checked = earlier.check(program, &index, &resolution);
+        let messages = earlier
+            .check_program(
+                program,
+                &index,
+                &resolution,
+                &checked,
+                ShadowingLint::SameScope,
+            )
+            .into_iter()
+            .filter(|diagnostic| diagnostic.severity == Severity::Error)
+            .map(|diagnostic| diagnostic.message)
+            .collect();
+        (messages, resolution, checked.types)
+    }
+
+    #[test]
+    fn test_parts_see_what_earlier_parts_declared() {
+        let first = parse("union shape = circle(f64) | square(f64);").unwrap();
+        let index = NodeIndex::new(&first);
+        let resolution = crate::resolve(&first, &index);
+        let types = crate::check(&first, &index, &resolution).types;
+        let mut earlier = Earlier::new(&first, resolution, &types);
+
+        let partial = after(
+            &first,
+            "fn area(shape s) -> f64 { when s { circle(r): r * r; } }",
+        );
+        let (messages, resolution, _) = problems(&mut earlier, &partial);
+        assert_eq!(messages, ["'when' does not cover square(_)"]);
+        earlier.reject(resolution);
+
+        let area = after(
+            &first,
+            "fn area(shape s) -> f64 { when s { circle(r): r * r; square(l): l * l; } }",
+        );
+        let (messages, resolution, types) = problems(&mut earlier, &area);
+        assert!(messages.is_empty(), "{:?}", messages);
+        earlier.accept(&area, resolution, &types);
+
+        let again = after(&area, "fn area() {}");
+        let (messages, resolution, _) = problems(&mut earlier, &again);
+        assert_eq!(messages, ["'area' is already defined as a function"]);
+        earlier.reject(resolution);
+
+        let call = after(&area, "f64 x = area(circle(1.0));");
+        let (messages, _, _) = problems(&mut earlier, &call);
+        assert!(messages.is_empty(), "{:?}", messages);
+    }
+}
@@ -0,0 +1,38 @@
+//! Analyses of a parsed CV program that need more than its syntax.
+//!
+//! Each pass reads the tree and records what it learns in side tables keyed by
+//! [`NodeId`](parser::node_id::NodeId), so the same [`NodeIndex`](parser::node_id::NodeIndex) ties
+//! together the results of every pass.
+
+pub mod diagnostic;
+pub mod exhaustive;
+pub mod incremental;
+pub mod modules;
+pub mod monomorphize;
+pub mod mutability;
+pub mod pipeline;
+pub mod prelude;
+pub mod references;
+pub mod resolve;
+pub mod returns;
+pub mod scope;
+pub mod shadowing;
+pub mod symbols;
+pub mod typeck;
+pub mod unused;
+
+pub use diagnostic::{Diagnostic, Note, Severity};
+pub use exhaustive::{MatchError, check_matches};
+pub use incremental::Earlier;
+pub use modules::{Linked, Module, ModuleError, check_modules};
+pub use monomorphize::{Instance, MonomorphizeError, Monomorphized, monomorphize};
+pub use mutability::{MutabilityError, check_mutability};
+pub use pipeline::check_program;
+pub use prelude::with_prelude;
+pub use references::{ReferenceError, check_references};
+pub use resolve::{BUILTINS, Host, Resolution, ResolveError, resolve, resolve_with_hosts};
+pub use returns::{ReturnError, check_returns};
+pub use shadowing::{ShadowWarning, ShadowingLint, check_shadowing};
+pub use symbols::{Symbol, SymbolId, SymbolKind, SymbolTable};
+pub use typeck::{TypeCheck, TypeError, TypeMap, check, check_with_hosts};
+pub use unused::{UnusedWarning, check_unused};
@@ -0,0 +1,1160 @@
+//! Modules: each source file is a module, and a program is built from one or more of them.
+//!
+//! A module sees its own declarations and the ones it imports with `import module::name;`. It
+//! can only import the functions, records, unions, and consts another module declares `pub`, and
+//! importing a union imports its variants. A top-level variable is never visible outside its
+//! module. The fields of a record from another module can only be read, given in a record
+//! literal, or matched by a pattern if they are declared `pub` too.
+//!
+//! Modules may not import each other in a cycle. [`load`] reads each module once however the
+//! imports loop, and [`check_modules`] reports every cycle with the modules along it.
+//!
+//! Each module has top-level names of its own: two modules may declare the same name, and each
+//! sees its own declaration. A module can import a name only if it does not declare it too, and
+//! only from one module.
+//!
+//! The modules are linked into one program, which the other passes check as a whole. Linking
+//! renames a top-level name that more than one module declares to `module::name` wherever the
+//! module that declares it, or one that imports it, uses it, so the names stay apart within the
+//! program. The checks here are what keeps the modules apart otherwise.
+
+use crate::prelude::declared_names;
+use crate::resolve::Resolution;
+use crate::typeck::TypeMap;
+use parser::ast::{
+    ArraySize, ConstDeclaration, Declaration, Expression, ExpressionKind, FunctionDeclaration,
+    Parameter, Pattern, PatternKind, Program, RecordDeclaration, RecordField, Statement,
+    StatementKind, Type, TypeParameter, UnionDeclaration, UnionVariant,
+};
+use parser::node_id::{Node, NodeId, NodeIds, NodeIndex};
+use parser::visit::{
+    Visitor, walk_declaration, walk_expression, walk_parameter, walk_pattern, walk_record_field,
+    walk_statement, walk_type, walk_union_variant,
+};
+use parser::visit_mut::{self, MutVisitor};
+use parser::{ParseError, parse};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// The extension of CV source files.
+pub const EXTENSION: &str = "cv";
+
+/// A parsed source file, named after it, such as `geometry` for `geometry.cv`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Module {
+    pub name: String,
+    pub program: Program,
+}
+
+#[derive(Debug, Error)]
+pub enum LoadError {
+    #[error("Cannot read module '{module}' from {}: {source}", path.display())]
+    Io {
+        module: String,
+        path: PathBuf,
+        source: io::Error,
+    },
+    #[error("In module '{module}': {error}")]
+    Parse {
+        module: String,
+        error: Box<ParseError>,
+    },
+}
+
+/// Read the module `entry` from `directory` and every module it imports, directly or not, from
+/// the same directory, entry first. Each module is read once, so imports that form a cycle do
+/// not keep the loader going. An imported module without a file is left out, for
+/// [`check_modules`] to report.
+pub fn load(directory: &Path, entry: &str) -> Result<Vec<Module>, LoadError> {
+    let parse_module = |name: &str, _: &Path, source: String| {
+        parse(&source).map_err(|error| LoadError::Parse {
+            module: name.to_string(),
+            error: Box::new(error),
+        })
+    };
+    let path = directory.join(entry).with_extension(EXTENSION);
+    let source = std::fs::read_to_string(&path).map_err(|source| LoadError::Io {
+        module: entry.to_string(),
+        path: path.clone(),
+        source,
+    })?;
+    let entry = Module {
+        name: entry.to_string(),
+        program: parse_module(entry, &path, source)?,
+    };
+    load_imports(directory, entry, parse_module)
+}
+
+/// Read every module `entry` imports, directly or not, from `directory`, as [`load`] does,
+/// with `parse` turning the source of each one, given its name and path, into its program.
+/// Returns `entry` followed by the modules in the order they were read.
+pub fn load_imports(
+    directory: &Path,
+    entry: Module,
+    mut parse: impl FnMut(&str, &Path, String) -> Result<Program, LoadError>,
+) -> Result<Vec<Module>, LoadError> {
+    let mut seen = HashSet::from([entry.name.clone()]);
+    let mut pending = VecDeque::new();
+    queue_imports(&entry.program, &mut seen, &mut pending);
+    let mut modules = vec![entry];
+    while let Some(name) = pending.pop_front() {
+        let path = directory.join(&name).with_extension(EXTENSION);
+        let source = match std::fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+            Err(source) => {
+                return Err(LoadError::Io {
+                    module: name,
+                    path,
+                    source,
+                });
+            }
+        };
+        let program = parse(&name, &path, source)?;
+        queue_imports(&program, &mut seen, &mut pending);
+        modules.push(Module { name, program });
+    }
+    Ok(modules)
+}
+
+/// Add the modules `program` imports that have not been `seen` to `pending`.
+fn queue_imports(program: &Program, seen: &mut HashSet<String>, pending: &mut VecDeque<String>) {
+    for declaration in &program.declarations {
+        if let Declaration::Import(import) = declaration
+            && seen.insert(import.module.clone())
+        {
+            pending.push_back(import.module.clone());
+        }
+    }
+}
+
+/// Modules linked into one program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Linked {
+    /// Every declaration of every module, module by module in the order they were given.
+    pub program: Program,
+    /// The name of each module.
+    pub modules: Vec<String>,
+    /// The module each declaration of `program` comes from, as an index into `modules`, or
+    /// `None` for a declaration of the prelude.
+    origins: Vec<Option<usize>>,
+}
+
+impl Linked {
+    /// Link `modules`, giving the nodes of each one ids after the ones before it.
+    pub fn new(mut modules: Vec<Module>) -> Self {
+        let mut ids = NodeIds::new();
+        for module in &mut modules {
+            ids.number_program(&mut module.program);
+        }
+        Linked::numbered(modules)
+    }
+
+    /// Link `modules` whose nodes already have ids apart from each other's, such as ones parsed
+    /// each with ids after those of the modules before it, keeping the ids so that the spans
+    /// recorded for them stay right.
+    pub fn numbered(mut modules: Vec<Module>) -> Self {
+        qualify(&mut modules);
+        let mut linked = Linked {
+            program: Program {
+                declarations: Vec::new(),
+            },
+            modules: Vec::new(),
+            origins: Vec::new(),
+        };
+        for (position, module) in modules.into_iter().enumerate() {
+            linked.modules.push(module.name);
+            linked.origins.extend(std::iter::repeat_n(
+                Some(position),
+                module.program.declarations.len(),
+            ));
+            linked
+                .program
+                .declarations
+                .extend(module.program.declarations);
+        }
+        linked
+    }
+
+    /// The linked program with the [prelude](crate::prelude) in front of it. The prelude
+    /// belongs to no module, so every module sees it without importing it.
+    pub fn with_prelude(self) -> Self {
+        let program = crate::prelude::with_prelude(self.program);
+        let prelude = program.declarations.len() - self.origins.len();
+        let mut origins = vec![None; prelude];
+        origins.extend(self.origins);
+        Linked {
+            program,
+            modules: self.modules,
+            origins,
+        }
+    }
+
+    /// The name of the module the declaration at `position` in the program comes from, or
+    /// `None` for the prelude.
+    pub fn module_of(&self, position: usize) -> Option<&str> {
+        self.origins[position].map(|module| self.modules[module].as_str())
+    }
+}
+
+/// The name `name` of `module` takes in the linked program when another module declares it too.
+fn qualified_name(module: &str, name: &str) -> String {
+    format!("{}::{}", module, name)
+}
+
+/// `name` as its module writes it, without the module [`qualified_name`] adds.
+fn unqualified_name(name: &str) -> &str {
+    name.rsplit_once("::").map_or(name, |(_, name)| name)
+}
+
+/// Rename each top-level name that more than one of `modules` declares to its qualified name,
+/// in each module that declares it and each that imports it, so that linking keeps them apart.
+/// The first module is the entry and keeps its names, so that its `main` is the one that runs.
+fn qualify(modules: &mut [Module]) {
+    let declared: Vec<HashSet<String>> = modules
+        .iter()
+        .map(|module| {
+            let declarations = module.program.declarations.iter();
+            declarations.flat_map(declared_names).collect()
+        })
+        .collect();
+    let mut declarers: HashMap<&str, usize> = HashMap::new();
+    for name in declared.iter().flatten() {
+        *declarers.entry(name).or_default() += 1;
+    }
+    let is_qualified = |module: usize, name: &str| {
+        module != 0 && declared[module].contains(name) && declarers[name] > 1
+    };
+    let mut renames = Vec::new();
+    for (position, module) in modules.iter().enumerate() {
+        let mut names = HashMap::new();
+        for name in &declared[position] {
+            if is_qualified(position, name) {
+                names.insert(name.clone(), qualified_name(&module.name, name));
+            }
+        }
+        for declaration in &module.program.declarations {
+            let Declaration::Import(import) = declaration else {
+                continue;
+            };
+            let Some(target) = modules.iter().position(|m| m.name == import.module) else {
+                continue;
+            };
+            let items = modules[target]
+                .program
+                .declarations
+                .iter()
+                .map(declared_names);
+            let imported = items
+                .filter(|names| {
+                    names
+                        .first()
+                        .is_some_and(|name| import.items.contains(name))
+                })
+                .flatten();
+            for name in imported {
+                if !declared[position].contains(&name) && is_qualified(target, &name) {
+                    let qualified = qualified_name(&import.module, &name);
+                    names.entry(name).or_insert(qualified);
+                }
+            }
+        }
+        renames.push(names);
+    }
+    for (module, names) in modules.iter_mut().zip(renames) {
+        if !names.is_empty() {
+            Qualify(names).visit_program(&mut module.program);
+        }
+    }
+}
+
+/// Renames the top-level names of a module, and every use of them, from the keys of the map to
+/// its values. Fields and methods have names of their own and are left alone.
+struct Qualify(HashMap<String, String>);
+
+impl Qualify {
+    fn rename(&self, name: &mut String) {
+        if let Some(qualified) = self.0.get(name.as_str()) {
+            name.clone_from(qualified);
+        }
+    }
+}
+
+impl MutVisitor for Qualify {
+    fn visit_declaration(&mut self, declaration: &mut Declaration) {
+        if let Declaration::Function(FunctionDeclaration { name, .. })
+        | Declaration::Record(RecordDeclaration { name, .. })
+        | Declaration::Union(UnionDeclaration { name, .. })
+        | Declaration::Const(ConstDeclaration { name, .. }) = declaration
+        {
+            self.rename(name);
+        }
+        visit_mut::walk_declaration(self, declaration);
+    }
+
+    fn visit_parameter(&mut self, parameter: &mut Parameter) {
+        self.rename(&mut parameter.name);
+        visit_mut::walk_parameter(self, parameter);
+    }
+
+    fn visit_type_parameter(&mut self, parameter: &mut TypeParameter) {
+        self.rename(&mut parameter.name);
+        visit_mut::walk_type_parameter(self, parameter);
+    }
+
+    fn visit_union_variant(&mut self, variant: &mut UnionVariant) {
+        self.rename(&mut variant.name);
+        visit_mut::walk_union_variant(self, variant);
+    }
+
+    fn visit_statement(&mut self, statement: &mut Statement) {
+        if let StatementKind::VariableDeclaration { name, .. } = &mut statement.kind {
+            self.rename(name);
+        }
+        visit_mut::walk_statement(self, statement);
+    }
+
+    fn visit_expression(&mut self, expression: &mut Expression) {
+        if let ExpressionKind::Identifier(name)
+        | ExpressionKind::For { variable: name, .. }
+        | ExpressionKind::UnionLiteral { variant: name, .. } = &mut expression.kind
+        {
+            self.rename(name);
+        }
+        visit_mut::walk_expression(self, expression);
+    }
+
+    fn visit_pattern(&mut self, pattern: &mut Pattern) {
+        if let PatternKind::Identifier(name) | PatternKind::Union { variant: name, .. } =
+            &mut pattern.kind
+        {
+            self.rename(name);
+        }
+        visit_mut::walk_pattern(self, pattern);
+    }
+
+    fn visit_type(&mut self, ty: &mut Type) {
+        if let Type::Named(name)
+        | Type::Generic { name, .. }
+        | Type::FixedArray {
+            size: ArraySize::Parameter(name),
+            ..
+        } = ty
+        {
+            self.rename(name);
+        }
+        visit_mut::walk_type(self, ty);
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Clone)]
+pub enum ModuleError {
+    /// `node` is the import.
+    #[error("Cannot find module '{module}'")]
+    UnknownModule { module: String, node: NodeId },
+    /// `node` is the import.
+    #[error("Module '{module}' has no item '{item}'")]
+    UnknownItem {
+        module: String,
+        item: String,
+        node: NodeId,
+    },
+    /// `node` is the import and `declaration` the private declaration.
+    #[error("'{item}' exists in module '{module}' but is private")]
+    PrivateItem {
+        module: String,
+        item: String,
+        node: NodeId,
+        declaration: NodeId,
+    },
+    /// `node` is the expression, pattern, or type that uses the name.
+    #[error("'{name}' belongs to module '{module}' and is not imported")]
+    NotImported {
+        name: String,
+        module: String,
+        node: NodeId,
+    },
+    /// `node` is the field access, record literal, or record pattern.
+    #[error("Field '{field}' of '{record}' exists but is private to module '{module}'")]
+    PrivateField {
+        record: String,
+        field: String,
+        module: String,
+        node: NodeId,
+    },
+    /// `node` is the import that closes the cycle. The cycle starts and ends with the same
+    /// module.
+    #[error("Modules import each other in a cycle: {}", cycle.join(" -> "))]
+    ImportCycle { cycle: Vec<String>, node: NodeId },
+    /// `node` is the import and `declaration` the importing module's own declaration of the name.
+    #[error("Cannot import '{name}' from module '{module}' because this module declares it too")]
+    ImportClash {
+        name: String,
+        module: String,
+        node: NodeId,
+        declaration: NodeId,
+    },
+    /// `node` is the second import and `first` the first.
+    #[error("'{name}' is imported from both module '{first_module}' and module '{module}'")]
+    ConflictingImport {
+        name: String,
+        first_module: String,
+        module: String,
+        node: NodeId,
+        first: NodeId,
+    },
+}
+
+impl ModuleError {
+    pub fn node(&self) -> NodeId {
+        match self {
+            ModuleError::UnknownModule { node, .. }
+            | ModuleError::UnknownItem { node, .. }
+            | ModuleError::PrivateItem { node, .. }
+            | ModuleError::NotImported { node, .. }
+            | ModuleError::PrivateField { node, .. }
+            | ModuleError::ImportCycle { node, .. }
+            | ModuleError::ImportClash { node, .. }
+            | ModuleError::ConflictingImport { node, .. } => *node,
+        }
+    }
+
+    /// The declaration that explains the error, if there is one.
+    pub fn declaration(&self) -> Option<NodeId> {
+        match self {
+            ModuleError::PrivateItem { declaration, .. }
+            | ModuleError::ImportClash { declaration, .. } => Some(*declaration),
+            _ => None,
+        }
+    }
+}
+
+/// Check the imports of `linked` and that no module uses a name or field another module keeps
+/// to itself. `index`, `resolution`, and `types` are those of the linked program.
+pub fn check_modules(
+    linked: &Linked,
+    index: &NodeIndex,
+    resolution: &Resolution,
+    types: &TypeMap,
+) -> Vec<ModuleError> {
+    let mut checker = Checker {
+        linked,
+        index,
+        resolution,
+        types,
+        items: HashMap::new(),
+        owners: HashMap::new(),
+        records: HashMap::new(),
+        declared: vec![HashMap::new(); linked.modules.len()],
+        imported: vec![HashSet::new(); linked.modules.len()],
+        imported_names: vec![HashMap::new(); linked.modules.len()],
+        dependencies: vec![Vec::new(); linked.modules.len()],
+        current: 0,
+        enclosing: NodeId::UNASSIGNED,
+        errors: Vec::new(),
+    };
+    checker.collect_items();
+    checker.check_imports();
+    checker.check_cycles();
+    for (position, declaration) in linked.program.declarations.iter().enumerate() {
+        if let Some(module) = linked.origins[position] {
+            checker.current = module;
+            checker.visit_declaration(declaration);
+        }
+    }
+    checker.errors
+}
+
+/// A top-level declaration, as another module sees it.
+struct Item<'l> {
+    module: usize,
+    is_public: bool,
+    node: NodeId,
+    /// Whether it can be imported, as functions, records, unions, and consts can.
+    importable: bool,
+    /// The names importing it brings into a module, as its own module writes them: its name
+    /// and, for a union, those of its variants.
+    names: Vec<&'l str>,
+}
+
+struct Checker<'l, 'i> {
+    linked: &'l Linked,
+    index: &'i NodeIndex,
+    resolution: &'i Resolution,
+    types: &'i TypeMap,
+    items: HashMap<&'l str, Item<'l>>,
+    /// The item each top-level defining node belongs to: a union for its variants, and the
+    /// declaration itself otherwise.
+    owners: HashMap<NodeId, &'l str>,
+    records: HashMap<&'l str, &'l RecordDeclaration>,
+    /// The top-level names each module declares, as it writes them, with their declarations.
+    declared: Vec<HashMap<&'l str, NodeId>>,
+    /// The items each module imports.
+    imported: Vec<HashSet<&'l str>>,
+    /// The names each module imports, as it writes them, with the module each comes from and
+    /// the import.
+    imported_names: Vec<HashMap<&'l str, (usize, NodeId)>>,
+    /// The other modules each module imports from, with the import.
+    dependencies: Vec<Vec<(usize, NodeId)>>,
+    /// The module of the declaration being checked.
+    current: usize,
+    /// The innermost node being checked, which the types written in it are reported at.
+    enclosing: NodeId,
+    errors: Vec<ModuleError>,
+}
+
+impl<'l> Checker<'l, '_> {
+    /// Run `check` with `node` as the enclosing node.
+    fn within(&mut self, node: &impl Node, check: impl FnOnce(&mut Self)) {
+        let node = self.index.expect_id(node);
+        let outer = std::mem::replace(&mut self.enclosing, node);
+        check(self);
+        self.enclosing = outer;
+    }
+
+    fn collect_items(&mut self) {
+        for (position, declaration) in self.linked.program.declarations.iter().enumerate() {
+            let Some(module) = self.linked.origins[position] else {
+                continue;
+            };
+            let (name, is_public, node, importable) = match declaration {
+                Declaration::Function(function) => (
+                    &function.name,
+                    function.is_public,
+                    self.index.expect_id(function),
+                    true,
+                ),
+                Declaration::Record(record) => {
+                    self.records.insert(&record.name, record);
+                    (
+                        &record.name,
+                        record.is_public,
+                        self.index.expect_id(record),
+                        true,
+                    )
+                }
+                Declaration::Union(union) => (
+                    &union.name,
+                    union.is_public,
+                    self.index.expect_id(union),
+                    true,
+                ),
+                Declaration::Const(constant) => (
+                    &constant.name,
+                    constant.is_public,
+                    self.index.expect_id(constant),
+                    true,
+                ),
+                Declaration::Statement(
+                    statement @ Statement {
+                        kind: StatementKind::VariableDeclaration { name, .. },
+                        ..
+                    },
+                ) => (name, false, self.index.expect_id(statement), false),
+                Declaration::Patch(_) | Declaration::Import(_) | Declaration::Statement(_) => {
+                    continue;
+                }
+            };
+            self.owners.insert(node, name);
+            let mut names = vec![(unqualified_name(name), node)];
+            if let Declaration::Union(union) = declaration {
+                for variant in &union.variants {
+                    let variant_node = self.index.expect_id(variant);
+                    self.owners.insert(variant_node, name);
+                    names.push((unqualified_name(&variant.name), variant_node));
+                }
+            }
+            for &(declared, node) in &names {
+                self.declared[module].entry(declared).or_insert(node);
+            }
+            self.items.entry(name).or_insert(Item {
+                module,
+                is_public,
+                node,
+                importable,
+                names: names.into_iter().map(|(name, _)| name).collect(),
+            });
+        }
+    }
+
+    fn check_imports(&mut self) {
+        for (position, declaration) in self.linked.program.declarations.iter().enumerate() {
+            let Declaration::Import(import) = declaration else {
+                continue;
+            };
+            let node = self.index.expect_id(declaration);
+            let Some(target) = self.linked.modules.iter().position(|m| *m == import.module) else {
+                self.errors.push(ModuleError::UnknownModule {
+                    module: import.module.clone(),
+                    node,
+                });
+                continue;
+            };
+            let Some(module) = self.linked.origins[position] else {
+                continue;
+            };
+            if target != module {
+                self.dependencies[module].push((target, node));
+            }
+            for name in &import.items {
+                // The item has its qualified name if another module declares the name too.
+                let item = [qualified_name(&import.module, name), name.clone()]
+                    .iter()
+                    .find_map(|key| self.items.get_key_value(key.as_str()))
+                    .filter(|(_, item)| item.module == target && item.importable)
+                    .map(|(&key, item)| (key, item.is_public, item.node));
+                let error = match item {
+                    Some((key, true, _)) => match self.import(module, target, key, node) {
+                        Some(error) => error,
+                        None => continue,
+                    },
+                    Some((_, false, declaration)) => ModuleError::PrivateItem {
+                        module: import.module.clone(),
+                        item: name.clone(),
+                        node,
+                        declaration,
+                    },
+                    None => ModuleError::UnknownItem {
+                        module: import.module.clone(),
+                        item: name.clone(),
+                        node,
+                    },
+                };
+                self.errors.push(error);
+            }
+        }
+    }
+
+    /// Import the item `key` of module `target` into `module` with the import at `node`, unless
+    /// a name it brings is one `module` declares itself or imports from another module.
+    fn import(
+        &mut self,
+        module: usize,
+        target: usize,
+        key: &'l str,
+        node: NodeId,
+    ) -> Option<ModuleError> {
+        let names = &self.items[key].names;
+        for &name in names {
+            if let Some(&declaration) = self.declared[module].get(name) {
+                return Some(ModuleError::ImportClash {
+                    name: name.to_string(),
+                    module: self.linked.modules[target].clone(),
+                    node,
+                    declaration,
+                });
+            }
+            if let Some(&(first_module, first)) = self.imported_names[module].get(name)
+                && first_module != target
+            {
+                return Some(ModuleError::ConflictingImport {
+                    name: name.to_string(),
+                    first_module: self.linked.modules[first_module].clone(),
+                    module: self.linked.modules[target].clone(),
+                    node,
+                    first,
+                });
+            }
+        }
+        for &name in names {
+            self.imported_names[module].insert(name, (target, node));
+        }
+        self.imported[module].insert(key);
+        None
+    }
+
+    /// Report each cycle of imports once, by a depth-first search over the modules. The search
+    /// keeps its own stack, so a long chain of imports cannot overflow the thread's.
+    fn check_cycles(&mut self) {
+        #[derive(Clone, Copy, PartialEq)]
+        enum State {
+            New,
+            Open,
+            Done,
+        }
+        let mut states = vec![State::New; self.linked.modules.len()];
+        for start in 0..self.linked.modules.len() {
+            if states[start] != State::New {
+                continue;
+            }
+            // Each open module with the position of the next import to follow.
+            let mut path = vec![(start, 0)];
+            states[start] = State::Open;
+            while let Some((module, next)) = path.last_mut() {
+                let Some(&(target, node)) = self.dependencies[*module].get(*next) else {
+                    states[*module] = State::Done;
+                    path.pop();
+                    continue;
+                };
+                *next += 1;
+                match states[target] {
+                    State::New => {
+                        states[target] = State::Open;
+                        path.push((target, 0));
+                    }
+                    State::Open => {
+                        let from = path.iter().position(|(open, _)| *open == target);
+                        let cycle = path[from.unwrap_or(0)..]
+                            .iter()
+                            .map(|(open, _)| open)
+                            .chain([&target])
+                            .map(|&open| self.linked.modules[open].clone())
+                            .collect();
+                        self.errors.push(ModuleError::ImportCycle { cycle, node });
+                    }
+                    State::Done => {}
+                }
+            }
+        }
+    }
+
+    /// Report `name`, used at `node`, if it belongs to an item of another module that the
+    /// current one does not import: `item` itself, or the union of a variant.
+    fn check_visible(&mut self, item: &str, name: &str, node: NodeId) {
+        let Some(declared) = self.items.get(item) else {
+            return;
+        };
+        if declared.module != self.current && !self.imported[self.current].contains(item) {
+            self.errors.push(ModuleError::NotImported {
+                name: unqualified_name(name).to_string(),
+                module: self.linked.modules[declared.module].clone(),
+                node,
+            });
+        }
+    }
+
+    /// Check that the symbol `name` refers to at `node`, if it is top-level, is visible.
+    fn check_reference(&mut self, name: &str, node: NodeId) {
+        let definition = self
+            .resolution
+            .symbol_of(node)
+            .and_then(|symbol| self.resolution.symbols.symbol(symbol).node)
+            .and_then(|definition| self.owners.get(&definition));
+        if let Some(&owner) = definition {
+            self.check_visible(owner, name, node);
+        }
+    }
+
+    /// Report each of `fields` that `record_type` keeps private to another module.
+    fn check_fields<'f>(
+        &mut self,
+        record_type: &Type,
+        fields: impl IntoIterator<Item = &'f str>,
+        node: NodeId,
+    ) {
+        let (Type::Named(name) | Type::Generic { name, .. }) = record_type else {
+            return;
+        };
+        let (Some(&record), Some(item)) = (
+            self.records.get(name.as_str()),
+            self.items.get(name.as_str()),
+        ) else {
+            return;
+        };
+        if item.module == self.current {
+            return;
+        }
+        let module = self.linked.modules[item.module].clone();
+        for field in fields {
+            let private = record
+                .fields
+                .iter()
+                .any(|declared| declared.name == field && !declared.is_public);
+            if private {
+                self.errors.push(ModuleError::PrivateField {
+                    record: unqualified_name(&record.name).to_string(),
+                    field: field.to_string(),
+                    module: module.clone(),
+                    node,
+                });
+            }
+        }
+    }
+}
+
+impl Visitor for Checker<'_, '_> {
+    fn visit_declaration(&mut self, declaration: &Declaration) {
+        self.within(declaration, |checker| {
+            walk_declaration(checker, declaration)
+        });
+    }
+
+    fn visit_parameter(&mut self, parameter: &Parameter) {
+        self.within(parameter, |checker| walk_parameter(checker, parameter));
+    }
+
+    fn visit_record_field(&mut self, field: &RecordField) {
+        self.within(field, |checker| walk_record_field(checker, field));
+    }
+
+    fn visit_union_variant(&mut self, variant: &UnionVariant) {
+        self.within(variant, |checker| walk_union_variant(checker, variant));
+    }
+
+    fn visit_statement(&mut self, statement: &Statement) {
+        self.within(statement, |checker| walk_statement(checker, statement));
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        self.within(expression, |checker| checker.check_expression(expression));
+    }
+
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        self.within(pattern, |checker| checker.check_pattern(pattern));
+    }
+
+    fn visit_type(&mut self, ty: &Type) {
+        if let Type::Named(name) | Type::Generic { name, .. } = ty {
+            self.check_visible(name, name, self.enclosing);
+        }
+        walk_type(self, ty);
+    }
+}
+
+impl Checker<'_, '_> {
+    fn check_expression(&mut self, expression: &Expression) {
+        let node = self.index.expect_id(expression);
+        match &expression.kind {
+            ExpressionKind::Identifier(name) => self.check_reference(name, node),
+            ExpressionKind::RecordAccess { record, field } => {
+                let record_type = self
+                    .types
+                    .expression(self.index.expect_id(record.as_ref()))
+                    .cloned();
+                if let Some(mut record_type) = record_type {
+                    while let Type::Reference { ref_type, .. } = record_type {
+                        record_type = *ref_type;
+                    }
+                    self.check_fields(&record_type, [field.as_str()], node);
+                }
+            }
+            ExpressionKind::RecordLiteral {
+                record_type,
+                fields,
+                ..
+            } => {
+                let names = fields.iter().map(|(field, _)| field.as_str());
+                self.check_fields(record_type, names, node);
+            }
+            _ => {}
+        }
+        walk_expression(self, expression);
+    }
+
+    fn check_pattern(&mut self, pattern: &Pattern) {
+        let node = self.index.expect_id(pattern);
+        match &pattern.kind {
+            PatternKind::Identifier(name) | PatternKind::Union { variant: name, .. } => {
+                self.check_reference(name, node)
+            }
+            PatternKind::Record {
+                record_type,
+                fields,
+            } => {
+                let names = fields.iter().map(|(field, _)| field.as_str());
+                self.check_fields(record_type, names, node);
+            }
+            _ => {}
+        }
+        walk_pattern(self, pattern);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolve::resolve;
+    use crate::typeck::check;
+    use parser::parse;
+    use test_case::test_case;
+
+    /// The module errors of a program made of `modules`, given as names and sources.
+    fn module_errors(modules: &[(&str, &str)]) -> Vec<String> {
+        let linked = Linked::new(
+            modules
+                .iter()
+                .map(|(name, source)| Module {
+                    name: name.to_string(),
+                    program: parse(source).expect("Parse error"),
+                })
+                .collect(),
+        );
+        let index = NodeIndex::new(&linked.program);
+        let resolution = resolve(&linked.program, &index);
+        let types = check(&linked.program, &index, &resolution).types;
+        check_modules(&linked, &index, &resolution, &types)
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    const GEOMETRY: &str = "
+        pub record point { pub x: f64; pub y: f64; id: i32; }
+        pub union shape = circle(f64) | square(f64);
+        pub fn origin() -> point { point { x: 0.0, y: 0.0, id: 0 } }
+        fn helper() -> i32 { 1 }
+        pub const f64 pi = 3.14;
+        cache = 0;
+    ";
+
+    #[test_case(
+        "import geometry::{point, origin}; fn main() { p = origin(); print(p.x); q = point { x: 1.0, y: 2.0, ..p }; }" ;
+        "public items and fields"
+    )]
+    #[test_case(
+        "import geometry::shape; fn area(shape s) -> f64 { when s { circle(r): r * r; square(side): side * side; } }" ;
+        "union variants come with the union"
+    )]
+    #[test_case("import geometry::pi; fn main() { print(pi); }" ; "constant")]
+    #[test_case("fn main() { helper = 2; print(helper); }" ; "own name shadows nothing imported")]
+    fn test_valid(main: &str) {
+        assert_eq!(
+            module_errors(&[("geometry", GEOMETRY), ("main", main)]),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test_case("import shapes::point;", "Cannot find module 'shapes'" ; "unknown module")]
+    #[test_case("import geometry::line;", "Module 'geometry' has no item 'line'" ; "unknown item")]
+    #[test_case("import geometry::cache;", "Module 'geometry' has no item 'cache'" ; "variable")]
+    #[test_case("import geometry::helper;", "'helper' exists in module 'geometry' but is private" ; "private function")]
+    #[test_case(
+        "fn main() { print(origin()); }",
+        "'origin' belongs to module 'geometry' and is not imported" ;
+        "not imported"
+    )]
+    #[test_case(
+        "fn main() { print(cache); }",
+        "'cache' belongs to module 'geometry' and is not imported" ;
+        "top-level variable"
+    )]
+    #[test_case(
+        "fn f(point p) {}",
+        "'point' belongs to module 'geometry' and is not imported" ;
+        "type name"
+    )]
+    #[test_case(
+        "import geometry::origin; fn main() { print(origin().id); }",
+        "Field 'id' of 'point' exists but is private to module 'geometry'" ;
+        "private field"
+    )]
+    #[test_case(
+        "import geometry::point; p = point { x: 1.0, y: 1.0, id: 7 };",
+        "Field 'id' of 'point' exists but is private to module 'geometry'" ;
+        "private field in a literal"
+    )]
+    #[test_case(
+        "import geometry::point; fn f(point& p) -> i32 { when p { point { x, y, id }: id; } }",
+        "Field 'id' of 'point' exists but is private to module 'geometry'" ;
+        "private field in a pattern"
+    )]
+    #[test_case(
+        "fn f() -> f64 { when circle(1.0) { circle(r): r; _: 0.0; } }",
+        "'circle' belongs to module 'geometry' and is not imported" ;
+        "variant"
+    )]
+    #[test_case(
+        "import geometry::origin; fn origin() {}",
+        "Cannot import 'origin' from module 'geometry' because this module declares it too" ;
+        "import of an own name"
+    )]
+    #[test_case(
+        "import geometry::shape; fn circle() {}",
+        "Cannot import 'circle' from module 'geometry' because this module declares it too" ;
+        "import of an own variant name"
+    )]
+    fn test_invalid(main: &str, expected: &str) {
+        let errors = module_errors(&[("geometry", GEOMETRY), ("main", main)]);
+        assert!(!errors.is_empty());
+        assert!(errors.iter().all(|error| error == expected), "{:?}", errors);
+    }
+
+    #[test_case(
+        &[("a", "import b::g; pub fn f() {}"), ("b", "import a::f; pub fn g() {}")],
+        &["Modules import each other in a cycle: a -> b -> a"] ;
+        "two modules"
+    )]
+    #[test_case(
+        &[
+            ("a", "import b::g; pub fn f() {}"),
+            ("b", "import c::h; pub fn g() {}"),
+            ("c", "import a::f; pub fn h() {}"),
+        ],
+        &["Modules import each other in a cycle: a -> b -> c -> a"] ;
+        "three modules"
+    )]
+    #[test_case(
+        &[
+            ("main", "import a::f;"),
+            ("a", "import b::g; pub fn f() {}"),
+            ("b", "import a::f; pub fn g() {}"),
+        ],
+        &["Modules import each other in a cycle: a -> b -> a"] ;
+        "cycle behind an import"
+    )]
+    #[test_case(
+        &[("a", "import b::g; pub fn f() {}"), ("b", "pub fn g() {}"), ("c", "import b::g;")],
+        &[] ;
+        "shared import"
+    )]
+    fn test_import_cycles(modules: &[(&str, &str)], expected: &[&str]) {
+        assert_eq!(module_errors(modules), expected);
+    }
+
+    #[test_case("import a::f; import b::f;", &["'f' is imported from both module 'a' and module 'b'"] ; "two modules")]
+    #[test_case("import a::f; import a::f;", &[] ; "one module twice")]
+    fn test_conflicting_imports(main: &str, expected: &[&str]) {
+        let modules = [
+            ("main", main),
+            ("a", "pub fn f() {}"),
+            ("b", "pub fn f() {}"),
+        ];
+        assert_eq!(module_errors(&modules), expected);
+    }
+
+    /// The ids of the identifiers in `declaration` that name `name`, as its module writes it.
+    fn uses(declaration: &Declaration, index: &NodeIndex, name: &str) -> Vec<NodeId> {
+        struct Uses<'a> {
+            index: &'a NodeIndex,
+            name: &'a str,
+            ids: Vec<NodeId>,
+        }
+
+        impl Visitor for Uses<'_> {
+            fn visit_expression(&mut self, expression: &Expression) {
+                if let ExpressionKind::Identifier(name) = &expression.kind
+                    && unqualified_name(name) == self.name
+                {
+                    self.ids.push(self.index.expect_id(expression));
+                }
+                walk_expression(self, expression);
+            }
+        }
+
+        let mut uses = Uses {
+            index,
+            name,
+            ids: Vec::new(),
+        };
+        uses.visit_declaration(declaration);
+        uses.ids
+    }
+
+    #[test]
+    fn test_private_names_of_modules_stay_apart() {
+        let linked = Linked::new(vec![
+            Module {
+                name: "a".to_string(),
+                program: parse("fn helper() -> i32 { 1 } pub fn one() -> i32 { helper() }").unwrap(),
+            },
+            Module {
+                name: "b".to_string(),
+                program: parse("import a::one; fn helper() -> i32 { 2 } fn main() { print(one() + helper()); }")
+                    .unwrap(),
+            },
+        ]);
+        let index = NodeIndex::new(&linked.program);
+        let resolution = resolve(&linked.program, &index);
+        let types = check(&linked.program, &index, &resolution).types;
+
+        assert_eq!(resolution.errors, []);
+        assert_eq!(check_modules(&linked, &index, &resolution, &types), []);
+        let declarations = &linked.program.declarations;
+        for (helper, caller) in [(0, 1), (3, 4)] {
+            let Declaration::Function(helper) = &declarations[helper] else {
+                panic!("expected a function, found {:?}", declarations[helper]);
+            };
+            let uses = uses(&declarations[caller], &index, "helper");
+            let definition = resolution
+                .symbol_of(uses[0])
+                .and_then(|symbol| resolution.symbols.symbol(symbol).node);
+            assert_eq!(uses.len(), 1);
+            assert_eq!(definition, index.id(helper));
+        }
+    }
+
+    /// A fresh directory holding `files`, given as module names and sources.
+    fn module_directory(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("cv-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+        for (name, source) in files {
+            std::fs::write(directory.join(name).with_extension(EXTENSION), source).unwrap();
+        }
+        directory
+    }
+
+    #[test]
+    fn test_load_follows_imports_once() {
+        let directory = module_directory(
+            "load",
+            &[
+                ("main", "import a::f; import missing::x;"),
+                ("a", "import b::g; pub fn f() {}"),
+                ("b", "import a::f; pub fn g() {}"),
+                ("unused", "fn h() {}"),
+            ],
+        );
+        let modules = load(&directory, "main").unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        let names: Vec<_> = modules.iter().map(|module| module.name.as_str()).collect();
+        assert_eq!(names, ["main", "a", "b"]);
+    }
+
+    #[test]
+    fn test_load_errors() {
+        let directory = module_directory("load-errors", &[("main", "import a::f;"), ("a", "fn {")]);
+        let missing = load(&directory, "nothing");
+        let invalid = load(&directory, "main");
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert!(matches!(missing, Err(LoadError::Io { module, .. }) if module == "nothing"));
+        assert!(matches!(invalid, Err(LoadError::Parse { module, .. }) if module == "a"));
+    }
+
+    #[test]
+    fn test_module_of_declaration() {
+        let linked = Linked::new(vec![
+            Module {
+                name: "a".to_string(),
+                program: parse("fn f() {} fn g() {}").unwrap(),
+            },
+            Module {
+                name: "b".to_string(),
+                program: parse("x = 1;").unwrap(),
+            },
+        ]);
+
+        assert_eq!(linked.program.declarations.len(), 3);
+        assert_eq!(linked.module_of(1), Some("a"));
+        assert_eq!(linked.module_of(2), Some("b"));
+    }
+
+    #[test]
+    fn test_prelude_is_visible_to_every_module() {
+        let linked = Linked::new(vec![
+            Module {
+                name: "a".to_string(),
+                program: parse("pub fn first() -> option<i32> { some(1) }").unwrap(),
+            },
+            Module {
+                name: "b".to_string(),
+                program: parse("import a::first; x = first().unwrapOr(none.unwrapOr(0));").unwrap(),
+            },
+        ])
+        .with_prelude();
+        let index = NodeIndex::new(&linked.program);
+        let resolution = resolve(&linked.program, &index);
+        let types = check(&linked.program, &index, &resolution).types;
+
+        assert_eq!(linked.module_of(0), None);
+        assert_eq!(
+            linked.module_of(linked.program.declarations.len() - 1),
+            Some("b")
+        );
+        assert_eq!(check_modules(&linked, &index, &resolution, &types), []);
+    }
+}
@@ -0,0 +1,748 @@
+//! Monomorphization: a copy of each generic function for every set of types it is used with.
+//!
+//! The type arguments of a call to a generic function are inferred by matching the types of its
+//! arguments against the declared parameter types. The first call with a new set of type
+//! arguments creates an instance: a copy of the function with the arguments substituted for its
+//! type parameters, named like the type it would be written as (`identity<i32>`). Every call with
+//! those arguments is renamed to call the instance. Instances are checked in turn, so a generic
+//! function that calls another one gets its own instances of it.
+//!
+//! Generic unions are instantiated for every set of concrete type arguments the program writes
+//! them with. Their variants keep their names, so the program still matches and builds values
+//! with the generic union's variants, and the instances describe the concrete layouts.
+//!
+//! A function or union that needs a bigger instance of itself each time, such as
+//! `fn f<T>(T x) { f([x]) }`, would never stop producing instances, so nesting instances stops at
+//! [`MAX_INSTANTIATION_DEPTH`].
+
+use crate::resolve::{Resolution, resolve};
+use crate::typeck::{TypeMap, check, is_concrete, patch_type_parameters};
+use parser::ast::{
+    ArraySize, Declaration, Expression, ExpressionKind, FunctionDeclaration, Literal,
+    PatchDeclaration, Program, Type, TypeParameter, UnionDeclaration,
+};
+use parser::node_id::{NodeId, NodeIds, NodeIndex};
+use parser::visit::{self, Visitor};
+use parser::visit_mut::{self, MutVisitor};
+use std::collections::{HashMap, HashSet};
+use thiserror::Error;
+
+/// How many instances deep an instance may be created from inside another one.
+pub const MAX_INSTANTIATION_DEPTH: usize = 32;
+
+#[derive(Debug, Error, PartialEq, Clone)]
+pub enum MonomorphizeError {
+    /// `node` is the generic declaration, in the program that was monomorphized.
+    #[error("Cannot infer the type arguments of '{generic}' where '{caller}' calls it")]
+    CannotInfer {
+        generic: String,
+        caller: String,
+        node: NodeId,
+    },
+    /// `node` is the generic declaration, in the program that was monomorphized.
+    #[error(
+        "Instantiating '{generic}' needs '{instance}', more than {MAX_INSTANTIATION_DEPTH} instances deep"
+    )]
+    RecursionLimit {
+        generic: String,
+        instance: String,
+        node: NodeId,
+    },
+}
+
+/// A generic declaration copied for one set of type arguments.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instance {
+    pub generic: String,
+    /// The type arguments in the order of the type parameters. A const parameter's argument is a
+    /// [`Type::ConstValue`].
+    pub type_arguments: Vec<Type>,
+    /// The name of the copy, such as `identity<i32>`.
+    pub name: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Monomorphized {
+    /// The program with an instance of each generic function for every set of type arguments
+    /// it is called with, and without the generic functions themselves.
+    pub program: Program,
+    /// An instance of each generic union for every set of type arguments the program writes.
+    pub unions: Vec<UnionDeclaration>,
+    /// Every instance created, functions and unions, in the order they were created.
+    pub instances: Vec<Instance>,
+    pub errors: Vec<MonomorphizeError>,
+}
+
+/// Instantiate the generic functions and unions of `program`.
+pub fn monomorphize(program: &Program) -> Monomorphized {
+    let input = NodeIndex::new(program);
+    let generic_node = |name: &str| {
+        program
+            .declarations
+            .iter()
+            .find_map(|declaration| match declaration {
+                Declaration::Function(function) if function.name == name => input.id(function),
+                Declaration::Union(union) if union.name == name => input.id(union),
+                _ => None,
+            })
+            .expect("every instance is of a generic declaration in the input")
+    };
+
+    let mut state = State::default();
+    let mut working = program.clone();
+    loop {
+        let (renames, instances) = state.instantiate_calls(&working);
+        if renames.is_empty() {
+            break;
+        }
+        Rename(renames).visit_program(&mut working);
+        let mut ids = NodeIds::after(&working);
+        for mut instance in instances {
+            ids.number_function(&mut instance);
+            working.declarations.push(Declaration::Function(instance));
+        }
+    }
+    working.declarations.retain(|declaration| {
+        !matches!(declaration, Declaration::Function(function) if !function.type_parameters.is_empty())
+    });
+    let unions = state.instantiate_unions(&working);
+
+    let errors = state
+        .errors
+        .into_iter()
+        .map(|error| match error {
+            Failure::CannotInfer { generic, caller } => MonomorphizeError::CannotInfer {
+                node: generic_node(&generic),
+                generic,
+                caller,
+            },
+            Failure::RecursionLimit { generic, instance } => MonomorphizeError::RecursionLimit {
+                node: generic_node(&generic),
+                generic,
+                instance,
+            },
+        })
+        .collect();
+    Monomorphized {
+        program: working,
+        unions,
+        instances: state.instances,
+        errors,
+    }
+}
+
+/// An error before it is located in the input program.
+#[derive(PartialEq, Eq, Hash)]
+enum Failure {
+    CannotInfer { generic: String, caller: String },
+    RecursionLimit { generic: String, instance: String },
+}
+
+#[derive(Default)]
+struct State {
+    /// How deep each instance is, by name: one more than the function or union that needed it.
+    depths: HashMap<String, usize>,
+    instances: Vec<Instance>,
+    errors: Vec<Failure>,
+    reported: HashSet<String>,
+}
+
+impl State {
+    fn report(&mut self, failure: Failure) {
+        let key = match &failure {
+            Failure::CannotInfer { generic, caller } => format!("{} {}", generic, caller),
+            Failure::RecursionLimit { instance, .. } => instance.clone(),
+        };
+        if self.reported.insert(key) {
+            self.errors.push(failure);
+        }
+    }
+
+    /// Find the calls to generic functions in `program` that can call an instance instead.
+    /// Returns the name each call's callee should be renamed to, by the id of the callee
+    /// expression, and the instances that do not exist yet.
+    fn instantiate_calls(
+        &mut self,
+        program: &Program,
+    ) -> (HashMap<NodeId, String>, Vec<FunctionDeclaration>) {
+        let index = NodeIndex::new(program);
+        let resolution = resolve(program, &index);
+        let types = check(program, &index, &resolution).types;
+        let generics = program
+            .declarations
+            .iter()
+            .filter_map(|declaration| match declaration {
+                Declaration::Function(function) if !function.type_parameters.is_empty() => {
+                    Some((index.id(function)?, function))
+                }
+                _ => None,
+            })
+            .collect();
+
+        let mut calls = Calls {
+            index: &index,
+            resolution: &resolution,
+            types: &types,
+            generics: &generics,
+            caller: None,
+            found: Vec::new(),
+        };
+        calls.visit_program(program);
+
+        let mut renames = HashMap::new();
+        let mut instances = Vec::new();
+        let existing: HashSet<&str> = program
+            .declarations
+            .iter()
+            .filter_map(|declaration| match declaration {
+                Declaration::Function(function) => Some(function.name.as_str()),
+                _ => None,
+            })
+            .collect();
+        let mut created: HashSet<String> = HashSet::new();
+        for call in calls.found {
+            let caller = call.caller.unwrap_or_else(|| "top level".to_string());
+            let Some(arguments) = call.type_arguments else {
+                self.report(Failure::CannotInfer {
+                    generic: call.generic.name.clone(),
+                    caller,
+                });
+                continue;
+            };
+            let name = instance_name(&call.generic.name, &arguments);
+            if !existing.contains(name.as_str()) && !created.contains(&name) {
+                let depth = self.depths.get(&caller).map_or(1, |depth| depth + 1);
+                if depth > MAX_INSTANTIATION_DEPTH {
+                    self.report(Failure::RecursionLimit {
+                        generic: call.generic.name.clone(),
+                        instance: name,
+                    });
+                    continue;
+                }
+                self.depths.insert(name.clone(), depth);
+                self.instances.push(Instance {
+                    generic: call.generic.name.clone(),
+                    type_arguments: arguments.clone(),
+                    name: name.clone(),
+                });
+                instances.push(instantiate_function(call.generic, &arguments, &name));
+                created.insert(name.clone());
+            }
+            renames.insert(call.callee, name);
+        }
+        (renames, instances)
+    }
+
+    /// The instances of the generic unions in `program` for the types it writes.
+    fn instantiate_unions(&mut self, program: &Program) -> Vec<UnionDeclaration> {
+        let generics: HashMap<&str, &UnionDeclaration> = program
+            .declarations
+            .iter()
+            .filter_map(|declaration| match declaration {
+                Declaration::Union(union) if !union.type_parameters.is_empty() => {
+                    Some((union.name.as_str(), union))
+                }
+                _ => None,
+            })
+            .collect();
+        let mut written = WrittenTypes::default();
+        written.visit_program(program);
+
+        let mut pending: Vec<(Type, usize)> = written.types.into_iter().map(|ty| (ty, 1)).collect();
+        let mut created: HashSet<String> = HashSet::new();
+        let mut unions = Vec::new();
+        while let Some((ty, depth)) = pending.pop() {
+            let Type::Generic { name, parameters } = &ty else {
+                continue;
+            };
+            let Some(generic) = generics.get(name.as_str()) else {
+                continue;
+            };
+            let instance = ty.to_string();
+            if !is_concrete(&ty) || created.contains(&instance) {
+                continue;
+            }
+            if depth > MAX_INSTANTIATION_DEPTH {
+                self.report(Failure::RecursionLimit {
+                    generic: name.clone(),
+                    instance,
+                });
+                continue;
+            }
+            let mut union = (*generic).clone();
+            union.name = instance.clone();
+            union.type_parameters.clear();
+            Substitute::new(&generic.type_parameters, parameters).visit_union(&mut union);
+
+            let mut nested = WrittenTypes::default();
+            visit::Visitor::visit_union(&mut nested, &union);
+            pending.extend(nested.types.into_iter().map(|ty| (ty, depth + 1)));
+            self.instances.push(Instance {
+                generic: name.clone(),
+                type_arguments: parameters.clone(),
+                name: instance.clone(),
+            });
+            created.insert(instance);
+            unions.push(union);
+        }
+        unions
+    }
+}
+
+fn instance_name(generic: &str, arguments: &[Type]) -> String {
+    Type::Generic {
+        name: generic.to_string(),
+        parameters: arguments.to_vec(),
+    }
+    .to_string()
+}
+
+fn instantiate_function(
+    generic: &FunctionDeclaration,
+    arguments: &[Type],
+    name: &str,
+) -> FunctionDeclaration {
+    let mut instance = generic.clone();
+    instance.name = name.to_string();
+    instance.type_parameters.clear();
+    Substitute::new(&generic.type_parameters, arguments).visit_function(&mut instance);
+    instance
+}
+
+/// A call to a generic function.
+struct Call<'p> {
+    generic: &'p FunctionDeclaration,
+    /// The callee expression, which is renamed to call the instance.
+    callee: NodeId,
+    /// The function the call is in, or `None` at the top level.
+    caller: Option<String>,
+    /// The inferred type arguments, or `None` if some could not be inferred.
+    type_arguments: Option<Vec<Type>>,
+}
+
+struct Calls<'p, 'i> {
+    index: &'i NodeIndex,
+    resolution: &'i Resolution,
+    types: &'i TypeMap,
+    generics: &'i HashMap<NodeId, &'p FunctionDeclaration>,
+    caller: Option<String>,
+    found: Vec<Call<'p>>,
+}
+
+impl<'p> Calls<'p, '_> {
+    fn type_of(&self, expression: &Expression) -> Type {
+        self.index
+            .id(expression)
+            .and_then(|node| self.types.expression(node))
+            .cloned()
+            .unwrap_or(Type::Inferred)
+    }
+
+    /// The type arguments of a call to `generic` with `arguments`, if all of them are known.
+    fn infer(&self, generic: &FunctionDeclaration, arguments: &[Expression]) -> Option<Vec<Type>> {
+        let mut bindings = HashMap::new();
+        for (parameter, argument) in generic.params.iter().zip(arguments) {
+            bind(
+                &generic.type_parameters,
+                &parameter.param_type,
+                &self.type_of(argument),
+                &mut bindings,
+            );
+        }
+        generic
+            .type_parameters
+            .iter()
+            .map(|parameter| bindings.remove(parameter.name.as_str()))
+            .collect::<Option<Vec<Type>>>()
+            .filter(|arguments| arguments.iter().all(is_concrete))
+    }
+}
+
+impl Visitor for Calls<'_, '_> {
+    fn visit_patch(&mut self, patch: &PatchDeclaration) {
+        // Nor are the types inside the methods of a patch for a generic type.
+        if patch_type_parameters(patch).is_empty() {
+            visit::walk_patch(self, patch);
+        }
+    }
+
+    fn visit_function(&mut self, function: &FunctionDeclaration) {
+        // The types inside a generic function are not known until it is instantiated.
+        if !function.type_parameters.is_empty() {
+            return;
+        }
+        let outer = self.caller.replace(function.name.clone());
+        visit::walk_function(self, function);
+        self.caller = outer;
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        if let ExpressionKind::FunctionCall {
+            function: callee,
+            arguments,
+        } = &expression.kind
+            && let Some(callee) = self.index.id(callee.as_ref())
+            && let Some(symbol) = self.resolution.symbol_of(callee)
+            && let Some(node) = self.resolution.symbols.symbol(symbol).node
+            && let Some(&generic) = self.generics.get(&node)
+        {
+            self.found.push(Call {
+                generic,
+                callee,
+                caller: self.caller.clone(),
+                type_arguments: self.infer(generic, arguments),
+            });
+        }
+        visit::walk_expression(self, expression);
+    }
+}
+
+/// Bind the type parameters that `parameter` mentions to the parts of `argument` in the same
+/// positions. A parameter keeps the first type it is bound to.
+pub(crate) fn bind(
+    parameters: &[TypeParameter],
+    parameter: &Type,
+    argument: &Type,
+    bindings: &mut HashMap<String, Type>,
+) {
+    let is_parameter = |name: &str| parameters.iter().any(|parameter| parameter.name == name);
+    match (parameter, argument) {
+        (_, Type::Inferred) => {}
+        (Type::Named(name), argument) if is_parameter(name) => {
+            bindings
+                .entry(name.clone())
+                .or_insert_with(|| argument.clone());
+        }
+        (
+            Type::Reference {
+                ref_type: parameter,
+                ..
+            },
+            Type::Reference {
+                ref_type: argument, ..
+            },
+        )
+        | (Type::ArrayList(parameter), Type::ArrayList(argument)) => {
+            bind(parameters, parameter, argument, bindings)
+        }
+        (
+            Type::FixedArray {
+                element_type: parameter,
+                size,
+            },
+            Type::FixedArray {
+                element_type: argument,
+                size: argument_size,
+            },
+        ) => {
+            if let (ArraySize::Parameter(name), ArraySize::Literal(length)) = (size, argument_size)
+                && is_parameter(name)
+            {
+                bindings
+                    .entry(name.clone())
+                    .or_insert(Type::ConstValue(*length));
+            }
+            bind(parameters, parameter, argument, bindings);
+        }
+        (
+            Type::Generic {
+                name,
+                parameters: type_parameters,
+            },
+            Type::Generic {
+                name: argument_name,
+                parameters: type_arguments,
+            },
+        ) if name == argument_name => {
+            for (parameter, argument) in type_parameters.iter().zip(type_arguments) {
+                bind(parameters, parameter, argument, bindings);
+            }
+        }
+        (
+            Type::Function {
+                param_types,
+                return_type,
+            },
+            Type::Function {
+                param_types: argument_types,
+                return_type: argument_return,
+            },
+        ) => {
+            for (parameter, argument) in param_types.iter().zip(argument_types) {
+                bind(parameters, parameter, argument, bindings);
+            }
+            if let (Some(parameter), Some(argument)) = (return_type, argument_return) {
+                bind(parameters, parameter, argument, bindings);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Renames the callees found by [`Calls`], by their id.
+struct Rename(HashMap<NodeId, String>);
+
+impl MutVisitor for Rename {
+    fn visit_expression(&mut self, expression: &mut Expression) {
+        if let Some(name) = self.0.get(&expression.id) {
+            expression.kind = ExpressionKind::Identifier(name.clone());
+        }
+        visit_mut::walk_expression(self, expression);
+    }
+}
+
+/// Replaces type parameters with type arguments, and const parameters used as values with
+/// their value.
+struct Substitute {
+    types: HashMap<String, Type>,
+}
+
+impl Substitute {
+    fn new(parameters: &[TypeParameter], arguments: &[Type]) -> Self {
+        Substitute {
+            types: parameters
+                .iter()
+                .zip(arguments)
+                .map(|(parameter, argument)| (parameter.name.clone(), argument.clone()))
+                .collect(),
+        }
+    }
+
+    fn constant(&self, name: &str) -> Option<usize> {
+        match self.types.get(name) {
+            Some(Type::ConstValue(value)) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+impl MutVisitor for Substitute {
+    fn visit_type(&mut self, ty: &mut Type) {
+        match ty {
+            Type::Named(name) => {
+                if let Some(argument) = self.types.get(name.as_str()) {
+                    *ty = argument.clone();
+                }
+            }
+            Type::FixedArray { size, .. } => {
+                if let ArraySize::Parameter(name) = size
+                    && let Some(value) = self.constant(name)
+                {
+                    *size = ArraySize::Literal(value);
+                }
+            }
+            _ => {}
+        }
+        visit_mut::walk_type(self, ty);
+    }
+
+    fn visit_expression(&mut self, expression: &mut Expression) {
+        if let ExpressionKind::Identifier(name) = &expression.kind
+            && let Some(value) = self.constant(name)
+        {
+            expression.kind = ExpressionKind::Literal(Literal::Integer(value as i128));
+        }
+        visit_mut::walk_expression(self, expression);
+    }
+}
+
+/// The concrete generic types written in a program, outside generic declarations.
+#[derive(Default)]
+struct WrittenTypes {
+    types: Vec<Type>,
+}
+
+impl Visitor for WrittenTypes {
+    fn visit_declaration(&mut self, declaration: &Declaration) {
+        let generic = match declaration {
+            Declaration::Function(function) => !function.type_parameters.is_empty(),
+            Declaration::Record(record) => !record.type_parameters.is_empty(),
+            Declaration::Union(union) => !union.type_parameters.is_empty(),
+            Declaration::Patch(patch) => {
+                !is_concrete(&patch.target_type)
+                    || matches!(patch.target_type, Type::Generic { .. })
+            }
+            _ => false,
+        };
+        if !generic {
+            visit::walk_declaration(self, declaration);
+        }
+    }
+
+    fn visit_type(&mut self, ty: &Type) {
+        if matches!(ty, Type::Generic { .. }) && is_concrete(ty) {
+            self.types.push(ty.clone());
+        }
+        visit::walk_type(self, ty);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::parse;
+
+    fn function_names(program: &Program) -> Vec<String> {
+        program
+            .declarations
+            .iter()
+            .filter_map(|declaration| match declaration {
+                Declaration::Function(function) => Some(function.name.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_one_instance_per_type_argument_set() {
+        let program = parse(
+            "fn identity<T>(T x) -> T { x }
+             fn main() { a = identity(1); b = identity(\"s\"); c = identity(2); }",
+        )
+        .unwrap();
+        let monomorphized = monomorphize(&program);
+
+        assert_eq!(monomorphized.errors, []);
+        assert_eq!(
+            function_names(&monomorphized.program),
+            ["main", "identity<i32>", "identity<string>"]
+        );
+        assert_eq!(
+            monomorphized.instances[0],
+            Instance {
+                generic: "identity".to_string(),
+                type_arguments: vec![Type::I32],
+                name: "identity<i32>".to_string(),
+            }
+        );
+        let source = monomorphized.program.to_source();
+        assert!(source.contains("a = identity<i32>(1);"), "{}", source);
+        assert!(source.contains("c = identity<i32>(2);"), "{}", source);
+        assert!(
+            source.contains("fn identity<string>(string x) -> string"),
+            "{}",
+            source
+        );
+    }
+
+    #[test]
+    fn test_instances_instantiate_their_calls() {
+        let program = parse(
+            "fn wrap<T>(T x) -> arrayList<T> { [x] }
+             fn twice<T>(T x) -> arrayList<T> { wrap(x) }
+             fn main() { xs = twice(true); }",
+        )
+        .unwrap();
+        let monomorphized = monomorphize(&program);
+
+        assert_eq!(monomorphized.errors, []);
+        assert_eq!(
+            function_names(&monomorphized.program),
+            ["main", "twice<bool>", "wrap<bool>"]
+        );
+    }
+
+    #[test]
+    fn test_recursive_instance_reuses_itself() {
+        let program = parse(
+            "fn count<T>(arrayList<T> xs, i32 n) -> i32 { if n == 0 { 0 } else { count(xs, n - 1) } }
+             fn main() { n = count([1.5], 3); }",
+        )
+        .unwrap();
+        let monomorphized = monomorphize(&program);
+
+        assert_eq!(monomorphized.errors, []);
+        assert_eq!(
+            function_names(&monomorphized.program),
+            ["main", "count<f64>"]
+        );
+    }
+
+    #[test]
+    fn test_const_parameters() {
+        let program = parse(
+            "fn size<N: usize>(fixedArray<i32, N> xs) -> usize { N }
+             fn main() { fixedArray<i32, 3> xs = [1, 2, 3]; n = size(xs); }",
+        )
+        .unwrap();
+        let monomorphized = monomorphize(&program);
+
+        assert_eq!(monomorphized.errors, []);
+        let source = monomorphized.program.to_source();
+        assert!(
+            source.contains("fn size<3>(fixedArray<i32, 3> xs) -> usize {\n    3\n}"),
+            "{}",
+            source
+        );
+    }
+
+    #[test]
+    fn test_infinite_instantiation_stops() {
+        let program = parse(
+            "fn nest<T>(T x) -> i32 { nest([x]) }
+             fn main() { n = nest(1); }",
+        )
+        .unwrap();
+        let index = NodeIndex::new(&program);
+        let monomorphized = monomorphize(&program);
+
+        assert_eq!(monomorphized.errors.len(), 1);
+        let MonomorphizeError::RecursionLimit { generic, node, .. } = &monomorphized.errors[0]
+        else {
+            panic!("expected a recursion limit error");
+        };
+        assert_eq!(generic, "nest");
+        let Declaration::Function(nest) = &program.declarations[0] else {
+            panic!("expected a function");
+        };
+        assert_eq!(Some(*node), index.id(nest));
+        assert_eq!(monomorphized.instances.len(), MAX_INSTANTIATION_DEPTH);
+    }
+
+    #[test]
+    fn test_uninferable_arguments() {
+        let program = parse(
+            "fn empty<T>() -> arrayList<T> { [] }
+             fn main() { xs = empty(); }",
+        )
+        .unwrap();
+        let monomorphized = monomorphize(&program);
+
+        assert_eq!(
+            monomorphized
+                .errors
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            ["Cannot infer the type arguments of 'empty' where 'main' calls it"]
+        );
+    }
+
+    #[test]
+    fn test_union_instances() {
+        let program = parse(
+            "union option<T> = some(T) | none;
+             union tree<T> = leaf(T) | node(option<tree<T>>);
+             fn main(option<i32> a, tree<string> t) {}",
+        )
+        .unwrap();
+        let monomorphized = monomorphize(&program);
+
+        let mut names: Vec<&str> = monomorphized
+            .unions
+            .iter()
+            .map(|union| union.name.as_str())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            ["option<i32>", "option<tree<string>>", "tree<string>"]
+        );
+        let tree = monomorphized
+            .unions
+            .iter()
+            .find(|union| union.name == "tree<string>")
+            .unwrap();
+        assert_eq!(tree.variants[0].variant_type, Some(Type::String));
+    }
+}
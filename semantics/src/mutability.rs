@@ -0,0 +1,372 @@
+//! Mutability checking: only what is declared with `@` can change.
+//!
+//! A variable or parameter can be reassigned, have a compound assignment applied, or be borrowed
+//! with `&@` only if it is declared with `@`. Assigning to a field or element changes the
+//! variable that holds the record or array, unless the record or array is reached through a
+//! mutable reference (`T&@`), which allows changes on its own. Writing through `*r` needs `r` to
+//! be a mutable reference or a variable declared with `@`, as in `string& @buffer_ref`, so
+//! binding `&x` to such a variable, or passing it for such a parameter, needs `x` to be mutable
+//! too.
+
+use crate::resolve::Resolution;
+use crate::symbols::SymbolKind;
+use crate::typeck::TypeMap;
+use parser::ast::{
+    BinaryOperator, Declaration, Expression, ExpressionKind, FunctionDeclaration, Parameter,
+    Program, Statement, StatementKind, Type, UnaryOperator,
+};
+use parser::node_id::{Node, NodeId, NodeIndex};
+use parser::visit::{Visitor, walk_expression, walk_parameter, walk_statement};
+use std::collections::{HashMap, HashSet};
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Clone)]
+pub enum MutabilityError {
+    /// `node` is the assignment and `declaration` the node that declares `name`, where `@` is
+    /// missing.
+    #[error("Cannot assign to immutable {kind} '{name}'")]
+    AssignToImmutable {
+        name: String,
+        kind: SymbolKind,
+        node: NodeId,
+        declaration: Option<NodeId>,
+    },
+    /// `node` is the `&@` expression and `declaration` the node that declares `name`.
+    #[error("Cannot take a mutable reference to immutable {kind} '{name}'")]
+    MutableReferenceToImmutable {
+        name: String,
+        kind: SymbolKind,
+        node: NodeId,
+        declaration: Option<NodeId>,
+    },
+}
+
+impl MutabilityError {
+    pub fn node(&self) -> NodeId {
+        match self {
+            MutabilityError::AssignToImmutable { node, .. }
+            | MutabilityError::MutableReferenceToImmutable { node, .. } => *node,
+        }
+    }
+
+    /// The declaration of the immutable name, or `None` for a builtin.
+    pub fn declaration(&self) -> Option<NodeId> {
+        match self {
+            MutabilityError::AssignToImmutable { declaration, .. }
+            | MutabilityError::MutableReferenceToImmutable { declaration, .. } => *declaration,
+        }
+    }
+}
+
+/// Check every assignment and `&@` in `program`, using the types `types` gives its expressions
+/// to tell which writes go through a mutable reference.
+pub fn check_mutability(
+    program: &Program,
+    index: &NodeIndex,
+    resolution: &Resolution,
+    types: &TypeMap,
+) -> Vec<MutabilityError> {
+    check_mutability_after(program, index, resolution, types, &[])
+}
+
+/// Check `program` as [`check_mutability`] does, after the programs that made `declarations`,
+/// whose functions it may call.
+pub(crate) fn check_mutability_after<'p>(
+    program: &'p Program,
+    index: &NodeIndex,
+    resolution: &Resolution,
+    types: &TypeMap,
+    declarations: &[&'p Declaration],
+) -> Vec<MutabilityError> {
+    let mut functions = HashMap::new();
+    for declaration in declarations.iter().copied().chain(&program.declarations) {
+        match declaration {
+            Declaration::Function(function) => {
+                functions.insert(function.id, function);
+            }
+            Declaration::Patch(patch) => {
+                for method in &patch.methods {
+                    functions.insert(method.id, method);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut checker = Checker {
+        index,
+        resolution,
+        types,
+        functions,
+        mutable_parameters: HashSet::new(),
+        errors: Vec::new(),
+    };
+    checker.visit_program(program);
+    checker.errors
+}
+
+/// A change that needs its target to be mutable.
+#[derive(Clone, Copy)]
+enum Change {
+    Assignment,
+    MutableReference,
+}
+
+struct Checker<'p, 'i> {
+    index: &'i NodeIndex,
+    resolution: &'i Resolution,
+    types: &'i TypeMap,
+    /// Functions and patch methods, by the node that declares them.
+    functions: HashMap<NodeId, &'p FunctionDeclaration>,
+    /// The parameters declared with `@`.
+    mutable_parameters: HashSet<NodeId>,
+    errors: Vec<MutabilityError>,
+}
+
+impl Checker<'_, '_> {
+    /// Whether the value of `expression` is a mutable reference, so that what it points to can
+    /// change whatever holds it.
+    fn is_mutable_reference(&self, expression: &Expression) -> bool {
+        matches!(
+            self.types.expression(self.index.expect_id(expression)),
+            Some(Type::Reference {
+                is_mutable: true,
+                ..
+            })
+        )
+    }
+
+    /// Report `change` at `node` unless the place `target` names may change.
+    fn require_mutable<T: Node>(&mut self, target: &Expression, change: Change, node: &T) {
+        match &target.kind {
+            ExpressionKind::Identifier(name) => {
+                self.require_mutable_name(name, target, change, node)
+            }
+            ExpressionKind::Grouped(inner) => self.require_mutable(inner, change, node),
+            ExpressionKind::RecordAccess { record: owner, .. }
+            | ExpressionKind::IndexAccess {
+                collection: owner, ..
+            }
+            | ExpressionKind::Dereference(owner)
+            | ExpressionKind::UnaryOperation {
+                operator: UnaryOperator::Dereference,
+                operand: owner,
+            } if !self.is_mutable_reference(owner) => self.require_mutable(owner, change, node),
+            // Writes through a mutable reference, and to temporary values, are always allowed.
+            _ => {}
+        }
+    }
+
+    /// Report binding `value` to a variable or parameter declared with `@` if it is `&place` and
+    /// `place` may not change, since what the reference points to can be written through it.
+    fn require_mutable_referent(&mut self, value: &Expression) {
+        match &value.kind {
+            ExpressionKind::Grouped(inner) => self.require_mutable_referent(inner),
+            ExpressionKind::Reference {
+                is_mutable: false,
+                expression: referent,
+            }
+            | ExpressionKind::UnaryOperation {
+                operator: UnaryOperator::Reference,
+                operand: referent,
+            } => self.require_mutable(referent, Change::MutableReference, value),
+            _ => {}
+        }
+    }
+
+    /// Check the `arguments` passed to the function `function` declares for its parameters
+    /// declared with `@`.
+    fn arguments(&mut self, function: Option<NodeId>, arguments: &[Expression]) {
+        let Some(function) = function.and_then(|function| self.functions.get(&function)) else {
+            return;
+        };
+        for (parameter, argument) in function.params.iter().zip(arguments) {
+            if parameter.is_mutable && parameter.is_ref {
+                self.require_mutable_referent(argument);
+            }
+        }
+    }
+
+    fn require_mutable_name<T: Node>(
+        &mut self,
+        name: &str,
+        identifier: &Expression,
+        change: Change,
+        node: &T,
+    ) {
+        let Some(symbol_id) = self.resolution.symbol_of(self.index.expect_id(identifier)) else {
+            return;
+        };
+        let symbol = self.resolution.symbols.symbol(symbol_id);
+        let is_mutable = match symbol.kind {
+            SymbolKind::Variable { is_mutable } => is_mutable,
+            SymbolKind::Parameter => symbol
+                .node
+                .is_some_and(|parameter| self.mutable_parameters.contains(&parameter)),
+            _ => false,
+        };
+        if is_mutable {
+            return;
+        }
+        let (name, kind, node, declaration) = (
+            name.to_string(),
+            symbol.kind,
+            self.index.expect_id(node),
+            symbol.node,
+        );
+        self.errors.push(match change {
+            Change::Assignment => MutabilityError::AssignToImmutable {
+                name,
+                kind,
+                node,
+                declaration,
+            },
+            Change::MutableReference => MutabilityError::MutableReferenceToImmutable {
+                name,
+                kind,
+                node,
+                declaration,
+            },
+        });
+    }
+}
+
+impl Visitor for Checker<'_, '_> {
+    fn visit_parameter(&mut self, parameter: &Parameter) {
+        if parameter.is_mutable {
+            self.mutable_parameters
+                .insert(self.index.expect_id(parameter));
+        }
+        walk_parameter(self, parameter);
+    }
+
+    fn visit_statement(&mut self, statement: &Statement) {
+        match &statement.kind {
+            StatementKind::VariableDeclaration {
+                is_mutable: true,
+                value,
+                ..
+            } => self.require_mutable_referent(value),
+            StatementKind::Assignment {
+                target,
+                operator,
+                value,
+            } => {
+                self.require_mutable(target, Change::Assignment, statement);
+                if *operator == BinaryOperator::Assign
+                    && matches!(target.kind, ExpressionKind::Identifier(_))
+                {
+                    self.require_mutable_referent(value);
+                }
+            }
+            _ => {}
+        }
+        walk_statement(self, statement);
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        match &expression.kind {
+            ExpressionKind::Reference {
+                is_mutable: true,
+                expression: referent,
+            }
+            | ExpressionKind::UnaryOperation {
+                operator: UnaryOperator::MutableReference,
+                operand: referent,
+            } => self.require_mutable(referent, Change::MutableReference, expression),
+            ExpressionKind::FunctionCall {
+                function,
+                arguments,
+            } => {
+                let declaration = self
+                    .resolution
+                    .symbol_of(self.index.expect_id(function.as_ref()))
+                    .and_then(|symbol| self.resolution.symbols.symbol(symbol).node);
+                self.arguments(declaration, arguments);
+            }
+            ExpressionKind::MethodCall { arguments, .. } => {
+                let declaration = self.types.method(self.index.expect_id(expression));
+                self.arguments(declaration, arguments);
+            }
+            _ => {}
+        }
+        walk_expression(self, expression);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolve::resolve;
+    use crate::typeck::check;
+    use parser::parse;
+    use test_case::test_case;
+
+    fn mutability_errors(source: &str) -> Vec<String> {
+        let program = parse(source).expect("Parse error");
+        let index = NodeIndex::new(&program);
+        let resolution = resolve(&program, &index);
+        let types = check(&program, &index, &resolution).types;
+        check_mutability(&program, &index, &resolution, &types)
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test_case("@count = 0; (count) = 1; count += 2;" ; "mutable variable")]
+    #[test_case("fn f(i32 @n) { n -= 1; }" ; "mutable parameter")]
+    #[test_case("record point { x: i32; } point @p = point { x: 1 }; p.x = 2;" ; "field of a mutable variable")]
+    #[test_case("fn f(arrayList<i32>& @xs) { xs[0] = 1; }" ; "element through a mutable reference variable")]
+    #[test_case("fn f(arrayList<i32>&@ xs) { xs[0] = 1; }" ; "element through a mutable reference")]
+    #[test_case("fn f(i32&@ p) { *p = 1; }" ; "write through a mutable reference")]
+    #[test_case("string @buffer = \"a\"; string& @r = &buffer; *r = \"b\";" ; "write through a mutable variable")]
+    #[test_case("@counter = 0; r = &@counter;" ; "borrow of a mutable variable")]
+    #[test_case("x = 1; r = &x;" ; "immutable borrow")]
+    #[test_case("fn g(i32& r) -> i32 { *r } fn f() { x = 1; g(&x); }" ; "borrow passed for an immutable parameter")]
+    #[test_case("@x = 1; fn g(i32& @r) { *r = 9; } fn f() { g(&x); }" ; "borrow of a mutable variable passed for a mutable parameter")]
+    fn test_allowed(source: &str) {
+        assert_eq!(mutability_errors(source), Vec::<String>::new());
+    }
+
+    #[test_case("count = 0; (count) = 1;", "Cannot assign to immutable variable 'count'" ; "reassignment")]
+    #[test_case("count = 0; count += 1;", "Cannot assign to immutable variable 'count'" ; "compound assignment")]
+    #[test_case("fn f(i32 n) { n -= 1; }", "Cannot assign to immutable parameter 'n'" ; "parameter")]
+    #[test_case("record point { x: i32; } p = point { x: 1 }; p.x = 2;", "Cannot assign to immutable variable 'p'" ; "field")]
+    #[test_case("fn f(arrayList<i32>& xs) { xs[0] = 1; }", "Cannot assign to immutable parameter 'xs'" ; "element through an immutable reference")]
+    #[test_case("fn f(i32& p) { *p = 1; }", "Cannot assign to immutable parameter 'p'" ; "write through an immutable reference")]
+    #[test_case("fn f(arrayList<i32> xs) { for x in xs { (x) = 0; } }", "Cannot assign to immutable loop variable 'x'" ; "loop variable")]
+    #[test_case("const i32 limit = 1; fn f() { (limit) = 2; }", "Cannot assign to immutable const 'limit'" ; "constant")]
+    #[test_case("counter = 0; r = &@counter;", "Cannot take a mutable reference to immutable variable 'counter'" ; "mutable borrow")]
+    #[test_case("fn f(i32 n) -> i32&@ { &@n }", "Cannot take a mutable reference to immutable parameter 'n'" ; "mutable borrow of a parameter")]
+    #[test_case("x = 1; i32& @r = &x; *r = 5;", "Cannot take a mutable reference to immutable variable 'x'" ; "borrow bound to a mutable variable")]
+    #[test_case("x = 1; @r = &(x);", "Cannot take a mutable reference to immutable variable 'x'" ; "grouped borrow")]
+    #[test_case("x = 1; @y = 2; i32& @r = &y; (r) = &x;", "Cannot take a mutable reference to immutable variable 'x'" ; "borrow assigned to a mutable variable")]
+    #[test_case("const i32 K = 1; fn g(i32& @r) { *r = 9; } fn f() { g(&K); }", "Cannot take a mutable reference to immutable const 'K'" ; "borrow passed for a mutable parameter")]
+    #[test_case(
+        "record box { n: i32; } patch box { fn set(i32& @r) { *r = 1; } } fn f(box b) { x = 1; b.set(&x); }",
+        "Cannot take a mutable reference to immutable variable 'x'" ;
+        "borrow passed for a mutable method parameter"
+    )]
+    fn test_immutable(source: &str, expected: &str) {
+        assert_eq!(mutability_errors(source), [expected]);
+    }
+
+    #[test]
+    fn test_error_points_at_declaration() {
+        let program = parse("fn f() { total = 0; total += 1; }").expect("Parse error");
+        let index = NodeIndex::new(&program);
+        let resolution = resolve(&program, &index);
+        let types = check(&program, &index, &resolution).types;
+        let errors = check_mutability(&program, &index, &resolution, &types);
+
+        let parser::ast::Declaration::Function(function) = &program.declarations[0] else {
+            panic!("expected a function");
+        };
+        let ExpressionKind::Block { statements, .. } = &function.body.kind else {
+            panic!("expected a block");
+        };
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].declaration(), index.id(&statements[0]));
+        assert_eq!(errors[0].node(), index.id(&statements[1]).unwrap());
+    }
+}
@@ -0,0 +1,89 @@
+//! Every check a resolved and type-checked program goes through before it runs.
+//!
+//! `cv run` and the engine that embeds CV in Rust applications run these on a whole program, so
+//! both accept and reject the same programs. The REPL runs them, but for monomorphization, on
+//! each input after the ones before it, through [`Earlier`](crate::Earlier).
+
+use crate::diagnostic::Diagnostic;
+use crate::resolve::Resolution;
+use crate::shadowing::ShadowingLint;
+use crate::typeck::TypeCheck;
+use parser::ast::{Declaration, Program};
+use parser::node_id::NodeIndex;
+
+/// Every problem the checks find in `program`, given its resolution and type check, with the
+/// shadowing `shadowing` asks for.
+pub fn check_program(
+    program: &Program,
+    index: &NodeIndex,
+    resolution: &Resolution,
+    checked: &TypeCheck,
+    shadowing: ShadowingLint,
+) -> Vec<Diagnostic> {
+    let mut diagnostics = check_program_after(program, index, resolution, checked, shadowing, &[]);
+    diagnostics.extend(
+        crate::monomorphize(program)
+            .errors
+            .into_iter()
+            .map(Diagnostic::from),
+    );
+    diagnostics
+}
+
+/// The problems the checks of [`check_program`] find in `program`, after the programs that made
+/// `declarations`, but for those of monomorphization, which needs every generic function the
+/// program calls and so only checks a whole program.
+pub(crate) fn check_program_after(
+    program: &Program,
+    index: &NodeIndex,
+    resolution: &Resolution,
+    checked: &TypeCheck,
+    shadowing: ShadowingLint,
+    declarations: &[&Declaration],
+) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = parser::validate::validate_after(program, declarations)
+        .into_iter()
+        .map(Diagnostic::from)
+        .collect();
+    diagnostics.extend(resolution.errors.iter().cloned().map(Diagnostic::from));
+    diagnostics.extend(checked.errors.iter().cloned().map(Diagnostic::from));
+    diagnostics.extend(
+        crate::check_returns(program, index)
+            .into_iter()
+            .map(Diagnostic::from),
+    );
+    diagnostics.extend(
+        crate::exhaustive::check_matches_after(
+            program,
+            index,
+            resolution,
+            &checked.types,
+            declarations,
+        )
+        .into_iter()
+        .map(Diagnostic::from),
+    );
+    diagnostics.extend(
+        crate::mutability::check_mutability_after(
+            program,
+            index,
+            resolution,
+            &checked.types,
+            declarations,
+        )
+        .into_iter()
+        .map(Diagnostic::from),
+    );
+    diagnostics.extend(
+        crate::check_references(program, index, resolution, &checked.types)
+            .into_iter()
+            .map(Diagnostic::from),
+    );
+    diagnostics.extend(
+        crate::check_shadowing(program, index, resolution, shadowing)
+            .into_iter()
+            .map(Diagnostic::from),
+    );
+    diagnostics.extend(crate::check_unused(program, index, resolution));
+    diagnostics
+}
@@ -0,0 +1,178 @@
+//! The prelude: declarations written in CV that every program can use without declaring them,
+//! such as the `option` and `result` unions, `min` and `max`, and `fold` over arrays.
+//!
+//! The prelude is not a module a program imports. Its declarations are put in front of the
+//! program's own, and the program is checked and run as if it had declared them itself. A
+//! program that declares one of the prelude's names keeps its own declaration: the prelude's is
+//! left out, together with every prelude declaration that uses it.
+
+use parser::ast::{
+    Declaration, Expression, ExpressionKind, Pattern, PatternKind, Program, Statement,
+    StatementKind, Type,
+};
+use parser::node_id::NodeIds;
+use parser::visit::{Visitor, walk_expression, walk_pattern, walk_type};
+use std::collections::HashSet;
+
+/// The source of the prelude.
+pub const SOURCE: &str = include_str!("prelude.cv");
+
+/// The declarations of the prelude.
+pub fn declarations() -> Vec<Declaration> {
+    parser::parse(SOURCE)
+        .expect("the prelude parses")
+        .declarations
+}
+
+/// `program` with the prelude in front of its declarations, except for the prelude
+/// declarations the program replaces. The prelude's nodes get ids after the program's, which
+/// keeps its own.
+pub fn with_prelude(program: Program) -> Program {
+    with_prelude_except(program, &[])
+}
+
+/// `program` with the prelude as [`with_prelude`] gives it, also leaving out the prelude
+/// declarations that take one of the `reserved` names, such as those of host functions.
+pub fn with_prelude_except(program: Program, reserved: &[&str]) -> Program {
+    let declared: HashSet<String> = program
+        .declarations
+        .iter()
+        .flat_map(declared_names)
+        .chain(reserved.iter().map(ToString::to_string))
+        .collect();
+    let mut prelude = declarations();
+    // The names of the prelude declarations left out so far, which the ones that use them have
+    // to go with.
+    let mut left_out = HashSet::new();
+    loop {
+        let before = prelude.len();
+        prelude.retain(|declaration| {
+            let names = declared_names(declaration);
+            let keep = !names.iter().any(|name| declared.contains(name))
+                && used_names(declaration).is_disjoint(&left_out);
+            if !keep {
+                left_out.extend(names);
+            }
+            keep
+        });
+        if prelude.len() == before {
+            break;
+        }
+    }
+    let mut ids = NodeIds::after(&program);
+    for declaration in &mut prelude {
+        ids.number_declaration(declaration);
+    }
+    prelude.extend(program.declarations);
+    Program {
+        declarations: prelude,
+    }
+}
+
+/// The top-level names `declaration` defines. A union defines its variants as well.
+pub(crate) fn declared_names(declaration: &Declaration) -> Vec<String> {
+    match declaration {
+        Declaration::Function(function) => vec![function.name.clone()],
+        Declaration::Record(record) => vec![record.name.clone()],
+        Declaration::Union(union) => std::iter::once(&union.name)
+            .chain(union.variants.iter().map(|variant| &variant.name))
+            .cloned()
+            .collect(),
+        Declaration::Const(constant) => vec![constant.name.clone()],
+        Declaration::Statement(Statement {
+            kind: StatementKind::VariableDeclaration { name, .. },
+            ..
+        }) => vec![name.clone()],
+        Declaration::Patch(_) | Declaration::Import(_) | Declaration::Statement(_) => Vec::new(),
+    }
+}
+
+/// Every name `declaration` mentions, whether it refers to a top-level declaration or not.
+fn used_names(declaration: &Declaration) -> HashSet<String> {
+    let mut names = Names(HashSet::new());
+    names.visit_declaration(declaration);
+    names.0
+}
+
+struct Names(HashSet<String>);
+
+impl Visitor for Names {
+    fn visit_expression(&mut self, expression: &Expression) {
+        if let ExpressionKind::Identifier(name) = &expression.kind {
+            self.0.insert(name.clone());
+        }
+        walk_expression(self, expression);
+    }
+
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        if let PatternKind::Identifier(name) | PatternKind::Union { variant: name, .. } =
+            &pattern.kind
+        {
+            self.0.insert(name.clone());
+        }
+        walk_pattern(self, pattern);
+    }
+
+    fn visit_type(&mut self, ty: &Type) {
+        if let Type::Named(name) | Type::Generic { name, .. } = ty {
+            self.0.insert(name.clone());
+        }
+        walk_type(self, ty);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{check, resolve};
+    use parser::node_id::NodeIndex;
+
+    fn names(program: &Program) -> Vec<String> {
+        program
+            .declarations
+            .iter()
+            .flat_map(declared_names)
+            .collect()
+    }
+
+    #[test]
+    fn test_prelude_checks() {
+        let program = with_prelude(Program {
+            declarations: Vec::new(),
+        });
+        let index = NodeIndex::new(&program);
+        let resolution = resolve(&program, &index);
+        let checked = check(&program, &index, &resolution);
+
+        assert_eq!(resolution.errors, []);
+        assert_eq!(checked.errors, []);
+    }
+
+    #[test]
+    fn test_program_replaces_prelude_declarations() {
+        let program = parser::parse("fn min(i32 a) -> i32 { a } union maybe = some | nothing;")
+            .expect("Parse error");
+        let names = names(&with_prelude(program));
+
+        // `find` returns an `option`, which went with its `some` variant.
+        assert!(!names.contains(&"option".to_string()));
+        assert!(!names.contains(&"find".to_string()));
+        assert!(names.contains(&"result".to_string()));
+        assert!(names.contains(&"max".to_string()));
+        assert_eq!(names.iter().filter(|name| *name == "min").count(), 1);
+        assert_eq!(names.last().map(String::as_str), Some("nothing"));
+    }
+
+    #[test]
+    fn test_local_names_of_the_prelude_are_not_replaced() {
+        let program = parser::parse("value = 1; item = 2;").expect("Parse error");
+        let prelude = with_prelude(Program {
+            declarations: Vec::new(),
+        });
+
+        assert_eq!(
+            with_prelude(program).declarations.len(),
+            prelude.declarations.len() + 2
+        );
+    }
+}
@@ -0,0 +1,316 @@
+//! Reference checks that need no lifetime analysis.
+//!
+//! A function or closure may not return a reference to one of its own variables, parameters, or
+//! bindings, since they are gone once it returns; a reference reached through a reference
+//! parameter points outside the function and may be returned. A variable bound to a reference to
+//! a local, as `r` in `r = &x; r`, may not be returned either. Only `*` of a reference is allowed.
+//! Taking `&@` of a name that is not declared with `@` is reported by the
+//! [mutability checks](crate::mutability).
+
+use crate::resolve::Resolution;
+use crate::scope::ScopeId;
+use crate::symbols::{SymbolId, SymbolKind};
+use crate::typeck::TypeMap;
+use parser::ast::{
+    BinaryOperator, Expression, ExpressionKind, FunctionDeclaration, Program, Statement,
+    StatementKind, Type, UnaryOperator,
+};
+use parser::node_id::{Node, NodeId, NodeIndex};
+use parser::visit::{Visitor, walk_expression, walk_function, walk_statement};
+use std::collections::HashMap;
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Clone)]
+pub enum ReferenceError {
+    /// `node` is the reference and `declaration` the node that declares `name`.
+    #[error("Cannot return a reference to local {kind} '{name}'")]
+    LocalReferenceEscapes {
+        name: String,
+        kind: SymbolKind,
+        node: NodeId,
+        declaration: Option<NodeId>,
+    },
+    #[error("Cannot dereference a value of type '{found}'")]
+    NotAReference { found: Type, node: NodeId },
+}
+
+impl ReferenceError {
+    pub fn node(&self) -> NodeId {
+        match self {
+            ReferenceError::LocalReferenceEscapes { node, .. }
+            | ReferenceError::NotAReference { node, .. } => *node,
+        }
+    }
+}
+
+/// Check the references in `program`, using `types` to tell references from other values.
+pub fn check_references(
+    program: &Program,
+    index: &NodeIndex,
+    resolution: &Resolution,
+    types: &TypeMap,
+) -> Vec<ReferenceError> {
+    let mut checker = Checker {
+        index,
+        resolution,
+        types,
+        functions: Vec::new(),
+        borrows: HashMap::new(),
+        errors: Vec::new(),
+    };
+    checker.visit_program(program);
+    checker.errors
+}
+
+struct Checker<'i> {
+    index: &'i NodeIndex,
+    resolution: &'i Resolution,
+    types: &'i TypeMap,
+    /// The scope of each function or closure being checked, innermost last.
+    functions: Vec<ScopeId>,
+    /// The declaration of each variable bound to a reference to another variable, with that
+    /// variable.
+    borrows: HashMap<NodeId, SymbolId>,
+    errors: Vec<ReferenceError>,
+}
+
+impl Checker<'_> {
+    fn is_reference(&self, expression: &Expression) -> bool {
+        matches!(
+            self.types.expression(self.index.expect_id(expression)),
+            Some(Type::Reference { .. })
+        )
+    }
+
+    /// The variable whose storage `place` is part of, or `None` if it is reached through a
+    /// reference or is not stored in a variable.
+    fn owner(&self, place: &Expression) -> Option<SymbolId> {
+        match &place.kind {
+            ExpressionKind::Identifier(_) => self.resolution.symbol_of(self.index.expect_id(place)),
+            ExpressionKind::Grouped(inner) => self.owner(inner),
+            ExpressionKind::RecordAccess { record: owner, .. }
+            | ExpressionKind::IndexAccess {
+                collection: owner, ..
+            } if !self.is_reference(owner) => self.owner(owner),
+            _ => None,
+        }
+    }
+
+    /// The variable the reference `expression` points into, following variables bound to such a
+    /// reference.
+    fn referent(&self, expression: &Expression) -> Option<SymbolId> {
+        match &expression.kind {
+            ExpressionKind::Reference {
+                expression: place, ..
+            }
+            | ExpressionKind::UnaryOperation {
+                operator: UnaryOperator::Reference | UnaryOperator::MutableReference,
+                operand: place,
+            } => self.owner(place),
+            ExpressionKind::Identifier(_) => {
+                let symbol = self
+                    .resolution
+                    .symbol_of(self.index.expect_id(expression))?;
+                let declaration = self.resolution.symbols.symbol(symbol).node?;
+                self.borrows.get(&declaration).copied()
+            }
+            ExpressionKind::Grouped(inner)
+            | ExpressionKind::Cast {
+                expression: inner, ..
+            } => self.referent(inner),
+            _ => None,
+        }
+    }
+
+    /// Remember the variable `value` refers to, if any, as one the variable declared by
+    /// `declaration` may refer to.
+    fn bind(&mut self, declaration: NodeId, value: &Expression) {
+        if let Some(referent) = self.referent(value) {
+            self.borrows.insert(declaration, referent);
+        }
+    }
+
+    /// Check the expressions whose value `expression` returns from the innermost function.
+    fn returned(&mut self, expression: &Expression) {
+        match &expression.kind {
+            ExpressionKind::Block {
+                final_expression: Some(final_expression),
+                ..
+            } => self.returned(final_expression),
+            ExpressionKind::If {
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.returned(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.returned(else_branch);
+                }
+            }
+            ExpressionKind::When { branches, .. } => {
+                for branch in branches {
+                    self.returned(&branch.body);
+                }
+            }
+            ExpressionKind::Grouped(inner)
+            | ExpressionKind::Cast {
+                expression: inner, ..
+            } => self.returned(inner),
+            _ => {
+                if let Some(referent) = self.referent(expression) {
+                    self.check_escape(referent, expression);
+                }
+            }
+        }
+    }
+
+    /// Report `reference`, a returned reference into the variable `symbol_id`, if it is local.
+    fn check_escape(&mut self, symbol_id: SymbolId, reference: &Expression) {
+        let Some(&function) = self.functions.last() else {
+            return;
+        };
+        let symbol = self.resolution.symbols.symbol(symbol_id);
+        let is_storage = matches!(
+            symbol.kind,
+            SymbolKind::Variable { .. }
+                | SymbolKind::Parameter
+                | SymbolKind::Binding
+                | SymbolKind::LoopVariable
+        );
+        // A variable holding a reference is a place to start from, not the storage it refers to.
+        let holds_reference = matches!(self.types.symbol(symbol_id), Some(Type::Reference { .. }));
+        if is_storage
+            && !holds_reference
+            && self.resolution.symbols.is_within(symbol.scope, function)
+        {
+            self.errors.push(ReferenceError::LocalReferenceEscapes {
+                name: self.resolution.symbols.symbol_name(symbol_id).to_string(),
+                kind: symbol.kind,
+                node: self.index.expect_id(reference),
+                declaration: symbol.node,
+            });
+        }
+    }
+
+    /// Check the body of the function or closure `node`, whose value it returns.
+    fn function<T: Node>(&mut self, node: &T, body: &Expression, walk: impl FnOnce(&mut Self)) {
+        let Some(&scope) = self.resolution.scope_of.get(&self.index.expect_id(node)) else {
+            return walk(self);
+        };
+        self.functions.push(scope);
+        walk(self);
+        // After the body, so that what its variables refer to is known.
+        self.returned(body);
+        self.functions.pop();
+    }
+}
+
+impl Visitor for Checker<'_> {
+    fn visit_function(&mut self, function: &FunctionDeclaration) {
+        self.function(function, &function.body, |checker| {
+            walk_function(checker, function)
+        });
+    }
+
+    fn visit_statement(&mut self, statement: &Statement) {
+        match &statement.kind {
+            StatementKind::Return(Some(value)) => self.returned(value),
+            StatementKind::VariableDeclaration { value, .. } => {
+                self.bind(self.index.expect_id(statement), value);
+            }
+            StatementKind::Assignment {
+                target,
+                operator: BinaryOperator::Assign,
+                value,
+            } => {
+                if let ExpressionKind::Identifier(_) = target.kind
+                    && let Some(symbol) = self
+                        .resolution
+                        .symbol_of(self.index.expect_id(target.as_ref()))
+                    && let Some(declaration) = self.resolution.symbols.symbol(symbol).node
+                {
+                    self.bind(declaration, value);
+                }
+            }
+            _ => {}
+        }
+        walk_statement(self, statement);
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        match &expression.kind {
+            ExpressionKind::Closure { body, .. } => {
+                return self.function(expression, body, |checker| {
+                    walk_expression(checker, expression)
+                });
+            }
+            ExpressionKind::Dereference(operand)
+            | ExpressionKind::UnaryOperation {
+                operator: UnaryOperator::Dereference,
+                operand,
+            } => {
+                if let Some(found) = self
+                    .types
+                    .expression(self.index.expect_id(operand.as_ref()))
+                    && !matches!(found, Type::Reference { .. } | Type::Inferred)
+                {
+                    self.errors.push(ReferenceError::NotAReference {
+                        found: found.clone(),
+                        node: self.index.expect_id(expression),
+                    });
+                }
+            }
+            _ => {}
+        }
+        walk_expression(self, expression);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolve::resolve;
+    use crate::typeck::check;
+    use parser::parse;
+    use test_case::test_case;
+
+    fn reference_errors(source: &str) -> Vec<String> {
+        let program = parse(source).expect("Parse error");
+        let index = NodeIndex::new(&program);
+        let resolution = resolve(&program, &index);
+        let types = check(&program, &index, &resolution).types;
+        check_references(&program, &index, &resolution, &types)
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test_case("fn f(i32& n) -> i32& { n }" ; "reference parameter")]
+    #[test_case("record point { x: i32; } fn f(point& p) -> i32& { &p.x }" ; "field through a reference")]
+    #[test_case("fn f(arrayList<i32>&@ xs) -> i32&@ { &@xs[0] }" ; "element through a mutable reference")]
+    #[test_case("i32 limit = 3; fn f() -> i32& { &limit }" ; "top-level variable")]
+    #[test_case("fn f(i32& n) -> i32& { m = n; &*m }" ; "reborrow")]
+    #[test_case("fn f(i32& n) -> i32 { *n + 1 }" ; "dereference")]
+    #[test_case("fn f() { x = 1; r = &x; print(*r); }" ; "local reference that stays local")]
+    #[test_case("fn f(i32& n) -> i32& { r = n; s = r; s }" ; "copied reference parameter")]
+    #[test_case("fn f() -> i32 { x = 1; r = &x; y = *r; y }" ; "value read through a local reference")]
+    fn test_valid(source: &str) {
+        assert_eq!(reference_errors(source), Vec::<String>::new());
+    }
+
+    #[test_case("fn f() -> i32& { x = 1; &x }", "Cannot return a reference to local variable 'x'" ; "variable")]
+    #[test_case("fn f(i32 n) -> i32& { &n }", "Cannot return a reference to local parameter 'n'" ; "parameter by value")]
+    #[test_case("fn f(arrayList<i32> xs) -> i32& { return &xs[0]; }", "Cannot return a reference to local parameter 'xs'" ; "return statement")]
+    #[test_case("fn f(bool b) -> i32& { @y = 2; if b { &@y } else { &y } }", "Cannot return a reference to local variable 'y'" ; "both branches")]
+    #[test_case("fn f() -> i32& { x = 1; r = &x; r }", "Cannot return a reference to local variable 'x'" ; "variable bound to a reference")]
+    #[test_case("fn f() -> i32& { x = 1; r = &x; s = r; return s; }", "Cannot return a reference to local variable 'x'" ; "copied reference")]
+    #[test_case("fn f(i32& n) -> i32& { x = 1; @r = n; r = &x; r }", "Cannot return a reference to local variable 'x'" ; "reassigned reference")]
+    #[test_case("g = |i32 n| &n;", "Cannot return a reference to local parameter 'n'" ; "closure")]
+    #[test_case("fn f() -> i32& { @x = 1; &@x as i32& }", "Cannot return a reference to local variable 'x'" ; "cast")]
+    #[test_case("x = 1; y = *x;", "Cannot dereference a value of type 'i32'" ; "dereference of an integer")]
+    fn test_invalid(source: &str, expected: &str) {
+        let errors = reference_errors(source);
+        assert!(!errors.is_empty());
+        assert!(errors.iter().all(|error| error == expected), "{:?}", errors);
+    }
+}
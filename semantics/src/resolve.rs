@@ -0,0 +1,597 @@
+//! Name resolution: linking every name used in a program to the symbol it refers to.
+//!
+//! Functions, records, unions, their variants, and consts are visible throughout the program.
+//! Top-level statements run in order, so a top-level variable is visible to the statements after
+//! it and to every function body. Inside a function, a variable is visible from the statement
+//! after its declaration to the end of the enclosing block, and a later declaration of the same
+//! name shadows it: in `data = data.trim();` the `data` on the right is the earlier one. A
+//! top-level variable is the exception: it may not take the name of a function, record, union,
+//! variant, or const. Which shadowing deserves a warning is for [`crate::shadowing`] to decide.
+
+use crate::scope::{ScopeId, ScopeKind};
+use crate::symbols::{Mark, Symbol, SymbolId, SymbolKind, SymbolTable};
+use parser::ast::{
+    Declaration, Expression, ExpressionKind, FunctionDeclaration, Parameter, PatchDeclaration,
+    Pattern, PatternKind, Program, RecordDeclaration, Statement, StatementKind, Type,
+    TypeParameter, UnionDeclaration, WhenBranch,
+};
+use parser::node_id::{Node, NodeId, NodeIndex};
+use parser::visit::{
+    Visitor, walk_expression, walk_function, walk_parameter, walk_patch, walk_pattern, walk_record,
+    walk_statement, walk_type_parameter, walk_union, walk_when_branch,
+};
+use std::collections::HashMap;
+use thiserror::Error;
+
+/// Functions every program can call without declaring them.
+pub const BUILTINS: &[&str] = &["print", "println", "len", "assert", "to_string"];
+
+/// A function the application running a program provides, which the program calls like a
+/// builtin.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Host {
+    pub name: String,
+    /// The function's type, a [`Type::Function`].
+    pub ty: Type,
+}
+
+#[derive(Debug, Error, PartialEq, Clone)]
+pub enum ResolveError {
+    /// `node` is the identifier expression that uses the name.
+    #[error("Cannot find '{name}' in this scope")]
+    UnresolvedName { name: String, node: NodeId },
+    /// `node` is the pattern that names the variant.
+    #[error("Cannot find a union variant named '{name}'")]
+    UnresolvedVariant { name: String, node: NodeId },
+    /// `node` is the later declaration and `first` the one before it with the same name.
+    #[error("'{name}' is already defined as a {first_kind}")]
+    DuplicateDefinition {
+        name: String,
+        kind: SymbolKind,
+        first_kind: SymbolKind,
+        node: NodeId,
+        first: NodeId,
+    },
+    /// `node` is the declaration that takes the name of a function the application provides,
+    /// which would hide it from the whole program.
+    #[error("'{name}' is already defined as a host function")]
+    HostName { name: String, node: NodeId },
+}
+
+impl ResolveError {
+    pub fn node(&self) -> NodeId {
+        match self {
+            ResolveError::UnresolvedName { node, .. }
+            | ResolveError::UnresolvedVariant { node, .. }
+            | ResolveError::DuplicateDefinition { node, .. }
+            | ResolveError::HostName { node, .. } => *node,
+        }
+    }
+
+    /// The earlier declaration a duplicate conflicts with.
+    pub fn declaration(&self) -> Option<NodeId> {
+        match self {
+            ResolveError::DuplicateDefinition { first, .. } => Some(*first),
+            _ => None,
+        }
+    }
+}
+
+/// The scopes of a program and what each name in it refers to.
+#[derive(Debug, Clone)]
+pub struct Resolution {
+    /// Every scope and symbol of the program, after those of the programs it was resolved after.
+    pub symbols: SymbolTable,
+    /// Where the program's own scopes and symbols start in `symbols`.
+    pub start: Mark,
+    /// The symbol used by each identifier expression, and by each pattern that names a variant
+    /// or repeats a binding in another alternative of an or-pattern.
+    pub references: HashMap<NodeId, SymbolId>,
+    /// The scope each function, closure, block, loop, and `when` branch opens, by the node that
+    /// opens it.
+    pub scope_of: HashMap<NodeId, ScopeId>,
+    /// Each variable, parameter, pattern binding, and `for` variable that hides another value of
+    /// the same name, with the symbol it hides, in the order they are defined.
+    pub shadows: Vec<(SymbolId, SymbolId)>,
+    pub errors: Vec<ResolveError>,
+}
+
+impl Resolution {
+    /// The symbol `node` refers to, if it is a name that resolved.
+    pub fn symbol_of(&self, node: NodeId) -> Option<SymbolId> {
+        self.references.get(&node).copied()
+    }
+
+    /// The symbols the program defines, without those of the programs it was resolved after.
+    pub fn own_symbols(&self) -> impl Iterator<Item = (SymbolId, &Symbol)> {
+        self.symbols.symbols_since(self.start)
+    }
+}
+
+/// Resolve every name in `program`, whose nodes `index` numbers.
+pub fn resolve(program: &Program, index: &NodeIndex) -> Resolution {
+    resolve_with_hosts(program, index, &[])
+}
+
+/// Resolve every name in `program` as [`resolve`] does, with the functions in `hosts` visible
+/// next to the builtins.
+pub fn resolve_with_hosts(program: &Program, index: &NodeIndex, hosts: &[Host]) -> Resolution {
+    let mut symbols = SymbolTable::new();
+    let root = symbols.root();
+    for builtin in BUILTINS {
+        symbols.define(root, builtin, SymbolKind::Builtin, None);
+    }
+    for host in hosts {
+        symbols.define(root, &host.name, SymbolKind::Host, None);
+    }
+    resolve_after(program, index, symbols, root)
+}
+
+/// Resolve every name in `program` after the programs whose scopes and symbols `symbols` holds,
+/// with the names they declared visible. The top-level declarations of `program` go in `global`,
+/// where they may not take each other's names or those of the declarations already there, but
+/// may hide the ones of the scopes above it.
+pub(crate) fn resolve_after(
+    program: &Program,
+    index: &NodeIndex,
+    symbols: SymbolTable,
+    global: ScopeId,
+) -> Resolution {
+    let start = symbols.mark();
+    let mut resolver = Resolver {
+        index,
+        global,
+        current: global,
+        symbols,
+        references: HashMap::new(),
+        scope_of: HashMap::new(),
+        shadows: Vec::new(),
+        errors: Vec::new(),
+    };
+    resolver.visit_program(program);
+    Resolution {
+        symbols: resolver.symbols,
+        start,
+        references: resolver.references,
+        scope_of: resolver.scope_of,
+        shadows: resolver.shadows,
+        errors: resolver.errors,
+    }
+}
+
+struct Resolver<'i> {
+    index: &'i NodeIndex,
+    symbols: SymbolTable,
+    /// The scope the program's top-level declarations go in.
+    global: ScopeId,
+    current: ScopeId,
+    references: HashMap<NodeId, SymbolId>,
+    scope_of: HashMap<NodeId, ScopeId>,
+    shadows: Vec<(SymbolId, SymbolId)>,
+    errors: Vec<ResolveError>,
+}
+
+impl Resolver<'_> {
+    fn define<T: Node>(&mut self, name: &str, kind: SymbolKind, node: &T) -> SymbolId {
+        let node = self.index.expect_id(node);
+        self.symbols.define(self.current, name, kind, Some(node))
+    }
+
+    /// Run `f` in a new scope of `kind` opened by `node`.
+    fn in_scope<T: Node>(&mut self, kind: ScopeKind, node: &T, f: impl FnOnce(&mut Self)) {
+        let node = self.index.expect_id(node);
+        let outer = self.current;
+        self.current = self.symbols.add_scope(kind, outer, Some(node));
+        self.scope_of.insert(node, self.current);
+        f(self);
+        self.current = outer;
+    }
+
+    /// Define a name that is visible throughout the program, reporting it if another
+    /// declaration already took it. Functions, records, unions, variants, consts, and top-level
+    /// variables share one namespace, though a top-level variable may be declared again, and a
+    /// declaration may reuse the name of a builtin. It may not take the name of a host function,
+    /// which the application expects the program to call.
+    fn define_global<T: Node>(&mut self, name: &str, kind: SymbolKind, node: &T) -> SymbolId {
+        let first = self.symbols.lookup_in(self.global, name, |symbol| {
+            !matches!(
+                symbol.kind,
+                SymbolKind::Builtin | SymbolKind::Host | SymbolKind::Variable { .. }
+            )
+        });
+        if let Some(first) = first {
+            let first = self.symbols.symbol(first);
+            self.errors.push(ResolveError::DuplicateDefinition {
+                name: name.to_string(),
+                kind,
+                first_kind: first.kind,
+                node: self.index.expect_id(node),
+                first: first.node.expect("declared names have a node"),
+            });
+        } else if self
+            .symbols
+            .lookup(self.global, name, |symbol| symbol.kind == SymbolKind::Host)
+            .is_some()
+        {
+            self.errors.push(ResolveError::HostName {
+                name: name.to_string(),
+                node: self.index.expect_id(node),
+            });
+        }
+        self.define(name, kind, node)
+    }
+
+    /// Define a variable, parameter, pattern binding, or `for` variable, noting the value of the
+    /// same name it hides, if any.
+    fn define_local<T: Node>(&mut self, name: &str, kind: SymbolKind, node: &T) {
+        let hidden = self
+            .symbols
+            .lookup(self.current, name, |symbol| symbol.kind.is_value());
+        let symbol = if self.current == self.global {
+            self.define_global(name, kind, node)
+        } else {
+            self.define(name, kind, node)
+        };
+        if let Some(hidden) = hidden {
+            self.shadows.push((symbol, hidden));
+        }
+    }
+
+    /// Define the names that are visible throughout the program.
+    fn define_globals(&mut self, program: &Program) {
+        for declaration in &program.declarations {
+            match declaration {
+                Declaration::Function(function) => {
+                    self.define_global(&function.name, SymbolKind::Function, function);
+                }
+                Declaration::Record(record) => {
+                    self.define_global(&record.name, SymbolKind::Record, record);
+                }
+                Declaration::Union(union) => {
+                    self.define_global(&union.name, SymbolKind::Union, union);
+                    for variant in &union.variants {
+                        self.define_global(&variant.name, SymbolKind::Variant, variant);
+                    }
+                }
+                Declaration::Const(constant) => {
+                    self.define_global(&constant.name, SymbolKind::Const, constant);
+                }
+                Declaration::Patch(_) | Declaration::Import(_) | Declaration::Statement(_) => {}
+            }
+        }
+    }
+
+    fn reference(&mut self, node: NodeId, name: &str, filter: impl Fn(SymbolKind) -> bool) -> bool {
+        match self
+            .symbols
+            .lookup(self.current, name, |symbol| filter(symbol.kind))
+        {
+            Some(symbol) => {
+                self.references.insert(node, symbol);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Visitor for Resolver<'_> {
+    /// Globals first, then top-level statements in order, then the bodies of functions and patch
+    /// methods, which see every top-level variable.
+    fn visit_program(&mut self, program: &Program) {
+        self.define_globals(program);
+        let (bodies, others): (Vec<_>, Vec<_>) =
+            program.declarations.iter().partition(|declaration| {
+                matches!(
+                    declaration,
+                    Declaration::Function(_) | Declaration::Patch(_)
+                )
+            });
+        for declaration in others.into_iter().chain(bodies) {
+            self.visit_declaration(declaration);
+        }
+    }
+
+    fn visit_function(&mut self, function: &FunctionDeclaration) {
+        self.in_scope(ScopeKind::Function, function, |resolver| {
+            walk_function(resolver, function)
+        });
+    }
+
+    fn visit_parameter(&mut self, parameter: &Parameter) {
+        walk_parameter(self, parameter);
+        self.define_local(&parameter.name, SymbolKind::Parameter, parameter);
+    }
+
+    fn visit_type_parameter(&mut self, parameter: &TypeParameter) {
+        walk_type_parameter(self, parameter);
+        let kind = match parameter.const_type {
+            Some(_) => SymbolKind::ConstParameter,
+            None => SymbolKind::TypeParameter,
+        };
+        self.define(&parameter.name, kind, parameter);
+    }
+
+    fn visit_record(&mut self, record: &RecordDeclaration) {
+        self.in_scope(ScopeKind::Generics, record, |resolver| {
+            walk_record(resolver, record)
+        });
+    }
+
+    fn visit_union(&mut self, union: &UnionDeclaration) {
+        self.in_scope(ScopeKind::Generics, union, |resolver| {
+            walk_union(resolver, union)
+        });
+    }
+
+    fn visit_patch(&mut self, patch: &PatchDeclaration) {
+        self.in_scope(ScopeKind::Patch, patch, |resolver| {
+            resolver.define("self", SymbolKind::SelfValue, patch);
+            for method in &patch.methods {
+                resolver.define(&method.name, SymbolKind::PatchMethod, method);
+            }
+            walk_patch(resolver, patch);
+        });
+    }
+
+    fn visit_statement(&mut self, statement: &Statement) {
+        walk_statement(self, statement);
+        if let StatementKind::VariableDeclaration {
+            name, is_mutable, ..
+        } = &statement.kind
+        {
+            let kind = SymbolKind::Variable {
+                is_mutable: *is_mutable,
+            };
+            self.define_local(name, kind, statement);
+        }
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        match &expression.kind {
+            ExpressionKind::Identifier(name) => {
+                let node = self.index.expect_id(expression);
+                if !self.reference(node, name, SymbolKind::is_value) {
+                    self.errors.push(ResolveError::UnresolvedName {
+                        name: name.clone(),
+                        node,
+                    });
+                }
+            }
+            ExpressionKind::Block { .. } | ExpressionKind::Closure { .. } => {
+                let kind = match &expression.kind {
+                    ExpressionKind::Block { .. } => ScopeKind::Block,
+                    _ => ScopeKind::Closure,
+                };
+                self.in_scope(kind, expression, |resolver| {
+                    walk_expression(resolver, expression)
+                });
+            }
+            ExpressionKind::Loop { body } => {
+                self.in_scope(ScopeKind::Loop, expression, |resolver| {
+                    resolver.visit_expression(body)
+                });
+            }
+            ExpressionKind::While { condition, body } => {
+                self.visit_expression(condition);
+                self.in_scope(ScopeKind::Loop, expression, |resolver| {
+                    resolver.visit_expression(body)
+                });
+            }
+            ExpressionKind::For {
+                variable,
+                iterable,
+                body,
+            } => {
+                self.visit_expression(iterable);
+                self.in_scope(ScopeKind::Loop, expression, |resolver| {
+                    resolver.define_local(variable, SymbolKind::LoopVariable, expression);
+                    resolver.visit_expression(body);
+                });
+            }
+            _ => walk_expression(self, expression),
+        }
+    }
+
+    fn visit_when_branch(&mut self, branch: &WhenBranch) {
+        self.in_scope(ScopeKind::Branch, branch, |resolver| {
+            walk_when_branch(resolver, branch)
+        });
+    }
+
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        match &pattern.kind {
+            // A bare name matches the variant it names, repeats a binding made by another
+            // alternative of an or-pattern, or binds a new variable.
+            PatternKind::Identifier(name) => {
+                let node = self.index.expect_id(pattern);
+                let repeated = self.symbols.lookup_in(self.current, name, |symbol| {
+                    symbol.kind == SymbolKind::Binding
+                });
+                if let Some(binding) = repeated {
+                    self.references.insert(node, binding);
+                } else if !self.reference(node, name, |kind| kind == SymbolKind::Variant) {
+                    self.define_local(name, SymbolKind::Binding, pattern);
+                }
+            }
+            PatternKind::Union { variant, .. } => {
+                let node = self.index.expect_id(pattern);
+                if !self.reference(node, variant, |kind| kind == SymbolKind::Variant) {
+                    self.errors.push(ResolveError::UnresolvedVariant {
+                        name: variant.clone(),
+                        node,
+                    });
+                }
+                walk_pattern(self, pattern);
+            }
+            _ => walk_pattern(self, pattern),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::parse;
+    use test_case::test_case;
+
+    /// Each identifier expression in `source`, in source order, with the kind of symbol it
+    /// resolves to, and the names that did not resolve.
+    fn resolved(source: &str) -> (Vec<(String, SymbolKind)>, Vec<String>) {
+        let program = parse(source).expect("Parse error");
+        let index = NodeIndex::new(&program);
+        let resolution = resolve(&program, &index);
+
+        let mut identifiers = Identifiers(&index, Vec::new());
+        identifiers.visit_program(&program);
+        let references = identifiers
+            .1
+            .into_iter()
+            .filter_map(|(name, id)| {
+                let definition = resolution.symbol_of(id)?;
+                Some((name, resolution.symbols.symbol(definition).kind))
+            })
+            .collect();
+        let errors = resolution.errors.iter().map(ToString::to_string).collect();
+        (references, errors)
+    }
+
+    /// Collects every identifier expression with its id.
+    struct Identifiers<'i>(&'i NodeIndex, Vec<(String, NodeId)>);
+
+    impl Visitor for Identifiers<'_> {
+        fn visit_expression(&mut self, expression: &Expression) {
+            if let ExpressionKind::Identifier(name) = &expression.kind {
+                self.1.push((name.clone(), self.0.id(expression).unwrap()));
+            }
+            walk_expression(self, expression);
+        }
+    }
+
+    fn kinds(source: &str) -> Vec<SymbolKind> {
+        let (references, errors) = resolved(source);
+        assert!(errors.is_empty(), "{:?}", errors);
+        references.into_iter().map(|(_, kind)| kind).collect()
+    }
+
+    const VARIABLE: SymbolKind = SymbolKind::Variable { is_mutable: false };
+
+    #[test_case("fn f(i32 a) -> i32 { b = a; b }", &[SymbolKind::Parameter, VARIABLE] ; "parameter and local")]
+    #[test_case("fn f(string data) { data = data.trim(); data }", &[SymbolKind::Parameter, VARIABLE] ; "shadowed parameter")]
+    #[test_case("fn f() { g() } fn g() {}", &[SymbolKind::Function] ; "function declared later")]
+    #[test_case("fn f() { limit } limit = 10;", &[VARIABLE] ; "top-level variable in function")]
+    #[test_case("const i32 n = 1; x = n + 1; print(x);", &[SymbolKind::Const, SymbolKind::Builtin, VARIABLE] ; "top-level statements")]
+    #[test_case("fn f(arrayList<i32> xs) { for x in xs { x } }", &[SymbolKind::Parameter, SymbolKind::LoopVariable] ; "for variable")]
+    #[test_case("f = |x, y| x + y;", &[SymbolKind::Parameter, SymbolKind::Parameter] ; "closure parameters")]
+    #[test_case("patch point { fn norm() -> i32 { self.x } }", &[SymbolKind::SelfValue] ; "self in patch")]
+    #[test_case("union option<T> = some(T) | none; x = none; y = some(1);", &[SymbolKind::Variant, SymbolKind::Variant] ; "variants as values")]
+    #[test_case(
+        "fn f<N: usize>(fixedArray<i32, N + 1> xs) -> i32 { N }",
+        &[SymbolKind::ConstParameter, SymbolKind::ConstParameter] ;
+        "const parameter"
+    )]
+    #[test_case(
+        "union shape = circle(i32) | square(i32); fn f(shape s) -> i32 { when s { circle(r) | square(r): r; } }",
+        &[SymbolKind::Parameter, SymbolKind::Binding] ;
+        "or-pattern binding"
+    )]
+    #[test_case(
+        "union color = red | green; fn f(color c) -> i32 { when c { red: 1; other if other == green: 2; } }",
+        &[SymbolKind::Parameter, SymbolKind::Binding, SymbolKind::Variant] ;
+        "variant and binding patterns"
+    )]
+    fn test_resolves_to(source: &str, expected: &[SymbolKind]) {
+        assert_eq!(kinds(source), expected);
+    }
+
+    #[test_case("fn f() { y }", &["Cannot find 'y' in this scope"] ; "undeclared")]
+    #[test_case("fn f() { { y = 1; }; y }", &["Cannot find 'y' in this scope"] ; "block local after its block")]
+    #[test_case("fn f() { x = x; }", &["Cannot find 'x' in this scope"] ; "own initializer")]
+    #[test_case("fn f(arrayList<i32> xs) { for x in xs {}; x }", &["Cannot find 'x' in this scope"] ; "for variable after its loop")]
+    #[test_case("record point { x: i32; } p = point;", &["Cannot find 'point' in this scope"] ; "record as value")]
+    #[test_case("fn f<T>(T a) { T }", &["Cannot find 'T' in this scope"] ; "type parameter as value")]
+    #[test_case("fn f(i32 a) { g(a) } fn h() { a }", &["Cannot find 'g' in this scope", "Cannot find 'a' in this scope"] ; "several")]
+    #[test_case("patch point { fn a() {} fn b() { a() } }", &["Cannot find 'a' in this scope"] ; "method without receiver")]
+    #[test_case("fn f(i32 a) { when a { some(n): n; } }", &["Cannot find a union variant named 'some'"] ; "unknown variant")]
+    fn test_unresolved(source: &str, expected: &[&str]) {
+        assert_eq!(resolved(source).1, expected);
+    }
+
+    #[test_case("fn f() {} fn f(i32 a) {}", "'f' is already defined as a function" ; "functions")]
+    #[test_case("record point { x: i32; } union point = a | b;", "'point' is already defined as a record" ; "record and union")]
+    #[test_case("union shape = circle | square; fn circle() {}", "'circle' is already defined as a variant" ; "variant and function")]
+    #[test_case("union a = x | y; union b = y | z;", "'y' is already defined as a variant" ; "variants of two unions")]
+    #[test_case("union color = red | green | red;", "'red' is already defined as a variant" ; "variants of one union")]
+    #[test_case("fn limit() {} const i32 limit = 1;", "'limit' is already defined as a function" ; "function and constant")]
+    #[test_case("fn limit() {} limit = 1;", "'limit' is already defined as a function" ; "top-level variable and function")]
+    fn test_duplicate_definition(source: &str, expected: &str) {
+        assert_eq!(resolved(source).1, [expected]);
+    }
+
+    #[test_case("fn print(string s) {}" ; "builtin")]
+    #[test_case("fn f() {} x = 1; x = 2;" ; "top-level variables")]
+    #[test_case("fn f(i32 f) { f = 1; }" ; "local names")]
+    fn test_not_a_duplicate(source: &str) {
+        assert_eq!(resolved(source).1, Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_duplicate_points_at_both_declarations() {
+        let program = parse("fn f() {} record r { x: i32; } fn f() {}").expect("Parse error");
+        let index = NodeIndex::new(&program);
+        let errors = resolve(&program, &index).errors;
+
+        let Declaration::Function(first) = &program.declarations[0] else {
+            panic!("expected a function");
+        };
+        let Declaration::Function(duplicate) = &program.declarations[2] else {
+            panic!("expected a function");
+        };
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].node(), index.id(duplicate).unwrap());
+        assert_eq!(errors[0].declaration(), index.id(first));
+    }
+
+    #[test]
+    fn test_scope_tree() {
+        let program = parse("fn f(i32 a) { for x in a { y = x; } }").expect("Parse error");
+        let index = NodeIndex::new(&program);
+        let resolution = resolve(&program, &index);
+        let symbols = &resolution.symbols;
+
+        let shape: Vec<(ScopeKind, Option<ScopeKind>, Vec<&str>)> = symbols
+            .scopes()
+            .map(|(_, scope)| {
+                (
+                    scope.kind,
+                    scope.parent.map(|parent| symbols.scope(parent).kind),
+                    scope
+                        .symbols
+                        .iter()
+                        .map(|&id| symbols.symbol_name(id))
+                        .collect(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            shape,
+            [
+                (
+                    ScopeKind::Global,
+                    None,
+                    vec!["print", "println", "len", "assert", "to_string", "f"]
+                ),
+                (ScopeKind::Function, Some(ScopeKind::Global), vec!["a"]),
+                (ScopeKind::Block, Some(ScopeKind::Function), vec![]),
+                (ScopeKind::Loop, Some(ScopeKind::Block), vec!["x"]),
+                (ScopeKind::Block, Some(ScopeKind::Loop), vec!["y"]),
+            ]
+        );
+
+        let Declaration::Function(function) = &program.declarations[0] else {
+            panic!("expected a function");
+        };
+        let function_scope = resolution.scope_of[&index.id(function).unwrap()];
+        assert_eq!(symbols.scope(function_scope).kind, ScopeKind::Function);
+    }
+}
@@ -0,0 +1,238 @@
+//! Definite return analysis: a function that declares a return type must produce a value on
+//! every path through its body.
+//!
+//! A path produces a value by ending in an expression, by reaching a `return`, or by never
+//! finishing, as a `loop` without a `break` does. It falls off the end at a block with no final
+//! expression, an `if` without an `else`, or a `while` or `for` loop, none of which has a value.
+
+use parser::ast::{
+    Declaration, Expression, ExpressionKind, FunctionDeclaration, Program, Statement,
+    StatementKind, Type,
+};
+use parser::node_id::{NodeId, NodeIndex};
+use parser::visit::{Visitor, walk_expression, walk_statement};
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Clone)]
+pub enum ReturnError {
+    /// `node` is the expression where the path ends.
+    #[error("'{function}' should return '{expected}', but this path ends without a value")]
+    MissingValue {
+        function: String,
+        expected: Type,
+        node: NodeId,
+    },
+}
+
+impl ReturnError {
+    pub fn node(&self) -> NodeId {
+        match self {
+            ReturnError::MissingValue { node, .. } => *node,
+        }
+    }
+}
+
+/// Check every function and patch method in `program` that declares a return type.
+pub fn check_returns(program: &Program, index: &NodeIndex) -> Vec<ReturnError> {
+    let functions = program
+        .declarations
+        .iter()
+        .flat_map(|declaration| match declaration {
+            Declaration::Function(function) => std::slice::from_ref(function),
+            Declaration::Patch(patch) => patch.methods.as_slice(),
+            _ => &[],
+        });
+    functions
+        .filter_map(|function| check_function(function, index))
+        .collect()
+}
+
+fn check_function(function: &FunctionDeclaration, index: &NodeIndex) -> Option<ReturnError> {
+    let expected = function.return_type.as_ref()?;
+    if *expected == Type::Unit {
+        return None;
+    }
+    let end = missing_value(&function.body)?;
+    Some(ReturnError::MissingValue {
+        function: function.name.clone(),
+        expected: expected.clone(),
+        node: index.id(end)?,
+    })
+}
+
+/// The expression where some path through `expression` ends without a value, if there is one.
+pub(crate) fn missing_value(expression: &Expression) -> Option<&Expression> {
+    match &expression.kind {
+        ExpressionKind::Block {
+            statements,
+            final_expression,
+        } => {
+            if statements.iter().any(statement_diverges) {
+                return None;
+            }
+            match final_expression {
+                Some(final_expression) => missing_value(final_expression),
+                None => Some(expression),
+            }
+        }
+        ExpressionKind::If {
+            then_branch,
+            else_branch: Some(else_branch),
+            ..
+        } => missing_value(then_branch).or_else(|| missing_value(else_branch)),
+        ExpressionKind::If {
+            else_branch: None, ..
+        }
+        | ExpressionKind::While { .. }
+        | ExpressionKind::For { .. } => Some(expression),
+        ExpressionKind::Loop { body } => Breaks::of(body).without_value.then_some(expression),
+        ExpressionKind::When { branches, .. } => branches
+            .iter()
+            .find_map(|branch| missing_value(&branch.body)),
+        ExpressionKind::Grouped(inner) => missing_value(inner),
+        _ => None,
+    }
+}
+
+/// Whether control never continues past `statement`, because it leaves the function or loop or
+/// never finishes.
+pub(crate) fn statement_diverges(statement: &Statement) -> bool {
+    match &statement.kind {
+        StatementKind::Return(_) | StatementKind::Break(_) => true,
+        StatementKind::Expression(expression) => diverges(expression),
+        StatementKind::VariableDeclaration { value, .. }
+        | StatementKind::Assignment { value, .. } => diverges(value),
+    }
+}
+
+/// Whether evaluating `expression` never finishes with a value.
+pub(crate) fn diverges(expression: &Expression) -> bool {
+    match &expression.kind {
+        ExpressionKind::Block {
+            statements,
+            final_expression,
+        } => {
+            statements.iter().any(statement_diverges)
+                || final_expression.as_deref().is_some_and(diverges)
+        }
+        ExpressionKind::If {
+            then_branch,
+            else_branch: Some(else_branch),
+            ..
+        } => diverges(then_branch) && diverges(else_branch),
+        ExpressionKind::When { branches, .. } => {
+            !branches.is_empty() && branches.iter().all(|branch| diverges(&branch.body))
+        }
+        ExpressionKind::Loop { body } => !Breaks::of(body).any,
+        ExpressionKind::Grouped(inner) => diverges(inner),
+        _ => false,
+    }
+}
+
+/// The `break`s that leave a loop, rather than a loop inside it.
+#[derive(Default)]
+struct Breaks {
+    any: bool,
+    /// Whether a `break` leaves without a value, so the loop has none.
+    without_value: bool,
+}
+
+impl Breaks {
+    fn of(body: &Expression) -> Self {
+        let mut breaks = Breaks::default();
+        breaks.visit_expression(body);
+        breaks
+    }
+}
+
+impl Visitor for Breaks {
+    fn visit_statement(&mut self, statement: &Statement) {
+        if let StatementKind::Break(value) = &statement.kind {
+            self.any = true;
+            self.without_value |= value.is_none();
+        }
+        walk_statement(self, statement);
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        if !matches!(
+            expression.kind,
+            ExpressionKind::Loop { .. }
+                | ExpressionKind::While { .. }
+                | ExpressionKind::For { .. }
+                | ExpressionKind::Closure { .. }
+        ) {
+            walk_expression(self, expression);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::parse;
+    use test_case::test_case;
+
+    fn return_errors(source: &str) -> Vec<String> {
+        let program = parse(source).expect("Parse error");
+        let index = NodeIndex::new(&program);
+        check_returns(&program, &index)
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test_case("fn f() -> i32 { 1 }" ; "tail expression")]
+    #[test_case("fn f() -> i32 { return 1; }" ; "return statement")]
+    #[test_case("fn f(bool b) -> i32 { if b { return 1; } 2 }" ; "early return")]
+    #[test_case("fn f(bool b) -> i32 { if b { 1 } else { return 2; } }" ; "both branches")]
+    #[test_case("fn f(i32 n) -> i32 { when n { 0: 1; _: { return 2; } } }" ; "when branches")]
+    #[test_case("fn f() -> i32 { loop { print(1); } }" ; "endless loop")]
+    #[test_case("fn f() -> i32 { loop { break 3; } }" ; "loop with a value")]
+    #[test_case("fn f() -> i32 { x = loop { return 1; }; x }" ; "diverging initializer")]
+    #[test_case("fn f() -> i32 { loop { for x in [1] { break; } } }" ; "break from an inner loop")]
+    #[test_case("fn f() { for x in [1] {} }" ; "no return type")]
+    #[test_case("patch i32 { fn double() -> i32 { self * 2 } }" ; "patch method")]
+    fn test_every_path_returns(source: &str) {
+        assert_eq!(return_errors(source), Vec::<String>::new());
+    }
+
+    #[test_case("fn f() -> i32 { print(1); }" ; "no final expression")]
+    #[test_case("fn f(bool b) -> i32 { if b { return 1; } }" ; "if without else")]
+    #[test_case("fn f(bool b) -> i32 { if b { 1 } else { print(0); } }" ; "else branch")]
+    #[test_case("fn f(i32 n) -> i32 { when n { 0: 1; _: {}; } }" ; "when branch")]
+    #[test_case("fn f() -> i32 { for x in [1] { return x; } }" ; "for loop")]
+    #[test_case("fn f() -> i32 { loop { g = || { loop { break; } }; break; } }" ; "loop with a break")]
+    fn test_missing_value(source: &str) {
+        assert_eq!(
+            return_errors(source),
+            ["'f' should return 'i32', but this path ends without a value"]
+        );
+    }
+
+    #[test]
+    fn test_error_points_at_the_path() {
+        let program = parse("fn f(bool b) -> i32 { if b { 1 } else { print(0); } }").unwrap();
+        let index = NodeIndex::new(&program);
+        let errors = check_returns(&program, &index);
+
+        let Declaration::Function(function) = &program.declarations[0] else {
+            panic!("expected a function");
+        };
+        let ExpressionKind::Block {
+            final_expression: Some(if_expression),
+            ..
+        } = &function.body.kind
+        else {
+            panic!("expected a final expression");
+        };
+        let ExpressionKind::If {
+            else_branch: Some(else_branch),
+            ..
+        } = &if_expression.kind
+        else {
+            panic!("expected an if");
+        };
+        assert_eq!(errors[0].node(), index.id(else_branch.as_ref()).unwrap());
+    }
+}
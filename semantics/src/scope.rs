@@ -0,0 +1,54 @@
+//! The scopes of a program.
+//!
+//! Scopes form a tree rooted at the global scope. A function, closure, block, loop, or `when`
+//! branch opens a scope inside the one it appears in, and a name is visible in the scope that
+//! defines it and every scope below. The tree is stored in the
+//! [`SymbolTable`](crate::symbols::SymbolTable) alongside the symbols defined in it.
+
+use crate::symbols::SymbolId;
+use parser::node_id::NodeId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ScopeId(u32);
+
+impl ScopeId {
+    /// The global scope.
+    pub const ROOT: ScopeId = ScopeId(0);
+
+    pub(crate) fn new(index: usize) -> Self {
+        ScopeId(index as u32)
+    }
+
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ScopeKind {
+    /// Functions, records, unions, consts, union variants, and top-level variables.
+    Global,
+    /// The type parameters of a record or union.
+    Generics,
+    /// A function's type parameters and parameters.
+    Function,
+    /// `self` and the methods of a `patch`.
+    Patch,
+    /// A closure's parameters.
+    Closure,
+    Block,
+    /// The body of a `loop`, `while`, or `for`, with the variable of a `for`.
+    Loop,
+    /// The names bound by the pattern of a `when` branch, visible in its guard and body.
+    Branch,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scope {
+    pub kind: ScopeKind,
+    pub parent: Option<ScopeId>,
+    /// The node that opens the scope, or `None` for the global scope.
+    pub node: Option<NodeId>,
+    /// The symbols defined directly in this scope, in the order they are defined.
+    pub symbols: Vec<SymbolId>,
+}
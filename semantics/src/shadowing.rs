@@ -0,0 +1,272 @@
+//! Warnings about declarations that hide another name.
+//!
+//! Declaring a name that an enclosing scope already has is allowed without comment: a block,
+//! loop, branch, or closure can reuse any name. Declaring a variable again in its own scope is
+//! allowed too, as a step in a chain like `data = data.trim();`, but is reported when the new
+//! value does not use the old one, since the old one then cannot be used again. Redeclaring a
+//! mutable variable is reported in any case, because `count = count + 1;` was almost certainly
+//! meant as the assignment `(count) = count + 1;`.
+//!
+//! Code that should never reuse a name can opt in to [`ShadowingLint::All`], which reports every
+//! declaration that hides another.
+
+use crate::resolve::Resolution;
+use crate::symbols::{SymbolId, SymbolKind};
+use parser::ast::{Expression, ExpressionKind, Program, Statement, StatementKind};
+use parser::node_id::{NodeId, NodeIndex};
+use parser::visit::{Visitor, walk_expression, walk_statement};
+use std::collections::{HashMap, HashSet};
+use thiserror::Error;
+
+/// Which shadowing to report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShadowingLint {
+    /// Only variables declared again in their own scope.
+    #[default]
+    SameScope,
+    /// Every declaration that hides another name.
+    All,
+}
+
+#[derive(Debug, Error, PartialEq, Clone)]
+pub enum ShadowWarning {
+    /// `node` is the new declaration and `previous` the mutable variable's.
+    #[error(
+        "'{name}' is declared again in the same scope, hiding a mutable variable; to assign to it, write '({name}) = ...'"
+    )]
+    RedeclaredMutable {
+        name: String,
+        node: NodeId,
+        previous: NodeId,
+    },
+    /// `node` is the new declaration and `previous` the one it hides.
+    #[error("'{name}' is declared again in the same scope without using its previous value")]
+    Redeclared {
+        name: String,
+        node: NodeId,
+        previous: NodeId,
+    },
+    /// Only reported by [`ShadowingLint::All`]. `previous` is `None` for a builtin.
+    #[error("{kind} '{name}' shadows a {previous_kind} of the same name")]
+    Shadowed {
+        name: String,
+        kind: SymbolKind,
+        previous_kind: SymbolKind,
+        node: NodeId,
+        previous: Option<NodeId>,
+    },
+}
+
+impl ShadowWarning {
+    pub fn node(&self) -> NodeId {
+        match self {
+            ShadowWarning::RedeclaredMutable { node, .. }
+            | ShadowWarning::Redeclared { node, .. }
+            | ShadowWarning::Shadowed { node, .. } => *node,
+        }
+    }
+
+    /// The declaration of the name that is hidden.
+    pub fn previous(&self) -> Option<NodeId> {
+        match self {
+            ShadowWarning::RedeclaredMutable { previous, .. }
+            | ShadowWarning::Redeclared { previous, .. } => Some(*previous),
+            ShadowWarning::Shadowed { previous, .. } => *previous,
+        }
+    }
+}
+
+/// Report the shadowing in `program` that `lint` asks for, in the order the names are declared.
+pub fn check_shadowing(
+    program: &Program,
+    index: &NodeIndex,
+    resolution: &Resolution,
+    lint: ShadowingLint,
+) -> Vec<ShadowWarning> {
+    let mut initializers = Initializers {
+        index,
+        resolution,
+        reads: HashMap::new(),
+    };
+    initializers.visit_program(program);
+
+    let symbols = &resolution.symbols;
+    resolution
+        .shadows
+        .iter()
+        .filter_map(|&(id, hidden_id)| {
+            let symbol = symbols.symbol(id);
+            let hidden = symbols.symbol(hidden_id);
+            let name = symbols.symbol_name(id).to_string();
+            let node = symbol.node?;
+            let redeclared = symbol.scope == hidden.scope
+                && matches!(symbol.kind, SymbolKind::Variable { .. })
+                && matches!(hidden.kind, SymbolKind::Variable { .. });
+            if redeclared {
+                let previous = hidden.node?;
+                if hidden.kind == (SymbolKind::Variable { is_mutable: true }) {
+                    return Some(ShadowWarning::RedeclaredMutable {
+                        name,
+                        node,
+                        previous,
+                    });
+                }
+                let uses_previous = initializers
+                    .reads
+                    .get(&node)
+                    .is_some_and(|reads| reads.contains(&hidden_id));
+                if !uses_previous {
+                    return Some(ShadowWarning::Redeclared {
+                        name,
+                        node,
+                        previous,
+                    });
+                }
+            }
+            (lint == ShadowingLint::All).then_some(ShadowWarning::Shadowed {
+                name,
+                kind: symbol.kind,
+                previous_kind: hidden.kind,
+                node,
+                previous: hidden.node,
+            })
+        })
+        .collect()
+}
+
+/// The symbols the value of each variable declaration reads, by the declaration.
+struct Initializers<'r, 'i> {
+    index: &'i NodeIndex,
+    resolution: &'r Resolution,
+    reads: HashMap<NodeId, HashSet<SymbolId>>,
+}
+
+impl Visitor for Initializers<'_, '_> {
+    fn visit_statement(&mut self, statement: &Statement) {
+        if let StatementKind::VariableDeclaration { value, .. } = &statement.kind
+            && let Some(node) = self.index.id(statement)
+        {
+            let mut reads = Reads {
+                index: self.index,
+                resolution: self.resolution,
+                symbols: HashSet::new(),
+            };
+            reads.visit_expression(value);
+            self.reads.insert(node, reads.symbols);
+        }
+        walk_statement(self, statement);
+    }
+}
+
+/// Collects the symbols an expression reads.
+struct Reads<'r, 'i> {
+    index: &'i NodeIndex,
+    resolution: &'r Resolution,
+    symbols: HashSet<SymbolId>,
+}
+
+impl Visitor for Reads<'_, '_> {
+    fn visit_expression(&mut self, expression: &Expression) {
+        if let ExpressionKind::Identifier(_) = expression.kind
+            && let Some(symbol) = self
+                .index
+                .id(expression)
+                .and_then(|node| self.resolution.symbol_of(node))
+        {
+            self.symbols.insert(symbol);
+        }
+        walk_expression(self, expression);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostic::Diagnostic;
+    use crate::resolve::resolve;
+    use parser::parse;
+    use test_case::test_case;
+
+    fn warnings(source: &str, lint: ShadowingLint) -> Vec<String> {
+        let program = parse(source).expect("Parse error");
+        let index = NodeIndex::new(&program);
+        let resolution = resolve(&program, &index);
+        assert!(resolution.errors.is_empty(), "{:?}", resolution.errors);
+        check_shadowing(&program, &index, &resolution, lint)
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test_case("fn f(string data) { data = data.trim(); data = data.lower(); print(data); }" ; "transformation chain")]
+    #[test_case("fn f() { x = 1; { x = 2; print(x); }; print(x); }" ; "nested block")]
+    #[test_case("fn f(i32 n) { n = n + 1; print(n); }" ; "parameter")]
+    #[test_case("fn f(arrayList<i32> xs) { x = 0; for x in xs { print(x); }; print(x); }" ; "loop variable")]
+    #[test_case("x = 1; fn f() { g = |x| x + 1; print(g(x)); }" ; "closure parameter")]
+    #[test_case("fn f(i32 @n) { n = n * 2; print(n); }" ; "mutable parameter")]
+    #[test_case("fn f() { print = 1; }" ; "builtin")]
+    fn test_allowed(source: &str) {
+        assert_eq!(
+            warnings(source, ShadowingLint::SameScope),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test_case(
+        "fn f() { x = 1; print(x); x = 2; print(x); }",
+        "'x' is declared again in the same scope without using its previous value" ;
+        "unrelated value"
+    )]
+    #[test_case(
+        "fn f() { @count = 0; count = count + 1; print(count); }",
+        "'count' is declared again in the same scope, hiding a mutable variable; to assign to it, write '(count) = ...'" ;
+        "mutable variable"
+    )]
+    #[test_case(
+        "limit = 1; print(limit); limit = 2;",
+        "'limit' is declared again in the same scope without using its previous value" ;
+        "top level"
+    )]
+    fn test_same_scope(source: &str, expected: &str) {
+        assert_eq!(warnings(source, ShadowingLint::SameScope), [expected]);
+    }
+
+    #[test]
+    fn test_lint_reports_all_shadowing() {
+        let source = "
+            fn f(string data, arrayList<i32> xs) {
+                data = data.trim();
+                for data in xs { print(data); }
+                print = 1;
+                x = 1; print(x); x = 2; print(x);
+            }";
+
+        assert_eq!(
+            warnings(source, ShadowingLint::All),
+            [
+                "variable 'data' shadows a parameter of the same name",
+                "loop variable 'data' shadows a variable of the same name",
+                "variable 'print' shadows a builtin function of the same name",
+                "'x' is declared again in the same scope without using its previous value",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_warning_points_at_both_declarations() {
+        let program = parse("fn f() { @n = 0; n = 1; print(n); }").unwrap();
+        let index = NodeIndex::new(&program);
+        let resolution = resolve(&program, &index);
+        let warnings = check_shadowing(&program, &index, &resolution, ShadowingLint::SameScope);
+        let diagnostic = Diagnostic::from(warnings[0].clone());
+
+        let parser::ast::Declaration::Function(function) = &program.declarations[0] else {
+            panic!("expected a function");
+        };
+        let ExpressionKind::Block { statements, .. } = &function.body.kind else {
+            panic!("expected a block");
+        };
+        assert_eq!(diagnostic.node, index.id(&statements[1]).unwrap());
+        assert_eq!(diagnostic.related, index.id(&statements[0]));
+    }
+}
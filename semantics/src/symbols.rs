@@ -0,0 +1,396 @@
+//! The symbol table: every name a program defines, the scope it is defined in, and what it is.
+//!
+//! The resolver fills the table, and the passes after it (type checking, and editor features
+//! such as go-to-definition) read it instead of walking scopes of their own. Names are interned,
+//! so comparing two of them compares integers.
+
+use crate::scope::{Scope, ScopeId, ScopeKind};
+use parser::node_id::NodeId;
+use std::collections::HashMap;
+use std::fmt;
+
+/// An interned name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Name(u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SymbolId(u32);
+
+impl SymbolId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SymbolKind {
+    /// A function provided by the language rather than declared in the program.
+    Builtin,
+    /// A function provided by the application running the program.
+    Host,
+    Function,
+    Const,
+    Record,
+    Union,
+    Variant,
+    /// A method added to a type by a `patch`. Methods are only called on a receiver, so their
+    /// names are not values.
+    PatchMethod,
+    TypeParameter,
+    /// A type parameter with an integer type, such as `N` in `fn f<N: usize>()`.
+    ConstParameter,
+    Parameter,
+    Variable {
+        is_mutable: bool,
+    },
+    /// A name bound by a pattern.
+    Binding,
+    /// The variable of a `for` loop.
+    LoopVariable,
+    /// `self` in a patch method.
+    SelfValue,
+}
+
+impl SymbolKind {
+    /// Whether the name can be used in an expression. Records, unions, and type parameters name
+    /// types, and patch methods are called through a receiver.
+    pub fn is_value(self) -> bool {
+        !matches!(
+            self,
+            SymbolKind::Record
+                | SymbolKind::Union
+                | SymbolKind::TypeParameter
+                | SymbolKind::PatchMethod
+        )
+    }
+}
+
+impl fmt::Display for SymbolKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind_str = match self {
+            SymbolKind::Builtin => "builtin function",
+            SymbolKind::Host => "host function",
+            SymbolKind::Function => "function",
+            SymbolKind::Const => "const",
+            SymbolKind::Record => "record",
+            SymbolKind::Union => "union",
+            SymbolKind::Variant => "variant",
+            SymbolKind::PatchMethod => "method",
+            SymbolKind::TypeParameter => "type parameter",
+            SymbolKind::ConstParameter => "const parameter",
+            SymbolKind::Parameter => "parameter",
+            SymbolKind::Variable { .. } => "variable",
+            SymbolKind::Binding => "binding",
+            SymbolKind::LoopVariable => "loop variable",
+            SymbolKind::SelfValue => "self",
+        };
+        write!(f, "{}", kind_str)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: Name,
+    pub kind: SymbolKind,
+    pub scope: ScopeId,
+    /// The node that defines the symbol, or `None` for a builtin. The syntax tree has no spans,
+    /// so this is where a definition is located.
+    pub node: Option<NodeId>,
+}
+
+/// How many scopes and symbols a table had at some point, to tell the ones added since apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Mark {
+    scopes: usize,
+    symbols: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SymbolTable {
+    names: Vec<String>,
+    name_ids: HashMap<String, Name>,
+    scopes: Vec<Scope>,
+    symbols: Vec<Symbol>,
+}
+
+impl Default for SymbolTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SymbolTable {
+    /// A table holding only the empty global scope.
+    pub fn new() -> Self {
+        SymbolTable {
+            names: Vec::new(),
+            name_ids: HashMap::new(),
+            scopes: vec![Scope {
+                kind: ScopeKind::Global,
+                parent: None,
+                node: None,
+                symbols: Vec::new(),
+            }],
+            symbols: Vec::new(),
+        }
+    }
+
+    pub fn intern(&mut self, name: &str) -> Name {
+        if let Some(&id) = self.name_ids.get(name) {
+            return id;
+        }
+        let id = Name(self.names.len() as u32);
+        self.names.push(name.to_string());
+        self.name_ids.insert(name.to_string(), id);
+        id
+    }
+
+    /// The interned `name`, or `None` if no symbol has ever had it.
+    pub fn name_id(&self, name: &str) -> Option<Name> {
+        self.name_ids.get(name).copied()
+    }
+
+    pub fn name(&self, name: Name) -> &str {
+        &self.names[name.0 as usize]
+    }
+
+    pub fn root(&self) -> ScopeId {
+        ScopeId::ROOT
+    }
+
+    pub fn scope(&self, id: ScopeId) -> &Scope {
+        &self.scopes[id.index()]
+    }
+
+    pub fn symbol(&self, id: SymbolId) -> &Symbol {
+        &self.symbols[id.index()]
+    }
+
+    /// The name of symbol `id` as text.
+    pub fn symbol_name(&self, id: SymbolId) -> &str {
+        self.name(self.symbol(id).name)
+    }
+
+    pub fn scopes(&self) -> impl Iterator<Item = (ScopeId, &Scope)> {
+        self.scopes
+            .iter()
+            .enumerate()
+            .map(|(index, scope)| (ScopeId::new(index), scope))
+    }
+
+    pub fn symbols(&self) -> impl Iterator<Item = (SymbolId, &Symbol)> {
+        (0..).map(SymbolId).zip(&self.symbols)
+    }
+
+    /// The symbols defined since `mark`, in the order they were defined.
+    pub fn symbols_since(&self, mark: Mark) -> impl Iterator<Item = (SymbolId, &Symbol)> {
+        self.symbols().skip(mark.symbols)
+    }
+
+    /// How many scopes and symbols the table has now.
+    pub fn mark(&self) -> Mark {
+        Mark {
+            scopes: self.scopes.len(),
+            symbols: self.symbols.len(),
+        }
+    }
+
+    /// Remove every scope opened and every symbol defined since `mark`. Names interned since
+    /// stay interned.
+    pub fn truncate(&mut self, mark: Mark) {
+        self.scopes.truncate(mark.scopes);
+        self.symbols.truncate(mark.symbols);
+        for scope in &mut self.scopes {
+            while scope
+                .symbols
+                .last()
+                .is_some_and(|symbol| symbol.index() >= mark.symbols)
+            {
+                scope.symbols.pop();
+            }
+        }
+    }
+
+    /// The symbols defined directly in `scope`, in the order they were defined.
+    pub fn symbols_in(&self, scope: ScopeId) -> impl Iterator<Item = (SymbolId, &Symbol)> {
+        self.scope(scope)
+            .symbols
+            .iter()
+            .map(|&id| (id, self.symbol(id)))
+    }
+
+    /// Open a scope inside `parent`.
+    pub fn add_scope(&mut self, kind: ScopeKind, parent: ScopeId, node: Option<NodeId>) -> ScopeId {
+        let id = ScopeId::new(self.scopes.len());
+        self.scopes.push(Scope {
+            kind,
+            parent: Some(parent),
+            node,
+            symbols: Vec::new(),
+        });
+        id
+    }
+
+    /// Define `name` in `scope`. A later definition of the same name in the same scope shadows
+    /// this one.
+    pub fn define(
+        &mut self,
+        scope: ScopeId,
+        name: &str,
+        kind: SymbolKind,
+        node: Option<NodeId>,
+    ) -> SymbolId {
+        let name = self.intern(name);
+        let id = SymbolId(self.symbols.len() as u32);
+        self.symbols.push(Symbol {
+            name,
+            kind,
+            scope,
+            node,
+        });
+        self.scopes[scope.index()].symbols.push(id);
+        id
+    }
+
+    /// The latest symbol named `name` and accepted by `filter` that is defined in `scope` itself.
+    pub fn lookup_in(
+        &self,
+        scope: ScopeId,
+        name: &str,
+        filter: impl Fn(&Symbol) -> bool,
+    ) -> Option<SymbolId> {
+        let name = self.name_id(name)?;
+        self.scope(scope).symbols.iter().rev().copied().find(|&id| {
+            let symbol = self.symbol(id);
+            symbol.name == name && filter(symbol)
+        })
+    }
+
+    /// The latest symbol named `name` and accepted by `filter` that is visible in `scope`: the
+    /// one in `scope` itself, or in the nearest scope above it that has one.
+    pub fn lookup(
+        &self,
+        scope: ScopeId,
+        name: &str,
+        filter: impl Fn(&Symbol) -> bool,
+    ) -> Option<SymbolId> {
+        let mut current = Some(scope);
+        while let Some(scope) = current {
+            if let Some(id) = self.lookup_in(scope, name, &filter) {
+                return Some(id);
+            }
+            current = self.scope(scope).parent;
+        }
+        None
+    }
+
+    /// Whether `scope` is `ancestor` or lies inside it.
+    pub fn is_within(&self, scope: ScopeId, ancestor: ScopeId) -> bool {
+        let mut current = Some(scope);
+        while let Some(scope) = current {
+            if scope == ancestor {
+                return true;
+            }
+            current = self.scope(scope).parent;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VARIABLE: SymbolKind = SymbolKind::Variable { is_mutable: false };
+
+    #[test]
+    fn test_names_are_interned() {
+        let mut table = SymbolTable::new();
+        let x = table.intern("x");
+
+        assert_eq!(table.intern("x"), x);
+        assert_ne!(table.intern("y"), x);
+        assert_eq!(table.name(x), "x");
+        assert_eq!(table.name_id("z"), None);
+    }
+
+    #[test]
+    fn test_inner_symbols_shadow_outer_ones() {
+        let mut table = SymbolTable::new();
+        let root = table.root();
+        let outer = table.define(root, "x", SymbolKind::Const, None);
+        let block = table.add_scope(ScopeKind::Block, root, None);
+
+        assert_eq!(table.lookup(block, "x", |_| true), Some(outer));
+        assert_eq!(table.lookup_in(block, "x", |_| true), None);
+
+        let first = table.define(block, "x", VARIABLE, None);
+        let second = table.define(block, "x", VARIABLE, None);
+        assert_ne!(first, second);
+        assert_eq!(table.symbol(first).name, table.symbol(outer).name);
+        assert_eq!(table.lookup(block, "x", |_| true), Some(second));
+        assert_eq!(table.lookup(root, "x", |_| true), Some(outer));
+        assert_eq!(
+            table.lookup(block, "x", |symbol| symbol.kind == SymbolKind::Const),
+            Some(outer)
+        );
+        assert_eq!(table.lookup(block, "y", |_| true), None);
+    }
+
+    #[test]
+    fn test_symbols_in_scope() {
+        let mut table = SymbolTable::new();
+        let root = table.root();
+        let patch = table.add_scope(ScopeKind::Patch, root, None);
+        table.define(patch, "self", SymbolKind::SelfValue, None);
+        table.define(patch, "norm", SymbolKind::PatchMethod, None);
+        table.define(root, "main", SymbolKind::Function, None);
+
+        let symbols: Vec<(&str, SymbolKind)> = table
+            .symbols_in(patch)
+            .map(|(id, symbol)| (table.symbol_name(id), symbol.kind))
+            .collect();
+        assert_eq!(
+            symbols,
+            [
+                ("self", SymbolKind::SelfValue),
+                ("norm", SymbolKind::PatchMethod)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_truncate() {
+        let mut table = SymbolTable::new();
+        let root = table.root();
+        let kept = table.define(root, "x", SymbolKind::Const, None);
+        let mark = table.mark();
+        let block = table.add_scope(ScopeKind::Block, root, None);
+        table.define(block, "y", VARIABLE, None);
+        let added = table.define(root, "x", SymbolKind::Function, None);
+
+        assert_eq!(
+            table.symbols_since(mark).map(|(id, _)| id).last(),
+            Some(added)
+        );
+        table.truncate(mark);
+        assert_eq!(table.mark(), mark);
+        assert_eq!(table.lookup(root, "x", |_| true), Some(kept));
+        assert_eq!(table.scopes().count(), 1);
+    }
+
+    #[test]
+    fn test_is_within() {
+        let mut table = SymbolTable::new();
+        let root = table.root();
+        let function = table.add_scope(ScopeKind::Function, root, None);
+        let block = table.add_scope(ScopeKind::Block, function, None);
+        let other = table.add_scope(ScopeKind::Function, root, None);
+
+        assert!(table.is_within(block, function));
+        assert!(table.is_within(block, root));
+        assert!(table.is_within(block, block));
+        assert!(!table.is_within(block, other));
+        assert!(!table.is_within(function, block));
+    }
+}
@@ -0,0 +1,2392 @@
+//! Type checking: giving every expression a type and checking that the types fit together.
+//!
+//! The checker runs after name resolution and follows its symbols. It checks each value against
+//! the type that is expected where it appears: the declared type of a variable, const, parameter,
+//! or return value; `bool` for a condition; and the other branch of an `if` or `when`. Integer and
+//! float literals take the expected type when it is a numeric one, so `u8 age = 30;` is fine.
+//!
+//! A method call is looked up in the patches for the receiver's type, including patches of
+//! primitive types such as `patch i32` and of arrays such as `patch arrayList<T>`, and checked
+//! against the method it finds; a method no patch declares is an error. A call to a builtin is
+//! checked against what the builtin takes: `print` and `println` any values, `len` an array,
+//! string, or range, `assert` a `bool`, and `to_string` any one value.
+//!
+//! Inside a generic declaration, a type parameter such as `T` is a type of its own: it fits only
+//! itself, and only operators work on it, since the declaration is checked once for every type
+//! it may stand for. A call to a generic function or variant gives each type parameter the type
+//! the expected result or the arguments give it, and is checked with those types substituted.
+//!
+//! [`Type::Inferred`] stands for a type the checker cannot know yet, such as the result of a
+//! method of a type parameter or of a call already reported as an error. It fits every type, so
+//! an unknown type never causes an error, and one mistake is reported once rather than again in
+//! every expression that uses it.
+
+use crate::diagnostic::Severity;
+use crate::monomorphize::bind;
+use crate::resolve::{Host, Resolution};
+use crate::returns;
+use crate::symbols::{SymbolId, SymbolKind};
+use parser::ast::{
+    ArraySize, BinaryOperator, Declaration, Expression, ExpressionKind, FunctionDeclaration,
+    Literal, Parameter, PatchDeclaration, Pattern, PatternKind, Program, RecordDeclaration,
+    Statement, StatementKind, Type, TypeParameter, UnaryOperator, UnionDeclaration, UnionVariant,
+};
+use parser::node_id::{Node, NodeId, NodeIndex};
+use parser::operators::{ResultType, TypeClass, integer_bounds};
+use std::collections::{HashMap, HashSet};
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Clone)]
+pub enum TypeError {
+    #[error("Expected '{expected}', found '{found}'")]
+    Mismatch {
+        expected: Type,
+        found: Type,
+        node: NodeId,
+    },
+    #[error("'{function}' takes {expected} arguments, but {found} were given")]
+    ArgumentCount {
+        function: String,
+        expected: usize,
+        found: usize,
+        node: NodeId,
+    },
+    /// `node` is the function's body or the value of the `return`.
+    #[error("'{function}' should return '{expected}', but returns '{found}'")]
+    ReturnMismatch {
+        function: String,
+        expected: Type,
+        found: Type,
+        node: NodeId,
+    },
+    #[error("Cannot call a value of type '{found}'")]
+    NotCallable { found: Type, node: NodeId },
+    /// `node` is the argument, which is of a type the builtin `function` does not take.
+    #[error("'{function}' cannot take a value of type '{found}'")]
+    InvalidArgument {
+        function: String,
+        found: Type,
+        node: NodeId,
+    },
+    #[error("Operator '{operator}' cannot be applied to '{left}' and '{right}'")]
+    InvalidOperands {
+        operator: BinaryOperator,
+        left: Type,
+        right: Type,
+        node: NodeId,
+    },
+    #[error("Operator '{operator}' cannot be applied to '{operand}'")]
+    InvalidOperand {
+        operator: UnaryOperator,
+        operand: Type,
+        node: NodeId,
+    },
+    #[error("Type '{record_type}' has no field '{field}'")]
+    UnknownField {
+        record_type: Type,
+        field: String,
+        node: NodeId,
+    },
+    /// `node` is the record literal or pattern.
+    #[error("Unknown record type '{name}'")]
+    UnknownRecord { name: String, node: NodeId },
+    /// `node` is the record literal, which neither sets the field nor has a base to take it from.
+    #[error("Record literal of '{record_type}' is missing field '{field}'")]
+    MissingField {
+        record_type: Type,
+        field: String,
+        node: NodeId,
+    },
+    #[error("Cannot index into a value of type '{found}'")]
+    NotIndexable { found: Type, node: NodeId },
+    #[error("Cannot iterate over a value of type '{found}'")]
+    NotIterable { found: Type, node: NodeId },
+    /// `node` is the literal, with its sign if it has one.
+    #[error("Literal '{value}' does not fit in '{ty}'")]
+    LiteralOutOfRange { value: i128, ty: Type, node: NodeId },
+    #[error("Cannot cast '{from}' to '{to}'")]
+    InvalidCast { from: Type, to: Type, node: NodeId },
+    /// A warning: the cast is allowed but may not keep the value, as when narrowing an integer or
+    /// turning a float into one.
+    #[error("Casting '{from}' to '{to}' may lose information")]
+    LossyCast { from: Type, to: Type, node: NodeId },
+    /// `node` is the method call.
+    #[error("Type '{receiver}' has no method '{method}'")]
+    UnknownMethod {
+        method: String,
+        receiver: Type,
+        node: NodeId,
+    },
+    /// `node` is the method call.
+    #[error("Method '{method}' of '{receiver}' is defined by more than one patch")]
+    AmbiguousMethod {
+        method: String,
+        receiver: Type,
+        node: NodeId,
+    },
+    /// A warning: `node` is an expression statement whose value is thrown away.
+    #[error("Result of type '{ty}' is not used")]
+    UnusedResult { ty: Type, node: NodeId },
+    /// `node` is the pattern.
+    #[error("Pattern '{pattern}' cannot match a value of type '{ty}'")]
+    PatternMismatch {
+        pattern: String,
+        ty: Type,
+        node: NodeId,
+    },
+    /// `node` is the pattern, which matches a payload `variant` has not, or leaves out the one it
+    /// has.
+    #[error(
+        "Variant '{variant}' {}",
+        if *has_payload { "has a payload the pattern must match" } else { "has no payload to match" }
+    )]
+    PayloadMismatch {
+        variant: String,
+        has_payload: bool,
+        node: NodeId,
+    },
+}
+
+impl TypeError {
+    /// The node the error is about.
+    pub fn node(&self) -> NodeId {
+        match self {
+            TypeError::Mismatch { node, .. }
+            | TypeError::ArgumentCount { node, .. }
+            | TypeError::ReturnMismatch { node, .. }
+            | TypeError::NotCallable { node, .. }
+            | TypeError::InvalidArgument { node, .. }
+            | TypeError::InvalidOperands { node, .. }
+            | TypeError::InvalidOperand { node, .. }
+            | TypeError::UnknownField { node, .. }
+            | TypeError::UnknownRecord { node, .. }
+            | TypeError::MissingField { node, .. }
+            | TypeError::NotIndexable { node, .. }
+            | TypeError::NotIterable { node, .. }
+            | TypeError::LiteralOutOfRange { node, .. }
+            | TypeError::InvalidCast { node, .. }
+            | TypeError::LossyCast { node, .. }
+            | TypeError::UnknownMethod { node, .. }
+            | TypeError::AmbiguousMethod { node, .. }
+            | TypeError::UnusedResult { node, .. }
+            | TypeError::PatternMismatch { node, .. }
+            | TypeError::PayloadMismatch { node, .. } => *node,
+        }
+    }
+
+    /// A lossy cast and an unused result are warnings; everything else is an error.
+    pub fn severity(&self) -> Severity {
+        match self {
+            TypeError::LossyCast { .. } | TypeError::UnusedResult { .. } => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+/// The type of every expression, by node, and of every symbol that names a value, along with the
+/// patch method each method call calls, the patterns that cannot match the values they are
+/// matched against, and the expressions whose value is never used.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TypeMap {
+    expressions: HashMap<NodeId, Type>,
+    symbols: HashMap<SymbolId, Type>,
+    methods: HashMap<NodeId, NodeId>,
+    mismatched: HashSet<NodeId>,
+    discarded: HashSet<NodeId>,
+    /// The type parameters of the generic functions and variants, whose types mention them.
+    generics: HashMap<SymbolId, Vec<TypeParameter>>,
+}
+
+impl TypeMap {
+    /// The type of the expression `node`.
+    pub fn expression(&self, node: NodeId) -> Option<&Type> {
+        self.expressions.get(&node)
+    }
+
+    /// The type of the value `symbol` names.
+    pub fn symbol(&self, symbol: SymbolId) -> Option<&Type> {
+        self.symbols.get(&symbol)
+    }
+
+    /// The declaration of the patch method the method call `node` calls, if it calls one.
+    pub fn method(&self, node: NodeId) -> Option<NodeId> {
+        self.methods.get(&node).copied()
+    }
+
+    /// Whether the pattern `node` cannot match the values it is matched against.
+    pub fn is_mismatched(&self, node: NodeId) -> bool {
+        self.mismatched.contains(&node)
+    }
+
+    /// Whether the value of the expression `node` is never used, as that of an expression
+    /// statement is. A `when` whose value is unused may match no branch.
+    pub fn is_discarded(&self, node: NodeId) -> bool {
+        self.discarded.contains(&node)
+    }
+
+    /// The type parameters of the generic function or variant `symbol`, which its type mentions.
+    pub fn generics(&self, symbol: SymbolId) -> Option<&[TypeParameter]> {
+        self.generics.get(&symbol).map(Vec::as_slice)
+    }
+
+    /// Add the types `other` gives symbols.
+    pub(crate) fn add_symbols(&mut self, other: &TypeMap) {
+        self.symbols.extend(
+            other
+                .symbols
+                .iter()
+                .map(|(&symbol, ty)| (symbol, ty.clone())),
+        );
+        self.generics.extend(
+            other
+                .generics
+                .iter()
+                .map(|(&symbol, parameters)| (symbol, parameters.clone())),
+        );
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TypeCheck {
+    pub types: TypeMap,
+    pub errors: Vec<TypeError>,
+}
+
+/// Type check `program`, whose nodes `index` numbers and whose names `resolution` resolves.
+pub fn check(program: &Program, index: &NodeIndex, resolution: &Resolution) -> TypeCheck {
+    check_with_hosts(program, index, resolution, &[])
+}
+
+/// Type check `program` as [`check`] does, with calls to the functions in `hosts` checked
+/// against their types. `resolution` must have been made with the same `hosts`.
+pub fn check_with_hosts(
+    program: &Program,
+    index: &NodeIndex,
+    resolution: &Resolution,
+    hosts: &[Host],
+) -> TypeCheck {
+    let earlier = TypeMap::default();
+    let mut checker = Checker::new(index, resolution, &earlier);
+    let root = resolution.symbols.root();
+    for host in hosts {
+        let symbol = resolution
+            .symbols
+            .lookup_in(root, &host.name, |symbol| symbol.kind == SymbolKind::Host);
+        if let Some(symbol) = symbol {
+            checker.types.symbols.insert(symbol, host.ty.clone());
+        }
+    }
+    checker.program(program);
+    TypeCheck {
+        types: checker.types,
+        errors: checker.errors,
+    }
+}
+
+/// Type check `program` as [`check`] does, after the programs that made `declarations`, whose
+/// symbols `types` gives the types of. `resolution` must have resolved `program` after them.
+pub(crate) fn check_after<'p>(
+    program: &'p Program,
+    index: &NodeIndex,
+    resolution: &Resolution,
+    types: &TypeMap,
+    declarations: &[&'p Declaration],
+) -> TypeCheck {
+    let mut checker = Checker::new(index, resolution, types);
+    for declaration in declarations {
+        checker.register(declaration);
+    }
+    checker.program(program);
+    TypeCheck {
+        types: checker.types,
+        errors: checker.errors,
+    }
+}
+
+struct Checker<'p, 'i> {
+    index: &'i NodeIndex,
+    resolution: &'i Resolution,
+    /// The types of the symbols of the programs checked before this one.
+    earlier: &'i TypeMap,
+    /// The symbol each defining node defines.
+    defined_by: HashMap<NodeId, SymbolId>,
+    records: HashMap<&'p str, &'p RecordDeclaration>,
+    variants: HashMap<NodeId, (&'p UnionDeclaration, &'p UnionVariant)>,
+    patches: Vec<&'p PatchDeclaration>,
+    types: TypeMap,
+    errors: Vec<TypeError>,
+    /// The type parameters in scope, each a type of its own inside the declaration that has it.
+    type_parameters: Vec<String>,
+    /// The name and return type of each function or closure being checked, innermost last.
+    returns: Vec<(String, Type)>,
+    /// The type of the values each enclosing loop breaks with, once one is seen.
+    loops: Vec<Option<Type>>,
+}
+
+impl<'p, 'i> Checker<'p, 'i> {
+    fn new(index: &'i NodeIndex, resolution: &'i Resolution, earlier: &'i TypeMap) -> Self {
+        Checker {
+            index,
+            resolution,
+            earlier,
+            defined_by: resolution
+                .own_symbols()
+                .filter_map(|(id, symbol)| Some((symbol.node?, id)))
+                .collect(),
+            records: HashMap::new(),
+            variants: HashMap::new(),
+            patches: Vec::new(),
+            types: TypeMap::default(),
+            errors: Vec::new(),
+            type_parameters: Vec::new(),
+            returns: Vec::new(),
+            loops: Vec::new(),
+        }
+    }
+
+    /// Set the type of the symbol `node` defines.
+    fn define<T: Node>(&mut self, node: &T, ty: Type) {
+        if let Some(&symbol) = self.defined_by.get(&self.index.expect_id(node)) {
+            self.types.symbols.insert(symbol, ty);
+        }
+    }
+
+    /// Set the type of the generic function or variant `node` declares, which mentions
+    /// `parameters`.
+    fn define_generic<T: Node>(&mut self, node: &T, ty: Type, parameters: &[TypeParameter]) {
+        if let Some(&symbol) = self.defined_by.get(&self.index.expect_id(node)) {
+            self.types.symbols.insert(symbol, ty);
+            if !parameters.is_empty() {
+                self.types.generics.insert(symbol, parameters.to_vec());
+            }
+        }
+    }
+
+    fn symbol_type(&self, symbol: SymbolId) -> Type {
+        self.types
+            .symbol(symbol)
+            .or_else(|| self.earlier.symbol(symbol))
+            .cloned()
+            .unwrap_or(Type::Inferred)
+    }
+
+    /// The type parameters of the generic function or variant `symbol`, or none.
+    fn symbol_generics(&self, symbol: SymbolId) -> &[TypeParameter] {
+        self.types
+            .generics(symbol)
+            .or_else(|| self.earlier.generics(symbol))
+            .unwrap_or_default()
+    }
+
+    /// Whether `ty` is one of the type parameters in scope.
+    fn is_type_parameter(&self, ty: &Type) -> bool {
+        matches!(ty, Type::Named(name) if self.type_parameters.contains(name))
+    }
+
+    /// Check `f` with `parameters` as the type parameters in scope.
+    fn with_type_parameters<T>(
+        &mut self,
+        parameters: impl IntoIterator<Item = String>,
+        f: impl FnOnce(&mut Self) -> T,
+    ) -> T {
+        let outer = self.type_parameters.len();
+        self.type_parameters.extend(parameters);
+        let result = f(self);
+        self.type_parameters.truncate(outer);
+        result
+    }
+
+    fn program(&mut self, program: &'p Program) {
+        self.types.discarded = crate::exhaustive::discarded(program, self.index);
+        for declaration in &program.declarations {
+            self.declare(declaration);
+        }
+        // Top-level statements run before any function is called, as in name resolution.
+        let (bodies, others): (Vec<_>, Vec<_>) =
+            program.declarations.iter().partition(|declaration| {
+                matches!(
+                    declaration,
+                    Declaration::Function(_) | Declaration::Patch(_)
+                )
+            });
+        for declaration in others.into_iter().chain(bodies) {
+            match declaration {
+                Declaration::Function(function) => self.function(function),
+                Declaration::Patch(patch) => self.patch(patch),
+                Declaration::Const(constant) => {
+                    self.check(&constant.value, &constant.const_type);
+                }
+                Declaration::Statement(statement) => {
+                    self.returns.push(("top level".to_string(), Type::Inferred));
+                    self.statement(statement);
+                    self.returns.pop();
+                }
+                Declaration::Record(_) | Declaration::Union(_) | Declaration::Import(_) => {}
+            }
+        }
+    }
+
+    /// Make the records, union variants, and patches `declaration` declares known to the
+    /// program, which may be a later one than the declaration's.
+    fn register(&mut self, declaration: &'p Declaration) {
+        match declaration {
+            Declaration::Patch(patch) => self.patches.push(patch),
+            Declaration::Record(record) => {
+                self.records.insert(&record.name, record);
+            }
+            Declaration::Union(union) => {
+                for variant in &union.variants {
+                    self.variants.insert(variant.id, (union, variant));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Record the types of the names a declaration makes visible throughout the program.
+    fn declare(&mut self, declaration: &'p Declaration) {
+        self.register(declaration);
+        match declaration {
+            Declaration::Function(function) => {
+                let ty = self.function_type(function, &[]);
+                self.define_generic(function, ty, &function.type_parameters);
+            }
+            Declaration::Patch(patch) => {
+                let parameters = patch_type_parameters(patch);
+                for method in &patch.methods {
+                    let mut unknown = parameters.clone();
+                    unknown.extend(method.type_parameters.iter().map(|p| p.name.clone()));
+                    let ty = self.function_type(method, &unknown);
+                    self.define(method, ty);
+                }
+            }
+            Declaration::Union(union) => {
+                for variant in &union.variants {
+                    match &variant.variant_type {
+                        Some(payload) => {
+                            let ty = Type::Function {
+                                param_types: vec![payload.clone()],
+                                return_type: Some(Box::new(generic_union_type(union))),
+                            };
+                            self.define_generic(variant, ty, &union.type_parameters);
+                        }
+                        None => self.define(variant, union_type(union)),
+                    }
+                }
+            }
+            Declaration::Const(constant) => self.define(constant, constant.const_type.clone()),
+            Declaration::Record(_) | Declaration::Statement(_) | Declaration::Import(_) => {}
+        }
+    }
+
+    /// The type of `function` as a value, with the type parameters in `unknown` unknown.
+    fn function_type(&self, function: &FunctionDeclaration, unknown: &[String]) -> Type {
+        let unknown: HashMap<&str, Type> = unknown
+            .iter()
+            .map(|name| (name.as_str(), Type::Inferred))
+            .collect();
+        Type::Function {
+            param_types: function
+                .params
+                .iter()
+                .map(|parameter| substitute(&parameter.param_type, &unknown))
+                .collect(),
+            return_type: function
+                .return_type
+                .as_ref()
+                .map(|return_type| Box::new(substitute(return_type, &unknown))),
+        }
+    }
+
+    fn patch(&mut self, patch: &'p PatchDeclaration) {
+        self.with_type_parameters(patch_type_parameters(patch), |checker| {
+            let self_type = patch.target_type.clone();
+            checker.define(patch, self_type);
+            for method in &patch.methods {
+                checker.function(method);
+            }
+        });
+    }
+
+    fn function(&mut self, function: &'p FunctionDeclaration) {
+        let type_parameters = function.type_parameters.iter().map(|p| p.name.clone());
+        self.with_type_parameters(type_parameters, |checker| {
+            for parameter in &function.type_parameters {
+                checker.type_parameter(parameter);
+            }
+            for parameter in &function.params {
+                checker.parameter(parameter, None);
+            }
+            let expected = function.result_type();
+            checker
+                .returns
+                .push((function.name.clone(), expected.clone()));
+            let found = checker.expression(&function.body, Some(&expected));
+            checker.returns.pop();
+            // A body that can end without any value is reported by the return path checks,
+            // which point at where it ends.
+            let missing_value =
+                found == Type::Unit && returns::missing_value(&function.body).is_some();
+            if !compatible(&expected, &found) && !missing_value {
+                checker.errors.push(TypeError::ReturnMismatch {
+                    function: function.name.clone(),
+                    expected,
+                    found,
+                    node: checker.index.expect_id(function.body.as_ref()),
+                });
+            }
+        });
+    }
+
+    fn type_parameter(&mut self, parameter: &TypeParameter) {
+        if let Some(const_type) = &parameter.const_type {
+            self.define(parameter, const_type.clone());
+        }
+    }
+
+    /// Give a parameter its written type, or `inferred` if it has none.
+    fn parameter(&mut self, parameter: &Parameter, inferred: Option<&Type>) {
+        let ty = match (&parameter.param_type, inferred) {
+            (Type::Inferred, Some(inferred)) => inferred.clone(),
+            (written, _) => written.clone(),
+        };
+        self.define(parameter, ty);
+    }
+
+    fn statement(&mut self, statement: &'p Statement) {
+        match &statement.kind {
+            StatementKind::VariableDeclaration {
+                var_type, value, ..
+            } => {
+                let ty = match var_type {
+                    Some(var_type) => {
+                        let var_type = var_type.clone();
+                        self.check(value, &var_type);
+                        var_type
+                    }
+                    None => self.expression(value, None),
+                };
+                self.define(statement, ty);
+            }
+            StatementKind::Assignment {
+                target,
+                operator,
+                value,
+            } => {
+                let target_type = self.expression(target, None);
+                if *operator == BinaryOperator::Assign {
+                    self.check(value, &target_type);
+                } else {
+                    let value_type = self.expression(value, Some(&target_type));
+                    self.operands(*operator, &target_type, &value_type, statement);
+                }
+            }
+            StatementKind::Expression(expression) => {
+                let ty = self.expression(expression, None);
+                // The value of an `if`, `when`, or loop written as a statement is incidental.
+                if expression.requires_semicolon() && !matches!(ty, Type::Unit | Type::Inferred) {
+                    self.errors.push(TypeError::UnusedResult {
+                        ty,
+                        node: self.index.expect_id(expression.as_ref()),
+                    });
+                }
+            }
+            StatementKind::Return(value) => {
+                let (function, expected) = self
+                    .returns
+                    .last()
+                    .cloned()
+                    .unwrap_or(("top level".to_string(), Type::Inferred));
+                let (found, node) = match value {
+                    Some(value) => (
+                        self.expression(value, Some(&expected)),
+                        self.index.expect_id(value.as_ref()),
+                    ),
+                    None => (Type::Unit, self.index.expect_id(statement)),
+                };
+                if !compatible(&expected, &found) {
+                    self.errors.push(TypeError::ReturnMismatch {
+                        function,
+                        expected,
+                        found,
+                        node,
+                    });
+                }
+            }
+            StatementKind::Break(value) => {
+                let found = match value {
+                    Some(value) => {
+                        let expected = self.loops.last().cloned().flatten();
+                        match expected {
+                            Some(expected) => self.check(value, &expected),
+                            None => self.expression(value, None),
+                        }
+                    }
+                    None => Type::Unit,
+                };
+                if let Some(broken) = self.loops.last_mut()
+                    && broken.is_none()
+                {
+                    *broken = Some(found);
+                }
+            }
+        }
+    }
+
+    /// The type of `expression`, checked against `expected`.
+    fn check(&mut self, expression: &'p Expression, expected: &Type) -> Type {
+        let found = self.expression(expression, Some(expected));
+        if !compatible(expected, &found) {
+            self.errors.push(TypeError::Mismatch {
+                expected: expected.clone(),
+                found: found.clone(),
+                node: self.index.expect_id(expression),
+            });
+        }
+        found
+    }
+
+    /// The type of `expression`, recorded in the type map. `expected` is the type the context
+    /// wants, used to type literals; any mismatch with it is for the caller to report.
+    fn expression(&mut self, expression: &'p Expression, expected: Option<&Type>) -> Type {
+        let ty = self.infer(expression, expected);
+        let node = self.index.expect_id(expression);
+        self.types.expressions.insert(node, ty.clone());
+        ty
+    }
+
+    fn infer(&mut self, expression: &'p Expression, expected: Option<&Type>) -> Type {
+        if let Some(value) = integer_value(expression) {
+            return self.integer_literal(expression, value, expected);
+        }
+        match &expression.kind {
+            ExpressionKind::Literal(literal) => literal_type(literal, expected),
+            // A generic function used as a value could stand for any of its instances.
+            ExpressionKind::Identifier(_) => {
+                match self.resolution.symbol_of(self.index.expect_id(expression)) {
+                    Some(symbol) => erase(&self.symbol_type(symbol), self.symbol_generics(symbol)),
+                    None => Type::Inferred,
+                }
+            }
+            ExpressionKind::Grouped(inner) => self.expression(inner, expected),
+            ExpressionKind::BinaryOperation {
+                left,
+                operator,
+                right,
+            } => {
+                let (left_type, right_type) = if is_literal(left) && !is_literal(right) {
+                    let right_type = self.expression(right, None);
+                    (self.expression(left, Some(&right_type)), right_type)
+                } else {
+                    // Arithmetic gives its operands' type, so a literal on the left can take the
+                    // type the whole operation is expected to have.
+                    let hint = match operator.signatures()[0].result {
+                        ResultType::Operand => expected,
+                        _ => None,
+                    };
+                    let left_type = self.expression(left, hint);
+                    (left_type.clone(), self.expression(right, Some(&left_type)))
+                };
+                self.operands(*operator, &left_type, &right_type, expression)
+            }
+            ExpressionKind::UnaryOperation { operator, operand } => {
+                let hint = expected.filter(|_| *operator == UnaryOperator::Negate);
+                let operand_type = self.expression(operand, hint);
+                self.operand(*operator, &operand_type, expression)
+            }
+            ExpressionKind::Reference {
+                is_mutable,
+                expression: referent,
+            } => {
+                let referent = self.expression(referent, None);
+                Type::Reference {
+                    is_mutable: *is_mutable,
+                    ref_type: Box::new(referent),
+                }
+            }
+            ExpressionKind::Dereference(reference) => match self.expression(reference, None) {
+                Type::Reference { ref_type, .. } => *ref_type,
+                // Dereferencing anything else is reported by the reference checks.
+                _ => Type::Inferred,
+            },
+            ExpressionKind::FunctionCall {
+                function,
+                arguments,
+            } => self.call(function, arguments, expected, expression),
+            ExpressionKind::MethodCall {
+                receiver,
+                method,
+                arguments,
+            } => self.method_call(receiver, method, arguments, expected, expression),
+            ExpressionKind::RecordAccess { record, field } => {
+                let record_type = self.expression(record, None);
+                self.field_type(&record_type, field, expression)
+            }
+            ExpressionKind::IndexAccess { collection, index } => {
+                let collection_type = self.expression(collection, None);
+                match dereferenced(&collection_type) {
+                    Type::ArrayList(element)
+                    | Type::FixedArray {
+                        element_type: element,
+                        ..
+                    } => {
+                        self.index(index);
+                        element.as_ref().clone()
+                    }
+                    Type::Generic { name, parameters }
+                        if name == "hashMap" && parameters.len() == 2 =>
+                    {
+                        self.check(index, &parameters[0]);
+                        parameters[1].clone()
+                    }
+                    Type::String => {
+                        self.index(index);
+                        Type::Char
+                    }
+                    Type::Inferred => {
+                        self.expression(index, None);
+                        Type::Inferred
+                    }
+                    _ => {
+                        self.expression(index, None);
+                        self.errors.push(TypeError::NotIndexable {
+                            found: collection_type,
+                            node: self.index.expect_id(expression),
+                        });
+                        Type::Inferred
+                    }
+                }
+            }
+            ExpressionKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.check(condition, &Type::Bool);
+                let then_type = self.expression(then_branch, expected);
+                match else_branch {
+                    Some(else_branch) => self.branches(then_type, else_branch, expected),
+                    None => Type::Unit,
+                }
+            }
+            ExpressionKind::When {
+                expression: value,
+                branches,
+            } => {
+                let value_type = self.expression(value, None);
+                let mut result: Option<Type> = None;
+                for branch in branches {
+                    self.pattern(&branch.pattern, &value_type);
+                    if let Some(guard) = &branch.guard {
+                        self.check(guard, &Type::Bool);
+                    }
+                    result = Some(match result {
+                        None => self.expression(&branch.body, expected),
+                        Some(result) => self.branches(result, &branch.body, expected),
+                    });
+                }
+                result.unwrap_or(Type::Unit)
+            }
+            ExpressionKind::Block {
+                statements,
+                final_expression,
+            } => {
+                for statement in statements {
+                    self.statement(statement);
+                }
+                match final_expression {
+                    Some(final_expression) => self.expression(final_expression, expected),
+                    // A block that returns or breaks part way never produces a value of its own.
+                    None if statements.iter().any(returns::statement_diverges) => Type::Inferred,
+                    None => Type::Unit,
+                }
+            }
+            ExpressionKind::Loop { body } => {
+                self.loops.push(None);
+                self.expression(body, None);
+                // A loop that never breaks never finishes, so it can stand for any type.
+                self.loops.pop().flatten().unwrap_or(Type::Inferred)
+            }
+            ExpressionKind::While { condition, body } => {
+                self.check(condition, &Type::Bool);
+                self.loops.push(None);
+                self.expression(body, None);
+                self.loops.pop();
+                Type::Unit
+            }
+            ExpressionKind::For { iterable, body, .. } => {
+                let iterable_type = self.expression(iterable, None);
+                let element = match element_type(&iterable_type) {
+                    Some(element) => element,
+                    None => {
+                        self.errors.push(TypeError::NotIterable {
+                            found: iterable_type,
+                            node: self.index.expect_id(iterable.as_ref()),
+                        });
+                        Type::Inferred
+                    }
+                };
+                self.define(expression, element);
+                self.loops.push(None);
+                self.expression(body, None);
+                self.loops.pop();
+                Type::Unit
+            }
+            ExpressionKind::ArrayLiteral(elements) => {
+                // A list of an unknown element type, such as a generic parameter's, takes the
+                // type of its first element.
+                let (mut element, result) = match expected.map(dereferenced) {
+                    Some(Type::ArrayList(element)) if **element != Type::Inferred => {
+                        (Some(element.as_ref().clone()), None)
+                    }
+                    Some(
+                        fixed @ Type::FixedArray {
+                            element_type: element,
+                            ..
+                        },
+                    ) => (Some(element.as_ref().clone()), Some(fixed.clone())),
+                    _ => (None, None),
+                };
+                for value in elements {
+                    match &element {
+                        Some(element) => {
+                            self.check(value, element);
+                        }
+                        None => element = Some(self.expression(value, None)),
+                    }
+                }
+                result
+                    .unwrap_or_else(|| Type::ArrayList(Box::new(element.unwrap_or(Type::Inferred))))
+            }
+            ExpressionKind::RecordLiteral {
+                record_type,
+                fields,
+                base,
+            } => {
+                let record_type = record_type.clone();
+                self.record_literal(&record_type, fields, base.is_some(), expression);
+                for (field, value) in fields {
+                    let field_type = self.field_type(&record_type, field, expression);
+                    self.check(value, &field_type);
+                }
+                if let Some(base) = base {
+                    self.check(base, &record_type);
+                }
+                record_type
+            }
+            ExpressionKind::UnionLiteral {
+                union_type, value, ..
+            } => {
+                if let Some(value) = value {
+                    self.expression(value, None);
+                }
+                union_type.clone()
+            }
+            ExpressionKind::Range { start, end, .. } => {
+                let (start_type, end_type) = if is_literal(start) && !is_literal(end) {
+                    let end_type = self.expression(end, None);
+                    (self.check(start, &end_type), end_type)
+                } else {
+                    let start_type = self.expression(start, None);
+                    (start_type.clone(), self.check(end, &start_type))
+                };
+                let element = match start_type {
+                    Type::Inferred => end_type,
+                    start_type => start_type,
+                };
+                Type::Generic {
+                    name: "range".to_string(),
+                    parameters: vec![element],
+                }
+            }
+            ExpressionKind::TypeAnnotation {
+                expression: value,
+                annotated_type,
+            } => {
+                let annotated_type = annotated_type.clone();
+                self.check(value, &annotated_type);
+                annotated_type
+            }
+            ExpressionKind::Cast {
+                expression: value,
+                target,
+            } => {
+                let target = target.clone();
+                let found = self.expression(value, None);
+                let error = match conversion(&found, &target) {
+                    Some(Conversion::Exact) => None,
+                    Some(Conversion::Lossy) => Some(TypeError::LossyCast {
+                        from: found,
+                        to: target.clone(),
+                        node: self.index.expect_id(expression),
+                    }),
+                    None => Some(TypeError::InvalidCast {
+                        from: found,
+                        to: target.clone(),
+                        node: self.index.expect_id(expression),
+                    }),
+                };
+                self.errors.extend(error);
+                target
+            }
+            ExpressionKind::Closure {
+                params,
+                return_type,
+                body,
+                ..
+            } => {
+                let expected_params = match expected {
+                    Some(Type::Function { param_types, .. })
+                        if param_types.len() == params.len() =>
+                    {
+                        Some(param_types)
+                    }
+                    _ => None,
+                };
+                for (position, parameter) in params.iter().enumerate() {
+                    let inferred = expected_params.map(|types| &types[position]);
+                    self.parameter(parameter, inferred);
+                }
+                let param_types = params
+                    .iter()
+                    .map(
+                        |parameter| match self.defined_by.get(&self.index.expect_id(parameter)) {
+                            Some(&symbol) => self.symbol_type(symbol),
+                            None => Type::Inferred,
+                        },
+                    )
+                    .collect();
+                let declared = return_type.clone();
+                self.returns.push((
+                    "closure".to_string(),
+                    declared.clone().unwrap_or(Type::Inferred),
+                ));
+                let body_type = match &declared {
+                    Some(declared) => {
+                        let found = self.expression(body, Some(declared));
+                        if !compatible(declared, &found) {
+                            self.errors.push(TypeError::ReturnMismatch {
+                                function: "closure".to_string(),
+                                expected: declared.clone(),
+                                found,
+                                node: self.index.expect_id(body.as_ref()),
+                            });
+                        }
+                        declared.clone()
+                    }
+                    None => self.expression(body, None),
+                };
+                self.returns.pop();
+                Type::Function {
+                    param_types,
+                    return_type: Some(Box::new(body_type)),
+                }
+            }
+        }
+    }
+
+    /// The type of a two-branch expression whose first branch has type `first`, checking that
+    /// `other` has the same type.
+    fn branches(&mut self, first: Type, other: &'p Expression, expected: Option<&Type>) -> Type {
+        match first {
+            Type::Inferred => self.expression(other, expected),
+            first => {
+                self.check(other, &first);
+                first
+            }
+        }
+    }
+
+    /// The result of applying `operator` to operands of the given types.
+    fn operands<T: Node>(
+        &mut self,
+        operator: BinaryOperator,
+        left: &Type,
+        right: &Type,
+        node: &T,
+    ) -> Type {
+        if *left == Type::Inferred || *right == Type::Inferred {
+            let known = if *left == Type::Inferred { right } else { left };
+            return match operator.signatures()[0].result {
+                ResultType::Operand => known.clone(),
+                ResultType::Bool => Type::Bool,
+                ResultType::Unit => Type::Unit,
+                ResultType::Reference { .. } | ResultType::Referent => Type::Inferred,
+            };
+        }
+        match operator.signature_for(left, right) {
+            Some(signature) => signature.result.resolve(left).unwrap_or(Type::Inferred),
+            // Whatever a type parameter stands for, the operator must work on two of it.
+            None if left == right && self.is_type_parameter(left) => {
+                match operator.signatures()[0].result {
+                    ResultType::Operand => left.clone(),
+                    ResultType::Bool => Type::Bool,
+                    ResultType::Unit => Type::Unit,
+                    ResultType::Reference { .. } | ResultType::Referent => Type::Inferred,
+                }
+            }
+            None => {
+                self.errors.push(TypeError::InvalidOperands {
+                    operator,
+                    left: left.clone(),
+                    right: right.clone(),
+                    node: self.index.expect_id(node),
+                });
+                Type::Inferred
+            }
+        }
+    }
+
+    /// The type of an integer literal whose value, with any signs and parentheses, is `value`,
+    /// checked to fit in it. The parts inside take the same type, so a negative literal has the
+    /// unsigned type it is expected to have and is reported as out of its range.
+    fn integer_literal(
+        &mut self,
+        expression: &Expression,
+        value: i128,
+        expected: Option<&Type>,
+    ) -> Type {
+        let ty = literal_type(&Literal::Integer(0), expected);
+        let mut inner = expression;
+        while let ExpressionKind::Grouped(operand)
+        | ExpressionKind::UnaryOperation {
+            operator: UnaryOperator::Negate,
+            operand,
+        } = &inner.kind
+        {
+            inner = operand;
+            let node = self.index.expect_id(inner);
+            self.types.expressions.insert(node, ty.clone());
+        }
+        if let Some((min, max)) = integer_bounds(&ty)
+            && !(min..=max).contains(&value)
+        {
+            self.errors.push(TypeError::LiteralOutOfRange {
+                value,
+                ty: ty.clone(),
+                node: self.index.expect_id(expression),
+            });
+        }
+        ty
+    }
+
+    /// The result of applying `operator` to an operand of type `operand`.
+    fn operand(&mut self, operator: UnaryOperator, operand: &Type, node: &Expression) -> Type {
+        if *operand == Type::Inferred {
+            return Type::Inferred;
+        }
+        match operator.signature_for(operand) {
+            Some(signature) => signature.result.resolve(operand).unwrap_or(Type::Inferred),
+            None if self.is_type_parameter(operand) => match operator {
+                UnaryOperator::Not => Type::Bool,
+                _ => operand.clone(),
+            },
+            None => {
+                self.errors.push(TypeError::InvalidOperand {
+                    operator,
+                    operand: operand.clone(),
+                    node: self.index.expect_id(node),
+                });
+                Type::Inferred
+            }
+        }
+    }
+
+    fn call(
+        &mut self,
+        function: &'p Expression,
+        arguments: &'p [Expression],
+        expected: Option<&Type>,
+        call: &'p Expression,
+    ) -> Type {
+        let mut function_type = self.expression(function, None);
+        if let ExpressionKind::Identifier(name) = &function.kind
+            && let Some(symbol) = self.resolution.symbol_of(self.index.expect_id(function))
+            && self.resolution.symbols.symbol(symbol).kind == SymbolKind::Builtin
+        {
+            return self.builtin_call(name, arguments, expected, call);
+        }
+        // A generic function is called with the types of its own parameters, to be substituted.
+        let mut generics = Vec::new();
+        if let ExpressionKind::Identifier(_) = &function.kind
+            && let Some(symbol) = self.resolution.symbol_of(self.index.expect_id(function))
+            && !self.symbol_generics(symbol).is_empty()
+        {
+            generics = self.symbol_generics(symbol).to_vec();
+            function_type = self.symbol_type(symbol);
+        }
+        match function_type {
+            Type::Function {
+                param_types,
+                return_type,
+            } => {
+                let name = match &function.kind {
+                    ExpressionKind::Identifier(name) => name.clone(),
+                    _ => function.to_source(),
+                };
+                let result = return_type.map_or(Type::Unit, |return_type| *return_type);
+                let call_type = CallType {
+                    generics: &generics,
+                    param_types: &param_types,
+                    result,
+                };
+                self.arguments(&name, call_type, arguments, expected, call)
+            }
+            found => {
+                for argument in arguments {
+                    self.expression(argument, None);
+                }
+                if found != Type::Inferred {
+                    self.errors.push(TypeError::NotCallable {
+                        found,
+                        node: self.index.expect_id(function),
+                    });
+                }
+                Type::Inferred
+            }
+        }
+    }
+
+    /// Check a call to the builtin `name` against what it takes, and give the type of its result.
+    /// The length `len` gives takes the expected type if that is an integer type, as an integer
+    /// literal does.
+    fn builtin_call(
+        &mut self,
+        name: &str,
+        arguments: &'p [Expression],
+        expected: Option<&Type>,
+        call: &'p Expression,
+    ) -> Type {
+        if !matches!(name, "print" | "println") && arguments.len() != 1 {
+            self.errors.push(TypeError::ArgumentCount {
+                function: name.to_string(),
+                expected: 1,
+                found: arguments.len(),
+                node: self.index.expect_id(call),
+            });
+            for argument in arguments {
+                self.expression(argument, None);
+            }
+        } else {
+            for argument in arguments {
+                match name {
+                    "assert" => {
+                        self.check(argument, &Type::Bool);
+                    }
+                    "len" => {
+                        let found = self.expression(argument, None);
+                        if !has_length(&found) {
+                            self.errors.push(TypeError::InvalidArgument {
+                                function: name.to_string(),
+                                found,
+                                node: self.index.expect_id(argument),
+                            });
+                        }
+                    }
+                    _ => {
+                        self.expression(argument, None);
+                    }
+                }
+            }
+        }
+        match name {
+            "len" => literal_type(&Literal::Integer(0), expected),
+            "to_string" => Type::String,
+            _ => Type::Unit,
+        }
+    }
+
+    /// Check `arguments` against the parameter types of the function or method `name`, and give
+    /// the type of its result. The type parameters of a generic one stand for the types the
+    /// expected result and then the arguments give them; number literals and closures come after
+    /// the other arguments, so that they can take the types those give.
+    fn arguments(
+        &mut self,
+        name: &str,
+        function: CallType,
+        arguments: &'p [Expression],
+        expected: Option<&Type>,
+        call: &'p Expression,
+    ) -> Type {
+        let CallType {
+            generics,
+            param_types,
+            result,
+        } = function;
+        if param_types.len() != arguments.len() {
+            self.errors.push(TypeError::ArgumentCount {
+                function: name.to_string(),
+                expected: param_types.len(),
+                found: arguments.len(),
+                node: self.index.expect_id(call),
+            });
+        }
+        let mut bindings = HashMap::new();
+        if let Some(expected) = expected {
+            bind(generics, &result, expected, &mut bindings);
+        }
+        let mut order: Vec<usize> = (0..arguments.len()).collect();
+        if !generics.is_empty() {
+            order.sort_by_key(|&position| match &arguments[position].kind {
+                ExpressionKind::Closure { .. } => 2,
+                _ if is_literal(&arguments[position]) => 1,
+                _ => 0,
+            });
+        }
+        for position in order {
+            let argument = &arguments[position];
+            match param_types.get(position) {
+                Some(param_type) => {
+                    let expected = substitute(param_type, &bound(generics, &bindings));
+                    let found = self.check(argument, &expected);
+                    bind(generics, param_type, &found, &mut bindings);
+                }
+                None => {
+                    self.expression(argument, None);
+                }
+            }
+        }
+        substitute(&result, &bound(generics, &bindings))
+    }
+
+    /// Look up `method` in the patches of the receiver's type and check the call against it.
+    /// Every method comes from a patch, so one no patch defines is an error once the receiver's
+    /// type is known.
+    fn method_call(
+        &mut self,
+        receiver: &'p Expression,
+        method: &str,
+        arguments: &'p [Expression],
+        expected: Option<&Type>,
+        call: &'p Expression,
+    ) -> Type {
+        let receiver_type = dereferenced(&self.expression(receiver, None)).clone();
+        let mut candidates: Vec<_> = self
+            .patches
+            .iter()
+            .filter_map(|patch| {
+                let declaration = patch.methods.iter().find(|m| m.name == method)?;
+                let patch_arguments = patch_arguments(patch, &receiver_type)?;
+                Some((declaration, patch_arguments))
+            })
+            .collect();
+        let found = match candidates.len() {
+            1 => candidates.pop(),
+            // What a type parameter stands for may have any method.
+            0 => {
+                if is_concrete(&receiver_type) && !self.is_type_parameter(&receiver_type) {
+                    self.errors.push(TypeError::UnknownMethod {
+                        method: method.to_string(),
+                        receiver: receiver_type,
+                        node: self.index.expect_id(call),
+                    });
+                }
+                None
+            }
+            // Patches of different types may all fit a receiver that is not fully known.
+            _ => {
+                if is_concrete(&receiver_type) {
+                    self.errors.push(TypeError::AmbiguousMethod {
+                        method: method.to_string(),
+                        receiver: receiver_type,
+                        node: self.index.expect_id(call),
+                    });
+                }
+                None
+            }
+        };
+        let Some((declaration, patch_arguments)) = found else {
+            for argument in arguments {
+                self.expression(argument, None);
+            }
+            return Type::Inferred;
+        };
+        self.types
+            .methods
+            .insert(self.index.expect_id(call), declaration.id);
+        let param_types: Vec<Type> = declaration
+            .params
+            .iter()
+            .map(|parameter| substitute(&parameter.param_type, &patch_arguments))
+            .collect();
+        let result = declaration
+            .return_type
+            .as_ref()
+            .map_or(Type::Unit, |return_type| {
+                substitute(return_type, &patch_arguments)
+            });
+        let call_type = CallType {
+            generics: &declaration.type_parameters,
+            param_types: &param_types,
+            result,
+        };
+        self.arguments(method, call_type, arguments, expected, call)
+    }
+
+    /// Check the index of an array or string.
+    fn index(&mut self, index: &'p Expression) {
+        let found = self.expression(index, Some(&Type::USize));
+        if found != Type::Inferred && !TypeClass::Integer.contains(&found) {
+            self.errors.push(TypeError::Mismatch {
+                expected: Type::USize,
+                found,
+                node: self.index.expect_id(index),
+            });
+        }
+    }
+
+    /// Report a record literal of a type that is not a record, or one that leaves a field unset
+    /// with no `base` to take it from. Unknown fields are reported as each one is checked.
+    fn record_literal(
+        &mut self,
+        record_type: &Type,
+        fields: &[(String, Expression)],
+        has_base: bool,
+        literal: &Expression,
+    ) {
+        let name = match record_type {
+            Type::Named(name) | Type::Generic { name, .. } => name,
+            _ => return,
+        };
+        let Some(record) = self.records.get(name.as_str()) else {
+            self.errors.push(TypeError::UnknownRecord {
+                name: name.clone(),
+                node: self.index.expect_id(literal),
+            });
+            return;
+        };
+        if has_base {
+            return;
+        }
+        for declared in &record.fields {
+            if !fields.iter().any(|(field, _)| *field == declared.name) {
+                self.errors.push(TypeError::MissingField {
+                    record_type: record_type.clone(),
+                    field: declared.name.clone(),
+                    node: self.index.expect_id(literal),
+                });
+            }
+        }
+    }
+
+    /// The type of `field` in a value of type `record_type`, reporting an unknown field at
+    /// `node`.
+    fn field_type(&mut self, record_type: &Type, field: &str, node: &Expression) -> Type {
+        let (name, arguments) = match dereferenced(record_type) {
+            Type::Named(name) => (name, &[][..]),
+            Type::Generic { name, parameters } => (name, parameters.as_slice()),
+            _ => return Type::Inferred,
+        };
+        let Some(record) = self.records.get(name.as_str()) else {
+            return Type::Inferred;
+        };
+        let Some(declared) = record.fields.iter().find(|declared| declared.name == field) else {
+            self.errors.push(TypeError::UnknownField {
+                record_type: record_type.clone(),
+                field: field.to_string(),
+                node: self.index.expect_id(node),
+            });
+            return Type::Inferred;
+        };
+        let arguments = generic_arguments(&record.type_parameters, arguments);
+        substitute(&declared.field_type, &arguments)
+    }
+
+    /// Check that `pattern` can match a value of type `ty`, and give the names it binds their
+    /// types. A pattern that cannot match is reported, and the names inside it are unknown.
+    fn pattern(&mut self, pattern: &'p Pattern, ty: &Type) {
+        if let Some(error) = self.pattern_error(pattern, ty) {
+            self.types.mismatched.insert(self.index.expect_id(pattern));
+            self.errors.push(error);
+        }
+    }
+
+    /// The error that keeps `pattern` from matching a value of type `ty`, if there is one, after
+    /// checking the patterns inside it.
+    fn pattern_error(&mut self, pattern: &'p Pattern, ty: &Type) -> Option<TypeError> {
+        let matched = dereferenced(ty);
+        let mismatch = || TypeError::PatternMismatch {
+            pattern: pattern.to_source(),
+            ty: ty.clone(),
+            node: self.index.expect_id(pattern),
+        };
+        match &pattern.kind {
+            PatternKind::Identifier(_) => match self.pattern_variant(pattern) {
+                Some((union, variant)) => self.variant_pattern(pattern, union, variant, None, ty),
+                None => {
+                    self.define(pattern, ty.clone());
+                    None
+                }
+            },
+            PatternKind::Union { payload, .. } => match self.pattern_variant(pattern) {
+                Some((union, variant)) => {
+                    self.variant_pattern(pattern, union, variant, payload.as_deref(), ty)
+                }
+                // The name is reported by name resolution.
+                None => {
+                    if let Some(payload) = payload {
+                        self.pattern(payload, &Type::Inferred);
+                    }
+                    None
+                }
+            },
+            PatternKind::Record {
+                record_type,
+                fields,
+            } => {
+                let record_type = record_type.clone();
+                let record = match &record_type {
+                    Type::Named(name) | Type::Generic { name, .. } => {
+                        self.records.get(name.as_str()).copied()
+                    }
+                    _ => None,
+                };
+                let Some(record) = record else {
+                    for (_, field_pattern) in fields {
+                        self.pattern(field_pattern, &Type::Inferred);
+                    }
+                    return Some(TypeError::UnknownRecord {
+                        name: record_type.to_string(),
+                        node: self.index.expect_id(pattern),
+                    });
+                };
+                // The matched value's type arguments say what the fields' types are.
+                let fields_of = match matched {
+                    Type::Inferred => Some(&record_type),
+                    Type::Named(name) | Type::Generic { name, .. } if *name == record.name => {
+                        Some(matched)
+                    }
+                    _ => None,
+                };
+                let mut error = fields_of.is_none().then(mismatch);
+                for (field, field_pattern) in fields {
+                    if !record.fields.iter().any(|declared| declared.name == *field) {
+                        error.get_or_insert_with(|| TypeError::UnknownField {
+                            record_type: record_type.clone(),
+                            field: field.clone(),
+                            node: self.index.expect_id(pattern),
+                        });
+                    }
+                    let field_type = fields_of.map_or(Type::Inferred, |fields_of| {
+                        self.record_field(fields_of, field)
+                    });
+                    self.pattern(field_pattern, &field_type);
+                }
+                error
+            }
+            PatternKind::Or(alternatives) => {
+                for alternative in alternatives {
+                    self.pattern(alternative, ty);
+                }
+                None
+            }
+            // No type is made of tuples, so a tuple pattern only fits a value of unknown type.
+            PatternKind::Tuple(elements) => {
+                for element in elements {
+                    self.pattern(element, &Type::Inferred);
+                }
+                (*matched != Type::Inferred).then(mismatch)
+            }
+            PatternKind::Literal(literal) => {
+                let fits = compatible(matched, &literal_type(literal, Some(matched)));
+                (!fits).then(mismatch)
+            }
+            PatternKind::Range { start, end, .. } => {
+                let fits = [start, end]
+                    .into_iter()
+                    .all(|literal| compatible(matched, &literal_type(literal, Some(matched))));
+                (!fits).then(mismatch)
+            }
+            PatternKind::Wildcard | PatternKind::Else => None,
+        }
+    }
+
+    /// The union variant `pattern` names, if it names one.
+    fn pattern_variant(
+        &self,
+        pattern: &Pattern,
+    ) -> Option<(&'p UnionDeclaration, &'p UnionVariant)> {
+        let symbol = self.resolution.symbol_of(self.index.expect_id(pattern))?;
+        let node = self.resolution.symbols.symbol(symbol).node?;
+        self.variants.get(&node).copied()
+    }
+
+    /// The error that keeps a pattern for `variant` of `union`, with `payload` as the pattern for
+    /// its payload, from matching a value of type `ty`, after checking `payload`.
+    fn variant_pattern(
+        &mut self,
+        pattern: &'p Pattern,
+        union: &'p UnionDeclaration,
+        variant: &'p UnionVariant,
+        payload: Option<&'p Pattern>,
+        ty: &Type,
+    ) -> Option<TypeError> {
+        let arguments = match dereferenced(ty) {
+            Type::Inferred => Some(&[][..]),
+            Type::Named(name) if *name == union.name => Some(&[][..]),
+            Type::Generic { name, parameters } if *name == union.name => {
+                Some(parameters.as_slice())
+            }
+            _ => None,
+        };
+        let error = match (&variant.variant_type, payload) {
+            _ if arguments.is_none() => Some(TypeError::PatternMismatch {
+                pattern: pattern.to_source(),
+                ty: ty.clone(),
+                node: self.index.expect_id(pattern),
+            }),
+            (Some(_), None) | (None, Some(_)) => Some(TypeError::PayloadMismatch {
+                variant: variant.name.clone(),
+                has_payload: variant.variant_type.is_some(),
+                node: self.index.expect_id(pattern),
+            }),
+            (Some(_), Some(_)) | (None, None) => None,
+        };
+        if let Some(payload) = payload {
+            let payload_type = match (&variant.variant_type, arguments) {
+                (Some(variant_type), Some(arguments)) if error.is_none() => substitute(
+                    variant_type,
+                    &generic_arguments(&union.type_parameters, arguments),
+                ),
+                _ => Type::Inferred,
+            };
+            self.pattern(payload, &payload_type);
+        }
+        error
+    }
+
+    /// The type of `field` in `record_type`, or unknown if it has no such field.
+    fn record_field(&self, record_type: &Type, field: &str) -> Type {
+        let (name, arguments) = match record_type {
+            Type::Named(name) => (name, &[][..]),
+            Type::Generic { name, parameters } => (name, parameters.as_slice()),
+            _ => return Type::Inferred,
+        };
+        self.records
+            .get(name.as_str())
+            .and_then(|record| {
+                let declared = record
+                    .fields
+                    .iter()
+                    .find(|declared| declared.name == field)?;
+                let arguments = generic_arguments(&record.type_parameters, arguments);
+                Some(substitute(&declared.field_type, &arguments))
+            })
+            .unwrap_or(Type::Inferred)
+    }
+}
+
+/// Whether a value of type `found` can be used where `expected` is wanted. Unknown types fit
+/// everything, a mutable reference can stand in for an immutable one, and `fixedArray` lengths
+/// are compared only when both are literals.
+pub fn compatible(expected: &Type, found: &Type) -> bool {
+    match (expected, found) {
+        (Type::Inferred, _) | (_, Type::Inferred) => true,
+        (
+            Type::Reference {
+                is_mutable: expected_mutable,
+                ref_type: expected,
+            },
+            Type::Reference {
+                is_mutable: found_mutable,
+                ref_type: found,
+            },
+        ) => (*found_mutable || !*expected_mutable) && compatible(expected, found),
+        (Type::ArrayList(expected), Type::ArrayList(found)) => compatible(expected, found),
+        (
+            Type::FixedArray {
+                element_type: expected,
+                size: expected_size,
+            },
+            Type::FixedArray {
+                element_type: found,
+                size: found_size,
+            },
+        ) => {
+            let sizes_match = match (expected_size, found_size) {
+                (ArraySize::Literal(expected), ArraySize::Literal(found)) => expected == found,
+                _ => true,
+            };
+            sizes_match && compatible(expected, found)
+        }
+        (
+            Type::Generic {
+                name: expected_name,
+                parameters: expected,
+            },
+            Type::Generic {
+                name: found_name,
+                parameters: found,
+            },
+        ) => expected_name == found_name && all_compatible(expected, found),
+        (
+            Type::Function {
+                param_types: expected_params,
+                return_type: expected_return,
+            },
+            Type::Function {
+                param_types: found_params,
+                return_type: found_return,
+            },
+        ) => {
+            let unit = Type::Unit;
+            all_compatible(expected_params, found_params)
+                && compatible(
+                    expected_return.as_deref().unwrap_or(&unit),
+                    found_return.as_deref().unwrap_or(&unit),
+                )
+        }
+        _ => expected == found,
+    }
+}
+
+fn all_compatible(expected: &[Type], found: &[Type]) -> bool {
+    expected.len() == found.len()
+        && expected
+            .iter()
+            .zip(found)
+            .all(|(expected, found)| compatible(expected, found))
+}
+
+/// The type of a literal, where a number takes the `expected` type if that is a numeric type of
+/// its kind.
+fn literal_type(literal: &Literal, expected: Option<&Type>) -> Type {
+    let expected = expected.filter(|expected| match literal {
+        Literal::Integer(_) => TypeClass::Integer.contains(expected),
+        Literal::Float(_) => TypeClass::Float.contains(expected),
+        _ => false,
+    });
+    if let Some(expected) = expected {
+        return expected.clone();
+    }
+    match literal {
+        Literal::Integer(_) => Type::I32,
+        Literal::Float(_) => Type::F64,
+        Literal::Boolean(_) => Type::Bool,
+        Literal::String(_) => Type::String,
+        Literal::Char(_) => Type::Char,
+        Literal::Bytes(_) => Type::ArrayList(Box::new(Type::U8)),
+        Literal::Unit => Type::Unit,
+    }
+}
+
+/// Whether `expression` is a number literal, possibly negated or parenthesized, whose type
+/// depends on where it is used.
+fn is_literal(expression: &Expression) -> bool {
+    match &expression.kind {
+        ExpressionKind::Literal(Literal::Integer(_) | Literal::Float(_)) => true,
+        ExpressionKind::UnaryOperation {
+            operator: UnaryOperator::Negate,
+            operand,
+        }
+        | ExpressionKind::Grouped(operand) => is_literal(operand),
+        _ => false,
+    }
+}
+
+/// The value of an integer literal, possibly negated or parenthesized.
+fn integer_value(expression: &Expression) -> Option<i128> {
+    match &expression.kind {
+        ExpressionKind::Literal(Literal::Integer(value)) => Some(*value),
+        ExpressionKind::UnaryOperation {
+            operator: UnaryOperator::Negate,
+            operand,
+        } => integer_value(operand).map(|value| -value),
+        ExpressionKind::Grouped(operand) => integer_value(operand),
+        _ => None,
+    }
+}
+
+/// What a cast does to the values it converts.
+enum Conversion {
+    /// Every value comes out the same.
+    Exact,
+    /// Some values are truncated, wrapped, or rounded.
+    Lossy,
+}
+
+/// How a cast from `from` to `to` converts, or `None` if it is not allowed. Any number can be
+/// cast to any numeric type, and a reference to a reference to the same type that is no more
+/// mutable.
+fn conversion(from: &Type, to: &Type) -> Option<Conversion> {
+    if from == to || *from == Type::Inferred || *to == Type::Inferred {
+        return Some(Conversion::Exact);
+    }
+    let exact = match (from, to) {
+        (
+            Type::Reference {
+                is_mutable: from_mutable,
+                ref_type: from_referent,
+            },
+            Type::Reference {
+                is_mutable: to_mutable,
+                ref_type: to_referent,
+            },
+        ) => {
+            return (compatible(to_referent, from_referent) && (*from_mutable || !*to_mutable))
+                .then_some(Conversion::Exact);
+        }
+        (Type::F32, Type::F64) => true,
+        (Type::F64, Type::F32) => false,
+        (_, Type::F32 | Type::F64) => {
+            let (min, max) = integer_bounds(from)?;
+            let exact = if *to == Type::F32 { 1 << 24 } else { 1 << 53 };
+            -exact <= min && max <= exact
+        }
+        (Type::F32 | Type::F64, _) => {
+            integer_bounds(to)?;
+            false
+        }
+        _ => {
+            let (from_min, from_max) = integer_bounds(from)?;
+            let (to_min, to_max) = integer_bounds(to)?;
+            to_min <= from_min && from_max <= to_max
+        }
+    };
+    Some(if exact {
+        Conversion::Exact
+    } else {
+        Conversion::Lossy
+    })
+}
+
+/// The type of the elements of an iterable type, or `None` if it cannot be iterated.
+fn element_type(iterable: &Type) -> Option<Type> {
+    match dereferenced(iterable) {
+        Type::ArrayList(element)
+        | Type::FixedArray {
+            element_type: element,
+            ..
+        } => Some(element.as_ref().clone()),
+        Type::Generic { name, parameters }
+            if parameters.len() == 1 && ["range", "linkedList"].contains(&name.as_str()) =>
+        {
+            Some(parameters[0].clone())
+        }
+        Type::String => Some(Type::Char),
+        Type::Generic { name, .. } if name == "hashMap" => Some(Type::Inferred),
+        Type::Inferred => Some(Type::Inferred),
+        _ => None,
+    }
+}
+
+/// Whether `len` takes a value of type `ty`: an array, string, or range.
+fn has_length(ty: &Type) -> bool {
+    match dereferenced(ty) {
+        Type::ArrayList(_) | Type::FixedArray { .. } | Type::String | Type::Inferred => true,
+        Type::Generic { name, .. } => name == "range",
+        _ => false,
+    }
+}
+
+/// `ty` with any references stripped, as field access and indexing see through them.
+fn dereferenced(ty: &Type) -> &Type {
+    match ty {
+        Type::Reference { ref_type, .. } => dereferenced(ref_type),
+        ty => ty,
+    }
+}
+
+/// The type of what a call passes and gets back: the types of a function's parameters and
+/// result, which mention its type parameters if it is generic.
+struct CallType<'t> {
+    generics: &'t [TypeParameter],
+    param_types: &'t [Type],
+    result: Type,
+}
+
+/// What each of `generics` stands for in a call, given the `bindings` found so far; the ones
+/// not bound yet are unknown.
+fn bound<'d>(
+    generics: &'d [TypeParameter],
+    bindings: &HashMap<String, Type>,
+) -> HashMap<&'d str, Type> {
+    generics
+        .iter()
+        .map(|parameter| {
+            let argument = bindings.get(&parameter.name).cloned();
+            (parameter.name.as_str(), argument.unwrap_or(Type::Inferred))
+        })
+        .collect()
+}
+
+/// The type a union's variants construct, written with its type parameters.
+fn generic_union_type(union: &UnionDeclaration) -> Type {
+    if union.type_parameters.is_empty() {
+        Type::Named(union.name.clone())
+    } else {
+        Type::Generic {
+            name: union.name.clone(),
+            parameters: union
+                .type_parameters
+                .iter()
+                .map(|parameter| Type::Named(parameter.name.clone()))
+                .collect(),
+        }
+    }
+}
+
+/// The type a union's variants construct, with its type parameters unknown.
+fn union_type(union: &UnionDeclaration) -> Type {
+    if union.type_parameters.is_empty() {
+        Type::Named(union.name.clone())
+    } else {
+        Type::Generic {
+            name: union.name.clone(),
+            parameters: vec![Type::Inferred; union.type_parameters.len()],
+        }
+    }
+}
+
+/// The type parameters a patch introduces: the names among the arguments of its target, as `T`
+/// and `E` in `patch result<T, E>` or `T` in `patch arrayList<T>`.
+pub(crate) fn patch_type_parameters(patch: &PatchDeclaration) -> Vec<String> {
+    let arguments = match &patch.target_type {
+        Type::Generic { parameters, .. } => parameters.iter().collect(),
+        Type::ArrayList(element)
+        | Type::FixedArray {
+            element_type: element,
+            ..
+        } => vec![element.as_ref()],
+        _ => Vec::new(),
+    };
+    arguments
+        .into_iter()
+        .filter_map(|argument| match argument {
+            Type::Named(name) => Some(name.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// What the type parameters of `patch` stand for when its methods are called on a value of type
+/// `receiver`, or `None` if the patch is not for that type.
+fn patch_arguments<'d>(
+    patch: &'d PatchDeclaration,
+    receiver: &Type,
+) -> Option<HashMap<&'d str, Type>> {
+    let mut arguments = HashMap::new();
+    let mut argument = |target: &'d Type, receiver: &Type| match target {
+        Type::Named(name) => {
+            arguments.insert(name.as_str(), receiver.clone());
+            true
+        }
+        target => compatible(target, receiver),
+    };
+    let fits = match (&patch.target_type, receiver) {
+        (_, Type::Inferred) => false,
+        (
+            Type::Generic {
+                name: target_name,
+                parameters: target_parameters,
+            },
+            Type::Generic {
+                name: receiver_name,
+                parameters: receiver_parameters,
+            },
+        ) => {
+            target_name == receiver_name
+                && target_parameters.len() == receiver_parameters.len()
+                && target_parameters
+                    .iter()
+                    .zip(receiver_parameters)
+                    .all(|(target, receiver)| argument(target, receiver))
+        }
+        (Type::ArrayList(target), Type::ArrayList(receiver)) => argument(target, receiver),
+        (
+            Type::FixedArray {
+                element_type: target,
+                size: target_size,
+            },
+            Type::FixedArray {
+                element_type: receiver,
+                size: receiver_size,
+            },
+        ) => target_size == receiver_size && argument(target, receiver),
+        (target, receiver) => compatible(target, receiver),
+    };
+    fits.then_some(arguments)
+}
+
+/// Whether `ty` is fully known, without a part left to inference.
+pub(crate) fn is_concrete(ty: &Type) -> bool {
+    match ty {
+        Type::Inferred => false,
+        Type::Generic { parameters, .. } => parameters.iter().all(is_concrete),
+        Type::Reference { ref_type, .. } => is_concrete(ref_type),
+        Type::ArrayList(element) => is_concrete(element),
+        Type::FixedArray { element_type, size } => {
+            is_concrete(element_type) && !matches!(size, ArraySize::Parameter(_))
+        }
+        Type::Function {
+            param_types,
+            return_type,
+        } => param_types.iter().all(is_concrete) && return_type.as_deref().is_none_or(is_concrete),
+        _ => true,
+    }
+}
+
+/// What each of `parameters` stands for given `arguments`; parameters without an argument are
+/// unknown.
+fn generic_arguments<'d>(
+    parameters: &'d [TypeParameter],
+    arguments: &[Type],
+) -> HashMap<&'d str, Type> {
+    parameters
+        .iter()
+        .enumerate()
+        .map(|(position, parameter)| {
+            let argument = arguments.get(position).cloned().unwrap_or(Type::Inferred);
+            (parameter.name.as_str(), argument)
+        })
+        .collect()
+}
+
+/// `ty` with the type parameters in `parameters` unknown.
+fn erase(ty: &Type, parameters: &[TypeParameter]) -> Type {
+    substitute(ty, &generic_arguments(parameters, &[]))
+}
+
+/// `ty` with every named type in `arguments` replaced by its argument.
+fn substitute(ty: &Type, arguments: &HashMap<&str, Type>) -> Type {
+    match ty {
+        Type::Named(name) => arguments.get(name.as_str()).cloned().unwrap_or(ty.clone()),
+        Type::Generic { name, parameters } => Type::Generic {
+            name: name.clone(),
+            parameters: parameters
+                .iter()
+                .map(|parameter| substitute(parameter, arguments))
+                .collect(),
+        },
+        Type::Reference {
+            is_mutable,
+            ref_type,
+        } => Type::Reference {
+            is_mutable: *is_mutable,
+            ref_type: Box::new(substitute(ref_type, arguments)),
+        },
+        Type::ArrayList(element) => Type::ArrayList(Box::new(substitute(element, arguments))),
+        Type::FixedArray { element_type, size } => Type::FixedArray {
+            element_type: Box::new(substitute(element_type, arguments)),
+            size: size.clone(),
+        },
+        Type::Function {
+            param_types,
+            return_type,
+        } => Type::Function {
+            param_types: param_types
+                .iter()
+                .map(|param_type| substitute(param_type, arguments))
+                .collect(),
+            return_type: return_type
+                .as_ref()
+                .map(|return_type| Box::new(substitute(return_type, arguments))),
+        },
+        _ => ty.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolve::resolve;
+    use parser::parse;
+    use parser::visit::{Visitor, walk_expression};
+    use test_case::test_case;
+
+    fn type_errors(source: &str) -> Vec<String> {
+        let program = parse(source).expect("Parse error");
+        let index = NodeIndex::new(&program);
+        let resolution = resolve(&program, &index);
+        check(&program, &index, &resolution)
+            .errors
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    /// The type of the value of the last top-level variable in `source`.
+    fn type_of_last(source: &str) -> Type {
+        let program = parse(source).expect("Parse error");
+        let index = NodeIndex::new(&program);
+        let resolution = resolve(&program, &index);
+        let checked = check(&program, &index, &resolution);
+        assert!(checked.errors.is_empty(), "{:?}", checked.errors);
+
+        let value = program
+            .declarations
+            .iter()
+            .rev()
+            .find_map(|declaration| match declaration {
+                Declaration::Statement(Statement {
+                    kind: StatementKind::VariableDeclaration { value, .. },
+                    ..
+                }) => Some(value.as_ref()),
+                _ => None,
+            })
+            .expect("no variable declaration");
+        checked
+            .types
+            .expression(index.id(value).unwrap())
+            .cloned()
+            .expect("untyped expression")
+    }
+
+    #[test_case("x = 1 + 2;", Type::I32 ; "integer arithmetic")]
+    #[test_case("u8 a = 1; x = 2 * a;", Type::U8 ; "literal takes the other operand's type")]
+    #[test_case("x = 1.5 < 2.0;", Type::Bool ; "comparison")]
+    #[test_case("x = \"a\" + \"b\";", Type::String ; "string concatenation")]
+    #[test_case("fn f(i32 a) -> bool { a > 0 } x = f(1);", Type::Bool ; "call result")]
+    #[test_case("x = [1, 2, 3];", Type::ArrayList(Box::new(Type::I32)) ; "array literal")]
+    #[test_case("x = if true { 'a' } else { 'b' };", Type::Char ; "if expression")]
+    #[test_case("i64 n = 1; x = &n;", Type::Reference { is_mutable: false, ref_type: Box::new(Type::I64) } ; "reference")]
+    #[test_case("record point { x: f64; } p = point { x: 1.0 }; x = p.x;", Type::F64 ; "field")]
+    #[test_case(
+        "record box<T> { value: T; } box<string> b = makeBox(); x = b.value;",
+        Type::String ;
+        "generic field"
+    )]
+    #[test_case("xs = [1, 2]; x = xs[0];", Type::I32 ; "index")]
+    #[test_case("x = loop { break 'q'; };", Type::Char ; "loop with break value")]
+    #[test_case(
+        "x = |i32 a| a * 2;",
+        Type::Function { param_types: vec![Type::I32], return_type: Some(Box::new(Type::I32)) } ;
+        "closure"
+    )]
+    #[test_case(
+        "union shape = circle(f64) | dot; s = circle(1.0); x = when s { circle(r): r; dot: 0.0; };",
+        Type::F64 ;
+        "variant payload binding"
+    )]
+    #[test_case("patch i32 { fn double() -> i64 { 0 } } n = 1; x = n.double();", Type::I64 ; "patch method")]
+    #[test_case("fn f<T>(T a) -> arrayList<T> { [a] } x = f('c');", Type::ArrayList(Box::new(Type::Char)) ; "generic call")]
+    #[test_case(
+        "union opt<T> = s(T) | n; x = s(1.5);",
+        Type::Generic { name: "opt".to_string(), parameters: vec![Type::F64] } ;
+        "generic variant"
+    )]
+    #[test_case(
+        "union opt<T> = s(T) | n; patch opt<T> { fn map<U>(fn(T) -> U f) -> opt<U> { n } } o = s(1); x = o.map(|v| v > 0);",
+        Type::Generic { name: "opt".to_string(), parameters: vec![Type::Bool] } ;
+        "generic method"
+    )]
+    #[test_case(
+        "union option<T> = some(T) | none; patch option<T> { fn unwrapOr(T d) -> T { d } } option<char> o = none; x = o.unwrapOr('a');",
+        Type::Char ;
+        "generic patch method"
+    )]
+    #[test_case(
+        "patch arrayList<T> { fn first() -> T { self[0] } } xs = ['a']; x = xs.first();",
+        Type::Char ;
+        "patch method on a list"
+    )]
+    #[test_case(
+        "patch fixedArray<T, 2> { fn last() -> T { self[1] } } fixedArray<bool, 2> xs = [true, false]; x = xs.last();",
+        Type::Bool ;
+        "patch method on an array"
+    )]
+    #[test_case(
+        "record point { x: i32; } patch point { fn norm() -> f32 { 1.0 } } x = point { x: 1 }.norm();",
+        Type::F32 ;
+        "patch method on a record"
+    )]
+    fn test_expression_type(source: &str, expected: Type) {
+        assert_eq!(type_of_last(source), expected);
+    }
+
+    #[test_case("fn f(u8 age) {} fn g() { f(200 - 1); h(1); }" ; "unknown function")]
+    #[test_case("fn f<T>(T a, T b) -> T { if a < b { b } else { a } } x = f(1, 2);" ; "generic function")]
+    #[test_case("patch string { fn trim() -> string { self } } fn f(string& s) -> string { s.trim() }" ; "method call")]
+    #[test_case("fn f<T>(T value) { value.show(); }" ; "method of a type parameter")]
+    #[test_case("fn f<T>(T x) -> T { if x < -x { -x } else { x } } i64 n = 1; y = f(n);" ; "operators on a type parameter")]
+    #[test_case("fn f<T>(T a, T b) -> T { a } i64 n = 1; x = f(1, n);" ; "literal argument takes a bound type")]
+    #[test_case("fn f<T>(arrayList<T> xs, fn(T) -> T g) {} f([1], |x| x * 2);" ; "closure argument takes a bound type")]
+    #[test_case("union opt<T> = s(T) | n; fn f<T>(T x) -> opt<T> { s(x) }" ; "variant of a type parameter")]
+    #[test_case("fn f() -> i32 { return 1; }" ; "body ends with return")]
+    #[test_case("fn f(bool b) -> i32 { if b { return 1; } }" ; "missing value left to return paths")]
+    #[test_case("fn f(arrayList<i32> xs) { for x in xs { print(x + 1); } }" ; "loop variable")]
+    #[test_case("fn f(arrayList<i32>& xs) -> i32 { xs[0] }" ; "index through reference")]
+    #[test_case("fn f(i32 a) -> i32 { when a { 0: 1; n if n > 0: n; _: -1; } }" ; "when branches")]
+    #[test_case(
+        "union option<T> = some(T) | none; fn f(option<i32>& o) -> i32 { when o { some(n): n + 1; none: 0; } }" ;
+        "variant patterns through reference"
+    )]
+    #[test_case("record point { x: i32; } fn f(point p) -> i32 { when p { point { x }: x; } }" ; "record pattern")]
+    #[test_case("i32 @count = 0; count += 1; (count) = count * 2;" ; "assignments")]
+    #[test_case("apply = |fn(i32) -> i32 f| f(1); y = apply(|x| x + 1);" ; "closure argument")]
+    #[test_case("fn f(i32& @p) { *p = 2; }" ; "assignment through reference")]
+    fn test_well_typed(source: &str) {
+        assert_eq!(type_errors(source), Vec::<String>::new());
+    }
+
+    #[test_case("i32 x = \"a\";", &["Expected 'i32', found 'string'"] ; "declared type")]
+    #[test_case("const bool on = 1;", &["Expected 'bool', found 'i32'"] ; "const value")]
+    #[test_case("fn f(i32 a) {} x = f(1, 2);", &["'f' takes 1 arguments, but 2 were given"] ; "too many arguments")]
+    #[test_case("fn f(i32 a, i32 b) {} x = f(1);", &["'f' takes 2 arguments, but 1 were given"] ; "too few arguments")]
+    #[test_case("fn f(string s) {} fn g() { f(1); }", &["Expected 'string', found 'i32'"] ; "argument type")]
+    #[test_case("fn f() -> i32 { \"no\" }", &["'f' should return 'i32', but returns 'string'"] ; "body type")]
+    #[test_case("fn f() -> i32 { return true; }", &["'f' should return 'i32', but returns 'bool'"] ; "return value")]
+    #[test_case("fn f() -> bool { if 1 { true } else { 0 } }", &["Expected 'bool', found 'i32'", "Expected 'bool', found 'i32'"] ; "condition and branch")]
+    #[test_case("x = 1 + \"a\";", &["Operator '+' cannot be applied to 'i32' and 'string'"] ; "operands")]
+    #[test_case("x = not 1;", &["Operator '!' cannot be applied to 'i32'"] ; "operand")]
+    #[test_case("x = 1; y = x();", &["Cannot call a value of type 'i32'"] ; "not callable")]
+    #[test_case("record point { x: i32; } p = point { x: 1, z: 2 };", &["Type 'point' has no field 'z'"] ; "record literal field")]
+    #[test_case("record point { x: i32; } fn f(point& p) -> i32 { p.y }", &["Type 'point&' has no field 'y'"] ; "field access")]
+    #[test_case("x = true; y = x[0];", &["Cannot index into a value of type 'bool'"] ; "not indexable")]
+    #[test_case("fn f() { for c in 5 {} }", &["Cannot iterate over a value of type 'i32'"] ; "not iterable")]
+    #[test_case("xs = [1, \"two\"];", &["Expected 'i32', found 'string'"] ; "array elements")]
+    #[test_case("f = |i32 a| -> bool { a };", &["'closure' should return 'bool', but returns 'i32'"] ; "closure return type")]
+    #[test_case("patch string { fn repeat(i32 times) -> string { self } } s = \"a\".repeat(\"b\");", &["Expected 'i32', found 'string'"] ; "method argument")]
+    #[test_case("patch string { fn repeat(i32 times) -> string { self } } s = \"a\".repeat();", &["'repeat' takes 1 arguments, but 0 were given"] ; "method argument count")]
+    #[test_case(
+        "patch i32 { fn double() -> i32 { self * 2 } } patch i32 { fn double() -> i32 { self + self } } n = 1; x = n.double();",
+        &["Method 'double' of 'i32' is defined by more than one patch"] ;
+        "ambiguous method"
+    )]
+    #[test_case("xs = [1, 2]; xs.push(3);", &["Type 'arrayList<i32>' has no method 'push'"] ; "unknown method")]
+    #[test_case("fn f<T>(T x) -> T { x } i32 y = f(\"a\");", &["Expected 'i32', found 'string'"] ; "generic result")]
+    #[test_case("fn f<T>(T a, T b) {} fn g(bool b) { f(b, 1); }", &["Expected 'bool', found 'i32'"] ; "type parameter bound twice")]
+    #[test_case("union opt<T> = s(T) | n; opt<i32> x = s(\"a\");", &["Expected 'i32', found 'string'"] ; "generic variant")]
+    #[test_case("fn f<T>(T a) -> T { a + 1 }", &["Operator '+' cannot be applied to 'T' and 'i32'"] ; "type parameter in the body")]
+    #[test_case("fn f<T>(T a) -> i32 { a }", &["'f' should return 'i32', but returns 'T'"] ; "type parameter returned as another type")]
+    #[test_case(
+        "union shape = rect(f64) | dot; fn f(shape s) -> f64 { when s { rect((a, b)): a; dot: 0.0; } }",
+        &["Pattern '(a, b)' cannot match a value of type 'f64'"] ;
+        "tuple pattern"
+    )]
+    #[test_case(
+        "union shape = dot; union color = red; fn f(shape s) -> i32 { when s { red: 1; _: 0; } }",
+        &["Pattern 'red' cannot match a value of type 'shape'"] ;
+        "variant of another union"
+    )]
+    #[test_case(
+        "union n = zero | succ(n); fn f(n x) -> i32 { when x { zero(y): 0; _: 1; } }",
+        &["Variant 'zero' has no payload to match"] ;
+        "payload of a variant without one"
+    )]
+    #[test_case(
+        "union n = zero | succ(n); fn f(n x) -> i32 { when x { succ: 1; _: 0; } }",
+        &["Variant 'succ' has a payload the pattern must match"] ;
+        "missing payload"
+    )]
+    #[test_case("fn f(i32 a) -> i32 { when a { \"one\": 1; _: 0; } }", &["Pattern '\"one\"' cannot match a value of type 'i32'"] ; "literal pattern")]
+    #[test_case("fn f(char c) -> i32 { when c { 0..9: 1; _: 0; } }", &["Pattern '0..9' cannot match a value of type 'char'"] ; "range pattern")]
+    #[test_case(
+        "record point { x: i32; } fn f(point p) -> i32 { when p { point { y }: y; } }",
+        &["Type 'point' has no field 'y'"] ;
+        "record pattern field"
+    )]
+    #[test_case(
+        "record point { x: i32; } record size { x: i32; } fn f(size s) -> i32 { when s { point { x }: x; } }",
+        &["Pattern 'point { x }' cannot match a value of type 'size'"] ;
+        "record pattern of another record"
+    )]
+    fn test_type_errors(source: &str, expected: &[&str]) {
+        assert_eq!(type_errors(source), expected);
+    }
+
+    #[test_case("u8 x = 300;", &["Literal '300' does not fit in 'u8'"] ; "declared type")]
+    #[test_case("u32 x = -1;", &["Literal '-1' does not fit in 'u32'"] ; "negative unsigned")]
+    #[test_case("i8 x = -(129);", &["Literal '-129' does not fit in 'i8'"] ; "parenthesized")]
+    #[test_case("fn f(u16 n) {} fn g() { f(70000); }", &["Literal '70000' does not fit in 'u16'"] ; "argument")]
+    #[test_case("fn f() -> i8 { 200 }", &["Literal '200' does not fit in 'i8'"] ; "return value")]
+    #[test_case("u8 n = 1; b = n < 256;", &["Literal '256' does not fit in 'u8'"] ; "operand")]
+    #[test_case("x = 3000000000;", &["Literal '3000000000' does not fit in 'i32'"] ; "default type")]
+    #[test_case("i64 x = 9223372036854775808;", &["Literal '9223372036854775808' does not fit in 'i64'"] ; "above i64")]
+    fn test_literal_out_of_range(source: &str, expected: &[&str]) {
+        assert_eq!(type_errors(source), expected);
+    }
+
+    #[test_case("u8 x = 255; u8 y = 0; u8 z = -0;" ; "unsigned bounds")]
+    #[test_case("i8 x = -128; i8 y = 127; i8 z = -(-1);" ; "signed bounds")]
+    #[test_case("i64 x = 9223372036854775807; u64 y = 3000000000;" ; "wide types")]
+    #[test_case("u64 x = 18446744073709551615; usize y = 18446744073709551615;" ; "full unsigned range")]
+    #[test_case("f32 x = -1.5;" ; "float")]
+    fn test_literal_in_range(source: &str) {
+        assert_eq!(type_errors(source), Vec::<String>::new());
+    }
+
+    #[test_case("i32 n = 1; x = n as f64;", Type::F64 ; "integer to float")]
+    #[test_case("u8 n = 1; x = n as u32;", Type::U32 ; "widening")]
+    #[test_case("f32 n = 1.0; x = n as f64;", Type::F64 ; "float widening")]
+    #[test_case("i16 n = 1; x = n as f32;", Type::F32 ; "small integer to f32")]
+    #[test_case("i32 @n = 1; r = &@n; x = r as i32&;", Type::Reference { is_mutable: false, ref_type: Box::new(Type::I32) } ; "dropping mutability")]
+    #[test_case("string s = \"a\"; x = s as string;", Type::String ; "same type")]
+    fn test_exact_cast(source: &str, expected: Type) {
+        assert_eq!(type_of_last(source), expected);
+    }
+
+    #[test_case("i32 n = 1; x = n as u8;", "'i32' to 'u8'" ; "narrowing")]
+    #[test_case("i32 n = 1; x = n as u32;", "'i32' to 'u32'" ; "signed to unsigned")]
+    #[test_case("u64 n = 1; x = n as i64;", "'u64' to 'i64'" ; "unsigned to signed")]
+    #[test_case("f64 n = 1.5; x = n as i64;", "'f64' to 'i64'" ; "float to integer")]
+    #[test_case("f64 n = 1.5; x = n as f32;", "'f64' to 'f32'" ; "float narrowing")]
+    #[test_case("i64 n = 1; x = n as f64;", "'i64' to 'f64'" ; "wide integer to float")]
+    #[test_case("i32 n = 1; x = n as f32;", "'i32' to 'f32'" ; "integer to f32")]
+    fn test_lossy_cast(source: &str, conversion: &str) {
+        assert_eq!(
+            type_errors(source),
+            [format!("Casting {} may lose information", conversion)]
+        );
+    }
+
+    #[test_case("s = \"1\"; x = s as i32;", "Cannot cast 'string' to 'i32'" ; "string to integer")]
+    #[test_case("b = true; x = b as i32;", "Cannot cast 'bool' to 'i32'" ; "bool to integer")]
+    #[test_case("n = 1; x = n as string;", "Cannot cast 'i32' to 'string'" ; "integer to string")]
+    #[test_case("n = 1; r = &n; x = r as i32&@;", "Cannot cast 'i32&' to 'i32&@'" ; "adding mutability")]
+    #[test_case("n = 1; r = &n; x = r as f64&;", "Cannot cast 'i32&' to 'f64&'" ; "reference to another type")]
+    #[test_case("n = 1; r = &n; x = r as i64;", "Cannot cast 'i32&' to 'i64'" ; "reference to number")]
+    fn test_invalid_cast(source: &str, expected: &str) {
+        assert_eq!(type_errors(source), [expected]);
+    }
+
+    #[test]
+    fn test_lossy_cast_is_a_warning() {
+        let program = parse("i32 n = 1; x = n as u8;").unwrap();
+        let index = NodeIndex::new(&program);
+        let resolution = resolve(&program, &index);
+        let errors = check(&program, &index, &resolution).errors;
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].severity(), Severity::Warning);
+    }
+
+    #[test_case("string s = len([1]);", &["Expected 'string', found 'i32'"] ; "result of len")]
+    #[test_case("x = len(1, 2);", &["'len' takes 1 arguments, but 2 were given"] ; "too many arguments")]
+    #[test_case("x = to_string();", &["'to_string' takes 1 arguments, but 0 were given"] ; "too few arguments")]
+    #[test_case("assert(5);", &["Expected 'bool', found 'i32'"] ; "assert of an integer")]
+    #[test_case("x = len(5);", &["'len' cannot take a value of type 'i32'"] ; "len of an integer")]
+    #[test_case("i32 n = to_string(1);", &["Expected 'i32', found 'string'"] ; "result of to_string")]
+    fn test_builtin_call_errors(source: &str, expected: &[&str]) {
+        assert_eq!(type_errors(source), expected);
+    }
+
+    #[test_case("xs = [1, 2]; n = len(xs);", Type::I32 ; "len of an array")]
+    #[test_case("u64 n = len(\"ab\");", Type::U64 ; "len taking the expected type")]
+    #[test_case("r = 0..3; n = len(&r);", Type::I32 ; "len of a range reference")]
+    #[test_case("s = to_string([1]);", Type::String ; "to_string")]
+    #[test_case("u = println(1, \"a\", [true]);", Type::Unit ; "println of any values")]
+    fn test_builtin_call(source: &str, expected: Type) {
+        assert_eq!(type_of_last(source), expected);
+    }
+
+    #[test_case("fn f() -> i32 { 1 } fn main() { f(); 5; }", &["Result of type 'i32' is not used", "Result of type 'i32' is not used"] ; "call and literal")]
+    #[test_case("patch string { fn trim() -> string { self } } \"a \".trim();", &["Result of type 'string' is not used"] ; "method call")]
+    #[test_case("fn f() {} fn g(bool b) { f(); (); if b { 1 } else { 2 } loop { break 1; } f(); }", &[] ; "unit and block-like statements")]
+    #[test_case("fn f(arrayList<i32> xs) { print(xs); }", &[] ; "builtin result")]
+    fn test_unused_result(source: &str, expected: &[&str]) {
+        assert_eq!(type_errors(source), expected);
+    }
+
+    #[test]
+    fn test_unused_result_is_a_warning() {
+        let program = parse("1 + 2;").unwrap();
+        let index = NodeIndex::new(&program);
+        let resolution = resolve(&program, &index);
+        let errors = check(&program, &index, &resolution).errors;
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].severity(), Severity::Warning);
+    }
+
+    #[test]
+    fn test_every_expression_has_a_type() {
+        let program = parse(
+            "fn f(i32 a) -> i32 { b = a * 2; if b > 10 { b } else { g(b) } } fn g(i32 c) -> i32 { c }",
+        )
+        .expect("Parse error");
+        let index = NodeIndex::new(&program);
+        let resolution = resolve(&program, &index);
+        let checked = check(&program, &index, &resolution);
+
+        struct Expressions<'i>(&'i NodeIndex, Vec<NodeId>);
+        impl Visitor for Expressions<'_> {
+            fn visit_expression(&mut self, expression: &Expression) {
+                self.1.push(self.0.id(expression).unwrap());
+                walk_expression(self, expression);
+            }
+        }
+        let mut expressions = Expressions(&index, Vec::new());
+        expressions.visit_program(&program);
+
+        let untyped = expressions
+            .1
+            .iter()
+            .filter(|&&id| checked.types.expression(id).is_none())
+            .count();
+        assert_eq!(untyped, 0);
+    }
+
+    #[test]
+    fn test_errors_point_at_their_node() {
+        let program = parse("fn f() { i32 x = true; }").expect("Parse error");
+        let index = NodeIndex::new(&program);
+        let resolution = resolve(&program, &index);
+        let checked = check(&program, &index, &resolution);
+
+        let Declaration::Function(function) = &program.declarations[0] else {
+            panic!("expected a function");
+        };
+        let ExpressionKind::Block { statements, .. } = &function.body.kind else {
+            panic!("expected a block");
+        };
+        let StatementKind::VariableDeclaration { value, .. } = &statements[0].kind else {
+            panic!("expected a declaration");
+        };
+        assert_eq!(checked.errors.len(), 1);
+        assert_eq!(checked.errors[0].node(), index.id(value.as_ref()).unwrap());
+    }
+
+    #[test]
+    fn test_method_calls_resolve_to_the_patch_of_their_receiver() {
+        let program = parse(
+            "patch i32 { fn describe() -> string { \"int\" } }
+             patch string { fn describe() -> string { self } }
+             x = \"s\".describe();",
+        )
+        .expect("Parse error");
+        let index = NodeIndex::new(&program);
+        let resolution = resolve(&program, &index);
+        let checked = check(&program, &index, &resolution);
+
+        let Declaration::Patch(string_patch) = &program.declarations[1] else {
+            panic!("expected a patch");
+        };
+        let Declaration::Statement(Statement {
+            kind: StatementKind::VariableDeclaration { value, .. },
+            ..
+        }) = &program.declarations[2]
+        else {
+            panic!("expected a declaration");
+        };
+        assert_eq!(
+            checked.types.method(index.id(value.as_ref()).unwrap()),
+            index.id(&string_patch.methods[0])
+        );
+    }
+
+    #[test]
+    fn test_calls_to_host_functions_are_checked() {
+        let hosts = [Host {
+            name: "shout".to_string(),
+            ty: Type::Function {
+                param_types: vec![Type::String],
+                return_type: Some(Box::new(Type::String)),
+            },
+        }];
+        let program = parse("loud = shout(\"hi\"); i32 n = shout(1);").expect("Parse error");
+        let index = NodeIndex::new(&program);
+        let resolution = crate::resolve::resolve_with_hosts(&program, &index, &hosts);
+        let checked = check_with_hosts(&program, &index, &resolution, &hosts);
+
+        assert_eq!(resolution.errors, []);
+        let errors: Vec<String> = checked.errors.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            errors,
+            [
+                "Expected 'string', found 'i32'",
+                "Expected 'i32', found 'string'"
+            ]
+        );
+    }
+}
@@ -0,0 +1,188 @@
+//! Warnings about names a program defines but never uses.
+//!
+//! A variable, parameter, pattern binding, or `for` variable that is never read is reported, as
+//! is a function that is never called. Assigning to a variable with `(x) = value` does not read
+//! it. `main`, which runs the program, counts as used, as does a `pub` function, which another
+//! module may call, and any name that starts with an underscore, which is how to keep a name
+//! that is unused on purpose, such as a parameter a callback must accept.
+
+use crate::diagnostic::Diagnostic;
+use crate::resolve::Resolution;
+use crate::symbols::SymbolKind;
+use parser::ast::{BinaryOperator, Declaration, Program, Statement, StatementKind};
+use parser::node_id::{NodeId, NodeIndex, NodeKind};
+use parser::visit::{Visitor, walk_statement};
+use std::collections::HashSet;
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Clone)]
+pub enum UnusedWarning {
+    /// `node` is the node that defines the name.
+    #[error("Unused {kind} '{name}'")]
+    Binding {
+        name: String,
+        kind: SymbolKind,
+        node: NodeId,
+    },
+    /// `node` is the function declaration.
+    #[error("Function '{name}' is never called")]
+    Function { name: String, node: NodeId },
+}
+
+impl From<UnusedWarning> for Diagnostic {
+    fn from(warning: UnusedWarning) -> Self {
+        let node = match &warning {
+            UnusedWarning::Binding { node, .. } | UnusedWarning::Function { node, .. } => *node,
+        };
+        Diagnostic::warning(warning.to_string(), node)
+    }
+}
+
+/// Report every unused name in `program`, in the order the names are defined.
+pub fn check_unused(
+    program: &Program,
+    index: &NodeIndex,
+    resolution: &Resolution,
+) -> Vec<Diagnostic> {
+    let mut targets = AssignmentTargets {
+        index,
+        nodes: HashSet::new(),
+    };
+    targets.visit_program(program);
+    let public: HashSet<NodeId> = program
+        .declarations
+        .iter()
+        .filter_map(|declaration| match declaration {
+            Declaration::Function(function) if function.is_public => index.id(function),
+            _ => None,
+        })
+        .collect();
+
+    // A pattern that repeats a binding in another alternative of an or-pattern defines it
+    // again rather than reading it.
+    let read: HashSet<_> = resolution
+        .references
+        .iter()
+        .filter(|(node, _)| {
+            !targets.nodes.contains(node) && index.kind(**node) != Some(NodeKind::Pattern)
+        })
+        .map(|(_, symbol)| *symbol)
+        .collect();
+
+    let symbols = &resolution.symbols;
+    resolution
+        .own_symbols()
+        .filter(|(id, _)| !read.contains(id) && !symbols.symbol_name(*id).starts_with('_'))
+        .filter_map(|(id, symbol)| {
+            let name = symbols.symbol_name(id).to_string();
+            let node = symbol.node?;
+            let warning = match symbol.kind {
+                SymbolKind::Function if name == "main" || public.contains(&node) => return None,
+                SymbolKind::Function => UnusedWarning::Function { name, node },
+                SymbolKind::Variable { .. }
+                | SymbolKind::Parameter
+                | SymbolKind::Binding
+                | SymbolKind::LoopVariable => UnusedWarning::Binding {
+                    name,
+                    kind: symbol.kind,
+                    node,
+                },
+                _ => return None,
+            };
+            Some(warning.into())
+        })
+        .collect()
+}
+
+/// The identifiers that are only written to, as the target of a plain assignment.
+struct AssignmentTargets<'i> {
+    index: &'i NodeIndex,
+    nodes: HashSet<NodeId>,
+}
+
+impl Visitor for AssignmentTargets<'_> {
+    fn visit_statement(&mut self, statement: &Statement) {
+        if let StatementKind::Assignment {
+            target,
+            operator: BinaryOperator::Assign,
+            ..
+        } = &statement.kind
+            && let Some(node) = self.index.id(target.ungrouped())
+        {
+            self.nodes.insert(node);
+        }
+        walk_statement(self, statement);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostic::Severity;
+    use crate::resolve::resolve;
+    use parser::parse;
+    use test_case::test_case;
+
+    fn unused(source: &str) -> Vec<String> {
+        let program = parse(source).expect("Parse error");
+        let index = NodeIndex::new(&program);
+        let resolution = resolve(&program, &index);
+        check_unused(&program, &index, &resolution)
+            .iter()
+            .map(|diagnostic| {
+                assert_eq!(diagnostic.severity, Severity::Warning);
+                diagnostic.message.clone()
+            })
+            .collect()
+    }
+
+    #[test_case("fn main() { x = 1; print(x); }" ; "read variable")]
+    #[test_case("fn main() { print(twice(2)); } fn twice(i32 n) -> i32 { n * 2 }" ; "called function")]
+    #[test_case("fn main() { @n = 0; n += 1; print(n); }" ; "compound assignment")]
+    #[test_case("fn main() { _scratch = 1; } fn _helper(i32 _n) {}" ; "underscore")]
+    #[test_case("pub fn helper() {} fn main() {}" ; "public function")]
+    #[test_case(
+        "union shape = circle(f64) | square(f64); fn main() { when circle(1.0) { circle(s) | square(s): print(s); }; }" ;
+        "or-pattern binding"
+    )]
+    #[test_case("fn main() { for _ in [1, 2] { print(0); } }" ; "wildcard loop variable")]
+    fn test_nothing_unused(source: &str) {
+        assert_eq!(unused(source), Vec::<String>::new());
+    }
+
+    #[test_case("fn main() { x = 1; }", &["Unused variable 'x'"] ; "variable")]
+    #[test_case("fn main() { @x = 1; (x) = 2; }", &["Unused variable 'x'"] ; "only assigned")]
+    #[test_case("fn main() { f(1); } fn f(i32 a, i32 b) { print(a); }", &["Unused parameter 'b'"] ; "parameter")]
+    #[test_case("fn helper() {} fn main() {}", &["Function 'helper' is never called"] ; "function")]
+    #[test_case("fn main() { for item in [1] { print(0); } }", &["Unused loop variable 'item'"] ; "loop variable")]
+    #[test_case(
+        "union option<T> = some(T) | none; fn main() { when some(1) { some(v): print(0); none: print(1); }; }",
+        &["Unused binding 'v'"] ;
+        "pattern binding"
+    )]
+    #[test_case("fn main() { f = |a, b| a; print(f(1, 2)); }", &["Unused parameter 'b'"] ; "closure parameter")]
+    fn test_unused(source: &str, expected: &[&str]) {
+        assert_eq!(unused(source), expected);
+    }
+
+    #[test]
+    fn test_unused_declarations_are_located() {
+        let mut sources = lexer::source::SourceMap::new();
+        let file = sources.add_file("main.cv", "fn main() {}\nfn helper(\n    i32 count) {}");
+        let (program, spans, _) = parser::parse_file(sources.get(file).unwrap());
+        let index = NodeIndex::new(&program);
+        let resolution = resolve(&program, &index);
+        let rendered: Vec<_> = check_unused(&program, &index, &resolution)
+            .iter()
+            .map(|diagnostic| diagnostic.render(&spans, &sources))
+            .collect();
+
+        assert_eq!(
+            rendered,
+            [
+                "warning at 2:4: Function 'helper' is never called",
+                "warning at 3:9: Unused parameter 'count'",
+            ]
+        );
+    }
+}
@@ -1,3 +1,739 @@
-fn main() {
-    println!("Hello, world!");
+use crate::syntax::SyntaxFormat;
+use interp::backtrace::Frame;
+use interp::{Interpreter, RuntimeError, Vm};
+use lexer::Lexer;
+use lexer::fingerprint::Fingerprint;
+use lexer::source::SourceMap;
+use parser::ast::Program;
+use parser::node_id::{NodeId, NodeIds, NodeIndex};
+use parser::spans::Spans;
+use parser::{ParseError, Parser};
+use semantics::modules::LoadError;
+use semantics::{
+    Diagnostic, Linked, Module, Resolution, Severity, ShadowingLint, TypeCheck, TypeMap,
+};
+use std::path::Path;
+use std::process::ExitCode;
+
+mod repl;
+mod syntax;
+
+const USAGE: &str = "Usage:
+  cv run [--vm] [--no-prelude] [--warn-shadowing] <file>
+  cv repl [--no-prelude]
+  cv fingerprint <file>
+  cv --emit ast <file>
+  cv export-syntax --format=tmlanguage|vim|emacs";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.as_slice() {
+        [command, flags @ .., path] if command == "run" => match Options::parse(flags) {
+            Some(options) => run(path, options),
+            None => usage(),
+        },
+        [command] if command == "repl" => repl::start(true),
+        [command, flag] if command == "repl" && flag == "--no-prelude" => repl::start(false),
+        [command, path] if command == "fingerprint" => fingerprint(path),
+        [command, format] if command == "export-syntax" => export_syntax(format),
+        [flag, kind, path] if flag == "--emit" && kind == "ast" => emit_ast(path),
+        _ => usage(),
+    }
+}
+
+fn usage() -> ExitCode {
+    eprintln!("{}", USAGE);
+    ExitCode::FAILURE
+}
+
+/// The stack of the thread a program runs on. The interpreter recurses as deeply as the program
+/// does, so this leaves room for [`interp::MAX_CALL_DEPTH`] nested calls.
+const RUN_STACK_SIZE: usize = 256 * 1024 * 1024;
+
+/// How `cv run` runs a program.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Engine {
+    Interpreter,
+    /// Compile the program to bytecode and run it on the virtual machine.
+    Vm,
+}
+
+/// How `cv run` checks and runs a program, as its flags say.
+#[derive(Clone, Copy)]
+struct Options {
+    engine: Engine,
+    /// Whether programs get the [prelude](semantics::prelude).
+    prelude: bool,
+    /// Which declarations that hide another name to warn about.
+    shadowing: ShadowingLint,
+}
+
+impl Options {
+    /// The options `flags` give, or `None` if one of them is unknown.
+    fn parse(flags: &[String]) -> Option<Self> {
+        let mut options = Options {
+            engine: Engine::Interpreter,
+            prelude: true,
+            shadowing: ShadowingLint::SameScope,
+        };
+        for flag in flags {
+            match flag.as_str() {
+                "--vm" => options.engine = Engine::Vm,
+                "--no-prelude" => options.prelude = false,
+                "--warn-shadowing" => options.shadowing = ShadowingLint::All,
+                _ => return None,
+            }
+        }
+        Some(options)
+    }
+}
+
+/// Check the program in the file at `path` and, if it has no errors, run it as `options` say.
+fn run(path: &str, options: Options) -> ExitCode {
+    let source = match read_source(path) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut sources = SourceMap::new();
+    let file = sources.add_file(path, source.as_str());
+    let (program, mut spans, errors) =
+        parser::parse_file(sources.get(file).expect("file was just added"));
+    if !errors.is_empty() {
+        for error in &errors {
+            eprintln!("{}: {}", path, render_parse_error(&source, error));
+        }
+        return ExitCode::FAILURE;
+    }
+    let linked = match link(path, program, &mut sources, &mut spans) {
+        Ok(linked) if options.prelude => linked.with_prelude(),
+        Ok(linked) => linked,
+        Err(messages) => {
+            for message in &messages {
+                eprintln!("{}", message);
+            }
+            return ExitCode::FAILURE;
+        }
+    };
+    let program = &linked.program;
+
+    let index = NodeIndex::new(program);
+    let resolution = semantics::resolve(program, &index);
+    let checked = semantics::check(program, &index, &resolution);
+    let diagnostics = diagnose(&linked, &index, &resolution, &checked, options.shadowing);
+    let (messages, failed) = report(&diagnostics, &spans, &sources);
+    for (diagnostic, message) in diagnostics.iter().zip(&messages) {
+        eprintln!(
+            "{}: {}",
+            file_of(diagnostic.node, path, &spans, &sources),
+            message
+        );
+    }
+    if failed {
+        return ExitCode::FAILURE;
+    }
+
+    let result = std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .stack_size(RUN_STACK_SIZE)
+            .spawn_scoped(scope, || {
+                execute(program, &index, &checked.types, options.engine).map_err(
+                    |(error, backtrace)| {
+                        runtime_error_in(path, &error, &backtrace, &spans, &sources)
+                    },
+                )
+            })
+            .expect("the interpreter thread can be started")
+            .join()
+            .expect("the interpreter does not panic")
+    });
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("{}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// The name of the file `node` was written in, which may be an imported module, or `path`, the
+/// file being run, for a node without a span.
+fn file_of<'a>(node: NodeId, path: &'a str, spans: &Spans, sources: &'a SourceMap) -> &'a str {
+    spans
+        .get(node)
+        .and_then(|span| sources.get(span.file))
+        .map_or(path, |file| file.name.as_str())
+}
+
+/// Link `program`, parsed from the file at `path`, with every module it imports from the same
+/// directory, directly or not. Each imported module is added to `sources` and parsed with ids
+/// after the ones before it, and where its nodes were written is added to `spans`. Returns the
+/// messages for the modules that cannot be read or parsed.
+fn link(
+    path: &str,
+    program: Program,
+    sources: &mut SourceMap,
+    spans: &mut Spans,
+) -> Result<Linked, Vec<String>> {
+    let path = Path::new(path);
+    let directory = path.parent().unwrap_or(Path::new(""));
+    let mut ids = NodeIds::after(&program);
+    let mut messages = Vec::new();
+    let entry = Module {
+        name: module_name(path),
+        program,
+    };
+    let modules = semantics::modules::load_imports(directory, entry, |_, path, source| {
+        let file = sources.add_file(path.display().to_string(), source);
+        let file = sources.get(file).expect("file was just added");
+        let (program, parsed, errors) = parser::parse_file_numbered(file, ids.clone());
+        for error in &errors {
+            let message = render_parse_error(&file.content, error);
+            messages.push(format!("{}: {}", file.name, message));
+        }
+        // A module without declarations hands out no ids, so the next one starts where it did.
+        if !program.declarations.is_empty() {
+            ids = NodeIds::after(&program);
+        }
+        spans.extend(parsed);
+        Ok(program)
+    });
+    match modules {
+        Ok(modules) if messages.is_empty() => Ok(Linked::numbered(modules)),
+        Ok(_) => Err(messages),
+        Err(error) => Err(vec![format!("{}: {}", path.display(), error)]),
+    }
+}
+
+/// The name of the module in the file at `path`, such as `geometry` for `geometry.cv`.
+fn module_name(path: &Path) -> String {
+    path.file_stem()
+        .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned())
+}
+
+/// Every problem the checks find in `linked`, given its resolution and type check, with the
+/// shadowing `shadowing` asks for.
+fn diagnose(
+    linked: &Linked,
+    index: &NodeIndex,
+    resolution: &Resolution,
+    checked: &TypeCheck,
+    shadowing: ShadowingLint,
+) -> Vec<Diagnostic> {
+    let program = &linked.program;
+    let mut diagnostics = semantics::check_program(program, index, resolution, checked, shadowing);
+    diagnostics.extend(
+        semantics::check_modules(linked, index, resolution, &checked.types)
+            .into_iter()
+            .map(Diagnostic::from),
+    );
+    diagnostics
+}
+
+/// Run a checked program on `engine`, returning the error that stopped it, if one did, with the
+/// calls that were running.
+fn execute<'p>(
+    program: &'p Program,
+    index: &'p NodeIndex,
+    types: &'p TypeMap,
+    engine: Engine,
+) -> Result<(), (RuntimeError, Vec<Frame<'p>>)> {
+    match engine {
+        Engine::Interpreter => {
+            let mut interpreter = Interpreter::new(program, index, types);
+            interpreter
+                .run()
+                .map_err(|error| (error, interpreter.backtrace().to_vec()))?;
+        }
+        Engine::Vm => {
+            let bytecode = interp::compile(program, index, types);
+            let mut vm = Vm::new(&bytecode);
+            vm.run().map_err(|error| (error, vm.backtrace()))?;
+        }
+    }
+    Ok(())
+}
+
+/// The message reporting a runtime `error`, at the places in `sources` the calls of `backtrace`
+/// were written, which `spans` gives.
+fn runtime_error(
+    error: &RuntimeError,
+    backtrace: &[Frame],
+    spans: &Spans,
+    sources: &SourceMap,
+) -> String {
+    match interp::backtrace::diagnostic(error, backtrace) {
+        Some(diagnostic) => diagnostic.render(spans, sources),
+        None => format!("runtime error: {}", error),
+    }
+}
+
+/// [`runtime_error`] after the name of the file the error happened in, which may be an imported
+/// module, or of `path`, the file being run, if that is not known.
+fn runtime_error_in(
+    path: &str,
+    error: &RuntimeError,
+    backtrace: &[Frame],
+    spans: &Spans,
+    sources: &SourceMap,
+) -> String {
+    let file = interp::backtrace::diagnostic(error, backtrace).map_or(path, |diagnostic| {
+        file_of(diagnostic.node, path, spans, sources)
+    });
+    format!(
+        "{}: {}",
+        file,
+        runtime_error(error, backtrace, spans, sources)
+    )
+}
+
+/// The messages to print for the problems [`semantics::check_program`] found, with the places in
+/// `sources` that `spans` gives for the nodes they are about, and whether any of them is an error.
+fn report(diagnostics: &[Diagnostic], spans: &Spans, sources: &SourceMap) -> (Vec<String>, bool) {
+    let failed = diagnostics
+        .iter()
+        .any(|diagnostic| diagnostic.severity == Severity::Error);
+    let messages = diagnostics
+        .iter()
+        .map(|diagnostic| diagnostic.render(spans, sources))
+        .collect();
+    (messages, failed)
+}
+
+/// Print the content fingerprint of the file at `path` and the modules it imports, for build
+/// systems to key caches on.
+fn fingerprint(path: &str) -> ExitCode {
+    match fingerprint_file(path) {
+        Ok(fingerprint) => {
+            println!("{}", fingerprint);
+            ExitCode::SUCCESS
+        }
+        Err(message) => {
+            eprintln!("{}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// The fingerprint of the file at `path`, with its version, folded together with the ones of
+/// the modules it imports, directly or not, in the order they are imported.
+fn fingerprint_file(path: &str) -> Result<Fingerprint, String> {
+    let (module, program) = read_source(path)
+        .and_then(|source| fingerprint_module(&source).map_err(|e| e.to_string()))
+        .map_err(|e| format!("{}: {}", path, e))?;
+    let path = Path::new(path);
+    let entry = Module {
+        name: module_name(path),
+        program,
+    };
+    let mut dependencies = Vec::new();
+    let directory = path.parent().unwrap_or(Path::new(""));
+    semantics::modules::load_imports(directory, entry, |name, _, source| {
+        let (dependency, program) =
+            fingerprint_module(&source).map_err(|error| LoadError::Parse {
+                module: name.to_string(),
+                error: Box::new(error),
+            })?;
+        dependencies.push(dependency);
+        Ok(program)
+    })
+    .map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(module.with_dependencies(&dependencies))
+}
+
+/// The fingerprint of the tokens of `source` and the version it declares, with the program it
+/// parses to as far as it does, whose imports are the modules it depends on.
+fn fingerprint_module(source: &str) -> Result<(Fingerprint, Program), ParseError> {
+    let mut lexer = Lexer::new(source).fold_negative_literals();
+    let version = lexer.version_pragma()?;
+    let tokens = lexer.tokenize()?;
+    let fingerprint = Fingerprint::of_tokens(&tokens).with_version(version);
+    let (program, _) = Parser::new(tokens).parse_program_recovering();
+    Ok((fingerprint, program))
+}
+
+/// Print the syntax tree of the file at `path` as JSON, for external tools to consume.
+fn emit_ast(path: &str) -> ExitCode {
+    let json = match read_source(path).and_then(|source| ast_json(&source)) {
+        Ok(json) => json,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    println!("{}", json);
+    ExitCode::SUCCESS
+}
+
+/// The syntax tree of `source` as JSON, or every syntax error in it, one after another.
+fn ast_json(source: &str) -> Result<String, String> {
+    let (program, errors) = parser::parse_all(source);
+    if !errors.is_empty() {
+        let rendered: Vec<String> = errors
+            .iter()
+            .map(|error| render_parse_error(source, error))
+            .collect();
+        return Err(rendered.join("\n"));
+    }
+    serde_json::to_string_pretty(&program).map_err(|e| e.to_string())
+}
+
+/// Render a parse error with its code, its location as `line:column`, the location that
+/// explains it, and the fix to apply, each on a line of its own.
+fn render_parse_error(source: &str, error: &ParseError) -> String {
+    let mut sources = SourceMap::new();
+    let file = sources.add_file("", source);
+    let file = sources.get(file).expect("file was just added");
+    let location = |offset: usize| {
+        let (line, column) = file.line_col(offset);
+        format!("{}:{}", line, column)
+    };
+
+    let mut rendered = match error.span() {
+        Some(span) => format!(
+            "error[{}] at {}: {}",
+            error.code(),
+            location(span.start),
+            error.message()
+        ),
+        None => format!("error[{}]: {}", error.code(), error),
+    };
+    if let Some((span, label)) = error.secondary_span() {
+        rendered.push_str(&format!("\n  note: {} at {}", label, location(span.start)));
+    }
+    if let Some(suggestion) = error.suggestion() {
+        rendered.push_str(&format!("\n  help: {}", suggestion.message));
+    }
+    rendered
+}
+
+/// Read a source file. The lexer only accepts valid UTF-8, so a file that is not is rejected
+/// here with the byte offset of the first invalid sequence.
+fn read_source(path: &str) -> Result<String, String> {
+    let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+    decode_source(bytes)
+}
+
+fn decode_source(bytes: Vec<u8>) -> Result<String, String> {
+    String::from_utf8(bytes)
+        .map_err(|e| format!("invalid UTF-8 at byte {}", e.utf8_error().valid_up_to()))
+}
+
+/// Print a syntax highlighting definition for the editor format named by `--format=<name>`.
+fn export_syntax(format: &str) -> ExitCode {
+    match format
+        .strip_prefix("--format=")
+        .and_then(SyntaxFormat::parse)
+    {
+        Some(format) => {
+            print!("{}", format.generate());
+            ExitCode::SUCCESS
+        }
+        None => usage(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_source() {
+        assert_eq!(decode_source(b"x = 1;".to_vec()), Ok("x = 1;".to_string()));
+        assert_eq!(
+            decode_source(b"x = \"\xff\";".to_vec()),
+            Err("invalid UTF-8 at byte 5".to_string())
+        );
+    }
+
+    #[test]
+    fn test_ast_json_round_trips() {
+        let source = "fn main() { @total = sum(items, |x| x * 2); }";
+        let json = ast_json(source).expect("Emit error");
+        let program: parser::ast::Program = serde_json::from_str(&json).expect("Invalid JSON");
+
+        assert_eq!(program, parser::parse(source).unwrap());
+    }
+
+    #[test]
+    fn test_parse_errors_are_rendered_with_code_and_help() {
+        assert_eq!(
+            ast_json("fn main() {\n    f(1;\n}"),
+            Err(
+                "error[E0005] at 2:7: Expected ')', found ';'\n  note: opened here at 2:6\n  help: insert the missing ')'"
+                    .to_string()
+            )
+        );
+    }
+
+    /// The messages `cv run` prints for the problems in the file at `path` with `source` in it,
+    /// checked with the prelude and with the shadowing `shadowing` asks for, and whether any of
+    /// them is an error.
+    fn check_file(path: &str, source: &str, shadowing: ShadowingLint) -> (Vec<String>, bool) {
+        let mut sources = SourceMap::new();
+        let file = sources.add_file(path, source);
+        let (program, mut spans, errors) = parser::parse_file(sources.get(file).unwrap());
+        assert_eq!(errors, []);
+        let linked = link(path, program, &mut sources, &mut spans)
+            .unwrap()
+            .with_prelude();
+        let index = NodeIndex::new(&linked.program);
+        let resolution = semantics::resolve(&linked.program, &index);
+        let checked = semantics::check(&linked.program, &index, &resolution);
+
+        let diagnostics = diagnose(&linked, &index, &resolution, &checked, shadowing);
+        report(&diagnostics, &spans, &sources)
+    }
+
+    /// [`check_file`] for a file of the current directory with the default options.
+    fn check_source(source: &str) -> (Vec<String>, bool) {
+        check_file("main.cv", source, ShadowingLint::SameScope)
+    }
+
+    #[test]
+    fn test_check_program_reports_errors_before_running() {
+        let (messages, failed) = check_source(
+            "fn main() {\n    x = 1;\n    (x) = 2;\n    print(y);\n    @n = 0;\n    n = n + 1;\n}",
+        );
+        assert!(failed);
+        assert_eq!(
+            messages,
+            [
+                "error at 4:11: Cannot find 'y' in this scope",
+                "error at 3:5: Cannot assign to immutable variable 'x'\n  note: declared here at 2:5",
+                "warning at 6:5: 'n' is declared again in the same scope, hiding a mutable variable; to assign to it, write '(n) = ...'\n  note: declared here at 5:5",
+                "warning at 2:5: Unused variable 'x'",
+                "warning at 6:5: Unused variable 'n'",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_duplicates_are_reported_at_both_occurrences() {
+        let (messages, failed) = check_source(
+            "record point { x: i32; y: i32; }\nprintln(point { x: 1,\n  x: 2, y: 3 });",
+        );
+        assert!(failed);
+        assert_eq!(
+            messages,
+            [
+                "error at 3:6: Duplicate field 'x' in literal of 'point'\n  note: first occurrence at 2:20"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validation_errors_are_located() {
+        let (messages, _) = check_source(
+            "union shape = circle(f64) | square(f64);\nconst i32 ratio = 1 / 0;\n\
+             fn f(shape s) -> f64 {\n    when s { circle(r) | square(side): r * side; }\n}",
+        );
+        assert_eq!(
+            messages[..3],
+            [
+                "error at 2:11: Cannot evaluate const 'ratio': Division by zero",
+                "error at 4:14: Alternative 1 of an or-pattern does not bind 'side', but another alternative does",
+                "error at 4:26: Alternative 2 of an or-pattern does not bind 'r', but another alternative does",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_i64_minimum_is_a_literal() {
+        assert_eq!(
+            check_source("pub fn smallest() -> i64 { -9223372036854775808 }"),
+            (Vec::new(), false)
+        );
+    }
+
+    #[test]
+    fn test_unreachable_branches_are_located_at_their_pattern() {
+        let (messages, failed) = check_source(
+            "pub fn f(i32 n) -> i32 {\n    when n {\n        _: 0;\n        1: 1;\n    }\n}",
+        );
+        assert!(!failed);
+        assert_eq!(
+            messages,
+            ["warning at 4:9: Unreachable branch '1': earlier branches match every value it does"]
+        );
+    }
+
+    #[test]
+    fn test_imported_modules_are_linked() {
+        let directory = std::env::temp_dir().join(format!("cv-link-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let geometry = "pub record point { pub x: f64; id: i32; }\n\
+                        pub fn origin() -> point { point { x: 0.0, id: 0 } }";
+        std::fs::write(directory.join("geometry.cv"), geometry).unwrap();
+        let path = directory.join("main.cv").display().to_string();
+        let checked = check_file(
+            &path,
+            "import geometry::{origin};\nfn main() { p = origin();\n  print(p.x, p.id); }",
+            ShadowingLint::SameScope,
+        );
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(
+            checked,
+            (
+                vec![
+                    "error at 3:14: Field 'id' of 'point' exists but is private to module 'geometry'"
+                        .to_string()
+                ],
+                true
+            )
+        );
+    }
+
+    #[test]
+    fn test_fingerprint_covers_imports_and_version() {
+        let directory = std::env::temp_dir().join(format!("cv-fingerprint-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("main.cv").display().to_string();
+        let fingerprint = |main: &str, geometry: &str| {
+            std::fs::write(&path, main).unwrap();
+            std::fs::write(directory.join("geometry.cv"), geometry).unwrap();
+            fingerprint_file(&path).unwrap()
+        };
+        let main = "import geometry::{origin};\nfn main() { origin(); }";
+
+        let original = fingerprint(main, "pub fn origin() -> i32 { 0 }");
+        let edited_import = fingerprint(main, "pub fn origin() -> i32 { 1 }");
+        let versioned = fingerprint(
+            &format!("#:version 0.1\n{}", main),
+            "pub fn origin() -> i32 { 0 }",
+        );
+        let reformatted = fingerprint(main, "pub fn origin() -> i32 {\n    0\n}");
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_ne!(original, edited_import);
+        assert_ne!(original, versioned);
+        assert_eq!(original, reformatted);
+    }
+
+    #[test]
+    fn test_generic_recursion_is_rejected_before_running() {
+        let (messages, failed) =
+            check_source("fn main() { nest(1); }\nfn nest<T>(T x) { nest([x]) }");
+        let nested = format!("{}i32{}", "arrayList<".repeat(32), ">".repeat(32));
+        assert!(failed);
+        assert_eq!(
+            messages,
+            [format!(
+                "error at 2:4: Instantiating 'nest' needs 'nest<{}>', more than 32 instances deep",
+                nested
+            )]
+        );
+    }
+
+    #[test]
+    fn test_prelude_has_no_diagnostics() {
+        assert_eq!(
+            check_file("main.cv", "", ShadowingLint::All),
+            (Vec::new(), false)
+        );
+    }
+
+    #[test]
+    fn test_runtime_errors_are_reported_with_backtrace() {
+        let mut sources = SourceMap::new();
+        let file = sources.add_file(
+            "main.cv",
+            "fn main() {\n    xs = [1, 2];\n    println(last(xs, 2));\n}\n\
+             fn last(arrayList<i32> xs, i32 n) -> i32 { xs[n] }",
+        );
+        let (program, spans, _) = parser::parse_file(sources.get(file).unwrap());
+        let program = semantics::with_prelude(program);
+        let index = NodeIndex::new(&program);
+        let resolution = semantics::resolve(&program, &index);
+        let checked = semantics::check(&program, &index, &resolution);
+
+        for engine in [Engine::Interpreter, Engine::Vm] {
+            let (error, backtrace) = execute(&program, &index, &checked.types, engine).unwrap_err();
+            assert_eq!(
+                runtime_error(&error, &backtrace, &spans, &sources),
+                "error at 5:44: Index 2 is out of bounds for length 2\n  \
+                 note: in last at 5:44\n  note: in main at 3:13"
+            );
+        }
+    }
+
+    #[test]
+    fn test_runtime_errors_name_the_file_they_happen_in() {
+        let directory = std::env::temp_dir().join(format!("cv-runtime-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(
+            directory.join("numbers.cv"),
+            "pub fn half(i32 n) -> i32 {\n    n / 0\n}",
+        )
+        .unwrap();
+        let path = directory.join("main.cv").display().to_string();
+        let mut sources = SourceMap::new();
+        let file = sources.add_file(
+            path.as_str(),
+            "import numbers::{half};\nfn main() { half(2); }",
+        );
+        let (program, mut spans, _) = parser::parse_file(sources.get(file).unwrap());
+        let linked = link(&path, program, &mut sources, &mut spans).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        let index = NodeIndex::new(&linked.program);
+        let resolution = semantics::resolve(&linked.program, &index);
+        let checked = semantics::check(&linked.program, &index, &resolution);
+
+        let (error, backtrace) =
+            execute(&linked.program, &index, &checked.types, Engine::Interpreter).unwrap_err();
+        let message = runtime_error_in(&path, &error, &backtrace, &spans, &sources);
+        let numbers = directory.join("numbers.cv").display().to_string();
+        assert_eq!(
+            message,
+            format!(
+                "{}: error at 2:5: Division by zero\n  note: in half at 2:5\n  note: in main at {}:2:13",
+                numbers, path
+            )
+        );
+    }
+
+    #[test]
+    fn test_run_options() {
+        let flags = |flags: &[&str]| {
+            let flags: Vec<String> = flags.iter().map(ToString::to_string).collect();
+            Options::parse(&flags)
+                .map(|options| (options.engine, options.prelude, options.shadowing))
+        };
+
+        assert_eq!(
+            flags(&[]),
+            Some((Engine::Interpreter, true, ShadowingLint::SameScope))
+        );
+        assert_eq!(
+            flags(&["--no-prelude", "--vm"]),
+            Some((Engine::Vm, false, ShadowingLint::SameScope))
+        );
+        assert_eq!(
+            flags(&["--warn-shadowing"]),
+            Some((Engine::Interpreter, true, ShadowingLint::All))
+        );
+        assert_eq!(flags(&["--fast"]), None);
+    }
+
+    #[test]
+    fn test_lexer_errors_are_rendered_with_location() {
+        assert_eq!(
+            ast_json("x = 1;\ny = 1.2.3;"),
+            Err("error[E0001] at 2:5: Invalid number format".to_string())
+        );
+    }
+
+    #[test]
+    fn test_every_parse_error_is_reported() {
+        assert_eq!(
+            ast_json("x = ;\ny = 1\n"),
+            Err("error[E0002] at 1:5: Expected expression, found ';'\n\
+                 error[E0004] at 2:5: Expected ';', found end of input\n  \
+                 help: insert the missing ';'"
+                .to_string())
+        );
+    }
 }
@@ -0,0 +1,535 @@
+//! `cv repl`: an interactive session that checks and runs one input at a time.
+//!
+//! Every input is checked on its own against what the earlier inputs declared, which [`Earlier`]
+//! keeps, so it can call their functions and read their variables without them being checked
+//! again. An input with an error is rejected as a whole. The interpreter keeps running with each
+//! accepted input [loaded](Interpreter::extend) on top of the ones before it, so variables keep
+//! the values earlier inputs gave them. The values it holds can point into the program of any
+//! input, so those programs are kept in [`Programs`], which outlives the session.
+
+use crate::{RUN_STACK_SIZE, render_parse_error, report, runtime_error};
+use interp::{Interpreter, Value};
+use lexer::source::SourceMap;
+use lexer::{Lexer, LexerError};
+use parser::ast::{Declaration, Program, Statement, StatementKind};
+use parser::node_id::{Node, NodeId, NodeIds, NodeIndex};
+use parser::spans::Spans;
+use parser::{ParseError, Parser};
+use semantics::{Earlier, Severity, ShadowingLint, TypeMap};
+use std::cell::OnceCell;
+use std::collections::HashSet;
+use std::io::{BufRead, Write};
+use std::process::ExitCode;
+
+/// What a [`Session`] makes of one input.
+#[derive(Debug, PartialEq)]
+pub enum Reply {
+    /// The input stops in the middle of a construct, such as a block that is still open. The
+    /// rest of it may follow on the next line; the error is what to report if it does not.
+    Incomplete(ParseError),
+    Complete {
+        /// Diagnostics and errors, one per line.
+        messages: Vec<String>,
+        /// The value of the expression the input ended with, if it was not unit.
+        value: Option<String>,
+    },
+}
+
+/// The programs a [`Session`] has loaded, starting with an empty one: the prelude, or another
+/// empty program without it, and then each accepted input with only its own declarations. A
+/// program is only ever added after the last, so a session can hold on to the ones before it
+/// while it adds another.
+pub struct Programs {
+    program: Program,
+    index: NodeIndex,
+    types: TypeMap,
+    /// The program loaded after this one.
+    next: OnceCell<Box<Programs>>,
+}
+
+impl Programs {
+    /// Add `programs` after the last program that follows this one.
+    fn push(&self, programs: Programs) -> &Programs {
+        let mut last = self;
+        while let Some(next) = last.next.get() {
+            last = next;
+        }
+        last.next.get_or_init(|| Box::new(programs))
+    }
+}
+
+impl Default for Programs {
+    fn default() -> Self {
+        let program = Program {
+            declarations: Vec::new(),
+        };
+        Programs {
+            index: NodeIndex::new(&program),
+            program,
+            types: TypeMap::default(),
+            next: OnceCell::new(),
+        }
+    }
+}
+
+/// The state a REPL keeps between inputs.
+pub struct Session<'s> {
+    /// Whether inputs are checked after the [prelude](semantics::prelude).
+    prelude: bool,
+    /// What the accepted inputs declared, once the first input has come.
+    earlier: Option<Earlier<'s>>,
+    /// The ids the nodes of the next input are numbered from. Every input's nodes get ids after
+    /// the ones of the inputs before it, accepted or not, so that their spans stay apart.
+    ids: NodeIds,
+    /// The source of every input.
+    sources: SourceMap,
+    /// Where the nodes of every input were written.
+    spans: Spans,
+    /// The last of the programs the interpreter has loaded.
+    last: &'s Programs,
+    interpreter: Interpreter<'s>,
+}
+
+impl<'s> Session<'s> {
+    /// A session with nothing declared yet, which keeps the programs it loads in `programs` and
+    /// whose programs print to `output`.
+    pub fn new(programs: &'s Programs, output: impl Write + 's) -> Self {
+        Session {
+            prelude: true,
+            earlier: None,
+            ids: NodeIds::new(),
+            sources: SourceMap::new(),
+            spans: Spans::default(),
+            last: programs,
+            interpreter: Interpreter::new(&programs.program, &programs.index, &programs.types)
+                .with_output(output),
+        }
+    }
+
+    /// Check inputs without the prelude.
+    pub fn without_prelude(mut self) -> Self {
+        self.prelude = false;
+        self
+    }
+
+    /// Check and run `source`. An input that ends with an expression instead of a statement
+    /// shows its value.
+    pub fn input(&mut self, source: &str) -> Reply {
+        if self.earlier.is_none() {
+            self.earlier = Some(self.start());
+        }
+        let (program, spans, trailing) = match parse(source, self.ids.clone(), &mut self.sources) {
+            Ok(parsed) => parsed,
+            Err(error) if is_incomplete(&error) => return Reply::Incomplete(error),
+            Err(error) => {
+                return Reply::Complete {
+                    messages: vec![render_parse_error(source, &error)],
+                    value: None,
+                };
+            }
+        };
+        if !program.declarations.is_empty() {
+            self.ids = NodeIds::after(&program);
+        }
+        self.spans.extend(spans);
+
+        let earlier = self.earlier.as_mut().expect("the session has started");
+        let index = NodeIndex::new(&program);
+        let resolution = earlier.resolve(&program, &index);
+        let checked = earlier.check(&program, &index, &resolution);
+        let mut diagnostics = earlier.check_program(
+            &program,
+            &index,
+            &resolution,
+            &checked,
+            ShadowingLint::SameScope,
+        );
+        // A later input may still use what this one declares, and the value of a trailing
+        // expression is shown rather than thrown away.
+        let mut unwarned: HashSet<NodeId> = program.declarations.iter().map(Node::id).collect();
+        if trailing
+            && let Some(Declaration::Statement(Statement {
+                kind: StatementKind::Expression(expression),
+                ..
+            })) = program.declarations.last()
+        {
+            unwarned.insert(expression.id());
+        }
+        diagnostics.retain(|diagnostic| {
+            diagnostic.severity == Severity::Error || !unwarned.contains(&diagnostic.node)
+        });
+        let (mut messages, failed) = report(&diagnostics, &self.spans, &self.sources);
+        if failed {
+            earlier.reject(resolution);
+            return Reply::Complete {
+                messages,
+                value: None,
+            };
+        }
+        let last = self.last.push(Programs {
+            program,
+            index,
+            types: checked.types,
+            next: OnceCell::new(),
+        });
+        self.last = last;
+        earlier.accept(&last.program, resolution, &last.types);
+        if let Err(error) = self
+            .interpreter
+            .extend(&last.program, &last.index, &last.types)
+        {
+            messages.push(runtime_error(
+                &error,
+                self.interpreter.backtrace(),
+                &self.spans,
+                &self.sources,
+            ));
+            return Reply::Complete {
+                messages,
+                value: None,
+            };
+        }
+
+        let declarations = &last.program.declarations;
+        let mut value = None;
+        for (position, declaration) in declarations.iter().enumerate() {
+            let Declaration::Statement(statement) = declaration else {
+                continue;
+            };
+            let result = match &statement.kind {
+                StatementKind::Expression(expression)
+                    if trailing && position == declarations.len() - 1 =>
+                {
+                    self.interpreter.evaluate(expression).map(|result| {
+                        if result != Value::Unit {
+                            value = Some(result.quoted().to_string());
+                        }
+                    })
+                }
+                _ => self.interpreter.execute(statement),
+            };
+            // A variable whose initializer did not run stays declared, so that functions that
+            // read it still check; reading it reports that it has no value.
+            if let Err(error) = result {
+                messages.push(runtime_error(
+                    &error,
+                    self.interpreter.backtrace(),
+                    &self.spans,
+                    &self.sources,
+                ));
+                break;
+            }
+        }
+        Reply::Complete { messages, value }
+    }
+
+    /// Load the prelude, or an empty program without it, for the first input to be checked
+    /// after.
+    fn start(&mut self) -> Earlier<'s> {
+        let program = Program {
+            declarations: if self.prelude {
+                semantics::prelude::declarations()
+            } else {
+                Vec::new()
+            },
+        };
+        // The prelude's nodes are numbered from the start, so the inputs' come after them.
+        self.ids = NodeIds::after(&program);
+        let index = NodeIndex::new(&program);
+        let resolution = semantics::resolve(&program, &index);
+        let types = semantics::check(&program, &index, &resolution).types;
+        let last = self.last.push(Programs {
+            program,
+            index,
+            types,
+            next: OnceCell::new(),
+        });
+        self.last = last;
+        self.interpreter
+            .extend(&last.program, &last.index, &last.types)
+            .expect("the prelude's consts can be evaluated");
+        Earlier::new(&last.program, resolution, &last.types)
+    }
+}
+
+/// Parse `source` as a file of `sources`, numbering its nodes with `ids`. Returns where its
+/// statements and expressions were written, and whether it ends with an expression that is
+/// missing its `;`, which a REPL input may leave off to have its value shown.
+fn parse(
+    source: &str,
+    ids: NodeIds,
+    sources: &mut SourceMap,
+) -> Result<(Program, Spans, bool), ParseError> {
+    match parse_numbered(source, ids.clone(), sources) {
+        Ok((program, spans)) => {
+            let trailing = !source.trim_end().ends_with(';')
+                && matches!(
+                    program.declarations.last(),
+                    Some(Declaration::Statement(Statement {
+                        kind: StatementKind::Expression(_),
+                        ..
+                    }))
+                );
+            Ok((program, spans, trailing))
+        }
+        Err(error @ ParseError::MissingSemicolon { found: None, .. }) => {
+            // The `;` may be missing from inside a block that is still open.
+            match parse_numbered(&format!("{};", source), ids, sources) {
+                Ok((program, spans)) => Ok((program, spans, true)),
+                Err(retried) if is_incomplete(&retried) => Err(retried),
+                Err(_) => Err(error),
+            }
+        }
+        Err(error) => Err(error),
+    }
+}
+
+fn parse_numbered(
+    source: &str,
+    ids: NodeIds,
+    sources: &mut SourceMap,
+) -> Result<(Program, Spans), ParseError> {
+    let file = sources.add_file("input", source);
+    let file = sources.get(file).expect("file was just added");
+    let tokens = Lexer::for_file(file).fold_negative_literals().tokenize()?;
+    let mut parser = Parser::new(tokens).numbered_from(ids).ending_at(file);
+    let program = parser.parse_program()?;
+    Ok((program, parser.spans().clone()))
+}
+
+/// Whether `error` only says that the input ended too soon.
+fn is_incomplete(error: &ParseError) -> bool {
+    matches!(
+        error,
+        ParseError::UnexpectedEof { .. }
+            | ParseError::UnclosedDelimiter { found: None, .. }
+            | ParseError::Lexer(
+                LexerError::UnterminatedString(_) | LexerError::UnterminatedComment(_)
+            )
+    )
+}
+
+/// Read inputs from standard input until it ends, running each one as it is complete. A line
+/// that leaves a construct open is continued on the next, and an empty line gives up on it.
+/// Inputs are checked with the prelude unless `prelude` is false.
+pub fn start(prelude: bool) -> ExitCode {
+    std::thread::Builder::new()
+        .stack_size(RUN_STACK_SIZE)
+        .spawn(move || read_eval_print(prelude))
+        .expect("the interpreter thread can be started")
+        .join()
+        .expect("the interpreter does not panic")
+}
+
+fn read_eval_print(prelude: bool) -> ExitCode {
+    let programs = Programs::default();
+    let mut session = Session::new(&programs, std::io::stdout());
+    if !prelude {
+        session = session.without_prelude();
+    }
+    let mut stdin = std::io::stdin().lock();
+    let mut source = String::new();
+    loop {
+        print!("{}", if source.is_empty() { "> " } else { "... " });
+        // A prompt that cannot be shown is not worth stopping for.
+        let _ = std::io::stdout().flush();
+        let mut line = String::new();
+        match stdin.read_line(&mut line) {
+            Ok(0) => return ExitCode::SUCCESS,
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("{}", e);
+                return ExitCode::FAILURE;
+            }
+        }
+        let given_up = line.trim().is_empty();
+        source.push_str(&line);
+        if source.trim().is_empty() {
+            source.clear();
+            continue;
+        }
+        match session.input(&source) {
+            Reply::Incomplete(error) if given_up => {
+                eprintln!("{}", render_parse_error(&source, &error))
+            }
+            Reply::Incomplete(_) => continue,
+            Reply::Complete { messages, value } => {
+                for message in &messages {
+                    eprintln!("{}", message);
+                }
+                if let Some(value) = value {
+                    println!("{}", value);
+                }
+            }
+        }
+        source.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use test_case::test_case;
+
+    /// Output that stays readable after the session writing to it takes it.
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(bytes)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// What the last of `inputs` shows, after entering all of them in one session.
+    fn last(inputs: &[&str]) -> Reply {
+        let programs = Programs::default();
+        let mut session = Session::new(&programs, Output::default());
+        let (last, earlier) = inputs.split_last().expect("at least one input");
+        for input in earlier {
+            session.input(input);
+        }
+        session.input(last)
+    }
+
+    fn value(value: &str) -> Reply {
+        Reply::Complete {
+            messages: Vec::new(),
+            value: Some(value.to_string()),
+        }
+    }
+
+    fn nothing() -> Reply {
+        Reply::Complete {
+            messages: Vec::new(),
+            value: None,
+        }
+    }
+
+    fn error(message: &str) -> Reply {
+        Reply::Complete {
+            messages: vec![message.to_string()],
+            value: None,
+        }
+    }
+
+    #[test_case(&["1 + 2 * 3"], value("7") ; "trailing expression")]
+    #[test_case(&["\"hi\""], value("\"hi\"") ; "strings are quoted")]
+    #[test_case(&["x = 1;", "x"], value("1") ; "variables persist")]
+    #[test_case(&["fn double(i32 n) -> i32 { n * 2 }", "double(21)"], value("42") ; "functions persist")]
+    #[test_case(&["@n = 1;", "n += 1;", "n"], value("2") ; "assignments persist")]
+    #[test_case(&["n = 2;", "scale = |i32 x| x * n;", "scale(21)"], value("42") ; "closures persist")]
+    #[test_case(&["record point { x: i32; }", "p = point { x: 3 }; p.x"], value("3") ; "records persist")]
+    #[test_case(&["fn f() {}", "f();"], nothing() ; "statement shows nothing")]
+    #[test_case(&["1;"], error("warning at 1:1: Result of type 'i32' is not used") ; "ignored result")]
+    #[test_case(&["x = 1; fn f() {}"], nothing() ; "declarations are left for later inputs to use")]
+    #[test_case(&["x = ;", "2"], value("2") ; "recovers from a parse error")]
+    #[test_case(&["x = 1 + true;", "x"], error("error at 1:1: Cannot find 'x' in this scope") ; "rejected input declares nothing")]
+    #[test_case(&["zero = 0;", "a = 1; b = 1 / zero;", "a"], value("1") ; "statements before a runtime error keep their effect")]
+    #[test_case(&["zero = 0;", "b = 1 / zero;", "b"], error("error at 1:1: Cannot find 'b'\n  note: in <top level> at 1:1") ; "variable that was never set")]
+    #[test_case(&["max(2, 7)"], value("7") ; "prelude")]
+    #[test_case(&["fn max(i32 n) -> i32 { n }", "max(3)"], value("3") ; "input replaces the prelude")]
+    #[test_case(&["const i32 A = 2;", "const i32 B = A * 3;", "B"], value("6") ; "consts use earlier consts")]
+    #[test_case(&["union shape = circle(i32) | square(i32);", "when square(2) { circle(r): r; square(l): l * l; }"], value("4") ; "when over an earlier union")]
+    #[test_case(&["x = when some(1) { some(n): n; };"], error("error at 1:5: 'when' does not cover none") ; "when over a prelude union")]
+    #[test_case(&["record point { x: i32; }", "patch point { fn twice() -> i32 { self.x * 2 } }", "point { x: 4 }.twice()"], value("8") ; "methods of an earlier patch")]
+    #[test_case(&["fn f() {}", "fn f() {}"], error("error at 1:4: 'f' is already defined as a function\n  note: first defined here at 1:4") ; "duplicate of an earlier declaration")]
+    #[test_case(&["fn f() -> i32 { 1 } g = 2 + true;", "fn f() -> i32 { 3 }", "f()"], value("3") ; "rejected input is forgotten")]
+    fn test_input(inputs: &[&str], expected: Reply) {
+        assert_eq!(last(inputs), expected);
+    }
+
+    #[test]
+    fn test_without_prelude() {
+        let programs = Programs::default();
+        let mut session = Session::new(&programs, Output::default()).without_prelude();
+
+        assert_eq!(
+            session.input("max(2, 7)"),
+            error("error at 1:1: Cannot find 'max' in this scope")
+        );
+    }
+
+    #[test]
+    fn test_runtime_error_is_reported() {
+        assert_eq!(
+            last(&["zero = 0;", "1 / zero"]),
+            error("error at 1:1: Division by zero\n  note: in <top level> at 1:1")
+        );
+    }
+
+    #[test]
+    fn test_program_output() {
+        let output = Output::default();
+        let programs = Programs::default();
+        let mut session = Session::new(&programs, output.clone());
+        session.input("fn greet(string name) { println(\"hello\", name); }");
+        session.input("greet(\"cv\");");
+
+        assert_eq!(String::from_utf8_lossy(&output.0.borrow()), "hello cv\n");
+    }
+
+    #[test]
+    fn test_runtime_error_in_an_earlier_input() {
+        assert_eq!(
+            last(&[
+                "fn divide(i32 n, i32 d) -> i32 {\n    n / d\n}",
+                "zero = 0;",
+                "divide(1, zero)",
+            ]),
+            error(
+                "error at 2:5: Division by zero\n  note: in divide at 2:5\n  note: in <top level> at 1:1"
+            )
+        );
+    }
+
+    #[test]
+    fn test_programs_keep_only_their_own_declarations() {
+        let programs = Programs::default();
+        let mut session = Session::new(&programs, Output::default());
+        for input in ["x = 1;", "y = x + true;", "fn f(", "fn f() {} f();", "x"] {
+            session.input(input);
+        }
+
+        let prelude = programs.next.get().expect("the prelude is loaded");
+        assert_eq!(
+            prelude.program.declarations.len(),
+            semantics::prelude::declarations().len()
+        );
+        let mut kept = Vec::new();
+        let mut last = &**prelude;
+        while let Some(next) = last.next.get() {
+            kept.push(next.program.declarations.len());
+            last = next;
+        }
+        assert_eq!(kept, [1, 2, 1]);
+    }
+
+    #[test_case("fn f() -> i32 {" ; "open block")]
+    #[test_case("x = [1,\n2," ; "open array")]
+    #[test_case("s = \"abc" ; "open string")]
+    #[test_case("x = 1 +" ; "unfinished expression")]
+    #[test_case("fn f() -> i32 {\n    1" ; "block without its last semicolon")]
+    fn test_incomplete_input(source: &str) {
+        assert!(matches!(last(&[source]), Reply::Incomplete(_)));
+    }
+
+    #[test]
+    fn test_continued_input() {
+        let programs = Programs::default();
+        let mut session = Session::new(&programs, Output::default());
+        assert!(matches!(
+            session.input("fn f() -> i32 {\n"),
+            Reply::Incomplete(_)
+        ));
+        session.input("fn f() -> i32 {\n    40 + 2\n}\n");
+
+        assert_eq!(session.input("f()"), value("42"));
+    }
+}
@@ -0,0 +1,349 @@
+//! Syntax highlighting definitions for editors, generated from the lexer's keyword and operator
+//! tables so that new tokens show up in every editor without hand-editing grammar files.
+
+use lexer::tokens::{KEYWORDS, OPERATORS, TokenKind};
+use std::fmt::Write;
+
+/// The editor formats `cv export-syntax` can produce.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SyntaxFormat {
+    /// A TextMate grammar in JSON form, as used by VS Code, Sublime Text, and others.
+    TmLanguage,
+    Vim,
+    Emacs,
+}
+
+impl SyntaxFormat {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "tmlanguage" => Some(SyntaxFormat::TmLanguage),
+            "vim" => Some(SyntaxFormat::Vim),
+            "emacs" => Some(SyntaxFormat::Emacs),
+            _ => None,
+        }
+    }
+
+    pub fn generate(&self) -> String {
+        match self {
+            SyntaxFormat::TmLanguage => tm_language(),
+            SyntaxFormat::Vim => vim(),
+            SyntaxFormat::Emacs => emacs(),
+        }
+    }
+}
+
+/// Regular expressions for the literal grammar, in TextMate's (Oniguruma) syntax.
+const NUMBER_PATTERN: &str = r"\b[0-9]+(\.[0-9]+)?\b";
+const CHAR_PATTERN: &str = r"b?'(\\.|[^\\'])+'";
+
+/// Keywords other than `true`, `false`, and the word operators, which get their own scopes.
+fn control_keywords() -> Vec<&'static str> {
+    KEYWORDS
+        .iter()
+        .filter(|keyword| {
+            !keyword.kind.is_literal() && !keyword.kind.is_operator() && !keyword.contextual
+        })
+        .map(|keyword| keyword.text)
+        .collect()
+}
+
+fn contextual_keywords() -> Vec<&'static str> {
+    KEYWORDS
+        .iter()
+        .filter(|keyword| keyword.contextual)
+        .map(|keyword| keyword.text)
+        .collect()
+}
+
+fn literal_keywords() -> Vec<&'static str> {
+    KEYWORDS
+        .iter()
+        .filter(|keyword| keyword.kind.is_literal())
+        .map(|keyword| keyword.text)
+        .collect()
+}
+
+fn word_operators() -> Vec<&'static str> {
+    KEYWORDS
+        .iter()
+        .filter(|keyword| keyword.kind.is_operator())
+        .map(|keyword| keyword.text)
+        .collect()
+}
+
+/// The spelling of every symbolic operator, longest first so that alternations built from them
+/// prefer `..=` over `..`.
+fn symbolic_operators() -> Vec<String> {
+    let mut operators: Vec<String> = OPERATORS
+        .iter()
+        .filter(|operator| !operator.is_keyword())
+        .map(TokenKind::to_string)
+        .collect();
+    operators.sort_by_key(|operator| std::cmp::Reverse(operator.len()));
+    operators
+}
+
+/// Escape the characters that are special in a regular expression.
+fn escape_regex(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        if "\\.+*?()|[]{}^$".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Quote `text` as a JSON string.
+fn json_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if c.is_control() => write!(quoted, "\\u{:04x}", c as u32).unwrap(),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn word_alternation(words: &[&str]) -> String {
+    format!(r"\b({})\b", words.join("|"))
+}
+
+fn tm_language() -> String {
+    let operators: Vec<String> = symbolic_operators()
+        .iter()
+        .map(|operator| escape_regex(operator))
+        .collect();
+    let matches = [
+        ("keyword.control.cv", word_alternation(&control_keywords())),
+        ("keyword.other.cv", word_alternation(&contextual_keywords())),
+        (
+            "keyword.operator.word.cv",
+            word_alternation(&word_operators()),
+        ),
+        (
+            "constant.language.cv",
+            word_alternation(&literal_keywords()),
+        ),
+        ("constant.numeric.cv", NUMBER_PATTERN.to_string()),
+        ("constant.character.cv", CHAR_PATTERN.to_string()),
+        ("keyword.operator.cv", operators.join("|")),
+        (
+            "variable.other.mutable.cv",
+            r"@[A-Za-z_][A-Za-z0-9_]*".to_string(),
+        ),
+    ];
+
+    let mut patterns = vec![
+        r#"{ "name": "comment.line.double-slash.cv", "match": "//.*$" }"#.to_string(),
+        r#"{ "name": "comment.block.cv", "begin": "/\\*", "end": "\\*/" }"#.to_string(),
+        r#"{ "name": "string.quoted.double.cv", "begin": "b?\"", "end": "\"", "patterns": [{ "name": "constant.character.escape.cv", "match": "\\\\." }] }"#.to_string(),
+    ];
+    patterns.extend(matches.iter().map(|(scope, pattern)| {
+        format!(
+            "{{ \"name\": {}, \"match\": {} }}",
+            json_string(scope),
+            json_string(pattern)
+        )
+    }));
+
+    format!(
+        "{{\n  \"name\": \"CV\",\n  \"scopeName\": \"source.cv\",\n  \"fileTypes\": [\"cv\"],\n  \"patterns\": [\n    {}\n  ]\n}}\n",
+        patterns.join(",\n    ")
+    )
+}
+
+fn vim() -> String {
+    let mut out = String::from(
+        "\" Vim syntax file\n\" Language: CV\n\" Generated by `cv export-syntax --format=vim`.\n\n\
+         if exists(\"b:current_syntax\")\n  finish\nendif\n\n",
+    );
+    writeln!(
+        out,
+        "syn keyword cvKeyword {}",
+        control_keywords().join(" ")
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "syn keyword cvContextual {}",
+        contextual_keywords().join(" ")
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "syn keyword cvOperatorWord {}",
+        word_operators().join(" ")
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "syn keyword cvBoolean {}",
+        literal_keywords().join(" ")
+    )
+    .unwrap();
+    for operator in symbolic_operators() {
+        writeln!(out, "syn match cvOperator \"\\V{}\"", operator).unwrap();
+    }
+    out.push_str(
+        "syn match cvNumber \"\\<[0-9]\\+\\(\\.[0-9]\\+\\)\\=\\>\"\n\
+         syn match cvMutable \"@\\h\\w*\"\n\
+         syn region cvString start=+b\\=\"+ skip=+\\\\.+ end=+\"+\n\
+         syn match cvChar \"b\\='\\(\\\\.\\|[^\\\\']\\)\\+'\"\n\
+         syn match cvComment \"//.*$\"\n\
+         syn region cvComment start=\"/\\*\" end=\"\\*/\"\n\n\
+         hi def link cvKeyword Keyword\n\
+         hi def link cvContextual Keyword\n\
+         hi def link cvOperatorWord Operator\n\
+         hi def link cvOperator Operator\n\
+         hi def link cvBoolean Boolean\n\
+         hi def link cvNumber Number\n\
+         hi def link cvMutable Identifier\n\
+         hi def link cvString String\n\
+         hi def link cvChar Character\n\
+         hi def link cvComment Comment\n\n\
+         let b:current_syntax = \"cv\"\n",
+    );
+    out
+}
+
+fn emacs() -> String {
+    let elisp_strings = |words: &[&str]| {
+        words
+            .iter()
+            .map(|word| json_string(word))
+            .collect::<Vec<String>>()
+            .join(" ")
+    };
+    let operators: Vec<String> = symbolic_operators();
+    let operators: Vec<&str> = operators.iter().map(String::as_str).collect();
+
+    format!(
+        r#";;; cv-mode.el --- Major mode for the CV language -*- lexical-binding: t -*-
+;; Generated by `cv export-syntax --format=emacs`.
+
+(defconst cv-keywords '({keywords}))
+(defconst cv-contextual-keywords '({contextual}))
+(defconst cv-word-operators '({word_operators}))
+(defconst cv-constants '({constants}))
+(defconst cv-operators '({operators}))
+
+(defconst cv-font-lock-keywords
+  `((,(regexp-opt cv-keywords 'symbols) . font-lock-keyword-face)
+    (,(regexp-opt cv-contextual-keywords 'symbols) . font-lock-keyword-face)
+    (,(regexp-opt cv-word-operators 'symbols) . font-lock-builtin-face)
+    (,(regexp-opt cv-constants 'symbols) . font-lock-constant-face)
+    ("\\_<[0-9]+\\(?:\\.[0-9]+\\)?\\_>" . font-lock-constant-face)
+    ("@\\(?:\\sw\\|\\s_\\)+" . font-lock-variable-name-face)
+    (,(regexp-opt cv-operators) . font-lock-operator-face)))
+
+(defvar cv-mode-syntax-table
+  (let ((table (make-syntax-table)))
+    (modify-syntax-entry ?_ "_" table)
+    (modify-syntax-entry ?\" "\"" table)
+    (modify-syntax-entry ?' "\"" table)
+    (modify-syntax-entry ?\\ "\\" table)
+    (modify-syntax-entry ?/ ". 124b" table)
+    (modify-syntax-entry ?* ". 23" table)
+    (modify-syntax-entry ?\n "> b" table)
+    table))
+
+;;;###autoload
+(define-derived-mode cv-mode prog-mode "CV"
+  "Major mode for editing CV source files."
+  (setq-local comment-start "// ")
+  (setq-local comment-end "")
+  (setq-local font-lock-defaults '(cv-font-lock-keywords)))
+
+;;;###autoload
+(add-to-list 'auto-mode-alist '("\\.cv\\'" . cv-mode))
+
+(provide 'cv-mode)
+;;; cv-mode.el ends here
+"#,
+        keywords = elisp_strings(&control_keywords()),
+        contextual = elisp_strings(&contextual_keywords()),
+        word_operators = elisp_strings(&word_operators()),
+        constants = elisp_strings(&literal_keywords()),
+        operators = elisp_strings(&operators),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_format() {
+        assert_eq!(SyntaxFormat::parse("vim"), Some(SyntaxFormat::Vim));
+        assert_eq!(
+            SyntaxFormat::parse("tmlanguage"),
+            Some(SyntaxFormat::TmLanguage)
+        );
+        assert_eq!(SyntaxFormat::parse("vscode"), None);
+    }
+
+    #[test]
+    fn test_every_keyword_is_highlighted() {
+        for format in [
+            SyntaxFormat::TmLanguage,
+            SyntaxFormat::Vim,
+            SyntaxFormat::Emacs,
+        ] {
+            let definition = format.generate();
+            for keyword in KEYWORDS {
+                assert!(
+                    definition.contains(keyword.text),
+                    "{:?} is missing keyword {}",
+                    format,
+                    keyword.text
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_every_operator_is_highlighted() {
+        let vim = vim();
+        let tm_language = tm_language();
+        for operator in symbolic_operators() {
+            assert!(
+                vim.contains(&format!("\"\\V{}\"", operator)),
+                "{}",
+                operator
+            );
+            let escaped = json_string(&escape_regex(&operator));
+            let escaped = &escaped[1..escaped.len() - 1];
+            assert!(tm_language.contains(escaped), "{}", operator);
+        }
+    }
+
+    #[test]
+    fn test_longer_operators_come_first() {
+        let operators = symbolic_operators();
+        let position = |text: &str| operators.iter().position(|o| o == text).unwrap();
+
+        assert!(position("..=") < position(".."));
+        assert!(position("==") < position("="));
+        assert!(position("&&") < position("&"));
+    }
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string(r#"a"b\c"#), r#""a\"b\\c""#);
+        assert_eq!(json_string("\n\u{1}"), r#""\n\u0001""#);
+    }
+
+    #[test]
+    fn test_escape_regex() {
+        assert_eq!(escape_regex("..="), r"\.\.=");
+        assert_eq!(escape_regex("||"), r"\|\|");
+        assert_eq!(escape_regex("*="), r"\*=");
+    }
+}